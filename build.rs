@@ -0,0 +1,73 @@
+//! Embeds the git revision and compiler version reported by `GET /admin/info`,
+//! and checks the storage queries against the migrations.
+
+use rusqlite::{functions::FunctionFlags, Connection};
+use std::{fs, path::Path, process::Command};
+
+#[allow(dead_code)]
+#[path = "src/storage/queries.rs"]
+mod queries;
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    let text = String::from_utf8(out.stdout).ok()?.trim().to_string();
+    (out.status.success() && !text.is_empty()).then_some(text)
+}
+
+/// Paths of the files embedded in `db::MIGRATIONS`, in order
+fn migrations() -> Vec<String> {
+    let db = fs::read_to_string("src/db.rs").expect("read src/db.rs");
+    let list = db
+        .split_once("pub const MIGRATIONS")
+        .and_then(|(_, rest)| rest.split_once("];"))
+        .map(|(list, _)| list)
+        .expect("MIGRATIONS in src/db.rs");
+    list.lines()
+        .filter_map(|line| line.trim().strip_prefix('"')?.strip_suffix("\","))
+        .map(|file| format!("migrations/{}", file))
+        .collect()
+}
+
+/// Prepare every statement in `storage::queries` against the schema the
+/// migrations create; one naming a missing table or column fails the build
+fn check_queries() {
+    println!("cargo:rerun-if-changed=src/storage/queries.rs");
+    println!("cargo:rerun-if-changed=src/db.rs");
+    println!("cargo:rerun-if-changed=migrations");
+    let conn = Connection::open_in_memory().expect("open in-memory database");
+    // stands in for `db::register_functions`; it only runs on existing rows
+    conn.create_scalar_function("sha256_hex", 1, FunctionFlags::SQLITE_UTF8, |ctx| ctx.get::<String>(0))
+        .expect("register sha256_hex");
+    for file in migrations() {
+        let sql = fs::read_to_string(&file).unwrap_or_else(|e| panic!("read {}: {}", file, e));
+        conn.execute_batch(&sql).unwrap_or_else(|e| panic!("migration {} failed: {}", file, e));
+    }
+    let broken: Vec<String> = queries::ALL
+        .iter()
+        .filter_map(|(name, sql)| conn.prepare(sql).err().map(|e| format!("  {}: {}", name, e)))
+        .collect();
+    if !broken.is_empty() {
+        panic!("queries in src/storage/queries.rs do not match the migrations:\n{}", broken.join("\n"));
+    }
+}
+
+fn main() {
+    check_queries();
+    // images built without `.git` pass the revision in (`--build-arg GIT_SHA=...`)
+    let git_sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
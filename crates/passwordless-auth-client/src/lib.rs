@@ -0,0 +1,422 @@
+//! Client-side verification of tokens issued by Passwordless Auth.
+//!
+//! `no_std` + `alloc` so it compiles to `wasm32-unknown-unknown` for browsers
+//! and edge workers. The crate does no I/O: fetch the JWKS document with the
+//! host's HTTP client and hand the JSON to [`Jwks::from_json`].
+//!
+//! ```ignore
+//! let jwks = Jwks::from_json(&fetched_body)?;
+//! let claims = Verifier::new(&jwks).verify(token, &Validation::access(now_unix_seconds))?;
+//! ```
+//!
+//! [`Verifier`] checks HS256 signatures. Servers signing with RS256 or ES256
+//! publish the public keys ([`Jwk::n`], [`Jwk::x`], ...); check those tokens
+//! with the host's RSA/ECDSA implementation, then apply [`validate_claims`].
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use data_encoding::BASE64URL_NOPAD;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Claims carried by access and refresh tokens
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String, // public subject (access) or session token (refresh)
+    pub exp: usize,
+    pub iat: usize,
+    /// Not valid before; absent on tokens issued by older servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<usize>,
+    /// Issuer, when the server sets `jwt_issuer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// Audiences: the APIs the issuing application may call, or its
+    /// `client_id` when it lists none. A single audience is a JSON string,
+    /// several an array.
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "one_or_many")]
+    pub aud: Vec<String>,
+    /// Session id of an access token; absent on older and refresh tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Unique token id, by which a single access token can be revoked;
+    /// absent on tokens issued by older servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    pub kind: String, // "access" | "refresh" | "profile" (restricted, profile incomplete)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// Not three base64url segments, or undecodable JSON
+    Malformed,
+    /// `alg` is not supported by this helper
+    UnsupportedAlgorithm(String),
+    /// No key in the JWKS matches the token's `kid`/`alg`
+    UnknownKey,
+    BadSignature,
+    Expired,
+    /// `iat` or `nbf` lies in the future beyond the allowed leeway
+    NotYetValid,
+    /// `kind` does not match the expected token kind
+    WrongKind,
+    /// `iss` is missing or not the expected issuer
+    WrongIssuer,
+    /// `aud` is missing or does not name the expected audience
+    WrongAudience,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed token"),
+            Self::UnsupportedAlgorithm(alg) => write!(f, "unsupported algorithm {}", alg),
+            Self::UnknownKey => write!(f, "no matching key"),
+            Self::BadSignature => write!(f, "bad signature"),
+            Self::Expired => write!(f, "token expired"),
+            Self::NotYetValid => write!(f, "token not yet valid"),
+            Self::WrongKind => write!(f, "wrong token kind"),
+            Self::WrongIssuer => write!(f, "wrong issuer"),
+            Self::WrongAudience => write!(f, "wrong audience"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyError {}
+
+/// One key of a JWKS document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// `sig` for signing keys
+    #[serde(default, rename = "use", skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+    /// Symmetric key material (`kty = "oct"`), base64url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<String>,
+    /// RSA modulus (`kty = "RSA"`), base64url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// RSA public exponent, base64url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Curve of an EC key (`kty = "EC"`), `P-256`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// EC public point coordinates, base64url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+/// JSON Web Key Set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    pub fn from_json(json: &str) -> Result<Self, VerifyError> {
+        serde_json::from_str(json).map_err(|_| VerifyError::Malformed)
+    }
+
+    /// A single shared HS256 secret (for edge workers deployed with `jwt_secret`)
+    pub fn shared_secret(secret: &[u8]) -> Self {
+        Self {
+            keys: alloc::vec![Jwk {
+                kty: "oct".into(),
+                kid: None,
+                alg: Some("HS256".into()),
+                key_use: None,
+                k: Some(BASE64URL_NOPAD.encode(secret)),
+                n: None,
+                e: None,
+                crv: None,
+                x: None,
+                y: None,
+            }],
+        }
+    }
+
+    fn find(&self, kid: Option<&str>, alg: &str) -> impl Iterator<Item = &Jwk> {
+        let kid = kid.map(String::from);
+        let alg = String::from(alg);
+        self.keys.iter().filter(move |k| {
+            (kid.is_none() || k.kid == kid) && k.alg.as_deref().is_none_or(|a| a == alg)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// Claim rules applied after the signature check
+#[derive(Debug, Clone)]
+pub struct Validation {
+    /// Current time, seconds since the Unix epoch (supplied by the host)
+    pub now: u64,
+    /// Clock skew tolerated for `exp`, `nbf` and `iat`
+    pub leeway: u64,
+    /// Required `kind`, if any
+    pub kind: Option<&'static str>,
+    /// Required `iss`, if any
+    pub issuer: Option<String>,
+    /// Audience that `aud` must contain, if any
+    pub audience: Option<String>,
+}
+
+impl Validation {
+    /// Rules for access tokens, as applied by the server
+    pub fn access(now: u64) -> Self {
+        Self {
+            now,
+            leeway: 0,
+            kind: Some("access"),
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    /// Tolerate `seconds` of clock skew between issuer and verifier
+    pub fn with_leeway(mut self, seconds: u64) -> Self {
+        self.leeway = seconds;
+        self
+    }
+
+    /// Require tokens issued by `issuer`
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Require tokens whose `aud` names `audience` (a registered API, or an
+    /// application's `client_id`)
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+}
+
+/// Check `exp`, `nbf`, `iat`, `kind`, `iss` and `aud` against the rules
+pub fn validate_claims(claims: &Claims, rules: &Validation) -> Result<(), VerifyError> {
+    if (claims.exp as u64).saturating_add(rules.leeway) <= rules.now {
+        return Err(VerifyError::Expired);
+    }
+    let not_before = claims.nbf.map_or(claims.iat, |nbf| nbf.max(claims.iat));
+    if not_before as u64 > rules.now.saturating_add(rules.leeway) {
+        return Err(VerifyError::NotYetValid);
+    }
+    if rules.kind.is_some_and(|k| k != claims.kind) {
+        return Err(VerifyError::WrongKind);
+    }
+    if rules.issuer.is_some() && claims.iss != rules.issuer {
+        return Err(VerifyError::WrongIssuer);
+    }
+    if rules.audience.as_ref().is_some_and(|a| !claims.aud.contains(a)) {
+        return Err(VerifyError::WrongAudience);
+    }
+    Ok(())
+}
+
+/// `aud` as a JSON string (one audience) or array (several)
+mod one_or_many {
+    use alloc::{string::String, vec::Vec};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    pub fn serialize<S: Serializer>(aud: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        match aud {
+            [one] => one.serialize(serializer),
+            many => many.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+        Ok(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(one) => alloc::vec![one],
+            OneOrMany::Many(many) => many,
+        })
+    }
+}
+
+/// Decode the claims without checking the signature (display purposes only)
+pub fn decode_unverified(token: &str) -> Result<Claims, VerifyError> {
+    let mut parts = token.split('.');
+    let _header = parts.next().ok_or(VerifyError::Malformed)?;
+    let payload = parts.next().ok_or(VerifyError::Malformed)?;
+    decode_json(payload)
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(segment: &str) -> Result<T, VerifyError> {
+    let bytes = BASE64URL_NOPAD
+        .decode(segment.as_bytes())
+        .map_err(|_| VerifyError::Malformed)?;
+    serde_json::from_slice(&bytes).map_err(|_| VerifyError::Malformed)
+}
+
+/// Verifies token signatures against a key set
+pub struct Verifier<'a> {
+    jwks: &'a Jwks,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(jwks: &'a Jwks) -> Self {
+        Self { jwks }
+    }
+
+    pub fn verify(&self, token: &str, rules: &Validation) -> Result<Claims, VerifyError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(VerifyError::Malformed);
+        };
+        let header: Header = decode_json(header)?;
+        let signature = BASE64URL_NOPAD
+            .decode(signature.as_bytes())
+            .map_err(|_| VerifyError::Malformed)?;
+        let (signed, _) = token.rsplit_once('.').ok_or(VerifyError::Malformed)?;
+
+        match header.alg.as_str() {
+            "HS256" => {
+                let mut matched = false;
+                for jwk in self.jwks.find(header.kid.as_deref(), "HS256") {
+                    let Some(k) = jwk.k.as_deref() else { continue };
+                    let Ok(secret) = BASE64URL_NOPAD.decode(k.as_bytes()) else { continue };
+                    matched = true;
+                    let mut mac = Hmac::<Sha256>::new_from_slice(&secret).map_err(|_| VerifyError::UnknownKey)?;
+                    mac.update(signed.as_bytes());
+                    if mac.verify_slice(&signature).is_ok() {
+                        let claims: Claims = decode_json(payload)?;
+                        validate_claims(&claims, rules)?;
+                        return Ok(claims);
+                    }
+                }
+                Err(if matched { VerifyError::BadSignature } else { VerifyError::UnknownKey })
+            }
+            other => Err(VerifyError::UnsupportedAlgorithm(other.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &[u8], claims: &Claims) -> String {
+        let header = BASE64URL_NOPAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = BASE64URL_NOPAD.encode(&serde_json::to_vec(claims).unwrap());
+        let signed = alloc::format!("{}.{}", header, payload);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(signed.as_bytes());
+        alloc::format!("{}.{}", signed, BASE64URL_NOPAD.encode(&mac.finalize().into_bytes()))
+    }
+
+    fn claims(exp: usize, kind: &str) -> Claims {
+        Claims {
+            sub: "subject".into(),
+            exp,
+            iat: 1_000,
+            nbf: Some(1_000),
+            iss: None,
+            aud: Vec::new(),
+            sid: None,
+            jti: None,
+            kind: kind.into(),
+        }
+    }
+
+    #[test]
+    fn verifies_hs256_with_shared_secret() {
+        let jwks = Jwks::shared_secret(b"supersecret1234567890");
+        let token = sign(b"supersecret1234567890", &claims(2_000, "access"));
+        let verified = Verifier::new(&jwks).verify(&token, &Validation::access(1_500)).unwrap();
+        assert_eq!(verified.sub, "subject");
+    }
+
+    #[test]
+    fn rejects_bad_signature_expiry_and_kind() {
+        let jwks = Jwks::shared_secret(b"supersecret1234567890");
+        let verifier = Verifier::new(&jwks);
+        let forged = sign(b"other", &claims(2_000, "access"));
+        assert_eq!(verifier.verify(&forged, &Validation::access(1_500)), Err(VerifyError::BadSignature));
+
+        let expired = sign(b"supersecret1234567890", &claims(1_200, "access"));
+        assert_eq!(verifier.verify(&expired, &Validation::access(1_500)), Err(VerifyError::Expired));
+
+        let refresh = sign(b"supersecret1234567890", &claims(2_000, "refresh"));
+        assert_eq!(verifier.verify(&refresh, &Validation::access(1_500)), Err(VerifyError::WrongKind));
+    }
+
+    #[test]
+    fn leeway_covers_skewed_clocks() {
+        let jwks = Jwks::shared_secret(b"supersecret1234567890");
+        let verifier = Verifier::new(&jwks);
+        let fresh = sign(b"supersecret1234567890", &claims(2_000, "access"));
+        // verifier clock 30s behind the issuer
+        assert_eq!(verifier.verify(&fresh, &Validation::access(970)), Err(VerifyError::NotYetValid));
+        assert!(verifier.verify(&fresh, &Validation::access(970).with_leeway(60)).is_ok());
+
+        let expired = sign(b"supersecret1234567890", &claims(1_200, "access"));
+        assert!(verifier.verify(&expired, &Validation::access(1_230).with_leeway(60)).is_ok());
+    }
+
+    #[test]
+    fn issuer_and_audience_must_match_when_required() {
+        let jwks = Jwks::shared_secret(b"supersecret1234567890");
+        let verifier = Verifier::new(&jwks);
+        let mut issued = claims(2_000, "access");
+        issued.iss = Some("https://auth.example.com".into());
+        issued.aud = alloc::vec!["billing".into()];
+        let token = sign(b"supersecret1234567890", &issued);
+
+        let rules = Validation::access(1_500).with_issuer("https://auth.example.com");
+        assert!(verifier.verify(&token, &rules.clone().with_audience("billing")).is_ok());
+        assert_eq!(verifier.verify(&token, &rules.with_audience("reports")), Err(VerifyError::WrongAudience));
+
+        let bare = sign(b"supersecret1234567890", &claims(2_000, "access"));
+        let rules = Validation::access(1_500).with_issuer("https://auth.example.com");
+        assert_eq!(verifier.verify(&bare, &rules), Err(VerifyError::WrongIssuer));
+    }
+
+    #[test]
+    fn audience_lists_round_trip_and_match_any_entry() {
+        let jwks = Jwks::shared_secret(b"supersecret1234567890");
+        let verifier = Verifier::new(&jwks);
+        let mut issued = claims(2_000, "access");
+        issued.aud = alloc::vec!["billing".into(), "reports".into()];
+        let token = sign(b"supersecret1234567890", &issued);
+        assert_eq!(decode_unverified(&token).unwrap().aud, issued.aud);
+
+        let rules = Validation::access(1_500);
+        assert!(verifier.verify(&token, &rules.clone().with_audience("reports")).is_ok());
+        assert_eq!(verifier.verify(&token, &rules.with_audience("admin")), Err(VerifyError::WrongAudience));
+
+        // a single audience stays a plain string on the wire
+        let single: serde_json::Value = serde_json::to_value(claims(2_000, "access")).unwrap();
+        assert!(single.get("aud").is_none());
+        let mut one = claims(2_000, "access");
+        one.aud = alloc::vec!["billing".into()];
+        assert_eq!(serde_json::to_value(&one).unwrap()["aud"], "billing");
+    }
+}
@@ -0,0 +1,73 @@
+//! Minimal webhook receiver that checks signatures and drops replays.
+//!
+//! ```sh
+//! WEBHOOK_SECRET=your-webhook-secret cargo run --example webhook_receiver
+//! # config.toml: webhook_url = "http://localhost:4000/webhooks/auth"
+//! ```
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use passwordless_auth::webhooks::{self, WebhookPayload, DEFAULT_TOLERANCE};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+#[derive(Clone)]
+struct Receiver {
+    secret: String,
+    /// Event ids seen within the tolerance window
+    seen: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    // verify the raw bytes before parsing them
+    let event_id = match webhooks::verify_signature(&headers, &body, &receiver.secret, DEFAULT_TOLERANCE) {
+        Ok(id) => id.to_string(),
+        Err(e) => {
+            eprintln!("rejected delivery: {}", e);
+            return StatusCode::UNAUTHORIZED;
+        }
+    };
+
+    {
+        let mut seen = receiver.seen.lock().unwrap();
+        // anything older than the window would fail the timestamp check anyway
+        let window = DEFAULT_TOLERANCE * 2;
+        seen.retain(|_, at| at.elapsed() < window);
+        if seen.insert(event_id.clone(), Instant::now()).is_some() {
+            // a retry of something already handled: acknowledge, do nothing
+            return StatusCode::OK;
+        }
+    }
+
+    match serde_json::from_slice::<WebhookPayload>(&body) {
+        Ok(payload) => {
+            println!("{} {:?} for user {}", event_id, payload.event, payload.user_id);
+            StatusCode::OK
+        }
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let secret = std::env::var("WEBHOOK_SECRET").expect("WEBHOOK_SECRET must be set");
+    let receiver = Receiver {
+        secret,
+        seen: Arc::new(Mutex::new(HashMap::new())),
+    };
+    let app = Router::new()
+        .route("/webhooks/auth", post(receive))
+        .with_state(receiver);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:4000").await.unwrap();
+    println!("listening on http://127.0.0.1:4000/webhooks/auth");
+    axum::serve(listener, app).await.unwrap();
+}
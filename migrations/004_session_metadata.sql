@@ -0,0 +1,12 @@
+-- Opaque per-application metadata attached to sessions by relying apps
+CREATE TABLE IF NOT EXISTS session_metadata (
+    session_id TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    metadata TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (session_id, client_id)
+);
+
+-- Public session identifier (the refresh token itself is a secret)
+ALTER TABLE refresh_tokens ADD COLUMN session_id TEXT;
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session_id ON refresh_tokens(session_id);
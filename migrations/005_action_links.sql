@@ -0,0 +1,16 @@
+-- Single-use signed action links (verify email, approve device, revoke session, ...)
+CREATE TABLE IF NOT EXISTS action_links (
+    id TEXT PRIMARY KEY,
+    purpose TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    payload TEXT,
+    expires_at INTEGER NOT NULL,
+    used_at INTEGER,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_action_links_user_id ON action_links(user_id);
+CREATE INDEX IF NOT EXISTS idx_action_links_expires_at ON action_links(expires_at);
+
+ALTER TABLE users ADD COLUMN email_verified_at INTEGER;
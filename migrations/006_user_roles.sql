@@ -0,0 +1,2 @@
+-- User roles, used to resolve per-role token lifetimes at issuance
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
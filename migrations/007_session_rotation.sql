@@ -0,0 +1,4 @@
+-- Refresh token rotation: the predecessor row is marked rotated and points at
+-- its successor; both rows share the same session_id
+ALTER TABLE refresh_tokens ADD COLUMN rotated_at INTEGER;
+ALTER TABLE refresh_tokens ADD COLUMN replaced_by TEXT;
@@ -0,0 +1,20 @@
+-- Transactional outbox: auth events are written in the same transaction as the
+-- domain change and delivered to audit_logs / webhooks by a background dispatcher
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_id TEXT NOT NULL UNIQUE,
+    audit_event TEXT NOT NULL,
+    webhook_event TEXT,
+    user_id TEXT,
+    email TEXT,
+    metadata TEXT,
+    success INTEGER NOT NULL DEFAULT 1,
+    created_at INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    audit_written_at INTEGER,
+    delivered_at INTEGER,
+    last_error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_due ON outbox(delivered_at, next_attempt_at);
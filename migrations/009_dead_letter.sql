@@ -0,0 +1,3 @@
+-- Dead-letter tracking for the email queue (status 'dead') and the outbox
+ALTER TABLE email_queue ADD COLUMN dead_at INTEGER;
+ALTER TABLE outbox ADD COLUMN dead_at INTEGER;
@@ -0,0 +1,11 @@
+-- Public subject identifiers: access tokens carry these instead of users.id.
+-- client_id is '' for the shared public subject, or the application's client_id
+-- for pairwise subjects
+CREATE TABLE IF NOT EXISTS user_subjects (
+    subject TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    client_id TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL,
+    UNIQUE(user_id, client_id),
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+-- Failed TOTP attempt windows backing proof-of-continuity attempt tokens
+CREATE TABLE IF NOT EXISTS totp_attempts (
+    user_id TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
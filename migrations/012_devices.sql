@@ -0,0 +1,19 @@
+-- Devices a user has signed in from, keyed by client-hint fingerprint
+CREATE TABLE IF NOT EXISTS user_devices (
+    user_id TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    hints TEXT NOT NULL,
+    first_seen_at INTEGER NOT NULL,
+    last_seen_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, fingerprint),
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Client hints captured (with consent) when a session was created
+CREATE TABLE IF NOT EXISTS session_devices (
+    session_id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    hints TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
@@ -0,0 +1,7 @@
+-- How often each user has been prompted to add a passkey after a magic-link login
+CREATE TABLE IF NOT EXISTS passkey_nudges (
+    user_id TEXT PRIMARY KEY,
+    prompts INTEGER NOT NULL DEFAULT 0,
+    last_prompted_at INTEGER NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- Post-login redirect requested with a magic link, validated against the
+-- application's allowed_redirect_uris when issued and again when consumed
+ALTER TABLE magic_links ADD COLUMN client_id TEXT;
+ALTER TABLE magic_links ADD COLUMN redirect_uri TEXT;
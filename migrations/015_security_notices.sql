@@ -0,0 +1,22 @@
+-- Countries each user has signed in from (new-country notices)
+CREATE TABLE IF NOT EXISTS user_countries (
+    user_id TEXT NOT NULL,
+    country TEXT NOT NULL,
+    first_seen_at INTEGER NOT NULL,
+    last_seen_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, country),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+-- Per-user opt-outs from security notices; a missing row means enabled
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    enabled INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, kind),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+-- Set by the "freeze my account" link in notices; blocks new sign-ins
+ALTER TABLE users ADD COLUMN frozen_at INTEGER;
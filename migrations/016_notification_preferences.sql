@@ -0,0 +1,8 @@
+-- Preference center: notification_preferences.kind now holds a category
+-- (sign_in, security, new_sign_in, product_updates, marketing) with a channel
+ALTER TABLE notification_preferences ADD COLUMN channel TEXT NOT NULL DEFAULT 'email';
+ALTER TABLE users ADD COLUMN phone TEXT;
+
+UPDATE OR IGNORE notification_preferences SET kind = 'new_sign_in' WHERE kind IN ('new_device', 'new_country');
+DELETE FROM notification_preferences
+WHERE kind IN ('new_device', 'new_country', 'passkey_added', 'totp_disabled', 'recovery_codes_regenerated');
@@ -0,0 +1,9 @@
+-- Profile fields collected after sign-in for applications that require them
+CREATE TABLE IF NOT EXISTS user_profile_fields (
+    user_id TEXT NOT NULL,
+    field TEXT NOT NULL,
+    value TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, field),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
@@ -0,0 +1,14 @@
+-- Redacted request/response pairs of sampled failed auth requests ([debug_sampling])
+CREATE TABLE IF NOT EXISTS debug_samples (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL,
+    request_id TEXT,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    request_headers TEXT NOT NULL,
+    request_body TEXT,
+    response_body TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_debug_samples_created_at ON debug_samples(created_at);
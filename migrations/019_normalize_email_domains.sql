@@ -0,0 +1,7 @@
+-- Addresses are stored with a lowercase ASCII (punycode) domain; bring rows
+-- written before normalization in line so their owners still match.
+-- Rows whose normalized form already belongs to another account are left as is.
+UPDATE OR IGNORE users
+SET email = substr(email, 1, instr(email, '@')) || lower(substr(email, instr(email, '@') + 1))
+WHERE instr(email, '@') > 0
+  AND substr(email, instr(email, '@') + 1) != lower(substr(email, instr(email, '@') + 1));
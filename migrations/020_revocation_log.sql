@@ -0,0 +1,21 @@
+-- Revocations exchanged between regions in the `eventual` session mode.
+-- Entries are tombstones: they are never updated, and the same revocation
+-- arriving twice (from its origin and through another peer) is ignored.
+CREATE TABLE IF NOT EXISTS revocation_log (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,        -- 'session' or 'user'
+    subject TEXT NOT NULL,     -- session id or user id
+    at INTEGER NOT NULL,       -- when it was revoked in the origin region
+    origin TEXT NOT NULL,      -- region that revoked it
+    event TEXT NOT NULL,       -- the RevocationEvent as JSON
+    UNIQUE (kind, subject, at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_revocation_log_subject ON revocation_log(kind, subject);
+
+-- Newest session assertion generation presented to this region
+CREATE TABLE IF NOT EXISTS session_generations (
+    session_id TEXT PRIMARY KEY,
+    generation INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
@@ -0,0 +1,33 @@
+-- Change tracking for admin list ETags. Any write to a user row, and any
+-- passkey added or removed, moves users.updated_at (unix milliseconds) past
+-- the current maximum, so MAX(updated_at) changes whenever the list does.
+CREATE TRIGGER IF NOT EXISTS users_touch_on_insert AFTER INSERT ON users
+BEGIN
+    UPDATE users SET updated_at = (
+        SELECT MAX(CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER), COALESCE(MAX(updated_at), 0) + 1) FROM users
+    ) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_touch_on_update AFTER UPDATE ON users
+WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+    UPDATE users SET updated_at = (
+        SELECT MAX(CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER), COALESCE(MAX(updated_at), 0) + 1) FROM users
+    ) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_touch_on_credential_insert AFTER INSERT ON webauthn_registrations
+BEGIN
+    UPDATE users SET updated_at = (
+        SELECT MAX(CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER), COALESCE(MAX(updated_at), 0) + 1) FROM users
+    ) WHERE id = NEW.user_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_touch_on_credential_delete AFTER DELETE ON webauthn_registrations
+BEGIN
+    UPDATE users SET updated_at = (
+        SELECT MAX(CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER), COALESCE(MAX(updated_at), 0) + 1) FROM users
+    ) WHERE id = OLD.user_id;
+END;
+
+ALTER TABLE users ADD COLUMN updated_at INTEGER;
@@ -0,0 +1,2 @@
+-- Issue time of magic links, for the [magic_links] resend window
+ALTER TABLE magic_links ADD COLUMN created_at INTEGER;
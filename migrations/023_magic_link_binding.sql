@@ -0,0 +1,3 @@
+-- Optional client binding of magic links: PKCE S256 challenge and SHA-256 of `state`
+ALTER TABLE magic_links ADD COLUMN code_challenge TEXT;
+ALTER TABLE magic_links ADD COLUMN state_hash TEXT;
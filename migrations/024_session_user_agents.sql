@@ -0,0 +1,7 @@
+-- Browser and OS parsed from the User-Agent a session was created with,
+-- carried over to each rotated refresh token of the session
+ALTER TABLE refresh_tokens ADD COLUMN ua_browser TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN ua_browser_version TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN ua_os TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN ua_os_version TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN ua_device TEXT;
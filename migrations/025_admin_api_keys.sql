@@ -0,0 +1,10 @@
+-- API keys for /admin/* when [admin] require_api_key is set; only hashes are stored
+CREATE TABLE IF NOT EXISTS admin_api_keys (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    user_id TEXT,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER,
+    revoked_at INTEGER
+);
@@ -0,0 +1,3 @@
+-- Login-flow correlation id shared by a magic link request, its email and its verification
+ALTER TABLE magic_links ADD COLUMN flow_id TEXT;
+CREATE INDEX IF NOT EXISTS idx_magic_links_flow_id ON magic_links(flow_id);
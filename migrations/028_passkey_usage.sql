@@ -0,0 +1,4 @@
+-- Per-credential usage, so users can tell which passkeys are stale
+ALTER TABLE webauthn_registrations ADD COLUMN last_used_at INTEGER;
+ALTER TABLE webauthn_registrations ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE webauthn_registrations ADD COLUMN last_ip TEXT;
@@ -0,0 +1,2 @@
+-- When the session_expiring webhook was queued for this refresh token
+ALTER TABLE refresh_tokens ADD COLUMN expiry_notified_at INTEGER;
@@ -0,0 +1,10 @@
+-- "This wasn't me" reports: who requested each magic link, and IPs reported for unsolicited links
+ALTER TABLE magic_links ADD COLUMN requested_ip TEXT;
+ALTER TABLE magic_links ADD COLUMN reported_at INTEGER;
+
+CREATE TABLE IF NOT EXISTS ip_reputation (
+    ip TEXT PRIMARY KEY,
+    -- reports within the current [abuse_reports] window
+    reports INTEGER NOT NULL DEFAULT 0,
+    last_reported_at INTEGER NOT NULL
+);
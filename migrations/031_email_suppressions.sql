@@ -0,0 +1,9 @@
+-- Addresses that must not be emailed (hard bounces, complaints, manual blocks)
+CREATE TABLE IF NOT EXISTS email_suppressions (
+    email TEXT PRIMARY KEY COLLATE NOCASE,
+    reason TEXT NOT NULL,
+    source TEXT NOT NULL,
+    note TEXT,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_email_suppressions_created ON email_suppressions(created_at);
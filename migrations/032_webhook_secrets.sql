@@ -0,0 +1,8 @@
+-- Webhook signing secrets; retired ones keep signing until expires_at
+CREATE TABLE IF NOT EXISTS webhook_secrets (
+    version INTEGER PRIMARY KEY AUTOINCREMENT,
+    secret TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    retired_at INTEGER,
+    expires_at INTEGER
+);
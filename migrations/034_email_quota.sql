@@ -0,0 +1,7 @@
+-- Emails sent to each user, for the sliding-window email budget
+CREATE TABLE IF NOT EXISTS email_sends (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    sent_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_email_sends_user ON email_sends(user_id, sent_at);
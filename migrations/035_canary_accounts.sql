@@ -0,0 +1,9 @@
+-- Decoy addresses: any sign-in attempt against one raises a security alert
+CREATE TABLE IF NOT EXISTS canary_accounts (
+    email TEXT PRIMARY KEY,
+    note TEXT,
+    ban_ip INTEGER NOT NULL DEFAULT 1,
+    created_at INTEGER NOT NULL,
+    triggers INTEGER NOT NULL DEFAULT 0,
+    last_triggered_at INTEGER
+);
@@ -0,0 +1,26 @@
+-- Admin announcements to a segment of users, sent through the email queue
+CREATE TABLE IF NOT EXISTS broadcasts (
+    id TEXT PRIMARY KEY,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    category TEXT NOT NULL,
+    segment TEXT NOT NULL,
+    status TEXT NOT NULL, -- queued, sending, completed, canceled
+    created_at INTEGER NOT NULL,
+    started_at INTEGER,
+    finished_at INTEGER
+);
+
+-- Recipients are fixed when the broadcast is created
+CREATE TABLE IF NOT EXISTS broadcast_recipients (
+    broadcast_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending', -- pending, queued, skipped, canceled
+    skip_reason TEXT,
+    email_id TEXT,
+    updated_at INTEGER,
+    PRIMARY KEY (broadcast_id, user_id),
+    FOREIGN KEY(broadcast_id) REFERENCES broadcasts(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_broadcast_recipients_pending ON broadcast_recipients(status, broadcast_id);
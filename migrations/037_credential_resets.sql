@@ -0,0 +1,50 @@
+-- Incident-response resets: revoke sessions and links, optionally remove
+-- second factors, and email the affected users to sign in again
+CREATE TABLE IF NOT EXISTS credential_resets (
+    id TEXT PRIMARY KEY,
+    scope TEXT NOT NULL, -- JSON: {"user_ids": [...]} or {"all_users": true}
+    reset_totp INTEGER NOT NULL DEFAULT 0,
+    reset_passkeys INTEGER NOT NULL DEFAULT 0,
+    notify INTEGER NOT NULL DEFAULT 1,
+    reason TEXT,
+    status TEXT NOT NULL, -- queued, running, completed, canceled, rolled_back
+    created_at INTEGER NOT NULL,
+    started_at INTEGER,
+    finished_at INTEGER
+);
+
+-- Users are fixed when the reset is created
+CREATE TABLE IF NOT EXISTS credential_reset_targets (
+    reset_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending', -- pending, done, canceled, rolled_back
+    sessions_revoked INTEGER NOT NULL DEFAULT 0,
+    challenges_invalidated INTEGER NOT NULL DEFAULT 0,
+    totp_removed INTEGER NOT NULL DEFAULT 0,
+    passkeys_removed INTEGER NOT NULL DEFAULT 0,
+    -- kept for rollback until the rollback window closes
+    saved_totp_secret TEXT,
+    email_id TEXT,
+    updated_at INTEGER,
+    PRIMARY KEY (reset_id, user_id),
+    FOREIGN KEY(reset_id) REFERENCES credential_resets(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_credential_reset_targets_pending ON credential_reset_targets(status, reset_id);
+
+-- Passkeys removed by a reset, kept for rollback until the rollback window closes
+CREATE TABLE IF NOT EXISTS credential_reset_passkeys (
+    reset_id TEXT NOT NULL,
+    id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    credential_id BLOB NOT NULL,
+    public_key BLOB NOT NULL,
+    sign_count INTEGER NOT NULL,
+    transports TEXT,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    last_ip TEXT,
+    PRIMARY KEY (reset_id, id),
+    FOREIGN KEY(reset_id) REFERENCES credential_resets(id) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+-- Refresh and magic link tokens are stored as their hex SHA-256
+-- (`crypto::token_digest`); hash the rows written in plaintext. Digests are
+-- 64 characters and plaintext tokens 36, so re-running this changes nothing.
+UPDATE refresh_tokens SET token = sha256_hex(token) WHERE length(token) != 64;
+UPDATE refresh_tokens SET replaced_by = sha256_hex(replaced_by) WHERE length(replaced_by) != 64;
+UPDATE magic_links SET token = sha256_hex(token) WHERE length(token) != 64;
@@ -0,0 +1,19 @@
+-- Inactivity policy: accounts without a sign-in are flagged, then disabled
+-- and eventually purged unless the user signs in again
+ALTER TABLE users ADD COLUMN last_login_at INTEGER;
+
+-- Best guess for existing accounts: when their newest session was created
+UPDATE users SET last_login_at = (SELECT MAX(created_at) FROM refresh_tokens r WHERE r.user_id = users.id)
+WHERE last_login_at IS NULL;
+
+-- Current state per account; purged rows outlive the user
+CREATE TABLE IF NOT EXISTS stale_accounts (
+    user_id TEXT PRIMARY KEY,
+    state TEXT NOT NULL, -- flagged, disabled, purged, reactivated, reinstated
+    flagged_at INTEGER NOT NULL,
+    email_id TEXT,
+    disabled_at INTEGER,
+    purged_at INTEGER,
+    updated_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_stale_accounts_state ON stale_accounts(state, updated_at);
@@ -0,0 +1,15 @@
+-- Admin roles for /admin/*: viewer (read), operator (routine changes) and
+-- superadmin (destructive changes and managing admins)
+CREATE TABLE IF NOT EXISTS admin_users (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'operator', 'superadmin')),
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- Users given the `admin` role by `passwordless-auth init` keep full access.
+INSERT OR IGNORE INTO admin_users (user_id, role, created_at, updated_at)
+SELECT id, 'superadmin', strftime('%s', 'now'), strftime('%s', 'now') FROM users WHERE role = 'admin';
+
+-- Existing keys keep full access.
+ALTER TABLE admin_api_keys ADD COLUMN role TEXT NOT NULL DEFAULT 'superadmin';
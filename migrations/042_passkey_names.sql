@@ -0,0 +1,13 @@
+-- Authenticator model names by AAGUID, fetched by the `[aaguids]` refresh job
+CREATE TABLE IF NOT EXISTS aaguid_names (
+    aaguid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- Authenticator model and display name of each passkey, also kept while a
+-- credential reset holds it for rollback
+ALTER TABLE webauthn_registrations ADD COLUMN aaguid TEXT;
+ALTER TABLE webauthn_registrations ADD COLUMN name TEXT;
+ALTER TABLE credential_reset_passkeys ADD COLUMN aaguid TEXT;
+ALTER TABLE credential_reset_passkeys ADD COLUMN name TEXT;
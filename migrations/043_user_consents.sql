@@ -0,0 +1,9 @@
+-- Profile data scopes (email, profile, phone) each user approved per application
+CREATE TABLE IF NOT EXISTS user_consents (
+    user_id TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    scope TEXT NOT NULL CHECK (scope IN ('email', 'profile', 'phone')),
+    granted_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, client_id, scope),
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+-- Generated access-token signing keys of `[key_rotation]`; each signs from
+-- activates_at until retires_at and is deleted once no longer published
+CREATE TABLE IF NOT EXISTS keys (
+    kid TEXT PRIMARY KEY,
+    algorithm TEXT NOT NULL,
+    private_key TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    activates_at INTEGER NOT NULL,
+    retires_at INTEGER
+);
@@ -0,0 +1,18 @@
+-- Relying applications registered at runtime through `POST /oauth/register`
+-- (RFC 7591), next to the `[[applications]]` of config.toml. Only approved
+-- clients can authenticate or redirect; open registrations wait as pending.
+CREATE TABLE IF NOT EXISTS applications (
+    client_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- `crypto::token_digest` of the client secret; NULL for public clients
+    secret_hash TEXT,
+    redirect_uris TEXT NOT NULL,
+    scopes TEXT NOT NULL DEFAULT '[]',
+    status TEXT NOT NULL CHECK (status IN ('pending', 'approved', 'rejected')),
+    -- admin API key that registered the client, if any
+    registered_by TEXT,
+    created_at INTEGER NOT NULL,
+    reviewed_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_applications_status ON applications(status, created_at);
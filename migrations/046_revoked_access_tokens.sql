@@ -0,0 +1,10 @@
+-- Access tokens revoked one by one at `POST /oauth/revoke` (RFC 7009), by
+-- their `jti`. Kept until the token expires and reloaded at startup, so a
+-- restarted instance keeps rejecting them.
+CREATE TABLE IF NOT EXISTS revoked_access_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at INTEGER NOT NULL,
+    revoked_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_revoked_access_tokens_expires ON revoked_access_tokens(expires_at);
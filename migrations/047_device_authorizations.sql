@@ -0,0 +1,20 @@
+-- Pending device authorization grants (RFC 8628). A row lives from
+-- `POST /device/code` until the device redeems the approval at
+-- `POST /device/token`, is denied, or the codes expire.
+CREATE TABLE IF NOT EXISTS device_authorizations (
+    -- `crypto::token_digest` of the device code; the code itself is only
+    -- known to the device
+    device_code_hash TEXT PRIMARY KEY,
+    -- normalized: upper case, without the separator
+    user_code TEXT NOT NULL UNIQUE,
+    client_id TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'denied')),
+    -- the user who approved or denied the request
+    user_id TEXT REFERENCES users(id) ON DELETE CASCADE,
+    interval_seconds INTEGER NOT NULL,
+    last_polled_at INTEGER,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_authorizations_expires ON device_authorizations(expires_at);
@@ -0,0 +1,3 @@
+-- Application a session was signed in to; its token lifetimes and audiences
+-- apply on every refresh, carried across refresh token rotations
+ALTER TABLE refresh_tokens ADD COLUMN client_id TEXT;
@@ -0,0 +1,3 @@
+-- Random id of the current attempt window; attempt tokens name it instead of
+-- the user id
+ALTER TABLE totp_attempts ADD COLUMN window_id TEXT;
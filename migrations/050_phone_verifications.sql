@@ -0,0 +1,10 @@
+-- A phone number waiting for its SMS code; users.phone is only set once the
+-- code is confirmed
+CREATE TABLE IF NOT EXISTS phone_verifications (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    phone TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Read-only switch shared by every instance on this database
+CREATE TABLE IF NOT EXISTS maintenance_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    read_only INTEGER NOT NULL DEFAULT 0,
+    reason TEXT,
+    since INTEGER,
+    updated_at INTEGER NOT NULL
+);
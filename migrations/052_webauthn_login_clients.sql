@@ -0,0 +1,3 @@
+-- Application a passkey login ceremony was started for; tokens from the
+-- assertion are issued to it rather than to the completing request's client
+ALTER TABLE pending_webauthn ADD COLUMN client_id TEXT;
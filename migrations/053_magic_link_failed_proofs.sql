@@ -0,0 +1,3 @@
+-- Wrong code_verifier/state presentations per bound link; the link is
+-- expired once it reaches magic_link::MAX_FAILED_PROOFS
+ALTER TABLE magic_links ADD COLUMN failed_proofs INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,51 @@
+-- Core records for the PostgreSQL storage backend (`storage::postgres`)
+
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    email TEXT UNIQUE NOT NULL,
+    totp_secret TEXT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS magic_links (
+    -- issue order, for the resend and max_outstanding policies
+    seq BIGSERIAL,
+    token TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id),
+    expires_at BIGINT NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT NOT NULL,
+    client_id TEXT,
+    redirect_uri TEXT,
+    code_challenge TEXT,
+    state_hash TEXT,
+    flow_id TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_magic_links_user ON magic_links(user_id, used, expires_at);
+
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id),
+    session_id TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT NOT NULL,
+    rotated_at BIGINT,
+    replaced_by TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session ON refresh_tokens(session_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user ON refresh_tokens(user_id);
+
+CREATE TABLE IF NOT EXISTS webauthn_registrations (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id),
+    credential_id BYTEA UNIQUE NOT NULL,
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL,
+    transports TEXT,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT,
+    use_count BIGINT NOT NULL DEFAULT 0,
+    last_ip TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_webauthn_registrations_user ON webauthn_registrations(user_id);
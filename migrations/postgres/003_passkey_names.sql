@@ -0,0 +1,3 @@
+-- Authenticator model (AAGUID) and display name of each passkey
+ALTER TABLE webauthn_registrations ADD COLUMN IF NOT EXISTS aaguid TEXT;
+ALTER TABLE webauthn_registrations ADD COLUMN IF NOT EXISTS name TEXT;
@@ -0,0 +1,6 @@
+-- The server keeps users in SQLite and only magic links and refresh tokens
+-- here (`store = "postgres"`), so those rows cannot reference this users
+-- table. Sessions remember the application they were signed in to.
+ALTER TABLE magic_links DROP CONSTRAINT IF EXISTS magic_links_user_id_fkey;
+ALTER TABLE refresh_tokens DROP CONSTRAINT IF EXISTS refresh_tokens_user_id_fkey;
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS client_id TEXT;
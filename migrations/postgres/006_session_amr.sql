@@ -0,0 +1,3 @@
+-- Authentication methods (JSON array of RFC 8176 `amr` values) of the
+-- sign-in that started a session; carried across refresh token rotations
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS amr TEXT;
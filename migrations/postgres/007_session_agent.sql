@@ -0,0 +1,3 @@
+-- Browser and OS (JSON `UserAgent`) the session was started from; carried
+-- across refresh token rotations
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS agent TEXT;
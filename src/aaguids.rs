@@ -0,0 +1,276 @@
+//! Friendly passkey names from the authenticator's AAGUID.
+//!
+//! The attested credential data of a registration carries the AAGUID of the
+//! authenticator model. It is looked up in the names fetched from
+//! `[aaguids] source_url` (stored in `aaguid_names`, so every instance sees
+//! them), then in a small built-in list derived from the FIDO Metadata
+//! Service, and the result is stored as the credential's default `name`.
+
+use crate::{db::Database, leader::LeaderElection};
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Client;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// `[aaguids]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct AaguidConfig {
+    /// Periodically fetch AAGUID names from `source_url`
+    #[serde(default)]
+    pub refresh: bool,
+    /// JSON object keyed by AAGUID with a `name` per entry, as published by
+    /// the passkey-authenticator-aaguids project
+    #[serde(default = "default_source_url")]
+    pub source_url: String,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+impl Default for AaguidConfig {
+    fn default() -> Self {
+        Self {
+            refresh: false,
+            source_url: default_source_url(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+fn default_source_url() -> String {
+    "https://raw.githubusercontent.com/passkeydeveloper/passkey-authenticator-aaguids/main/aaguid.json".to_string()
+}
+
+fn default_interval_seconds() -> u64 {
+    86400
+}
+
+/// Common authenticators, used until (and where) fetched names are missing
+const BUILT_IN: &[(&str, &str)] = &[
+    ("ea9b8d66-4d01-1d21-3ce4-b6b48cb575d4", "Google Password Manager"),
+    ("adce0002-35bc-c60a-648b-0b25f1f05503", "Chrome on Mac"),
+    ("b5397666-4885-aa6b-cebf-e52262a439a2", "Chromium Browser"),
+    ("771b48fd-d3d4-4f74-9232-fc157ab0507a", "Edge on Mac"),
+    ("fbfc3007-154e-4ecc-8c0b-6e020557d7bd", "iCloud Keychain"),
+    ("dd4ec289-e01d-41c9-bb89-70fa845d4bf2", "iCloud Keychain (Managed)"),
+    ("08987058-cadc-4b81-b6e1-30de50dcbe96", "Windows Hello"),
+    ("9ddd1817-af5a-4672-a2b9-3e3dd95000a9", "Windows Hello"),
+    ("6028b017-b1d4-4c02-b4b3-afcdafc96bb2", "Windows Hello"),
+    ("53414d53-554e-4700-0000-000000000000", "Samsung Pass"),
+    ("bada5566-a7aa-401f-bd96-45619a55120d", "1Password"),
+    ("d548826e-79b4-db40-a3d8-11116f7e8349", "Bitwarden"),
+    ("531126d6-e717-415c-9320-3d9aa6981239", "Dashlane"),
+    ("0ea242b4-43c4-4a1b-8b17-dd6d0b6baec6", "Keeper"),
+    ("b84e4048-15dc-4dd0-8640-f4f60813c8af", "NordPass"),
+    ("cb69481e-8ff7-4039-93ec-0a2729a154a8", "YubiKey 5 Series"),
+    ("ee882879-721c-4913-9775-3dfcce97072a", "YubiKey 5 Series"),
+    ("fa2b99dc-9e39-4257-8f92-4a30d23c4118", "YubiKey 5 Series with NFC"),
+    ("2fc0579f-8113-47ea-b116-bb5a8db9202a", "YubiKey 5 Series with NFC"),
+    ("c5ef55ff-ad9a-4b9f-b580-adebafe026d0", "YubiKey 5Ci"),
+    ("d8522d9f-575b-4866-88a9-ba99fa02f35b", "YubiKey Bio Series"),
+];
+
+/// Names longer than this in a fetched list are ignored
+const MAX_NAME_LEN: usize = 80;
+
+#[derive(Debug, Error)]
+pub enum AaguidError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("fetch failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("invalid list: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("the list has no usable entries")]
+    Empty,
+}
+
+/// AAGUID of the authenticator in a raw registration response
+/// (`response.attestationObject`); `None` when absent or all zeros, as
+/// authenticators that do not disclose their model report it
+pub fn from_registration(response: &serde_json::Value) -> Option<String> {
+    let encoded = response.get("response")?.get("attestationObject")?.as_str()?;
+    let object = BASE64URL_NOPAD.decode(encoded.trim_end_matches('=').as_bytes()).ok()?;
+    from_attestation_object(&object)
+}
+
+/// AAGUID in a CBOR attestation object. Only the `authData` byte string is
+/// read: rpIdHash (32), flags (1), signCount (4), then the AAGUID (16) when
+/// the attested-credential-data flag is set.
+pub fn from_attestation_object(object: &[u8]) -> Option<String> {
+    // text string "authData" (major type 3, length 8)
+    const KEY: &[u8] = b"\x68authData";
+    let start = object.windows(KEY.len()).position(|w| w == KEY)? + KEY.len();
+    let header = *object.get(start)?;
+    // byte string (major type 2) with its length inline or in 1, 2 or 4 bytes
+    let (len, offset) = match header {
+        0x40..=0x57 => ((header - 0x40) as usize, 1),
+        0x58 => (*object.get(start + 1)? as usize, 2),
+        0x59 => (u16::from_be_bytes(object.get(start + 1..start + 3)?.try_into().ok()?) as usize, 3),
+        0x5a => (u32::from_be_bytes(object.get(start + 1..start + 5)?.try_into().ok()?) as usize, 5),
+        _ => return None,
+    };
+    let auth_data = object.get(start + offset..start + offset + len)?;
+    if auth_data.get(32)? & 0x40 == 0 {
+        return None;
+    }
+    let aaguid = Uuid::from_slice(auth_data.get(37..53)?).ok()?;
+    (!aaguid.is_nil()).then(|| aaguid.to_string())
+}
+
+/// Name of the authenticator model with `aaguid`: fetched names first, then
+/// the built-in list
+pub fn name_for(db: &Database, aaguid: &str) -> Result<Option<String>, rusqlite::Error> {
+    let fetched: Option<String> = db
+        .conn()
+        .query_row("SELECT name FROM aaguid_names WHERE aaguid = ?1", params![aaguid], |r| r.get(0))
+        .optional()?;
+    Ok(fetched.or_else(|| {
+        BUILT_IN
+            .iter()
+            .find(|(id, _)| *id == aaguid)
+            .map(|(_, name)| name.to_string())
+    }))
+}
+
+#[derive(Deserialize)]
+struct ListEntry {
+    name: Option<String>,
+}
+
+/// `(aaguid, name)` pairs of a fetched list; malformed entries are skipped
+pub fn parse_list(json: &str) -> Result<Vec<(String, String)>, serde_json::Error> {
+    let list: HashMap<String, serde_json::Value> = serde_json::from_str(json)?;
+    let mut entries: Vec<(String, String)> = list
+        .into_iter()
+        .filter_map(|(aaguid, entry)| {
+            let aaguid = Uuid::parse_str(&aaguid).ok()?;
+            let name = serde_json::from_value::<ListEntry>(entry).ok()?.name?;
+            let name: String = name.chars().filter(|c| !c.is_control()).collect();
+            let name = name.trim();
+            let usable = !aaguid.is_nil() && !name.is_empty() && name.chars().count() <= MAX_NAME_LEN;
+            usable.then(|| (aaguid.to_string(), name.to_string()))
+        })
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// Replace the stored names with `entries`. Credentials keep the name they
+/// were registered with.
+pub fn store(db: &Database, entries: &[(String, String)], now: i64) -> Result<usize, AaguidError> {
+    if entries.is_empty() {
+        return Err(AaguidError::Empty);
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM aaguid_names", [])?;
+    for (aaguid, name) in entries {
+        tx.execute(
+            "INSERT OR REPLACE INTO aaguid_names (aaguid, name, updated_at) VALUES (?1, ?2, ?3)",
+            params![aaguid, name, now],
+        )?;
+    }
+    tx.commit()?;
+    Ok(entries.len())
+}
+
+/// Fetch the list at `url` and store it
+pub async fn refresh(db: &Database, client: &Client, url: &str) -> Result<usize, AaguidError> {
+    let body = client.get(url).send().await?.error_for_status()?.text().await?;
+    store(db, &parse_list(&body)?, Database::now_ts())
+}
+
+/// Refresh the names every `interval_seconds` on the leader
+pub fn spawn_refresh(db: Arc<Database>, leader: Arc<LeaderElection>, cfg: AaguidConfig) {
+    if !cfg.refresh {
+        return;
+    }
+    let client = Client::builder().timeout(Duration::from_secs(30)).build().unwrap();
+    let every = Duration::from_secs(cfg.interval_seconds.max(60));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("aaguid_names", every) {
+                continue;
+            }
+            match refresh(&db, &client, &cfg.source_url).await {
+                Ok(count) => info!("Refreshed {} authenticator names", count),
+                Err(e) => warn!("Failed to refresh authenticator names: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attestation_object(flags: u8, aaguid: [u8; 16]) -> Vec<u8> {
+        let mut auth_data = vec![0u8; 32];
+        auth_data.push(flags);
+        auth_data.extend_from_slice(&[0, 0, 0, 1]);
+        auth_data.extend_from_slice(&aaguid);
+        auth_data.extend_from_slice(&[0, 4, 1, 2, 3, 4]);
+        // {"fmt": "none", "attStmt": {}, "authData": h'...'}
+        let mut object = vec![0xa3, 0x63];
+        object.extend_from_slice(b"fmt");
+        object.push(0x64);
+        object.extend_from_slice(b"none");
+        object.push(0x67);
+        object.extend_from_slice(b"attStmt");
+        object.push(0xa0);
+        object.push(0x68);
+        object.extend_from_slice(b"authData");
+        object.push(0x58);
+        object.push(auth_data.len() as u8);
+        object.extend_from_slice(&auth_data);
+        object
+    }
+
+    #[test]
+    fn aaguid_is_read_from_attested_credential_data() {
+        let yubikey = *Uuid::parse_str("cb69481e-8ff7-4039-93ec-0a2729a154a8").unwrap().as_bytes();
+        let object = attestation_object(0x45, yubikey);
+        assert_eq!(
+            from_attestation_object(&object).as_deref(),
+            Some("cb69481e-8ff7-4039-93ec-0a2729a154a8")
+        );
+
+        let response = serde_json::json!({
+            "id": "abc",
+            "response": { "attestationObject": BASE64URL_NOPAD.encode(&object), "clientDataJSON": "" },
+        });
+        assert_eq!(
+            from_registration(&response).as_deref(),
+            Some("cb69481e-8ff7-4039-93ec-0a2729a154a8")
+        );
+
+        // no attested credential data, an undisclosed model, or a truncated object
+        assert_eq!(from_attestation_object(&attestation_object(0x05, yubikey)), None);
+        assert_eq!(from_attestation_object(&attestation_object(0x45, [0; 16])), None);
+        assert_eq!(from_attestation_object(&object[..object.len() - 20]), None);
+    }
+
+    #[test]
+    fn fetched_lists_skip_malformed_entries() {
+        let json = r#"{
+            "cb69481e-8ff7-4039-93ec-0a2729a154a8": {"name": "YubiKey 5C", "icon_dark": "data:..."},
+            "not-a-uuid": {"name": "Broken"},
+            "00000000-0000-0000-0000-000000000000": {"name": "Nobody"},
+            "fbfc3007-154e-4ecc-8c0b-6e020557d7bd": {"name": "  iCloud\nKeychain  "},
+            "08987058-cadc-4b81-b6e1-30de50dcbe96": {"icon_light": "data:..."}
+        }"#;
+        assert_eq!(
+            parse_list(json).unwrap(),
+            vec![
+                ("cb69481e-8ff7-4039-93ec-0a2729a154a8".to_string(), "YubiKey 5C".to_string()),
+                ("fbfc3007-154e-4ecc-8c0b-6e020557d7bd".to_string(), "iCloudKeychain".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! "This wasn't me" reports for unsolicited magic links.
+//!
+//! Magic link emails carry a second link to `/report/unsolicited`. A report
+//! expires the link, counts against the IP that requested it in the
+//! `ip_reputation` table and raises a security-severity
+//! `unsolicited_link_reported` event for admins. IPs reported `block_after`
+//! times within the window can no longer request magic links.
+
+use crate::{
+    audit::AuditEventType,
+    crypto,
+    db::Database,
+    outbox::{Outbox, OutboxError, OutboxEvent},
+    webhooks::WebhookEventType,
+};
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+
+/// `[abuse_reports]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct AbuseReportConfig {
+    /// Add the report link to magic link emails
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Report page; the link token is appended as `?token=`
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    /// How long a report counts against the requesting IP
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: i64,
+    /// Reports within the window after which an IP may not request magic
+    /// links; 0 only flags the IP
+    #[serde(default = "default_block_after")]
+    pub block_after: i64,
+}
+
+impl Default for AbuseReportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            base_url: default_base_url(),
+            window_seconds: default_window_seconds(),
+            block_after: default_block_after(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_base_url() -> String {
+    "http://localhost:3000/report/unsolicited".to_string()
+}
+
+fn default_window_seconds() -> i64 {
+    7 * 86400
+}
+
+fn default_block_after() -> i64 {
+    3
+}
+
+impl AbuseReportConfig {
+    /// Report link for a magic link token, when reports are enabled
+    pub fn link(&self, token: &str) -> Option<String> {
+        self.enabled.then(|| format!("{}?token={}", self.base_url, token))
+    }
+}
+
+/// A reported magic link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub user_id: String,
+    pub flow_id: Option<String>,
+    pub requested_ip: Option<String>,
+    /// The link was still usable and has been expired
+    pub invalidated: bool,
+    /// The link had been reported before; nothing was counted again
+    pub duplicate: bool,
+}
+
+/// `user_id, flow_id, requested_ip, used, expires_at, reported_at` of a reported link
+type LinkRow = (String, Option<String>, Option<String>, bool, i64, Option<i64>);
+
+/// Record a report against the magic link `token`. Returns `None` for
+/// unknown tokens. Each link counts against its requesting IP only once.
+pub fn report(db: &Database, token: &str, cfg: &AbuseReportConfig) -> Result<Option<Report>, OutboxError> {
+    let now = Database::now_ts();
+    let digest = crypto::token_digest(token);
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let link: Option<LinkRow> = tx
+        .query_row(
+            "SELECT user_id, flow_id, requested_ip, used, expires_at, reported_at FROM magic_links WHERE token = ?1",
+            params![digest],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get::<_, i64>(3)? != 0, r.get(4)?, r.get(5)?)),
+        )
+        .optional()?;
+    let Some((user_id, flow_id, requested_ip, used, expires_at, reported_at)) = link else {
+        return Ok(None);
+    };
+    if reported_at.is_some() {
+        return Ok(Some(Report {
+            user_id,
+            flow_id,
+            requested_ip,
+            invalidated: false,
+            duplicate: true,
+        }));
+    }
+
+    let invalidated = !used && expires_at >= now;
+    // expired rather than deleted, like links invalidated by an admin
+    tx.execute(
+        "UPDATE magic_links SET reported_at = ?1, expires_at = MIN(expires_at, ?2) WHERE token = ?3",
+        params![now, now - 1, digest],
+    )?;
+    let mut reports = None;
+    if let Some(ip) = &requested_ip {
+        reports = Some(tx.query_row(
+            "INSERT INTO ip_reputation (ip, reports, last_reported_at) VALUES (?1, 1, ?2)
+             ON CONFLICT(ip) DO UPDATE SET
+                 reports = CASE WHEN last_reported_at < ?2 - ?3 THEN 1 ELSE reports + 1 END,
+                 last_reported_at = ?2
+             RETURNING reports",
+            params![ip, now, cfg.window_seconds],
+            |r| r.get::<_, i64>(0),
+        )?);
+    }
+    let event = OutboxEvent::new(AuditEventType::UnsolicitedLinkReported)
+        .user(&user_id)
+        .webhook(WebhookEventType::UnsolicitedLinkReported)
+        .metadata(serde_json::json!({
+            "flow_id": flow_id,
+            "requested_ip": requested_ip,
+            "ip_reports": reports,
+            "link_used": used,
+        }));
+    Outbox::enqueue(&tx, &event)?;
+    tx.commit()?;
+    Ok(Some(Report {
+        user_id,
+        flow_id,
+        requested_ip,
+        invalidated,
+        duplicate: false,
+    }))
+}
+
+/// Whether `ip` has been reported too often to request magic links
+pub fn is_blocked(db: &Database, ip: &str, cfg: &AbuseReportConfig) -> Result<bool, rusqlite::Error> {
+    if cfg.block_after <= 0 {
+        return Ok(false);
+    }
+    let reports: Option<i64> = db
+        .conn()
+        .query_row(
+            "SELECT reports FROM ip_reputation WHERE ip = ?1 AND last_reported_at >= ?2",
+            params![ip, Database::now_ts() - cfg.window_seconds],
+            |r| r.get(0),
+        )
+        .optional()?;
+    Ok(reports.is_some_and(|reports| reports >= cfg.block_after))
+}
@@ -0,0 +1,139 @@
+use crate::{
+    crypto::{constant_time_eq, hmac_sha256},
+    db::Database,
+};
+use data_encoding::BASE64URL_NOPAD;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// What a signed action link authorizes when followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionPurpose {
+    /// Mark the user's email address as verified
+    VerifyEmail,
+    /// Revoke a session (payload: `session_id`)
+    RevokeSession,
+    /// Switch the account to a new email address (payload: `new_email`)
+    ConfirmEmailChange,
+    /// Block new sign-ins and revoke every session (from security notices)
+    FreezeAccount,
+}
+
+impl ActionPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::VerifyEmail => "verify_email",
+            Self::RevokeSession => "revoke_session",
+            Self::ConfirmEmailChange => "confirm_email_change",
+            Self::FreezeAccount => "freeze_account",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "verify_email" => Some(Self::VerifyEmail),
+            "revoke_session" => Some(Self::RevokeSession),
+            "confirm_email_change" => Some(Self::ConfirmEmailChange),
+            "freeze_account" => Some(Self::FreezeAccount),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ActionLinkError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("invalid or expired link")]
+    Invalid,
+    #[error("link already used")]
+    Used,
+}
+
+/// A consumed action link, ready to be dispatched
+#[derive(Debug)]
+pub struct ActionLink {
+    pub id: String,
+    pub purpose: ActionPurpose,
+    pub user_id: String,
+    pub payload: serde_json::Value,
+}
+
+/// Links are signed with a key derived from `jwt_secret` for this purpose
+/// only, so no other token signed with the secret can pass as one
+fn signature(secret: &str, id: &str, purpose: ActionPurpose, expires_at: i64) -> String {
+    let key = hmac_sha256(secret.as_bytes(), b"action-link");
+    let data = format!("{}|{}|{}", id, purpose.as_str(), expires_at);
+    BASE64URL_NOPAD.encode(&hmac_sha256(&key, data.as_bytes()))
+}
+
+impl ActionLink {
+    /// Create a single-use link and return its signed token (`<id>.<signature>`)
+    pub fn issue(
+        db: &Database,
+        secret: &str,
+        purpose: ActionPurpose,
+        user_id: &str,
+        payload: &serde_json::Value,
+        ttl_seconds: i64,
+    ) -> Result<String, ActionLinkError> {
+        let id = Uuid::new_v4().to_string();
+        let now = Database::now_ts();
+        let expires_at = now + ttl_seconds;
+        db.conn().execute(
+            "INSERT INTO action_links (id, purpose, user_id, payload, expires_at, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, purpose.as_str(), user_id, payload.to_string(), expires_at, now],
+        )?;
+        Ok(format!("{}.{}", id, signature(secret, &id, purpose, expires_at)))
+    }
+
+    /// Verify the signature and mark the link used; succeeds at most once per link
+    pub fn consume(db: &Database, secret: &str, token: &str) -> Result<Self, ActionLinkError> {
+        let (id, sig) = token.split_once('.').ok_or(ActionLinkError::Invalid)?;
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
+            "SELECT purpose, user_id, payload, expires_at, used_at FROM action_links WHERE id = ?1",
+        )?;
+        let mut rows = stmt.query(params![id])?;
+        let row = rows.next()?.ok_or(ActionLinkError::Invalid)?;
+        let purpose: String = row.get(0)?;
+        let user_id: String = row.get(1)?;
+        let payload: Option<String> = row.get(2)?;
+        let expires_at: i64 = row.get(3)?;
+        let used_at: Option<i64> = row.get(4)?;
+
+        let purpose = ActionPurpose::parse(&purpose).ok_or(ActionLinkError::Invalid)?;
+        let expected = signature(secret, id, purpose, expires_at);
+        if !constant_time_eq(expected.as_bytes(), sig.as_bytes()) {
+            return Err(ActionLinkError::Invalid);
+        }
+        if used_at.is_some() {
+            return Err(ActionLinkError::Used);
+        }
+        let now = Database::now_ts();
+        if now > expires_at {
+            return Err(ActionLinkError::Invalid);
+        }
+
+        // guard against a concurrent consume of the same link
+        let updated = db.conn().execute(
+            "UPDATE action_links SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL",
+            params![now, id],
+        )?;
+        if updated == 0 {
+            return Err(ActionLinkError::Used);
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            purpose,
+            user_id,
+            payload: payload
+                .and_then(|p| serde_json::from_str(&p).ok())
+                .unwrap_or(serde_json::Value::Null),
+        })
+    }
+}
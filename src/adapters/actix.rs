@@ -0,0 +1,275 @@
+use crate::{
+    db::Database,
+    ip_bans,
+    load_shed::{self, Admission},
+    magic_link::{LinkBinding, LinkProof, FLOW_HEADER},
+    service::{AuthService, ServiceError},
+};
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{HttpServiceFactory, ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    middleware::{from_fn, Next},
+    web, HttpRequest, HttpResponse, Route,
+};
+use serde::Deserialize;
+
+/// Register the auth routes on an actix-web app:
+///
+/// ```ignore
+/// App::new().configure(passwordless_auth::adapters::actix::configure(service))
+/// ```
+pub fn configure(service: AuthService) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(web::Data::new(service))
+            .service(guarded("/request/magic", web::post().to(request_magic)))
+            .service(guarded("/verify/magic", web::get().to(verify_magic)))
+            .service(guarded("/totp/enroll", web::post().to(totp_enroll)))
+            .service(guarded("/totp/verify", web::post().to(totp_verify)))
+            .service(guarded("/token/refresh", web::post().to(refresh_token)))
+            .service(guarded("/webauthn/register/options", web::post().to(webauthn_register_options)))
+            .service(guarded("/webauthn/register/complete", web::post().to(webauthn_register_complete)))
+            .service(guarded("/webauthn/login/options", web::post().to(webauthn_login_options)))
+            .service(guarded("/webauthn/login/complete", web::post().to(webauthn_login_complete)));
+    }
+}
+
+/// `route` behind the IP bans, load shedding and `Cache-Control: no-store`
+/// the bundled router puts in front of its auth routes
+fn guarded(path: &str, route: Route) -> impl HttpServiceFactory {
+    web::resource(path).route(route).wrap(from_fn(protect))
+}
+
+async fn protect(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(svc) = req.app_data::<web::Data<AuthService>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let state = svc.state();
+    let ip = client_ip(req.request(), state.cfg.ip_access.trust_forwarded_for);
+    if let Some(wait) = ip.as_deref().and_then(|ip| state.ip_bans.banned_for(ip)) {
+        let response = HttpResponse::Forbidden()
+            .insert_header((header::RETRY_AFTER, wait.to_string()))
+            .body(ip_bans::BANNED_MESSAGE);
+        return Ok(req.into_response(response));
+    }
+    let _slot = match state.shedder.admit_request(req.path()).await {
+        Admission::Shed => {
+            let response = HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, state.shedder.retry_after_seconds().to_string()))
+                .body(load_shed::BUSY_MESSAGE);
+            return Ok(req.into_response(response));
+        }
+        admission => admission,
+    };
+
+    let mut response = next.call(req).await?;
+    if let (Some(ip), Ok(status)) = (&ip, axum::http::StatusCode::from_u16(response.status().as_u16())) {
+        state.ip_bans.observe(ip, status);
+    }
+    // token responses must not land in browser or proxy caches
+    let headers = response.headers_mut();
+    if !headers.contains_key(header::CACHE_CONTROL) {
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    }
+    Ok(response.map_into_boxed_body())
+}
+
+/// The address `middleware::client_ip` picks for the same request on the
+/// axum router
+fn client_ip(req: &HttpRequest, trust_forwarded_for: bool) -> Option<String> {
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+    trust_forwarded_for
+        .then(|| {
+            header("X-Forwarded-For")
+                .and_then(|v| v.split(',').next())
+                .map(|ip| ip.trim().to_string())
+                .or_else(|| header("X-Real-IP").map(str::to_string))
+        })
+        .flatten()
+        .or_else(|| req.peer_addr().map(|addr| addr.ip().to_string()))
+}
+
+#[derive(Deserialize)]
+struct EmailBody {
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct RequestMagicBody {
+    email: String,
+    #[serde(default)]
+    redirect_uri: Option<String>,
+    #[serde(default)]
+    code_challenge: Option<String>,
+    #[serde(default)]
+    code_challenge_method: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VerifyQuery {
+    token: String,
+    #[serde(flatten)]
+    proof: LinkProof,
+}
+
+#[derive(Deserialize)]
+struct TotpVerifyBody {
+    email: String,
+    code: String,
+    #[serde(default)]
+    attempt_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RefreshBody {
+    refresh_token: String,
+}
+
+#[derive(Deserialize)]
+struct PendingBody {
+    pending_id: String,
+    response: serde_json::Value,
+}
+
+fn error_response(e: ServiceError) -> HttpResponse {
+    let status = StatusCode::from_u16(e.status().as_u16())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = HttpResponse::build(status);
+    if let ServiceError::EmailQuotaExceeded { reset_at } = e {
+        let wait = (reset_at - Database::now_ts()).max(1);
+        response.insert_header((header::RETRY_AFTER, wait.to_string()));
+    }
+    if let ServiceError::ReadOnly { retry_after } = e {
+        response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+    }
+    response.body(e.public_message())
+}
+
+async fn request_magic(
+    req: HttpRequest,
+    svc: web::Data<AuthService>,
+    body: web::Json<RequestMagicBody>,
+) -> HttpResponse {
+    let binding = match LinkBinding::new(
+        body.code_challenge.as_deref(),
+        body.code_challenge_method.as_deref(),
+        body.state.as_deref(),
+    ) {
+        Ok(binding) => binding,
+        Err(e) => return error_response(e.into()),
+    };
+    let ip = client_ip(&req, svc.state().cfg.ip_access.trust_forwarded_for);
+    match svc
+        .as_ref()
+        .clone()
+        .with_ip(ip)
+        .request_magic(&body.email, body.redirect_uri.as_deref(), &binding)
+        .await
+    {
+        Ok(flow_id) => HttpResponse::Ok()
+            .insert_header((FLOW_HEADER, flow_id))
+            .body("magic link sent"),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn verify_magic(svc: web::Data<AuthService>, q: web::Query<VerifyQuery>) -> HttpResponse {
+    match svc.verify_magic(&q.token, &q.proof).await {
+        Ok(resp) => HttpResponse::Ok().json(resp),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn totp_enroll(svc: web::Data<AuthService>, body: web::Json<EmailBody>) -> HttpResponse {
+    match svc.totp_enroll(&body.email).await {
+        Ok(resp) => HttpResponse::Ok().json(resp),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn totp_verify(svc: web::Data<AuthService>, body: web::Json<TotpVerifyBody>) -> HttpResponse {
+    match svc
+        .totp_verify(&body.email, &body.code, body.attempt_token.as_deref())
+        .await
+    {
+        Ok(resp) => HttpResponse::Ok().json(resp),
+        Err(ServiceError::InvalidTotpRetry {
+            attempt_token,
+            remaining_attempts,
+        }) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "invalid totp",
+            "attempt_token": attempt_token,
+            "remaining_attempts": remaining_attempts,
+        })),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn refresh_token(svc: web::Data<AuthService>, body: web::Json<RefreshBody>) -> HttpResponse {
+    match svc.refresh(&body.refresh_token).await {
+        Ok(resp) => HttpResponse::Ok().json(resp),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn webauthn_register_options(
+    svc: web::Data<AuthService>,
+    body: web::Json<EmailBody>,
+) -> HttpResponse {
+    match svc.webauthn_register_options(&body.email).await {
+        Ok(opts) => HttpResponse::Ok().json(opts),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn webauthn_register_complete(
+    svc: web::Data<AuthService>,
+    body: web::Json<PendingBody>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    match svc
+        .webauthn_register_complete(&body.pending_id, body.response)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().body("registered"),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn webauthn_login_options(
+    svc: web::Data<AuthService>,
+    body: web::Json<EmailBody>,
+) -> HttpResponse {
+    match svc.webauthn_login_options(&body.email).await {
+        Ok(opts) => HttpResponse::Ok().json(opts),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn webauthn_login_complete(
+    req: HttpRequest,
+    svc: web::Data<AuthService>,
+    body: web::Json<PendingBody>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let ip = client_ip(&req, svc.state().cfg.ip_access.trust_forwarded_for);
+    match svc
+        .as_ref()
+        .clone()
+        .with_ip(ip)
+        .webauthn_login_complete(&body.pending_id, body.response)
+        .await
+    {
+        Ok(resp) => HttpResponse::Ok().json(resp),
+        Err(e) => error_response(e),
+    }
+}
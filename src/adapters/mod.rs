@@ -0,0 +1,7 @@
+//! Adapters exposing [`AuthService`](crate::service::AuthService) to HTTP
+//! stacks other than the bundled axum router.
+
+pub mod tower;
+
+#[cfg(feature = "actix")]
+pub mod actix;
@@ -0,0 +1,109 @@
+use crate::{
+    ip_bans, load_shed,
+    magic_link::{LinkBinding, LinkProof},
+    middleware::no_store,
+    routes::{router, AppState},
+    service::{AuthResponse, AuthService, ServiceError, TotpEnrollResp},
+};
+use axum::{body::Body, middleware::{from_fn, from_fn_with_state}, routing::RouterIntoService};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::Service;
+use webauthn_rs_core::proto::{CreationChallengeResponse, RequestChallengeResponse};
+
+/// Domain-level operations accepted by the tower facade
+#[derive(Debug)]
+pub enum AuthRequest {
+    RequestMagic { email: String, redirect_uri: Option<String>, binding: LinkBinding },
+    VerifyMagic { token: String, proof: LinkProof },
+    TotpEnroll { email: String },
+    TotpVerify { email: String, code: String, attempt_token: Option<String> },
+    Refresh { refresh_token: String },
+    WebauthnRegisterOptions { email: String },
+    WebauthnRegisterComplete { pending_id: String, response: serde_json::Value },
+    WebauthnLoginOptions { email: String },
+    WebauthnLoginComplete { pending_id: String, response: serde_json::Value },
+}
+
+/// Result of a successful [`AuthRequest`]
+pub enum AuthReply {
+    /// Magic link was sent; `flow_id` correlates it with its verification
+    MagicLinkSent { flow_id: String },
+    /// A login flow completed and tokens were issued
+    Tokens(AuthResponse),
+    /// TOTP secret was generated
+    TotpEnrollment(TotpEnrollResp),
+    /// WebAuthn registration challenge
+    RegistrationOptions(CreationChallengeResponse),
+    /// WebAuthn credential was stored
+    Registered,
+    /// WebAuthn login challenge
+    LoginOptions(RequestChallengeResponse),
+}
+
+impl Service<AuthRequest> for AuthService {
+    type Response = AuthReply;
+    type Error = ServiceError;
+    type Future = Pin<Box<dyn Future<Output = Result<AuthReply, ServiceError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: AuthRequest) -> Self::Future {
+        let svc = self.clone();
+        Box::pin(async move {
+            match req {
+                AuthRequest::RequestMagic { email, redirect_uri, binding } => svc
+                    .request_magic(&email, redirect_uri.as_deref(), &binding)
+                    .await
+                    .map(|flow_id| AuthReply::MagicLinkSent { flow_id }),
+                AuthRequest::VerifyMagic { token, proof } => {
+                    svc.verify_magic(&token, &proof).await.map(AuthReply::Tokens)
+                }
+                AuthRequest::TotpEnroll { email } => {
+                    svc.totp_enroll(&email).await.map(AuthReply::TotpEnrollment)
+                }
+                AuthRequest::TotpVerify { email, code, attempt_token } => svc
+                    .totp_verify(&email, &code, attempt_token.as_deref())
+                    .await
+                    .map(AuthReply::Tokens),
+                AuthRequest::Refresh { refresh_token } => {
+                    svc.refresh(&refresh_token).await.map(AuthReply::Tokens)
+                }
+                AuthRequest::WebauthnRegisterOptions { email } => svc
+                    .webauthn_register_options(&email)
+                    .await
+                    .map(AuthReply::RegistrationOptions),
+                AuthRequest::WebauthnRegisterComplete { pending_id, response } => svc
+                    .webauthn_register_complete(&pending_id, response)
+                    .await
+                    .map(|_| AuthReply::Registered),
+                AuthRequest::WebauthnLoginOptions { email } => svc
+                    .webauthn_login_options(&email)
+                    .await
+                    .map(AuthReply::LoginOptions),
+                AuthRequest::WebauthnLoginComplete { pending_id, response } => svc
+                    .webauthn_login_complete(&pending_id, response)
+                    .await
+                    .map(AuthReply::Tokens),
+            }
+        })
+    }
+}
+
+/// HTTP-level `tower::Service` serving the auth routes, for mounting under
+/// plain hyper or any other tower-compatible server. Applies the same IP bans,
+/// load shedding and `Cache-Control: no-store` as the bundled server.
+pub fn http_service(state: AppState) -> RouterIntoService<Body> {
+    let shedder = state.shedder.clone();
+    let ban_guard = state.ip_bans.clone();
+    router(state)
+        .layer(from_fn_with_state(shedder, load_shed::shed))
+        .layer(from_fn(no_store))
+        .layer(from_fn_with_state(ban_guard, ip_bans::enforce))
+        .into_service()
+}
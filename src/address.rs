@@ -0,0 +1,114 @@
+use std::fmt;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+/// RFC 5321 limits, in octets
+const MAX_LOCAL_LEN: usize = 64;
+const MAX_DOMAIN_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("email address must contain @")]
+    MissingAt,
+    #[error("email local part is empty or longer than 64 bytes")]
+    LocalLength,
+    #[error("email local part contains characters that are not allowed")]
+    InvalidLocal,
+    #[error("email domain is not a valid (internationalized) domain name")]
+    InvalidDomain,
+}
+
+/// A parsed, normalized email address (RFC 6531 / RFC 6532).
+///
+/// The local part is kept as typed apart from Unicode NFC normalization
+/// (it is case-sensitive by the standard, and some providers treat it so);
+/// the domain is stored in its ASCII (punycode) form, lowercased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailAddress {
+    local: String,
+    domain: String,
+}
+
+/// RFC 5322 `atext`, extended by RFC 6531 with any non-ASCII character
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || (!c.is_ascii() && !c.is_control() && !c.is_whitespace())
+}
+
+/// Dot-atom local part; quoted local parts are not accepted
+fn valid_local(local: &str) -> bool {
+    local.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+fn valid_ascii_domain(domain: &str) -> bool {
+    domain.len() <= MAX_DOMAIN_LEN
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        })
+}
+
+impl EmailAddress {
+    /// Parse and normalize user input. Surrounding whitespace and a trailing
+    /// root dot are ignored, and a full-width `＠` (typed with Japanese and
+    /// Chinese input methods) is accepted as the separator.
+    pub fn parse(input: &str) -> Result<Self, AddressError> {
+        let input = input.trim().replace('\u{FF20}', "@");
+        let (local, domain) = input.rsplit_once('@').ok_or(AddressError::MissingAt)?;
+        let local: String = local.nfc().collect();
+        if local.is_empty() || local.len() > MAX_LOCAL_LEN {
+            return Err(AddressError::LocalLength);
+        }
+        if !valid_local(&local) {
+            return Err(AddressError::InvalidLocal);
+        }
+        // UTS #46 also maps full-width letters and ideographic full stops
+        let domain = idna::domain_to_ascii(domain).map_err(|_| AddressError::InvalidDomain)?;
+        let domain = domain.trim_end_matches('.').to_string();
+        if !valid_ascii_domain(&domain) {
+            return Err(AddressError::InvalidDomain);
+        }
+        Ok(Self { local, domain })
+    }
+
+    pub fn local(&self) -> &str {
+        &self.local
+    }
+
+    /// ASCII (punycode) domain
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Unicode domain for display
+    pub fn unicode_domain(&self) -> String {
+        idna::domain_to_unicode(&self.domain).0
+    }
+
+    /// Form shown to people, e.g. in email bodies: `ユーザー@例え.jp`
+    pub fn display(&self) -> String {
+        format!("{}@{}", self.local, self.unicode_domain())
+    }
+
+    /// Delivery to a non-ASCII local part needs an SMTPUTF8 server (RFC 6531);
+    /// a non-ASCII domain alone does not, since it is sent as punycode
+    pub fn requires_smtputf8(&self) -> bool {
+        !self.local.is_ascii()
+    }
+}
+
+/// Canonical form used for storage, lookups and the SMTP envelope:
+/// `ユーザー@xn--r8jz45g.jp`
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.local, self.domain)
+    }
+}
+
+/// Display form of a stored address; unparseable input is returned as is
+pub fn display(email: &str) -> String {
+    EmailAddress::parse(email).map(|a| a.display()).unwrap_or_else(|_| email.to_string())
+}
@@ -1,24 +1,60 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{delete, get, post},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use crate::{
-    audit::AuditLogger,
+    admin_users::{self, AdminRole, AdminUser, AdminUserError, Superadmin},
+    audit::{AuditLogger, AuditQuery, AuditSeverity},
+    broadcasts::{self, Broadcast, BroadcastError, NewBroadcast, Segment},
+    canaries::{self, Canary, CanaryError},
+    client_registration::{self, RegisteredClient, RegistrationError},
+    credential_resets::{self, CredentialReset, CredentialResetConfig, CredentialResetError, NewCredentialReset},
     db::Database,
+    debug_sampling,
+    device::{self, ClientHints},
+    dlq::{self, DeadLetterId, DeadLetterQueue},
     error::{ApiError, ErrorResponse},
+    ip_bans::{self, Ban, IpBanConfig},
+    jwt::KeyRing,
+    key_rotation::{self, KeyRotationConfig, KeyRotationError, KeyVersion},
+    maintenance::{self, MaintenanceMode},
+    metrics::MetricsRecorder,
+    models::{RefreshToken, User, UserSession},
+    revocation::{RevocationBus, RevocationEvent},
+    runtime_info::RuntimeInfo,
     session::Session,
+    stale_accounts::{self, StaleAccount, StaleAccountError},
+    suppression::{self, ImportSummary, Reason, SuppressionError},
+    user_agent::UserAgent,
+    webauthn,
+    webhook_secrets::{self, Rotation, SecretVersion, WebhookSecretConfig},
+    webhooks::WebhookSender,
 };
-use tracing::error;
+use tracing::{error, warn};
 
 #[derive(Clone)]
 pub struct AdminState {
     pub db: Arc<Database>,
     pub audit: Arc<AuditLogger>,
+    pub revocations: Arc<RevocationBus>,
+    pub info: Arc<RuntimeInfo>,
+    pub webhook: Arc<WebhookSender>,
+    /// Configured `webhook_secret`, current until the first rotation
+    pub webhook_secret: Option<String>,
+    pub webhook_secrets: WebhookSecretConfig,
+    /// Access-token signing keys, reloaded after a manual rotation
+    pub keys: Arc<KeyRing>,
+    pub key_rotation: KeyRotationConfig,
+    pub ip_bans: IpBanConfig,
+    pub credential_resets: CredentialResetConfig,
+    pub maintenance: Arc<MaintenanceMode>,
+    /// Refresh tokens outside SQLite (`store = "redis"` or `"postgres"`)
+    pub tokens: Option<crate::storage::SharedTokenStore>,
 }
 
 /// User information response
@@ -27,18 +63,91 @@ pub struct UserInfo {
     pub id: String,
     pub email: String,
     pub totp_enabled: bool,
-    pub webauthn_credentials_count: i32,
-    pub created_at: String,
+    pub webauthn_credentials_count: i64,
+    pub created_at: i64,
+}
+
+impl UserInfo {
+    fn new(user: User, webauthn_credentials_count: i64) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            totp_enabled: user.totp_secret.is_some(),
+            webauthn_credentials_count,
+            created_at: user.created_at,
+        }
+    }
 }
 
 /// Session information response
 #[derive(Serialize)]
 pub struct SessionInfo {
+    /// Hex SHA-256 of the refresh token, the `{token}` of `DELETE /admin/sessions/{token}`
     pub token: String,
+    pub session_id: Option<String>,
     pub user_id: String,
-    pub created_at: String,
-    pub expires_at: String,
+    pub created_at: i64,
+    pub expires_at: i64,
     pub revoked: bool,
+    /// Client hints captured at sign-in (only with client consent)
+    pub device: Option<ClientHints>,
+    /// Browser and OS parsed from the sign-in's `User-Agent`
+    pub agent: Option<UserAgent>,
+    /// e.g. `Chrome 126 on Windows`
+    pub agent_display: Option<String>,
+}
+
+impl From<UserSession> for SessionInfo {
+    fn from(session: UserSession) -> Self {
+        let refresh = session.refresh;
+        Self {
+            token: refresh.token,
+            session_id: refresh.session_id,
+            user_id: refresh.user_id,
+            created_at: refresh.created_at,
+            expires_at: refresh.expires_at,
+            revoked: refresh.revoked,
+            device: session.device,
+            agent_display: session.agent.as_ref().map(UserAgent::display),
+            agent: session.agent,
+        }
+    }
+}
+
+fn db_error(e: rusqlite::Error) -> ErrorResponse {
+    error!("Database error: {}", e);
+    ErrorResponse::internal_error(ApiError::internal_error())
+}
+
+/// Weak ETag for a list response, derived from the listed data's version
+/// (counts and latest change) rather than from the serialized body
+fn list_etag(kind: &str, version: &str) -> String {
+    let digest = crate::crypto::sha256(format!("{}|{}", kind, version).as_bytes());
+    format!("W/\"{}\"", data_encoding::HEXLOWER.encode(&digest[..12]))
+}
+
+/// Whether `If-None-Match` already names `etag` (weak comparison)
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| tag.trim() == "*" || strip(tag) == strip(etag))
+}
+
+/// `304 Not Modified` when the client's copy is current, otherwise the
+/// listing built by `load`, with its ETag either way
+fn conditional<T: Serialize>(
+    headers: &HeaderMap,
+    etag: String,
+    load: impl FnOnce() -> Result<T, ErrorResponse>,
+) -> Result<Response, ErrorResponse> {
+    if etag_matches(headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(([(header::ETAG, etag)], Json(load()?)).into_response())
 }
 
 /// Pagination query parameters
@@ -61,46 +170,24 @@ fn default_limit() -> i32 {
 /// List all users with pagination
 pub async fn list_users(
     State(state): State<AdminState>,
+    headers: HeaderMap,
     Query(params): Query<PaginationQuery>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    let mut stmt = state.db.conn
-        .prepare(
-            "SELECT id, email, totp_secret, created_at FROM users ORDER BY created_at DESC LIMIT ?1 OFFSET ?2"
-        )
-        .map_err(|e| {
-            error!("Database error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?;
-
-    let users = stmt
-        .query_map(rusqlite::params![params.limit, params.offset], |row| {
-            let id: String = row.get(0)?;
-            let email: String = row.get(1)?;
-            let totp_secret: Option<String> = row.get(2)?;
-            let created_at: String = row.get(3)?;
-
-            // Count WebAuthn credentials
-            let cred_count: i32 = row.get(0).unwrap_or(0);
-
-            Ok(UserInfo {
-                id,
-                email,
-                totp_enabled: totp_secret.is_some(),
-                webauthn_credentials_count: cred_count,
-                created_at,
+    let version = state.db.users().list_version().map_err(db_error)?;
+    conditional(&headers, list_etag("users", &version), || {
+        state
+            .db
+            .users()
+            .list(params.limit as i64, params.offset as i64)
+            .map_err(db_error)?
+            .into_iter()
+            .map(|user| {
+                let credentials = state.db.credentials().count_for_user(&user.id)?;
+                Ok(UserInfo::new(user, credentials))
             })
-        })
-        .map_err(|e| {
-            error!("Query error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| {
-            error!("Row mapping error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?;
-
-    Ok(Json(users))
+            .collect::<Result<Vec<_>, rusqlite::Error>>()
+            .map_err(db_error)
+    })
 }
 
 /// Get user by ID
@@ -108,96 +195,86 @@ pub async fn get_user(
     State(state): State<AdminState>,
     Path(user_id): Path<String>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    let mut stmt = state.db.conn
-        .prepare("SELECT id, email, totp_secret, created_at FROM users WHERE id = ?1")
-        .map_err(|e| {
-            error!("Database error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?;
+    let user = state
+        .db
+        .users()
+        .find_by_id(&user_id)
+        .map_err(db_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::user_not_found()))?;
+    let credentials = state.db.credentials().count_for_user(&user.id).map_err(db_error)?;
 
-    let user = stmt
-        .query_row(rusqlite::params![user_id], |row| {
-            let id: String = row.get(0)?;
-            let email: String = row.get(1)?;
-            let totp_secret: Option<String> = row.get(2)?;
-            let created_at: String = row.get(3)?;
-
-            Ok(UserInfo {
-                id,
-                email,
-                totp_enabled: totp_secret.is_some(),
-                webauthn_credentials_count: 0,
-                created_at,
-            })
-        })
-        .map_err(|e| match e {
-            rusqlite::Error::QueryReturnedNoRows => {
-                ErrorResponse::not_found(ApiError::user_not_found())
-            }
-            _ => {
-                error!("Query error: {}", e);
-                ErrorResponse::internal_error(ApiError::internal_error())
-            }
-        })?;
-
-    Ok(Json(user))
+    Ok(Json(UserInfo::new(user, credentials)))
 }
 
 /// List sessions for a user
 pub async fn list_user_sessions(
     State(state): State<AdminState>,
+    headers: HeaderMap,
     Path(user_id): Path<String>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    let mut stmt = state.db.conn
-        .prepare(
-            "SELECT token, user_id, created_at, expires_at, revoked FROM refresh_tokens WHERE user_id = ?1 ORDER BY created_at DESC"
-        )
-        .map_err(|e| {
-            error!("Database error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?;
+    let version = state.db.sessions().list_version(&user_id).map_err(db_error)?;
+    conditional(&headers, list_etag("sessions", &version), || {
+        let sessions: Vec<SessionInfo> = state
+            .db
+            .sessions()
+            .list_for_user(&user_id)
+            .map_err(db_error)?
+            .into_iter()
+            .map(SessionInfo::from)
+            .collect();
+        Ok(sessions)
+    })
+}
 
-    let sessions = stmt
-        .query_map(rusqlite::params![user_id], |row| {
-            Ok(SessionInfo {
-                token: row.get(0)?,
-                user_id: row.get(1)?,
-                created_at: row.get(2)?,
-                expires_at: row.get(3)?,
-                revoked: row.get(4)?,
-            })
-        })
-        .map_err(|e| {
-            error!("Query error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| {
-            error!("Row mapping error: {}", e);
-            ErrorResponse::internal_error(ApiError::internal_error())
-        })?;
+/// Devices a user has signed in from
+pub async fn list_user_devices(
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let devices = device::known_devices(&state.db, &user_id).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+
+    Ok(Json(devices))
+}
+
+/// A user's passkeys with their usage statistics
+pub async fn list_user_credentials(
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let credentials = webauthn::list_credentials(&state.db, &user_id).map_err(db_error)?;
 
-    Ok(Json(sessions))
+    Ok(Json(credentials))
 }
 
-/// Revoke a specific session
+/// Revoke a specific session by the token digest its listing shows
 pub async fn revoke_session(
     State(state): State<AdminState>,
     Path(token): Path<String>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    Session::revoke_refresh_token(&state.db, &token).map_err(|e| {
+    let session = state.db.sessions().find_by_digest(&token).map_err(db_error)?;
+
+    Session::revoke_by_digest(&state.db, &token).map_err(|e| {
         error!("Failed to revoke session: {}", e);
         ErrorResponse::internal_error(ApiError::internal_error())
     })?;
 
+    if let Some(RefreshToken { user_id, session_id: Some(session_id), .. }) = session {
+        state
+            .revocations
+            .publish(RevocationEvent::SessionRevoked { session_id, user_id });
+    }
+
     state.audit.log(
-        &state.db.conn,
+        &state.db,
         crate::audit::AuditEventType::SessionRevoked,
         None,
         None,
         None,
         None,
-        Some(&token),
+        Some(&serde_json::json!({ "token": token }).to_string()),
         true,
     );
 
@@ -206,64 +283,181 @@ pub async fn revoke_session(
 
 /// Revoke all sessions for a user
 pub async fn revoke_all_user_sessions(
+    _: Superadmin,
     State(state): State<AdminState>,
     Path(user_id): Path<String>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    state.db.conn
-        .execute(
-            "UPDATE refresh_tokens SET revoked = 1 WHERE user_id = ?1",
-            rusqlite::params![user_id],
-        )
-        .map_err(|e| {
+    state.db.sessions().revoke_all_for_user(&user_id).map_err(|e| {
             error!("Failed to revoke sessions: {}", e);
             ErrorResponse::internal_error(ApiError::internal_error())
         })?;
+    if let Some(tokens) = &state.tokens {
+        tokens.revoke_user_refresh_tokens(&user_id).map_err(|e| {
+            error!("Failed to revoke sessions in the token store: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
+        })?;
+    }
+
+    state.revocations.publish(RevocationEvent::UserSessionsRevoked {
+        user_id: user_id.clone(),
+        at: Database::now_ts(),
+    });
 
     state.audit.log(
-        &state.db.conn,
+        &state.db,
         crate::audit::AuditEventType::SessionRevoked,
         Some(&user_id),
         None,
         None,
         None,
-        Some("all_sessions"),
+        Some(&serde_json::json!({ "scope": "all_sessions" }).to_string()),
         true,
     );
 
     Ok((StatusCode::OK, "All sessions revoked"))
 }
 
+/// Lift a freeze set from a security notice's "freeze my account" link
+pub async fn unfreeze_user(
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let unfrozen = state.db.users().unfreeze(&user_id).map_err(db_error)?;
+
+    if !unfrozen {
+        return Err(ErrorResponse::not_found(ApiError::not_found("frozen user not found")));
+    }
+
+    Ok((StatusCode::OK, "User unfrozen"))
+}
+
+/// Delete a user and everything stored about them, signing them out everywhere
+pub async fn delete_user(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    // the last superadmin cannot delete themselves out of the admin API
+    admin_users::remove(&state.db, &user_id).map_err(admin_user_error)?;
+    if !state.db.users().delete(&user_id).map_err(db_error)? {
+        return Err(ErrorResponse::not_found(ApiError::user_not_found()));
+    }
+
+    state.revocations.publish(RevocationEvent::UserDisabled {
+        user_id: user_id.clone(),
+        at: Database::now_ts(),
+    });
+
+    // the user row is gone, so the id is only named in the metadata
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::UserDeleted,
+        None,
+        None,
+        None,
+        None,
+        Some(&serde_json::json!({ "user_id": user_id }).to_string()),
+        true,
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Pending challenge listing query (`?user_id=&limit=`)
+#[derive(Deserialize)]
+pub struct PendingQuery {
+    pub user_id: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+/// Outstanding magic links and WebAuthn ceremonies
+pub async fn list_pending_challenges(
+    State(state): State<AdminState>,
+    Query(params): Query<PendingQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let challenges = state
+        .db
+        .challenges()
+        .list_pending(params.user_id.as_deref(), params.limit as i64)
+        .map_err(db_error)?;
+
+    Ok(Json(challenges))
+}
+
+/// Optional `?reason=` recorded in the audit trail
+#[derive(Deserialize)]
+pub struct InvalidateQuery {
+    pub reason: Option<String>,
+}
+
+/// Invalidate one pending challenge (e.g. a link sent during a suspected phishing attempt)
+pub async fn invalidate_pending_challenge(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+    Query(params): Query<InvalidateQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let challenge = state
+        .db
+        .challenges()
+        .invalidate(&id)
+        .map_err(db_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("pending challenge not found")))?;
+
+    let metadata = serde_json::json!({ "id": challenge.id, "kind": challenge.kind, "reason": params.reason });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::ChallengesInvalidated,
+        Some(&challenge.user_id),
+        Some(&challenge.email),
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+
+    Ok(Json(challenge))
+}
+
+/// Invalidate every pending challenge of a user
+pub async fn invalidate_user_pending_challenges(
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+    Query(params): Query<InvalidateQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let invalidated = state.db.challenges().invalidate_for_user(&user_id).map_err(db_error)?;
+
+    let metadata = serde_json::json!({ "count": invalidated, "reason": params.reason });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::ChallengesInvalidated,
+        Some(&user_id),
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+
+    Ok(Json(serde_json::json!({ "invalidated": invalidated })))
+}
+
 /// Get system statistics
 #[derive(Serialize)]
 pub struct SystemStats {
-    pub total_users: i32,
-    pub total_sessions: i32,
-    pub active_sessions: i32,
-    pub total_audit_logs: i32,
+    pub total_users: i64,
+    pub total_sessions: i64,
+    pub active_sessions: i64,
+    pub total_audit_logs: i64,
 }
 
 pub async fn get_stats(
     State(state): State<AdminState>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    let total_users: i32 = state.db.conn
-        .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
-        .unwrap_or(0);
-
-    let total_sessions: i32 = state.db.conn
-        .query_row("SELECT COUNT(*) FROM refresh_tokens", [], |row| row.get(0))
-        .unwrap_or(0);
-
-    let active_sessions: i32 = state.db.conn
-        .query_row(
-            "SELECT COUNT(*) FROM refresh_tokens WHERE revoked = 0 AND datetime(expires_at) > datetime('now')",
-            [],
-            |row| row.get(0),
-        )
-        .unwrap_or(0);
-
-    let total_audit_logs: i32 = state.db.conn
-        .query_row("SELECT COUNT(*) FROM audit_logs", [], |row| row.get(0))
-        .unwrap_or(0);
+    let total_users = state.db.users().count().map_err(db_error)?;
+    let total_sessions = state.db.sessions().count().map_err(db_error)?;
+    let active_sessions = state.db.sessions().count_active().map_err(db_error)?;
+    let total_audit_logs = AuditLogger::count(&state.db).map_err(db_error)?;
 
     let stats = SystemStats {
         total_users,
@@ -275,14 +469,963 @@ pub async fn get_stats(
     Ok(Json(stats))
 }
 
+/// Build, configuration (secrets redacted), migrations and enabled
+/// subsystems of this deployment
+pub async fn get_info(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.info.as_ref().clone())
+}
+
+/// Audit log listing query (`?user_id=&from=&to=&severity=&q=&meta.<key>=&offset=&limit=`)
+#[derive(Deserialize)]
+pub struct AuditListQuery {
+    pub user_id: Option<String>,
+    pub severity: Option<AuditSeverity>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    pub q: Option<String>,
+    #[serde(default = "default_offset")]
+    pub offset: i32,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+/// Apply the `meta.<key>=<value>` parameters of a query string to an audit filter
+fn metadata_filters(filter: &mut AuditQuery, pairs: &[(String, String)]) -> Result<(), ErrorResponse> {
+    for (key, value) in pairs {
+        if let Some(key) = key.strip_prefix("meta.") {
+            filter
+                .metadata_filter(key, value)
+                .map_err(|e| ErrorResponse::bad_request(ApiError::validation_error(e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// List audit logs across partitions, newest first
+pub async fn list_audit_logs(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(params): Query<AuditListQuery>,
+    Query(pairs): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let mut filter = AuditQuery {
+        user_id: params.user_id,
+        from: params.from,
+        to: params.to,
+        severity: params.severity,
+        q: params.q,
+        metadata: Vec::new(),
+    };
+    metadata_filters(&mut filter, &pairs)?;
+    let version = AuditLogger::version(&state.db, &filter).map_err(db_error)?;
+    conditional(&headers, list_etag("audit", &version), || {
+        state
+            .audit
+            .query(&state.db, &filter, params.offset, params.limit)
+            .map_err(db_error)
+    })
+}
+
+/// Export audit logs as NDJSON, one partition at a time
+pub async fn export_audit_logs(
+    State(state): State<AdminState>,
+    Query(mut filter): Query<AuditQuery>,
+    Query(pairs): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    metadata_filters(&mut filter, &pairs)?;
+    let mut body = String::new();
+    state
+        .audit
+        .export(&state.db, &filter, |log| {
+            if let Ok(line) = serde_json::to_string(&log) {
+                body.push_str(&line);
+                body.push('\n');
+            }
+        })
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
+        })?;
+
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body))
+}
+
+/// Dead-letter listing query (`?queue=email|webhook&limit=`)
+#[derive(Deserialize)]
+pub struct DlqQuery {
+    pub queue: Option<DeadLetterQueue>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+/// List dead-lettered emails and webhook deliveries
+pub async fn list_dead_letters(
+    State(state): State<AdminState>,
+    Query(params): Query<DlqQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let items = dlq::list(&state.db, params.queue, params.limit as i64).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+
+    Ok(Json(items))
+}
+
+/// Requeue a single dead-lettered item (`email:<id>` or `webhook:<id>`)
+pub async fn requeue_dead_letter(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let id: DeadLetterId = id
+        .parse()
+        .map_err(|_| ErrorResponse::bad_request(ApiError::validation_error("expected <queue>:<id>")))?;
+
+    let requeued = dlq::requeue(&state.db, &id).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+    if !requeued {
+        return Err(ErrorResponse::not_found(ApiError::not_found("dead letter not found")));
+    }
+
+    Ok((StatusCode::OK, "Requeued"))
+}
+
+/// Bulk requeue body; omit `queue` to requeue everything
+#[derive(Deserialize)]
+pub struct BulkRequeueBody {
+    pub queue: Option<DeadLetterQueue>,
+}
+
+#[derive(Serialize)]
+pub struct BulkRequeueResponse {
+    pub requeued: usize,
+}
+
+/// Requeue all dead-lettered items
+pub async fn requeue_all_dead_letters(
+    State(state): State<AdminState>,
+    Json(body): Json<BulkRequeueBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let requeued = dlq::requeue_all(&state.db, body.queue).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+
+    Ok(Json(BulkRequeueResponse { requeued }))
+}
+
+/// Debug sample listing query (`?path=/webauthn&limit=`)
+#[derive(Deserialize)]
+pub struct DebugSampleQuery {
+    /// Only samples whose path starts with this prefix
+    pub path: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+/// List sampled failed requests (`[debug_sampling]`), newest first
+pub async fn list_debug_samples(
+    State(state): State<AdminState>,
+    Query(params): Query<DebugSampleQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let samples = debug_sampling::list(&state.db, params.path.as_deref(), params.limit as i64).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+
+    Ok(Json(samples))
+}
+
+/// One sampled request with its redacted request and response bodies
+pub async fn get_debug_sample(
+    State(state): State<AdminState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let sample = debug_sampling::get(&state.db, id)
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
+        })?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("debug sample not found")))?;
+
+    Ok(Json(sample))
+}
+
+/// Suppression listing query (`?reason=&offset=&limit=`)
+#[derive(Deserialize)]
+pub struct SuppressionQuery {
+    pub reason: Option<Reason>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default = "default_offset")]
+    pub offset: i32,
+}
+
+/// Addresses no email is sent to, newest first
+pub async fn list_suppressions(
+    State(state): State<AdminState>,
+    Query(params): Query<SuppressionQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let entries =
+        suppression::list(&state.db, params.reason, params.limit as i64, params.offset as i64).map_err(db_error)?;
+    Ok(Json(entries))
+}
+
+#[derive(Deserialize)]
+pub struct SuppressBody {
+    pub email: String,
+    #[serde(default = "default_suppression_reason")]
+    pub reason: Reason,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+fn default_suppression_reason() -> Reason {
+    Reason::Manual
+}
+
+/// Suppress one address
+pub async fn add_suppression(
+    State(state): State<AdminState>,
+    Json(body): Json<SuppressBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let added = suppression::add(&state.db, &body.email, body.reason, "admin", body.note.as_deref()).map_err(|e| match e {
+        SuppressionError::InvalidAddress => ErrorResponse::bad_request(ApiError::validation_error("invalid email address")),
+        SuppressionError::Db(e) => db_error(e),
+    })?;
+    let entry = suppression::find(&state.db, &body.email).map_err(db_error)?;
+    let status = if added { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(entry)))
+}
+
+/// Lift the suppression of an address
+pub async fn remove_suppression(
+    State(state): State<AdminState>,
+    Path(email): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if !suppression::remove(&state.db, &email).map_err(db_error)? {
+        return Err(ErrorResponse::not_found(ApiError::not_found("address is not suppressed")));
+    }
+    Ok((StatusCode::OK, "Suppression removed"))
+}
+
+/// Import query (`?reason=hard_bounce&source=sendgrid`)
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    /// For rows whose reason column is missing or unrecognised
+    #[serde(default = "default_import_reason")]
+    pub reason: Reason,
+    /// Recorded as `import:<source>`
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+fn default_import_reason() -> Reason {
+    Reason::HardBounce
+}
+
+/// Import a provider's suppression export (CSV, or one address per line)
+pub async fn import_suppressions(
+    State(state): State<AdminState>,
+    Query(params): Query<ImportQuery>,
+    body: String,
+) -> Result<Json<ImportSummary>, ErrorResponse> {
+    let source = format!("import:{}", params.source.as_deref().unwrap_or("file"));
+    let summary = suppression::import(&state.db, &body, params.reason, &source).map_err(db_error)?;
+    Ok(Json(summary))
+}
+
+/// Canary accounts, most recently triggered first
+pub async fn list_canaries(State(state): State<AdminState>) -> Result<Json<Vec<Canary>>, ErrorResponse> {
+    Ok(Json(canaries::list(&state.db).map_err(db_error)?))
+}
+
+#[derive(Deserialize)]
+pub struct CanaryBody {
+    pub email: String,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Ban the address of anyone who tries the account
+    #[serde(default = "default_ban_ip")]
+    pub ban_ip: bool,
+}
+
+fn default_ban_ip() -> bool {
+    true
+}
+
+/// Register a canary account, or change its note and ban setting
+pub async fn add_canary(
+    State(state): State<AdminState>,
+    Json(body): Json<CanaryBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let existed = canaries::find(&state.db, &body.email).map_err(db_error)?.is_some();
+    let canary = canaries::add(&state.db, &body.email, body.note.as_deref(), body.ban_ip).map_err(|e| match e {
+        CanaryError::InvalidAddress => ErrorResponse::bad_request(ApiError::validation_error("invalid email address")),
+        CanaryError::Db(e) => db_error(e),
+    })?;
+    let metadata = serde_json::json!({ "action": "added", "ban_ip": canary.ban_ip, "note": canary.note });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::CanaryAccountChanged,
+        None,
+        Some(&canary.email),
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+    let status = if existed { StatusCode::OK } else { StatusCode::CREATED };
+    Ok((status, Json(canary)))
+}
+
+/// Stop treating an address as a canary
+pub async fn remove_canary(
+    State(state): State<AdminState>,
+    Path(email): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if !canaries::remove(&state.db, &email).map_err(db_error)? {
+        return Err(ErrorResponse::not_found(ApiError::not_found("address is not a canary")));
+    }
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::CanaryAccountChanged,
+        None,
+        Some(&email),
+        None,
+        None,
+        Some(&serde_json::json!({ "action": "removed" }).to_string()),
+        true,
+    );
+    Ok((StatusCode::OK, "Canary removed"))
+}
+
+fn broadcast_error(e: BroadcastError) -> ErrorResponse {
+    match e {
+        BroadcastError::Db(e) => db_error(e),
+        BroadcastError::Finished(_) => ErrorResponse::conflict(ApiError::conflict(e.to_string())),
+        e => ErrorResponse::bad_request(ApiError::validation_error(e.to_string())),
+    }
+}
+
+fn audit_broadcast(state: &AdminState, action: &str, broadcast: &Broadcast) {
+    let metadata = serde_json::json!({
+        "action": action,
+        "broadcast_id": broadcast.id,
+        "subject": broadcast.subject,
+        "category": broadcast.category,
+        "segment": broadcast.segment,
+        "recipients": broadcast.progress.total,
+    });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::BroadcastChanged,
+        None,
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+}
+
+/// Broadcasts with their progress, newest first
+pub async fn list_broadcasts(State(state): State<AdminState>) -> Result<Json<Vec<Broadcast>>, ErrorResponse> {
+    Ok(Json(broadcasts::list(&state.db, 100).map_err(db_error)?))
+}
+
+/// Queue an announcement to every user in the segment
+pub async fn create_broadcast(
+    State(state): State<AdminState>,
+    Json(body): Json<NewBroadcast>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let broadcast = broadcasts::create(&state.db, &body, Database::now_ts()).map_err(broadcast_error)?;
+    audit_broadcast(&state, "created", &broadcast);
+    Ok((StatusCode::CREATED, Json(broadcast)))
+}
+
+/// How many users a segment matches, without creating anything
+pub async fn preview_broadcast(
+    State(state): State<AdminState>,
+    Json(segment): Json<Segment>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    let recipients = broadcasts::count(&state.db, &segment).map_err(db_error)?;
+    Ok(Json(serde_json::json!({ "recipients": recipients })))
+}
+
+pub async fn get_broadcast(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<Json<Broadcast>, ErrorResponse> {
+    broadcasts::get(&state.db, &id)
+        .map_err(db_error)?
+        .map(Json)
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("broadcast not found")))
+}
+
+/// Stop queueing a broadcast and withdraw its emails still waiting in the queue
+pub async fn cancel_broadcast(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<Json<Broadcast>, ErrorResponse> {
+    let broadcast = broadcasts::cancel(&state.db, &id, Database::now_ts())
+        .map_err(broadcast_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("broadcast not found")))?;
+    audit_broadcast(&state, "canceled", &broadcast);
+    Ok(Json(broadcast))
+}
+
+fn credential_reset_error(e: CredentialResetError) -> ErrorResponse {
+    match e {
+        CredentialResetError::Db(e) => db_error(e),
+        CredentialResetError::Finished(_) | CredentialResetError::RollbackExpired => {
+            ErrorResponse::conflict(ApiError::conflict(e.to_string()))
+        }
+        e => ErrorResponse::bad_request(ApiError::validation_error(e.to_string())),
+    }
+}
+
+fn audit_credential_reset(state: &AdminState, action: &str, reset: &CredentialReset, extra: serde_json::Value) {
+    let metadata = serde_json::json!({
+        "action": action,
+        "reset_id": reset.id,
+        "scope": reset.scope,
+        "reset_totp": reset.reset_totp,
+        "reset_passkeys": reset.reset_passkeys,
+        "reason": reset.reason,
+        "users": reset.progress.total,
+        "detail": extra,
+    });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::CredentialResetChanged,
+        None,
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+}
+
+/// Credential resets with their progress, newest first
+pub async fn list_credential_resets(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<CredentialReset>>, ErrorResponse> {
+    Ok(Json(credential_resets::list(&state.db, 100).map_err(db_error)?))
+}
+
+/// Start a forced reset of the listed users, or of everyone
+pub async fn create_credential_reset(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Json(body): Json<NewCredentialReset>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let reset = credential_resets::create(&state.db, &body, Database::now_ts()).map_err(credential_reset_error)?;
+    audit_credential_reset(&state, "created", &reset, serde_json::Value::Null);
+    Ok((StatusCode::CREATED, Json(reset)))
+}
+
+pub async fn get_credential_reset(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<Json<CredentialReset>, ErrorResponse> {
+    credential_resets::get(&state.db, &id)
+        .map_err(db_error)?
+        .map(Json)
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("credential reset not found")))
+}
+
+/// Stop a reset before it reaches the remaining users
+pub async fn cancel_credential_reset(
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<Json<CredentialReset>, ErrorResponse> {
+    let reset = credential_resets::cancel(&state.db, &id, Database::now_ts())
+        .map_err(credential_reset_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("credential reset not found")))?;
+    audit_credential_reset(&state, "canceled", &reset, serde_json::Value::Null);
+    Ok(Json(reset))
+}
+
+/// Undo a mistaken reset: restore removed factors and withdraw unsent emails
+pub async fn rollback_credential_reset(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    let (reset, rollback) = credential_resets::rollback(&state.db, &state.credential_resets, &id, Database::now_ts())
+        .map_err(credential_reset_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("credential reset not found")))?;
+    let rollback = serde_json::to_value(&rollback).expect("rollback serializes");
+    audit_credential_reset(&state, "rolled_back", &reset, rollback.clone());
+    Ok(Json(serde_json::json!({ "reset": reset, "rollback": rollback })))
+}
+
+/// Stale account listing query (`?state=&limit=`)
+#[derive(Deserialize)]
+pub struct StaleAccountQuery {
+    pub state: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+/// Accounts the inactivity policy acted on, most recently changed first
+pub async fn list_stale_accounts(
+    State(state): State<AdminState>,
+    Query(params): Query<StaleAccountQuery>,
+) -> Result<Json<Vec<StaleAccount>>, ErrorResponse> {
+    let accounts = stale_accounts::list(&state.db, params.state.as_deref(), params.limit as i64).map_err(db_error)?;
+    Ok(Json(accounts))
+}
+
+/// Take a flagged or disabled account out of the inactivity policy's hands
+pub async fn reinstate_stale_account(
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+) -> Result<Json<StaleAccount>, ErrorResponse> {
+    let account = stale_accounts::reinstate(&state.db, &user_id, Database::now_ts())
+        .map_err(|e| match e {
+            StaleAccountError::NotStale(_) => ErrorResponse::conflict(ApiError::conflict(e.to_string())),
+            e => {
+                error!("Failed to reinstate stale account: {}", e);
+                ErrorResponse::internal_error(ApiError::internal_error())
+            }
+        })?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("stale account not found")))?;
+    Ok(Json(account))
+}
+
+fn admin_user_error(e: AdminUserError) -> ErrorResponse {
+    match e {
+        AdminUserError::Db(e) => db_error(e),
+        AdminUserError::UnknownUser => ErrorResponse::not_found(ApiError::user_not_found()),
+        AdminUserError::UnknownRole(_) => ErrorResponse::bad_request(ApiError::bad_request(e.to_string())),
+        AdminUserError::LastSuperadmin => ErrorResponse::conflict(ApiError::conflict(e.to_string())),
+    }
+}
+
+/// Users allowed into the admin API with an access token, highest role first
+pub async fn list_admins(
+    _: Superadmin,
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<AdminUser>>, ErrorResponse> {
+    Ok(Json(admin_users::list(&state.db).map_err(db_error)?))
+}
+
+/// Body of `PUT /admin/admins/{user_id}`
+#[derive(Deserialize)]
+pub struct AdminRoleRequest {
+    pub role: AdminRole,
+}
+
+/// Grant a user an admin role, or change it
+pub async fn set_admin(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+    Json(body): Json<AdminRoleRequest>,
+) -> Result<Json<AdminUser>, ErrorResponse> {
+    let previous = admin_users::set(&state.db, &user_id, body.role).map_err(admin_user_error)?;
+    log_admin_role(&state, &user_id, previous, Some(body.role));
+    let admin = admin_users::get(&state.db, &user_id)
+        .map_err(db_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::user_not_found()))?;
+    Ok(Json(admin))
+}
+
+/// Take away a user's admin access
+pub async fn remove_admin(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let previous = admin_users::remove(&state.db, &user_id).map_err(admin_user_error)?;
+    if previous.is_none() {
+        return Err(ErrorResponse::not_found(ApiError::not_found("admin not found")));
+    }
+    log_admin_role(&state, &user_id, previous, None);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn log_admin_role(state: &AdminState, user_id: &str, from: Option<AdminRole>, to: Option<AdminRole>) {
+    let metadata = serde_json::json!({ "from": from, "to": to });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::AdminRoleChanged,
+        Some(user_id),
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+}
+
+/// Stored webhook secrets, newest first (fingerprints only)
+pub async fn list_webhook_secrets(State(state): State<AdminState>) -> Result<Json<Vec<SecretVersion>>, ErrorResponse> {
+    Ok(Json(webhook_secrets::list(&state.db).map_err(db_error)?))
+}
+
+/// Mint a new webhook secret; the previous one keeps signing for the grace period
+pub async fn rotate_webhook_secret(
+    _: Superadmin,
+    State(state): State<AdminState>,
+) -> Result<Json<Rotation>, ErrorResponse> {
+    let rotation = webhook_secrets::rotate(
+        &state.db,
+        state.webhook_secret.as_deref(),
+        state.webhook_secrets.grace_period_seconds,
+    )
+    .map_err(db_error)?;
+    webhook_secrets::load(&state.db, &state.webhook, state.webhook_secret.as_deref()).map_err(db_error)?;
+
+    let metadata = serde_json::json!({
+        "version": rotation.version,
+        "fingerprint": webhook_secrets::fingerprint(&rotation.secret),
+        "previous_expires_at": rotation.previous_expires_at,
+    });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::WebhookSecretRotated,
+        None,
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+    Ok(Json(rotation))
+}
+
+/// End the grace period: only the current webhook secret signs from now on
+pub async fn expire_previous_webhook_secrets(
+    _: Superadmin,
+    State(state): State<AdminState>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let expired = webhook_secrets::expire_previous(&state.db).map_err(db_error)?;
+    webhook_secrets::load(&state.db, &state.webhook, state.webhook_secret.as_deref()).map_err(db_error)?;
+
+    let metadata = serde_json::json!({ "expired_previous": expired });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::WebhookSecretRotated,
+        None,
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+    Ok(Json(serde_json::json!({ "expired": expired })))
+}
+
+/// Generated access-token signing keys, newest first (never the private keys)
+pub async fn list_signing_keys(State(state): State<AdminState>) -> Result<Json<Vec<KeyVersion>>, ErrorResponse> {
+    Ok(Json(key_rotation::list(&state.db).map_err(db_error)?))
+}
+
+#[derive(Deserialize)]
+pub struct RotateKeyQuery {
+    /// Sign with the new key at once instead of after the pre-publication
+    /// period, e.g. when the current key leaked. Verifiers reject its tokens
+    /// until they refetch the JWKS.
+    #[serde(default)]
+    pub immediate: bool,
+}
+
+/// Generate a new signing key now instead of waiting for the schedule
+pub async fn rotate_signing_key(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Query(params): Query<RotateKeyQuery>,
+) -> Result<Json<key_rotation::Rotation>, ErrorResponse> {
+    if !state.key_rotation.enabled {
+        return Err(ErrorResponse::conflict(ApiError::conflict("key rotation is not enabled")));
+    }
+    let now = Database::now_ts();
+    let prepublish = state.keys.publication().prepublish_seconds.max(0);
+    let activates_at = if params.immediate { now } else { now + prepublish };
+    let key_error = |e: KeyRotationError| {
+        error!("Signing key rotation failed: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    };
+    let rotation = key_rotation::rotate(&state.db, now, activates_at).map_err(key_error)?;
+    key_rotation::load(&state.db, &state.keys).map_err(key_error)?;
+    Ok(Json(rotation))
+}
+
+#[derive(Deserialize)]
+pub struct ClientQuery {
+    /// `pending`, `approved` or `rejected`
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+fn registration_error(e: RegistrationError) -> ErrorResponse {
+    match e {
+        RegistrationError::Db(e) => db_error(e),
+        e @ RegistrationError::Reviewed(_) => ErrorResponse::conflict(ApiError::conflict(e.to_string())),
+        e => ErrorResponse::bad_request(ApiError::validation_error(e.to_string())),
+    }
+}
+
+fn log_client(state: &AdminState, action: &str, client_id: &str) {
+    let metadata = serde_json::json!({ "action": action, "client_id": client_id });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::ClientRegistrationChanged,
+        None,
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+}
+
+/// Clients registered at `/oauth/register`, oldest first
+/// (`?status=pending` is the review queue)
+pub async fn list_clients(
+    State(state): State<AdminState>,
+    Query(params): Query<ClientQuery>,
+) -> Result<Json<Vec<RegisteredClient>>, ErrorResponse> {
+    let clients =
+        client_registration::list(&state.db, params.status.as_deref(), params.limit as i64).map_err(db_error)?;
+    Ok(Json(clients))
+}
+
+fn review_client(state: &AdminState, client_id: &str, approve: bool) -> Result<Json<RegisteredClient>, ErrorResponse> {
+    let client = client_registration::review(&state.db, client_id, approve, Database::now_ts())
+        .map_err(registration_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("No such client")))?;
+    log_client(state, &client.status, client_id);
+    Ok(Json(client))
+}
+
+/// Let a registered client authenticate and redirect
+pub async fn approve_client(
+    State(state): State<AdminState>,
+    Path(client_id): Path<String>,
+) -> Result<Json<RegisteredClient>, ErrorResponse> {
+    review_client(&state, &client_id, true)
+}
+
+/// Turn a registration down, or stop an approved client
+pub async fn reject_client(
+    State(state): State<AdminState>,
+    Path(client_id): Path<String>,
+) -> Result<Json<RegisteredClient>, ErrorResponse> {
+    review_client(&state, &client_id, false)
+}
+
+/// Forget a registered client
+pub async fn delete_client(
+    State(state): State<AdminState>,
+    Path(client_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if !client_registration::delete(&state.db, &client_id).map_err(db_error)? {
+        return Err(ErrorResponse::not_found(ApiError::not_found("No such client")));
+    }
+    log_client(&state, "deleted", &client_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct IpBanQuery {
+    /// Include expired and lifted bans
+    #[serde(default)]
+    pub include_expired: bool,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default = "default_offset")]
+    pub offset: i32,
+}
+
+/// Address bans, newest first
+pub async fn list_ip_bans(
+    State(state): State<AdminState>,
+    Query(params): Query<IpBanQuery>,
+) -> Result<Json<Vec<Ban>>, ErrorResponse> {
+    let bans = ip_bans::list(&state.db, params.include_expired, params.limit as i64, params.offset as i64)
+        .map_err(db_error)?;
+    Ok(Json(bans))
+}
+
+#[derive(Deserialize)]
+pub struct BanBody {
+    pub ip: String,
+    /// Defaults to the escalating automatic length
+    #[serde(default)]
+    pub duration_seconds: Option<i64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+fn valid_ip(ip: &str) -> Result<String, ErrorResponse> {
+    ip.parse::<std::net::IpAddr>()
+        .map(|ip| ip.to_string())
+        .map_err(|_| ErrorResponse::bad_request(ApiError::validation_error("invalid IP address")))
+}
+
+fn log_ban(state: &AdminState, event: crate::audit::AuditEventType, ip: &str, metadata: serde_json::Value) {
+    state.audit.log(&state.db, event, None, None, Some(ip), None, Some(&metadata.to_string()), true);
+}
+
+/// Ban an address by hand
+pub async fn add_ip_ban(
+    State(state): State<AdminState>,
+    Json(body): Json<BanBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let ip = valid_ip(&body.ip)?;
+    if body.duration_seconds.is_some_and(|s| s <= 0) {
+        return Err(ErrorResponse::bad_request(ApiError::validation_error("duration_seconds must be positive")));
+    }
+    let reason = body.reason.as_deref().unwrap_or("banned by admin");
+    let ban = ip_bans::ban(&state.db, &ip, &state.ip_bans, reason, "admin", body.duration_seconds).map_err(db_error)?;
+    MetricsRecorder::record_ip_ban("admin");
+    log_ban(
+        &state,
+        crate::audit::AuditEventType::IpBanned,
+        &ip,
+        serde_json::json!({ "source": "admin", "reason": reason, "strikes": ban.strikes, "expires_at": ban.expires_at }),
+    );
+    Ok((StatusCode::CREATED, Json(ban)))
+}
+
+#[derive(Deserialize)]
+pub struct ExtendBanBody {
+    pub seconds: i64,
+}
+
+/// Lengthen an active ban
+pub async fn extend_ip_ban(
+    State(state): State<AdminState>,
+    Path(ip): Path<String>,
+    Json(body): Json<ExtendBanBody>,
+) -> Result<Json<Ban>, ErrorResponse> {
+    if body.seconds <= 0 {
+        return Err(ErrorResponse::bad_request(ApiError::validation_error("seconds must be positive")));
+    }
+    let ip = valid_ip(&ip)?;
+    let ban = ip_bans::extend(&state.db, &ip, body.seconds)
+        .map_err(db_error)?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::not_found("address is not banned")))?;
+    log_ban(
+        &state,
+        crate::audit::AuditEventType::IpBanned,
+        &ip,
+        serde_json::json!({ "source": "admin", "extended_by": body.seconds, "expires_at": ban.expires_at }),
+    );
+    Ok(Json(ban))
+}
+
+/// Lift an active ban
+pub async fn lift_ip_ban(
+    State(state): State<AdminState>,
+    Path(ip): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let ip = valid_ip(&ip)?;
+    if !ip_bans::lift(&state.db, &ip).map_err(db_error)? {
+        return Err(ErrorResponse::not_found(ApiError::not_found("address is not banned")));
+    }
+    log_ban(&state, crate::audit::AuditEventType::IpBanLifted, &ip, serde_json::json!({ "source": "admin" }));
+    Ok((StatusCode::OK, "Ban lifted"))
+}
+
+/// Whether the instances on this database are in read-only mode
+pub async fn get_maintenance(State(state): State<AdminState>) -> Json<maintenance::Status> {
+    Json(state.maintenance.status())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceRequest {
+    pub read_only: bool,
+    /// Shown to clients whose requests are refused
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Enter or leave read-only mode on every instance sharing the database
+pub async fn set_maintenance(
+    _: Superadmin,
+    State(state): State<AdminState>,
+    Json(body): Json<MaintenanceRequest>,
+) -> Result<Json<maintenance::Status>, ErrorResponse> {
+    let before = state.maintenance.is_read_only();
+    let status = state.maintenance.set(body.read_only, body.reason).map_err(db_error)?;
+    if before != status.read_only {
+        warn!(read_only = status.read_only, reason = ?status.reason, "Maintenance mode changed");
+    }
+    let metadata = serde_json::json!({ "read_only": status.read_only, "reason": status.reason });
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::MaintenanceModeChanged,
+        None,
+        None,
+        None,
+        None,
+        Some(&metadata.to_string()),
+        true,
+    );
+    Ok(Json(status))
+}
+
 /// Create admin router
 pub fn admin_router(state: AdminState) -> Router {
     Router::new()
         .route("/users", get(list_users))
-        .route("/users/:user_id", get(get_user))
+        .route("/users/:user_id", get(get_user).delete(delete_user))
         .route("/users/:user_id/sessions", get(list_user_sessions))
+        .route("/users/:user_id/devices", get(list_user_devices))
+        .route("/users/:user_id/credentials", get(list_user_credentials))
         .route("/sessions/:token", delete(revoke_session))
         .route("/users/:user_id/sessions", delete(revoke_all_user_sessions))
+        .route("/users/:user_id/unfreeze", post(unfreeze_user))
+        .route("/users/:user_id/pending", delete(invalidate_user_pending_challenges))
+        .route("/pending", get(list_pending_challenges))
+        .route("/pending/:id", delete(invalidate_pending_challenge))
         .route("/stats", get(get_stats))
+        .route("/info", get(get_info))
+        .route("/audit", get(list_audit_logs))
+        .route("/audit/export", get(export_audit_logs))
+        .route("/dlq", get(list_dead_letters))
+        .route("/dlq/requeue", post(requeue_all_dead_letters))
+        .route("/dlq/:id/requeue", post(requeue_dead_letter))
+        .route("/debug/samples", get(list_debug_samples))
+        .route("/debug/samples/:id", get(get_debug_sample))
+        .route("/suppressions", get(list_suppressions).post(add_suppression))
+        .route("/suppressions/import", post(import_suppressions))
+        .route("/suppressions/:email", delete(remove_suppression))
+        .route("/canaries", get(list_canaries).post(add_canary))
+        .route("/canaries/:email", delete(remove_canary))
+        .route("/broadcasts", get(list_broadcasts).post(create_broadcast))
+        .route("/broadcasts/preview", post(preview_broadcast))
+        .route("/broadcasts/:id", get(get_broadcast))
+        .route("/broadcasts/:id/cancel", post(cancel_broadcast))
+        .route("/credential-resets", get(list_credential_resets).post(create_credential_reset))
+        .route("/credential-resets/:id", get(get_credential_reset))
+        .route("/credential-resets/:id/cancel", post(cancel_credential_reset))
+        .route("/credential-resets/:id/rollback", post(rollback_credential_reset))
+        .route("/stale-accounts", get(list_stale_accounts))
+        .route("/stale-accounts/:user_id/reinstate", post(reinstate_stale_account))
+        .route("/admins", get(list_admins))
+        .route("/admins/:user_id", put(set_admin).delete(remove_admin))
+        .route("/ip-bans", get(list_ip_bans).post(add_ip_ban))
+        .route("/ip-bans/:ip/extend", post(extend_ip_ban))
+        .route("/ip-bans/:ip", delete(lift_ip_ban))
+        .route("/maintenance", get(get_maintenance).put(set_maintenance))
+        .route("/webhook/secrets", get(list_webhook_secrets))
+        .route("/webhook/secrets/rotate", post(rotate_webhook_secret))
+        .route("/webhook/secrets/expire-previous", post(expire_previous_webhook_secrets))
+        .route("/keys", get(list_signing_keys))
+        .route("/keys/rotate", post(rotate_signing_key))
+        .route("/clients", get(list_clients))
+        .route("/clients/:client_id", delete(delete_client))
+        .route("/clients/:client_id/approve", post(approve_client))
+        .route("/clients/:client_id/reject", post(reject_client))
         .with_state(state)
 }
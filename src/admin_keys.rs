@@ -0,0 +1,249 @@
+use crate::{
+    admin_users::{self, AdminRole},
+    audit::{AuditEventType, AuditLogger},
+    crypto,
+    db::Database,
+    error::{ApiError, ErrorResponse},
+    jwt::KeyRing,
+    revocation::RevocationBus,
+    subjects,
+};
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use rand::RngCore;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// Prefix of admin API keys, so leaked keys are easy to recognise and scan for
+pub const KEY_PREFIX: &str = "pak_";
+
+/// `[admin]` configuration
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AdminAuthConfig {
+    /// Require `Authorization: Bearer <admin API key>` on `/admin/*`, in
+    /// addition to the `[ip_access]` rules
+    #[serde(default)]
+    pub require_api_key: bool,
+    /// Also accept access tokens of users in `admin_users`; turns
+    /// authentication on by itself
+    #[serde(default)]
+    pub access_tokens: bool,
+}
+
+impl AdminAuthConfig {
+    /// Whether `/admin/*` requires a credential at all
+    pub fn enabled(&self) -> bool {
+        self.require_api_key || self.access_tokens
+    }
+}
+
+/// A newly created admin API key; the secret is only ever shown here
+pub struct NewAdminKey {
+    pub id: String,
+    pub secret: String,
+}
+
+fn hash(secret: &str) -> String {
+    HEXLOWER.encode(&crypto::sha256(secret.as_bytes()))
+}
+
+/// Create an admin API key with `role`. Only its SHA-256 is stored.
+pub fn create(
+    db: &Database,
+    name: &str,
+    user_id: Option<&str>,
+    role: AdminRole,
+) -> Result<NewAdminKey, rusqlite::Error> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let secret = format!("{}{}", KEY_PREFIX, BASE64URL_NOPAD.encode(&bytes));
+    let id = Uuid::new_v4().to_string();
+    db.conn().execute(
+        "INSERT INTO admin_api_keys (id, name, key_hash, user_id, role, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![id, name, hash(&secret), user_id, role.as_str(), Database::now_ts()],
+    )?;
+    Ok(NewAdminKey { id, secret })
+}
+
+/// A live admin API key
+#[derive(Debug, Clone)]
+pub struct AdminKey {
+    pub id: String,
+    pub name: String,
+    pub role: AdminRole,
+}
+
+/// The live key matching `secret`, recording its use
+pub fn verify(db: &Database, secret: &str) -> Result<Option<AdminKey>, rusqlite::Error> {
+    if !secret.starts_with(KEY_PREFIX) {
+        return Ok(None);
+    }
+    let key = db
+        .conn()
+        .query_row(
+            "SELECT id, name, role FROM admin_api_keys WHERE key_hash = ?1 AND revoked_at IS NULL",
+            params![hash(secret)],
+            |r| {
+                Ok(AdminKey {
+                    id: r.get(0)?,
+                    name: r.get(1)?,
+                    role: r.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    if let Some(key) = &key {
+        db.conn().execute(
+            "UPDATE admin_api_keys SET last_used_at = ?1 WHERE id = ?2",
+            params![Database::now_ts(), key.id],
+        )?;
+    }
+    Ok(key)
+}
+
+/// Who made an admin request; added to the request's extensions by
+/// [`authenticate`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AdminActor {
+    ApiKey { key_id: String, name: String, role: AdminRole },
+    User { user_id: String, role: AdminRole },
+    /// `dev_mode` with `[admin]` authentication off: anyone reaching `/admin`
+    /// may do everything; added by [`dev_mode`]
+    DevMode,
+}
+
+impl AdminActor {
+    pub fn role(&self) -> AdminRole {
+        match self {
+            Self::ApiKey { role, .. } | Self::User { role, .. } => *role,
+            Self::DevMode => AdminRole::Superadmin,
+        }
+    }
+}
+
+/// Middleware state for [`authenticate`]
+#[derive(Clone)]
+pub struct AdminGuard {
+    pub db: Arc<Database>,
+    pub audit: Arc<AuditLogger>,
+    pub revocations: Arc<RevocationBus>,
+    /// Verifies access tokens, as the auth routes do
+    pub keys: Arc<KeyRing>,
+    pub jwt_leeway_seconds: u64,
+    pub cfg: AdminAuthConfig,
+}
+
+impl AdminGuard {
+    /// Resolve a bearer credential: an API key, or an access token of an
+    /// admin user
+    pub fn actor(&self, presented: &str) -> Result<Option<AdminActor>, rusqlite::Error> {
+        if presented.starts_with(KEY_PREFIX) {
+            return Ok(verify(&self.db, presented)?.map(|key| AdminActor::ApiKey {
+                key_id: key.id,
+                name: key.name,
+                role: key.role,
+            }));
+        }
+        if !self.cfg.access_tokens {
+            return Ok(None);
+        }
+        let Ok(claims) = self.keys.verify(presented, self.jwt_leeway_seconds) else {
+            return Ok(None);
+        };
+        if claims.kind != "access" {
+            return Ok(None);
+        }
+        let Some(user_id) = subjects::resolve(&self.db, &claims.sub)? else {
+            return Ok(None);
+        };
+        if self.revocations.cache().is_access_token_revoked(&user_id, &claims) {
+            return Ok(None);
+        }
+        // the role is read now, so a demoted admin loses access immediately
+        Ok(admin_users::role(&self.db, &user_id)?.map(|role| AdminActor::User { user_id, role }))
+    }
+}
+
+/// Reject admin requests without a valid credential or with a role below
+/// `viewer` for reads and `operator` for changes, and audit every
+/// state-changing request with the key or user that made it
+pub async fn authenticate(State(guard): State<AdminGuard>, mut request: Request, next: Next) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    let Some(presented) = presented else {
+        return ErrorResponse::unauthorized(ApiError::unauthorized("admin credential required")).into_response();
+    };
+    let actor = match guard.actor(presented) {
+        Ok(Some(actor)) => actor,
+        Ok(None) => {
+            warn!("Rejected admin request with an unknown credential");
+            return ErrorResponse::unauthorized(ApiError::unauthorized("invalid admin credential")).into_response();
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return ErrorResponse::internal_error(ApiError::internal_error()).into_response();
+        }
+    };
+
+    let method = request.method().clone();
+    // the nest strips `/admin`; the audit trail shows the full path
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let read_only = matches!(method, Method::GET | Method::HEAD | Method::OPTIONS);
+    let needed = if read_only { AdminRole::Viewer } else { AdminRole::Operator };
+    let response = match admin_users::require(&actor, needed) {
+        Ok(()) => {
+            request.extensions_mut().insert(actor.clone());
+            next.run(request).await
+        }
+        Err(rejection) => rejection.into_response(),
+    };
+
+    if !read_only {
+        let user_id = match &actor {
+            AdminActor::User { user_id, .. } => Some(user_id.as_str()),
+            AdminActor::ApiKey { .. } | AdminActor::DevMode => None,
+        };
+        let metadata = serde_json::json!({
+            "actor": actor,
+            "method": method.as_str(),
+            "path": path,
+            "status": response.status().as_u16(),
+        });
+        guard.audit.log(
+            &guard.db,
+            AuditEventType::AdminRequest,
+            user_id,
+            None,
+            None,
+            None,
+            Some(&metadata.to_string()),
+            response.status().is_success(),
+        );
+    }
+    response
+}
+
+/// Stand-in for [`authenticate`] in `dev_mode` without `[admin]`
+/// authentication: marks every request as [`AdminActor::DevMode`], so role
+/// checks see that explicitly instead of a missing actor. Without `dev_mode`
+/// the server does not mount `/admin` at all then.
+pub async fn dev_mode(mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(AdminActor::DevMode);
+    next.run(request).await
+}
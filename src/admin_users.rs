@@ -0,0 +1,254 @@
+//! Admin roles: who may read and change what under `/admin`.
+//!
+//! Each admin user and API key has one [`AdminRole`]. The authentication
+//! middleware lets any role read (`GET`) and requires `operator` for changes;
+//! handlers for destructive changes additionally take the [`Superadmin`]
+//! extractor.
+
+use crate::{
+    admin_keys::AdminActor,
+    db::Database,
+    error::{ApiError, ErrorResponse},
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+/// Admin roles, each including the permissions of the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// Read users, sessions, audit logs and settings
+    Viewer,
+    /// Routine changes: revoke a session, ban an address, requeue emails, ...
+    Operator,
+    /// Revoke all of a user's sessions, delete users, manage admins and
+    /// change server-wide settings
+    Superadmin,
+}
+
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Superadmin => "superadmin",
+        }
+    }
+}
+
+impl fmt::Display for AdminRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AdminRole {
+    type Err = AdminUserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Self::Viewer),
+            "operator" => Ok(Self::Operator),
+            "superadmin" => Ok(Self::Superadmin),
+            other => Err(AdminUserError::UnknownRole(other.to_string())),
+        }
+    }
+}
+
+impl rusqlite::types::FromSql for AdminRole {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AdminUserError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("unknown admin role {0:?}")]
+    UnknownRole(String),
+    #[error("user not found")]
+    UnknownUser,
+    #[error("the last superadmin cannot be demoted or removed")]
+    LastSuperadmin,
+}
+
+/// A user allowed into `/admin` with an access token
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminUser {
+    pub user_id: String,
+    pub email: String,
+    pub role: AdminRole,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+const COLUMNS: &str = "a.user_id, u.email, a.role, a.created_at, a.updated_at";
+
+fn from_row(r: &Row) -> rusqlite::Result<AdminUser> {
+    Ok(AdminUser {
+        user_id: r.get(0)?,
+        email: r.get(1)?,
+        role: r.get(2)?,
+        created_at: r.get(3)?,
+        updated_at: r.get(4)?,
+    })
+}
+
+/// Admins, highest role first
+pub fn list(db: &Database) -> Result<Vec<AdminUser>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM admin_users a JOIN users u ON u.id = a.user_id
+         ORDER BY CASE a.role WHEN 'superadmin' THEN 0 WHEN 'operator' THEN 1 ELSE 2 END, u.email",
+        COLUMNS
+    ))?;
+    let admins = stmt.query_map([], from_row)?;
+    admins.collect()
+}
+
+pub fn get(db: &Database, user_id: &str) -> Result<Option<AdminUser>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            &format!(
+                "SELECT {} FROM admin_users a JOIN users u ON u.id = a.user_id WHERE a.user_id = ?1",
+                COLUMNS
+            ),
+            params![user_id],
+            from_row,
+        )
+        .optional()
+}
+
+/// Role of an admin user; `None` for everyone else
+pub fn role(db: &Database, user_id: &str) -> Result<Option<AdminRole>, rusqlite::Error> {
+    db.conn()
+        .query_row("SELECT role FROM admin_users WHERE user_id = ?1", params![user_id], |r| r.get(0))
+        .optional()
+}
+
+/// Make `user_id` an admin with `role`, or change their role. Returns the
+/// previous role.
+pub fn set(db: &Database, user_id: &str, role: AdminRole) -> Result<Option<AdminRole>, AdminUserError> {
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let exists: bool = tx.query_row("SELECT EXISTS(SELECT 1 FROM users WHERE id = ?1)", params![user_id], |r| {
+        r.get(0)
+    })?;
+    if !exists {
+        return Err(AdminUserError::UnknownUser);
+    }
+    let previous: Option<AdminRole> = tx
+        .query_row("SELECT role FROM admin_users WHERE user_id = ?1", params![user_id], |r| r.get(0))
+        .optional()?;
+    if previous == Some(AdminRole::Superadmin) && role != AdminRole::Superadmin {
+        ensure_other_superadmin(&tx, user_id)?;
+    }
+    if role != AdminRole::Superadmin {
+        clear_legacy_role(&tx, user_id)?;
+    }
+    let now = Database::now_ts();
+    tx.execute(
+        "INSERT INTO admin_users (user_id, role, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)
+         ON CONFLICT(user_id) DO UPDATE SET role = excluded.role, updated_at = excluded.updated_at",
+        params![user_id, role.as_str(), now],
+    )?;
+    tx.commit()?;
+    Ok(previous)
+}
+
+/// Take away a user's admin access. Returns the role they had.
+pub fn remove(db: &Database, user_id: &str) -> Result<Option<AdminRole>, AdminUserError> {
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let previous: Option<AdminRole> = tx
+        .query_row("SELECT role FROM admin_users WHERE user_id = ?1", params![user_id], |r| r.get(0))
+        .optional()?;
+    if previous == Some(AdminRole::Superadmin) {
+        ensure_other_superadmin(&tx, user_id)?;
+    }
+    tx.execute("DELETE FROM admin_users WHERE user_id = ?1", params![user_id])?;
+    clear_legacy_role(&tx, user_id)?;
+    tx.commit()?;
+    Ok(previous)
+}
+
+/// Drop the `users.role = 'admin'` set by `passwordless-auth init`, so the
+/// legacy role does not outlive the admin it granted
+fn clear_legacy_role(conn: &rusqlite::Connection, user_id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute("UPDATE users SET role = 'user' WHERE id = ?1 AND role = 'admin'", params![user_id])?;
+    Ok(())
+}
+
+/// Refuse to leave the admin API without a superadmin user or API key
+fn ensure_other_superadmin(conn: &rusqlite::Connection, user_id: &str) -> Result<(), AdminUserError> {
+    let others: i64 = conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM admin_users WHERE role = 'superadmin' AND user_id != ?1)
+              + (SELECT COUNT(*) FROM admin_api_keys WHERE role = 'superadmin' AND revoked_at IS NULL)",
+        params![user_id],
+        |r| r.get(0),
+    )?;
+    if others == 0 {
+        return Err(AdminUserError::LastSuperadmin);
+    }
+    Ok(())
+}
+
+/// Whether the request's admin may act with `role`
+pub fn permits(actor: &AdminActor, role: AdminRole) -> bool {
+    actor.role() >= role
+}
+
+fn forbidden(role: AdminRole) -> ErrorResponse {
+    ErrorResponse::forbidden(ApiError::forbidden(format!("requires the {} admin role", role)))
+}
+
+/// Guard for handlers only a superadmin may call
+pub struct Superadmin;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Superadmin {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // every admin request carries an actor; a missing one is a wiring bug
+        match parts.extensions.get::<AdminActor>() {
+            Some(actor) if permits(actor, AdminRole::Superadmin) => Ok(Self),
+            _ => Err(forbidden(AdminRole::Superadmin)),
+        }
+    }
+}
+
+/// Rejection for a request whose method needs a higher role than the admin has
+pub(crate) fn require(actor: &AdminActor, role: AdminRole) -> Result<(), ErrorResponse> {
+    if permits(actor, role) {
+        Ok(())
+    } else {
+        Err(forbidden(role))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_include_lower_ones() {
+        let operator = AdminActor::User {
+            user_id: "u1".into(),
+            role: AdminRole::Operator,
+        };
+        assert!(permits(&operator, AdminRole::Viewer));
+        assert!(permits(&operator, AdminRole::Operator));
+        assert!(!permits(&operator, AdminRole::Superadmin));
+        assert!(permits(&AdminActor::DevMode, AdminRole::Superadmin), "authentication is off");
+        assert_eq!("superadmin".parse::<AdminRole>().unwrap(), AdminRole::Superadmin);
+        assert!("admin".parse::<AdminRole>().is_err());
+    }
+}
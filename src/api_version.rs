@@ -0,0 +1,292 @@
+//! Versioned response shapes for the public auth routes.
+//!
+//! Clients pick a version with a path prefix (`/v2/token/refresh`) or the
+//! `Accept` header (`application/vnd.passwordless.v2+json`); anything else
+//! gets `[api_versions] default_version`. Handlers keep answering in the v1
+//! shape and this layer converts: v2 wraps every error in one JSON envelope
+//! and plain-text bodies in `{"message": ...}`, while v1 responses carry
+//! deprecation headers pointing at their v2 successor. Handlers that need to
+//! branch on the version read it from the request's `ApiVersion` extension.
+
+use crate::{error::ApiError, middleware::RequestId};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// Response header naming the version that served the request
+pub const VERSION_HEADER: &str = "api-version";
+
+/// Vendor media type selecting a version, e.g. `application/vnd.passwordless.v2+json`
+const MEDIA_TYPE_PREFIX: &str = "application/vnd.passwordless.v";
+
+/// Top-level keys of the v1 error shapes, folded into the v2 `error` object
+const ERROR_KEYS: &[&str] = &["code", "message", "details", "request_id", "error"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiVersion {
+    /// Original shapes: plain-text errors and messages
+    #[default]
+    V1,
+    /// JSON everywhere, errors as `{"error": {"code", "message", ...}}`
+    V2,
+}
+
+impl ApiVersion {
+    pub fn number(self) -> &'static str {
+        match self {
+            Self::V1 => "1",
+            Self::V2 => "2",
+        }
+    }
+
+    /// Version requested by `Accept`, either as the vendor media type or a
+    /// `version=` parameter (`application/json; version=2`)
+    pub fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+        accept.split(',').find_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media = params.next()?.to_ascii_lowercase();
+            let number = match media.strip_prefix(MEDIA_TYPE_PREFIX) {
+                Some(rest) => rest.strip_suffix("+json").unwrap_or(rest).to_string(),
+                None => params.find_map(|p| p.strip_prefix("version="))?.to_string(),
+            };
+            match number.as_str() {
+                "1" => Some(Self::V1),
+                "2" => Some(Self::V2),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// `[api_versions]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiVersionConfig {
+    /// Version for requests without a `/v1` or `/v2` prefix or versioned `Accept`
+    #[serde(default)]
+    pub default_version: ApiVersion,
+    /// Mark v1 responses deprecated (`Deprecation` and a `successor-version` link)
+    #[serde(default = "default_deprecate_v1")]
+    pub deprecate_v1: bool,
+    /// HTTP date after which v1 may be removed, sent as `Sunset`
+    #[serde(default)]
+    pub v1_sunset: Option<String>,
+}
+
+impl Default for ApiVersionConfig {
+    fn default() -> Self {
+        Self {
+            default_version: ApiVersion::V1,
+            deprecate_v1: default_deprecate_v1(),
+            v1_sunset: None,
+        }
+    }
+}
+
+fn default_deprecate_v1() -> bool {
+    true
+}
+
+/// State of [`negotiate`] for one mount of the auth routes
+#[derive(Debug, Clone)]
+pub struct Versioning {
+    /// Set for the `/v1` and `/v2` mounts; `None` negotiates via `Accept`
+    pub pinned: Option<ApiVersion>,
+    pub cfg: ApiVersionConfig,
+}
+
+/// Pick the version for a request and shape its response accordingly
+pub async fn negotiate(State(versioning): State<Versioning>, mut request: Request, next: Next) -> Response {
+    let version = versioning
+        .pinned
+        .or_else(|| ApiVersion::from_accept(request.headers()))
+        .unwrap_or(versioning.cfg.default_version);
+    request.extensions_mut().insert(version);
+    let request_id = request.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let successor = format!("</v2{}>; rel=\"successor-version\"", request.uri().path());
+
+    let mut response = next.run(request).await;
+    if version == ApiVersion::V2 {
+        response = to_v2(response, request_id).await;
+    }
+    let headers = response.headers_mut();
+    headers.insert(VERSION_HEADER, HeaderValue::from_static(version.number()));
+    if versioning.pinned.is_none() {
+        headers.append(header::VARY, HeaderValue::from_static("accept"));
+    }
+    if version == ApiVersion::V1 && versioning.cfg.deprecate_v1 {
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        if let Some(sunset) = versioning.cfg.v1_sunset.as_deref().and_then(|s| HeaderValue::from_str(s).ok()) {
+            headers.insert("sunset", sunset);
+        }
+        if let Ok(link) = HeaderValue::from_str(&successor) {
+            headers.append(header::LINK, link);
+        }
+    }
+    response
+}
+
+/// Only plain-text confirmations are wrapped in v2. JSON is already in shape
+/// and HTML pages (such as the abuse report confirmation) are meant for a
+/// browser, so every other successful body passes through unchanged.
+fn is_plain_text(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/plain"))
+}
+
+/// Convert a v1 response to the v2 shape
+async fn to_v2(response: Response, request_id: Option<String>) -> Response {
+    let status = response.status();
+    let is_error = status.is_client_error() || status.is_server_error();
+    if !(is_error || status.is_success() && is_plain_text(response.headers())) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    if !is_error && bytes.is_empty() {
+        return Response::from_parts(parts, Body::empty());
+    }
+    let body = if is_error {
+        error_envelope(status, parts.extensions.get::<ApiError>(), &bytes, request_id)
+    } else {
+        json!({ "message": String::from_utf8_lossy(&bytes) })
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+/// Generic code for errors raised without an [`ApiError`]
+fn status_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::UNAUTHORIZED => "UNAUTHORIZED",
+        StatusCode::FORBIDDEN => "FORBIDDEN",
+        StatusCode::NOT_FOUND => "NOT_FOUND",
+        StatusCode::CONFLICT => "CONFLICT",
+        StatusCode::PRECONDITION_REQUIRED => "PRECONDITION_REQUIRED",
+        StatusCode::TOO_MANY_REQUESTS => "RATE_LIMITED",
+        StatusCode::SERVICE_UNAVAILABLE => "SERVICE_UNAVAILABLE",
+        s if s.is_server_error() => "INTERNAL_ERROR",
+        _ => "BAD_REQUEST",
+    }
+}
+
+/// The v2 error body: `{"error": {...}}` plus any extra fields of the v1 body
+/// (e.g. `attempt_token` on a failed TOTP attempt)
+pub fn error_envelope(status: StatusCode, tagged: Option<&ApiError>, body: &[u8], request_id: Option<String>) -> Value {
+    let mut fields = match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    let mut error = match tagged {
+        Some(error) => error.clone(),
+        None => match serde_json::from_value::<ApiError>(Value::Object(fields.clone())) {
+            Ok(error) => error,
+            Err(_) => {
+                let message = match fields.get("error") {
+                    Some(Value::String(message)) => message.clone(),
+                    _ => String::from_utf8_lossy(body).into_owned(),
+                };
+                ApiError::new(status_code(status), message)
+            }
+        },
+    };
+    if error.request_id.is_none() {
+        error.request_id = request_id;
+    }
+    fields.retain(|key, _| !ERROR_KEYS.contains(&key.as_str()));
+    fields.insert("error".to_string(), serde_json::to_value(error).unwrap_or_default());
+    Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn v1_error_shapes_become_one_envelope() {
+        let text = error_envelope(StatusCode::BAD_REQUEST, None, b"missing refresh token", None);
+        assert_eq!(text, json!({ "error": { "code": "BAD_REQUEST", "message": "missing refresh token" } }));
+
+        let retry = br#"{"error":"invalid totp","attempt_token":"at","remaining_attempts":2}"#;
+        let tagged = ApiError::invalid_totp();
+        let envelope = error_envelope(StatusCode::BAD_REQUEST, Some(&tagged), retry, Some("req-1".into()));
+        assert_eq!(envelope["error"]["code"], "INVALID_TOTP");
+        assert_eq!(envelope["error"]["request_id"], "req-1");
+        assert_eq!(envelope["remaining_attempts"], 2);
+
+        let api_error = serde_json::to_vec(&ApiError::session_not_found()).unwrap();
+        let envelope = error_envelope(StatusCode::NOT_FOUND, None, &api_error, None);
+        assert_eq!(envelope, json!({ "error": { "code": "SESSION_NOT_FOUND", "message": "Session not found" } }));
+    }
+
+    #[tokio::test]
+    async fn accept_header_selects_the_version() {
+        let versioning = Versioning {
+            pinned: None,
+            cfg: ApiVersionConfig {
+                v1_sunset: Some("Wed, 01 Jul 2026 00:00:00 GMT".into()),
+                ..Default::default()
+            },
+        };
+        let app = Router::new()
+            .route("/totp/disable", get(|| async { (StatusCode::FORBIDDEN, "account frozen") }))
+            .layer(axum::middleware::from_fn_with_state(versioning, negotiate));
+        let request = |accept: &str| {
+            Request::get("/totp/disable")
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let v1 = app.clone().oneshot(request("*/*")).await.unwrap();
+        assert_eq!(v1.headers()["deprecation"], "true");
+        assert_eq!(v1.headers()["sunset"], "Wed, 01 Jul 2026 00:00:00 GMT");
+        assert_eq!(v1.headers()[header::LINK], "</v2/totp/disable>; rel=\"successor-version\"");
+
+        let v2 = app.oneshot(request("application/vnd.passwordless.v2+json")).await.unwrap();
+        assert_eq!(v2.headers()[VERSION_HEADER], "2");
+        assert!(v2.headers().get("deprecation").is_none());
+        let body: Value = serde_json::from_slice(&to_bytes(v2.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["error"]["code"], "FORBIDDEN");
+        assert_eq!(body["error"]["message"], "account frozen");
+    }
+
+    #[tokio::test]
+    async fn v2_wraps_plain_text_but_not_html() {
+        let versioning = Versioning {
+            pinned: Some(ApiVersion::V2),
+            cfg: ApiVersionConfig::default(),
+        };
+        let app = Router::new()
+            .route("/logout", get(|| async { "logged out" }))
+            .route("/report", get(|| async { axum::response::Html("<p>Thanks</p>") }))
+            .layer(axum::middleware::from_fn_with_state(versioning, negotiate));
+
+        let text = app.clone().oneshot(Request::get("/logout").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(text.headers()[header::CONTENT_TYPE], "application/json");
+        let bytes = to_bytes(text.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], br#"{"message":"logged out"}"#);
+
+        let html = app.oneshot(Request::get("/report").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(html.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+        let bytes = to_bytes(html.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"<p>Thanks</p>");
+    }
+}
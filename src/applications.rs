@@ -0,0 +1,84 @@
+use crate::config::TokenLifetimes;
+use crate::consents::ConsentScope;
+use crate::geo_policy::GeoRules;
+use crate::subjects::SubjectType;
+use crate::transport::TokenTransport;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+
+/// Header relying applications use to identify themselves
+pub const CLIENT_ID_HEADER: &str = "X-Client-Id";
+
+/// A relying application registered in `config.toml` under `[[applications]]`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApplicationConfig {
+    pub client_id: String,
+    pub name: String,
+    /// How issued tokens are returned to this application (falls back to the
+    /// global `token_transport` when unset)
+    #[serde(default)]
+    pub token_transport: Option<TokenTransport>,
+    /// Secret for server-to-server calls (HTTP Basic `client_id:client_secret`)
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// `public` (default) or `pairwise` subject identifiers in access tokens
+    #[serde(default)]
+    pub subject_type: SubjectType,
+    /// Secret salt for HMAC-derived pairwise subjects; keep stable, changing it
+    /// changes every user's `sub` for this application. Must differ between
+    /// applications (checked at startup).
+    #[serde(default)]
+    pub pairwise_salt: Option<String>,
+    /// Redirect targets this application may request, matched exactly
+    /// (`https://*.example.com/...` allows one subdomain label)
+    #[serde(default)]
+    pub allowed_redirect_uris: Vec<String>,
+    /// Profile fields users must provide before this application receives a
+    /// full access token (e.g. `["display_name", "company"]`)
+    #[serde(default)]
+    pub required_profile_fields: Vec<String>,
+    /// Profile data this application asks users to share (`email`, `profile`,
+    /// `phone`); it receives each only once the user consents
+    #[serde(default)]
+    pub scopes: Vec<ConsentScope>,
+    /// Per-application `access_token_expiry_seconds` / `refresh_token_expiry_seconds`
+    #[serde(flatten)]
+    pub lifetimes: TokenLifetimes,
+    /// Country/ASN login rules replacing the global `[geo_policy]` ones
+    #[serde(default)]
+    pub geo_policy: Option<GeoRules>,
+    /// `[[apis]]` audiences this application's access tokens are valid for
+    #[serde(default)]
+    pub allowed_apis: Vec<String>,
+}
+
+impl ApplicationConfig {
+    /// `aud` of access tokens issued to this application: its allowed APIs,
+    /// or its own `client_id` when it lists none
+    pub fn audiences(&self) -> Vec<&str> {
+        if self.allowed_apis.is_empty() {
+            vec![self.client_id.as_str()]
+        } else {
+            self.allowed_apis.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// Whether a token carrying the `aud` claim `aud` was issued to this application
+    pub fn issued(&self, aud: &[String]) -> bool {
+        self.audiences().iter().any(|audience| aud.iter().any(|a| a == audience))
+    }
+}
+
+/// A resource server registered under `[[apis]]`. Its `audience` is the
+/// `aud` value it requires; tokens minted for other APIs do not carry it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiConfig {
+    pub audience: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+/// Read the calling application's client id from the request headers
+pub fn client_id(headers: &HeaderMap) -> Option<&str> {
+    headers.get(CLIENT_ID_HEADER).and_then(|v| v.to_str().ok())
+}
@@ -0,0 +1,108 @@
+use crate::{
+    crypto::{constant_time_eq, hmac_sha256},
+    db::Database,
+};
+use data_encoding::BASE64URL_NOPAD;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Proof-of-continuity token handed out after a failed verification attempt.
+///
+/// The next attempt must present it; each token is bound to the server-side
+/// attempt sequence and is accepted once, so guesses cannot be spread across
+/// parallel connections.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AttemptClaims {
+    /// Opaque id of the attempt window; tokens never carry the user id
+    pub window: String,
+    /// Failed attempts so far in the current window
+    pub failures: u32,
+    pub seq: i64,
+    pub exp: i64,
+}
+
+/// Server-side attempt window for a user
+#[derive(Debug, Clone)]
+pub struct AttemptState {
+    pub window_id: String,
+    pub failures: u32,
+    pub seq: i64,
+    pub expires_at: i64,
+}
+
+/// Key attempt tokens are signed with, derived from the JWT secret so it is
+/// never used for two purposes
+pub fn key(jwt_secret: &str) -> String {
+    data_encoding::HEXLOWER.encode(&hmac_sha256(jwt_secret.as_bytes(), b"totp-attempt"))
+}
+
+pub fn sign(secret: &str, claims: &AttemptClaims) -> String {
+    let body = BASE64URL_NOPAD.encode(serde_json::to_string(claims).unwrap_or_default().as_bytes());
+    let mac = hmac_sha256(secret.as_bytes(), format!("attempt|{}", body).as_bytes());
+    format!("{}.{}", body, BASE64URL_NOPAD.encode(&mac))
+}
+
+pub fn verify(secret: &str, token: &str) -> Option<AttemptClaims> {
+    let (body, sig) = token.split_once('.')?;
+    let mac = hmac_sha256(secret.as_bytes(), format!("attempt|{}", body).as_bytes());
+    if !constant_time_eq(BASE64URL_NOPAD.encode(&mac).as_bytes(), sig.as_bytes()) {
+        return None;
+    }
+    let claims: AttemptClaims = serde_json::from_slice(&BASE64URL_NOPAD.decode(body.as_bytes()).ok()?).ok()?;
+    (claims.exp > Database::now_ts()).then_some(claims)
+}
+
+/// Active attempt window for the user, if any
+pub fn current(db: &Database, user_id: &str) -> Result<Option<AttemptState>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            "SELECT COALESCE(window_id, ''), failures, seq, expires_at FROM totp_attempts
+             WHERE user_id = ?1 AND expires_at > ?2",
+            params![user_id, Database::now_ts()],
+            state,
+        )
+        .optional()
+}
+
+/// Consume the token for `seq`; only one concurrent attempt can win
+pub fn claim(db: &Database, user_id: &str, seq: i64) -> Result<bool, rusqlite::Error> {
+    let updated = db.conn().execute(
+        "UPDATE totp_attempts SET seq = seq + 1 WHERE user_id = ?1 AND seq = ?2",
+        params![user_id, seq],
+    )?;
+    Ok(updated == 1)
+}
+
+/// Count a failed attempt, starting a new window when none is active
+pub fn record_failure(db: &Database, user_id: &str, window_seconds: i64) -> Result<AttemptState, rusqlite::Error> {
+    let now = Database::now_ts();
+    db.conn().execute(
+        "INSERT INTO totp_attempts (user_id, failures, seq, expires_at, window_id) VALUES (?1, 1, 1, ?2, ?4)
+         ON CONFLICT(user_id) DO UPDATE SET
+             failures = CASE WHEN expires_at > ?3 THEN failures + 1 ELSE 1 END,
+             seq = CASE WHEN expires_at > ?3 THEN seq ELSE 1 END,
+             window_id = CASE WHEN expires_at > ?3 AND window_id IS NOT NULL THEN window_id ELSE ?4 END,
+             expires_at = ?2",
+        params![user_id, now + window_seconds, now, Uuid::new_v4().to_string()],
+    )?;
+    db.conn().query_row(
+        "SELECT window_id, failures, seq, expires_at FROM totp_attempts WHERE user_id = ?1",
+        params![user_id],
+        state,
+    )
+}
+
+fn state(r: &rusqlite::Row) -> rusqlite::Result<AttemptState> {
+    Ok(AttemptState {
+        window_id: r.get(0)?,
+        failures: r.get(1)?,
+        seq: r.get(2)?,
+        expires_at: r.get(3)?,
+    })
+}
+
+pub fn clear(db: &Database, user_id: &str) -> Result<(), rusqlite::Error> {
+    db.conn().execute("DELETE FROM totp_attempts WHERE user_id = ?1", params![user_id])?;
+    Ok(())
+}
@@ -1,8 +1,217 @@
-use chrono::{DateTime, Utc};
+use crate::{
+    db::Database,
+    leader::LeaderElection,
+    webhooks::{WebhookEventType, WebhookPayload, WebhookSender},
+};
+use chrono::{DateTime, Datelike, Utc};
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tracing::{error, info};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::{error, info, warn};
+
+/// Unpartitioned table; always queried so pre-partitioning history stays visible
+pub const LEGACY_TABLE: &str = "audit_logs";
+
+const COLUMNS: &str = "id, event_type, user_id, email, ip_address, user_agent, metadata, success, created_at";
+
+/// How audit rows are laid out in storage
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditPartitioning {
+    /// Single `audit_logs` table
+    #[default]
+    None,
+    /// One `audit_logs_YYYY_MM` table per calendar month (UTC), created on demand
+    Monthly,
+}
+
+/// How long events of each severity are kept, in days (0 keeps forever)
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuditRetention {
+    #[serde(default = "default_info_days")]
+    pub info_days: u32,
+    #[serde(default = "default_warn_days")]
+    pub warn_days: u32,
+    #[serde(default = "default_security_days")]
+    pub security_days: u32,
+}
+
+impl Default for AuditRetention {
+    fn default() -> Self {
+        Self {
+            info_days: default_info_days(),
+            warn_days: default_warn_days(),
+            security_days: default_security_days(),
+        }
+    }
+}
+
+fn default_info_days() -> u32 {
+    30
+}
+
+fn default_warn_days() -> u32 {
+    90
+}
+
+fn default_security_days() -> u32 {
+    365
+}
+
+impl AuditRetention {
+    pub fn days(&self, severity: AuditSeverity) -> u32 {
+        match severity {
+            AuditSeverity::Info => self.info_days,
+            AuditSeverity::Warn => self.warn_days,
+            AuditSeverity::Security => self.security_days,
+        }
+    }
+}
+
+/// `[audit]` configuration table
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AuditConfig {
+    #[serde(default)]
+    pub partitioning: AuditPartitioning,
+    /// Per-severity retention (`[audit.retention]`)
+    #[serde(default)]
+    pub retention: AuditRetention,
+    /// Forward security-severity events to the webhook as `security_alert`
+    #[serde(default)]
+    pub security_alerts: bool,
+    /// Keep an FTS5 index over email, IP and user agent so `q` searches do
+    /// not scan every row; without it `q` still works, just slower
+    #[serde(default)]
+    pub full_text_search: bool,
+}
+
+/// Filters for audit queries and exports
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct AuditQuery {
+    pub user_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub severity: Option<AuditSeverity>,
+    /// Substring of the email, IP address or user agent (case-insensitive)
+    #[serde(default)]
+    pub q: Option<String>,
+    /// `(key path, value)` pairs the JSON metadata must contain; from
+    /// `meta.<key>[.<nested>]=<value>` query parameters
+    #[serde(skip)]
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Most `meta.*` filters accepted in one query
+pub const MAX_METADATA_FILTERS: usize = 8;
+
+impl AuditQuery {
+    /// Add a metadata filter from a `meta.` query key (without the prefix).
+    /// Keys are dot-separated segments of letters, digits, `_` and `-`.
+    pub fn metadata_filter(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let valid = key
+            .split('.')
+            .all(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+        if !valid {
+            return Err(format!("invalid metadata key: meta.{}", key));
+        }
+        if self.metadata.len() >= MAX_METADATA_FILTERS {
+            return Err(format!("at most {} metadata filters are allowed", MAX_METADATA_FILTERS));
+        }
+        // quoted segments so keys like `credential-id` are valid JSON paths
+        let path = key.split('.').fold("$".to_string(), |p, s| format!("{}.\"{}\"", p, s));
+        self.metadata.push((path, value.to_string()));
+        Ok(())
+    }
+}
+
+/// WHERE clause of a query; identical for every table except the text search,
+/// which uses the table's full-text index when it has one
+struct Filter {
+    clauses: Vec<String>,
+    /// Parameter holding the `q` search text
+    text: Option<usize>,
+    /// Tables with a full-text index
+    indexed: HashSet<String>,
+}
+
+impl Filter {
+    fn sql(&self, table: &str) -> String {
+        let mut clauses = self.clauses.clone();
+        if let Some(p) = self.text {
+            clauses.push(if self.indexed.contains(table) {
+                // a quoted phrase is a substring match under the trigram tokenizer
+                format!(
+                    "id IN (SELECT rowid FROM {t}_fts WHERE {t}_fts MATCH '\"' || replace(?{p}, '\"', '\"\"') || '\"')",
+                    t = table,
+                    p = p
+                )
+            } else {
+                format!(
+                    "(instr(lower(email), lower(?{p})) > 0 OR instr(lower(ip_address), lower(?{p})) > 0 OR instr(lower(user_agent), lower(?{p})) > 0)",
+                    p = p
+                )
+            });
+        }
+        if clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", clauses.join(" AND "))
+        }
+    }
+}
+
+/// How much an audit event matters; drives retention and alerting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditSeverity {
+    /// Routine activity, rotated aggressively
+    Info,
+    /// Failures and abuse signals
+    Warn,
+    /// Factor and session changes; kept longest and may raise alerts
+    Security,
+}
+
+impl AuditSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Security => "security",
+        }
+    }
+
+    /// Severity of a stored event name; unknown names are treated as info
+    pub fn of(event_type: &str) -> Self {
+        AuditEventType::ALL
+            .iter()
+            .find(|e| e.as_str() == event_type)
+            .map_or(Self::Info, AuditEventType::severity)
+    }
+
+    /// Stored event names with this severity
+    fn event_names(&self) -> Vec<&'static str> {
+        AuditEventType::ALL
+            .iter()
+            .filter(|e| e.severity() == *self)
+            .map(AuditEventType::as_str)
+            .collect()
+    }
+}
+
+/// A security-severity event forwarded to the alert hook
+#[derive(Debug, Clone)]
+pub struct AuditAlert {
+    pub event_type: String,
+    pub user_id: Option<String>,
+    pub email: Option<String>,
+    pub ip_address: Option<String>,
+    pub metadata: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
 
 /// Audit event types for tracking authentication activities
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +224,8 @@ pub enum AuditEventType {
     MagicLinkFailed,
     /// User enrolled TOTP
     TotpEnrolled,
+    /// User turned TOTP off
+    TotpDisabled,
     /// User verified TOTP successfully
     TotpVerified,
     /// TOTP verification failed
@@ -43,15 +254,160 @@ pub enum AuditEventType {
     RateLimitExceeded,
     /// Invalid request
     InvalidRequest,
+    /// Signed action link followed
+    ActionLinkUsed,
+    /// Admin invalidated pending magic links or WebAuthn ceremonies
+    ChallengesInvalidated,
+    /// Pre-issuance fraud hook denied a completed login
+    IssuanceDenied,
+    /// A user's last session is about to expire
+    SessionExpiring,
+    /// A recipient reported a magic link they did not request
+    UnsolicitedLinkReported,
+    /// Admin rotated the webhook signing secret or ended a rotation's grace period
+    WebhookSecretRotated,
+    /// An address was banned for repeated auth failures, or by an admin
+    IpBanned,
+    /// Admin lifted an address ban
+    IpBanLifted,
+    /// A login was refused by a `[geo_policy]` block rule
+    GeoPolicyBlocked,
+    /// A login matched a `[geo_policy]` step-up rule (success when a second
+    /// factor was used)
+    GeoPolicyStepUp,
+    /// Admin switched read-only maintenance mode on or off
+    MaintenanceModeChanged,
+    /// Someone tried to sign in as a canary account
+    CanaryTriggered,
+    /// Admin added or removed a canary account
+    CanaryAccountChanged,
+    /// Admin created or canceled a broadcast
+    BroadcastChanged,
+    /// Admin created, canceled or rolled back a forced credential reset
+    CredentialResetChanged,
+    /// An account was flagged by the inactivity policy
+    StaleAccountFlagged,
+    /// A flagged account was disabled by the inactivity policy
+    StaleAccountDisabled,
+    /// A disabled inactive account was deleted
+    StaleAccountPurged,
+    /// A flagged account signed in again, or an admin reinstated a disabled one
+    StaleAccountReactivated,
+    /// An authenticated admin API call changed something; names the API key
+    /// or user that made it
+    AdminRequest,
+    /// A superadmin granted, changed or took away a user's admin role
+    AdminRoleChanged,
+    /// A superadmin deleted a user
+    UserDeleted,
+    /// A user granted or revoked an application's access to profile data
+    ConsentChanged,
+    /// A new access-token signing key was generated (`[key_rotation]`)
+    SigningKeyRotated,
+    /// An application registered at `/oauth/register`, or an admin approved,
+    /// rejected or deleted one
+    ClientRegistrationChanged,
+    /// A user approved or denied a device's sign-in request at `/device`
+    DeviceAuthorizationReviewed,
+    /// A device redeemed an approved device code for tokens
+    DeviceLoginCompleted,
 }
 
 impl AuditEventType {
+    pub const ALL: [AuditEventType; 46] = [
+        Self::MagicLinkRequested,
+        Self::MagicLinkVerified,
+        Self::MagicLinkFailed,
+        Self::TotpEnrolled,
+        Self::TotpDisabled,
+        Self::TotpVerified,
+        Self::TotpFailed,
+        Self::WebauthnRegisterStarted,
+        Self::WebauthnRegisterCompleted,
+        Self::WebauthnRegisterFailed,
+        Self::WebauthnLoginStarted,
+        Self::WebauthnLoginCompleted,
+        Self::WebauthnLoginFailed,
+        Self::TokenRefreshed,
+        Self::TokenRefreshFailed,
+        Self::SessionRevoked,
+        Self::UserLoggedOut,
+        Self::RateLimitExceeded,
+        Self::InvalidRequest,
+        Self::ActionLinkUsed,
+        Self::ChallengesInvalidated,
+        Self::IssuanceDenied,
+        Self::SessionExpiring,
+        Self::UnsolicitedLinkReported,
+        Self::WebhookSecretRotated,
+        Self::IpBanned,
+        Self::IpBanLifted,
+        Self::GeoPolicyBlocked,
+        Self::GeoPolicyStepUp,
+        Self::MaintenanceModeChanged,
+        Self::CanaryTriggered,
+        Self::CanaryAccountChanged,
+        Self::BroadcastChanged,
+        Self::CredentialResetChanged,
+        Self::StaleAccountFlagged,
+        Self::StaleAccountDisabled,
+        Self::StaleAccountPurged,
+        Self::StaleAccountReactivated,
+        Self::AdminRequest,
+        Self::AdminRoleChanged,
+        Self::UserDeleted,
+        Self::ConsentChanged,
+        Self::SigningKeyRotated,
+        Self::ClientRegistrationChanged,
+        Self::DeviceAuthorizationReviewed,
+        Self::DeviceLoginCompleted,
+    ];
+
+    pub fn severity(&self) -> AuditSeverity {
+        match self {
+            Self::TotpEnrolled
+            | Self::TotpDisabled
+            | Self::TotpFailed
+            | Self::WebauthnRegisterCompleted
+            | Self::SessionRevoked
+            | Self::ActionLinkUsed
+            | Self::ChallengesInvalidated
+            | Self::IssuanceDenied
+            | Self::UnsolicitedLinkReported
+            | Self::WebhookSecretRotated
+            | Self::SigningKeyRotated
+            | Self::ClientRegistrationChanged
+            | Self::DeviceAuthorizationReviewed
+            | Self::IpBanned
+            | Self::IpBanLifted
+            | Self::GeoPolicyBlocked
+            | Self::GeoPolicyStepUp
+            | Self::MaintenanceModeChanged
+            | Self::CanaryTriggered
+            | Self::CanaryAccountChanged
+            | Self::BroadcastChanged
+            | Self::CredentialResetChanged
+            | Self::StaleAccountDisabled
+            | Self::StaleAccountPurged
+            | Self::AdminRoleChanged
+            | Self::UserDeleted => AuditSeverity::Security,
+            Self::MagicLinkFailed
+            | Self::WebauthnRegisterFailed
+            | Self::WebauthnLoginFailed
+            | Self::TokenRefreshFailed
+            | Self::RateLimitExceeded
+            | Self::InvalidRequest => AuditSeverity::Warn,
+            _ => AuditSeverity::Info,
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::MagicLinkRequested => "magic_link_requested",
             Self::MagicLinkVerified => "magic_link_verified",
             Self::MagicLinkFailed => "magic_link_failed",
             Self::TotpEnrolled => "totp_enrolled",
+            Self::TotpDisabled => "totp_disabled",
             Self::TotpVerified => "totp_verified",
             Self::TotpFailed => "totp_failed",
             Self::WebauthnRegisterStarted => "webauthn_register_started",
@@ -66,6 +422,33 @@ impl AuditEventType {
             Self::UserLoggedOut => "user_logged_out",
             Self::RateLimitExceeded => "rate_limit_exceeded",
             Self::InvalidRequest => "invalid_request",
+            Self::ActionLinkUsed => "action_link_used",
+            Self::ChallengesInvalidated => "challenges_invalidated",
+            Self::IssuanceDenied => "issuance_denied",
+            Self::SessionExpiring => "session_expiring",
+            Self::UnsolicitedLinkReported => "unsolicited_link_reported",
+            Self::WebhookSecretRotated => "webhook_secret_rotated",
+            Self::IpBanned => "ip_banned",
+            Self::IpBanLifted => "ip_ban_lifted",
+            Self::GeoPolicyBlocked => "geo_policy_blocked",
+            Self::GeoPolicyStepUp => "geo_policy_step_up",
+            Self::MaintenanceModeChanged => "maintenance_mode_changed",
+            Self::CanaryTriggered => "canary_triggered",
+            Self::CanaryAccountChanged => "canary_account_changed",
+            Self::BroadcastChanged => "broadcast_changed",
+            Self::CredentialResetChanged => "credential_reset_changed",
+            Self::StaleAccountFlagged => "stale_account_flagged",
+            Self::StaleAccountDisabled => "stale_account_disabled",
+            Self::StaleAccountPurged => "stale_account_purged",
+            Self::StaleAccountReactivated => "stale_account_reactivated",
+            Self::AdminRequest => "admin_request",
+            Self::AdminRoleChanged => "admin_role_changed",
+            Self::UserDeleted => "user_deleted",
+            Self::ConsentChanged => "consent_changed",
+            Self::SigningKeyRotated => "signing_key_rotated",
+            Self::ClientRegistrationChanged => "client_registration_changed",
+            Self::DeviceAuthorizationReviewed => "device_authorization_reviewed",
+            Self::DeviceLoginCompleted => "device_login_completed",
         }
     }
 }
@@ -81,23 +464,442 @@ pub struct AuditLog {
     pub user_agent: Option<String>,
     pub metadata: Option<String>,
     pub success: bool,
+    pub severity: AuditSeverity,
     pub created_at: DateTime<Utc>,
 }
 
 /// Audit logger for tracking authentication events
 pub struct AuditLogger {
-    // In a production system, this might write to a separate database or log service
+    partitioning: AuditPartitioning,
+    /// Partition most recently ensured to exist, so DDL runs once per month
+    current_partition: Mutex<Option<String>>,
+    /// Receives security-severity events when alerting is enabled
+    alerts: Option<UnboundedSender<AuditAlert>>,
+    /// Give new partitions a full-text index
+    full_text_search: bool,
+}
+
+/// Name of the monthly partition holding events at `at`
+pub fn partition_name(at: DateTime<Utc>) -> String {
+    format!("{}_{:04}_{:02}", LEGACY_TABLE, at.year(), at.month())
+}
+
+/// Month `(year, month)` encoded in a partition table name
+fn partition_month(table: &str) -> Option<(i32, u32)> {
+    let rest = table.strip_prefix("audit_logs_")?;
+    let (year, month) = rest.split_once('_')?;
+    if year.len() != 4 || month.len() != 2 {
+        return None;
+    }
+    Some((year.parse().ok()?, month.parse().ok()?))
+}
+
+fn month_key(at: DateTime<Utc>) -> (i32, u32) {
+    (at.year(), at.month())
 }
 
 impl AuditLogger {
     pub fn new() -> Self {
-        Self {}
+        Self::with_partitioning(AuditPartitioning::None)
+    }
+
+    pub fn with_partitioning(partitioning: AuditPartitioning) -> Self {
+        Self {
+            partitioning,
+            current_partition: Mutex::new(None),
+            alerts: None,
+            full_text_search: false,
+        }
+    }
+
+    /// Keep full-text indexes for `q` searches (see [`AuditLogger::prepare_search`])
+    pub fn with_full_text_search(mut self, enabled: bool) -> Self {
+        self.full_text_search = enabled;
+        self
+    }
+
+    /// Forward security-severity events to `alerts` as they are recorded
+    pub fn with_alerts(mut self, alerts: UnboundedSender<AuditAlert>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Create a monthly partition (and its indexes) if it does not exist
+    pub fn ensure_partition(conn: &Connection, table: &str) -> Result<(), rusqlite::Error> {
+        if partition_month(table).is_none() {
+            return Err(rusqlite::Error::InvalidParameterName(table.to_string()));
+        }
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {t} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                event_type TEXT NOT NULL,
+                user_id TEXT,
+                email TEXT,
+                ip_address TEXT,
+                user_agent TEXT,
+                metadata TEXT,
+                success BOOLEAN NOT NULL DEFAULT 1,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE INDEX IF NOT EXISTS idx_{t}_user_id ON {t}(user_id);
+            CREATE INDEX IF NOT EXISTS idx_{t}_created_at ON {t}(created_at);
+            CREATE INDEX IF NOT EXISTS idx_{t}_event_type ON {t}(event_type);",
+            t = table
+        ))
+    }
+
+    /// Pre-create the partitions for this month and next so the first write
+    /// after a month boundary does not pay for DDL
+    pub fn prepare_partitions(&self, db: &Database) -> Result<(), rusqlite::Error> {
+        if self.partitioning != AuditPartitioning::Monthly {
+            return Ok(());
+        }
+        let conn = &db.conn();
+        let now = Utc::now();
+        let next = now
+            .with_day(1)
+            .and_then(|d| d.checked_add_months(chrono::Months::new(1)))
+            .unwrap_or(now);
+        for table in [partition_name(now), partition_name(next)] {
+            Self::ensure_partition(conn, &table)?;
+            if self.full_text_search {
+                Self::ensure_search_index(conn, &table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Table new events are written to
+    fn write_table(&self, conn: &Connection) -> String {
+        if self.partitioning == AuditPartitioning::None {
+            return LEGACY_TABLE.to_string();
+        }
+        let table = partition_name(Utc::now());
+        let mut current = self.current_partition.lock().unwrap();
+        if current.as_deref() != Some(table.as_str()) {
+            if let Err(e) = Self::ensure_partition(conn, &table) {
+                error!("Failed to create audit partition {}: {}", table, e);
+                return LEGACY_TABLE.to_string();
+            }
+            if self.full_text_search {
+                if let Err(e) = Self::ensure_search_index(conn, &table) {
+                    error!("Failed to index audit partition {}: {}", table, e);
+                }
+            }
+            *current = Some(table.clone());
+        }
+        table
+    }
+
+    /// Existing monthly partitions, newest first
+    pub fn partitions(conn: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'audit_logs_%'",
+        )?;
+        let mut tables: Vec<String> = stmt
+            .query_map([], |r| r.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|t| partition_month(t).is_some())
+            .collect();
+        tables.sort_by(|a, b| b.cmp(a));
+        Ok(tables)
+    }
+
+    /// Tables that may hold rows in the query's time range, newest first
+    fn tables_for(conn: &Connection, query: &AuditQuery) -> Result<Vec<String>, rusqlite::Error> {
+        let from = query.from.map(month_key);
+        let to = query.to.map(month_key);
+        let mut tables: Vec<String> = Self::partitions(conn)?
+            .into_iter()
+            .filter(|table| {
+                let month = partition_month(table);
+                from.is_none_or(|f| month >= Some(f)) && to.is_none_or(|t| month <= Some(t))
+            })
+            .collect();
+        tables.push(LEGACY_TABLE.to_string());
+        Ok(tables)
+    }
+
+    fn where_clause(
+        conn: &Connection,
+        query: &AuditQuery,
+    ) -> Result<(Filter, Vec<Box<dyn rusqlite::ToSql>>), rusqlite::Error> {
+        let mut clauses = Vec::new();
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        if let Some(user_id) = &query.user_id {
+            params.push(Box::new(user_id.clone()));
+            clauses.push(format!("user_id = ?{}", params.len()));
+        }
+        if let Some(from) = query.from {
+            params.push(Box::new(from.to_rfc3339()));
+            clauses.push(format!("created_at >= ?{}", params.len()));
+        }
+        if let Some(to) = query.to {
+            params.push(Box::new(to.to_rfc3339()));
+            clauses.push(format!("created_at <= ?{}", params.len()));
+        }
+        if let Some(severity) = query.severity {
+            clauses.push(Self::severity_clause(severity, &mut params));
+        }
+        for (path, value) in &query.metadata {
+            params.push(Box::new(path.clone()));
+            params.push(Box::new(value.clone()));
+            // rows written before metadata was always JSON are skipped, not errors
+            clauses.push(format!(
+                "CAST(CASE WHEN json_valid(metadata) THEN json_extract(metadata, ?{}) END AS TEXT) = ?{}",
+                params.len() - 1,
+                params.len()
+            ));
+        }
+        let mut text = None;
+        let mut indexed = HashSet::new();
+        if let Some(q) = query.q.as_deref().filter(|q| !q.is_empty()) {
+            params.push(Box::new(q.to_string()));
+            text = Some(params.len());
+            // trigrams cannot match anything shorter than three characters
+            if q.chars().count() >= 3 {
+                indexed = Self::search_indexes(conn)?;
+            }
+        }
+        Ok((Filter { clauses, text, indexed }, params))
+    }
+
+    /// Audit tables that have a full-text index
+    fn search_indexes(conn: &Connection) -> Result<HashSet<String>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT substr(name, 1, length(name) - 4) FROM sqlite_master WHERE type = 'table' AND name LIKE 'audit_logs%\\_fts' ESCAPE '\\'",
+        )?;
+        let tables = stmt.query_map([], |r| r.get::<_, String>(0))?;
+        tables.collect()
+    }
+
+    /// Create the full-text index of a table (and the triggers keeping it
+    /// current), indexing existing rows the first time
+    pub fn ensure_search_index(conn: &Connection, table: &str) -> Result<(), rusqlite::Error> {
+        if table != LEGACY_TABLE && partition_month(table).is_none() {
+            return Err(rusqlite::Error::InvalidParameterName(table.to_string()));
+        }
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [format!("{}_fts", table)],
+            |r| r.get(0),
+        )?;
+        if exists {
+            return Ok(());
+        }
+        // rows are never updated, so inserts and deletes are all that need mirroring
+        conn.execute_batch(&format!(
+            "CREATE VIRTUAL TABLE {t}_fts USING fts5(
+                email, ip_address, user_agent, content='{t}', content_rowid='id', tokenize='trigram'
+            );
+            CREATE TRIGGER IF NOT EXISTS {t}_fts_insert AFTER INSERT ON {t} BEGIN
+                INSERT INTO {t}_fts (rowid, email, ip_address, user_agent)
+                VALUES (new.id, new.email, new.ip_address, new.user_agent);
+            END;
+            CREATE TRIGGER IF NOT EXISTS {t}_fts_delete AFTER DELETE ON {t} BEGIN
+                INSERT INTO {t}_fts ({t}_fts, rowid, email, ip_address, user_agent)
+                VALUES ('delete', old.id, old.email, old.ip_address, old.user_agent);
+            END;
+            INSERT INTO {t}_fts ({t}_fts) VALUES ('rebuild');",
+            t = table
+        ))
+    }
+
+    /// Drop a table's full-text index and its triggers
+    fn drop_search_index(conn: &Connection, table: &str) -> Result<(), rusqlite::Error> {
+        conn.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS {t}_fts_insert;
+            DROP TRIGGER IF EXISTS {t}_fts_delete;
+            DROP TABLE IF EXISTS {t}_fts;",
+            t = table
+        ))
+    }
+
+    /// Build or remove the full-text indexes of every audit table to match
+    /// `[audit] full_text_search`; run once at startup
+    pub fn prepare_search(&self, db: &Database) -> Result<(), rusqlite::Error> {
+        let conn = &db.conn();
+        for table in Self::tables_for(conn, &AuditQuery::default())? {
+            if self.full_text_search {
+                Self::ensure_search_index(conn, &table)?;
+            } else {
+                Self::drop_search_index(conn, &table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Query audit logs across every partition overlapping the time range,
+    /// newest first. Row ids are unique per partition only.
+    pub fn query(
+        &self,
+        db: &Database,
+        query: &AuditQuery,
+        offset: i32,
+        limit: i32,
+    ) -> Result<Vec<AuditLog>, rusqlite::Error> {
+        let conn = &db.conn();
+        let (filter, mut params) = Self::where_clause(conn, query)?;
+        let union = Self::tables_for(conn, query)?
+            .iter()
+            .map(|t| format!("SELECT {} FROM {}{}", COLUMNS, t, filter.sql(t)))
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+        params.push(Box::new(limit));
+        params.push(Box::new(offset));
+        let sql = format!(
+            "SELECT {} FROM ({}) ORDER BY created_at DESC LIMIT ?{} OFFSET ?{}",
+            COLUMNS,
+            union,
+            params.len() - 1,
+            params.len()
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let logs = stmt.query_map(
+            rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+            Self::map_row,
+        )?;
+        logs.collect()
+    }
+
+    /// Visit every matching row one partition at a time (oldest month first),
+    /// so exports never materialize the whole history at once
+    pub fn export<F>(&self, db: &Database, query: &AuditQuery, mut visit: F) -> Result<usize, rusqlite::Error>
+    where
+        F: FnMut(AuditLog),
+    {
+        let conn = &db.conn();
+        let (filter, params) = Self::where_clause(conn, query)?;
+        let mut tables = Self::tables_for(conn, query)?;
+        // legacy rows predate every partition
+        tables.reverse();
+        let mut count = 0;
+        for table in tables {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM {}{} ORDER BY created_at ASC",
+                COLUMNS,
+                table,
+                filter.sql(&table)
+            ))?;
+            let rows = stmt.query_map(
+                rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+                Self::map_row,
+            )?;
+            for row in rows {
+                visit(row?);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// `event_type` filter matching one severity (unknown names count as info)
+    fn severity_clause(severity: AuditSeverity, params: &mut Vec<Box<dyn rusqlite::ToSql>>) -> String {
+        let (names, negate) = match severity {
+            AuditSeverity::Info => {
+                let mut other = AuditSeverity::Warn.event_names();
+                other.extend(AuditSeverity::Security.event_names());
+                (other, true)
+            }
+            _ => (severity.event_names(), false),
+        };
+        let placeholders = names
+            .into_iter()
+            .map(|name| {
+                params.push(Box::new(name));
+                format!("?{}", params.len())
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("event_type {}IN ({})", if negate { "NOT " } else { "" }, placeholders)
+    }
+
+    /// Delete events past their severity's retention and drop emptied
+    /// partitions from earlier months; returns the number of rows removed
+    pub fn purge_expired(conn: &Connection, retention: &AuditRetention) -> Result<usize, rusqlite::Error> {
+        let now = Utc::now();
+        let mut removed = 0;
+        for table in Self::tables_for(conn, &AuditQuery::default())? {
+            for severity in [AuditSeverity::Info, AuditSeverity::Warn, AuditSeverity::Security] {
+                let days = retention.days(severity);
+                if days == 0 {
+                    continue;
+                }
+                let cutoff = now - chrono::Duration::days(days as i64);
+                let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(cutoff.to_rfc3339())];
+                let clause = Self::severity_clause(severity, &mut params);
+                removed += conn.execute(
+                    &format!("DELETE FROM {} WHERE created_at < ?1 AND {}", table, clause),
+                    rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+                )?;
+            }
+
+            let past_month = partition_month(&table).is_some_and(|m| m < month_key(now));
+            if past_month {
+                let left: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |r| r.get(0))?;
+                if left == 0 {
+                    Self::drop_search_index(conn, &table)?;
+                    conn.execute_batch(&format!("DROP TABLE {}", table))?;
+                    info!("Dropped expired audit partition {}", table);
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Changes whenever [`AuditLogger::query`] with the same filter would
+    /// return something different (events are only appended and purged)
+    pub fn version(db: &Database, query: &AuditQuery) -> Result<String, rusqlite::Error> {
+        let conn = &db.conn();
+        let (filter, params) = Self::where_clause(conn, query)?;
+        let mut parts = Vec::new();
+        for table in Self::tables_for(conn, query)? {
+            let (count, max_id): (i64, Option<i64>) = conn.query_row(
+                &format!("SELECT COUNT(*), MAX(id) FROM {}{}", table, filter.sql(&table)),
+                rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )?;
+            parts.push(format!("{}:{}:{}", table, count, max_id.unwrap_or_default()));
+        }
+        Ok(parts.join(","))
+    }
+
+    /// Total rows across all partitions
+    pub fn count(db: &Database) -> Result<i64, rusqlite::Error> {
+        let conn = &db.conn();
+        let mut total = 0;
+        for table in Self::tables_for(conn, &AuditQuery::default())? {
+            total += conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |r| r.get::<_, i64>(0))?;
+        }
+        Ok(total)
+    }
+
+    fn map_row(row: &rusqlite::Row<'_>) -> Result<AuditLog, rusqlite::Error> {
+        Ok(AuditLog {
+            id: row.get(0)?,
+            event_type: row.get(1)?,
+            user_id: row.get(2)?,
+            email: row.get(3)?,
+            ip_address: row.get(4)?,
+            user_agent: row.get(5)?,
+            metadata: row.get(6)?,
+            success: row.get(7)?,
+            severity: AuditSeverity::of(&row.get::<_, String>(1)?),
+            created_at: {
+                let dt_str: String = row.get(8)?;
+                DateTime::parse_from_rfc3339(&dt_str)
+                    .map(|d| d.with_timezone(&Utc))
+                    .unwrap_or_default()
+            },
+        })
     }
 
     /// Log an audit event to the database
+    #[allow(clippy::too_many_arguments)]
     pub fn log(
         &self,
-        conn: &Connection,
+        db: &Database,
         event_type: AuditEventType,
         user_id: Option<&str>,
         email: Option<&str>,
@@ -106,11 +908,42 @@ impl AuditLogger {
         metadata: Option<&str>,
         success: bool,
     ) {
-        let event_str = event_type.as_str();
+        let result = self.record(
+            &db.conn(),
+            event_type.as_str(),
+            user_id,
+            email,
+            ip_address,
+            user_agent,
+            metadata,
+            success,
+        );
+        if let Err(e) = result {
+            error!("Failed to write audit log to database: {}", e);
+        }
+    }
+
+    /// Persist an event by its stored name (used when replaying from the
+    /// outbox). Security alerts go out only once the row is written, so a
+    /// retried write does not alert twice.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        conn: &Connection,
+        event_str: &str,
+        user_id: Option<&str>,
+        email: Option<&str>,
+        ip_address: Option<&str>,
+        user_agent: Option<&str>,
+        metadata: Option<&str>,
+        success: bool,
+    ) -> Result<(), rusqlite::Error> {
+        let severity = AuditSeverity::of(event_str);
 
         // Log to structured logs
         info!(
             event = event_str,
+            severity = severity.as_str(),
             user_id = user_id,
             email = email,
             ip_address = ip_address,
@@ -118,98 +951,69 @@ impl AuditLogger {
             "Audit event"
         );
 
+        // metadata is always stored as JSON so `meta.*` filters can reach it
+        let stored_metadata = metadata.map(|m| match serde_json::from_str::<serde_json::Value>(m) {
+            Ok(_) => m.to_string(),
+            Err(_) => serde_json::Value::String(m.to_string()).to_string(),
+        });
+
         // Also persist to database
-        let result = conn.execute(
-            "INSERT INTO audit_logs (event_type, user_id, email, ip_address, user_agent, metadata, success, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        let table = self.write_table(conn);
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (event_type, user_id, email, ip_address, user_agent, metadata, success, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                table
+            ),
             rusqlite::params![
                 event_str,
                 user_id,
                 email,
                 ip_address,
                 user_agent,
-                metadata,
+                stored_metadata,
                 success,
                 Utc::now().to_rfc3339()
             ],
-        );
+        )?;
 
-        if let Err(e) = result {
-            error!("Failed to write audit log to database: {}", e);
+        if severity == AuditSeverity::Security {
+            if let Some(alerts) = &self.alerts {
+                let _ = alerts.send(AuditAlert {
+                    event_type: event_str.to_string(),
+                    user_id: user_id.map(str::to_string),
+                    email: email.map(str::to_string),
+                    ip_address: ip_address.map(str::to_string),
+                    metadata: metadata.map(str::to_string),
+                    created_at: Utc::now(),
+                });
+            }
         }
+        Ok(())
     }
 
     /// Get recent audit logs for a user
     pub fn get_user_logs(
         &self,
-        conn: &Connection,
+        db: &Database,
         user_id: &str,
         limit: i32,
     ) -> Result<Vec<AuditLog>, rusqlite::Error> {
-        let mut stmt = conn.prepare(
-            "SELECT id, event_type, user_id, email, ip_address, user_agent, metadata, success, created_at
-             FROM audit_logs
-             WHERE user_id = ?1
-             ORDER BY created_at DESC
-             LIMIT ?2",
-        )?;
-
-        let logs = stmt.query_map(rusqlite::params![user_id, limit], |row| {
-            Ok(AuditLog {
-                id: row.get(0)?,
-                event_type: row.get(1)?,
-                user_id: row.get(2)?,
-                email: row.get(3)?,
-                ip_address: row.get(4)?,
-                user_agent: row.get(5)?,
-                metadata: row.get(6)?,
-                success: row.get(7)?,
-                created_at: {
-                    let dt_str: String = row.get(8)?;
-                    DateTime::parse_from_rfc3339(&dt_str)
-                        .unwrap()
-                        .with_timezone(&Utc)
-                },
-            })
-        })?;
-
-        logs.collect()
+        let query = AuditQuery {
+            user_id: Some(user_id.to_string()),
+            ..Default::default()
+        };
+        self.query(db, &query, 0, limit)
     }
 
     /// Get all audit logs with pagination
     pub fn get_all_logs(
         &self,
-        conn: &Connection,
+        db: &Database,
         offset: i32,
         limit: i32,
     ) -> Result<Vec<AuditLog>, rusqlite::Error> {
-        let mut stmt = conn.prepare(
-            "SELECT id, event_type, user_id, email, ip_address, user_agent, metadata, success, created_at
-             FROM audit_logs
-             ORDER BY created_at DESC
-             LIMIT ?1 OFFSET ?2",
-        )?;
-
-        let logs = stmt.query_map(rusqlite::params![limit, offset], |row| {
-            Ok(AuditLog {
-                id: row.get(0)?,
-                event_type: row.get(1)?,
-                user_id: row.get(2)?,
-                email: row.get(3)?,
-                ip_address: row.get(4)?,
-                user_agent: row.get(5)?,
-                metadata: row.get(6)?,
-                success: row.get(7)?,
-                created_at: {
-                    let dt_str: String = row.get(8)?;
-                    DateTime::parse_from_rfc3339(&dt_str)
-                        .unwrap()
-                        .with_timezone(&Utc)
-                },
-            })
-        })?;
-
-        logs.collect()
+        self.query(db, &AuditQuery::default(), offset, limit)
     }
 }
 
@@ -218,3 +1022,43 @@ impl Default for AuditLogger {
         Self::new()
     }
 }
+
+/// Apply per-severity retention every `every` in the background, on the
+/// elected replica only
+pub fn spawn_retention(db: Arc<Database>, leader: Arc<LeaderElection>, retention: AuditRetention, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("audit_retention", every) {
+                continue;
+            }
+            match AuditLogger::purge_expired(&db.conn(), &retention) {
+                Ok(0) => {}
+                Ok(n) => info!("Audit retention removed {} events", n),
+                Err(e) => warn!("Audit retention failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Deliver security alerts from [`AuditLogger::with_alerts`] as `security_alert` webhooks
+pub fn spawn_alert_forwarder(mut alerts: UnboundedReceiver<AuditAlert>, webhook: Arc<WebhookSender>) {
+    tokio::spawn(async move {
+        while let Some(alert) = alerts.recv().await {
+            webhook
+                .send(WebhookPayload {
+                    event: WebhookEventType::SecurityAlert,
+                    user_id: alert.user_id.unwrap_or_default(),
+                    email: alert.email,
+                    timestamp: alert.created_at.to_rfc3339(),
+                    metadata: Some(serde_json::json!({
+                        "audit_event": alert.event_type,
+                        "ip_address": alert.ip_address,
+                        "metadata": alert.metadata,
+                    })),
+                })
+                .await;
+        }
+    });
+}
@@ -0,0 +1,228 @@
+//! Signed per-request auth context for services behind a gateway.
+//!
+//! A fronting gateway sends each request's access token to
+//! `GET /auth/context` (forward-auth style) and receives the
+//! `X-Auth-Context` header: the internal user id, the scopes of the user's
+//! role, the session's authentication methods (`amr`) and the session id,
+//! HMAC-signed and valid for `ttl_seconds`. The gateway forwards the header
+//! and services check it with [`ContextVerifier`], one HMAC instead of a
+//! JWT verification plus user and session lookups.
+//!
+//! ```ignore
+//! let contexts = Arc::new(ContextVerifier::new(&context_secret));
+//! let app = Router::new().route("/orders", get(list_orders)).with_state(contexts);
+//!
+//! async fn list_orders(VerifiedContext(ctx): VerifiedContext) -> String {
+//!     ctx.uid
+//! }
+//! ```
+
+use crate::{
+    audit::AuditEventType,
+    crypto::{constant_time_eq, hmac_sha256},
+    db::Database,
+    error::{ApiError, ErrorResponse},
+};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+/// `[auth_context]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthContextConfig {
+    /// Serve `GET /auth/context`
+    #[serde(default)]
+    pub enabled: bool,
+    /// Response header carrying the context, forwarded as is by the gateway
+    #[serde(default = "default_header")]
+    pub header: String,
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: i64,
+    /// Signs contexts; services verifying them need the same value.
+    /// Unset = derived from `jwt_secret`
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Scopes granted per user role (`users.role`); other roles get none
+    #[serde(default)]
+    pub scopes: HashMap<String, Vec<String>>,
+}
+
+impl Default for AuthContextConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            header: default_header(),
+            ttl_seconds: default_ttl_seconds(),
+            secret: None,
+            scopes: HashMap::new(),
+        }
+    }
+}
+
+fn default_header() -> String {
+    "X-Auth-Context".to_string()
+}
+
+fn default_ttl_seconds() -> i64 {
+    60
+}
+
+impl AuthContextConfig {
+    /// Key for auth contexts
+    pub fn key(&self, jwt_secret: &str) -> String {
+        match &self.secret {
+            Some(secret) => secret.clone(),
+            None => data_encoding::HEXLOWER.encode(&hmac_sha256(jwt_secret.as_bytes(), b"auth-context")),
+        }
+    }
+
+    /// Scopes of a user with `role`
+    pub fn scopes_for(&self, role: &str) -> Vec<String> {
+        self.scopes.get(role).cloned().unwrap_or_default()
+    }
+}
+
+const CONTEXT_PREFIX: &str = "ac1.";
+
+/// Who is calling, as vouched for by the auth server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthContext {
+    /// Internal user id (not the token's public or pairwise `sub`)
+    pub uid: String,
+    /// Session the access token belongs to
+    pub sid: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Authentication methods of the session's sign-in (RFC 8176 values,
+    /// plus `email` for magic links)
+    #[serde(default)]
+    pub amr: Vec<String>,
+    pub exp: i64,
+}
+
+impl AuthContext {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+/// `amr` recorded for a session started by the login `method`
+pub fn amr_for(method: &AuditEventType) -> &'static [&'static str] {
+    match method {
+        AuditEventType::MagicLinkVerified => &["email"],
+        AuditEventType::TotpVerified => &["otp"],
+        AuditEventType::WebauthnLoginCompleted => &["hwk"],
+        _ => &[],
+    }
+}
+
+pub fn sign(key: &str, context: &AuthContext) -> String {
+    let body = BASE64URL_NOPAD.encode(serde_json::to_string(context).unwrap_or_default().as_bytes());
+    let mac = hmac_sha256(key.as_bytes(), format!("context|{}", body).as_bytes());
+    format!("{}{}.{}", CONTEXT_PREFIX, body, BASE64URL_NOPAD.encode(&mac))
+}
+
+/// Signature and expiry check of a context header value
+pub fn verify(key: &str, value: &str, now: i64) -> Option<AuthContext> {
+    let (body, sig) = value.strip_prefix(CONTEXT_PREFIX)?.split_once('.')?;
+    let mac = hmac_sha256(key.as_bytes(), format!("context|{}", body).as_bytes());
+    if !constant_time_eq(BASE64URL_NOPAD.encode(&mac).as_bytes(), sig.as_bytes()) {
+        return None;
+    }
+    let context: AuthContext = serde_json::from_slice(&BASE64URL_NOPAD.decode(body.as_bytes()).ok()?).ok()?;
+    (context.exp > now).then_some(context)
+}
+
+/// Checks forwarded context headers in a downstream service
+pub struct ContextVerifier {
+    key: String,
+    header: String,
+}
+
+impl ContextVerifier {
+    /// Verifier for contexts signed with `key` (the auth server's
+    /// `[auth_context] secret`) in the default header
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            header: default_header(),
+        }
+    }
+
+    /// Read the context from another header
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.header = header.to_string();
+        self
+    }
+
+    pub fn verify(&self, headers: &HeaderMap) -> Option<AuthContext> {
+        let value = headers.get(self.header.as_str())?.to_str().ok()?;
+        verify(&self.key, value, Database::now_ts())
+    }
+}
+
+/// A verified auth context header. Works in any router whose state provides
+/// an `Arc<ContextVerifier>`.
+pub struct VerifiedContext(pub AuthContext);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for VerifiedContext
+where
+    Arc<ContextVerifier>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Arc::<ContextVerifier>::from_ref(state)
+            .verify(&parts.headers)
+            .map(Self)
+            .ok_or_else(|| ErrorResponse::unauthorized(ApiError::unauthorized("Missing or invalid auth context")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(exp: i64) -> AuthContext {
+        AuthContext {
+            uid: "user-1".into(),
+            sid: "session-1".into(),
+            scopes: vec!["orders:read".into()],
+            amr: vec!["hwk".into()],
+            exp,
+        }
+    }
+
+    #[test]
+    fn signed_contexts_verify_until_expiry() {
+        let signed = sign("context-key", &context(1_000));
+        assert_eq!(verify("context-key", &signed, 999), Some(context(1_000)));
+        assert!(verify("context-key", &signed, 1_000).is_none());
+        assert!(verify("other-key", &signed, 999).is_none());
+
+        // a body swapped under the original signature is rejected
+        let (_, sig) = signed.rsplit_once('.').unwrap();
+        let mut forged = context(1_000);
+        forged.scopes.push("admin".into());
+        let body = sign("context-key", &forged);
+        let (forged_body, _) = body.rsplit_once('.').unwrap();
+        assert!(verify("context-key", &format!("{}.{}", forged_body, sig), 999).is_none());
+    }
+
+    #[test]
+    fn verifier_reads_the_configured_header() {
+        let signed = sign("context-key", &context(Database::now_ts() + 60));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-gateway-context", signed.parse().unwrap());
+        assert!(ContextVerifier::new("context-key").verify(&headers).is_none());
+        let verifier = ContextVerifier::new("context-key").with_header("x-gateway-context");
+        assert!(verifier.verify(&headers).unwrap().has_scope("orders:read"));
+    }
+}
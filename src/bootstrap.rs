@@ -0,0 +1,392 @@
+//! `passwordless-auth init`: bootstrap a production instance in one step.
+//!
+//! Writes a hardened `config.toml` (prod profile, fresh secrets, https-only
+//! URLs, admin API key required) and an ES256 signing key, creates and
+//! migrates the database, and creates the first admin user, a superadmin,
+//! with an admin API key.
+
+use crate::{
+    admin_keys,
+    admin_users::{self, AdminRole, AdminUserError},
+    config::{self, Config, ConfigError},
+    db::{Database, DbError},
+    jwt::KeyRing,
+    key_rotation::{self, KeyRotationError},
+};
+use data_encoding::BASE64URL_NOPAD;
+use rand::RngCore;
+use reqwest::Url;
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum InitError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0} already exists (pass --force to overwrite it)")]
+    Exists(String),
+    #[error("generated configuration is invalid: {0}")]
+    Config(#[from] ConfigError),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("database error: {0}")]
+    Db(#[from] DbError),
+    #[error("database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("admin setup failed: {0}")]
+    Admin(#[from] AdminUserError),
+    #[error("signing key: {0}")]
+    Key(#[from] KeyRotationError),
+}
+
+/// Answers for `init`, from flags or prompts
+#[derive(Debug, Clone)]
+pub struct InitOptions {
+    pub config_path: String,
+    pub database_path: String,
+    /// PEM file for the generated access token signing key; defaults to
+    /// `jwt-signing.pem` next to the config
+    pub signing_key_path: Option<String>,
+    /// `https://` origin users reach the server at, e.g. `https://auth.example.com`
+    pub public_url: Option<String>,
+    pub admin_email: Option<String>,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: String,
+    /// Left empty in the file when unset; supply `SMTP_PASSWORD` at runtime instead
+    pub smtp_password: String,
+    /// Defaults to `no-reply@<public host>`
+    pub email_from: Option<String>,
+    pub rp_name: String,
+    pub force: bool,
+    /// Fail on missing answers instead of prompting
+    pub non_interactive: bool,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        Self {
+            config_path: config::config_path(),
+            database_path: "auth.db".to_string(),
+            signing_key_path: None,
+            public_url: None,
+            admin_email: None,
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_username: String::new(),
+            smtp_password: std::env::var("SMTP_PASSWORD").unwrap_or_default(),
+            email_from: None,
+            rp_name: "Passwordless Auth".to_string(),
+            force: false,
+            non_interactive: false,
+        }
+    }
+}
+
+pub const USAGE: &str = "usage: passwordless-auth init --public-url https://auth.example.com --admin-email admin@example.com \
+--smtp-host smtp.example.com [--smtp-port 587] [--smtp-username USER] [--smtp-password PASS] [--email-from ADDR] \
+[--rp-name NAME] [--config config.toml] [--database auth.db] [--signing-key jwt-signing.pem] [--force] \
+[--non-interactive]";
+
+impl InitOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, InitError> {
+        let mut opts = Self::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| InitError::Usage(format!("{} needs a value\n{}", flag, USAGE)))
+            };
+            match flag.as_str() {
+                "--config" => opts.config_path = value()?,
+                "--database" => opts.database_path = value()?,
+                "--signing-key" => opts.signing_key_path = Some(value()?),
+                "--public-url" => opts.public_url = Some(value()?),
+                "--admin-email" => opts.admin_email = Some(value()?),
+                "--smtp-host" => opts.smtp_host = Some(value()?),
+                "--smtp-port" => {
+                    let port = value()?;
+                    opts.smtp_port = port
+                        .parse()
+                        .map_err(|_| InitError::Usage(format!("invalid --smtp-port {:?}", port)))?;
+                }
+                "--smtp-username" => opts.smtp_username = value()?,
+                "--smtp-password" => opts.smtp_password = value()?,
+                "--email-from" => opts.email_from = Some(value()?),
+                "--rp-name" => opts.rp_name = value()?,
+                "--force" => opts.force = true,
+                "--non-interactive" => opts.non_interactive = true,
+                "--help" | "-h" => return Err(InitError::Usage(USAGE.to_string())),
+                other => return Err(InitError::Usage(format!("unknown option {}\n{}", other, USAGE))),
+            }
+        }
+        Ok(opts)
+    }
+
+    pub fn signing_key_path(&self) -> String {
+        self.signing_key_path.clone().unwrap_or_else(|| {
+            Path::new(&self.config_path)
+                .with_file_name("jwt-signing.pem")
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    /// Ask for required answers that were not given as flags
+    pub fn prompt_missing(&mut self) -> Result<(), InitError> {
+        let interactive = !self.non_interactive && io::stdin().is_terminal();
+        let ask = |field: &mut Option<String>, flag: &str, question: &str| -> Result<(), InitError> {
+            if field.is_some() {
+                return Ok(());
+            }
+            if !interactive {
+                return Err(InitError::Usage(format!("{} is required\n{}", flag, USAGE)));
+            }
+            print!("{}: ", question);
+            io::stdout().flush()?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            let answer = line.trim();
+            if answer.is_empty() {
+                return Err(InitError::Usage(format!("{} is required", flag)));
+            }
+            *field = Some(answer.to_string());
+            Ok(())
+        };
+        ask(&mut self.public_url, "--public-url", "Public URL (https://...)")?;
+        ask(&mut self.admin_email, "--admin-email", "First admin's email")?;
+        ask(&mut self.smtp_host, "--smtp-host", "SMTP host")?;
+        Ok(())
+    }
+}
+
+/// What `init` created
+#[derive(Debug)]
+pub struct InitSummary {
+    pub config_path: String,
+    pub database_path: String,
+    pub signing_key_path: String,
+    pub admin_user_id: String,
+    pub admin_email: String,
+    /// Shown once; only its hash is stored
+    pub admin_api_key: String,
+}
+
+fn random_secret() -> String {
+    let mut bytes = [0u8; 48];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// Render the hardened configuration. Access tokens are signed with the ES256
+/// key `signing_kid` read from [`InitOptions::signing_key_path`]; `jwt_secret`
+/// still signs refresh and other server-internal tokens.
+pub fn render_config(opts: &InitOptions, jwt_secret: &str, signing_kid: &str) -> Result<String, InitError> {
+    let missing = |flag: &str| InitError::Usage(format!("{} is required", flag));
+    let public_url = opts.public_url.as_deref().ok_or_else(|| missing("--public-url"))?;
+    let url = Url::parse(public_url).map_err(|_| InitError::Usage(format!("--public-url {:?} is not a URL", public_url)))?;
+    let host = match url.host_str() {
+        Some(host) if url.scheme() == "https" => host.to_string(),
+        _ => return Err(InitError::Usage(format!("--public-url must be an https:// URL, got {}", public_url))),
+    };
+    let origin = url.origin().ascii_serialization();
+    let base = public_url.trim_end_matches('/');
+    let smtp_host = opts.smtp_host.as_deref().ok_or_else(|| missing("--smtp-host"))?;
+    let email_from = opts.email_from.clone().unwrap_or_else(|| format!("no-reply@{}", host));
+
+    Ok(format!(
+        r#"# Generated by `passwordless-auth init`.
+# Holds secrets unique to this instance: keep it private and out of version control.
+profile = "prod"
+
+jwt_secret = {jwt_secret}
+access_token_expiry_seconds = 900
+refresh_token_expiry_seconds = 604800
+
+magic_link_expiry_seconds = 600
+magic_link_base_url = {magic_link_base_url}
+action_link_base_url = {action_link_base_url}
+
+smtp_host = {smtp_host}
+smtp_port = {smtp_port}
+smtp_username = {smtp_username}
+smtp_password = {smtp_password}  # or leave empty and set SMTP_PASSWORD
+email_from = {email_from}
+email_delivery = "smtp"
+
+webauthn_rp_id = {rp_id}
+webauthn_origin = {origin}
+webauthn_rp_name = {rp_name}
+
+database_path = {database_path}
+
+server_host = "0.0.0.0"
+server_port = 3000
+log_level = "info"
+dev_mode = false
+hsts = true
+cors_allow_all = false
+cors_allowed_origins = [{origin}]
+
+[jwt_signing]
+algorithm = "ES256"
+
+[[jwt_signing.keys]]
+kid = {signing_kid}
+private_key_file = {signing_key_path}
+
+[admin]
+require_api_key = true
+
+# Restrict /admin and /metrics to your network as well:
+# [ip_access]
+# admin_allow = ["10.0.0.0/8"]
+# metrics_allow = ["10.0.0.0/8"]
+"#,
+        jwt_secret = quote(jwt_secret),
+        signing_kid = quote(signing_kid),
+        signing_key_path = quote(&opts.signing_key_path()),
+        magic_link_base_url = quote(&format!("{}/verify/magic", base)),
+        action_link_base_url = quote(&format!("{}/action", base)),
+        smtp_host = quote(smtp_host),
+        smtp_port = opts.smtp_port,
+        smtp_username = quote(&opts.smtp_username),
+        smtp_password = quote(&opts.smtp_password),
+        email_from = quote(&email_from),
+        rp_id = quote(&host),
+        origin = quote(&origin),
+        rp_name = quote(&opts.rp_name),
+        database_path = quote(&opts.database_path),
+    ))
+}
+
+/// Write a file readable only by its owner, refusing to replace one unless `force`
+fn write_private(path: &str, contents: &str, force: bool) -> Result<(), InitError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => InitError::Exists(path.to_string()),
+        _ => InitError::Io(e),
+    })?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// Write the config and signing key, migrate the database and create the
+/// first admin
+pub fn run(opts: &InitOptions) -> Result<InitSummary, InitError> {
+    if !opts.force && Path::new(&opts.config_path).exists() {
+        return Err(InitError::Exists(opts.config_path.clone()));
+    }
+    let admin_email = opts
+        .admin_email
+        .clone()
+        .ok_or_else(|| InitError::Usage("--admin-email is required".to_string()))?;
+    let rendered = render_config(opts, &random_secret(), &key_rotation::random_kid())?;
+    // the server would refuse anything the prod profile rejects, so check before writing
+    let cfg = Config::from_layers(&rendered, None, None)?;
+    cfg.check_profile()?;
+
+    let signing_key_path = opts.signing_key_path();
+    write_private(&signing_key_path, &key_rotation::generate_pem()?, opts.force)?;
+    KeyRing::load(&cfg).map_err(KeyRotationError::from)?;
+
+    let db = Database::open(&cfg.database_path)?;
+    db.apply_migrations()?;
+    let admin_user_id = db.get_or_create_user(&admin_email)?;
+    db.users().set_role(&admin_user_id, "admin")?;
+    admin_users::set(&db, &admin_user_id, AdminRole::Superadmin)?;
+    let key = admin_keys::create(&db, "init", Some(&admin_user_id), AdminRole::Superadmin)?;
+
+    write_private(&opts.config_path, &rendered, opts.force)?;
+    Ok(InitSummary {
+        config_path: opts.config_path.clone(),
+        database_path: cfg.database_path,
+        signing_key_path,
+        admin_user_id,
+        admin_email,
+        admin_api_key: key.secret,
+    })
+}
+
+/// CLI entry point; returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    let result = InitOptions::from_args(args).and_then(|mut opts| {
+        opts.prompt_missing()?;
+        run(&opts)
+    });
+    match result {
+        Ok(summary) => {
+            println!("Wrote {} (prod profile, fresh JWT secret)", summary.config_path);
+            println!("Wrote ES256 signing key {}", summary.signing_key_path);
+            println!("Migrated database {}", summary.database_path);
+            println!("Admin user {} ({})", summary.admin_email, summary.admin_user_id);
+            println!();
+            println!("Admin API key (shown once, store it now):");
+            println!("  {}", summary.admin_api_key);
+            println!();
+            println!("Send it as `Authorization: Bearer <key>` to /admin/*. Start the server with `passwordless-auth`.");
+            0
+        }
+        Err(e) => {
+            eprintln!("init failed: {}", e);
+            match e {
+                InitError::Usage(_) => 2,
+                _ => 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwt::SigningAlgorithm;
+
+    #[test]
+    fn renders_a_config_the_prod_profile_accepts() {
+        let opts = InitOptions {
+            public_url: Some("https://auth.example.com/".to_string()),
+            admin_email: Some("admin@example.com".to_string()),
+            smtp_host: Some("smtp.example.com".to_string()),
+            smtp_password: "p\"w".to_string(),
+            ..Default::default()
+        };
+        let rendered = render_config(&opts, &random_secret(), "init-key").unwrap();
+        let cfg = Config::from_layers(&rendered, None, None).unwrap();
+        cfg.check_profile().unwrap();
+        assert!(cfg.dev_settings().is_empty());
+        assert_eq!(cfg.webauthn_rp_id, "auth.example.com");
+        assert_eq!(cfg.magic_link_base_url, "https://auth.example.com/verify/magic");
+        assert_eq!(cfg.smtp_password, "p\"w");
+        assert!(cfg.admin.require_api_key && cfg.jwt_secret.len() >= 64);
+        assert_eq!(cfg.jwt_signing.algorithm, SigningAlgorithm::Es256);
+        assert_eq!(cfg.jwt_signing.keys[0].kid, "init-key");
+        assert_eq!(cfg.jwt_signing.keys[0].private_key_file, "jwt-signing.pem");
+
+        let http = InitOptions {
+            public_url: Some("http://auth.example.com".to_string()),
+            ..opts
+        };
+        assert!(matches!(render_config(&http, "x", "k"), Err(InitError::Usage(_))));
+    }
+}
@@ -0,0 +1,491 @@
+//! Admin announcements emailed to a segment of users, such as a security
+//! advisory asking everyone without a passkey to add one.
+//!
+//! Creating a broadcast fixes its recipients: every user matching the
+//! [`Segment`] at that moment. The elected replica then moves at most
+//! `[broadcasts] per_minute` recipients a minute into the email queue, and
+//! none while the queue already holds `max_queue_backlog` unsent emails, so
+//! a large broadcast never crowds out sign-in mail. `{{email}}` in the
+//! subject and body is replaced per recipient. Users who turned the
+//! broadcast's notification category off and suppressed addresses are
+//! skipped. Canceling stops queueing and withdraws the queued emails the
+//! worker has not picked up yet.
+
+use crate::{
+    db::Database,
+    email_queue::{EmailQueue, QueueError},
+    email_templates::{EmailTemplates, BROADCAST_EMAIL_PLACEHOLDER},
+    leader::LeaderElection,
+    metrics::MetricsRecorder,
+    notifications::{self, Category},
+};
+use rusqlite::{params, params_from_iter, types::Value, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// `[broadcasts]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct BroadcastConfig {
+    /// Off stops queueing; broadcasts can still be created and resume when turned back on
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Recipients moved into the email queue per minute, across all broadcasts
+    #[serde(default = "default_per_minute")]
+    pub per_minute: u32,
+    /// Nothing is queued while the email queue holds this many unsent emails
+    #[serde(default = "default_max_queue_backlog")]
+    pub max_queue_backlog: i64,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            per_minute: default_per_minute(),
+            max_queue_backlog: default_max_queue_backlog(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_per_minute() -> u32 {
+    60
+}
+
+fn default_max_queue_backlog() -> i64 {
+    500
+}
+
+fn default_interval_seconds() -> u64 {
+    10
+}
+
+impl BroadcastConfig {
+    /// Recipients to queue per run
+    fn batch_size(&self) -> i64 {
+        let per_run = u64::from(self.per_minute) * self.interval_seconds.max(1) / 60;
+        per_run.max(1) as i64
+    }
+}
+
+/// Which users receive a broadcast; unset filters match everyone
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    /// `false` selects users without a passkey
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_passkey: Option<bool>,
+    /// `false` selects users without an authenticator app
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_totp: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Users created before this time (unix seconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<i64>,
+    /// Frozen accounts are left out unless set
+    #[serde(default)]
+    pub include_frozen: bool,
+}
+
+impl Segment {
+    /// `WHERE` clause over `users u` and its parameters
+    fn filter(&self) -> (String, Vec<Value>) {
+        let mut clauses = Vec::new();
+        let mut values = Vec::new();
+        if let Some(has) = self.has_passkey {
+            let exists = "EXISTS (SELECT 1 FROM webauthn_registrations w WHERE w.user_id = u.id)";
+            clauses.push(if has { exists.to_string() } else { format!("NOT {}", exists) });
+        }
+        if let Some(has) = self.has_totp {
+            clauses.push(if has { "u.totp_secret IS NOT NULL" } else { "u.totp_secret IS NULL" }.to_string());
+        }
+        if let Some(role) = &self.role {
+            values.push(Value::Text(role.clone()));
+            clauses.push(format!("u.role = ?{}", values.len()));
+        }
+        if let Some(before) = self.created_before {
+            values.push(Value::Integer(before));
+            clauses.push(format!("u.created_at < ?{}", values.len()));
+        }
+        if !self.include_frozen {
+            clauses.push("u.frozen_at IS NULL".to_string());
+        }
+        if clauses.is_empty() {
+            clauses.push("1".to_string());
+        }
+        (clauses.join(" AND "), values)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BroadcastError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("{0} messages cannot be broadcast")]
+    Category(&'static str),
+    #[error("invalid template: {0}")]
+    Template(String),
+    #[error("broadcast already {0}")]
+    Finished(String),
+}
+
+/// Where the recipients of a broadcast are
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub total: i64,
+    /// Not yet moved into the email queue
+    pub pending: i64,
+    /// In the email queue, not yet sent
+    pub queued: i64,
+    pub sent: i64,
+    /// Dead-lettered by the email worker
+    pub failed: i64,
+    /// Opted out of the category, or suppressed
+    pub skipped: i64,
+    pub canceled: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Broadcast {
+    pub id: String,
+    pub subject: String,
+    pub body: String,
+    pub category: Category,
+    pub segment: Segment,
+    /// `queued`, `sending`, `completed` or `canceled`
+    pub status: String,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub progress: Progress,
+}
+
+/// `POST /admin/broadcasts` body
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewBroadcast {
+    pub subject: String,
+    pub body: String,
+    /// Recipients who turned this category off are skipped
+    #[serde(default = "default_category")]
+    pub category: Category,
+    #[serde(default)]
+    pub segment: Segment,
+}
+
+fn default_category() -> Category {
+    Category::Security
+}
+
+const ACTIVE: &str = "('queued', 'sending')";
+
+/// Placeholders in `text` other than `{{email}}`
+fn unknown_placeholders(text: &str) -> Option<String> {
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}").map_or(rest.len(), |e| start + e + 2);
+        let placeholder = &rest[start..end];
+        if placeholder != BROADCAST_EMAIL_PLACEHOLDER {
+            return Some(placeholder.to_string());
+        }
+        rest = &rest[end..];
+    }
+    None
+}
+
+fn validate(new: &NewBroadcast) -> Result<(), BroadcastError> {
+    if matches!(new.category, Category::SignIn | Category::NewSignIn) {
+        return Err(BroadcastError::Category(new.category.as_str()));
+    }
+    if new.subject.trim().is_empty() || new.body.trim().is_empty() {
+        return Err(BroadcastError::Template("subject and body are required".to_string()));
+    }
+    if new.subject.contains(['\r', '\n']) {
+        return Err(BroadcastError::Template("subject must be one line".to_string()));
+    }
+    if let Some(placeholder) = unknown_placeholders(&new.subject).or_else(|| unknown_placeholders(&new.body)) {
+        return Err(BroadcastError::Template(format!(
+            "unknown placeholder {} (only {} is supported)",
+            placeholder, BROADCAST_EMAIL_PLACEHOLDER
+        )));
+    }
+    Ok(())
+}
+
+/// Users the segment matches right now
+pub fn count(db: &Database, segment: &Segment) -> Result<i64, rusqlite::Error> {
+    let (filter, values) = segment.filter();
+    db.conn().query_row(
+        &format!("SELECT COUNT(*) FROM users u WHERE {}", filter),
+        params_from_iter(values),
+        |r| r.get(0),
+    )
+}
+
+/// Create a broadcast and fix its recipients
+pub fn create(db: &Database, new: &NewBroadcast, now: i64) -> Result<Broadcast, BroadcastError> {
+    validate(new)?;
+    let id = Uuid::new_v4().to_string();
+    let segment = serde_json::to_string(&new.segment).expect("segment serializes");
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO broadcasts (id, subject, body, category, segment, status, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, 'queued', ?6)",
+        params![id, new.subject, new.body, new.category.as_str(), segment, now],
+    )?;
+    let (filter, mut values) = new.segment.filter();
+    values.push(Value::Text(id.clone()));
+    tx.execute(
+        &format!(
+            "INSERT INTO broadcast_recipients (broadcast_id, user_id) SELECT ?{}, u.id FROM users u WHERE {}",
+            values.len(),
+            filter
+        ),
+        params_from_iter(values),
+    )?;
+    tx.commit()?;
+    Ok(get(db, &id)?.expect("broadcast just created"))
+}
+
+fn progress(db: &Database, id: &str) -> Result<Progress, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT r.status, q.status, COUNT(*) FROM broadcast_recipients r
+         LEFT JOIN email_queue q ON q.id = r.email_id
+         WHERE r.broadcast_id = ?1 GROUP BY r.status, q.status",
+    )?;
+    let rows = stmt.query_map(params![id], |r| {
+        Ok((r.get::<_, String>(0)?, r.get::<_, Option<String>>(1)?, r.get::<_, i64>(2)?))
+    })?;
+    let mut progress = Progress::default();
+    for row in rows {
+        let (recipient, email, n) = row?;
+        progress.total += n;
+        let bucket = match (recipient.as_str(), email.as_deref()) {
+            ("pending", _) => &mut progress.pending,
+            ("skipped", _) => &mut progress.skipped,
+            ("canceled", _) => &mut progress.canceled,
+            (_, Some("sent")) => &mut progress.sent,
+            (_, Some("dead")) => &mut progress.failed,
+            _ => &mut progress.queued,
+        };
+        *bucket += n;
+    }
+    Ok(progress)
+}
+
+fn from_row(r: &Row) -> rusqlite::Result<Broadcast> {
+    let category: String = r.get(3)?;
+    let segment: String = r.get(4)?;
+    Ok(Broadcast {
+        id: r.get(0)?,
+        subject: r.get(1)?,
+        body: r.get(2)?,
+        category: Category::parse(&category).unwrap_or(Category::Security),
+        segment: serde_json::from_str(&segment).unwrap_or_default(),
+        status: r.get(5)?,
+        created_at: r.get(6)?,
+        started_at: r.get(7)?,
+        finished_at: r.get(8)?,
+        progress: Progress::default(),
+    })
+}
+
+const COLUMNS: &str = "id, subject, body, category, segment, status, created_at, started_at, finished_at";
+
+pub fn get(db: &Database, id: &str) -> Result<Option<Broadcast>, rusqlite::Error> {
+    let broadcast = db
+        .conn()
+        .query_row(&format!("SELECT {} FROM broadcasts WHERE id = ?1", COLUMNS), params![id], from_row)
+        .optional()?;
+    broadcast
+        .map(|mut b| {
+            b.progress = progress(db, &b.id)?;
+            Ok(b)
+        })
+        .transpose()
+}
+
+/// Newest first
+pub fn list(db: &Database, limit: i64) -> Result<Vec<Broadcast>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM broadcasts ORDER BY created_at DESC, rowid DESC LIMIT ?1",
+        COLUMNS
+    ))?;
+    let broadcasts = stmt.query_map(params![limit], from_row)?.collect::<Result<Vec<_>, _>>()?;
+    broadcasts
+        .into_iter()
+        .map(|mut b| {
+            b.progress = progress(db, &b.id)?;
+            Ok(b)
+        })
+        .collect()
+}
+
+/// Stop a broadcast: pending recipients are canceled and queued emails the
+/// worker has not started on are withdrawn. `None` when there is no such broadcast.
+pub fn cancel(db: &Database, id: &str, now: i64) -> Result<Option<Broadcast>, BroadcastError> {
+    let Some(broadcast) = get(db, id)? else {
+        return Ok(None);
+    };
+    if broadcast.status != "queued" && broadcast.status != "sending" {
+        return Err(BroadcastError::Finished(broadcast.status));
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE broadcast_recipients SET status = 'canceled', updated_at = ?2
+         WHERE broadcast_id = ?1 AND (status = 'pending' OR (status = 'queued' AND email_id IN (
+             SELECT id FROM email_queue WHERE status = 'pending')))",
+        params![id, now],
+    )?;
+    tx.execute(
+        "DELETE FROM email_queue WHERE status = 'pending' AND id IN (
+             SELECT email_id FROM broadcast_recipients WHERE broadcast_id = ?1 AND status = 'canceled')",
+        params![id],
+    )?;
+    tx.execute(
+        "UPDATE broadcasts SET status = 'canceled', finished_at = ?2 WHERE id = ?1",
+        params![id, now],
+    )?;
+    tx.commit()?;
+    Ok(get(db, id)?)
+}
+
+/// Move the next recipients of active broadcasts into the email queue, within
+/// the throttle; returns how many were queued or skipped
+pub fn run_batch(db: &Database, cfg: &BroadcastConfig, now: i64) -> Result<usize, rusqlite::Error> {
+    let backlog: i64 = db.conn().query_row(
+        "SELECT COUNT(*) FROM email_queue WHERE status IN ('pending', 'sending', 'failed')",
+        [],
+        |r| r.get(0),
+    )?;
+    let budget = cfg.batch_size().min(cfg.max_queue_backlog - backlog);
+    let mut processed = 0;
+    if budget > 0 {
+        let conn = db.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT r.broadcast_id, r.user_id, u.email, b.subject, b.body, b.category
+             FROM broadcast_recipients r
+             JOIN broadcasts b ON b.id = r.broadcast_id
+             JOIN users u ON u.id = r.user_id
+             WHERE r.status = 'pending' AND b.status IN {}
+             ORDER BY b.created_at, b.id, r.rowid LIMIT ?1",
+            ACTIVE
+        ))?;
+        let due = stmt
+            .query_map(params![budget], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, String>(2)?,
+                    r.get::<_, String>(3)?,
+                    r.get::<_, String>(4)?,
+                    r.get::<_, String>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut started = HashSet::new();
+        for (broadcast_id, user_id, email, subject, body, category) in due {
+            let category = Category::parse(&category).unwrap_or(Category::Security);
+            // broadcasts are always emailed; an SMS preference still counts as opted in
+            let queued = match notifications::delivery(db, &user_id, category)? {
+                None => Err("opted_out"),
+                Some(_) => {
+                    let rendered = EmailTemplates::broadcast(&subject, &body, &email);
+                    match EmailQueue::enqueue(db, &email, &rendered.subject, &rendered.text, Some(&rendered.html)) {
+                        Ok(email_id) => Ok(email_id),
+                        Err(QueueError::Suppressed) => Err("suppressed"),
+                        Err(QueueError::Db(e)) => return Err(e),
+                    }
+                }
+            };
+            match &queued {
+                Ok(email_id) => db.conn().execute(
+                    "UPDATE broadcast_recipients SET status = 'queued', email_id = ?3, updated_at = ?4
+                     WHERE broadcast_id = ?1 AND user_id = ?2",
+                    params![broadcast_id, user_id, email_id, now],
+                )?,
+                Err(reason) => db.conn().execute(
+                    "UPDATE broadcast_recipients SET status = 'skipped', skip_reason = ?3, updated_at = ?4
+                     WHERE broadcast_id = ?1 AND user_id = ?2",
+                    params![broadcast_id, user_id, reason, now],
+                )?,
+            };
+            MetricsRecorder::record_broadcast_email(match queued {
+                Ok(_) => "queued",
+                Err(reason) => reason,
+            });
+            started.insert(broadcast_id);
+            processed += 1;
+        }
+        for id in started {
+            db.conn().execute(
+                "UPDATE broadcasts SET status = 'sending', started_at = ?2 WHERE id = ?1 AND status = 'queued'",
+                params![id, now],
+            )?;
+        }
+    }
+    let completed = db.conn().execute(
+        &format!(
+            "UPDATE broadcasts SET status = 'completed', started_at = COALESCE(started_at, ?1), finished_at = ?1
+             WHERE status IN {} AND NOT EXISTS (
+                 SELECT 1 FROM broadcast_recipients r WHERE r.broadcast_id = broadcasts.id AND r.status = 'pending')",
+            ACTIVE
+        ),
+        params![now],
+    )?;
+    if completed > 0 {
+        info!("{} broadcast(s) fully queued", completed);
+    }
+    Ok(processed)
+}
+
+/// Queue broadcast emails on the elected replica every `interval_seconds`
+pub fn spawn_sender(db: Arc<Database>, leader: Arc<LeaderElection>, cfg: BroadcastConfig) {
+    if !cfg.enabled {
+        return;
+    }
+    let every = Duration::from_secs(cfg.interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("broadcasts", every) {
+                continue;
+            }
+            if let Err(e) = run_batch(&db, &cfg, Database::now_ts()) {
+                warn!("Failed to queue broadcast emails: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_email_placeholder_is_accepted() {
+        assert_eq!(unknown_placeholders("Hi {{email}}, {{email}}"), None);
+        assert_eq!(unknown_placeholders("Hi {{name}}").as_deref(), Some("{{name}}"));
+        assert_eq!(unknown_placeholders("dangling {{email").as_deref(), Some("{{email"));
+
+        let segment: Segment = serde_json::from_str(r#"{"has_passkey": false, "role": "admin"}"#).unwrap();
+        let (filter, values) = segment.filter();
+        assert!(filter.starts_with("NOT EXISTS"));
+        assert!(filter.ends_with("u.frozen_at IS NULL"));
+        assert_eq!(values, vec![Value::Text("admin".to_string())]);
+    }
+}
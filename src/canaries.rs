@@ -0,0 +1,139 @@
+//! Canary accounts: decoy addresses no real user signs in with.
+//!
+//! Operators register addresses planted where only an attacker would find
+//! them (a honeypot page, a seeded user export) with `POST /admin/canaries`.
+//! A magic link request, TOTP verification or passkey login for one is
+//! answered like an ordinary attempt or failure, so the caller learns
+//! nothing, but raises a security-severity `canary_triggered` audit event,
+//! which `[audit] security_alerts` forwards as a `security_alert` webhook.
+//! Unless the canary was added with `ban_ip = false`, the requesting
+//! address is also banned (`source = "canary"`).
+
+use crate::{address::EmailAddress, db::Database};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Canary key for `email`: the normalized address with its local part
+/// lowercased, so a decoy trips however the caller capitalizes it
+fn normalize(email: &str) -> Option<String> {
+    let address = EmailAddress::parse(email).ok()?;
+    Some(format!("{}@{}", address.local().to_lowercase(), address.domain()))
+}
+
+#[derive(Debug, Error)]
+pub enum CanaryError {
+    #[error("invalid email address")]
+    InvalidAddress,
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+/// `[canaries]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct CanaryConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Length of canary bans; unset uses the escalating `[ip_bans]` length
+    #[serde(default)]
+    pub ban_seconds: Option<i64>,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            ban_seconds: None,
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A registered canary
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Canary {
+    pub email: String,
+    pub note: Option<String>,
+    /// Ban the address of anyone who tries it
+    pub ban_ip: bool,
+    pub created_at: i64,
+    /// Sign-in attempts so far
+    pub triggers: i64,
+    pub last_triggered_at: Option<i64>,
+}
+
+const COLUMNS: &str = "email, note, ban_ip, created_at, triggers, last_triggered_at";
+
+fn row(r: &rusqlite::Row) -> rusqlite::Result<Canary> {
+    Ok(Canary {
+        email: r.get(0)?,
+        note: r.get(1)?,
+        ban_ip: r.get(2)?,
+        created_at: r.get(3)?,
+        triggers: r.get(4)?,
+        last_triggered_at: r.get(5)?,
+    })
+}
+
+/// Register `email` as a canary, or update its note and ban setting
+pub fn add(db: &Database, email: &str, note: Option<&str>, ban_ip: bool) -> Result<Canary, CanaryError> {
+    let email = normalize(email).ok_or(CanaryError::InvalidAddress)?;
+    Ok(db.conn().query_row(
+        &format!(
+            "INSERT INTO canary_accounts (email, note, ban_ip, created_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(email) DO UPDATE SET note = ?2, ban_ip = ?3
+             RETURNING {}",
+            COLUMNS
+        ),
+        params![email, note, ban_ip, Database::now_ts()],
+        row,
+    )?)
+}
+
+pub fn remove(db: &Database, email: &str) -> Result<bool, rusqlite::Error> {
+    let email = normalize(email).unwrap_or_else(|| email.to_string());
+    Ok(db.conn().execute("DELETE FROM canary_accounts WHERE email = ?1", params![email])? > 0)
+}
+
+pub fn find(db: &Database, email: &str) -> Result<Option<Canary>, rusqlite::Error> {
+    let email = normalize(email).unwrap_or_else(|| email.to_string());
+    db.conn()
+        .query_row(
+            &format!("SELECT {} FROM canary_accounts WHERE email = ?1", COLUMNS),
+            params![email],
+            row,
+        )
+        .optional()
+}
+
+/// Canaries, most recently triggered first
+pub fn list(db: &Database) -> Result<Vec<Canary>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM canary_accounts ORDER BY last_triggered_at IS NULL, last_triggered_at DESC, email",
+        COLUMNS
+    ))?;
+    let rows = stmt.query_map([], row)?;
+    rows.collect()
+}
+
+/// Count a sign-in attempt against `email`; `None` unless it is a canary
+pub fn trip(db: &Database, email: &str, now: i64) -> Result<Option<Canary>, rusqlite::Error> {
+    let Some(email) = normalize(email) else {
+        return Ok(None);
+    };
+    db.conn()
+        .query_row(
+            &format!(
+                "UPDATE canary_accounts SET triggers = triggers + 1, last_triggered_at = ?2
+                 WHERE email = ?1 RETURNING {}",
+                COLUMNS
+            ),
+            params![email, now],
+            row,
+        )
+        .optional()
+}
@@ -0,0 +1,303 @@
+//! Storage of pending challenges (WebAuthn ceremonies) behind [`ChallengeStore`].
+//!
+//! [`CachedChallengeStore`] keeps challenges in memory and writes them through
+//! to the `pending_webauthn` table. A ceremony finished on the replica that
+//! started it never reads the database; after a restart, or on another
+//! replica, the row is read and cached. Consuming a challenge deletes its row
+//! and fails when the row is already gone, so an admin invalidation (which
+//! deletes rows) also catches copies cached elsewhere. Expired entries leave
+//! memory every `evict_interval_seconds`, and the elected replica deletes
+//! expired rows. Lookups are counted in `challenge_cache_lookups_total{result}`.
+//! With `store = "redis"`, [`RedisStore`](crate::storage::redis::RedisStore)
+//! replaces both and challenges expire by TTL.
+
+use crate::{db::Database, leader::LeaderElection, metrics::MetricsRecorder, storage::StorageError};
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// `[challenge_cache]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChallengeCacheConfig {
+    /// Off reads every challenge from the database
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Challenges held in memory; beyond that new ones are only written to the database
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    #[serde(default = "default_evict_interval_seconds")]
+    pub evict_interval_seconds: u64,
+}
+
+impl Default for ChallengeCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_entries: default_max_entries(),
+            evict_interval_seconds: default_evict_interval_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_max_entries() -> usize {
+    10_000
+}
+
+fn default_evict_interval_seconds() -> u64 {
+    60
+}
+
+/// What a challenge is for; stored in the `purpose` column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Register,
+    Login,
+}
+
+impl Purpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Register => "register",
+            Self::Login => "login",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "register" => Some(Self::Register),
+            "login" => Some(Self::Login),
+            _ => None,
+        }
+    }
+}
+
+/// A pending challenge with the serialized options it was issued with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub id: String,
+    pub user_id: String,
+    pub purpose: Purpose,
+    pub challenge: Vec<u8>,
+    pub options: Vec<u8>,
+    pub created_at: i64,
+    pub expires_at: i64,
+    /// Application a login ceremony was started for
+    pub client_id: Option<String>,
+}
+
+impl Challenge {
+    pub fn is_expired(&self, now: i64) -> bool {
+        now > self.expires_at
+    }
+}
+
+/// Where pending challenges live between the options and complete calls
+pub trait ChallengeStore: Send + Sync {
+    fn put(&self, db: &Database, challenge: Challenge) -> Result<(), StorageError>;
+
+    /// The challenge `id` issued for `purpose`, expired or not
+    fn get(&self, db: &Database, id: &str, purpose: Purpose) -> Result<Option<Challenge>, StorageError>;
+
+    /// Remove a used challenge; false when it was already gone (used or invalidated)
+    fn consume(&self, db: &Database, id: &str) -> Result<bool, StorageError>;
+
+    /// Expire every pending challenge (failure injection); returns how many were live
+    fn expire_all(&self, db: &Database, now: i64) -> Result<usize, StorageError>;
+
+    /// Drop expired challenges held in memory; returns how many
+    fn evict_expired(&self, _now: i64) -> usize {
+        0
+    }
+
+    /// Challenges held in memory
+    fn cached(&self) -> usize {
+        0
+    }
+}
+
+fn row(r: &rusqlite::Row) -> rusqlite::Result<Challenge> {
+    let purpose: String = r.get(2)?;
+    Ok(Challenge {
+        id: r.get(0)?,
+        user_id: r.get(1)?,
+        purpose: Purpose::parse(&purpose).ok_or(rusqlite::Error::InvalidColumnType(
+            2,
+            "purpose".to_string(),
+            rusqlite::types::Type::Text,
+        ))?,
+        challenge: r.get(3)?,
+        options: r.get(4)?,
+        created_at: r.get(5)?,
+        expires_at: r.get(6)?,
+        client_id: r.get(7)?,
+    })
+}
+
+/// Challenges in the `pending_webauthn` table only
+pub struct DbChallengeStore;
+
+impl ChallengeStore for DbChallengeStore {
+    fn put(&self, db: &Database, challenge: Challenge) -> Result<(), StorageError> {
+        db.conn().execute(
+            "INSERT INTO pending_webauthn
+                 (id, user_id, challenge, purpose, created_at, expires_at, serialized_options, client_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                challenge.id,
+                challenge.user_id,
+                challenge.challenge,
+                challenge.purpose.as_str(),
+                challenge.created_at,
+                challenge.expires_at,
+                challenge.options,
+                challenge.client_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, db: &Database, id: &str, purpose: Purpose) -> Result<Option<Challenge>, StorageError> {
+        Ok(db
+            .conn()
+            .query_row(
+                "SELECT id, user_id, purpose, challenge, serialized_options, created_at, expires_at, client_id
+                 FROM pending_webauthn WHERE id = ?1 AND purpose = ?2",
+                params![id, purpose.as_str()],
+                row,
+            )
+            .optional()?)
+    }
+
+    fn consume(&self, db: &Database, id: &str) -> Result<bool, StorageError> {
+        Ok(db.conn().execute("DELETE FROM pending_webauthn WHERE id = ?1", params![id])? > 0)
+    }
+
+    fn expire_all(&self, db: &Database, now: i64) -> Result<usize, StorageError> {
+        Ok(db.conn().execute(
+            "UPDATE pending_webauthn SET expires_at = ?1 WHERE expires_at > ?1",
+            params![now - 1],
+        )?)
+    }
+}
+
+/// Memory-first store writing through to [`DbChallengeStore`]
+pub struct CachedChallengeStore {
+    entries: Mutex<HashMap<String, Challenge>>,
+    max_entries: usize,
+}
+
+impl CachedChallengeStore {
+    pub fn new(cfg: &ChallengeCacheConfig) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_entries: cfg.max_entries,
+        }
+    }
+
+    fn cache(&self, challenge: Challenge) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            let now = Database::now_ts();
+            entries.retain(|_, c| !c.is_expired(now));
+        }
+        if entries.len() < self.max_entries {
+            entries.insert(challenge.id.clone(), challenge);
+        }
+    }
+}
+
+impl ChallengeStore for CachedChallengeStore {
+    fn put(&self, db: &Database, challenge: Challenge) -> Result<(), StorageError> {
+        DbChallengeStore.put(db, challenge.clone())?;
+        self.cache(challenge);
+        Ok(())
+    }
+
+    fn get(&self, db: &Database, id: &str, purpose: Purpose) -> Result<Option<Challenge>, StorageError> {
+        let cached = self.entries.lock().unwrap().get(id).cloned();
+        if let Some(challenge) = cached {
+            MetricsRecorder::record_challenge_lookup(true);
+            return Ok(Some(challenge).filter(|c| c.purpose == purpose));
+        }
+        MetricsRecorder::record_challenge_lookup(false);
+        let stored = DbChallengeStore.get(db, id, purpose)?;
+        if let Some(challenge) = &stored {
+            self.cache(challenge.clone());
+        }
+        Ok(stored)
+    }
+
+    fn consume(&self, db: &Database, id: &str) -> Result<bool, StorageError> {
+        self.entries.lock().unwrap().remove(id);
+        DbChallengeStore.consume(db, id)
+    }
+
+    fn expire_all(&self, db: &Database, now: i64) -> Result<usize, StorageError> {
+        for challenge in self.entries.lock().unwrap().values_mut() {
+            challenge.expires_at = challenge.expires_at.min(now - 1);
+        }
+        DbChallengeStore.expire_all(db, now)
+    }
+
+    fn evict_expired(&self, now: i64) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, c| !c.is_expired(now));
+        before - entries.len()
+    }
+
+    fn cached(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// The store `[challenge_cache]` asks for
+pub fn from_config(cfg: &ChallengeCacheConfig) -> Arc<dyn ChallengeStore> {
+    if cfg.enabled {
+        Arc::new(CachedChallengeStore::new(cfg))
+    } else {
+        Arc::new(DbChallengeStore)
+    }
+}
+
+/// Delete expired challenges from the database
+pub fn purge_expired(db: &Database, now: i64) -> Result<usize, rusqlite::Error> {
+    db.conn().execute("DELETE FROM pending_webauthn WHERE expires_at < ?1", params![now])
+}
+
+/// Evict expired challenges from memory on every replica, and from the
+/// database on the elected one, every `evict_interval_seconds`
+pub fn spawn_evictor(
+    store: Arc<dyn ChallengeStore>,
+    db: Arc<Database>,
+    leader: Arc<LeaderElection>,
+    cfg: ChallengeCacheConfig,
+) {
+    let every = Duration::from_secs(cfg.evict_interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            let now = Database::now_ts();
+            store.evict_expired(now);
+            MetricsRecorder::record_challenges_cached(store.cached());
+            if !leader.lead("challenge_eviction", every) {
+                continue;
+            }
+            match purge_expired(&db, now) {
+                Ok(0) => {}
+                Ok(n) => info!("Removed {} expired challenges", n),
+                Err(e) => warn!("Failed to remove expired challenges: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,144 @@
+use crate::{
+    error::{ApiError, ErrorResponse},
+    latency::{self, Stage},
+    models::MagicLink,
+    routes::AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use tracing::{error, warn};
+
+/// Failure injection switches, only reachable when `dev_mode = true`
+#[derive(Debug, Default)]
+pub struct ChaosState {
+    fail_email: AtomicBool,
+    db_latency_ms: AtomicU64,
+}
+
+impl ChaosState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether outgoing email should be treated as failed
+    pub fn email_failure(&self) -> bool {
+        self.fail_email.load(Ordering::Relaxed)
+    }
+
+    /// Sleep for the configured artificial storage latency, if any
+    pub async fn inject_db_latency(&self) {
+        let ms = self.db_latency_ms.load(Ordering::Relaxed);
+        if ms > 0 {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            latency::record(Stage::DbLookup, Duration::from_millis(ms));
+        }
+    }
+
+    fn snapshot(&self) -> ChaosSnapshot {
+        ChaosSnapshot {
+            fail_email: self.fail_email.load(Ordering::Relaxed),
+            db_latency_ms: self.db_latency_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.fail_email.store(false, Ordering::Relaxed);
+        self.db_latency_ms.store(0, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize)]
+struct ChaosSnapshot {
+    fail_email: bool,
+    db_latency_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct EmailFailureBody {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct DbLatencyBody {
+    ms: u64,
+}
+
+#[derive(Serialize)]
+struct ExpiredChallenges {
+    magic_links: usize,
+    webauthn_challenges: usize,
+}
+
+async fn get_chaos(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.chaos.snapshot())
+}
+
+async fn reset_chaos(State(state): State<AppState>) -> impl IntoResponse {
+    state.chaos.reset();
+    warn!("Chaos: all failure injections cleared");
+    Json(state.chaos.snapshot())
+}
+
+async fn set_email_failure(
+    State(state): State<AppState>,
+    Json(body): Json<EmailFailureBody>,
+) -> impl IntoResponse {
+    state.chaos.fail_email.store(body.enabled, Ordering::Relaxed);
+    warn!("Chaos: forced email failure = {}", body.enabled);
+    Json(state.chaos.snapshot())
+}
+
+async fn set_db_latency(
+    State(state): State<AppState>,
+    Json(body): Json<DbLatencyBody>,
+) -> impl IntoResponse {
+    state.chaos.db_latency_ms.store(body.ms, Ordering::Relaxed);
+    warn!("Chaos: artificial DB latency = {}ms", body.ms);
+    Json(state.chaos.snapshot())
+}
+
+async fn expire_challenges(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let internal = |e: &dyn std::fmt::Display| {
+        error!("Chaos: failed to expire challenges: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    };
+    let magic_links = MagicLink::expire_all(&state.db).map_err(|e| internal(&e))?;
+    let webauthn_challenges = state
+        .webauthn
+        .expire_challenges(&state.db)
+        .map_err(|e| internal(&e))?;
+    warn!(
+        "Chaos: expired {} magic links and {} WebAuthn challenges",
+        magic_links, webauthn_challenges
+    );
+
+    Ok((
+        StatusCode::OK,
+        Json(ExpiredChallenges {
+            magic_links,
+            webauthn_challenges,
+        }),
+    ))
+}
+
+/// Create the `/dev/chaos` router (mount only when `dev_mode` is enabled)
+pub fn chaos_router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(get_chaos).delete(reset_chaos))
+        .route("/email-failure", post(set_email_failure))
+        .route("/db-latency", post(set_db_latency))
+        .route("/expire-challenges", post(expire_challenges))
+        .with_state(state)
+}
@@ -0,0 +1,409 @@
+//! OAuth 2.0 dynamic client registration (RFC 7591).
+//!
+//! Relying parties register at `POST /oauth/register` instead of waiting for
+//! an operator to add an `[[applications]]` entry. With `[client_registration]
+//! mode = "admin"` the request must carry an admin API key as its bearer
+//! token and the client is approved at once; with `mode = "open"` anyone may
+//! register and the client waits under `/admin/clients` until an operator
+//! approves it. Clients are stored in the `applications` table. Approved ones
+//! act like configured applications: they authenticate with their secret,
+//! redirect to their registered URIs and ask users for consent scopes. They
+//! use the global token lifetimes, transport and geo rules.
+
+use crate::{
+    applications::ApplicationConfig,
+    config::TokenLifetimes,
+    consents::ConsentScope,
+    crypto,
+    db::Database,
+    redirects::RedirectRule,
+    subjects::SubjectType,
+};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use rand::RngCore;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Prefix of registered client ids; configured applications choose their own
+pub const CLIENT_ID_PREFIX: &str = "dyn_";
+
+/// Prefix of generated client secrets
+pub const SECRET_PREFIX: &str = "pcs_";
+
+/// Who may call `POST /oauth/register`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    /// The endpoint answers 404
+    #[default]
+    Disabled,
+    /// Callers present an admin API key (`operator` or above); clients are
+    /// approved at once
+    Admin,
+    /// Anyone; clients stay pending until approved under `/admin/clients`,
+    /// unless the caller presents an admin API key
+    Open,
+}
+
+/// `[client_registration]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClientRegistrationConfig {
+    #[serde(default)]
+    pub mode: RegistrationMode,
+    /// Most redirect URIs one client may register
+    #[serde(default = "default_max_redirect_uris")]
+    pub max_redirect_uris: usize,
+}
+
+impl Default for ClientRegistrationConfig {
+    fn default() -> Self {
+        Self {
+            mode: RegistrationMode::default(),
+            max_redirect_uris: default_max_redirect_uris(),
+        }
+    }
+}
+
+fn default_max_redirect_uris() -> usize {
+    10
+}
+
+#[derive(Debug, Error)]
+pub enum RegistrationError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    /// RFC 7591 `invalid_redirect_uri`
+    #[error("{0}")]
+    RedirectUri(String),
+    /// RFC 7591 `invalid_client_metadata`
+    #[error("{0}")]
+    Metadata(String),
+    #[error("client is already {0}")]
+    Reviewed(String),
+}
+
+/// Client metadata of a registration request (RFC 7591 §2). Other fields,
+/// such as `grant_types`, are accepted and ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ClientMetadata {
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    #[serde(default)]
+    pub client_name: Option<String>,
+    /// `client_secret_basic` (default) or `none` for public clients, which
+    /// get no secret
+    #[serde(default)]
+    pub token_endpoint_auth_method: Option<String>,
+    /// Space-separated consent scopes (`email profile phone`)
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+/// A registered client, as returned to it and listed to admins
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisteredClient {
+    pub client_id: String,
+    pub client_name: String,
+    pub redirect_uris: Vec<String>,
+    pub token_endpoint_auth_method: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub scope: String,
+    /// `pending`, `approved` or `rejected`
+    pub status: String,
+    pub client_id_issued_at: i64,
+    /// Admin API key that registered the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviewed_at: Option<i64>,
+}
+
+/// Response to a registration; the secret is only ever shown here
+#[derive(Debug, Clone, Serialize)]
+pub struct Registration {
+    #[serde(flatten)]
+    pub client: RegisteredClient,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    /// `0`: the secret does not expire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret_expires_at: Option<i64>,
+}
+
+/// Registered redirect URIs are exact `https` URLs, or `http` on a loopback
+/// host for native apps
+fn check_redirect_uri(uri: &str) -> Result<(), RegistrationError> {
+    let invalid = |why: &str| RegistrationError::RedirectUri(format!("{:?}: {}", uri, why));
+    if uri.contains('*') {
+        return Err(invalid("wildcards cannot be registered"));
+    }
+    RedirectRule::parse(uri).map_err(|e| RegistrationError::RedirectUri(e.to_string()))?;
+    let url = reqwest::Url::parse(uri).map_err(|_| invalid("not an absolute URL"))?;
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(invalid("must use https, or http on a loopback host")),
+    }
+}
+
+fn parse_scopes(scope: Option<&str>) -> Result<Vec<ConsentScope>, RegistrationError> {
+    let mut scopes = Vec::new();
+    for name in scope.unwrap_or_default().split_whitespace() {
+        let scope = ConsentScope::parse(name)
+            .ok_or_else(|| RegistrationError::Metadata(format!("unsupported scope {:?}", name)))?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    Ok(scopes)
+}
+
+fn random(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Store a new client. Clients registered by an admin API key (`registered_by`)
+/// are approved at once, others wait for review.
+pub fn register(
+    db: &Database,
+    cfg: &ClientRegistrationConfig,
+    metadata: &ClientMetadata,
+    registered_by: Option<&str>,
+    now: i64,
+) -> Result<Registration, RegistrationError> {
+    if metadata.redirect_uris.len() > cfg.max_redirect_uris {
+        return Err(RegistrationError::RedirectUri(format!(
+            "at most {} redirect URIs can be registered",
+            cfg.max_redirect_uris
+        )));
+    }
+    for uri in &metadata.redirect_uris {
+        check_redirect_uri(uri)?;
+    }
+    let public = match metadata.token_endpoint_auth_method.as_deref() {
+        None | Some("client_secret_basic") => false,
+        Some("none") => true,
+        Some(other) => {
+            return Err(RegistrationError::Metadata(format!(
+                "unsupported token_endpoint_auth_method {:?}",
+                other
+            )))
+        }
+    };
+    let scopes = parse_scopes(metadata.scope.as_deref())?;
+    let client_id = format!("{}{}", CLIENT_ID_PREFIX, HEXLOWER.encode(&random(12)));
+    let client_name = match metadata.client_name.as_deref().map(str::trim) {
+        Some(name) if name.chars().count() > 100 => {
+            return Err(RegistrationError::Metadata("client_name is longer than 100 characters".to_string()))
+        }
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => client_id.clone(),
+    };
+    let secret = (!public).then(|| format!("{}{}", SECRET_PREFIX, BASE64URL_NOPAD.encode(&random(32))));
+    let status = if registered_by.is_some() { "approved" } else { "pending" };
+    db.conn().execute(
+        "INSERT INTO applications
+             (client_id, name, secret_hash, redirect_uris, scopes, status, registered_by, created_at, reviewed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            client_id,
+            client_name,
+            secret.as_deref().map(crypto::token_digest),
+            serde_json::to_string(&metadata.redirect_uris).unwrap_or_default(),
+            serde_json::to_string(&scopes).unwrap_or_default(),
+            status,
+            registered_by,
+            now,
+            registered_by.map(|_| now),
+        ],
+    )?;
+    let client = get(db, &client_id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+    Ok(Registration {
+        client,
+        client_secret_expires_at: secret.is_some().then_some(0),
+        client_secret: secret,
+    })
+}
+
+const COLUMNS: &str =
+    "client_id, name, secret_hash, redirect_uris, scopes, status, registered_by, created_at, reviewed_at";
+
+fn from_row(r: &Row) -> rusqlite::Result<RegisteredClient> {
+    let redirect_uris: String = r.get(3)?;
+    let scopes: String = r.get(4)?;
+    let scopes: Vec<ConsentScope> = serde_json::from_str(&scopes).unwrap_or_default();
+    Ok(RegisteredClient {
+        client_id: r.get(0)?,
+        client_name: r.get(1)?,
+        token_endpoint_auth_method: match r.get::<_, Option<String>>(2)? {
+            Some(_) => "client_secret_basic".to_string(),
+            None => "none".to_string(),
+        },
+        redirect_uris: serde_json::from_str(&redirect_uris).unwrap_or_default(),
+        scope: scopes.iter().map(ConsentScope::as_str).collect::<Vec<_>>().join(" "),
+        status: r.get(5)?,
+        registered_by: r.get(6)?,
+        client_id_issued_at: r.get(7)?,
+        reviewed_at: r.get(8)?,
+    })
+}
+
+pub fn get(db: &Database, client_id: &str) -> Result<Option<RegisteredClient>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            &format!("SELECT {} FROM applications WHERE client_id = ?1", COLUMNS),
+            params![client_id],
+            from_row,
+        )
+        .optional()
+}
+
+/// Registered clients (with `status`, or all), oldest first so the review
+/// queue reads in order
+pub fn list(db: &Database, status: Option<&str>, limit: i64) -> Result<Vec<RegisteredClient>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM applications WHERE ?1 IS NULL OR status = ?1 ORDER BY created_at, rowid LIMIT ?2",
+        COLUMNS
+    ))?;
+    let clients = stmt.query_map(params![status, limit], from_row)?;
+    clients.collect()
+}
+
+/// Approve or reject a client; `None` when there is no such client.
+/// Rejecting an approved client stops it authenticating and redirecting.
+pub fn review(
+    db: &Database,
+    client_id: &str,
+    approve: bool,
+    now: i64,
+) -> Result<Option<RegisteredClient>, RegistrationError> {
+    let Some(client) = get(db, client_id)? else {
+        return Ok(None);
+    };
+    let status = if approve { "approved" } else { "rejected" };
+    if client.status == status {
+        return Err(RegistrationError::Reviewed(client.status));
+    }
+    db.conn().execute(
+        "UPDATE applications SET status = ?1, reviewed_at = ?2 WHERE client_id = ?3",
+        params![status, now, client_id],
+    )?;
+    Ok(get(db, client_id)?)
+}
+
+/// Remove a client; false when there is no such client
+pub fn delete(db: &Database, client_id: &str) -> Result<bool, rusqlite::Error> {
+    Ok(db.conn().execute("DELETE FROM applications WHERE client_id = ?1", params![client_id])? > 0)
+}
+
+fn application_from_row(r: &Row) -> rusqlite::Result<ApplicationConfig> {
+    let client = from_row(r)?;
+    Ok(ApplicationConfig {
+        client_id: client.client_id,
+        name: client.client_name,
+        token_transport: None,
+        // the secret is only stored hashed; see `authenticate`
+        client_secret: None,
+        subject_type: SubjectType::default(),
+        pairwise_salt: None,
+        allowed_redirect_uris: client.redirect_uris,
+        required_profile_fields: Vec::new(),
+        scopes: parse_scopes(Some(&client.scope)).unwrap_or_default(),
+        lifetimes: TokenLifetimes::default(),
+        geo_policy: None,
+        allowed_apis: Vec::new(),
+    })
+}
+
+/// An approved client as an application
+pub fn application(db: &Database, client_id: &str) -> Result<Option<ApplicationConfig>, rusqlite::Error> {
+    if !client_id.starts_with(CLIENT_ID_PREFIX) {
+        return Ok(None);
+    }
+    db.conn()
+        .query_row(
+            &format!("SELECT {} FROM applications WHERE client_id = ?1 AND status = 'approved'", COLUMNS),
+            params![client_id],
+            application_from_row,
+        )
+        .optional()
+}
+
+/// An approved public client (`token_endpoint_auth_method: none`), which
+/// identifies itself by its client id alone
+pub fn public_application(db: &Database, client_id: &str) -> Result<Option<ApplicationConfig>, rusqlite::Error> {
+    if !client_id.starts_with(CLIENT_ID_PREFIX) {
+        return Ok(None);
+    }
+    db.conn()
+        .query_row(
+            &format!(
+                "SELECT {} FROM applications WHERE client_id = ?1 AND status = 'approved' AND secret_hash IS NULL",
+                COLUMNS
+            ),
+            params![client_id],
+            application_from_row,
+        )
+        .optional()
+}
+
+/// Every approved client as an application
+pub fn approved(db: &Database) -> Result<Vec<ApplicationConfig>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM applications WHERE status = 'approved' ORDER BY created_at",
+        COLUMNS
+    ))?;
+    let apps = stmt.query_map([], application_from_row)?;
+    apps.collect()
+}
+
+/// The approved client with these HTTP Basic credentials
+pub fn authenticate(
+    db: &Database,
+    client_id: &str,
+    secret: &str,
+) -> Result<Option<ApplicationConfig>, rusqlite::Error> {
+    let stored: Option<Option<String>> = db
+        .conn()
+        .query_row(
+            "SELECT secret_hash FROM applications WHERE client_id = ?1 AND status = 'approved'",
+            params![client_id],
+            |r| r.get(0),
+        )
+        .optional()?;
+    let Some(Some(hash)) = stored else {
+        return Ok(None);
+    };
+    if !crypto::constant_time_eq(hash.as_bytes(), crypto::token_digest(secret).as_bytes()) {
+        return Ok(None);
+    }
+    application(db, client_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_exact_secure_redirect_uris_register() {
+        assert!(check_redirect_uri("https://app.example.com/callback").is_ok());
+        assert!(check_redirect_uri("http://127.0.0.1:8400/callback").is_ok());
+        assert!(check_redirect_uri("http://app.example.com/callback").is_err());
+        assert!(check_redirect_uri("https://*.example.com/callback").is_err());
+        assert!(check_redirect_uri("javascript:alert(1)").is_err());
+    }
+
+    #[test]
+    fn scopes_are_consent_scopes() {
+        let scopes = parse_scopes(Some("email  profile email")).unwrap();
+        assert_eq!(scopes, [ConsentScope::Email, ConsentScope::Profile]);
+        assert!(matches!(parse_scopes(Some("openid")), Err(RegistrationError::Metadata(_))));
+        assert!(parse_scopes(None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Scheduled SQLite checkpoints and incremental vacuum.
+//!
+//! A long-running deployment otherwise grows without bound: in WAL mode the
+//! `-wal` file only shrinks when a checkpoint gets a quiet moment, and pages
+//! freed by retention jobs stay in the file. Every `interval_seconds` the
+//! elected replica runs `PRAGMA wal_checkpoint(<checkpoint>)` and returns up
+//! to `vacuum_pages` free pages to the filesystem with
+//! `PRAGMA incremental_vacuum`. Every replica exports `sqlite_db_size_bytes`,
+//! `sqlite_wal_size_bytes` and `sqlite_freelist_bytes`; checkpoints that
+//! could not finish because of readers count as busy.
+
+use crate::{
+    db::{Database, DbError},
+    leader::LeaderElection,
+    metrics::MetricsRecorder,
+};
+use serde::Deserialize;
+use std::{
+    fs,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+/// `PRAGMA auto_vacuum` value that allows `incremental_vacuum`
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+/// `[compaction]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct CompactionConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    #[serde(default)]
+    pub checkpoint: CheckpointMode,
+    /// Free pages returned per run; 0 returns all of them
+    #[serde(default = "default_vacuum_pages")]
+    pub vacuum_pages: u32,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            interval_seconds: default_interval_seconds(),
+            checkpoint: CheckpointMode::default(),
+            vacuum_pages: default_vacuum_pages(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval_seconds() -> u64 {
+    3600
+}
+
+fn default_vacuum_pages() -> u32 {
+    1000
+}
+
+/// How hard a checkpoint tries; see the SQLite `wal_checkpoint` docs
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointMode {
+    /// Copy what it can without waiting for readers or writers
+    Passive,
+    /// Wait for writers, then copy everything
+    Full,
+    /// Like `full`, then wait for readers so the next writer restarts the log
+    Restart,
+    /// Like `restart`, and truncate the `-wal` file to zero bytes
+    #[default]
+    Truncate,
+}
+
+impl CheckpointMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Passive => "PASSIVE",
+            Self::Full => "FULL",
+            Self::Restart => "RESTART",
+            Self::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// Size of the database files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub db_bytes: u64,
+    pub wal_bytes: u64,
+    /// Space held by free pages, returned by incremental vacuum
+    pub freelist_bytes: u64,
+}
+
+/// Outcome of `PRAGMA wal_checkpoint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Readers or writers kept the checkpoint from finishing
+    pub busy: bool,
+    /// Frames in the WAL, and how many of them were copied into the database
+    pub wal_frames: i64,
+    pub checkpointed_frames: i64,
+}
+
+fn pragma_i64(db: &Database, pragma: &str) -> Result<i64, DbError> {
+    Ok(db.conn().query_row(&format!("PRAGMA {}", pragma), [], |r| r.get(0))?)
+}
+
+pub fn stats(db: &Database, path: &str) -> Result<Stats, DbError> {
+    let page_size = pragma_i64(db, "page_size")?.max(0) as u64;
+    let pages = pragma_i64(db, "page_count")?.max(0) as u64;
+    let free = pragma_i64(db, "freelist_count")?.max(0) as u64;
+    // in-memory databases and rollback journals have no -wal file
+    let wal_bytes = fs::metadata(format!("{}-wal", path)).map(|m| m.len()).unwrap_or(0);
+    Ok(Stats {
+        db_bytes: pages * page_size,
+        wal_bytes,
+        freelist_bytes: free * page_size,
+    })
+}
+
+/// Ask for incremental auto-vacuum. Takes effect on a database without
+/// tables, so call it before the first migration; returns whether it is on.
+pub fn prepare(db: &Database) -> Result<bool, DbError> {
+    db.conn().pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+    Ok(pragma_i64(db, "auto_vacuum")? == AUTO_VACUUM_INCREMENTAL)
+}
+
+/// Checkpoint the WAL; `None` when the database is not in WAL mode
+pub fn checkpoint(db: &Database, mode: CheckpointMode) -> Result<Option<Checkpoint>, DbError> {
+    let sql = format!("PRAGMA wal_checkpoint({})", mode.as_str());
+    let (busy, wal_frames, checkpointed_frames): (i64, i64, i64) =
+        db.conn().query_row(&sql, [], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+    if wal_frames < 0 {
+        return Ok(None);
+    }
+    Ok(Some(Checkpoint {
+        busy: busy != 0,
+        wal_frames,
+        checkpointed_frames,
+    }))
+}
+
+/// Return up to `pages` free pages (all with 0); the number returned
+pub fn incremental_vacuum(db: &Database, pages: u32) -> Result<i64, DbError> {
+    if pragma_i64(db, "auto_vacuum")? != AUTO_VACUUM_INCREMENTAL {
+        return Ok(0);
+    }
+    let before = pragma_i64(db, "freelist_count")?;
+    let sql = match pages {
+        0 => "PRAGMA incremental_vacuum".to_string(),
+        n => format!("PRAGMA incremental_vacuum({})", n),
+    };
+    // the pragma frees pages as it is stepped, so drain it
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query([])?;
+    while rows.next()?.is_some() {}
+    Ok(before - pragma_i64(db, "freelist_count")?)
+}
+
+fn export(db: &Database, path: &str) {
+    match stats(db, path) {
+        Ok(stats) => MetricsRecorder::record_db_size(stats.db_bytes, stats.wal_bytes, stats.freelist_bytes),
+        Err(e) => warn!("Failed to read database size: {}", e),
+    }
+}
+
+fn compact(db: &Database, cfg: &CompactionConfig) {
+    let started = Instant::now();
+    match checkpoint(db, cfg.checkpoint) {
+        Ok(Some(done)) => {
+            MetricsRecorder::record_checkpoint(done.busy, started.elapsed().as_secs_f64());
+            if done.busy {
+                warn!(
+                    "WAL checkpoint ({}) was blocked: {} of {} frames copied",
+                    cfg.checkpoint.as_str(),
+                    done.checkpointed_frames,
+                    done.wal_frames
+                );
+            } else {
+                debug!("WAL checkpoint copied {} frames", done.checkpointed_frames);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("WAL checkpoint failed: {}", e),
+    }
+    match incremental_vacuum(db, cfg.vacuum_pages) {
+        Ok(0) => {}
+        Ok(n) => {
+            MetricsRecorder::record_vacuumed_pages(n as u64);
+            info!("Incremental vacuum returned {} free pages", n);
+        }
+        Err(e) => warn!("Incremental vacuum failed: {}", e),
+    }
+}
+
+/// Export database sizes every minute and compact on the elected replica
+/// every `interval_seconds`
+pub fn spawn_compactor(db: Arc<Database>, leader: Arc<LeaderElection>, path: String, cfg: CompactionConfig) {
+    if cfg.enabled && pragma_i64(&db, "auto_vacuum").ok() != Some(AUTO_VACUUM_INCREMENTAL) {
+        warn!(
+            "Incremental vacuum is off for this database; enable it once with \
+             `PRAGMA auto_vacuum = INCREMENTAL; VACUUM;` while the server is stopped"
+        );
+    }
+    let every = Duration::from_secs(60);
+    let interval = Duration::from_secs(cfg.interval_seconds.max(60));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        let mut last_run: Option<Instant> = None;
+        loop {
+            ticker.tick().await;
+            export(&db, &path);
+            if !cfg.enabled || last_run.is_some_and(|at| at.elapsed() < interval) {
+                continue;
+            }
+            if !leader.lead("db_compaction", interval) {
+                continue;
+            }
+            last_run = Some(Instant::now());
+            compact(&db, &cfg);
+            export(&db, &path);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vacuum_returns_pages_freed_by_deletes() {
+        let dir = std::env::temp_dir().join(format!("compaction-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.db").to_string_lossy().into_owned();
+        let db = Database::open(&path).unwrap();
+        assert!(prepare(&db).unwrap());
+        let mode: String = db
+            .conn()
+            .pragma_update_and_check(None, "journal_mode", "WAL", |r| r.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+        db.migrate("CREATE TABLE blobs (data BLOB)").unwrap();
+        for _ in 0..64 {
+            db.conn().execute("INSERT INTO blobs VALUES (zeroblob(8192))", []).unwrap();
+        }
+        db.migrate("DELETE FROM blobs").unwrap();
+
+        let before = stats(&db, &path).unwrap();
+        assert!(before.wal_bytes > 0 && before.freelist_bytes > 0);
+        let done = checkpoint(&db, CheckpointMode::Truncate).unwrap().unwrap();
+        assert!(!done.busy);
+        assert_eq!(stats(&db, &path).unwrap().wal_bytes, 0);
+
+        assert_eq!(incremental_vacuum(&db, 10).unwrap(), 10);
+        assert!(incremental_vacuum(&db, 0).unwrap() > 0);
+        assert_eq!(stats(&db, &path).unwrap().freelist_bytes, 0);
+
+        let memory = Database::open(":memory:").unwrap();
+        assert_eq!(checkpoint(&memory, CheckpointMode::Passive).unwrap(), None);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
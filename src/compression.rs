@@ -0,0 +1,143 @@
+use crate::{config::Config, cors::RouteGroup};
+use axum::Router;
+use serde::Deserialize;
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate, SizeAbove},
+    CompressionLayer,
+};
+use tracing::info;
+
+/// `[compression]` table: one policy per route group
+#[derive(Debug, Deserialize, Clone)]
+pub struct CompressionConfig {
+    #[serde(default = "CompressionGroupConfig::on")]
+    pub public: CompressionGroupConfig,
+    #[serde(default = "CompressionGroupConfig::on")]
+    pub admin: CompressionGroupConfig,
+    /// Off by default: some scrapers mishandle compressed `/metrics`
+    #[serde(default = "CompressionGroupConfig::off")]
+    pub metrics: CompressionGroupConfig,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            public: CompressionGroupConfig::on(),
+            admin: CompressionGroupConfig::on(),
+            metrics: CompressionGroupConfig::off(),
+        }
+    }
+}
+
+/// Compression policy for a single route group
+#[derive(Debug, Deserialize, Clone)]
+pub struct CompressionGroupConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Responses with a known size below this are sent uncompressed; token
+    /// responses are a few hundred bytes and gain nothing from compression
+    #[serde(default = "default_min_size_bytes")]
+    pub min_size_bytes: u16,
+}
+
+impl CompressionGroupConfig {
+    fn on() -> Self {
+        Self {
+            enabled: true,
+            min_size_bytes: default_min_size_bytes(),
+        }
+    }
+
+    fn off() -> Self {
+        Self {
+            enabled: false,
+            ..Self::on()
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_min_size_bytes() -> u16 {
+    1024
+}
+
+impl Config {
+    /// Compression policy for a route group
+    pub fn compression_group(&self, group: RouteGroup) -> &CompressionGroupConfig {
+        match group {
+            RouteGroup::Public => &self.compression.public,
+            RouteGroup::Admin => &self.compression.admin,
+            RouteGroup::Metrics => &self.compression.metrics,
+        }
+    }
+}
+
+/// Compress a route group's responses according to its policy
+pub fn apply(router: Router, cfg: &Config, group: RouteGroup) -> Router {
+    let policy = cfg.compression_group(group);
+    if policy.enabled {
+        info!("Compression [{}]: responses over {} bytes", group.as_str(), policy.min_size_bytes);
+    } else {
+        info!("Compression [{}]: Disabled", group.as_str());
+    }
+    with_policy(router, policy)
+}
+
+fn with_policy(router: Router, policy: &CompressionGroupConfig) -> Router {
+    if !policy.enabled {
+        return router;
+    }
+    router.layer(
+        CompressionLayer::new().compress_when(DefaultPredicate::new().and(SizeAbove::new(policy.min_size_bytes))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::no_store;
+    use axum::{
+        body::Body,
+        http::{header, Request},
+        middleware::from_fn,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    fn app(policy: &CompressionGroupConfig) -> Router {
+        let router = Router::new()
+            .route("/token", get(|| async { r#"{"access_token":"x"}"# }))
+            .route("/list", get(|| async { "a".repeat(4096) }))
+            .route("/keys", get(|| async { ([(header::CACHE_CONTROL, "public, max-age=60")], "{}") }))
+            .layer(from_fn(no_store));
+        with_policy(router, policy)
+    }
+
+    async fn get_gzip(app: Router, path: &str) -> axum::response::Response {
+        let request = Request::get(path)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn compresses_only_large_responses_and_marks_them_no_store() {
+        let on = CompressionGroupConfig::on();
+        let small = get_gzip(app(&on), "/token").await;
+        assert!(small.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(small.headers()[header::CACHE_CONTROL], "no-store");
+
+        let large = get_gzip(app(&on), "/list").await;
+        assert_eq!(large.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let keys = get_gzip(app(&on), "/keys").await;
+        assert_eq!(keys.headers()[header::CACHE_CONTROL], "public, max-age=60");
+
+        let off = get_gzip(app(&CompressionGroupConfig::off()), "/list").await;
+        assert!(off.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+}
@@ -1,18 +1,171 @@
+use crate::aaguids::AaguidConfig;
+use crate::admin_keys::AdminAuthConfig;
+use crate::applications::{ApiConfig, ApplicationConfig};
+use crate::audit::AuditConfig;
+use crate::auth_context::AuthContextConfig;
+use crate::broadcasts::BroadcastConfig;
+use crate::canaries::CanaryConfig;
+use crate::challenge_store::ChallengeCacheConfig;
+use crate::passkey_nudge::PasskeyNudgeConfig;
+use crate::compaction::CompactionConfig;
+use crate::compression::CompressionConfig;
+use crate::credential_resets::CredentialResetConfig;
+use crate::cors::CorsConfig;
+use crate::debug_sampling::DebugSamplingConfig;
+use crate::deliverability::DeliverabilityConfig;
+use crate::issuance_hook::IssuanceHookConfig;
+use crate::jwt::JwtSigningConfig;
+use crate::key_rotation::KeyRotationConfig;
+use crate::exemplars::ExemplarConfig;
+use crate::client_registration::ClientRegistrationConfig;
+use crate::device_authorization::DeviceAuthorizationConfig;
+use crate::abuse_reports::AbuseReportConfig;
+use crate::api_version::ApiVersionConfig;
+use crate::session_expiry::SessionExpiryConfig;
+use crate::stale_accounts::StaleAccountConfig;
+use crate::email::SmtpPoolConfig;
+use crate::email_quota::EmailQuotaConfig;
+use crate::geo_policy::{GeoPolicyConfig, GeoRules};
+use crate::ip_access::IpAccessConfig;
+use crate::ip_bans::IpBanConfig;
+use crate::leader::LeaderConfig;
+use crate::notifications::NotificationConfig;
+use crate::key_publication::KeyPublicationConfig;
+use crate::latency::LatencyConfig;
+use crate::link_urls::{self, LinkBaseUrl};
+use crate::load_shed::LoadSheddingConfig;
+use crate::magic_link::MagicLinkIssuanceConfig;
+use crate::maintenance::MaintenanceConfig;
+use crate::regions::SessionsConfig;
+use crate::resilience::DependencyPolicy;
+use crate::revocation::RevocationConfig;
+use crate::secret_scan::{SecretScanConfig, SecretScanner};
+use crate::security_notices::SecurityNoticeConfig;
+use crate::storage::{
+    redis::{RedisStore, RedisStoreConfig},
+    PostgresStoreConfig, StoreBackend,
+};
+use crate::transport::{CookieConfig, TokenTransport};
+use crate::webauthn::WebauthnOptionsConfig;
+use crate::webhook_secrets::WebhookSecretConfig;
 use serde::Deserialize;
-use std::{env, fs, path::Path};
+use std::{collections::HashMap, env, fs, path::Path};
 use thiserror::Error;
 
+/// Deployment tier selected with `APP_PROFILE` (or `profile = "..."`)
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dev => "dev",
+            Self::Staging => "staging",
+            Self::Prod => "prod",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dev" | "development" => Some(Self::Dev),
+            "staging" => Some(Self::Staging),
+            "prod" | "production" => Some(Self::Prod),
+            _ => None,
+        }
+    }
+
+    /// Values used for keys that neither `config.toml` nor the profile
+    /// overlay set
+    fn defaults(&self) -> &'static [(&'static str, ProfileDefault)] {
+        match self {
+            Self::Dev => &[
+                ("cors_allow_all", ProfileDefault::Bool(true)),
+                ("email_delivery", ProfileDefault::Str("log")),
+                ("hsts", ProfileDefault::Bool(false)),
+            ],
+            Self::Staging | Self::Prod => &[
+                ("cors_allow_all", ProfileDefault::Bool(false)),
+                ("email_delivery", ProfileDefault::Str("smtp")),
+                ("hsts", ProfileDefault::Bool(true)),
+            ],
+        }
+    }
+}
+
+enum ProfileDefault {
+    Bool(bool),
+    Str(&'static str),
+}
+
+/// How outgoing email is delivered
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailDelivery {
+    #[default]
+    Smtp,
+    /// Write emails (links included) to the log instead of sending them; for local development
+    Log,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    /// Set from `APP_PROFILE`; `None` keeps the built-in defaults
+    #[serde(default)]
+    pub profile: Option<Profile>,
+
     // JWT Configuration
     pub jwt_secret: String,
     pub access_token_expiry_seconds: i64,
     pub refresh_token_expiry_seconds: i64,
+    /// Clock skew tolerated when checking `exp`, `nbf` and `iat`
+    #[serde(default = "default_jwt_leeway_seconds")]
+    pub jwt_leeway_seconds: u64,
+    /// `iss` claim of access tokens, for resource servers that check it
+    #[serde(default)]
+    pub jwt_issuer: Option<String>,
 
     // Magic Link Configuration
     pub magic_link_expiry_seconds: i64,
+    /// Used while no `[[link_base_urls]]` entry registers a magic link URL
+    #[serde(default)]
     pub magic_link_base_url: String,
 
+    /// Email link base URLs per environment and application (`[[link_base_urls]]`)
+    #[serde(default)]
+    pub link_base_urls: Vec<LinkBaseUrl>,
+
+    /// Resend / replace / cap policy for repeated link requests (`[magic_links]`)
+    #[serde(default)]
+    pub magic_links: MagicLinkIssuanceConfig,
+
+    // Signed Action Links (verify email, revoke session, ...)
+    #[serde(default = "default_action_link_base_url")]
+    pub action_link_base_url: String,
+
+    #[serde(default = "default_action_link_expiry_seconds")]
+    pub action_link_expiry_seconds: i64,
+
+    // TOTP attempt throttling (proof-of-continuity attempt tokens)
+    #[serde(default = "default_totp_max_attempts")]
+    pub totp_max_attempts: u32,
+
+    #[serde(default = "default_totp_attempt_window_seconds")]
+    pub totp_attempt_window_seconds: i64,
+
+    /// Failures in a window before further attempts need the attempt token,
+    /// so one mistyped code does not lock out the user's other devices
+    #[serde(default = "default_totp_attempt_token_after")]
+    pub totp_attempt_token_after: u32,
+
+    /// Maximum tokens accepted by one `/token/verify-batch` request
+    #[serde(default = "default_verify_batch_max_tokens")]
+    pub verify_batch_max_tokens: usize,
+
     // SMTP Configuration
     pub smtp_host: String,
     pub smtp_port: u16,
@@ -20,11 +173,67 @@ pub struct Config {
     pub smtp_password: String,
     pub email_from: String,
 
+    #[serde(default)]
+    pub smtp_pool: SmtpPoolConfig,
+
+    /// `log` prints emails instead of sending them (default in the dev profile)
+    #[serde(default)]
+    pub email_delivery: EmailDelivery,
+
+    /// Send `Strict-Transport-Security` (off by default in the dev profile)
+    #[serde(default = "default_hsts")]
+    pub hsts: bool,
+
+    /// Refuse to start unless every algorithm runs in the FIPS module: the
+    /// FIPS provider (`--features fips`) and no crypto outside it
+    #[serde(default)]
+    pub require_fips: bool,
+
+    /// Caching and pre-publication of signing keys in JWKS/discovery (`[key_publication]`)
+    #[serde(default)]
+    pub key_publication: KeyPublicationConfig,
+
+    /// Passkey upgrade prompts after magic-link logins (`[passkey_nudge]`)
+    #[serde(default)]
+    pub passkey_nudge: PasskeyNudgeConfig,
+
+    /// Emails on new devices/countries and credential changes (`[security_notices]`)
+    #[serde(default)]
+    pub security_notices: SecurityNoticeConfig,
+
+    /// SMS gateway for the notification preference center (`[notifications]`)
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+
+    /// Opt-in recording of redacted failed auth requests (`[debug_sampling]`)
+    #[serde(default)]
+    pub debug_sampling: DebugSamplingConfig,
+
+    /// MX pre-check of magic link recipients (`[deliverability]`)
+    #[serde(default)]
+    pub deliverability: DeliverabilityConfig,
+
+    /// External fraud check before tokens are issued (`[issuance_hook]`)
+    #[serde(default)]
+    pub issuance_hook: IssuanceHookConfig,
+
+    /// Default response version and v1 deprecation headers (`[api_versions]`)
+    #[serde(default)]
+    pub api_versions: ApiVersionConfig,
+
+    /// "This wasn't me" links in magic link emails (`[abuse_reports]`)
+    #[serde(default)]
+    pub abuse_reports: AbuseReportConfig,
+
     // WebAuthn Configuration
     pub webauthn_rp_id: String,
     pub webauthn_origin: String,
     pub webauthn_rp_name: String,
 
+    /// Challenge TTLs, authenticator requirements and algorithms (`[webauthn]`)
+    #[serde(default)]
+    pub webauthn: WebauthnOptionsConfig,
+
     // Database Configuration
     pub database_path: String,
 
@@ -42,6 +251,136 @@ pub struct Config {
     #[serde(default = "default_cors_allow_all")]
     pub cors_allow_all: bool,
 
+    /// Per route group policies (`[cors.public]`, `[cors.admin]`, `[cors.metrics]`)
+    #[serde(default)]
+    pub cors: CorsConfig,
+
+    /// Audit storage layout (`[audit] partitioning = "monthly"`)
+    #[serde(default)]
+    pub audit: AuditConfig,
+
+    /// Cross-instance revocation broadcast (`[revocation] redis_url = ...`)
+    #[serde(default)]
+    pub revocation: RevocationConfig,
+
+    /// Strict vs. eventual (multi-region) session validation (`[sessions] mode = "eventual"`)
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+
+    /// Re-authentication hints and `session_expiring` webhooks (`[session_expiry]`)
+    #[serde(default)]
+    pub session_expiry: SessionExpiryConfig,
+
+    /// Response compression per route group (`[compression.public]`, ...)
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Priority-aware load shedding of auth routes (`[load_shedding] enabled = true`)
+    #[serde(default)]
+    pub load_shedding: LoadSheddingConfig,
+
+    // IP access control for admin/metrics routes
+    #[serde(default)]
+    pub ip_access: IpAccessConfig,
+
+    /// Automatic bans of addresses that keep failing authentication (`[ip_bans]`)
+    #[serde(default)]
+    pub ip_bans: IpBanConfig,
+
+    /// Block or step up logins by client country and ASN (`[geo_policy]`)
+    #[serde(default)]
+    pub geo_policy: GeoPolicyConfig,
+
+    /// Read-only mode for maintenance windows (`[maintenance]`)
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
+    /// Per-stage latency tracing of auth requests (`[latency]`)
+    #[serde(default)]
+    pub latency: LatencyConfig,
+
+    /// Device authorization grant for CLIs and TVs at `/device` (`[device_authorization]`)
+    #[serde(default)]
+    pub device_authorization: DeviceAuthorizationConfig,
+
+    /// Who may register applications at `/oauth/register` (`[client_registration]`)
+    #[serde(default)]
+    pub client_registration: ClientRegistrationConfig,
+
+    /// Trace exemplars on latency histograms for OpenMetrics scrapers (`[exemplars]`)
+    #[serde(default)]
+    pub exemplars: ExemplarConfig,
+
+    /// Generated ES256 signing keys, replaced on a schedule (`[key_rotation]`)
+    #[serde(default)]
+    pub key_rotation: KeyRotationConfig,
+
+    /// RS256/ES256 access token signing keys, published as a JWKS (`[jwt_signing]`)
+    #[serde(default)]
+    pub jwt_signing: JwtSigningConfig,
+
+    /// Default passkey names from authenticator AAGUIDs (`[aaguids]`)
+    #[serde(default)]
+    pub aaguids: AaguidConfig,
+
+    /// Inactivity policy: flag, disable and purge unused accounts (`[stale_accounts]`)
+    #[serde(default)]
+    pub stale_accounts: StaleAccountConfig,
+
+    /// Signed auth context header for gateways (`[auth_context]`)
+    #[serde(default)]
+    pub auth_context: AuthContextConfig,
+
+    /// Pace and rollback window of forced credential resets (`[credential_resets]`)
+    #[serde(default)]
+    pub credential_resets: CredentialResetConfig,
+
+    /// Where magic links, refresh tokens and pending challenges live:
+    /// `sqlite`, `redis` or `postgres` (magic links and refresh tokens only;
+    /// challenges stay in SQLite)
+    #[serde(default)]
+    pub store: StoreBackend,
+
+    /// Redis server for `store = "redis"` (`[redis]`)
+    #[serde(default)]
+    pub redis: RedisStoreConfig,
+
+    /// PostgreSQL database for `store = "postgres"` (`[postgres]`)
+    #[serde(default)]
+    pub postgres: PostgresStoreConfig,
+
+    /// Throttled sending of admin broadcasts through the email queue (`[broadcasts]`)
+    #[serde(default)]
+    pub broadcasts: BroadcastConfig,
+
+    /// In-memory cache of pending WebAuthn challenges (`[challenge_cache]`)
+    #[serde(default)]
+    pub challenge_cache: ChallengeCacheConfig,
+
+    /// Alerts and IP bans for sign-in attempts on decoy accounts (`[canaries]`)
+    #[serde(default)]
+    pub canaries: CanaryConfig,
+
+    /// Per-user sliding-window email budget (`[email_quota]`)
+    #[serde(default)]
+    pub email_quota: EmailQuotaConfig,
+
+    /// Scheduled WAL checkpoints and incremental vacuum (`[compaction]`)
+    #[serde(default)]
+    pub compaction: CompactionConfig,
+
+    /// Fingerprint token-shaped values in logs and error bodies (`[secret_scanning]`)
+    #[serde(default)]
+    pub secret_scanning: SecretScanConfig,
+
+    /// Leader election for singleton background jobs (`[leader]`)
+    #[serde(default)]
+    pub leader: LeaderConfig,
+
+    /// Authentication of admin routes (`[admin]`)
+    #[serde(default)]
+    pub admin: AdminAuthConfig,
+
     // Server Configuration
     #[serde(default = "default_server_host")]
     pub server_host: String,
@@ -56,12 +395,87 @@ pub struct Config {
     #[serde(default)]
     pub webhook_secret: Option<String>,
 
+    /// Grace period and reloading of rotated webhook secrets (`[webhook_secrets]`)
+    #[serde(default)]
+    pub webhook_secrets: WebhookSecretConfig,
+
     // Observability
     #[serde(default = "default_enable_metrics")]
     pub enable_metrics: bool,
 
     #[serde(default = "default_log_level")]
     pub log_level: String,
+
+    /// Enables development-only endpoints such as `/dev/chaos`. Never in production.
+    #[serde(default)]
+    pub dev_mode: bool,
+
+    #[serde(default = "default_dependency_probe_interval_seconds")]
+    pub dependency_probe_interval_seconds: u64,
+
+    /// Circuit breaker / retry policy per outbound dependency (`[resilience.smtp]`)
+    #[serde(default)]
+    pub resilience: HashMap<String, DependencyPolicy>,
+
+    /// How often the outbox dispatcher delivers pending audit/webhook events
+    #[serde(default = "default_outbox_poll_interval_ms")]
+    pub outbox_poll_interval_ms: u64,
+
+    // Token Transport
+    #[serde(default)]
+    pub token_transport: TokenTransport,
+
+    #[serde(default)]
+    pub cookie: CookieConfig,
+
+    // Relying Applications
+    #[serde(default)]
+    pub applications: Vec<ApplicationConfig>,
+
+    /// Resource servers that accept access tokens (`[[apis]]`)
+    #[serde(default)]
+    pub apis: Vec<ApiConfig>,
+
+    /// Token lifetime overrides keyed by user role (`[roles.admin]`, `[roles.kiosk]`)
+    #[serde(default)]
+    pub roles: HashMap<String, TokenLifetimes>,
+}
+
+/// Optional overrides of the global token lifetimes
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TokenLifetimes {
+    #[serde(default)]
+    pub access_token_expiry_seconds: Option<i64>,
+    #[serde(default)]
+    pub refresh_token_expiry_seconds: Option<i64>,
+}
+
+fn default_jwt_leeway_seconds() -> u64 {
+    60
+}
+
+fn default_action_link_base_url() -> String {
+    "http://localhost:3000/action".to_string()
+}
+
+fn default_action_link_expiry_seconds() -> i64 {
+    86400
+}
+
+fn default_verify_batch_max_tokens() -> usize {
+    1000
+}
+
+fn default_totp_max_attempts() -> u32 {
+    5
+}
+
+fn default_totp_attempt_window_seconds() -> i64 {
+    900
+}
+
+fn default_totp_attempt_token_after() -> u32 {
+    3
 }
 
 fn default_rate_limit_per_minute() -> u32 {
@@ -92,6 +506,18 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_dependency_probe_interval_seconds() -> u64 {
+    60
+}
+
+fn default_outbox_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_hsts() -> bool {
+    true
+}
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("failed to read config file: {0}")]
@@ -100,6 +526,31 @@ pub enum ConfigError {
     Toml(#[from] toml::de::Error),
     #[error("environment variable error: {0}")]
     Env(String),
+    #[error("{0}")]
+    Profile(String),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Path of the configuration file: `CONFIG_PATH`, or `config.toml` in the
+/// working directory
+pub fn config_path() -> String {
+    env::var("CONFIG_PATH")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| "config.toml".to_string())
+}
+
+/// Merge `overlay` into `base`: tables merge key by key, anything else is replaced
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(table)) => merge(existing, table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
 }
 
 impl Config {
@@ -107,16 +558,241 @@ impl Config {
         // Load .env file if it exists (optional)
         let _ = dotenvy::dotenv();
 
-        // Load from TOML file
-        let s = fs::read_to_string(path)?;
-        let mut config: Config = toml::from_str(&s)?;
+        let profile = match env::var("APP_PROFILE") {
+            Ok(val) if !val.trim().is_empty() => Some(
+                Profile::parse(&val)
+                    .ok_or_else(|| ConfigError::Env(format!("APP_PROFILE {:?} is not dev, staging or prod", val)))?,
+            ),
+            _ => None,
+        };
+
+        // Load from TOML file, then the profile's overlay next to it (`config.prod.toml`)
+        let path = path.as_ref();
+        let base = fs::read_to_string(path)?;
+        let overlay = match profile {
+            Some(profile) => {
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("config");
+                let overlay = path.with_file_name(format!("{}.{}.toml", stem, profile.as_str()));
+                overlay.exists().then(|| fs::read_to_string(overlay)).transpose()?
+            }
+            None => None,
+        };
+        let mut config = Self::from_layers(&base, overlay.as_deref(), profile)?;
 
         // Override with environment variables if present
         config.override_from_env()?;
 
+        config.check_profile()?;
+        config.webauthn.validate().map_err(ConfigError::Invalid)?;
+        config.ip_access.validate().map_err(ConfigError::Invalid)?;
+        SecretScanner::new(&config.secret_scanning)
+            .map_err(|e| ConfigError::Invalid(format!("[secret_scanning] extra_patterns: {}", e)))?;
+        config.check_link_base_urls()?;
+        config.check_apis()?;
+        config.check_pairwise_salts()?;
+        if config.store == StoreBackend::Redis {
+            RedisStore::new(&config.redis).map_err(|e| ConfigError::Invalid(format!("[redis] {}", e)))?;
+        }
+        if config.store == StoreBackend::Postgres {
+            if !cfg!(feature = "postgres") {
+                return Err(ConfigError::Invalid(crate::storage::POSTGRES_FEATURE_REQUIRED.to_string()));
+            }
+            if config.postgres.url.is_empty() {
+                return Err(ConfigError::Invalid("[postgres] url is required for store = \"postgres\"".to_string()));
+            }
+        }
         Ok(config)
     }
 
+    /// Parse `base` with an optional overlay on top and fill unset keys from
+    /// the profile's defaults. A `profile` key in the files applies when
+    /// `profile` is `None`.
+    pub fn from_layers(base: &str, overlay: Option<&str>, profile: Option<Profile>) -> Result<Self, ConfigError> {
+        let mut table: toml::Table = toml::from_str(base)?;
+        if let Some(overlay) = overlay {
+            merge(&mut table, toml::from_str(overlay)?);
+        }
+        let profile = match profile {
+            Some(profile) => Some(profile),
+            None => match table.get("profile") {
+                Some(toml::Value::String(name)) => Some(
+                    Profile::parse(name).ok_or_else(|| ConfigError::Profile(format!("unknown profile {:?}", name)))?,
+                ),
+                _ => None,
+            },
+        };
+        if let Some(profile) = profile {
+            table.insert("profile".to_string(), toml::Value::String(profile.as_str().to_string()));
+            for (key, value) in profile.defaults() {
+                let value = match value {
+                    ProfileDefault::Bool(b) => toml::Value::Boolean(*b),
+                    ProfileDefault::Str(s) => toml::Value::String(s.to_string()),
+                };
+                table.entry(key.to_string()).or_insert(value);
+            }
+        }
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+
+    /// The prod profile refuses public URLs and browser origins that are not https
+    pub(crate) fn check_profile(&self) -> Result<(), ConfigError> {
+        if self.profile != Some(Profile::Prod) {
+            return Ok(());
+        }
+        let mut urls = vec![
+            ("magic_link_base_url", self.magic_link_base_url.as_str()),
+            ("action_link_base_url", self.action_link_base_url.as_str()),
+            ("webauthn_origin", self.webauthn_origin.as_str()),
+        ];
+        if self.device_authorization.enabled {
+            urls.push(("[device_authorization] verification_uri", self.device_authorization.verification_uri.as_str()));
+        }
+        urls.retain(|(_, url)| !url.is_empty());
+        urls.extend(link_urls::registered(self, Some(Profile::Prod)));
+        let groups = [&self.cors.public, &self.cors.admin, &self.cors.metrics];
+        for origin in self
+            .cors_allowed_origins
+            .iter()
+            .chain(groups.into_iter().flatten().flat_map(|g| g.allowed_origins.iter()))
+        {
+            urls.push(("cors origin", origin.as_str()));
+        }
+        let insecure: Vec<String> = urls
+            .into_iter()
+            .filter(|(_, url)| !url.starts_with("https://"))
+            .map(|(key, url)| format!("{} {}", key, url))
+            .collect();
+        if insecure.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Profile(format!(
+                "the prod profile requires https: {}",
+                insecure.join(", ")
+            )))
+        }
+    }
+
+    /// Every registered link base URL parses, and magic links have one
+    pub(crate) fn check_link_base_urls(&self) -> Result<(), ConfigError> {
+        for entry in &self.link_base_urls {
+            for url in [&entry.magic_link_base_url, &entry.action_link_base_url].into_iter().flatten() {
+                reqwest::Url::parse(url)
+                    .map_err(|_| ConfigError::Invalid(format!("[[link_base_urls]] {:?} is not a URL", url)))?;
+            }
+        }
+        let magic = self.link_base_urls.iter().any(|e| e.magic_link_base_url.is_some());
+        if !magic && self.magic_link_base_url.is_empty() {
+            return Err(ConfigError::Invalid(
+                "set magic_link_base_url or register one under [[link_base_urls]]".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `[[apis]]` audiences are unique and every application's
+    /// `allowed_apis` names one of them
+    pub(crate) fn check_apis(&self) -> Result<(), ConfigError> {
+        for (i, api) in self.apis.iter().enumerate() {
+            if api.audience.is_empty() {
+                return Err(ConfigError::Invalid("[[apis]] audience must not be empty".to_string()));
+            }
+            if self.apis[..i].iter().any(|a| a.audience == api.audience) {
+                return Err(ConfigError::Invalid(format!("[[apis]] audience {:?} is registered twice", api.audience)));
+            }
+        }
+        for app in &self.applications {
+            if let Some(unknown) = app.allowed_apis.iter().find(|a| !self.apis.iter().any(|api| &api.audience == *a)) {
+                return Err(ConfigError::Invalid(format!(
+                    "[[applications]] {:?} allows unregistered API {:?}",
+                    app.client_id, unknown
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// No two applications share a `pairwise_salt`: they would derive the
+    /// same subjects, making users linkable across them
+    pub(crate) fn check_pairwise_salts(&self) -> Result<(), ConfigError> {
+        for (i, app) in self.applications.iter().enumerate() {
+            let Some(salt) = app.pairwise_salt.as_deref() else {
+                continue;
+            };
+            if let Some(other) = self.applications[..i].iter().find(|a| a.pairwise_salt.as_deref() == Some(salt)) {
+                return Err(ConfigError::Invalid(format!(
+                    "[[applications]] {:?} and {:?} share a pairwise_salt",
+                    other.client_id, app.client_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings that are fine for development but suspicious in the prod profile
+    pub fn dev_settings(&self) -> Vec<String> {
+        if self.profile != Some(Profile::Prod) {
+            return Vec::new();
+        }
+        let mut found = Vec::new();
+        if self.dev_mode {
+            found.push("dev_mode = true exposes /dev endpoints".to_string());
+        }
+        let groups = [&self.cors.public, &self.cors.admin, &self.cors.metrics];
+        if self.cors_allow_all || groups.into_iter().flatten().any(|g| g.allow_all) {
+            found.push("CORS allows every origin".to_string());
+        }
+        if self.email_delivery == EmailDelivery::Log {
+            found.push("email_delivery = \"log\" writes sign-in links to the log instead of sending them".to_string());
+        }
+        if !self.hsts {
+            found.push("hsts = false".to_string());
+        }
+        if self.dev_mode && !self.admin.enabled() {
+            found.push("dev_mode = true with [admin] authentication off leaves /admin unauthenticated".to_string());
+        }
+        if matches!(self.log_level.as_str(), "debug" | "trace") {
+            found.push(format!("log_level = {:?} may log personal data", self.log_level));
+        }
+        if self.database_path == ":memory:" {
+            found.push("database_path is in-memory; data is lost on restart".to_string());
+        }
+        found
+    }
+
+    /// Look up a registered application by client id
+    pub fn application(&self, client_id: &str) -> Option<&ApplicationConfig> {
+        self.applications.iter().find(|a| a.client_id == client_id)
+    }
+
+    /// Country/ASN login rules for a calling application: its own
+    /// `geo_policy` table if it has one, else the global rules
+    pub fn geo_rules(&self, client_id: Option<&str>) -> &GeoRules {
+        client_id
+            .and_then(|id| self.application(id))
+            .and_then(|app| app.geo_policy.as_ref())
+            .unwrap_or(&self.geo_policy.rules)
+    }
+
+    /// Resolve `(access, refresh)` lifetimes in seconds for a token issuance.
+    ///
+    /// Role overrides win over application overrides, which win over the
+    /// global `*_token_expiry_seconds` values.
+    pub fn token_lifetimes(&self, client_id: Option<&str>, role: &str) -> (i64, i64) {
+        let mut access = self.access_token_expiry_seconds;
+        let mut refresh = self.refresh_token_expiry_seconds;
+        let app = client_id.and_then(|id| self.application(id)).map(|a| &a.lifetimes);
+        for overrides in [app, self.roles.get(role)].into_iter().flatten() {
+            access = overrides.access_token_expiry_seconds.unwrap_or(access);
+            refresh = overrides.refresh_token_expiry_seconds.unwrap_or(refresh);
+        }
+        (access, refresh)
+    }
+
+    /// Resilience policy for an outbound dependency, defaulting when unset
+    pub fn dependency_policy(&self, dependency: &str) -> DependencyPolicy {
+        self.resilience.get(dependency).cloned().unwrap_or_default()
+    }
+
     /// Override configuration with environment variables
     fn override_from_env(&mut self) -> Result<(), ConfigError> {
         if let Ok(val) = env::var("JWT_SECRET") {
@@ -165,6 +841,9 @@ impl Config {
         if let Ok(val) = env::var("CORS_ALLOWED_ORIGINS") {
             self.cors_allowed_origins = val.split(',').map(|s| s.trim().to_string()).collect();
         }
+        if let Ok(val) = env::var("DEV_MODE") {
+            self.dev_mode = val == "true" || val == "1";
+        }
         if let Ok(val) = env::var("LOG_LEVEL") {
             self.log_level = val;
         }
@@ -172,3 +851,120 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+jwt_secret = "test-secret-that-is-long-enough-1234"
+access_token_expiry_seconds = 900
+refresh_token_expiry_seconds = 604800
+magic_link_expiry_seconds = 600
+magic_link_base_url = "http://localhost/verify/magic"
+smtp_host = "localhost"
+smtp_port = 25
+smtp_username = ""
+smtp_password = ""
+email_from = "no-reply@example.com"
+webauthn_rp_id = "localhost"
+webauthn_origin = "http://localhost"
+webauthn_rp_name = "Test"
+database_path = ":memory:"
+
+[[applications]]
+client_id = "kiosk"
+name = "Kiosk"
+refresh_token_expiry_seconds = 2592000
+
+[roles.admin]
+access_token_expiry_seconds = 300
+"#;
+
+    #[test]
+    fn token_lifetimes_resolve_role_over_application() {
+        let cfg: Config = toml::from_str(BASE).unwrap();
+        assert_eq!(cfg.token_lifetimes(None, "user"), (900, 604800));
+        assert_eq!(cfg.token_lifetimes(Some("kiosk"), "user"), (900, 2592000));
+        assert_eq!(cfg.token_lifetimes(Some("kiosk"), "admin"), (300, 2592000));
+        assert_eq!(cfg.token_lifetimes(Some("unknown"), "admin"), (300, 604800));
+    }
+
+    #[test]
+    fn profiles_overlay_and_fill_defaults() {
+        let plain = Config::from_layers(BASE, None, None).unwrap();
+        assert_eq!(plain.profile, None);
+        assert_eq!(plain.email_delivery, EmailDelivery::Smtp);
+        assert!(plain.hsts && !plain.cors_allow_all);
+
+        let dev = Config::from_layers(BASE, None, Some(Profile::Dev)).unwrap();
+        assert_eq!(dev.email_delivery, EmailDelivery::Log);
+        assert!(!dev.hsts && dev.cors_allow_all);
+
+        // the overlay wins over the base and over profile defaults; tables merge
+        let overlay = r#"
+cors_allow_all = true
+magic_link_base_url = "https://auth.example.com/verify/magic"
+
+[roles.admin]
+refresh_token_expiry_seconds = 3600
+"#;
+        let prod = Config::from_layers(BASE, Some(overlay), Some(Profile::Prod)).unwrap();
+        assert!(prod.cors_allow_all && prod.hsts);
+        assert_eq!(prod.magic_link_base_url, "https://auth.example.com/verify/magic");
+        assert_eq!(prod.token_lifetimes(None, "admin"), (300, 3600));
+        assert!(prod.dev_settings().iter().any(|s| s.contains("CORS")));
+        // http://localhost URLs are refused in prod
+        assert!(matches!(prod.check_profile(), Err(ConfigError::Profile(_))));
+    }
+
+    #[test]
+    fn applications_may_only_allow_registered_apis() {
+        let with_apis = |allowed: &str| {
+            let extra = r#"
+[[apis]]
+audience = "billing"
+
+[[apis]]
+audience = "reports"
+
+[[applications]]
+client_id = "web"
+name = "Web"
+"#;
+            toml::from_str::<Config>(&format!("{}{}allowed_apis = {}\n", BASE, extra, allowed)).unwrap()
+        };
+        let cfg = with_apis(r#"["billing", "reports"]"#);
+        assert!(cfg.check_apis().is_ok());
+        assert_eq!(cfg.application("web").unwrap().audiences(), ["billing", "reports"]);
+        // applications without allowed APIs keep their client id as audience
+        assert_eq!(cfg.application("kiosk").unwrap().audiences(), ["kiosk"]);
+        // a token minted for the web app's APIs was not issued to the kiosk
+        let aud = vec!["reports".to_string()];
+        assert!(cfg.application("web").unwrap().issued(&aud));
+        assert!(!cfg.application("kiosk").unwrap().issued(&aud));
+
+        assert!(matches!(with_apis(r#"["admin"]"#).check_apis(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn applications_may_not_share_a_pairwise_salt() {
+        let with_salts = |second: &str| {
+            let extra = r#"
+[[applications]]
+client_id = "web"
+name = "Web"
+subject_type = "pairwise"
+pairwise_salt = "salt-a"
+
+[[applications]]
+client_id = "mobile"
+name = "Mobile"
+subject_type = "pairwise"
+"#;
+            toml::from_str::<Config>(&format!("{}{}pairwise_salt = {:?}\n", BASE, extra, second)).unwrap()
+        };
+        assert!(with_salts("salt-b").check_pairwise_salts().is_ok());
+        assert!(matches!(with_salts("salt-a").check_pairwise_salts(), Err(ConfigError::Invalid(_))));
+    }
+}
@@ -0,0 +1,199 @@
+//! User-approved access of applications to profile data.
+//!
+//! An application lists the [`ConsentScope`]s it asks for in its
+//! `[[applications]]` entry. Users grant and revoke them under
+//! `/me/consents`, and [`userinfo`] builds the claims an application
+//! receives about a user from the scopes it was granted only. An ID token
+//! must take its claims from there as well.
+
+use crate::{applications::ApplicationConfig, db::Database, subjects};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Claims of the other scopes; profile fields with these names are left out
+/// so that the `profile` scope cannot stand in for them
+const RESERVED_CLAIMS: [&str; 4] = ["sub", "email", "email_verified", "phone_number"];
+
+/// Group of profile claims a user can share with an application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentScope {
+    /// `email` and `email_verified`
+    Email,
+    /// Fields collected with progressive profiling
+    Profile,
+    /// `phone_number`, the user's SMS number
+    Phone,
+}
+
+impl ConsentScope {
+    pub const ALL: [ConsentScope; 3] = [Self::Email, Self::Profile, Self::Phone];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Profile => "profile",
+            Self::Phone => "phone",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == s)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConsentError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("application does not request the {0:?} scope")]
+    NotRequested(&'static str),
+}
+
+/// Scopes a user granted one application
+#[derive(Debug, Clone, Serialize)]
+pub struct Consent {
+    pub client_id: String,
+    /// The application's `name`; `None` once it is no longer configured
+    pub application: Option<String>,
+    pub scopes: Vec<ConsentScope>,
+    /// When the most recent scope was granted
+    pub granted_at: i64,
+}
+
+/// Every application the user granted a scope to, by `client_id`
+pub fn list(db: &Database, user_id: &str, applications: &[ApplicationConfig]) -> Result<Vec<Consent>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn
+        .prepare("SELECT client_id, scope, granted_at FROM user_consents WHERE user_id = ?1 ORDER BY client_id")?;
+    let rows = stmt.query_map(params![user_id], |r| {
+        Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?))
+    })?;
+    let mut consents: BTreeMap<String, Consent> = BTreeMap::new();
+    for row in rows {
+        let (client_id, scope, granted_at) = row?;
+        let consent = consents.entry(client_id.clone()).or_insert_with(|| Consent {
+            application: applications.iter().find(|a| a.client_id == client_id).map(|a| a.name.clone()),
+            client_id,
+            scopes: Vec::new(),
+            granted_at,
+        });
+        consent.scopes.extend(ConsentScope::parse(&scope));
+        consent.granted_at = consent.granted_at.max(granted_at);
+    }
+    Ok(consents
+        .into_values()
+        .map(|mut c| {
+            c.scopes.sort();
+            c
+        })
+        .collect())
+}
+
+/// Scopes the user granted `client_id`
+pub fn granted(db: &Database, user_id: &str, client_id: &str) -> Result<Vec<ConsentScope>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare("SELECT scope FROM user_consents WHERE user_id = ?1 AND client_id = ?2")?;
+    let scopes = stmt
+        .query_map(params![user_id, client_id], |r| r.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut scopes: Vec<ConsentScope> = scopes.iter().filter_map(|s| ConsentScope::parse(s)).collect();
+    scopes.sort();
+    Ok(scopes)
+}
+
+/// Record the user's approval of `scopes` for `app`. Only scopes the
+/// application requests can be granted; granting one again keeps its
+/// original time.
+pub fn grant(
+    db: &Database,
+    user_id: &str,
+    app: &ApplicationConfig,
+    scopes: &[ConsentScope],
+) -> Result<(), ConsentError> {
+    if let Some(scope) = scopes.iter().find(|s| !app.scopes.contains(s)) {
+        return Err(ConsentError::NotRequested(scope.as_str()));
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    for scope in scopes {
+        tx.execute(
+            "INSERT INTO user_consents (user_id, client_id, scope, granted_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(user_id, client_id, scope) DO NOTHING",
+            params![user_id, app.client_id, scope.as_str(), Database::now_ts()],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Withdraw one scope, or with `None` everything granted to `client_id`.
+/// Returns the scopes that were revoked.
+pub fn revoke(
+    db: &Database,
+    user_id: &str,
+    client_id: &str,
+    scope: Option<ConsentScope>,
+) -> Result<Vec<ConsentScope>, rusqlite::Error> {
+    let revoked: Vec<ConsentScope> = granted(db, user_id, client_id)?
+        .into_iter()
+        .filter(|s| scope.is_none_or(|scope| scope == *s))
+        .collect();
+    for scope in &revoked {
+        db.conn().execute(
+            "DELETE FROM user_consents WHERE user_id = ?1 AND client_id = ?2 AND scope = ?3",
+            params![user_id, client_id, scope.as_str()],
+        )?;
+    }
+    Ok(revoked)
+}
+
+/// Claims about the user for `app`: its `sub`, plus the claims of each scope
+/// that the application requests and the user granted it
+pub fn userinfo(db: &Database, user_id: &str, app: &ApplicationConfig) -> Result<Map<String, Value>, rusqlite::Error> {
+    let subject = subjects::subject_for(db, user_id, Some(app))?;
+    let scopes: Vec<ConsentScope> = granted(db, user_id, &app.client_id)?
+        .into_iter()
+        .filter(|s| app.scopes.contains(s))
+        .collect();
+    claims(db, user_id, subject, &scopes)
+}
+
+fn claims(
+    db: &Database,
+    user_id: &str,
+    subject: String,
+    scopes: &[ConsentScope],
+) -> Result<Map<String, Value>, rusqlite::Error> {
+    let mut claims = Map::new();
+    if scopes.contains(&ConsentScope::Profile) {
+        let conn = db.conn();
+        let mut stmt = conn.prepare("SELECT field, value FROM user_profile_fields WHERE user_id = ?1 ORDER BY field")?;
+        let fields = stmt.query_map(params![user_id], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+        for field in fields {
+            let (field, value) = field?;
+            if !RESERVED_CLAIMS.contains(&field.as_str()) {
+                claims.insert(field, Value::String(value));
+            }
+        }
+    }
+    claims.insert("sub".into(), Value::String(subject));
+    if scopes.contains(&ConsentScope::Email) {
+        let (email, verified): (String, bool) = db.conn().query_row(
+            "SELECT email, email_verified_at IS NOT NULL FROM users WHERE id = ?1",
+            params![user_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )?;
+        claims.insert("email".into(), Value::String(email));
+        claims.insert("email_verified".into(), Value::Bool(verified));
+    }
+    if scopes.contains(&ConsentScope::Phone) {
+        if let Some(phone) = crate::notifications::phone(db, user_id)? {
+            claims.insert("phone_number".into(), Value::String(phone));
+        }
+    }
+    Ok(claims)
+}
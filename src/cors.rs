@@ -0,0 +1,257 @@
+use crate::config::Config;
+use axum::http::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, Any, CorsLayer};
+use tracing::{info, warn};
+
+/// Route groups that carry their own CORS policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    /// Auth flows called by relying apps
+    Public,
+    /// `/admin/*`
+    Admin,
+    /// `/metrics` and health probes
+    Metrics,
+}
+
+impl RouteGroup {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Admin => "admin",
+            Self::Metrics => "metrics",
+        }
+    }
+}
+
+/// `[cors]` table: one policy per route group
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CorsConfig {
+    /// Falls back to the top-level `cors_allow_all` / `cors_allowed_origins`
+    #[serde(default)]
+    pub public: Option<CorsGroupConfig>,
+    /// Browsers are denied unless an admin origin is configured
+    #[serde(default)]
+    pub admin: Option<CorsGroupConfig>,
+    /// Browsers are denied unless configured
+    #[serde(default)]
+    pub metrics: Option<CorsGroupConfig>,
+}
+
+/// CORS policy for a single route group
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CorsGroupConfig {
+    #[serde(default)]
+    pub allow_all: bool,
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Request headers browsers may send; empty allows any
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight (`Access-Control-Max-Age`);
+    /// unset leaves it to the browser default (5 seconds in Chrome)
+    #[serde(default)]
+    pub max_age_seconds: Option<u64>,
+    /// Answer Chrome's Private Network Access preflights, for pages on a
+    /// public address calling this server on a private one
+    #[serde(default)]
+    pub allow_private_network: bool,
+}
+
+impl Config {
+    /// Effective CORS policy for a route group
+    pub fn cors_group(&self, group: RouteGroup) -> CorsGroupConfig {
+        let configured = match group {
+            RouteGroup::Public => &self.cors.public,
+            RouteGroup::Admin => &self.cors.admin,
+            RouteGroup::Metrics => &self.cors.metrics,
+        };
+        match (configured, group) {
+            (Some(c), _) => c.clone(),
+            (None, RouteGroup::Public) => CorsGroupConfig {
+                allow_all: self.cors_allow_all,
+                allowed_origins: self.cors_allowed_origins.clone(),
+                ..Default::default()
+            },
+            (None, _) => CorsGroupConfig::default(),
+        }
+    }
+}
+
+/// Build the CORS layer for a route group
+pub fn layer(cfg: &Config, group: RouteGroup) -> CorsLayer {
+    with_policy(&cfg.cors_group(group), group)
+}
+
+fn allowed_headers(policy: &CorsGroupConfig, group: RouteGroup) -> AllowHeaders {
+    if policy.allowed_headers.is_empty() {
+        return Any.into();
+    }
+    let headers: Vec<HeaderName> = policy
+        .allowed_headers
+        .iter()
+        .filter_map(|h| match h.parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                warn!("CORS [{}]: ignoring invalid header {:?}", group.as_str(), h);
+                None
+            }
+        })
+        .collect();
+    headers.into()
+}
+
+/// Preflight caching, allowed headers and private network access
+fn preflight(layer: CorsLayer, policy: &CorsGroupConfig, group: RouteGroup) -> CorsLayer {
+    let layer = layer
+        .allow_headers(allowed_headers(policy, group))
+        .allow_private_network(policy.allow_private_network);
+    match policy.max_age_seconds {
+        Some(seconds) => layer.max_age(Duration::from_secs(seconds)),
+        None => layer,
+    }
+}
+
+fn with_policy(policy: &CorsGroupConfig, group: RouteGroup) -> CorsLayer {
+    if policy.allow_all {
+        info!("CORS [{}]: Allowing all origins", group.as_str());
+        return preflight(CorsLayer::permissive(), policy, group);
+    }
+
+    let origins: Vec<HeaderValue> = policy
+        .allowed_origins
+        .iter()
+        .filter_map(|o| match o.parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                warn!("CORS [{}]: ignoring invalid origin {:?}", group.as_str(), o);
+                None
+            }
+        })
+        .collect();
+
+    if origins.is_empty() {
+        info!("CORS [{}]: Disabled", group.as_str());
+        CorsLayer::new()
+    } else {
+        info!("CORS [{}]: Allowing origins: {:?}", group.as_str(), policy.allowed_origins);
+        if policy.allow_private_network {
+            info!("CORS [{}]: Allowing private network access", group.as_str());
+        }
+        preflight(CorsLayer::new().allow_origin(origins).allow_methods(Any), policy, group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn preflight_is_cached_and_allows_private_network() {
+        let policy = CorsGroupConfig {
+            allowed_origins: vec!["https://dash.internal".into()],
+            allowed_headers: vec!["content-type".into(), "x-client-id".into()],
+            max_age_seconds: Some(600),
+            allow_private_network: true,
+            ..Default::default()
+        };
+        let app = Router::new()
+            .route("/token", post(|| async { "" }))
+            .layer(with_policy(&policy, RouteGroup::Admin));
+        let request = Request::options("/token")
+            .header("origin", "https://dash.internal")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-private-network", "true")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(headers["access-control-max-age"], "600");
+        assert_eq!(headers["access-control-allow-headers"], "content-type,x-client-id");
+        assert_eq!(headers["access-control-allow-private-network"], "true");
+    }
+
+    async fn preflight_headers(policy: &CorsGroupConfig, origin: &str) -> axum::http::HeaderMap {
+        let app = Router::new()
+            .route("/token", post(|| async { "" }))
+            .layer(with_policy(policy, RouteGroup::Public));
+        let request = Request::options("/token")
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    #[test]
+    fn groups_fall_back_to_top_level_settings_only_for_public() {
+        let mut cfg: Config = toml::from_str(
+            r#"
+jwt_secret = "supersecretandlongenoughforhs256"
+access_token_expiry_seconds = 900
+refresh_token_expiry_seconds = 604800
+magic_link_expiry_seconds = 600
+magic_link_base_url = "https://auth.example.com/verify/magic"
+smtp_host = "smtp.example.com"
+smtp_port = 587
+smtp_username = ""
+smtp_password = ""
+email_from = "no-reply@example.com"
+webauthn_rp_id = "auth.example.com"
+webauthn_origin = "https://auth.example.com"
+webauthn_rp_name = "Example"
+database_path = ":memory:"
+cors_allow_all = true
+"#,
+        )
+        .unwrap();
+        assert!(cfg.cors_group(RouteGroup::Public).allow_all);
+        assert!(!cfg.cors_group(RouteGroup::Admin).allow_all);
+        assert!(cfg.cors_group(RouteGroup::Metrics).allowed_origins.is_empty());
+
+        // a `[cors.public]` table replaces the top-level settings
+        cfg.cors.public = Some(CorsGroupConfig {
+            allowed_origins: vec!["https://app.example.com".into()],
+            ..Default::default()
+        });
+        let public = cfg.cors_group(RouteGroup::Public);
+        assert!(!public.allow_all);
+        assert_eq!(public.allowed_origins, ["https://app.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn allow_all_echoes_any_origin_without_caching() {
+        let policy = CorsGroupConfig {
+            allow_all: true,
+            ..Default::default()
+        };
+        let headers = preflight_headers(&policy, "https://anywhere.example").await;
+        assert!(headers.contains_key("access-control-allow-origin"));
+        assert!(!headers.contains_key("access-control-max-age"));
+        assert!(!headers.contains_key("access-control-allow-private-network"));
+    }
+
+    #[tokio::test]
+    async fn invalid_origins_are_skipped() {
+        let policy = CorsGroupConfig {
+            allowed_origins: vec!["bad\norigin".into(), "https://app.example.com".into()],
+            allowed_headers: vec!["not a header".into(), "content-type".into()],
+            ..Default::default()
+        };
+        let headers = preflight_headers(&policy, "https://app.example.com").await;
+        assert_eq!(headers["access-control-allow-origin"], "https://app.example.com");
+        assert_eq!(headers["access-control-allow-headers"], "content-type");
+
+        // nothing valid left: browsers are refused
+        let policy = CorsGroupConfig {
+            allowed_origins: vec!["bad\norigin".into()],
+            ..Default::default()
+        };
+        let headers = preflight_headers(&policy, "https://app.example.com").await;
+        assert!(!headers.contains_key("access-control-allow-origin"));
+    }
+}
@@ -0,0 +1,603 @@
+//! Forced credential resets for incident response, such as a leaked session
+//! database or a compromised authenticator vendor.
+//!
+//! Creating a reset fixes its targets: the listed users, or every user. The
+//! elected replica then works through `[credential_resets] users_per_run`
+//! targets per run. For each one it revokes all sessions, expires outstanding
+//! magic links and WebAuthn ceremonies, optionally removes the TOTP secret
+//! and passkeys, and queues an email asking the user to sign in again and
+//! re-enroll. Canceling stops the remaining targets.
+//!
+//! Rolling back undoes what can be undone: removed TOTP secrets and passkeys
+//! are restored (unless the user has already enrolled new ones) and unsent
+//! emails are withdrawn. Sessions and links stay revoked. Removed factors
+//! are kept for `rollback_window_seconds` after the reset finishes and are
+//! purged afterwards.
+
+use crate::{
+    db::Database,
+    email_queue::{EmailQueue, QueueError},
+    email_templates::EmailTemplates,
+    leader::LeaderElection,
+    metrics::MetricsRecorder,
+    revocation::{RevocationBus, RevocationEvent},
+    storage::{revoke_stored_sessions, SharedTokenStore},
+};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// `[credential_resets]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct CredentialResetConfig {
+    /// Off pauses running resets; they resume when turned back on
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Targets reset per run, across all resets
+    #[serde(default = "default_users_per_run")]
+    pub users_per_run: u32,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// How long after a reset finishes it can still be rolled back
+    #[serde(default = "default_rollback_window_seconds")]
+    pub rollback_window_seconds: i64,
+}
+
+impl Default for CredentialResetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            users_per_run: default_users_per_run(),
+            interval_seconds: default_interval_seconds(),
+            rollback_window_seconds: default_rollback_window_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_users_per_run() -> u32 {
+    50
+}
+
+fn default_interval_seconds() -> u64 {
+    10
+}
+
+fn default_rollback_window_seconds() -> i64 {
+    7 * 24 * 3600
+}
+
+/// Which users a reset covers: the listed ones, or everyone with `all_users`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scope {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub all_users: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum CredentialResetError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("invalid scope: {0}")]
+    Scope(String),
+    #[error("reset already {0}")]
+    Finished(String),
+    #[error("the rollback window has closed")]
+    RollbackExpired,
+}
+
+/// Where the targets of a reset are, and what was removed so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub total: i64,
+    pub pending: i64,
+    pub done: i64,
+    pub canceled: i64,
+    pub rolled_back: i64,
+    pub sessions_revoked: i64,
+    /// Magic links and WebAuthn ceremonies expired
+    pub challenges_invalidated: i64,
+    pub totp_removed: i64,
+    pub passkeys_removed: i64,
+    pub emails_queued: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialReset {
+    pub id: String,
+    pub scope: Scope,
+    pub reset_totp: bool,
+    pub reset_passkeys: bool,
+    /// Email each target asking them to sign in again and re-enroll
+    pub notify: bool,
+    pub reason: Option<String>,
+    /// `queued`, `running`, `completed`, `canceled` or `rolled_back`
+    pub status: String,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub progress: Progress,
+}
+
+/// `POST /admin/credential-resets` body
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewCredentialReset {
+    #[serde(flatten)]
+    pub scope: Scope,
+    #[serde(default)]
+    pub reset_totp: bool,
+    #[serde(default)]
+    pub reset_passkeys: bool,
+    #[serde(default = "default_notify")]
+    pub notify: bool,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+fn default_notify() -> bool {
+    true
+}
+
+/// What a rollback restored
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Rollback {
+    pub totp_restored: usize,
+    pub passkeys_restored: usize,
+    /// Restores skipped because the user already enrolled a replacement
+    pub kept_newer: usize,
+    pub emails_withdrawn: usize,
+}
+
+const ACTIVE: &str = "('queued', 'running')";
+
+fn validate(scope: &Scope) -> Result<(), CredentialResetError> {
+    match (scope.all_users, scope.user_ids.is_empty()) {
+        (true, false) => Err(CredentialResetError::Scope(
+            "give either user_ids or all_users, not both".to_string(),
+        )),
+        (false, true) => Err(CredentialResetError::Scope(
+            "user_ids is empty; set all_users to reset everyone".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Create a reset and fix its targets; unknown user ids are ignored
+pub fn create(db: &Database, new: &NewCredentialReset, now: i64) -> Result<CredentialReset, CredentialResetError> {
+    validate(&new.scope)?;
+    let id = Uuid::new_v4().to_string();
+    let scope = serde_json::to_string(&new.scope).expect("scope serializes");
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO credential_resets (id, scope, reset_totp, reset_passkeys, notify, reason, status, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'queued', ?7)",
+        params![id, scope, new.reset_totp, new.reset_passkeys, new.notify, new.reason, now],
+    )?;
+    if new.scope.all_users {
+        tx.execute(
+            "INSERT INTO credential_reset_targets (reset_id, user_id) SELECT ?1, id FROM users",
+            params![id],
+        )?;
+    } else {
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO credential_reset_targets (reset_id, user_id) SELECT ?1, id FROM users WHERE id = ?2",
+        )?;
+        for user_id in &new.scope.user_ids {
+            stmt.execute(params![id, user_id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(get(db, &id)?.expect("reset just created"))
+}
+
+fn progress(db: &Database, id: &str) -> Result<Progress, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT status, COUNT(*), SUM(sessions_revoked), SUM(challenges_invalidated), SUM(totp_removed),
+                SUM(passkeys_removed), COUNT(email_id)
+         FROM credential_reset_targets WHERE reset_id = ?1 GROUP BY status",
+    )?;
+    let rows = stmt.query_map(params![id], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            [r.get::<_, i64>(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?, r.get(6)?],
+        ))
+    })?;
+    let mut progress = Progress::default();
+    for row in rows {
+        let (status, [n, sessions, challenges, totp, passkeys, emails]) = row?;
+        progress.total += n;
+        let bucket = match status.as_str() {
+            "pending" => &mut progress.pending,
+            "canceled" => &mut progress.canceled,
+            "rolled_back" => &mut progress.rolled_back,
+            _ => &mut progress.done,
+        };
+        *bucket += n;
+        progress.sessions_revoked += sessions;
+        progress.challenges_invalidated += challenges;
+        progress.totp_removed += totp;
+        progress.passkeys_removed += passkeys;
+        progress.emails_queued += emails;
+    }
+    Ok(progress)
+}
+
+fn from_row(r: &Row) -> rusqlite::Result<CredentialReset> {
+    let scope: String = r.get(1)?;
+    Ok(CredentialReset {
+        id: r.get(0)?,
+        scope: serde_json::from_str(&scope).unwrap_or_default(),
+        reset_totp: r.get(2)?,
+        reset_passkeys: r.get(3)?,
+        notify: r.get(4)?,
+        reason: r.get(5)?,
+        status: r.get(6)?,
+        created_at: r.get(7)?,
+        started_at: r.get(8)?,
+        finished_at: r.get(9)?,
+        progress: Progress::default(),
+    })
+}
+
+const COLUMNS: &str =
+    "id, scope, reset_totp, reset_passkeys, notify, reason, status, created_at, started_at, finished_at";
+
+pub fn get(db: &Database, id: &str) -> Result<Option<CredentialReset>, rusqlite::Error> {
+    let reset = db
+        .conn()
+        .query_row(&format!("SELECT {} FROM credential_resets WHERE id = ?1", COLUMNS), params![id], from_row)
+        .optional()?;
+    reset
+        .map(|mut reset| {
+            reset.progress = progress(db, &reset.id)?;
+            Ok(reset)
+        })
+        .transpose()
+}
+
+/// Newest first
+pub fn list(db: &Database, limit: i64) -> Result<Vec<CredentialReset>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM credential_resets ORDER BY created_at DESC, rowid DESC LIMIT ?1",
+        COLUMNS
+    ))?;
+    let resets = stmt.query_map(params![limit], from_row)?.collect::<Result<Vec<_>, _>>()?;
+    resets
+        .into_iter()
+        .map(|mut reset| {
+            reset.progress = progress(db, &reset.id)?;
+            Ok(reset)
+        })
+        .collect()
+}
+
+/// Stop a reset; targets not reached yet are left alone. `None` when there is no such reset.
+pub fn cancel(db: &Database, id: &str, now: i64) -> Result<Option<CredentialReset>, CredentialResetError> {
+    let Some(reset) = get(db, id)? else {
+        return Ok(None);
+    };
+    if reset.status != "queued" && reset.status != "running" {
+        return Err(CredentialResetError::Finished(reset.status));
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE credential_reset_targets SET status = 'canceled', updated_at = ?2
+         WHERE reset_id = ?1 AND status = 'pending'",
+        params![id, now],
+    )?;
+    tx.execute(
+        "UPDATE credential_resets SET status = 'canceled', finished_at = ?2 WHERE id = ?1",
+        params![id, now],
+    )?;
+    tx.commit()?;
+    Ok(get(db, id)?)
+}
+
+/// Cancel the remaining targets and restore what the reset removed, where the
+/// user has not replaced it yet. `None` when there is no such reset.
+pub fn rollback(
+    db: &Database,
+    cfg: &CredentialResetConfig,
+    id: &str,
+    now: i64,
+) -> Result<Option<(CredentialReset, Rollback)>, CredentialResetError> {
+    let Some(reset) = get(db, id)? else {
+        return Ok(None);
+    };
+    if reset.status == "rolled_back" {
+        return Err(CredentialResetError::Finished(reset.status));
+    }
+    if reset.finished_at.is_some_and(|at| at < now - cfg.rollback_window_seconds) {
+        return Err(CredentialResetError::RollbackExpired);
+    }
+    let mut summary = Rollback::default();
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE credential_reset_targets SET status = 'canceled', updated_at = ?2
+         WHERE reset_id = ?1 AND status = 'pending'",
+        params![id, now],
+    )?;
+
+    // a secret is only put back while the user has none
+    let totp: Vec<(String, Option<String>)> = tx
+        .prepare(
+            "SELECT user_id, saved_totp_secret FROM credential_reset_targets
+             WHERE reset_id = ?1 AND status = 'done' AND totp_removed = 1",
+        )?
+        .query_map(params![id], |r| Ok((r.get(0)?, r.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (user_id, secret) in totp {
+        let restored = tx.execute(
+            "UPDATE users SET totp_secret = ?2 WHERE id = ?1 AND totp_secret IS NULL AND ?2 IS NOT NULL",
+            params![user_id, secret],
+        )?;
+        if restored > 0 {
+            summary.totp_restored += 1;
+        } else {
+            summary.kept_newer += 1;
+        }
+    }
+
+    // a passkey is only put back while its credential id is not registered again
+    let saved: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM credential_reset_passkeys WHERE reset_id = ?1",
+        params![id],
+        |r| r.get(0),
+    )?;
+    summary.passkeys_restored = tx.execute(
+        "INSERT INTO webauthn_registrations
+             (id, user_id, credential_id, public_key, sign_count, transports, created_at, last_used_at, use_count,
+              last_ip, aaguid, name)
+         SELECT p.id, p.user_id, p.credential_id, p.public_key, p.sign_count, p.transports, p.created_at,
+                p.last_used_at, p.use_count, p.last_ip, p.aaguid, p.name
+         FROM credential_reset_passkeys p JOIN users u ON u.id = p.user_id
+         WHERE p.reset_id = ?1 AND NOT EXISTS (
+             SELECT 1 FROM webauthn_registrations w WHERE w.id = p.id OR w.credential_id = p.credential_id)",
+        params![id],
+    )?;
+    summary.kept_newer += (saved as usize).saturating_sub(summary.passkeys_restored);
+
+    summary.emails_withdrawn = tx.execute(
+        "DELETE FROM email_queue WHERE status = 'pending' AND id IN (
+             SELECT email_id FROM credential_reset_targets WHERE reset_id = ?1)",
+        params![id],
+    )?;
+    tx.execute("DELETE FROM credential_reset_passkeys WHERE reset_id = ?1", params![id])?;
+    tx.execute(
+        "UPDATE credential_reset_targets SET status = 'rolled_back', saved_totp_secret = NULL, updated_at = ?2
+         WHERE reset_id = ?1 AND status = 'done'",
+        params![id, now],
+    )?;
+    tx.execute(
+        "UPDATE credential_resets SET status = 'rolled_back', finished_at = COALESCE(finished_at, ?2) WHERE id = ?1",
+        params![id, now],
+    )?;
+    tx.commit()?;
+    Ok(get(db, id)?.map(|reset| (reset, summary)))
+}
+
+/// A target due for reset, with the options of its reset
+struct Due {
+    reset_id: String,
+    user_id: String,
+    email: String,
+    reset_totp: bool,
+    reset_passkeys: bool,
+    notify: bool,
+}
+
+/// Reset one target in a transaction
+fn reset_target(db: &Database, due: &Due, now: i64) -> Result<(), rusqlite::Error> {
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let sessions = tx.execute(
+        "UPDATE refresh_tokens SET revoked = 1 WHERE user_id = ?1 AND revoked = 0",
+        params![due.user_id],
+    )?;
+    let links = tx.execute(
+        "UPDATE magic_links SET expires_at = ?2 WHERE user_id = ?1 AND used = 0 AND expires_at >= ?3",
+        params![due.user_id, now - 1, now],
+    )?;
+    let ceremonies = tx.execute("DELETE FROM pending_webauthn WHERE user_id = ?1", params![due.user_id])?;
+
+    let mut totp_removed = false;
+    if due.reset_totp {
+        let secret: Option<String> =
+            tx.query_row("SELECT totp_secret FROM users WHERE id = ?1", params![due.user_id], |r| r.get(0))?;
+        if let Some(secret) = secret {
+            tx.execute(
+                "UPDATE credential_reset_targets SET saved_totp_secret = ?3 WHERE reset_id = ?1 AND user_id = ?2",
+                params![due.reset_id, due.user_id, secret],
+            )?;
+            tx.execute("UPDATE users SET totp_secret = NULL WHERE id = ?1", params![due.user_id])?;
+            tx.execute("DELETE FROM totp_attempts WHERE user_id = ?1", params![due.user_id])?;
+            totp_removed = true;
+        }
+    }
+    let mut passkeys = 0;
+    if due.reset_passkeys {
+        passkeys = tx.execute(
+            "INSERT OR IGNORE INTO credential_reset_passkeys
+                 (reset_id, id, user_id, credential_id, public_key, sign_count, transports, created_at, last_used_at,
+                  use_count, last_ip, aaguid, name)
+             SELECT ?1, id, user_id, credential_id, public_key, sign_count, transports, created_at, last_used_at,
+                    use_count, last_ip, aaguid, name
+             FROM webauthn_registrations WHERE user_id = ?2",
+            params![due.reset_id, due.user_id],
+        )?;
+        tx.execute("DELETE FROM webauthn_registrations WHERE user_id = ?1", params![due.user_id])?;
+    }
+
+    // a suppressed address is reset without an email
+    let mut email_id = None;
+    if due.notify {
+        let rendered = EmailTemplates::credential_reset(&due.email, totp_removed, passkeys > 0);
+        match EmailQueue::enqueue(db, &due.email, &rendered.subject, &rendered.text, Some(&rendered.html)) {
+            Ok(id) => email_id = Some(id),
+            Err(QueueError::Suppressed) => {}
+            Err(QueueError::Db(e)) => return Err(e),
+        }
+    }
+    tx.execute(
+        "UPDATE credential_reset_targets
+         SET status = 'done', sessions_revoked = ?3, challenges_invalidated = ?4, totp_removed = ?5,
+             passkeys_removed = ?6, email_id = ?7, updated_at = ?8
+         WHERE reset_id = ?1 AND user_id = ?2",
+        params![
+            due.reset_id,
+            due.user_id,
+            sessions,
+            links + ceremonies,
+            totp_removed,
+            passkeys,
+            email_id,
+            now
+        ],
+    )?;
+    tx.commit()
+}
+
+/// Reset the next targets of active resets; returns the users reset, whose
+/// access tokens the caller should revoke
+pub fn run_batch(db: &Database, cfg: &CredentialResetConfig, now: i64) -> Result<Vec<String>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.reset_id, t.user_id, u.email, c.reset_totp, c.reset_passkeys, c.notify
+         FROM credential_reset_targets t
+         JOIN credential_resets c ON c.id = t.reset_id
+         JOIN users u ON u.id = t.user_id
+         WHERE t.status = 'pending' AND c.status IN {}
+         ORDER BY c.created_at, c.id, t.rowid LIMIT ?1",
+        ACTIVE
+    ))?;
+    let due = stmt
+        .query_map(params![cfg.users_per_run.max(1)], |r| {
+            Ok(Due {
+                reset_id: r.get(0)?,
+                user_id: r.get(1)?,
+                email: r.get(2)?,
+                reset_totp: r.get(3)?,
+                reset_passkeys: r.get(4)?,
+                notify: r.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut started = HashSet::new();
+    let mut reset = Vec::with_capacity(due.len());
+    for target in due {
+        reset_target(db, &target, now)?;
+        MetricsRecorder::record_credential_reset_user();
+        started.insert(target.reset_id);
+        reset.push(target.user_id);
+    }
+    for id in started {
+        db.conn().execute(
+            "UPDATE credential_resets SET status = 'running', started_at = ?2 WHERE id = ?1 AND status = 'queued'",
+            params![id, now],
+        )?;
+    }
+    let completed = db.conn().execute(
+        &format!(
+            "UPDATE credential_resets SET status = 'completed', started_at = COALESCE(started_at, ?1), finished_at = ?1
+             WHERE status IN {} AND NOT EXISTS (
+                 SELECT 1 FROM credential_reset_targets t
+                 WHERE t.reset_id = credential_resets.id AND t.status = 'pending')",
+            ACTIVE
+        ),
+        params![now],
+    )?;
+    if completed > 0 {
+        info!("{} credential reset(s) completed", completed);
+    }
+    Ok(reset)
+}
+
+/// Forget the factors of resets whose rollback window has closed; returns how many targets had a TOTP secret
+pub fn purge_rollback_data(db: &Database, cfg: &CredentialResetConfig, now: i64) -> Result<usize, rusqlite::Error> {
+    let cutoff = now - cfg.rollback_window_seconds;
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM credential_reset_passkeys WHERE reset_id IN (
+             SELECT id FROM credential_resets WHERE finished_at < ?1)",
+        params![cutoff],
+    )?;
+    let purged = tx.execute(
+        "UPDATE credential_reset_targets SET saved_totp_secret = NULL
+         WHERE saved_totp_secret IS NOT NULL AND reset_id IN (SELECT id FROM credential_resets WHERE finished_at < ?1)",
+        params![cutoff],
+    )?;
+    tx.commit()?;
+    Ok(purged)
+}
+
+/// Work through resets on the elected replica every `interval_seconds`,
+/// revoking the access tokens of each user reset
+pub fn spawn_runner(
+    db: Arc<Database>,
+    leader: Arc<LeaderElection>,
+    revocations: Arc<RevocationBus>,
+    tokens: Option<SharedTokenStore>,
+    cfg: CredentialResetConfig,
+) {
+    if !cfg.enabled {
+        return;
+    }
+    let every = Duration::from_secs(cfg.interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("credential_resets", every) {
+                continue;
+            }
+            let now = Database::now_ts();
+            match run_batch(&db, &cfg, now) {
+                Ok(users) => {
+                    for user_id in users {
+                        revoke_stored_sessions(tokens.as_deref(), &user_id);
+                        revocations.publish(RevocationEvent::UserSessionsRevoked { user_id, at: now });
+                    }
+                }
+                Err(e) => warn!("Failed to run credential resets: {}", e),
+            }
+            if let Err(e) = purge_rollback_data(&db, &cfg, now) {
+                warn!("Failed to purge credential reset rollback data: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_names_users_or_everyone() {
+        let new: NewCredentialReset = serde_json::from_str(r#"{"user_ids": ["u1"], "reset_totp": true}"#).unwrap();
+        assert!(validate(&new.scope).is_ok());
+        assert!(new.notify && new.reset_totp && !new.reset_passkeys);
+        assert_eq!(serde_json::to_string(&new.scope).unwrap(), r#"{"user_ids":["u1"]}"#);
+
+        let everyone: NewCredentialReset = serde_json::from_str(r#"{"all_users": true}"#).unwrap();
+        assert!(validate(&everyone.scope).is_ok());
+        for body in [r#"{}"#, r#"{"user_ids": []}"#, r#"{"user_ids": ["u1"], "all_users": true}"#] {
+            let new: NewCredentialReset = serde_json::from_str(body).unwrap();
+            assert!(matches!(validate(&new.scope), Err(CredentialResetError::Scope(_))));
+        }
+    }
+}
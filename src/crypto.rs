@@ -0,0 +1,202 @@
+//! Hashing and MAC primitives, behind a provider chosen at compile time.
+//!
+//! The default build uses the RustCrypto `sha2`/`hmac` crates. Building with
+//! `--features fips` switches to aws-lc-rs in FIPS mode (the AWS-LC FIPS
+//! module). Code outside this module goes through these functions rather
+//! than a crypto crate, so the provider is swapped in one place. Known-answer
+//! tests in [`self_test`] run at startup and confirm the provider works.
+
+use thiserror::Error;
+
+#[cfg(not(feature = "fips"))]
+mod backend {
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+
+    pub const NAME: &str = "rust-crypto";
+
+    pub struct Hasher(Sha256);
+
+    impl Hasher {
+        pub fn new() -> Self {
+            Self(Sha256::new())
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finish(self) -> [u8; 32] {
+            self.0.finalize().into()
+        }
+    }
+
+    pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+
+    pub fn check_module() -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fips")]
+mod backend {
+    use aws_lc_rs::{digest, hmac};
+
+    pub const NAME: &str = "aws-lc-rs (FIPS)";
+
+    pub struct Hasher(digest::Context);
+
+    impl Hasher {
+        pub fn new() -> Self {
+            Self(digest::Context::new(&digest::SHA256))
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finish(self) -> [u8; 32] {
+            self.0.finish().as_ref().try_into().expect("SHA-256 digests are 32 bytes")
+        }
+    }
+
+    pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        hmac::sign(&key, data).as_ref().try_into().expect("HMAC-SHA256 tags are 32 bytes")
+    }
+
+    /// The module must be running in its approved mode, not merely linked
+    pub fn check_module() -> Result<(), String> {
+        aws_lc_rs::try_fips_mode().map_err(|e| e.to_string())
+    }
+}
+
+/// Name of the compiled-in provider, for logs and the config doctor
+pub const PROVIDER: &str = backend::NAME;
+
+/// Whether this build uses the FIPS-validated provider
+pub const FIPS: bool = cfg!(feature = "fips");
+
+/// Linked dependencies that do their own crypto instead of going through
+/// the provider
+pub const OUTSIDE_PROVIDER: &[&str] = &[
+    "jsonwebtoken (ring) signs access tokens",
+    "totp-lite computes TOTP codes",
+    "webauthn-rs (OpenSSL) verifies passkey signatures",
+];
+
+/// Whether every algorithm this build runs is in the FIPS module; the
+/// error says what is not (`require_fips` refuses to start on it)
+pub fn check_fips_boundary() -> Result<(), String> {
+    if !FIPS {
+        return Err(format!("this build uses {}; rebuild with --features fips", PROVIDER));
+    }
+    match OUTSIDE_PROVIDER {
+        [] => Ok(()),
+        outside => Err(format!("crypto outside the FIPS module: {}", outside.join("; "))),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("{0} known-answer test failed")]
+    KnownAnswer(&'static str),
+    #[error("FIPS module is not in FIPS mode: {0}")]
+    FipsMode(String),
+}
+
+/// Incremental SHA-256
+pub struct Sha256Hasher(backend::Hasher);
+
+impl Sha256Hasher {
+    pub fn new() -> Self {
+        Self(backend::Hasher::new())
+    }
+
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.update(data.as_ref());
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.0.finish()
+    }
+}
+
+impl Default for Sha256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SHA-256 of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Hex SHA-256 of a bearer token. Refresh and magic link tokens are stored
+/// only in this form and looked up by it, so a leaked row cannot be replayed.
+pub fn token_digest(token: &str) -> String {
+    data_encoding::HEXLOWER.encode(&sha256(token.as_bytes()))
+}
+
+/// HMAC-SHA256 of `data` under `key`
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    backend::hmac_sha256(key, data).to_vec()
+}
+
+/// Compare two byte strings in constant time (with respect to their contents)
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Startup self-test: known answers for every primitive (FIPS 180-4 and
+/// RFC 4231 vectors), and for the FIPS provider, that its module is in FIPS mode
+pub fn self_test() -> Result<(), CryptoError> {
+    backend::check_module().map_err(CryptoError::FipsMode)?;
+    let sha = data_encoding::HEXLOWER.encode(&sha256(b"abc"));
+    if sha != "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad" {
+        return Err(CryptoError::KnownAnswer("SHA-256"));
+    }
+    let mut hasher = Sha256Hasher::new();
+    hasher.update("a");
+    hasher.update("bc");
+    if hasher.finalize() != sha256(b"abc") {
+        return Err(CryptoError::KnownAnswer("incremental SHA-256"));
+    }
+    let mac = data_encoding::HEXLOWER.encode(&hmac_sha256(b"Jefe", b"what do ya want for nothing?"));
+    if mac != "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843" {
+        return Err(CryptoError::KnownAnswer("HMAC-SHA256"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_passes_self_test() {
+        self_test().unwrap();
+        assert!(constant_time_eq(b"same", b"same"));
+        assert!(!constant_time_eq(b"same", b"diff"));
+    }
+
+    #[test]
+    fn fips_boundary_names_crypto_outside_the_module() {
+        let err = check_fips_boundary().unwrap_err();
+        if FIPS {
+            assert!(err.contains("jsonwebtoken"), "{}", err);
+        } else {
+            assert!(err.contains("--features fips"), "{}", err);
+        }
+    }
+}
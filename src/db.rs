@@ -1,31 +1,304 @@
-use rusqlite::{params, Connection};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::crypto;
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use rusqlite::{functions::FunctionFlags, params, Connection, OptionalExtension, Transaction};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+/// A schema migration, embedded in the binary so a deployment needs no
+/// `migrations/` directory next to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    /// File name under `migrations/`, recorded in `schema_migrations` once applied
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migrations {
+    ($($file:literal,)*) => {
+        [$(Migration { name: $file, sql: include_str!(concat!("../migrations/", $file)) },)*]
+    };
+}
+
+/// Migrations applied at startup, in order
+pub const MIGRATIONS: &[Migration] = &migrations![
+    "init.sql",
+    "002_email_queue.sql",
+    "003_production_features.sql",
+    "004_session_metadata.sql",
+    "005_action_links.sql",
+    "006_user_roles.sql",
+    "007_session_rotation.sql",
+    "008_outbox.sql",
+    "009_dead_letter.sql",
+    "010_user_subjects.sql",
+    "011_totp_attempts.sql",
+    "012_devices.sql",
+    "013_passkey_nudges.sql",
+    "014_magic_link_redirects.sql",
+    "015_security_notices.sql",
+    "016_notification_preferences.sql",
+    "017_user_profiles.sql",
+    "018_debug_samples.sql",
+    "019_normalize_email_domains.sql",
+    "020_revocation_log.sql",
+    "021_user_updated_at.sql",
+    "022_magic_link_issuance.sql",
+    "023_magic_link_binding.sql",
+    "024_session_user_agents.sql",
+    "025_admin_api_keys.sql",
+    "026_job_leases.sql",
+    "027_magic_link_flows.sql",
+    "028_passkey_usage.sql",
+    "029_session_expiry_notices.sql",
+    "030_abuse_reports.sql",
+    "031_email_suppressions.sql",
+    "032_webhook_secrets.sql",
+    "033_ip_bans.sql",
+    "034_email_quota.sql",
+    "035_canary_accounts.sql",
+    "036_broadcasts.sql",
+    "037_credential_resets.sql",
+    "038_hashed_tokens.sql",
+    "039_session_amr.sql",
+    "040_stale_accounts.sql",
+    "041_admin_users.sql",
+    "042_passkey_names.sql",
+    "043_user_consents.sql",
+    "044_keys.sql",
+    "045_applications.sql",
+    "046_revoked_access_tokens.sql",
+    "047_device_authorizations.sql",
+    "048_session_clients.sql",
+    "049_totp_attempt_windows.sql",
+    "050_phone_verifications.sql",
+    "051_maintenance_state.sql",
+    "052_webauthn_login_clients.sql",
+    "053_magic_link_failed_proofs.sql",
+];
+
+/// Tables holding a user's rows without `ON DELETE CASCADE`
+const USER_TABLES: [&str; 11] = [
+    "magic_links",
+    "refresh_tokens",
+    "webauthn_registrations",
+    "pending_webauthn",
+    "action_links",
+    "user_countries",
+    "notification_preferences",
+    "user_profile_fields",
+    "session_devices",
+    "email_sends",
+    "credential_reset_passkeys",
+];
+
+/// Delete a user and everything stored about them. Audit entries stay, with
+/// the user id cleared.
+pub fn delete_user(conn: &Connection, user_id: &str) -> Result<(), rusqlite::Error> {
+    for table in ["session_metadata", "session_generations"] {
+        conn.execute(
+            &format!(
+                "DELETE FROM {} WHERE session_id IN (SELECT session_id FROM refresh_tokens WHERE user_id = ?1)",
+                table
+            ),
+            params![user_id],
+        )?;
+    }
+    conn.execute(
+        "DELETE FROM email_queue WHERE status = 'pending' AND to_email = (SELECT email FROM users WHERE id = ?1)",
+        params![user_id],
+    )?;
+    for table in USER_TABLES {
+        conn.execute(&format!("DELETE FROM {} WHERE user_id = ?1", table), params![user_id])?;
+    }
+    conn.execute("DELETE FROM users WHERE id = ?1", params![user_id])?;
+    Ok(())
+}
+
+/// Tables and columns a migration creates, as `(table, None)` for
+/// `CREATE TABLE` and `(table, Some(column))` for `ALTER TABLE ... ADD`
+pub fn created_objects(sql: &str) -> Vec<(String, Option<String>)> {
+    let words: Vec<&str> = sql.split_whitespace().collect();
+    let mut created = Vec::new();
+    for (i, w) in words.iter().enumerate() {
+        let upper = |j: usize| words.get(j).map(|s| s.to_ascii_uppercase()).unwrap_or_default();
+        let ident = |j: usize| {
+            words
+                .get(j)
+                .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric() && c != '_').to_string())
+        };
+        if w.eq_ignore_ascii_case("CREATE") && upper(i + 1) == "TABLE" {
+            let at = if upper(i + 2) == "IF" { i + 5 } else { i + 2 };
+            if let Some(table) = ident(at) {
+                created.push((table, None));
+            }
+        } else if w.eq_ignore_ascii_case("ALTER") && upper(i + 1) == "TABLE" && upper(i + 3) == "ADD" {
+            let at = if upper(i + 4) == "COLUMN" { i + 5 } else { i + 4 };
+            if let (Some(table), Some(column)) = (ident(i + 2), ident(at)) {
+                created.push((table, Some(column)));
+            }
+        }
+    }
+    created
+}
+
+fn table_exists(conn: &Connection, table: &str) -> rusqlite::Result<bool> {
+    object_exists(conn, table, None)
+}
+
+/// Whether `table` (or its `column`) exists
+fn object_exists(conn: &Connection, table: &str, column: Option<&str>) -> rusqlite::Result<bool> {
+    let count: i64 = match column {
+        None => conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |r| r.get(0),
+        )?,
+        Some(column) => conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+            [table, column],
+            |r| r.get(0),
+        )?,
+    };
+    Ok(count > 0)
+}
+
+/// SQL functions the migrations rely on: `sha256_hex(text)` is
+/// [`crypto::token_digest`], used to hash tokens stored in plaintext
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "sha256_hex",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(crypto::token_digest(&ctx.get::<String>(0)?)),
+    )
+}
+
+/// The SQLite connection, shared by every request.
+///
+/// `rusqlite::Connection` is `Send` but not `Sync`, so it sits behind a lock.
+/// The lock is reentrant: storage helpers taking `&Database` run inside
+/// transactions opened by their callers on the same thread.
 #[derive(Debug)]
 pub struct Database {
-    pub conn: Connection,
+    conn: ReentrantMutex<Connection>,
 }
 
 #[derive(Debug, Error)]
 pub enum DbError {
     #[error("rusqlite error: {0}")]
     Sql(#[from] rusqlite::Error),
+    #[error("migration {0} failed: {1}")]
+    Migration(&'static str, rusqlite::Error),
 }
 
 impl Database {
     pub fn open(path: &str) -> Result<Self, DbError> {
         let conn = Connection::open(path)?;
         // enable foreign keys
-        conn.pragma_update(None, "foreign_keys", &"ON")?;
-        Ok(Self { conn })
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        // wait for concurrent writers (other connections, the email worker) instead of failing with SQLITE_BUSY
+        conn.busy_timeout(Duration::from_secs(5))?;
+        register_functions(&conn)?;
+        Ok(Self {
+            conn: ReentrantMutex::new(conn),
+        })
+    }
+
+    /// The connection, locked until the guard is dropped. Never hold the
+    /// guard across an `.await`. Handlers and the auth service go through
+    /// the repositories (`repository`) or the module owning the table.
+    pub(crate) fn conn(&self) -> ReentrantMutexGuard<'_, Connection> {
+        self.conn.lock()
+    }
+
+    /// Raw connection for test fixtures (backdating rows, dropping tables)
+    #[cfg(feature = "test-util")]
+    pub fn fixture_conn(&self) -> ReentrantMutexGuard<'_, Connection> {
+        self.conn()
+    }
+
+    /// Run `f` in a transaction, committed when it returns `Ok`. Storage
+    /// calls `f` makes on this thread join the transaction.
+    pub fn transaction<T, E: From<rusqlite::Error>>(
+        &self,
+        f: impl FnOnce(&Transaction) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let conn = self.conn();
+        let tx = conn.unchecked_transaction()?;
+        let value = f(&tx)?;
+        tx.commit()?;
+        Ok(value)
     }
 
     pub fn migrate(&self, sql: &str) -> Result<(), DbError> {
-        self.conn.execute_batch(sql)?;
+        self.conn().execute_batch(sql)?;
         Ok(())
     }
 
+    /// [`MIGRATIONS`] not yet applied to this database, in order.
+    ///
+    /// Applied migrations are recorded in `schema_migrations`. For a database
+    /// migrated before that table existed, the pending ones start at the
+    /// first migration whose tables or columns are missing.
+    pub fn pending_migrations(&self) -> Result<Vec<&'static Migration>, DbError> {
+        let conn = self.conn();
+        if table_exists(&conn, "schema_migrations")? {
+            let mut stmt = conn.prepare("SELECT version FROM schema_migrations")?;
+            let applied = stmt
+                .query_map([], |r| r.get::<_, String>(0))?
+                .collect::<Result<HashSet<_>, _>>()?;
+            return Ok(MIGRATIONS.iter().filter(|m| !applied.contains(m.name)).collect());
+        }
+        if !table_exists(&conn, "users")? {
+            return Ok(MIGRATIONS.iter().collect());
+        }
+        // migrations only ever ran in order, so everything from the first one
+        // with a missing table or column on is pending
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            for (table, column) in created_objects(migration.sql) {
+                if !object_exists(&conn, &table, column.as_deref())? {
+                    return Ok(MIGRATIONS[i..].iter().collect());
+                }
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// Apply the pending migrations, each in its own transaction together
+    /// with its `schema_migrations` row, and return their names. Stops at the
+    /// first one that fails; the ones before it stay applied.
+    pub fn apply_migrations(&self) -> Result<Vec<&'static str>, DbError> {
+        let conn = self.conn();
+        let pending = self.pending_migrations()?;
+        // not part of init.sql: WAL cannot be entered inside a transaction
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (version TEXT PRIMARY KEY, applied_at INTEGER NOT NULL)",
+        )?;
+        let now = Self::now_ts();
+        // a database from before version tracking: record what it already has
+        for migration in MIGRATIONS.iter().filter(|m| !pending.contains(m)) {
+            conn.execute(
+                "INSERT OR IGNORE INTO schema_migrations (version, applied_at) VALUES (?1, ?2)",
+                params![migration.name, now],
+            )?;
+        }
+        let mut applied = Vec::new();
+        for migration in pending {
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch(migration.sql)
+                .map_err(|e| DbError::Migration(migration.name, e))?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, applied_at) VALUES (?1, ?2)",
+                params![migration.name, now],
+            )?;
+            tx.commit()?;
+            applied.push(migration.name);
+        }
+        Ok(applied)
+    }
+
     pub fn now_ts() -> i64 {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -33,23 +306,24 @@ impl Database {
             .as_secs() as i64
     }
 
-    // helper for inserting user if not exists
+    /// Id of the user with `email`, creating the user on first sight.
+    ///
+    /// Safe for concurrent first-time requests on any number of connections:
+    /// the insert is a no-op for everyone but the first caller, and all of
+    /// them read back the same row.
     pub fn get_or_create_user(&self, email: &str) -> Result<String, DbError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id FROM users WHERE email = ?1")?;
-        let mut rows = stmt.query(params![email])?;
-        if let Some(r) = rows.next()? {
-            let id: String = r.get(0)?;
-            Ok(id)
-        } else {
-            let id = uuid::Uuid::new_v4().to_string();
-            let now = Self::now_ts();
-            self.conn.execute(
-                "INSERT INTO users (id, email, created_at) VALUES (?1, ?2, ?3)",
-                params![id, email, now],
-            )?;
-            Ok(id)
+        let find = || {
+            self.conn()
+                .query_row("SELECT id FROM users WHERE email = ?1", params![email], |r| r.get(0))
+                .optional()
+        };
+        if let Some(id) = find()? {
+            return Ok(id);
         }
+        self.conn().execute(
+            "INSERT INTO users (id, email, created_at) VALUES (?1, ?2, ?3) ON CONFLICT(email) DO NOTHING",
+            params![uuid::Uuid::new_v4().to_string(), email, Self::now_ts()],
+        )?;
+        Ok(find()?.ok_or(rusqlite::Error::QueryReturnedNoRows)?)
     }
 }
@@ -0,0 +1,324 @@
+use crate::{db::Database, leader::LeaderElection, middleware::RequestId};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+/// `[debug_sampling]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct DebugSamplingConfig {
+    /// Off by default; samples contain (redacted) request payloads
+    #[serde(default)]
+    pub enabled: bool,
+    /// Percentage (0-100) of failed auth requests to record
+    #[serde(default = "default_sample_percent")]
+    pub sample_percent: f64,
+    /// Samples older than this are deleted
+    #[serde(default = "default_retention_seconds")]
+    pub retention_seconds: i64,
+    /// Larger request or response bodies are recorded as a size placeholder
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+}
+
+impl Default for DebugSamplingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_percent: default_sample_percent(),
+            retention_seconds: default_retention_seconds(),
+            max_body_bytes: default_max_body_bytes(),
+        }
+    }
+}
+
+fn default_sample_percent() -> f64 {
+    5.0
+}
+
+fn default_retention_seconds() -> i64 {
+    86400
+}
+
+fn default_max_body_bytes() -> usize {
+    65536
+}
+
+/// JSON keys and query parameters whose values are never stored
+const SENSITIVE_KEYS: &[&str] = &[
+    "code", "code_verifier", "email", "otp", "password", "phone", "pending_id", "secret", "state", "ticket",
+];
+
+/// Headers worth keeping; everything else (cookies, authorization) is dropped
+const RECORDED_HEADERS: &[&str] = &["content-type", "user-agent", "x-client-id", "origin"];
+
+const REDACTED: &str = "[redacted]";
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.contains("token") || key.contains("secret") || SENSITIVE_KEYS.contains(&key.as_str())
+}
+
+/// Replace sensitive values anywhere in a JSON document
+pub fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if is_sensitive(key) {
+                    *v = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// Redact a body for storage; non-JSON bodies are summarized by size only
+pub fn redact_body(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        return None;
+    }
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut json) => {
+            redact_json(&mut json);
+            Some(json.to_string())
+        }
+        Err(_) => Some(format!("[{} bytes, not JSON]", bytes.len())),
+    }
+}
+
+/// Redact sensitive query parameters (`/verify/magic?token=...`)
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if is_sensitive(key) => format!("{}={}", key, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn recorded_headers(headers: &HeaderMap) -> Value {
+    let map = RECORDED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = headers.get(*name)?.to_str().ok()?;
+            Some((name.to_string(), Value::String(value.to_string())))
+        })
+        .collect();
+    Value::Object(map)
+}
+
+/// One recorded failed request
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugSample {
+    pub id: i64,
+    pub created_at: i64,
+    pub request_id: Option<String>,
+    pub method: String,
+    /// Path and redacted query string
+    pub path: String,
+    pub status: u16,
+    pub request_headers: Value,
+    pub request_body: Option<String>,
+    pub response_body: Option<String>,
+}
+
+/// Sample to store; [`record`] assigns the id and timestamp
+#[derive(Debug, Clone)]
+pub struct NewSample {
+    pub request_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub request_headers: Value,
+    pub request_body: Option<String>,
+    pub response_body: Option<String>,
+}
+
+pub fn record(db: &Database, sample: &NewSample) -> Result<i64, rusqlite::Error> {
+    db.conn().execute(
+        "INSERT INTO debug_samples (created_at, request_id, method, path, status, request_headers, request_body, response_body)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            Database::now_ts(),
+            sample.request_id,
+            sample.method,
+            sample.path,
+            sample.status,
+            sample.request_headers.to_string(),
+            sample.request_body,
+            sample.response_body,
+        ],
+    )?;
+    Ok(db.conn().last_insert_rowid())
+}
+
+fn sample_from_row(r: &rusqlite::Row) -> rusqlite::Result<DebugSample> {
+    let headers: String = r.get(6)?;
+    Ok(DebugSample {
+        id: r.get(0)?,
+        created_at: r.get(1)?,
+        request_id: r.get(2)?,
+        method: r.get(3)?,
+        path: r.get(4)?,
+        status: r.get(5)?,
+        request_headers: serde_json::from_str(&headers).unwrap_or(Value::Null),
+        request_body: r.get(7)?,
+        response_body: r.get(8)?,
+    })
+}
+
+const SAMPLE_COLUMNS: &str =
+    "id, created_at, request_id, method, path, status, request_headers, request_body, response_body";
+
+/// Newest samples first, optionally only for paths starting with `path_prefix`
+pub fn list(db: &Database, path_prefix: Option<&str>, limit: i64) -> Result<Vec<DebugSample>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM debug_samples WHERE ?1 IS NULL OR substr(path, 1, length(?1)) = ?1
+         ORDER BY id DESC LIMIT ?2",
+        SAMPLE_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![path_prefix, limit], sample_from_row)?;
+    rows.collect()
+}
+
+pub fn get(db: &Database, id: i64) -> Result<Option<DebugSample>, rusqlite::Error> {
+    db.conn().query_row(
+        &format!("SELECT {} FROM debug_samples WHERE id = ?1", SAMPLE_COLUMNS),
+        params![id],
+        sample_from_row,
+    )
+    .optional()
+}
+
+/// Delete samples older than the retention window; returns the count
+pub fn purge_expired(db: &Database, retention_seconds: i64) -> Result<usize, rusqlite::Error> {
+    db.conn().execute(
+        "DELETE FROM debug_samples WHERE created_at < ?1",
+        params![Database::now_ts() - retention_seconds],
+    )
+}
+
+pub fn spawn_retention(db: Arc<Database>, leader: Arc<LeaderElection>, retention_seconds: i64, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("debug_sample_retention", every) {
+                continue;
+            }
+            match purge_expired(&db, retention_seconds) {
+                Ok(0) => {}
+                Ok(n) => info!("Debug sample retention removed {} samples", n),
+                Err(e) => warn!("Debug sample retention failed: {}", e),
+            }
+        }
+    });
+}
+
+/// State for the [`sample`] middleware
+#[derive(Clone)]
+pub struct DebugSampler {
+    pub db: Arc<Database>,
+    pub cfg: DebugSamplingConfig,
+}
+
+/// Record a share of failed requests with their redacted bodies.
+///
+/// The sampling decision is made up front so that unsampled requests are
+/// streamed through untouched; only requests with a known, small enough
+/// body are buffered.
+pub async fn sample(State(sampler): State<DebugSampler>, request: Request, next: Next) -> Response {
+    let cfg = &sampler.cfg;
+    if !cfg.enabled || rand::random::<f64>() * 100.0 >= cfg.sample_percent {
+        return next.run(request).await;
+    }
+    let headers = request.headers();
+    let body_len = match headers.get(header::CONTENT_LENGTH) {
+        Some(v) => v.to_str().ok().and_then(|v| v.parse::<usize>().ok()),
+        // streamed bodies of unknown size are not buffered
+        None if headers.contains_key(header::TRANSFER_ENCODING) => None,
+        None => Some(0),
+    };
+    if body_len.is_none_or(|len| len > cfg.max_body_bytes) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let request_bytes = match to_bytes(body, cfg.max_body_bytes).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("debug sampling could not buffer request body: {}", e);
+            return (StatusCode::BAD_REQUEST, "invalid request body").into_response();
+        }
+    };
+    let path = match parts.uri.query() {
+        Some(query) => format!("{}?{}", parts.uri.path(), redact_query(query)),
+        None => parts.uri.path().to_string(),
+    };
+    let mut sample = NewSample {
+        request_id: parts.extensions.get::<RequestId>().map(|id| id.0.clone()),
+        method: parts.method.to_string(),
+        path,
+        status: 0,
+        request_headers: recorded_headers(&parts.headers),
+        request_body: redact_body(&request_bytes),
+        response_body: None,
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(request_bytes))).await;
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let response_bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("debug sampling could not buffer response body: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    sample.status = status.as_u16();
+    sample.response_body = if response_bytes.len() > cfg.max_body_bytes {
+        Some(format!("[{} bytes, not recorded]", response_bytes.len()))
+    } else {
+        redact_body(&response_bytes)
+    };
+    if let Err(e) = record(&sampler.db, &sample) {
+        warn!("Failed to record debug sample: {}", e);
+    }
+    Response::from_parts(parts, Body::from(response_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_but_payload_shape_is_kept() {
+        let body = br#"{"email":"a@example.com","code":"123456","response":{"id":"cred","clientDataJSON":"eyJ0"},"tokens":["x"]}"#;
+        let redacted: Value = serde_json::from_str(&redact_body(body).unwrap()).unwrap();
+        assert_eq!(redacted["email"], REDACTED);
+        assert_eq!(redacted["code"], REDACTED);
+        assert_eq!(redacted["tokens"], REDACTED);
+        assert_eq!(redacted["response"]["clientDataJSON"], "eyJ0");
+        assert_eq!(redact_body(b"not json"), Some("[8 bytes, not JSON]".to_string()));
+        assert_eq!(redact_query("token=abc&client=web"), "token=[redacted]&client=web");
+    }
+}
@@ -0,0 +1,211 @@
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    TokioAsyncResolver,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// What to do when the recipient domain cannot receive mail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Enforcement {
+    /// No DNS lookups
+    #[default]
+    Off,
+    /// Look up and log undeliverable domains, but send anyway
+    Warn,
+    /// Reject the request with `UNDELIVERABLE_DOMAIN`
+    Reject,
+}
+
+/// `[deliverability]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeliverabilityConfig {
+    #[serde(default)]
+    pub enforcement: Enforcement,
+    /// How long a lookup result is reused for the same domain
+    #[serde(default = "default_cache_ttl_seconds")]
+    pub cache_ttl_seconds: u64,
+    /// Domains kept in the cache at most
+    #[serde(default = "default_cache_capacity")]
+    pub cache_capacity: usize,
+    /// DNS lookups slower than this count as unknown (mail is sent)
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for DeliverabilityConfig {
+    fn default() -> Self {
+        Self {
+            enforcement: Enforcement::Off,
+            cache_ttl_seconds: default_cache_ttl_seconds(),
+            cache_capacity: default_cache_capacity(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+}
+
+fn default_cache_ttl_seconds() -> u64 {
+    3600
+}
+
+fn default_cache_capacity() -> usize {
+    10000
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+/// Result of checking a recipient domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Deliverable,
+    /// The domain definitely cannot receive mail; the reason is logged
+    Undeliverable(&'static str),
+}
+
+/// Lowercased domain part of an address, without a trailing root dot
+pub fn domain_of(email: &str) -> Option<String> {
+    let (_, domain) = email.rsplit_once('@')?;
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    (!domain.is_empty()).then_some(domain)
+}
+
+/// Classify a domain's MX records as `(preference, exchange)` pairs
+pub fn classify_mx(records: &[(u16, String)]) -> Verdict {
+    match records {
+        [] => Verdict::Undeliverable("no MX records"),
+        // RFC 7505 null MX: the domain explicitly accepts no mail
+        [(_, exchange)] if exchange.is_empty() || exchange == "." => Verdict::Undeliverable("null MX"),
+        _ => Verdict::Deliverable,
+    }
+}
+
+fn no_records(e: &ResolveError) -> bool {
+    matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+/// MX pre-check for magic link recipients, with a small per-domain cache.
+///
+/// Only definite answers (NXDOMAIN, no mail host, null MX) make a domain
+/// undeliverable. Timeouts and resolver failures fail open so a DNS outage
+/// never blocks sign-in.
+pub struct MxChecker {
+    cfg: DeliverabilityConfig,
+    resolver: Option<TokioAsyncResolver>,
+    cache: Mutex<HashMap<String, (Verdict, Instant)>>,
+}
+
+impl MxChecker {
+    pub fn new(cfg: &DeliverabilityConfig) -> Self {
+        let resolver = (cfg.enforcement != Enforcement::Off).then(|| {
+            let (config, mut opts) = hickory_resolver::system_conf::read_system_conf().unwrap_or_else(|e| {
+                warn!("Could not read system DNS config ({}), using public resolvers", e);
+                (ResolverConfig::default(), ResolverOpts::default())
+            });
+            opts.timeout = Duration::from_millis(cfg.timeout_ms);
+            opts.attempts = 1;
+            TokioAsyncResolver::tokio(config, opts)
+        });
+        Self {
+            cfg: cfg.clone(),
+            resolver,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enforcement(&self) -> Enforcement {
+        self.cfg.enforcement
+    }
+
+    fn cached(&self, domain: &str) -> Option<Verdict> {
+        let cache = self.cache.lock().unwrap();
+        let (verdict, at) = cache.get(domain)?;
+        (at.elapsed() < Duration::from_secs(self.cfg.cache_ttl_seconds)).then(|| verdict.clone())
+    }
+
+    fn remember(&self, domain: String, verdict: Verdict) {
+        let ttl = Duration::from_secs(self.cfg.cache_ttl_seconds);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.cfg.cache_capacity {
+            cache.retain(|_, (_, at)| at.elapsed() < ttl);
+            if cache.len() >= self.cfg.cache_capacity {
+                cache.clear();
+            }
+        }
+        cache.insert(domain, (verdict, Instant::now()));
+    }
+
+    /// `None` when the answer is unknown (lookup failed or timed out)
+    async fn lookup(&self, resolver: &TokioAsyncResolver, domain: &str) -> Option<Verdict> {
+        // fully qualified so search domains from resolv.conf are not appended
+        let fqdn = format!("{}.", domain);
+        let timeout = Duration::from_millis(self.cfg.timeout_ms);
+        let mx = match tokio::time::timeout(timeout, resolver.mx_lookup(fqdn.as_str())).await {
+            Ok(Ok(mx)) => mx,
+            Ok(Err(e)) if no_records(&e) => {
+                // RFC 5321 §5.1: without MX records the domain itself is the mail host
+                return match tokio::time::timeout(timeout, resolver.lookup_ip(fqdn.as_str())).await {
+                    Ok(Ok(ips)) if ips.iter().next().is_some() => Some(Verdict::Deliverable),
+                    Ok(Ok(_)) => Some(Verdict::Undeliverable("no MX or address records")),
+                    Ok(Err(e)) if no_records(&e) => Some(Verdict::Undeliverable("no MX or address records")),
+                    Ok(Err(e)) => {
+                        warn!("Address lookup for {} failed: {}", domain, e);
+                        None
+                    }
+                    Err(_) => None,
+                };
+            }
+            Ok(Err(e)) => {
+                warn!("MX lookup for {} failed: {}", domain, e);
+                return None;
+            }
+            Err(_) => {
+                warn!("MX lookup for {} timed out", domain);
+                return None;
+            }
+        };
+        let records: Vec<(u16, String)> = mx.iter().map(|r| (r.preference(), r.exchange().to_utf8())).collect();
+        Some(classify_mx(&records))
+    }
+
+    /// Check the recipient's domain. Always `Deliverable` when enforcement
+    /// is off, the address has no domain, or DNS gave no definite answer.
+    pub async fn check(&self, email: &str) -> Verdict {
+        let (Some(resolver), Some(domain)) = (&self.resolver, domain_of(email)) else {
+            return Verdict::Deliverable;
+        };
+        if let Some(verdict) = self.cached(&domain) {
+            return verdict;
+        }
+        let Some(verdict) = self.lookup(resolver, &domain).await else {
+            return Verdict::Deliverable;
+        };
+        if let Verdict::Undeliverable(reason) = &verdict {
+            info!("Recipient domain {} is undeliverable: {}", domain, reason);
+        }
+        self.remember(domain, verdict.clone());
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_mx_and_empty_answers_are_undeliverable() {
+        assert_eq!(classify_mx(&[]), Verdict::Undeliverable("no MX records"));
+        assert_eq!(classify_mx(&[(0, ".".to_string())]), Verdict::Undeliverable("null MX"));
+        assert_eq!(classify_mx(&[(10, "mx1.example.com.".to_string())]), Verdict::Deliverable);
+        assert_eq!(domain_of("User@Example.COM."), Some("example.com".to_string()));
+        assert_eq!(domain_of("no-at-sign"), None);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::crypto::Sha256Hasher;
+use crate::db::Database;
+use crate::user_agent;
+use axum::http::HeaderMap;
+use data_encoding::HEXLOWER;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+/// JSON header set by the client SDK: `{"consent": true, "screen": "1920x1080", "locale": "en-US", "timezone": "Europe/Berlin"}`
+pub const CLIENT_HINTS_HEADER: &str = "x-client-hints";
+
+/// Browser/device characteristics captured at sign-in.
+///
+/// Nothing is collected unless the client SDK reports the user's consent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientHints {
+    pub user_agent: Option<String>,
+    /// `Sec-CH-UA-Platform`
+    pub platform: Option<String>,
+    /// `Sec-CH-UA-Platform-Version`
+    pub platform_version: Option<String>,
+    /// `Sec-CH-UA` brand list
+    pub brands: Option<String>,
+    /// `Sec-CH-UA-Mobile`
+    pub mobile: Option<bool>,
+    /// `Sec-CH-UA-Model`
+    pub model: Option<String>,
+    pub screen: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SdkHints {
+    #[serde(default)]
+    consent: bool,
+    /// `PublicKeyCredential.isUserVerifyingPlatformAuthenticatorAvailable()`
+    #[serde(default)]
+    platform_authenticator: bool,
+    screen: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().trim_matches('"').to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Whether the client reports a platform authenticator. This is a capability,
+/// not a fingerprinting signal, so it is read without consent and never stored.
+pub fn platform_authenticator(headers: &HeaderMap) -> bool {
+    header(headers, CLIENT_HINTS_HEADER)
+        .and_then(|h| serde_json::from_str::<SdkHints>(&h).ok())
+        .is_some_and(|h| h.platform_authenticator)
+}
+
+impl ClientHints {
+    /// Collect hints from UA-CH headers and the SDK header, or `None`
+    /// when the client has not signalled consent
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let sdk: SdkHints = serde_json::from_str(&header(headers, CLIENT_HINTS_HEADER)?).ok()?;
+        if !sdk.consent {
+            return None;
+        }
+        Some(Self {
+            user_agent: header(headers, "user-agent"),
+            platform: header(headers, "sec-ch-ua-platform"),
+            platform_version: header(headers, "sec-ch-ua-platform-version"),
+            brands: headers
+                .get("sec-ch-ua")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            mobile: header(headers, "sec-ch-ua-mobile").map(|v| v == "?1"),
+            model: header(headers, "sec-ch-ua-model"),
+            screen: sdk.screen,
+            locale: sdk.locale.or_else(|| {
+                header(headers, "accept-language").and_then(|l| l.split(',').next().map(str::to_string))
+            }),
+            timezone: sdk.timezone,
+        })
+    }
+
+    /// Stable device identifier. Version numbers are left out so browser and
+    /// OS updates do not make a known device look new.
+    pub fn fingerprint(&self) -> String {
+        let agent = self.user_agent.as_deref().map(user_agent::parse).unwrap_or_default();
+        let parts = [
+            self.platform.as_deref(),
+            self.mobile.map(|m| if m { "mobile" } else { "desktop" }),
+            self.model.as_deref(),
+            self.screen.as_deref(),
+            self.locale.as_deref(),
+            self.timezone.as_deref(),
+            agent.browser.as_deref(),
+            agent.os.as_deref(),
+        ];
+        let mut hasher = Sha256Hasher::new();
+        for part in parts {
+            hasher.update(part.unwrap_or("").as_bytes());
+            hasher.update([0u8]);
+        }
+        HEXLOWER.encode(&hasher.finalize())
+    }
+}
+
+/// Device signal for a sign-in, consumed by risk scoring
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSignal {
+    pub fingerprint: String,
+    /// First sign-in from this device for the user
+    pub new_device: bool,
+    pub hints: ClientHints,
+}
+
+/// Known device for a user
+#[derive(Debug, Clone, Serialize)]
+pub struct KnownDevice {
+    pub fingerprint: String,
+    pub hints: ClientHints,
+    pub first_seen_at: i64,
+    pub last_seen_at: i64,
+}
+
+/// Attach hints to a new session and update the user's known devices.
+/// Pass the transaction that creates the session.
+pub fn record(
+    conn: &Connection,
+    user_id: &str,
+    session_id: &str,
+    hints: &ClientHints,
+) -> Result<DeviceSignal, rusqlite::Error> {
+    let fingerprint = hints.fingerprint();
+    let json = serde_json::to_string(hints).unwrap_or_default();
+    let now = Database::now_ts();
+    let known: Option<i64> = conn.query_row(
+            "SELECT first_seen_at FROM user_devices WHERE user_id = ?1 AND fingerprint = ?2",
+            params![user_id, fingerprint],
+            |r| r.get(0),
+        )
+        .optional()?;
+    conn.execute(
+        "INSERT INTO user_devices (user_id, fingerprint, hints, first_seen_at, last_seen_at) VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT(user_id, fingerprint) DO UPDATE SET hints = excluded.hints, last_seen_at = excluded.last_seen_at",
+        params![user_id, fingerprint, json, now],
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO session_devices (session_id, user_id, fingerprint, hints, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![session_id, user_id, fingerprint, json, now],
+    )?;
+    Ok(DeviceSignal {
+        fingerprint,
+        new_device: known.is_none(),
+        hints: hints.clone(),
+    })
+}
+
+/// Hints captured when the session was created
+pub fn for_session(db: &Database, session_id: &str) -> Result<Option<ClientHints>, rusqlite::Error> {
+    let hints: Option<String> = db
+        .conn()
+        .query_row(
+            "SELECT hints FROM session_devices WHERE session_id = ?1",
+            params![session_id],
+            |r| r.get(0),
+        )
+        .optional()?;
+    Ok(hints.and_then(|h| serde_json::from_str(&h).ok()))
+}
+
+/// Devices the user has signed in from, most recent first
+pub fn known_devices(db: &Database, user_id: &str) -> Result<Vec<KnownDevice>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT fingerprint, hints, first_seen_at, last_seen_at FROM user_devices WHERE user_id = ?1 ORDER BY last_seen_at DESC",
+    )?;
+    let rows = stmt.query_map(params![user_id], |r| {
+        Ok(KnownDevice {
+            fingerprint: r.get(0)?,
+            hints: serde_json::from_str(&r.get::<_, String>(1)?).unwrap_or_default(),
+            first_seen_at: r.get(2)?,
+            last_seen_at: r.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
@@ -0,0 +1,310 @@
+//! OAuth 2.0 device authorization grant (RFC 8628).
+//!
+//! Headless clients (CLIs, TVs) cannot follow a magic link or run a WebAuthn
+//! ceremony. They call `POST /device/code` and show the user a short user code
+//! and the verification URI. The user opens the URI on a phone or laptop where
+//! they are signed in and approves the code, while the device polls
+//! `POST /device/token` until it receives tokens for a new session of that
+//! user. Device codes are stored as their SHA-256 and redeemed once; codes
+//! nobody approved expire after `[device_authorization] code_ttl_seconds`.
+
+use crate::{crypto, db::Database, service::AuthResponse};
+use rand::{Rng, RngCore};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+/// Consonants only (RFC 8628 §6.1): codes cannot spell words and contain no
+/// characters that are easily confused
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+const USER_CODE_LEN: usize = 8;
+
+/// Added to a device's polling interval each time it polls too fast
+pub const SLOW_DOWN_SECONDS: i64 = 5;
+
+/// `[device_authorization]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeviceAuthorizationConfig {
+    /// Off: the `/device` endpoints answer 404
+    #[serde(default)]
+    pub enabled: bool,
+    /// Page users open to enter their code; this service's `GET /device`
+    /// unless an application hosts its own
+    #[serde(default = "default_verification_uri")]
+    pub verification_uri: String,
+    /// How long a user has to approve a code, and the device to redeem it
+    #[serde(default = "default_code_ttl_seconds")]
+    pub code_ttl_seconds: i64,
+    /// Seconds devices wait between polls; polling faster gets `slow_down`
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: i64,
+}
+
+impl Default for DeviceAuthorizationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            verification_uri: default_verification_uri(),
+            code_ttl_seconds: default_code_ttl_seconds(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+fn default_verification_uri() -> String {
+    "http://localhost:3000/device".to_string()
+}
+
+fn default_code_ttl_seconds() -> i64 {
+    600
+}
+
+fn default_interval_seconds() -> i64 {
+    5
+}
+
+/// Response to `POST /device/code` (RFC 8628 §3.2)
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    /// `WDJB-MJHT`; typed by the user at `verification_uri`
+    pub user_code: String,
+    pub verification_uri: String,
+    /// `verification_uri` with the user code filled in, e.g. for a QR code
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+/// A code waiting for the user's decision
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingDevice {
+    pub user_code: String,
+    pub client_id: String,
+    pub expires_at: i64,
+}
+
+/// Outcome of one `POST /device/token` poll
+#[derive(Debug, Clone, PartialEq)]
+pub enum Poll {
+    /// Not decided yet (`authorization_pending`)
+    Pending,
+    /// Polled before the interval passed, which grows by [`SLOW_DOWN_SECONDS`]
+    SlowDown,
+    /// The user denied the request (`access_denied`)
+    Denied,
+    /// The codes expired (`expired_token`)
+    Expired,
+    /// Approved by this user; the device code is used up
+    Approved(String),
+}
+
+/// Token response of `POST /device/token`: the RFC 6749 §5.1 fields next to
+/// the usual login response
+#[derive(Debug, Serialize)]
+pub struct DeviceTokens {
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    #[serde(flatten)]
+    pub tokens: AuthResponse,
+}
+
+impl DeviceTokens {
+    pub fn new(tokens: AuthResponse, now: i64) -> Self {
+        Self {
+            token_type: "Bearer",
+            expires_in: (tokens.expires_at - now).max(0),
+            tokens,
+        }
+    }
+}
+
+/// Upper case without separators or spaces, as stored
+pub fn normalize_user_code(input: &str) -> String {
+    input.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+/// `WDJBMJHT` as shown to users: `WDJB-MJHT`
+pub fn format_user_code(code: &str) -> String {
+    if code.len() == USER_CODE_LEN {
+        format!("{}-{}", &code[..USER_CODE_LEN / 2], &code[USER_CODE_LEN / 2..])
+    } else {
+        code.to_string()
+    }
+}
+
+fn new_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..USER_CODE_LEN)
+        .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn new_device_code() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    data_encoding::BASE64URL_NOPAD.encode(&bytes)
+}
+
+/// Start a device authorization for `client_id`; expired ones are dropped
+/// on the way
+pub fn start(
+    db: &Database,
+    cfg: &DeviceAuthorizationConfig,
+    client_id: &str,
+    now: i64,
+) -> Result<DeviceAuthorization, rusqlite::Error> {
+    db.conn().execute("DELETE FROM device_authorizations WHERE expires_at <= ?1", params![now])?;
+    let device_code = new_device_code();
+    let mut attempts = 0;
+    let user_code = loop {
+        let user_code = new_user_code();
+        let inserted = db.conn().execute(
+            "INSERT INTO device_authorizations
+                 (device_code_hash, user_code, client_id, interval_seconds, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                crypto::token_digest(&device_code),
+                user_code,
+                client_id,
+                cfg.interval_seconds,
+                now,
+                now + cfg.code_ttl_seconds,
+            ],
+        );
+        match inserted {
+            Ok(_) => break format_user_code(&user_code),
+            // a live user code was drawn again
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == rusqlite::ErrorCode::ConstraintViolation && attempts < 3 =>
+            {
+                attempts += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    let verification_uri_complete = match reqwest::Url::parse(&cfg.verification_uri) {
+        Ok(mut url) => {
+            url.query_pairs_mut().append_pair("user_code", &user_code);
+            url.to_string()
+        }
+        Err(_) => format!("{}?user_code={}", cfg.verification_uri, user_code),
+    };
+    Ok(DeviceAuthorization {
+        device_code,
+        user_code,
+        verification_uri: cfg.verification_uri.clone(),
+        verification_uri_complete,
+        expires_in: cfg.code_ttl_seconds,
+        interval: cfg.interval_seconds,
+    })
+}
+
+/// The undecided, unexpired request with this user code (in any spelling)
+pub fn pending(db: &Database, user_code: &str, now: i64) -> Result<Option<PendingDevice>, rusqlite::Error> {
+    let user_code = normalize_user_code(user_code);
+    db.conn()
+        .query_row(
+            "SELECT client_id, expires_at FROM device_authorizations
+             WHERE user_code = ?1 AND status = 'pending' AND expires_at > ?2",
+            params![user_code, now],
+            |r| {
+                Ok(PendingDevice {
+                    user_code: format_user_code(&user_code),
+                    client_id: r.get(0)?,
+                    expires_at: r.get(1)?,
+                })
+            },
+        )
+        .optional()
+}
+
+/// Record the signed-in user's decision; `None` when the code is unknown,
+/// expired or already decided
+pub fn review(
+    db: &Database,
+    user_code: &str,
+    user_id: &str,
+    approve: bool,
+    now: i64,
+) -> Result<Option<PendingDevice>, rusqlite::Error> {
+    let Some(pending) = pending(db, user_code, now)? else {
+        return Ok(None);
+    };
+    let status = if approve { "approved" } else { "denied" };
+    let updated = db.conn().execute(
+        "UPDATE device_authorizations SET status = ?1, user_id = ?2
+         WHERE user_code = ?3 AND status = 'pending' AND expires_at > ?4",
+        params![status, user_id, normalize_user_code(user_code), now],
+    )?;
+    Ok((updated > 0).then_some(pending))
+}
+
+/// One poll by the device; `None` when the device code is unknown, already
+/// redeemed or belongs to another client
+pub fn poll(db: &Database, device_code: &str, client_id: &str, now: i64) -> Result<Option<Poll>, rusqlite::Error> {
+    let hash = crypto::token_digest(device_code);
+    let row = db
+        .conn()
+        .query_row(
+            "SELECT client_id, status, user_id, interval_seconds, last_polled_at, expires_at
+             FROM device_authorizations WHERE device_code_hash = ?1",
+            params![hash],
+            |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, Option<String>>(2)?,
+                    r.get::<_, i64>(3)?,
+                    r.get::<_, Option<i64>>(4)?,
+                    r.get::<_, i64>(5)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((owner, status, user_id, interval, last_polled_at, expires_at)) = row else {
+        return Ok(None);
+    };
+    if owner != client_id {
+        return Ok(None);
+    }
+    let forget = || db.conn().execute("DELETE FROM device_authorizations WHERE device_code_hash = ?1", params![hash]);
+    if expires_at <= now {
+        forget()?;
+        return Ok(Some(Poll::Expired));
+    }
+    match (status.as_str(), user_id) {
+        ("approved", Some(user_id)) => {
+            // only one of two concurrent polls deletes the row and gets tokens
+            Ok((forget()? > 0).then_some(Poll::Approved(user_id)))
+        }
+        ("denied", _) => {
+            forget()?;
+            Ok(Some(Poll::Denied))
+        }
+        _ => {
+            let too_fast = last_polled_at.is_some_and(|at| now - at < interval);
+            db.conn().execute(
+                "UPDATE device_authorizations SET last_polled_at = ?1, interval_seconds = ?2
+                 WHERE device_code_hash = ?3",
+                params![now, if too_fast { interval + SLOW_DOWN_SECONDS } else { interval }, hash],
+            )?;
+            Ok(Some(if too_fast { Poll::SlowDown } else { Poll::Pending }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_codes_are_typed_loosely() {
+        let code = new_user_code();
+        assert_eq!(code.len(), USER_CODE_LEN);
+        assert!(code.bytes().all(|c| USER_CODE_ALPHABET.contains(&c)));
+        assert_eq!(format_user_code("WDJBMJHT"), "WDJB-MJHT");
+        assert_eq!(normalize_user_code(" wdjb-mjht "), "WDJBMJHT");
+        assert_eq!(normalize_user_code(&format_user_code(&code)), code);
+    }
+}
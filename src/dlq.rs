@@ -0,0 +1,162 @@
+use crate::db::Database;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Queues that dead-letter items after exhausting their retries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterQueue {
+    /// `email_queue` rows with status `dead`
+    Email,
+    /// Outbox webhook deliveries with `dead_at` set
+    Webhook,
+}
+
+impl DeadLetterQueue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Webhook => "webhook",
+        }
+    }
+}
+
+/// Dead-lettered item, addressed as `<queue>:<id>` (e.g. `email:3f2a...`, `webhook:42`)
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub id: String,
+    pub queue: DeadLetterQueue,
+    /// Recipient address or webhook event name
+    pub target: String,
+    pub summary: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub dead_at: Option<i64>,
+}
+
+/// Parsed `<queue>:<id>` identifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetterId {
+    pub queue: DeadLetterQueue,
+    pub id: String,
+}
+
+impl FromStr for DeadLetterId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (queue, id) = s.split_once(':').ok_or(())?;
+        let queue = match queue {
+            "email" => DeadLetterQueue::Email,
+            "webhook" => DeadLetterQueue::Webhook,
+            _ => return Err(()),
+        };
+        if id.is_empty() {
+            return Err(());
+        }
+        Ok(Self {
+            queue,
+            id: id.to_string(),
+        })
+    }
+}
+
+/// List dead-lettered items, newest first, optionally for one queue
+pub fn list(
+    db: &Database,
+    queue: Option<DeadLetterQueue>,
+    limit: i64,
+) -> Result<Vec<DeadLetter>, rusqlite::Error> {
+    let mut items = Vec::new();
+
+    if queue.is_none_or(|q| q == DeadLetterQueue::Email) {
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, to_email, subject, attempts, last_error, created_at, dead_at
+             FROM email_queue WHERE status = 'dead' ORDER BY dead_at DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit], |r| {
+            Ok(DeadLetter {
+                id: format!("email:{}", r.get::<_, String>(0)?),
+                queue: DeadLetterQueue::Email,
+                target: r.get(1)?,
+                summary: r.get(2)?,
+                attempts: r.get(3)?,
+                last_error: r.get(4)?,
+                created_at: r.get(5)?,
+                dead_at: r.get(6)?,
+            })
+        })?;
+        for row in rows {
+            items.push(row?);
+        }
+    }
+
+    if queue.is_none_or(|q| q == DeadLetterQueue::Webhook) {
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, webhook_event, event_id, attempts, last_error, created_at, dead_at
+             FROM outbox WHERE dead_at IS NOT NULL AND delivered_at IS NULL ORDER BY dead_at DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit], |r| {
+            Ok(DeadLetter {
+                id: format!("webhook:{}", r.get::<_, i64>(0)?),
+                queue: DeadLetterQueue::Webhook,
+                target: r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                summary: format!("event {}", r.get::<_, String>(2)?),
+                attempts: r.get(3)?,
+                last_error: r.get(4)?,
+                created_at: r.get(5)?,
+                dead_at: r.get(6)?,
+            })
+        })?;
+        for row in rows {
+            items.push(row?);
+        }
+    }
+
+    items.sort_by_key(|item| std::cmp::Reverse(item.dead_at));
+    items.truncate(limit.max(0) as usize);
+    Ok(items)
+}
+
+/// Put one dead-lettered item back on its queue with a fresh retry budget.
+/// Returns false when no such dead item exists.
+pub fn requeue(db: &Database, id: &DeadLetterId) -> Result<bool, rusqlite::Error> {
+    let now = Database::now_ts();
+    let updated = match id.queue {
+        DeadLetterQueue::Email => db.conn().execute(
+            "UPDATE email_queue SET status = 'pending', attempts = 0, next_try_at = ?1, dead_at = NULL
+             WHERE id = ?2 AND status = 'dead'",
+            params![now, id.id],
+        )?,
+        DeadLetterQueue::Webhook => db.conn().execute(
+            "UPDATE outbox SET attempts = 0, next_attempt_at = ?1, dead_at = NULL
+             WHERE id = ?2 AND dead_at IS NOT NULL AND delivered_at IS NULL",
+            params![now, id.id],
+        )?,
+    };
+    Ok(updated > 0)
+}
+
+/// Requeue every dead-lettered item, optionally for one queue; returns the count
+pub fn requeue_all(db: &Database, queue: Option<DeadLetterQueue>) -> Result<usize, rusqlite::Error> {
+    let now = Database::now_ts();
+    let mut total = 0;
+    if queue.is_none_or(|q| q == DeadLetterQueue::Email) {
+        total += db.conn().execute(
+            "UPDATE email_queue SET status = 'pending', attempts = 0, next_try_at = ?1, dead_at = NULL WHERE status = 'dead'",
+            params![now],
+        )?;
+    }
+    if queue.is_none_or(|q| q == DeadLetterQueue::Webhook) {
+        total += db.conn().execute(
+            "UPDATE outbox SET attempts = 0, next_attempt_at = ?1, dead_at = NULL
+             WHERE dead_at IS NOT NULL AND delivered_at IS NULL",
+            params![now],
+        )?;
+    }
+    Ok(total)
+}
@@ -0,0 +1,330 @@
+use crate::{
+    config::{Config, Profile},
+    crypto,
+    db::{Database, MIGRATIONS},
+    email::Emailer,
+    jwt::{KeyRing, SigningAlgorithm},
+    link_urls::{self, LinkKind},
+    redirects::RedirectRule,
+};
+use reqwest::Url;
+use std::{collections::HashMap, fmt};
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warn,
+    /// The server should not start with this configuration
+    Fatal,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Results of a configuration doctor run
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, name: &'static str, severity: Severity, message: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            severity,
+            message: message.into(),
+        });
+    }
+
+    pub fn has_fatal(&self) -> bool {
+        self.checks.iter().any(|c| c.severity == Severity::Fatal)
+    }
+
+    /// Checks that did not pass
+    pub fn problems(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| c.severity != Severity::Ok)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let tag = match check.severity {
+                Severity::Ok => "ok",
+                Severity::Warn => "WARN",
+                Severity::Fatal => "FATAL",
+            };
+            writeln!(f, "[{:>5}] {:<12} {}", tag, check.name, check.message)?;
+        }
+        let fatal = self.checks.iter().filter(|c| c.severity == Severity::Fatal).count();
+        let warn = self.checks.iter().filter(|c| c.severity == Severity::Warn).count();
+        write!(f, "{} checks, {} warnings, {} fatal", self.checks.len(), warn, fatal)
+    }
+}
+
+/// Validate configuration coherence and the database. `full` additionally
+/// contacts the SMTP server (used by `--doctor`; startup skips it).
+pub fn run(cfg: &Config, db: &Database, full: bool) -> Report {
+    let mut report = Report::default();
+    check_webauthn(cfg, &mut report);
+    check_base_urls(cfg, &mut report);
+    check_jwt_secret(cfg, &mut report);
+    check_jwt_signing(cfg, &mut report);
+    check_key_rotation(cfg, &mut report);
+    check_crypto(cfg, &mut report);
+    check_redirect_uris(cfg, &mut report);
+    check_sessions(cfg, &mut report);
+    check_profile(cfg, &mut report);
+    check_database(db, &mut report);
+    check_migrations(db, &mut report);
+    if full {
+        check_smtp(cfg, &mut report);
+    }
+    report
+}
+
+fn check_webauthn(cfg: &Config, report: &mut Report) {
+    let Ok(origin) = Url::parse(&cfg.webauthn_origin) else {
+        report.push("webauthn", Severity::Fatal, format!("webauthn_origin {:?} is not a URL", cfg.webauthn_origin));
+        return;
+    };
+    let host = origin.host_str().unwrap_or_default();
+    let rp_id = cfg.webauthn_rp_id.as_str();
+    if host == rp_id || host.ends_with(&format!(".{}", rp_id)) {
+        report.push("webauthn", Severity::Ok, format!("rp_id {} matches origin host {}", rp_id, host));
+    } else {
+        report.push(
+            "webauthn",
+            Severity::Fatal,
+            format!("rp_id {} is not the origin host {} or a parent domain of it", rp_id, host),
+        );
+    }
+}
+
+fn check_base_urls(cfg: &Config, report: &mut Report) {
+    let mut urls = vec![
+        ("magic_link_base_url", cfg.magic_link_base_url.as_str()),
+        ("action_link_base_url", cfg.action_link_base_url.as_str()),
+        ("webauthn_origin", cfg.webauthn_origin.as_str()),
+    ];
+    // the global link URLs may be left empty once [[link_base_urls]] registers them
+    urls.retain(|(_, url)| !url.is_empty());
+    urls.extend(link_urls::registered(cfg, cfg.profile));
+    for (key, value) in urls {
+        match Url::parse(value) {
+            Err(_) => report.push("base_urls", Severity::Fatal, format!("{} {:?} is not a URL", key, value)),
+            Ok(url) if url.scheme() != "https" && !cfg.dev_mode && cfg.profile != Some(Profile::Dev) => {
+                let local = matches!(url.host_str(), Some("localhost" | "127.0.0.1"));
+                let severity = if local { Severity::Warn } else { Severity::Fatal };
+                report.push("base_urls", severity, format!("{} must use https in production ({})", key, value));
+            }
+            Ok(_) => report.push("base_urls", Severity::Ok, format!("{} ok", key)),
+        }
+    }
+    let clients = std::iter::once(None).chain(cfg.applications.iter().map(|a| Some(a.client_id.as_str())));
+    for client in clients {
+        for kind in [LinkKind::Magic, LinkKind::Action] {
+            if let Err(e) = link_urls::resolve(cfg, kind, client) {
+                report.push("base_urls", Severity::Warn, format!("emails would fail: {}", e));
+            }
+        }
+    }
+}
+
+fn check_redirect_uris(cfg: &Config, report: &mut Report) {
+    let mut rules = 0;
+    for app in &cfg.applications {
+        for rule in &app.allowed_redirect_uris {
+            match RedirectRule::parse(rule) {
+                Ok(_) => rules += 1,
+                Err(e) => report.push("redirects", Severity::Fatal, format!("{}: {}", app.client_id, e)),
+            }
+        }
+    }
+    if rules > 0 {
+        report.push("redirects", Severity::Ok, format!("{} redirect rules valid", rules));
+    }
+}
+
+fn check_sessions(cfg: &Config, report: &mut Report) {
+    let sessions = &cfg.sessions;
+    if !sessions.is_eventual() {
+        return;
+    }
+    if sessions.log_retention_seconds <= cfg.refresh_token_expiry_seconds {
+        report.push(
+            "sessions",
+            Severity::Warn,
+            "log_retention_seconds does not outlast refresh_token_expiry_seconds; revoked sessions could come back",
+        );
+    }
+    match &sessions.sync_token {
+        None if !sessions.peers.is_empty() => {
+            report.push("sessions", Severity::Fatal, "peers are set but sync_token is not; revocations would not sync")
+        }
+        None => report.push("sessions", Severity::Warn, "eventual mode without peers or sync_token"),
+        Some(token) if token.len() < 32 => {
+            report.push("sessions", Severity::Warn, format!("sync_token is only {} characters", token.len()))
+        }
+        Some(_) => report.push(
+            "sessions",
+            Severity::Ok,
+            format!("region {} syncing with {} peers", sessions.region, sessions.peers.len()),
+        ),
+    }
+}
+
+fn check_profile(cfg: &Config, report: &mut Report) {
+    let Some(profile) = cfg.profile else {
+        return;
+    };
+    let dev_settings = cfg.dev_settings();
+    if dev_settings.is_empty() {
+        report.push("profile", Severity::Ok, format!("{} profile", profile.as_str()));
+    }
+    for setting in dev_settings {
+        report.push("profile", Severity::Warn, format!("prod profile with {}", setting));
+    }
+}
+
+fn check_crypto(cfg: &Config, report: &mut Report) {
+    if let Err(e) = crypto::self_test() {
+        report.push("crypto", Severity::Fatal, format!("{} self-test failed: {}", crypto::PROVIDER, e));
+    } else if let Some(e) = cfg.require_fips.then(crypto::check_fips_boundary).and_then(Result::err) {
+        report.push("crypto", Severity::Fatal, format!("require_fips is set but {}", e));
+    } else {
+        report.push("crypto", Severity::Ok, format!("{} passed its self-test", crypto::PROVIDER));
+    }
+}
+
+/// Estimated entropy in bits from the secret's length and character distribution
+pub fn estimate_entropy_bits(secret: &str) -> f64 {
+    let len = secret.chars().count() as f64;
+    if len == 0.0 {
+        return 0.0;
+    }
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in secret.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let per_char: f64 = counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+    per_char * len
+}
+
+fn check_jwt_secret(cfg: &Config, report: &mut Report) {
+    let secret = &cfg.jwt_secret;
+    let bits = estimate_entropy_bits(secret);
+    if secret.len() < 32 {
+        report.push("jwt_secret", Severity::Fatal, format!("only {} characters (minimum 32)", secret.len()));
+    } else if secret == "supersecretandlongenoughforhs256" {
+        let severity = if cfg.dev_mode { Severity::Warn } else { Severity::Fatal };
+        report.push("jwt_secret", severity, "still the example value from config.toml");
+    } else if bits < 128.0 {
+        report.push("jwt_secret", Severity::Warn, format!("low entropy (~{:.0} bits, want 128+)", bits));
+    } else {
+        report.push("jwt_secret", Severity::Ok, format!("~{:.0} bits of entropy", bits));
+    }
+}
+
+fn check_jwt_signing(cfg: &Config, report: &mut Report) {
+    let algorithm = cfg.jwt_signing.algorithm;
+    if algorithm == SigningAlgorithm::Hs256 {
+        if !cfg.key_rotation.enabled {
+            report.push("jwt_signing", Severity::Ok, "HS256 with jwt_secret; resource servers need the secret");
+        }
+        return;
+    }
+    if let Err(e) = KeyRing::load(cfg) {
+        report.push("jwt_signing", Severity::Fatal, e.to_string());
+    } else if cfg.key_publication.overlap_seconds < cfg.access_token_expiry_seconds {
+        // tokens of a retired key would fail at resource servers before they expire
+        let message = format!(
+            "[key_publication] overlap_seconds ({}) is shorter than access_token_expiry_seconds ({})",
+            cfg.key_publication.overlap_seconds, cfg.access_token_expiry_seconds
+        );
+        report.push("jwt_signing", Severity::Warn, message);
+    } else {
+        let message = format!("{} with {} key(s)", algorithm.as_str(), cfg.jwt_signing.keys.len());
+        report.push("jwt_signing", Severity::Ok, message);
+    }
+}
+
+fn check_key_rotation(cfg: &Config, report: &mut Report) {
+    let rotation = &cfg.key_rotation;
+    if !rotation.enabled {
+        return;
+    }
+    let publication = &cfg.key_publication;
+    if publication.overlap_seconds < cfg.access_token_expiry_seconds {
+        let message = format!(
+            "[key_publication] overlap_seconds ({}) is shorter than access_token_expiry_seconds ({})",
+            publication.overlap_seconds, cfg.access_token_expiry_seconds
+        );
+        report.push("key_rotation", Severity::Warn, message);
+    } else if rotation.interval_seconds <= publication.prepublish_seconds {
+        // each successor would be generated before the previous one signs
+        let message = format!(
+            "interval_seconds ({}) is not longer than [key_publication] prepublish_seconds ({})",
+            rotation.interval_seconds, publication.prepublish_seconds
+        );
+        report.push("key_rotation", Severity::Warn, message);
+    } else {
+        let message = format!("ES256 keys rotated every {} seconds", rotation.interval_seconds);
+        report.push("key_rotation", Severity::Ok, message);
+    }
+}
+
+fn check_database(db: &Database, report: &mut Report) {
+    // BEGIN IMMEDIATE takes the write lock without changing anything
+    match db.conn().execute_batch("BEGIN IMMEDIATE; ROLLBACK;") {
+        Ok(()) => report.push("database", Severity::Ok, "writable"),
+        Err(e) => report.push("database", Severity::Fatal, format!("not writable: {}", e)),
+    }
+}
+
+fn check_migrations(db: &Database, report: &mut Report) {
+    match db.pending_migrations() {
+        Ok(pending) if pending.is_empty() => {
+            report.push("migrations", Severity::Ok, format!("{} migrations applied", MIGRATIONS.len()))
+        }
+        Ok(pending) => {
+            let names: Vec<&str> = pending.iter().map(|m| m.name).collect();
+            report.push("migrations", Severity::Fatal, format!("pending: {}", names.join(", ")));
+        }
+        Err(e) => report.push("migrations", Severity::Fatal, format!("cannot read the applied migrations: {}", e)),
+    }
+}
+
+fn check_smtp(cfg: &Config, report: &mut Report) {
+    if cfg.email_from.parse::<lettre::message::Mailbox>().is_err() {
+        report.push("smtp", Severity::Fatal, format!("email_from {:?} is not a valid address", cfg.email_from));
+        return;
+    }
+    match Emailer::new(cfg).test_connection() {
+        Ok(true) => report.push("smtp", Severity::Ok, format!("connected to {}:{}", cfg.smtp_host, cfg.smtp_port)),
+        Ok(false) => report.push("smtp", Severity::Fatal, format!("{}:{} refused the connection", cfg.smtp_host, cfg.smtp_port)),
+        Err(e) => report.push("smtp", Severity::Fatal, format!("{}:{} failed: {}", cfg.smtp_host, cfg.smtp_port, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entropy_rewards_varied_secrets() {
+        assert!(estimate_entropy_bits("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa") < 1.0);
+        assert!(estimate_entropy_bits("k3J9x!Qz7Lp2Vw8Rt5Yb1Nc6Md4Hf0Gs") > 128.0);
+    }
+}
@@ -1,7 +1,83 @@
-use crate::config::Config;
+use crate::abuse_reports::AbuseReportConfig;
+use crate::address::EmailAddress;
+use crate::config::{Config, EmailDelivery};
+use crate::email_templates::RenderedEmail;
 use lettre::message::{header, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::client::SmtpConnection;
+use lettre::transport::smtp::extension::{ClientId, Extension};
+use lettre::transport::smtp::PoolConfig;
 use lettre::{Message, SmtpTransport, Transport};
+use crate::resilience::{CallError, CircuitBreaker};
+use crate::{exemplars, metrics::MetricsRecorder};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+/// `X-Auth-Flow` header carrying a login flow's correlation id
+#[derive(Debug, Clone)]
+struct AuthFlow(String);
+
+impl header::Header for AuthFlow {
+    fn name() -> header::HeaderName {
+        header::HeaderName::new_from_ascii_str(crate::magic_link::FLOW_HEADER)
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_string()))
+    }
+
+    fn display(&self) -> header::HeaderValue {
+        header::HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+/// `[smtp_pool]` configuration: pooled keep-alive connections and a cap on
+/// concurrent sends (the circuit breaker lives under `[resilience.smtp]`)
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmtpPoolConfig {
+    /// Connections kept alive and reused between messages
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    /// Idle pooled connections are closed after this long
+    #[serde(default = "default_idle_timeout_seconds")]
+    pub idle_timeout_seconds: u64,
+    /// Per-command SMTP timeout
+    #[serde(default = "default_send_timeout_seconds")]
+    pub send_timeout_seconds: u64,
+    /// How long a send waits for a free slot before failing fast
+    #[serde(default = "default_acquire_timeout_ms")]
+    pub acquire_timeout_ms: u64,
+}
+
+impl Default for SmtpPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: default_max_connections(),
+            idle_timeout_seconds: default_idle_timeout_seconds(),
+            send_timeout_seconds: default_send_timeout_seconds(),
+            acquire_timeout_ms: default_acquire_timeout_ms(),
+        }
+    }
+}
+
+fn default_max_connections() -> u32 {
+    4
+}
+
+fn default_idle_timeout_seconds() -> u64 {
+    60
+}
+
+fn default_send_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_acquire_timeout_ms() -> u64 {
+    5000
+}
 
 #[derive(Debug, Error)]
 pub enum EmailError {
@@ -9,12 +85,35 @@ pub enum EmailError {
     Build(#[from] lettre::error::Error),
     #[error("failed to send email: {0}")]
     Send(#[from] lettre::transport::smtp::Error),
+    #[error("invalid recipient address: {0}")]
+    Address(lettre::address::AddressError),
+    #[error("recipient address needs SMTPUTF8, which the smtp server does not support")]
+    Smtputf8Unsupported,
+    #[error("smtp circuit open, not attempting delivery")]
+    CircuitOpen,
+    #[error("timed out waiting for a free smtp connection")]
+    Busy,
+    #[error("smtp send task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
 }
 
+/// SMTP client; clones share the connection pool, send slots and breaker
+#[derive(Clone)]
 pub struct Emailer {
     mailer: SmtpTransport,
     from: Mailbox,
-    base_link: String,
+    /// `[abuse_reports]`, for the "wasn't me" link in magic link emails
+    abuse_reports: AbuseReportConfig,
+    /// Bounds concurrent SMTP sends to the pool size
+    slots: Arc<Semaphore>,
+    breaker: Arc<CircuitBreaker>,
+    acquire_timeout: Duration,
+    smtp_host: String,
+    smtp_port: u16,
+    /// SMTPUTF8 support, probed once on first need
+    smtputf8: Arc<Mutex<Option<bool>>>,
+    /// `email_delivery = "log"`: log messages instead of sending them
+    log_only: bool,
 }
 
 impl Emailer {
@@ -23,10 +122,18 @@ impl Emailer {
             cfg.smtp_username.clone(),
             cfg.smtp_password.clone(),
         );
+        let pool = &cfg.smtp_pool;
+        // pooled connections are checked with NOOP before reuse and dropped when dead
         let mailer = SmtpTransport::starttls_relay(&cfg.smtp_host)
             .unwrap()
             .port(cfg.smtp_port)
             .credentials(creds)
+            .timeout(Some(Duration::from_secs(pool.send_timeout_seconds)))
+            .pool_config(
+                PoolConfig::new()
+                    .max_size(pool.max_connections)
+                    .idle_timeout(Duration::from_secs(pool.idle_timeout_seconds)),
+            )
             .build();
         let from = cfg
             .email_from
@@ -35,23 +142,128 @@ impl Emailer {
         Self {
             mailer,
             from,
-            base_link: cfg.magic_link_base_url.clone(),
+            abuse_reports: cfg.abuse_reports.clone(),
+            slots: Arc::new(Semaphore::new(pool.max_connections.max(1) as usize)),
+            breaker: Arc::new(CircuitBreaker::new("smtp", cfg.dependency_policy("smtp"))),
+            acquire_timeout: Duration::from_millis(pool.acquire_timeout_ms),
+            smtp_host: cfg.smtp_host.clone(),
+            smtp_port: cfg.smtp_port,
+            smtputf8: Arc::new(Mutex::new(None)),
+            log_only: cfg.email_delivery == EmailDelivery::Log,
         }
     }
 
-    pub fn send_magic_link(&self, to_email: &str, token: &str) -> Result<(), EmailError> {
-        let magic_url = format!("{}?token={}", self.base_link, token);
+    /// Whether the SMTP server advertises SMTPUTF8 (RFC 6531), needed for
+    /// non-ASCII local parts. Read from the EHLO response on first use, on
+    /// the blocking pool, and cached; an unreachable server is assumed
+    /// capable and not cached, so the actual send decides.
+    pub async fn supports_smtputf8(&self) -> bool {
+        if let Some(known) = *self.smtputf8.lock().unwrap() {
+            return known;
+        }
+        let (host, port) = (self.smtp_host.clone(), self.smtp_port);
+        let probe = tokio::task::spawn_blocking(move || {
+            let mut conn = SmtpConnection::connect(
+                (host.as_str(), port),
+                Some(Duration::from_secs(5)),
+                &ClientId::default(),
+                None,
+                None,
+            )?;
+            let supported = conn.server_info().supports_feature(Extension::SmtpUtfEight);
+            let _ = conn.quit();
+            Ok::<_, lettre::transport::smtp::Error>(supported)
+        })
+        .await;
+        match probe {
+            Ok(Ok(supported)) => {
+                *self.smtputf8.lock().unwrap() = Some(supported);
+                supported
+            }
+            _ => true,
+        }
+    }
+
+    /// Open (and close) a connection to the SMTP server to check reachability
+    pub fn test_connection(&self) -> Result<bool, EmailError> {
+        Ok(self.mailer.test_connection()?)
+    }
+
+    /// Send a magic link under `base_url` (see [`crate::link_urls`]);
+    /// `flow_id` is set as the `X-Auth-Flow` header
+    pub async fn send_magic_link(
+        &self,
+        to_email: &str,
+        base_url: &str,
+        token: &str,
+        flow_id: &str,
+    ) -> Result<(), EmailError> {
+        let magic_url = format!("{}?token={}", base_url, token);
         let subject = "Your Magic Login Link";
-        let html_body = format!(
+        let mut html_body = format!(
             "<p>Click the link to login (valid for a short time):<br/><a href=\"{0}\">{0}</a></p>",
             magic_url
         );
-        let text_body = format!("Login: {}", magic_url);
+        let mut text_body = format!("Login: {}", magic_url);
+        if let Some(report_url) = self.abuse_reports.link(token) {
+            html_body.push_str(&format!(
+                "<p>Didn't request this? <a href=\"{}\">This wasn't me</a></p>",
+                report_url
+            ));
+            text_body.push_str(&format!("\n\nDidn't request this? Report it: {}", report_url));
+        }
 
-        let email = Message::builder()
+        self.send_in_flow(to_email, subject, text_body, html_body, Some(flow_id)).await
+    }
+
+    /// Send output of an `EmailTemplates` renderer
+    pub async fn send_rendered(&self, to_email: &str, email: RenderedEmail) -> Result<(), EmailError> {
+        self.send(to_email, &email.subject, email.text, email.html).await
+    }
+
+    /// Send a multipart (text + HTML) email
+    pub async fn send(
+        &self,
+        to_email: &str,
+        subject: &str,
+        text_body: String,
+        html_body: String,
+    ) -> Result<(), EmailError> {
+        self.send_in_flow(to_email, subject, text_body, html_body, None).await
+    }
+
+    /// Send a multipart email belonging to a login flow, tagged `X-Auth-Flow`
+    /// so mail logs and bounces can be traced back to it
+    async fn send_in_flow(
+        &self,
+        to_email: &str,
+        subject: &str,
+        text_body: String,
+        html_body: String,
+        flow_id: Option<&str>,
+    ) -> Result<(), EmailError> {
+        if self.log_only {
+            info!(to = to_email, subject = subject, flow_id = flow_id, "Email not sent (email_delivery = \"log\"):\n{}", text_body);
+            return Ok(());
+        }
+        // punycode domain on the envelope so only non-ASCII local parts need SMTPUTF8
+        let to = match EmailAddress::parse(to_email) {
+            Ok(address) => {
+                if address.requires_smtputf8() && !self.supports_smtputf8().await {
+                    return Err(EmailError::Smtputf8Unsupported);
+                }
+                address.to_string()
+            }
+            Err(_) => to_email.to_string(),
+        };
+        let mut builder = Message::builder()
             .from(self.from.clone())
-            .to(to_email.parse().unwrap())
-            .subject(subject)
+            .to(to.parse().map_err(EmailError::Address)?)
+            .subject(subject);
+        if let Some(flow_id) = flow_id {
+            builder = builder.header(AuthFlow(flow_id.to_string()));
+        }
+        let email = builder
             .multipart(MultiPart::alternative() // This is composed of two parts.
                 .singlepart(
                     SinglePart::builder()
@@ -65,7 +277,29 @@ impl Emailer {
                 ),
             )?;
 
-        self.mailer.send(&email)?;
-        Ok(())
+        let Ok(Ok(slot)) = tokio::time::timeout(self.acquire_timeout, self.slots.acquire()).await else {
+            return Err(EmailError::Busy);
+        };
+        let started = Instant::now();
+        // each attempt runs on the blocking pool (lettre's SMTP transport
+        // blocks); the breaker waits between retries without holding a worker
+        let result = self
+            .breaker
+            .call_async(|| {
+                let (mailer, email) = (self.mailer.clone(), email.clone());
+                async move { Ok::<_, EmailError>(tokio::task::spawn_blocking(move || mailer.send(&email)).await??) }
+            })
+            .await;
+        drop(slot);
+        MetricsRecorder::record_email_send_duration(
+            started.elapsed().as_secs_f64(),
+            exemplars::current_trace_id().as_deref(),
+        );
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(CallError::Open) => Err(EmailError::CircuitOpen),
+            Err(CallError::Failed(e)) => Err(e),
+        }
     }
 }
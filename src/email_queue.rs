@@ -1,33 +1,38 @@
 use crate::db::Database;
-use crate::email::Emailer;
+use crate::suppression;
 use rusqlite::params;
 use uuid::Uuid;
-use chrono::{Utc, Duration};
 use thiserror::Error;
-use std::thread;
-use std::time::Duration as StdDuration;
-use tracing::{info, error};
 
 #[derive(Debug, Error)]
 pub enum QueueError {
     #[error("db error: {0}")]
     Db(#[from] rusqlite::Error),
+    #[error("recipient is on the suppression list")]
+    Suppressed,
 }
 
+/// Emails are dead-lettered after this many failed attempts
+pub const MAX_ATTEMPTS: i64 = 8;
+
 pub struct EmailQueue;
 
 impl EmailQueue {
+    /// Queue an email; returns its id
     pub fn enqueue(
         db: &Database,
         to_email: &str,
         subject: &str,
         body_text: &str,
         body_html: Option<&str>,
-    ) -> Result<(), QueueError> {
+    ) -> Result<String, QueueError> {
+        if suppression::is_suppressed(db, to_email)? {
+            return Err(QueueError::Suppressed);
+        }
         let id = Uuid::new_v4().to_string();
         let now = Database::now_ts();
         let next_try_at = now;
-        db.conn.execute(
+        db.conn().execute(
             "INSERT INTO email_queue (id, to_email, subject, body_text, body_html, attempts, next_try_at, created_at, status) VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, ?7, 'pending')",
             params![
                 id,
@@ -39,12 +44,13 @@ impl EmailQueue {
                 now
             ],
         )?;
-        Ok(())
+        Ok(id)
     }
 
     pub fn fetch_due(db: &Database, limit: i64) -> Result<Vec<EmailTask>, QueueError> {
         let now = Database::now_ts();
-        let mut stmt = db.conn.prepare(
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
             "SELECT id, to_email, subject, body_text, body_html, attempts FROM email_queue WHERE status IN ('pending','failed') AND next_try_at <= ?1 ORDER BY created_at ASC LIMIT ?2",
         )?;
         let mut rows = stmt.query(params![now, limit])?;
@@ -64,7 +70,7 @@ impl EmailQueue {
     }
 
     pub fn mark_sending(db: &Database, id: &str) -> Result<(), QueueError> {
-        db.conn.execute(
+        db.conn().execute(
             "UPDATE email_queue SET status='sending' WHERE id=?1",
             params![id],
         )?;
@@ -73,7 +79,7 @@ impl EmailQueue {
 
     pub fn mark_sent(db: &Database, id: &str) -> Result<(), QueueError> {
         let now = Database::now_ts();
-        db.conn.execute(
+        db.conn().execute(
             "UPDATE email_queue SET status='sent', sent_at=?1 WHERE id=?2",
             params![now, id],
         )?;
@@ -81,9 +87,16 @@ impl EmailQueue {
     }
 
     pub fn mark_failed(db: &Database, id: &str, err: &str, attempts: i64) -> Result<(), QueueError> {
+        if attempts >= MAX_ATTEMPTS {
+            db.conn().execute(
+                "UPDATE email_queue SET status='dead', last_error=?1, attempts=?2, dead_at=?3 WHERE id=?4",
+                params![err, attempts, Database::now_ts(), id],
+            )?;
+            return Ok(());
+        }
         let backoff = 60 * 2_i64.pow(attempts as u32); // exponential backoff in seconds
         let next_try_at = Database::now_ts() + backoff;
-        db.conn.execute(
+        db.conn().execute(
             "UPDATE email_queue SET status='failed', last_error=?1, attempts=?2, next_try_at=?3 WHERE id=?4",
             params![err, attempts, next_try_at, id],
         )?;
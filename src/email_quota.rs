@@ -0,0 +1,110 @@
+//! Per-user email budget over sliding windows.
+//!
+//! Every magic link and action link emailed to a user is recorded in the
+//! `email_sends` table. Before another one is sent, each `[[email_quota.limits]]`
+//! window is counted; once a window is full the request fails with
+//! `EMAIL_QUOTA_EXCEEDED` and the time the oldest send in it ages out. The
+//! budget lives in the database, so it holds across restarts and replicas.
+//! Security notices are not counted and never refused.
+
+use crate::db::Database;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+
+/// `[email_quota]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmailQuotaConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Windows checked on every send; all of them must have room
+    #[serde(default = "default_limits")]
+    pub limits: Vec<Limit>,
+}
+
+impl Default for EmailQuotaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            limits: default_limits(),
+        }
+    }
+}
+
+/// At most `max` emails per `window_seconds`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub window_seconds: i64,
+    pub max: i64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_limits() -> Vec<Limit> {
+    vec![
+        Limit {
+            window_seconds: 3600,
+            max: 10,
+        },
+        Limit {
+            window_seconds: 86400,
+            max: 30,
+        },
+    ]
+}
+
+/// A full window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exceeded {
+    pub limit: Limit,
+    /// When the next email may be sent (unix seconds)
+    pub reset_at: i64,
+}
+
+/// Record an email to `user_id` at `now` if every window has room;
+/// otherwise the window that stays full longest
+pub fn reserve(
+    db: &Database,
+    cfg: &EmailQuotaConfig,
+    user_id: &str,
+    now: i64,
+) -> Result<Option<Exceeded>, rusqlite::Error> {
+    if !cfg.enabled || cfg.limits.is_empty() {
+        return Ok(None);
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let longest = cfg.limits.iter().map(|l| l.window_seconds).max().unwrap_or(0);
+    tx.execute(
+        "DELETE FROM email_sends WHERE user_id = ?1 AND sent_at <= ?2",
+        params![user_id, now - longest],
+    )?;
+    let mut exceeded: Option<Exceeded> = None;
+    for limit in &cfg.limits {
+        // the send that has to age out before the window has room again
+        let blocking: Option<i64> = tx
+            .query_row(
+                "SELECT sent_at FROM email_sends WHERE user_id = ?1 AND sent_at > ?2
+                 ORDER BY sent_at DESC LIMIT 1 OFFSET ?3",
+                params![user_id, now - limit.window_seconds, limit.max.max(1) - 1],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if let Some(sent_at) = blocking {
+            let reset_at = sent_at + limit.window_seconds;
+            if exceeded.is_none_or(|e| reset_at > e.reset_at) {
+                exceeded = Some(Exceeded { limit: *limit, reset_at });
+            }
+        }
+    }
+    if exceeded.is_none() {
+        tx.execute(
+            "INSERT INTO email_sends (user_id, sent_at) VALUES (?1, ?2)",
+            params![user_id, now],
+        )?;
+    }
+    tx.commit()?;
+    Ok(exceeded)
+}
+
@@ -1,3 +1,7 @@
+use crate::action_links::ActionPurpose;
+use crate::address;
+use crate::html::escape;
+use crate::security_notices::{NoticeKind, SecurityNotice};
 use serde::Serialize;
 
 /// Email template data for magic link
@@ -16,12 +20,21 @@ pub struct TotpEnrollTemplateData {
     pub qr_code_url: String,
 }
 
+/// A rendered email: subject with plain-text and HTML bodies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
 /// Email template renderer
 pub struct EmailTemplates;
 
 impl EmailTemplates {
     /// Render magic link email
-    pub fn magic_link(email: &str, token: &str, base_url: &str, expiry_seconds: i64) -> (String, String) {
+    pub fn magic_link(email: &str, token: &str, base_url: &str, expiry_seconds: i64) -> RenderedEmail {
+        let email = address::display(email);
         let magic_link = format!("{}?token={}", base_url, token);
         let expiry_minutes = expiry_seconds / 60;
 
@@ -99,14 +112,19 @@ The Passwordless Auth Team"#,
     </div>
 </body>
 </html>"#,
-            email, magic_link, magic_link, expiry_minutes
+            escape(&email), magic_link, magic_link, expiry_minutes
         );
 
-        (subject, format!("{}\n\n---HTML---\n\n{}", text_body, html_body))
+        RenderedEmail {
+            subject,
+            text: text_body,
+            html: html_body,
+        }
     }
 
     /// Render TOTP enrollment email
-    pub fn totp_enrollment(email: &str, secret: &str, otpauth_url: &str) -> (String, String) {
+    pub fn totp_enrollment(email: &str, secret: &str, otpauth_url: &str) -> RenderedEmail {
+        let email = address::display(email);
         let subject = "Two-Factor Authentication Enabled".to_string();
 
         let text_body = format!(
@@ -181,14 +199,19 @@ The Passwordless Auth Team"#,
     </div>
 </body>
 </html>"#,
-            email, secret, otpauth_url
+            escape(&email), secret, escape(otpauth_url)
         );
 
-        (subject, format!("{}\n\n---HTML---\n\n{}", text_body, html_body))
+        RenderedEmail {
+            subject,
+            text: text_body,
+            html: html_body,
+        }
     }
 
     /// Render session revocation notification
-    pub fn session_revoked(email: &str) -> (String, String) {
+    pub fn session_revoked(email: &str) -> RenderedEmail {
+        let email = address::display(email);
         let subject = "Your session has been revoked".to_string();
 
         let text_body = format!(
@@ -244,9 +267,399 @@ The Passwordless Auth Team"#,
     </div>
 </body>
 </html>"#,
+            escape(&email)
+        );
+
+        RenderedEmail {
+            subject,
+            text: text_body,
+            html: html_body,
+        }
+    }
+
+    /// Render the security notice sent after a TOTP secret is replaced
+    pub fn totp_rotated(email: &str) -> RenderedEmail {
+        let email = address::display(email);
+        let subject = "Your authenticator app was changed";
+
+        let text_body = format!(
+            r#"Hi {},
+
+The authenticator app (TOTP) for your account was just replaced. Codes from your previous authenticator no longer work.
+
+If this wasn't you, please contact support immediately.
+
+Thanks,
+The Passwordless Auth Team"#,
             email
         );
 
-        (subject, format!("{}\n\n---HTML---\n\n{}", text_body, html_body))
+        let html_body = wrap_html(
+            subject,
+            &format!(
+                r#"<h2>Authenticator changed</h2>
+        <p>Hi {},</p>
+        <p>The authenticator app (TOTP) for your account was just replaced. Codes from your previous authenticator no longer work.</p>
+        <p><strong>If this wasn't you, please contact support immediately.</strong></p>"#,
+                escape(&email)
+            ),
+        );
+
+        RenderedEmail {
+            subject: subject.to_string(),
+            text: text_body,
+            html: html_body,
+        }
+    }
+
+    /// Render the notice sent by a forced credential reset, naming the second
+    /// factors that were removed
+    pub fn credential_reset(email: &str, totp_removed: bool, passkeys_removed: bool) -> RenderedEmail {
+        let email = address::display(email);
+        let subject = "Please sign in again";
+        let removed = match (totp_removed, passkeys_removed) {
+            (true, true) => Some("your passkeys and authenticator app (TOTP)"),
+            (false, true) => Some("your passkeys"),
+            (true, false) => Some("your authenticator app (TOTP)"),
+            (false, false) => None,
+        };
+        let reenroll = removed.map_or(String::new(), |factors| {
+            format!(
+                " We also removed {} from your account; please set them up again after signing in.",
+                factors
+            )
+        });
+
+        let text_body = format!(
+            r#"Hi {},
+
+As a security precaution, we signed your account out everywhere and canceled any sign-in links we sent earlier.{}
+
+Request a new sign-in link to continue. If you have questions, please contact support.
+
+Thanks,
+The Passwordless Auth Team"#,
+            email, reenroll
+        );
+
+        let html_body = wrap_html(
+            subject,
+            &format!(
+                r#"<h2>Please sign in again</h2>
+        <p>Hi {},</p>
+        <p>As a security precaution, we signed your account out everywhere and canceled any sign-in links we sent earlier.{}</p>
+        <p>Request a new sign-in link to continue. If you have questions, please contact support.</p>"#,
+                escape(&email), reenroll
+            ),
+        );
+
+        RenderedEmail {
+            subject: subject.to_string(),
+            text: text_body,
+            html: html_body,
+        }
+    }
+
+    /// Render the notice to an account flagged as inactive, saying when it
+    /// will be disabled (`disable_in_days`) and whether it is deleted later
+    pub fn stale_account(
+        email: &str,
+        inactive_days: i64,
+        disable_in_days: Option<i64>,
+        deleted_later: bool,
+    ) -> RenderedEmail {
+        let email = address::display(email);
+        let subject = "Are you still using your account?";
+        let consequence = match (disable_in_days, deleted_later) {
+            (Some(days), true) => format!(
+                " If you don't sign in within {} days, we will disable your account and later delete it.",
+                days
+            ),
+            (Some(days), false) => format!(" If you don't sign in within {} days, we will disable your account.", days),
+            (None, _) => String::new(),
+        };
+
+        let text_body = format!(
+            r#"Hi {},
+
+You haven't signed in for {} days.{}
+
+To keep your account, simply request a sign-in link and sign in. If you no longer need it, you can ignore this email.
+
+Thanks,
+The Passwordless Auth Team"#,
+            email, inactive_days, consequence
+        );
+
+        let html_body = wrap_html(
+            subject,
+            &format!(
+                r#"<h2>Are you still using your account?</h2>
+        <p>Hi {},</p>
+        <p>You haven't signed in for {} days.{}</p>
+        <p>To keep your account, simply request a sign-in link and sign in. If you no longer need it, you can ignore this email.</p>"#,
+                escape(&email), inactive_days, consequence
+            ),
+        );
+
+        RenderedEmail {
+            subject: subject.to_string(),
+            text: text_body,
+            html: html_body,
+        }
+    }
+
+    /// Render a security notice with one-click revoke (when the change happened
+    /// in a session) and freeze links
+    pub fn security_notice(
+        notice: &SecurityNotice,
+        email: &str,
+        revoke_link: Option<&str>,
+        freeze_link: &str,
+    ) -> RenderedEmail {
+        let email = address::display(email);
+        let detail = notice.detail.as_deref().unwrap_or("an unknown location");
+        let (subject, intro) = match notice.kind {
+            NoticeKind::NewDevice => (
+                "New sign-in to your account",
+                format!("Your account was just signed in to from a new device: {}.", detail),
+            ),
+            NoticeKind::NewCountry => (
+                "Sign-in from a new country",
+                format!("Your account was just signed in to from {}, a country you have not signed in from before.", detail),
+            ),
+            NoticeKind::PasskeyAdded => (
+                "A passkey was added to your account",
+                "A new passkey was just added to your account. It can be used to sign in without an email link.".to_string(),
+            ),
+            NoticeKind::TotpDisabled => (
+                "Two-factor authentication was turned off",
+                "The authenticator app (TOTP) was just removed from your account.".to_string(),
+            ),
+            NoticeKind::RecoveryCodesRegenerated => (
+                "Your recovery codes were regenerated",
+                "New recovery codes were just generated for your account. Your previous codes no longer work.".to_string(),
+            ),
+        };
+        let intro = match &notice.device {
+            Some(device) if notice.kind == NoticeKind::NewCountry => format!("{} The sign-in used {}.", intro, device),
+            _ => intro,
+        };
+
+        let mut actions_text = String::new();
+        let mut actions_html = String::new();
+        if let Some(link) = revoke_link {
+            actions_text.push_str(&format!("Sign out that session: {}\n", link));
+            actions_html.push_str(&format!(r#"<a href="{}" class="button">Sign Out That Session</a> "#, link));
+        }
+        actions_text.push_str(&format!("Freeze your account: {}", freeze_link));
+        actions_html.push_str(&format!(r#"<a href="{}" class="button" style="background-color: #dc3545;">Freeze My Account</a>"#, freeze_link));
+
+        let text_body = format!(
+            r#"Hi {},
+
+{}
+
+If this was you, no action is needed. If it wasn't, act now:
+
+{}
+
+Freezing signs you out everywhere and blocks new sign-ins until support unfreezes your account.
+
+Thanks,
+The Passwordless Auth Team"#,
+            email, intro, actions_text
+        );
+
+        let html_body = wrap_html(
+            subject,
+            &format!(
+                r#"<h2>{}</h2>
+        <p>Hi {},</p>
+        <p>{}</p>
+        <p>If this was you, no action is needed. <strong>If it wasn't, act now:</strong></p>
+        <p>{}</p>
+        <p style="font-size: 12px; color: #666;">Freezing signs you out everywhere and blocks new sign-ins until support unfreezes your account.</p>"#,
+                subject, escape(&email), escape(&intro), actions_html
+            ),
+        );
+
+        RenderedEmail {
+            subject: subject.to_string(),
+            text: text_body,
+            html: html_body,
+        }
+    }
+
+    /// Render a signed action link email
+    pub fn action_link(
+        purpose: ActionPurpose,
+        email: &str,
+        link: &str,
+        expiry_seconds: i64,
+    ) -> RenderedEmail {
+        let email = address::display(email);
+        let (subject, heading, intro, button) = match purpose {
+            ActionPurpose::VerifyEmail => (
+                "Verify your email address",
+                "Verify your email",
+                "Please confirm that this email address belongs to you.",
+                "Verify Email",
+            ),
+            ActionPurpose::RevokeSession => (
+                "Sign out a session",
+                "Sign out session",
+                "Use the button below to immediately sign out the session.",
+                "Sign Out Session",
+            ),
+            ActionPurpose::ConfirmEmailChange => (
+                "Confirm your new email address",
+                "Confirm email change",
+                "Please confirm you want to use this address for your account.",
+                "Confirm Email",
+            ),
+            ActionPurpose::FreezeAccount => (
+                "Freeze your account",
+                "Freeze account",
+                "Use the button below to sign out everywhere and block new sign-ins until support unfreezes your account.",
+                "Freeze Account",
+            ),
+        };
+        let expiry_minutes = expiry_seconds / 60;
+
+        let text_body = format!(
+            r#"Hi {},
+
+{}
+
+{}
+
+This link will expire in {} minutes and can only be used once.
+
+If you didn't expect this email, you can safely ignore it.
+
+Thanks,
+The Passwordless Auth Team"#,
+            email, intro, link, expiry_minutes
+        );
+
+        let html_body = wrap_html(
+            subject,
+            &format!(
+                r#"<h2>{}</h2>
+        <p>Hi {},</p>
+        <p>{}</p>
+        <a href="{}" class="button">{}</a>
+        <p style="word-break: break-all; font-size: 12px; color: #666;">{}</p>
+        <p><strong>This link will expire in {} minutes and can only be used once.</strong></p>
+        <p>If you didn't expect this email, you can safely ignore it.</p>"#,
+                heading, escape(&email), intro, link, button, link, expiry_minutes
+            ),
+        );
+
+        RenderedEmail {
+            subject: subject.to_string(),
+            text: text_body,
+            html: html_body,
+        }
+    }
+
+    /// Render an admin broadcast, replacing `{{email}}` in the subject and body;
+    /// blank lines in the body separate paragraphs
+    pub fn broadcast(subject: &str, body: &str, email: &str) -> RenderedEmail {
+        let email = address::display(email);
+        let subject = subject.replace(BROADCAST_EMAIL_PLACEHOLDER, &email);
+        let body = body.replace(BROADCAST_EMAIL_PLACEHOLDER, &email);
+
+        let text_body = format!("{}\n\nThanks,\nThe Passwordless Auth Team", body.trim_end());
+
+        let paragraphs: String = body
+            .split("\n\n")
+            .filter(|p| !p.trim().is_empty())
+            .map(|p| format!("<p>{}</p>", escape(p.trim()).replace('\n', "<br>")))
+            .collect::<Vec<_>>()
+            .join("\n        ");
+        let title = escape(&subject);
+        let html_body = wrap_html(&title, &format!("<h2>{}</h2>\n        {}", title, paragraphs));
+
+        RenderedEmail {
+            subject,
+            text: text_body,
+            html: html_body,
+        }
+    }
+}
+
+/// Placeholder for the recipient's address in broadcast templates
+pub const BROADCAST_EMAIL_PLACEHOLDER: &str = "{{email}}";
+
+/// Shared HTML layout for templates
+fn wrap_html(title: &str, content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{}</title>
+    <style>
+        body {{
+            font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, 'Helvetica Neue', Arial, sans-serif;
+            line-height: 1.6;
+            color: #333;
+            max-width: 600px;
+            margin: 0 auto;
+            padding: 20px;
+        }}
+        .container {{
+            background-color: #f9f9f9;
+            border-radius: 8px;
+            padding: 30px;
+            border: 1px solid #e0e0e0;
+        }}
+        .button {{
+            display: inline-block;
+            padding: 12px 24px;
+            background-color: #007bff;
+            color: white;
+            text-decoration: none;
+            border-radius: 4px;
+            margin: 20px 0;
+        }}
+        .footer {{
+            margin-top: 30px;
+            padding-top: 20px;
+            border-top: 1px solid #e0e0e0;
+            font-size: 12px;
+            color: #666;
+        }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        {}
+        <div class="footer">
+            <p>Thanks,<br>The Passwordless Auth Team</p>
+        </div>
+    </div>
+</body>
+</html>"#,
+        title, content
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn security_notices_escape_user_derived_fields() {
+        let notice = SecurityNotice::new(NoticeKind::NewDevice).detail("<img src=x onerror=alert(1)> on Windows");
+        let RenderedEmail { text, html, .. } =
+            EmailTemplates::security_notice(&notice, "a&b@example.com", None, "https://example.com/f");
+        assert!(text.contains("<img src=x onerror=alert(1)> on Windows"));
+        assert!(html.contains("&lt;img src=x onerror=alert(1)&gt; on Windows"));
+        assert!(html.contains("Hi a&amp;b@example.com,"));
+        assert!(!html.contains("<img"));
     }
 }
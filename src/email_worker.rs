@@ -1,8 +1,9 @@
 use passwordless_auth::{
-    config::Config,
+    config::{self, Config},
     db::Database,
     email::Emailer,
-    email_queue::{EmailQueue, EmailTask, QueueError},
+    email_queue::{EmailQueue, EmailTask},
+    secret_scan::{self, SecretScanner},
 };
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
@@ -10,14 +11,12 @@ use tracing::{info, error};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().init();
-    let cfg = Config::load("config.toml")?;
+    let cfg = Config::load(config::config_path())?;
+    secret_scan::install(SecretScanner::for_config(&cfg)?);
+    tracing_subscriber::fmt().with_writer(secret_scan::ScrubbedStdout).init();
     let db = Database::open(&cfg.database_path)?;
     // run migrations if needed
-    let migration_sql = std::fs::read_to_string("migrations/init.sql")?;
-    db.migrate(&migration_sql)?;
-    let extra_sql = std::fs::read_to_string("migrations/002_email_queue.sql")?;
-    db.migrate(&extra_sql)?;
+    db.apply_migrations()?;
 
     let emailer = Emailer::new(&cfg);
     let db = Arc::new(db);
@@ -42,7 +41,9 @@ async fn main() -> anyhow::Result<()> {
 
 async fn process(db: &Database, emailer: &Emailer, task: &EmailTask) -> Result<(), anyhow::Error> {
     EmailQueue::mark_sending(db, &task.id)?;
-    let send_result = emailer.send_magic_link(&task.to_email, &format!("{}", task.body_text));
+    let send_result = emailer
+        .send(&task.to_email, &task.subject, task.body_text.clone(), task.body_html.clone())
+        .await;
     match send_result {
         Ok(_) => {
             info!("sent queued email to {}", task.to_email);
@@ -3,6 +3,7 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use crate::secret_scan;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -22,17 +23,21 @@ pub struct ApiError {
 }
 
 impl ApiError {
+    /// The message and details are scrubbed of token-shaped values (see
+    /// `secret_scan`), as they often carry formatted upstream errors
     pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        let message = message.into();
         Self {
             code: code.into(),
-            message: message.into(),
+            message: secret_scan::scrub(&message).into_owned(),
             details: None,
             request_id: None,
         }
     }
 
     pub fn with_details(mut self, details: impl Into<String>) -> Self {
-        self.details = Some(details.into());
+        let details = details.into();
+        self.details = Some(secret_scan::scrub(&details).into_owned());
         self
     }
 
@@ -116,6 +121,17 @@ impl ApiError {
     pub fn validation_error(details: impl Into<String>) -> Self {
         Self::new("VALIDATION_ERROR", "Validation failed").with_details(details)
     }
+
+    pub fn undeliverable_domain(domain: impl Into<String>) -> Self {
+        Self::new("UNDELIVERABLE_DOMAIN", "Recipient domain cannot receive email").with_details(domain)
+    }
+
+    /// Tag a response whose body has another shape, so the v2 error
+    /// envelope (see `api_version`) still reports this code
+    pub fn attach(self, mut response: Response) -> Response {
+        response.extensions_mut().insert(self);
+        response
+    }
 }
 
 impl fmt::Display for ApiError {
@@ -168,7 +184,7 @@ impl ErrorResponse {
 
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> Response {
-        (self.status, Json(self.error)).into_response()
+        self.error.clone().attach((self.status, Json(self.error)).into_response())
     }
 }
 
@@ -0,0 +1,223 @@
+//! OpenMetrics exemplars linking latency histograms to traces.
+//!
+//! Auth requests carrying a sampled W3C `traceparent` header, as set by an
+//! OpenTelemetry-instrumented proxy or client, attach their trace id to the
+//! `auth_stage_duration_seconds` observations they cause and to the
+//! `email_send_duration_seconds` of emails sent while handling them. With
+//! `[exemplars] enabled`, `/metrics` answers scrapers asking for OpenMetrics
+//! with the latest exemplar of each histogram bucket, so a dashboard can jump
+//! from a latency spike to an example trace. The server exports no spans of
+//! its own; the trace is the caller's.
+
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// W3C trace context request header
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Media type of the OpenMetrics text format
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Histogram buckets, shared with the Prometheus exporter so that exemplars
+/// land in the buckets it renders
+pub const STAGE_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+pub const EMAIL_SEND_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// `[exemplars]` configuration
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ExemplarConfig {
+    /// Serve exemplars to scrapers that accept OpenMetrics
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Trace id of a sampled `traceparent` (`00-<trace id>-<parent id>-<flags>`)
+pub fn sampled_trace_id(traceparent: &str) -> Option<&str> {
+    let mut parts = traceparent.trim().split('-');
+    let (version, trace_id, parent_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    if version == "ff" || !hex(version, 2) || !hex(trace_id, 32) || !hex(parent_id, 16) || !hex(flags, 2) {
+        return None;
+    }
+    let sampled = u8::from_str_radix(flags, 16).ok()? & 1 == 1;
+    (sampled && trace_id.bytes().any(|b| b != b'0')).then_some(trace_id)
+}
+
+tokio::task_local! {
+    static TRACE_ID: Option<String>;
+}
+
+/// Run `f` with `trace_id` as the current request's trace
+pub async fn scope<F: Future>(trace_id: Option<String>, f: F) -> F::Output {
+    TRACE_ID.scope(trace_id, f).await
+}
+
+/// Trace id of the request being handled, if it was traced
+pub fn current_trace_id() -> Option<String> {
+    TRACE_ID.try_with(Clone::clone).ok().flatten()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    /// Unix seconds
+    timestamp: f64,
+}
+
+/// Latest exemplar per histogram bucket
+#[derive(Debug, Default)]
+pub struct Exemplars {
+    /// Keyed by metric, rendered labels (`stage="db_lookup"`) and bucket bound
+    latest: Mutex<HashMap<(&'static str, String, u64), Exemplar>>,
+}
+
+fn buckets(metric: &str) -> Option<&'static [f64]> {
+    match metric {
+        "auth_stage_duration_seconds" => Some(STAGE_BUCKETS),
+        "email_send_duration_seconds" => Some(EMAIL_SEND_BUCKETS),
+        _ => None,
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, v)).collect::<Vec<_>>().join(",")
+}
+
+impl Exemplars {
+    /// Remember `trace_id` as the example of the bucket `value` falls into
+    pub fn observe(&self, metric: &'static str, labels: &[(&str, &str)], value: f64, trace_id: &str, timestamp: f64) {
+        let Some(bounds) = buckets(metric) else { return };
+        let bound = bounds.iter().copied().find(|b| value <= *b).unwrap_or(f64::INFINITY);
+        let exemplar = Exemplar {
+            trace_id: trace_id.to_string(),
+            value,
+            timestamp,
+        };
+        let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        latest.insert((metric, render_labels(labels), bound.to_bits()), exemplar);
+    }
+
+    /// Convert a Prometheus text exposition to OpenMetrics, appending the
+    /// exemplar of each bucket line that has one. Counter families lose the
+    /// `_total` suffix their samples keep, as OpenMetrics requires.
+    pub fn annotate(&self, exposition: &str) -> String {
+        let counters: HashSet<&str> = exposition
+            .lines()
+            .filter_map(|l| l.strip_prefix("# TYPE ")?.strip_suffix(" counter")?.strip_suffix("_total"))
+            .collect();
+        let latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::with_capacity(exposition.len() + 64);
+        for line in exposition.lines() {
+            match Self::counter_metadata(line, &counters) {
+                Some((prefix, family, rest)) => out.push_str(&format!("{}{}{}", prefix, family, rest)),
+                None => out.push_str(line),
+            }
+            if let Some(exemplar) = Self::bucket(line).and_then(|key| latest.get(&key)) {
+                out.push_str(&format!(
+                    " # {{trace_id=\"{}\"}} {} {:.3}",
+                    exemplar.trace_id, exemplar.value, exemplar.timestamp
+                ));
+            }
+            out.push('\n');
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// `# TYPE`/`# HELP` line of a counter split around its family name
+    fn counter_metadata<'a>(line: &'a str, counters: &HashSet<&str>) -> Option<(&'a str, &'a str, &'a str)> {
+        let prefix = ["# TYPE ", "# HELP "].into_iter().find(|p| line.starts_with(p))?;
+        let (name, _) = line[prefix.len()..].split_once(' ')?;
+        let family = name.strip_suffix("_total").filter(|f| counters.contains(f))?;
+        Some((prefix, family, &line[prefix.len() + name.len()..]))
+    }
+
+    /// Metric, labels without `le` and bound of a histogram bucket line,
+    /// e.g. `auth_stage_duration_seconds_bucket{stage="db_lookup",le="0.01"} 3`
+    fn bucket(line: &str) -> Option<(&'static str, String, u64)> {
+        let (series, _) = line.split_once("_bucket{")?;
+        let metric =
+            ["auth_stage_duration_seconds", "email_send_duration_seconds"].into_iter().find(|m| *m == series)?;
+        let (labels, _) = line[series.len() + 8..].split_once('}')?;
+        let mut le = None;
+        let mut rest = Vec::new();
+        for label in labels.split(',').filter(|l| !l.is_empty()) {
+            match label.strip_prefix("le=\"").and_then(|v| v.strip_suffix('"')) {
+                Some("+Inf") => le = Some(f64::INFINITY),
+                Some(v) => le = Some(v.parse::<f64>().ok()?),
+                None => rest.push(label),
+            }
+        }
+        Some((metric, rest.join(","), le?.to_bits()))
+    }
+}
+
+fn global() -> &'static OnceLock<Exemplars> {
+    static EXEMPLARS: OnceLock<Exemplars> = OnceLock::new();
+    &EXEMPLARS
+}
+
+/// Start collecting exemplars (`[exemplars] enabled`)
+pub fn install() {
+    let _ = global().set(Exemplars::default());
+}
+
+/// The process-wide exemplars, once installed
+pub fn installed() -> Option<&'static Exemplars> {
+    global().get()
+}
+
+/// Record an exemplar for an observation made on behalf of `trace_id`
+pub fn observe(metric: &'static str, labels: &[(&str, &str)], value: f64, trace_id: Option<&str>) {
+    if let (Some(exemplars), Some(trace_id)) = (installed(), trace_id) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        exemplars.observe(metric, labels, value, trace_id, now);
+    }
+}
+
+/// Whether a scraper's `Accept` header asks for OpenMetrics
+pub fn accepts_openmetrics(accept: &str) -> bool {
+    accept.split(',').any(|media| media.trim().starts_with("application/openmetrics-text"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sampled_trace_contexts_count() {
+        let trace = "4bf92f3577b34da6a3ce929d0e0e4736";
+        assert_eq!(sampled_trace_id(&format!("00-{}-00f067aa0ba902b7-01", trace)), Some(trace));
+        assert_eq!(sampled_trace_id(&format!("00-{}-00f067aa0ba902b7-00", trace)), None);
+        assert_eq!(sampled_trace_id("00-00000000000000000000000000000000-00f067aa0ba902b7-01"), None);
+        assert_eq!(sampled_trace_id("00-4BF92F35-00f067aa0ba902b7-01"), None);
+    }
+
+    #[test]
+    fn exemplars_follow_their_bucket_line() {
+        let exemplars = Exemplars::default();
+        exemplars.observe("auth_stage_duration_seconds", &[("stage", "db_lookup")], 0.004, "abc", 1700000000.0);
+        exemplars.observe("auth_stage_duration_seconds", &[("stage", "db_lookup")], 9.0, "def", 1700000001.0);
+        let exposition = "# TYPE emails_sent_total counter\n\
+            emails_sent_total 4\n\
+            # TYPE auth_stage_duration_seconds histogram\n\
+            auth_stage_duration_seconds_bucket{stage=\"db_lookup\",le=\"0.0025\"} 0\n\
+            auth_stage_duration_seconds_bucket{stage=\"db_lookup\",le=\"0.005\"} 1\n\
+            auth_stage_duration_seconds_bucket{stage=\"db_lookup\",le=\"+Inf\"} 2\n\
+            auth_stage_duration_seconds_count{stage=\"db_lookup\"} 2\n";
+        let annotated = exemplars.annotate(exposition);
+        let lines: Vec<&str> = annotated.lines().collect();
+        assert_eq!(&lines[..2], ["# TYPE emails_sent counter", "emails_sent_total 4"]);
+        assert!(lines[3].ends_with("} 0"));
+        assert!(lines[4].ends_with("} 1 # {trace_id=\"abc\"} 0.004 1700000000.000"));
+        assert!(lines[5].ends_with("} 2 # {trace_id=\"def\"} 9 1700000001.000"));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+        assert!(accepts_openmetrics("application/openmetrics-text;version=1.0.0,text/plain;q=0.5"));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    applications::ApplicationConfig,
+    client_registration,
+    crypto::constant_time_eq,
+    error::{ApiError, ErrorResponse},
+    jwt::Claims,
+    oauth::OAuthError,
+    profile,
+    routes::AppState,
+    subjects, transport,
+};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+/// Authenticated caller, resolved from an `Authorization: Bearer` access
+/// token or, for cookie-transport applications, the access-token cookie.
+/// Cookie-authenticated writes from other sites are refused with `403`
+/// (see [`transport::cookie_request_allowed`]).
+pub struct AuthUser {
+    /// Internal user id (the token's `sub` is a public subject)
+    pub user_id: String,
+    pub claims: Claims,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        authenticate(parts, state, &["access"])
+    }
+}
+
+/// JSON body that cookie clients may leave out: an empty body reads as
+/// `T::default()`, anything else is extracted as [`Json`], content type and all
+pub struct OptionalJson<T>(pub T);
+
+/// Largest body [`OptionalJson`] reads
+const OPTIONAL_JSON_LIMIT: usize = 64 * 1024;
+
+#[async_trait]
+impl<S, T> FromRequest<S> for OptionalJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Default,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let (parts, body) = request.into_parts();
+        let bytes = axum::body::to_bytes(body, OPTIONAL_JSON_LIMIT)
+            .await
+            .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self(T::default()));
+        }
+        let Json(value) = Json::from_request(Request::from_parts(parts, Body::from(bytes)), state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self(value))
+    }
+}
+
+/// Caller holding either a full access token or the restricted token issued
+/// while required profile fields are missing
+pub struct ProfileUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ProfileUser {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        authenticate(parts, state, &["access", profile::PROFILE_TOKEN_KIND]).map(ProfileUser)
+    }
+}
+
+fn authenticate(parts: &Parts, state: &AppState, kinds: &[&str]) -> Result<AuthUser, ErrorResponse> {
+    let token = match bearer_token(parts) {
+        Some(token) => token,
+        None => {
+            let token = transport::read_cookie(&parts.headers, &state.cfg.cookie.access_name)
+                .ok_or_else(|| ErrorResponse::unauthorized(ApiError::unauthorized("Missing access token")))?;
+            if !transport::cookie_request_allowed(&state.cfg.cookie, &parts.method, &parts.headers) {
+                return Err(cross_site());
+            }
+            token
+        }
+    };
+
+    let claims = state
+        .keys
+        .verify(&token, state.cfg.jwt_leeway_seconds)
+        .map_err(|_| ErrorResponse::unauthorized(ApiError::invalid_token()))?;
+    match accept(state, &claims, kinds) {
+        Ok(user_id) => Ok(AuthUser { user_id, claims }),
+        Err(Rejection::Db(_)) => Err(ErrorResponse::internal_error(ApiError::internal_error())),
+        Err(_) => Err(ErrorResponse::unauthorized(ApiError::invalid_token())),
+    }
+}
+
+/// Rejection of a cookie-authenticated write from another site
+pub(crate) fn cross_site() -> ErrorResponse {
+    ErrorResponse::forbidden(ApiError::forbidden("Cross-site requests are not allowed"))
+}
+
+/// Why verified claims are not accepted
+#[derive(Debug, Error)]
+pub enum Rejection {
+    #[error("token kind {0:?} is not accepted here")]
+    Kind(String),
+    #[error("unknown subject")]
+    UnknownSubject,
+    #[error("token revoked")]
+    Revoked,
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+/// Checks on a verified token's claims shared by every endpoint taking
+/// access tokens: its kind, its subject and revocation. Returns the
+/// internal user id.
+pub fn accept(state: &AppState, claims: &Claims, kinds: &[&str]) -> Result<String, Rejection> {
+    if !kinds.contains(&claims.kind.as_str()) {
+        return Err(Rejection::Kind(claims.kind.clone()));
+    }
+    let user_id = subjects::resolve(&state.db, &claims.sub)?.ok_or(Rejection::UnknownSubject)?;
+    // sessions revoked on any instance invalidate access tokens issued before,
+    // a revoked session those issued for it, and `/oauth/revoke` single tokens
+    if state.revocations.cache().is_access_token_revoked(&user_id, claims) {
+        return Err(Rejection::Revoked);
+    }
+    Ok(user_id)
+}
+
+/// Relying application authenticated with HTTP Basic client credentials
+pub struct AppClient(pub ApplicationConfig);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AppClient {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        authenticate_client(&parts.headers, state)
+            .map(AppClient)
+            .ok_or_else(|| ErrorResponse::unauthorized(ApiError::unauthorized("Invalid client credentials")))
+    }
+}
+
+/// [`AppClient`] for OAuth endpoints, rejecting with an RFC 6749 `invalid_client`
+pub struct OAuthClient(pub ApplicationConfig);
+
+#[async_trait]
+impl FromRequestParts<AppState> for OAuthClient {
+    type Rejection = OAuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        authenticate_client(&parts.headers, state).map(OAuthClient).ok_or_else(OAuthError::invalid_client)
+    }
+}
+
+/// Caller of an OAuth endpoint open to public clients (RFC 6749 §2.3):
+/// confidential clients authenticate with HTTP Basic, public ones, which
+/// have no secret, only send their `client_id`
+pub(crate) fn public_or_confidential_client(
+    headers: &HeaderMap,
+    state: &AppState,
+    client_id: Option<&str>,
+) -> Option<ApplicationConfig> {
+    if headers.contains_key(header::AUTHORIZATION) {
+        let app = authenticate_client(headers, state)?;
+        return (client_id.is_none() || client_id == Some(app.client_id.as_str())).then_some(app);
+    }
+    let client_id = client_id?;
+    match state.cfg.application(client_id) {
+        Some(app) => app.client_secret.is_none().then(|| app.clone()),
+        None => client_registration::public_application(&state.db, client_id).ok().flatten(),
+    }
+}
+
+fn authenticate_client(headers: &HeaderMap, state: &AppState) -> Option<ApplicationConfig> {
+    let (client_id, secret) = basic_credentials(headers)?;
+    let Some(app) = state.cfg.application(&client_id) else {
+        // registered clients' secrets are only stored hashed
+        return client_registration::authenticate(&state.db, &client_id, &secret).ok().flatten();
+    };
+    let expected = app.client_secret.as_deref()?;
+    constant_time_eq(expected.as_bytes(), secret.as_bytes()).then(|| app.clone())
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = data_encoding::BASE64.decode(encoded.trim().as_bytes()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (id, secret) = decoded.split_once(':')?;
+    Some((id.to_string(), secret.to_string()))
+}
+
+pub(crate) fn bearer_token(parts: &Parts) -> Option<String> {
+    parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string())
+}
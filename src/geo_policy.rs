@@ -0,0 +1,135 @@
+//! Login policy by client country and network (autonomous system).
+//!
+//! The country comes from the edge header configured as `[security_notices]
+//! country_header`, the AS number from `[geo_policy] asn_header`; both are
+//! set by the CDN or proxy that geolocates the client. A completed login
+//! from a blocked country or ASN is refused. A login from a step-up country
+//! or ASN must use a second factor (TOTP or a passkey); a magic link alone
+//! is refused. Applications may replace the global rules with their own
+//! `geo_policy` table. Every triggered rule is audited.
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+/// Countries and AS numbers a policy applies to
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct GeoRules {
+    /// ISO 3166 country codes whose logins are refused
+    #[serde(default)]
+    pub block_countries: Vec<String>,
+    /// Countries whose logins need TOTP or a passkey
+    #[serde(default)]
+    pub step_up_countries: Vec<String>,
+    /// AS numbers (e.g. hosting providers) whose logins are refused
+    #[serde(default)]
+    pub block_asns: Vec<u32>,
+    /// AS numbers whose logins need TOTP or a passkey
+    #[serde(default)]
+    pub step_up_asns: Vec<u32>,
+}
+
+impl GeoRules {
+    pub fn is_empty(&self) -> bool {
+        self.block_countries.is_empty()
+            && self.step_up_countries.is_empty()
+            && self.block_asns.is_empty()
+            && self.step_up_asns.is_empty()
+    }
+}
+
+/// `[geo_policy]` configuration
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct GeoPolicyConfig {
+    /// Header carrying the client's AS number (`13335` or `AS13335`); unset
+    /// disables the ASN rules
+    #[serde(default)]
+    pub asn_header: Option<String>,
+    /// Rules for applications without their own `geo_policy`
+    #[serde(flatten)]
+    pub rules: GeoRules,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    StepUp,
+    Block,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::StepUp => "step_up",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// A rule that matched a login
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Decision {
+    pub action: Action,
+    /// What matched, e.g. `country:KP` or `asn:16509`
+    pub trigger: String,
+}
+
+/// Client AS number from the configured edge header
+pub fn asn(cfg: &GeoPolicyConfig, headers: &HeaderMap) -> Option<u32> {
+    let name = cfg.asn_header.as_deref()?;
+    let value = headers.get(name)?.to_str().ok()?.trim();
+    let digits = value
+        .strip_prefix("AS")
+        .or_else(|| value.strip_prefix("as"))
+        .unwrap_or(value);
+    digits.parse().ok().filter(|asn| *asn != 0)
+}
+
+/// The strictest rule matching a login; `None` allows it. Blocks win over
+/// step-ups, and a country match is reported before an ASN match.
+pub fn evaluate(rules: &GeoRules, country: Option<&str>, asn: Option<u32>) -> Option<Decision> {
+    let in_countries = |list: &[String]| country.filter(|c| list.iter().any(|l| l.eq_ignore_ascii_case(c)));
+    let in_asns = |list: &[u32]| asn.filter(|a| list.contains(a));
+    let matched = |countries: &[String], asns: &[u32]| {
+        in_countries(countries)
+            .map(|c| format!("country:{}", c))
+            .or_else(|| in_asns(asns).map(|a| format!("asn:{}", a)))
+    };
+    if let Some(trigger) = matched(&rules.block_countries, &rules.block_asns) {
+        return Some(Decision { action: Action::Block, trigger });
+    }
+    matched(&rules.step_up_countries, &rules.step_up_asns).map(|trigger| Decision {
+        action: Action::StepUp,
+        trigger,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_win_and_asn_headers_parse() {
+        let rules = GeoRules {
+            block_countries: vec!["kp".into()],
+            step_up_countries: vec!["RU".into()],
+            block_asns: vec![16509],
+            step_up_asns: vec![14061],
+        };
+        assert!(!rules.is_empty());
+        assert_eq!(evaluate(&rules, Some("DE"), None), None);
+        assert_eq!(evaluate(&rules, Some("KP"), None).unwrap().trigger, "country:KP");
+        let decision = evaluate(&rules, Some("RU"), Some(16509)).unwrap();
+        assert_eq!((decision.action, decision.trigger.as_str()), (Action::Block, "asn:16509"));
+        assert_eq!(evaluate(&rules, None, Some(14061)).unwrap().action, Action::StepUp);
+
+        let cfg = GeoPolicyConfig {
+            asn_header: Some("X-Client-ASN".into()),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-asn", "AS16509".parse().unwrap());
+        assert_eq!(asn(&cfg, &headers), Some(16509));
+        headers.insert("x-client-asn", "unknown".parse().unwrap());
+        assert_eq!(asn(&cfg, &headers), None);
+    }
+}
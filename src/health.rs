@@ -0,0 +1,163 @@
+use crate::{db::Database, email::Emailer, webhooks::WebhookSender};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+/// Number of storage latency samples kept for percentile reporting
+const LATENCY_WINDOW: usize = 512;
+
+/// Result of the most recent probe of an outbound dependency. Served on an
+/// unauthenticated endpoint, so failure details (hosts, paths) only go to
+/// the log.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub reachable: bool,
+    pub latency_ms: u64,
+    pub checked_at: i64,
+}
+
+impl ProbeResult {
+    fn from_outcome(started: Instant, outcome: Result<(), String>) -> Self {
+        Self {
+            reachable: outcome.is_ok(),
+            latency_ms: started.elapsed().as_millis() as u64,
+            checked_at: Database::now_ts(),
+        }
+    }
+}
+
+/// Storage latency percentiles in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyPercentiles {
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+/// `GET /health/dependencies` response
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyReport {
+    pub smtp: Option<ProbeResult>,
+    /// `None` when no webhook endpoint is configured or it has not been probed yet
+    pub webhook: Option<ProbeResult>,
+    pub storage: LatencyPercentiles,
+}
+
+/// Cached health of outbound dependencies, refreshed by a background prober
+#[derive(Default)]
+pub struct DependencyHealth {
+    smtp: RwLock<Option<ProbeResult>>,
+    webhook: RwLock<Option<ProbeResult>>,
+    storage_latencies: RwLock<VecDeque<f64>>,
+}
+
+impl DependencyHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record how long a storage round trip took
+    pub fn record_storage_latency(&self, elapsed: Duration) {
+        let mut samples = self.storage_latencies.write().unwrap();
+        if samples.len() == LATENCY_WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(elapsed.as_secs_f64() * 1000.0);
+    }
+
+    pub fn storage_percentiles(&self) -> LatencyPercentiles {
+        let mut sorted: Vec<f64> = self.storage_latencies.read().unwrap().iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        LatencyPercentiles {
+            samples: sorted.len(),
+            p50_ms: percentile(&sorted, 0.50),
+            p95_ms: percentile(&sorted, 0.95),
+            p99_ms: percentile(&sorted, 0.99),
+        }
+    }
+
+    pub fn report(&self) -> DependencyReport {
+        DependencyReport {
+            smtp: self.smtp.read().unwrap().clone(),
+            webhook: self.webhook.read().unwrap().clone(),
+            storage: self.storage_percentiles(),
+        }
+    }
+
+    /// Probe SMTP, the webhook endpoint and storage every `every`
+    pub fn spawn_prober(
+        self: Arc<Self>,
+        db: Arc<Database>,
+        emailer: Arc<Emailer>,
+        webhook: Arc<WebhookSender>,
+        every: Duration,
+    ) {
+        tokio::spawn(async move {
+            let mut ticker = interval(every);
+            loop {
+                ticker.tick().await;
+
+                let started = Instant::now();
+                let mailer = emailer.clone();
+                let outcome = match tokio::task::spawn_blocking(move || mailer.test_connection()).await {
+                    Ok(Ok(true)) => Ok(()),
+                    Ok(Ok(false)) => Err("connection refused".to_string()),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Err(e) = &outcome {
+                    warn!("SMTP probe failed: {}", e);
+                }
+                *self.smtp.write().unwrap() = Some(ProbeResult::from_outcome(started, outcome));
+
+                if webhook.is_configured() {
+                    let started = Instant::now();
+                    let outcome = webhook.probe().await;
+                    if let Err(e) = &outcome {
+                        warn!("Webhook probe failed: {}", e);
+                    }
+                    *self.webhook.write().unwrap() = Some(ProbeResult::from_outcome(started, outcome));
+                }
+
+                let started = Instant::now();
+                match db.conn().query_row("SELECT 1", [], |r| r.get::<_, i64>(0)) {
+                    Ok(_) => self.record_storage_latency(started.elapsed()),
+                    Err(e) => warn!("Storage probe failed: {}", e),
+                }
+                debug!("Dependency probes completed");
+            }
+        });
+    }
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let idx = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[idx.min(sorted.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_percentiles() {
+        let health = DependencyHealth::new();
+        assert_eq!(health.storage_percentiles().samples, 0);
+        for ms in 1..=100 {
+            health.record_storage_latency(Duration::from_millis(ms));
+        }
+        let p = health.storage_percentiles();
+        assert_eq!(p.samples, 100);
+        assert!((p.p50_ms - 50.0).abs() <= 1.0);
+        assert!((p.p99_ms - 99.0).abs() <= 1.0);
+    }
+}
@@ -0,0 +1,371 @@
+//! HTML escaping and allow-list sanitizing for email bodies.
+//!
+//! User-derived fields (addresses, device names, countries) are passed
+//! through [`escape`] before they reach a template. Markup supplied by
+//! operators, such as custom templates and branding, is rendered with
+//! [`Template`]: `{{name}}` placeholders and nothing else, with escaped values
+//! and the output run through [`sanitize`], so only [`ALLOWED_TAGS`] survive.
+
+use thiserror::Error;
+
+/// Tags kept by [`sanitize`]. Attributes are dropped, except an `href` with
+/// an allowed scheme on `a`.
+pub const ALLOWED_TAGS: &[&str] = &[
+    "a", "b", "blockquote", "br", "code", "em", "h1", "h2", "h3", "hr", "i", "li", "ol", "p", "pre", "strong", "u",
+    "ul",
+];
+
+const VOID_TAGS: &[&str] = &["br", "hr"];
+
+/// Tags removed together with everything inside them
+const DROPPED_TAGS: &[&str] = &[
+    "iframe", "math", "noscript", "object", "script", "select", "style", "svg", "template", "textarea", "title",
+];
+
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Escape text for use in HTML content or a quoted attribute
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Reduce `html` to [`ALLOWED_TAGS`]: other tags are removed (keeping their
+/// text, except for script-like tags), comments are dropped, stray `<`, `>`
+/// and `&` are escaped and unclosed tags are closed at the end
+pub fn sanitize(html: &str) -> String {
+    let bytes = html.as_bytes();
+    let mut out = String::with_capacity(html.len());
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &html[i..];
+        match bytes[i] {
+            b'<' if rest.starts_with("<!--") => {
+                i = rest[4..].find("-->").map_or(bytes.len(), |end| i + 4 + end + 3);
+            }
+            b'<' => match parse_tag(rest) {
+                Some(tag) if !tag.closing && DROPPED_TAGS.contains(&tag.name.as_str()) => {
+                    // offsets are unchanged by ASCII lowercasing
+                    let closing = format!("</{}", tag.name);
+                    i = match rest.to_ascii_lowercase().find(&closing) {
+                        Some(end) => rest[end..].find('>').map_or(bytes.len(), |gt| i + end + gt + 1),
+                        None => bytes.len(),
+                    };
+                }
+                Some(tag) => {
+                    i += tag.len;
+                    emit_tag(&tag, &mut open, &mut out);
+                }
+                None => {
+                    out.push_str("&lt;");
+                    i += 1;
+                }
+            },
+            b'&' => match entity_len(rest) {
+                Some(len) => {
+                    out.push_str(&rest[..len]);
+                    i += len;
+                }
+                None => {
+                    out.push_str("&amp;");
+                    i += 1;
+                }
+            },
+            b'>' => {
+                out.push_str("&gt;");
+                i += 1;
+            }
+            b'"' => {
+                out.push_str("&quot;");
+                i += 1;
+            }
+            _ => {
+                let ch = rest.chars().next().unwrap_or_default();
+                out.push(ch);
+                i += ch.len_utf8();
+            }
+        }
+    }
+    for name in open.into_iter().rev() {
+        out.push_str(&format!("</{}>", name));
+    }
+    out
+}
+
+struct Tag<'a> {
+    /// Lowercased
+    name: String,
+    closing: bool,
+    attributes: &'a str,
+    /// Bytes up to and including the closing `>`
+    len: usize,
+}
+
+/// The tag at the start of `s`; `None` when `<` does not open one
+fn parse_tag(s: &str) -> Option<Tag<'_>> {
+    let b = s.as_bytes();
+    let closing = b.get(1) == Some(&b'/');
+    let start = if closing { 2 } else { 1 };
+    let mut j = start;
+    while j < b.len() && b[j].is_ascii_alphanumeric() {
+        j += 1;
+    }
+    if j == start || !b[start].is_ascii_alphabetic() {
+        return None;
+    }
+    let name = s[start..j].to_ascii_lowercase();
+    let attributes_start = j;
+    let mut quote = None;
+    while j < b.len() {
+        match (quote, b[j]) {
+            (None, b'>') => {
+                return Some(Tag {
+                    name,
+                    closing,
+                    attributes: &s[attributes_start..j],
+                    len: j + 1,
+                })
+            }
+            (None, q @ (b'"' | b'\'')) => quote = Some(q),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+        j += 1;
+    }
+    None
+}
+
+fn emit_tag(tag: &Tag, open: &mut Vec<&'static str>, out: &mut String) {
+    let Some(&name) = ALLOWED_TAGS.iter().find(|t| **t == tag.name) else {
+        return;
+    };
+    if tag.closing {
+        // closing an outer tag closes the ones still open inside it
+        if let Some(pos) = open.iter().rposition(|t| *t == name) {
+            for inner in open.drain(pos..).rev() {
+                out.push_str(&format!("</{}>", inner));
+            }
+        }
+    } else if VOID_TAGS.contains(&name) {
+        out.push_str(&format!("<{}>", name));
+    } else {
+        match (name, safe_href(tag.attributes)) {
+            ("a", Some(href)) => out.push_str(&format!(r#"<a href="{}" rel="noopener noreferrer">"#, escape(&href))),
+            _ => out.push_str(&format!("<{}>", name)),
+        }
+        open.push(name);
+    }
+}
+
+/// `href` among `attributes` when it is an absolute URL with an allowed scheme
+fn safe_href(attributes: &str) -> Option<String> {
+    let href = unescape(attribute(attributes, "href")?);
+    let scheme = &href[..href.find(':')?];
+    // entities, whitespace or control characters in a scheme are obfuscation
+    if !scheme.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.')) {
+        return None;
+    }
+    URL_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()).then_some(href)
+}
+
+/// Undo [`escape`], so a value is not escaped twice when re-emitted
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn attribute<'a>(attributes: &'a str, wanted: &str) -> Option<&'a str> {
+    let b = attributes.as_bytes();
+    let mut j = 0;
+    while j < b.len() {
+        let before = j;
+        while j < b.len() && (b[j].is_ascii_whitespace() || b[j] == b'/') {
+            j += 1;
+        }
+        let start = j;
+        while j < b.len() && !b[j].is_ascii_whitespace() && !matches!(b[j], b'=' | b'/') {
+            j += 1;
+        }
+        let name = &attributes[start..j];
+        while j < b.len() && b[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        let mut value = "";
+        if b.get(j) == Some(&b'=') {
+            j += 1;
+            while j < b.len() && b[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            match b.get(j) {
+                Some(&q @ (b'"' | b'\'')) => {
+                    let end = attributes[j + 1..].find(q as char).map_or(b.len(), |e| j + 1 + e);
+                    value = &attributes[j + 1..end];
+                    j = end + 1;
+                }
+                _ => {
+                    let start = j;
+                    while j < b.len() && !b[j].is_ascii_whitespace() {
+                        j += 1;
+                    }
+                    value = &attributes[start..j];
+                }
+            }
+        }
+        if name.eq_ignore_ascii_case(wanted) {
+            return Some(value);
+        }
+        if j == before {
+            j += 1;
+        }
+    }
+    None
+}
+
+/// Length of the character reference at the start of `s` (`&amp;`, `&#39;`,
+/// `&#x27;`); `None` for a bare `&`
+fn entity_len(s: &str) -> Option<usize> {
+    let end = s.get(1..)?.find(';')? + 1;
+    let body = &s[1..end];
+    let valid = match body.strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (1..=6).contains(&hex.len()) && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+            None => (1..=7).contains(&number.len()) && number.bytes().all(|b| b.is_ascii_digit()),
+        },
+        None => (1..=32).contains(&body.len()) && body.bytes().all(|b| b.is_ascii_alphanumeric()),
+    };
+    valid.then_some(end + 1)
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("unknown placeholder {0:?}")]
+    UnknownVariable(String),
+    #[error("unclosed placeholder")]
+    Unclosed,
+}
+
+/// An operator-supplied HTML template. The only syntax is `{{name}}` for a
+/// variable from the list given to [`Template::parse`]; there are no
+/// expressions, includes or raw output. Values are escaped and the rendered
+/// markup is sanitized, so neither the template nor a field value can emit
+/// scripts, handlers or unlisted tags.
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+}
+
+impl Template {
+    /// Check that every placeholder in `source` is one of `variables`
+    pub fn parse(source: &str, variables: &[&str]) -> Result<Self, TemplateError> {
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            let end = start + rest[start..].find("}}").ok_or(TemplateError::Unclosed)?;
+            let name = rest[start + 2..end].trim();
+            if !variables.contains(&name) {
+                return Err(TemplateError::UnknownVariable(name.to_string()));
+            }
+            rest = &rest[end + 2..];
+        }
+        Ok(Self {
+            source: source.to_string(),
+        })
+    }
+
+    /// Render with `values`; variables without a value render empty
+    pub fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut out = String::with_capacity(self.source.len());
+        let mut rest = self.source.as_str();
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find("}}").map(|e| start + e) else {
+                break;
+            };
+            let name = rest[start + 2..end].trim();
+            if let Some((_, value)) = values.iter().find(|(k, _)| *k == name) {
+                out.push_str(&escape(value));
+            }
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+        sanitize(&out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_common_payloads() {
+        let cases = [
+            ("<script>alert(1)</script>Hi", "Hi"),
+            ("<SCRIPT SRC=//evil.example/x.js></SCRIPT>ok", "ok"),
+            ("<img src=x onerror=alert(1)>", ""),
+            ("<svg onload=alert(1)><circle/></svg>after", "after"),
+            ("<p onclick=\"steal()\">text</p>", "<p>text</p>"),
+            ("<a href=\"javascript:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"jav&#x09;ascript:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\" javascript:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"data:text/html,<script>\">x</a>", "<a>x</a>"),
+            ("<iframe src=https://evil.example></iframe>", ""),
+            ("<style>body{display:none}</style>text", "text"),
+            ("<!-- <script>alert(1)</script> -->visible", "visible"),
+            ("<scr<script>ipt>alert(1)</script>", "ipt&gt;alert(1)"),
+            ("<b>bold", "<b>bold</b>"),
+            ("<ul><li>one<li>two</ul>", "<ul><li>one<li>two</li></li></ul>"),
+            ("1 < 2 & 3 > 2", "1 &lt; 2 &amp; 3 &gt; 2"),
+            ("&lt;kept&gt; &amp;", "&lt;kept&gt; &amp;"),
+            ("\"><script>alert(1)</script>", "&quot;&gt;"),
+            ("<script>never closed", ""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(sanitize(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn sanitize_keeps_safe_links() {
+        assert_eq!(
+            sanitize("<a HREF='https://example.com/?a=1&amp;b=2' target=_blank>Docs</a>"),
+            r#"<a href="https://example.com/?a=1&amp;b=2" rel="noopener noreferrer">Docs</a>"#
+        );
+        assert_eq!(
+            sanitize("<a href=mailto:help@example.com>help</a>"),
+            r#"<a href="mailto:help@example.com" rel="noopener noreferrer">help</a>"#
+        );
+    }
+
+    #[test]
+    fn templates_escape_values_and_reject_unknown_placeholders() {
+        let template = Template::parse(
+            r#"<p>Hi {{ email }}, new sign-in from {{device}}.</p><a href="{{link}}">Review</a>"#,
+            &["email", "device", "link"],
+        )
+        .unwrap();
+        let html = template.render(&[
+            ("email", "a@example.com"),
+            ("device", "<img src=x onerror=alert(1)>"),
+            ("link", "javascript:alert(1)"),
+        ]);
+        assert_eq!(
+            html,
+            "<p>Hi a@example.com, new sign-in from &lt;img src=x onerror=alert(1)&gt;.</p><a>Review</a>"
+        );
+        // a value cannot close the attribute it is placed in
+        let html = template.render(&[("link", "https://example.com/\" onclick=\"x")]);
+        assert!(html.contains(r#"<a href="https://example.com/&quot; onclick=&quot;x" rel="noopener noreferrer">"#));
+
+        assert_eq!(
+            Template::parse("{{secret}}", &["email"]).unwrap_err(),
+            TemplateError::UnknownVariable("secret".into())
+        );
+        assert_eq!(Template::parse("{{email", &["email"]).unwrap_err(), TemplateError::Unclosed);
+    }
+}
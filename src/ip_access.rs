@@ -0,0 +1,385 @@
+use crate::{
+    config::Config,
+    cors::RouteGroup,
+    error::{ApiError, ErrorResponse},
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+
+/// `[ip_access]` table: CIDR rules for the admin and metrics endpoints
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct IpAccessConfig {
+    /// When non-empty, only these ranges may reach `/admin/*`
+    #[serde(default)]
+    pub admin_allow: Vec<String>,
+    #[serde(default)]
+    pub admin_deny: Vec<String>,
+    /// When non-empty, only these ranges may reach `/metrics`
+    #[serde(default)]
+    pub metrics_allow: Vec<String>,
+    #[serde(default)]
+    pub metrics_deny: Vec<String>,
+    /// Use X-Forwarded-For / X-Real-IP (only behind a trusted proxy)
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Proxies in front of this service. When set, forwarding headers are
+    /// only read from connections they make, and the client is the rightmost
+    /// `X-Forwarded-For` entry that is not one of them; when empty, only the
+    /// connecting peer is trusted and the rightmost entry is the client.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl IpAccessConfig {
+    /// Every entry must be a valid CIDR: a dropped allow entry could leave
+    /// the list empty, which admits every address
+    pub fn validate(&self) -> Result<(), String> {
+        Policy::from_config(self).map(|_| ())
+    }
+}
+
+/// An IPv4 or IPv6 network in CIDR notation (a bare address is a /32 or /128)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("invalid address: {}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().map_err(|_| format!("invalid prefix: {}", s))?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(format!("prefix out of range: {}", s));
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl Rules {
+    fn permits(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Policy {
+    admin: Rules,
+    metrics: Rules,
+    trust_forwarded_for: bool,
+    trusted_proxies: Vec<Cidr>,
+}
+
+impl Policy {
+    fn from_config(cfg: &IpAccessConfig) -> Result<Self, String> {
+        Ok(Self {
+            admin: Rules {
+                allow: parse_all("admin_allow", &cfg.admin_allow)?,
+                deny: parse_all("admin_deny", &cfg.admin_deny)?,
+            },
+            metrics: Rules {
+                allow: parse_all("metrics_allow", &cfg.metrics_allow)?,
+                deny: parse_all("metrics_deny", &cfg.metrics_deny)?,
+            },
+            trust_forwarded_for: cfg.trust_forwarded_for,
+            trusted_proxies: parse_all("trusted_proxies", &cfg.trusted_proxies)?,
+        })
+    }
+
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|c| c.contains(ip))
+    }
+
+    /// Address of the client behind `peer`. Proxies append to
+    /// `X-Forwarded-For`, so only entries right of the last untrusted one are
+    /// trustworthy; anything further left is whatever the client sent.
+    fn client_ip(&self, headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+        let from_proxy = self.trusted_proxies.is_empty() || self.is_trusted_proxy(&peer);
+        if !self.trust_forwarded_for || !from_proxy {
+            return peer;
+        }
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        if let Some(forwarded_for) = header("X-Forwarded-For") {
+            let mut client = peer;
+            for entry in forwarded_for.rsplit(',') {
+                let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                    break;
+                };
+                client = ip;
+                if !self.is_trusted_proxy(&ip) {
+                    break;
+                }
+            }
+            return client;
+        }
+        header("X-Real-IP").and_then(|v| v.trim().parse().ok()).unwrap_or(peer)
+    }
+}
+
+/// Hot-reloadable IP access control for the admin and metrics route groups
+#[derive(Debug, Default)]
+pub struct IpAccessControl {
+    policy: RwLock<Policy>,
+}
+
+fn parse_all(key: &str, entries: &[String]) -> Result<Vec<Cidr>, String> {
+    entries
+        .iter()
+        .map(|e| e.parse().map_err(|err| format!("[ip_access] {}: {}", key, err)))
+        .collect()
+}
+
+impl IpAccessControl {
+    pub fn new(cfg: &IpAccessConfig) -> Result<Self, String> {
+        let acl = Self::default();
+        acl.reload(cfg)?;
+        Ok(acl)
+    }
+
+    /// Replace the active rules (e.g. after SIGHUP); with any invalid entry
+    /// the current rules stay in force
+    pub fn reload(&self, cfg: &IpAccessConfig) -> Result<(), String> {
+        let policy = Policy::from_config(cfg)?;
+        *self.policy.write().unwrap() = policy;
+        info!("IP access rules loaded");
+        Ok(())
+    }
+
+    pub fn permits(&self, group: RouteGroup, ip: &IpAddr) -> bool {
+        let policy = self.policy.read().unwrap();
+        match group {
+            RouteGroup::Admin => policy.admin.permits(ip),
+            RouteGroup::Metrics => policy.metrics.permits(ip),
+            RouteGroup::Public => true,
+        }
+    }
+
+    /// Client address of a request arriving from `peer`
+    pub fn client_ip(&self, headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+        self.policy.read().unwrap().client_ip(headers, peer)
+    }
+}
+
+/// Middleware state binding the shared rules to one route group
+#[derive(Clone)]
+pub struct IpGuard {
+    pub acl: Arc<IpAccessControl>,
+    pub group: RouteGroup,
+}
+
+/// Reject requests whose source address is not permitted for the group
+pub async fn enforce(
+    State(guard): State<IpGuard>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = guard.acl.client_ip(request.headers(), addr.ip());
+
+    if !guard.acl.permits(guard.group, &ip) {
+        warn!("Blocked {} request from {}", guard.group.as_str(), ip);
+        return ErrorResponse::forbidden(ApiError::forbidden("Access denied")).into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Reload IP rules from the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+pub fn spawn_reload_on_sighup(acl: Arc<IpAccessControl>, config_path: String) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to install SIGHUP handler: {}", e);
+                return;
+            }
+        };
+        while hup.recv().await.is_some() {
+            match Config::load(&config_path) {
+                Ok(cfg) => {
+                    if let Err(e) = acl.reload(&cfg.ip_access) {
+                        warn!("SIGHUP: keeping current IP rules: {}", e);
+                    }
+                }
+                Err(e) => warn!("SIGHUP: failed to reload config, keeping current IP rules: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+
+        let host: Cidr = "192.168.1.5".parse().unwrap();
+        assert!(host.contains(&"192.168.1.5".parse().unwrap()));
+        assert!(!host.contains(&"192.168.1.6".parse().unwrap()));
+
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(&"fd12::1".parse().unwrap()));
+        assert!(!v6.contains(&"10.0.0.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_deny_overrides_allow() {
+        let acl = IpAccessControl::new(&IpAccessConfig {
+            admin_allow: vec!["10.0.0.0/8".into()],
+            admin_deny: vec!["10.0.0.13".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(acl.permits(RouteGroup::Admin, &"10.0.0.12".parse().unwrap()));
+        assert!(!acl.permits(RouteGroup::Admin, &"10.0.0.13".parse().unwrap()));
+        assert!(!acl.permits(RouteGroup::Admin, &"8.8.8.8".parse().unwrap()));
+        // empty allow list means unrestricted
+        assert!(acl.permits(RouteGroup::Metrics, &"8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_invalid_entries_are_rejected() {
+        // dropping these would leave an empty allow list, which admits everyone
+        let typo = IpAccessConfig {
+            admin_allow: vec!["10.0.0.0/33".into()],
+            metrics_allow: vec!["10.0.0.0/8".into(), "not-an-ip".into()],
+            ..Default::default()
+        };
+        let err = typo.validate().unwrap_err();
+        assert!(err.contains("admin_allow"), "{}", err);
+        assert!(IpAccessControl::new(&typo).is_err());
+
+        // a bad reload keeps the rules in force
+        let acl = IpAccessControl::new(&IpAccessConfig {
+            admin_allow: vec!["10.0.0.0/8".into()],
+            metrics_allow: vec!["10.0.0.0/8".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(acl.reload(&typo).is_err());
+        for group in [RouteGroup::Admin, RouteGroup::Metrics] {
+            assert!(!acl.permits(group, &"8.8.8.8".parse().unwrap()));
+            assert!(acl.permits(group, &"10.1.2.3".parse().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_forwarded_client_is_the_rightmost_untrusted_entry() {
+        let headers = |forwarded_for: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Forwarded-For", forwarded_for.parse().unwrap());
+            headers
+        };
+        let acl = |trusted_proxies: Vec<String>| {
+            IpAccessControl::new(&IpAccessConfig {
+                trust_forwarded_for: true,
+                trusted_proxies,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let proxy: IpAddr = "10.1.0.5".parse().unwrap();
+
+        // only the peer is trusted: the entry it appended is the client
+        let single = acl(vec![]);
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(single.client_ip(&headers("10.0.0.1, 203.0.113.7"), proxy), client);
+
+        // a CDN in front of the load balancer
+        let chain = acl(vec!["10.1.0.0/16".into(), "198.51.100.0/24".into()]);
+        let spoofed = headers("10.0.0.1, 203.0.113.7, 198.51.100.20");
+        assert_eq!(chain.client_ip(&spoofed, proxy), client);
+        // connections from elsewhere do not get to pick their address
+        let direct: IpAddr = "203.0.113.50".parse().unwrap();
+        assert_eq!(chain.client_ip(&headers("10.0.0.1"), direct), direct);
+        assert_eq!(chain.client_ip(&headers("garbage"), proxy), proxy);
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_forwarded_for_is_denied() {
+        use axum::{body::Body, extract::connect_info::MockConnectInfo, routing::get, Router};
+        use tower::ServiceExt;
+
+        let acl = IpAccessControl::new(&IpAccessConfig {
+            admin_allow: vec!["10.0.0.0/16".into()],
+            trust_forwarded_for: true,
+            trusted_proxies: vec!["10.1.0.0/16".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let guard = IpGuard {
+            acl: Arc::new(acl),
+            group: RouteGroup::Admin,
+        };
+        let app = Router::new()
+            .route("/admin/users", get(|| async { "" }))
+            .layer(axum::middleware::from_fn_with_state(guard, enforce))
+            .layer(MockConnectInfo(SocketAddr::from(([10, 1, 0, 5], 443))));
+        let request = |forwarded_for: &str| {
+            Request::get("/admin/users")
+                .header("X-Forwarded-For", forwarded_for)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let spoofed = app.clone().oneshot(request("10.0.0.1, 203.0.113.7")).await.unwrap();
+        assert_eq!(spoofed.status(), axum::http::StatusCode::FORBIDDEN);
+        let internal = app.oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(internal.status(), axum::http::StatusCode::OK);
+    }
+}
@@ -0,0 +1,489 @@
+//! Temporary bans for addresses that keep failing authentication.
+//!
+//! Every auth response is counted per client IP over a sliding window. Once
+//! an address has made at least `min_attempts` requests in the window and
+//! `failure_ratio` of them failed (400/401/403), it is banned for
+//! `ban_seconds`. Repeat offenders are banned for twice as long each time,
+//! up to `max_ban_seconds`; the strike count decays back to zero once an
+//! address has stayed clean for `decay_seconds`. Bans live in the `ip_bans`
+//! table, so every replica enforces them, and admins can list, add, extend
+//! and lift them. Admin bans are enforced even with `enabled = false`.
+
+use crate::{
+    audit::{AuditEventType, AuditLogger},
+    db::Database,
+    error::{ApiError, ErrorResponse},
+    leader::LeaderElection,
+    metrics::MetricsRecorder,
+    middleware::client_ip,
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// `[ip_bans]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct IpBanConfig {
+    /// Ban addresses automatically (admin bans are always enforced)
+    #[serde(default)]
+    pub enabled: bool,
+    /// Sliding window over which failures are counted
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: i64,
+    /// Requests in the window before an address can be banned
+    #[serde(default = "default_min_attempts")]
+    pub min_attempts: u32,
+    /// Share of failed requests in the window that triggers a ban
+    #[serde(default = "default_failure_ratio")]
+    pub failure_ratio: f64,
+    /// Length of a first ban; doubled for each further strike
+    #[serde(default = "default_ban_seconds")]
+    pub ban_seconds: i64,
+    #[serde(default = "default_max_ban_seconds")]
+    pub max_ban_seconds: i64,
+    /// Strikes are forgotten this long after the last ban ended
+    #[serde(default = "default_decay_seconds")]
+    pub decay_seconds: i64,
+}
+
+impl Default for IpBanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_seconds: default_window_seconds(),
+            min_attempts: default_min_attempts(),
+            failure_ratio: default_failure_ratio(),
+            ban_seconds: default_ban_seconds(),
+            max_ban_seconds: default_max_ban_seconds(),
+            decay_seconds: default_decay_seconds(),
+        }
+    }
+}
+
+fn default_window_seconds() -> i64 {
+    600
+}
+
+fn default_min_attempts() -> u32 {
+    20
+}
+
+fn default_failure_ratio() -> f64 {
+    0.8
+}
+
+fn default_ban_seconds() -> i64 {
+    900
+}
+
+fn default_max_ban_seconds() -> i64 {
+    86400
+}
+
+fn default_decay_seconds() -> i64 {
+    86400
+}
+
+impl IpBanConfig {
+    /// Ban length for the given strike (1 = first ban)
+    pub fn ban_duration(&self, strikes: i64) -> i64 {
+        let doublings = (strikes - 1).clamp(0, 30) as u32;
+        self.ban_seconds.saturating_mul(1 << doublings).min(self.max_ban_seconds)
+    }
+}
+
+/// Weighted request counts of one address over the sliding window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowCounts {
+    pub attempts: f64,
+    pub failures: f64,
+}
+
+impl WindowCounts {
+    pub fn exceeds(&self, cfg: &IpBanConfig) -> bool {
+        self.attempts >= cfg.min_attempts as f64 && self.failures >= self.attempts * cfg.failure_ratio
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    start: i64,
+    attempts: u32,
+    failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    previous: Bucket,
+    current: Bucket,
+}
+
+/// Addresses tracked at most; idle ones are dropped beyond this
+const MAX_TRACKED: usize = 100_000;
+
+/// In-memory sliding-window counters per IP.
+///
+/// Two fixed buckets per address: the previous bucket is weighted by how
+/// much of it still overlaps the window ending now.
+pub struct FailureTracker {
+    window: i64,
+    ips: Mutex<HashMap<String, Window>>,
+}
+
+impl FailureTracker {
+    pub fn new(window_seconds: i64) -> Self {
+        Self {
+            window: window_seconds.max(1),
+            ips: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count one request of `ip` at `now` and return its window totals
+    pub fn record(&self, ip: &str, failed: bool, now: i64) -> WindowCounts {
+        let start = now - now.rem_euclid(self.window);
+        let mut ips = self.ips.lock().unwrap_or_else(|e| e.into_inner());
+        if ips.len() >= MAX_TRACKED && !ips.contains_key(ip) {
+            ips.retain(|_, w| w.current.start >= start - self.window);
+        }
+        let entry = ips.entry(ip.to_string()).or_default();
+        if entry.current.start != start {
+            entry.previous = if entry.current.start == start - self.window {
+                entry.current
+            } else {
+                Bucket::default()
+            };
+            entry.current = Bucket { start, ..Bucket::default() };
+        }
+        entry.current.attempts += 1;
+        if failed {
+            entry.current.failures += 1;
+        }
+        let overlap = 1.0 - (now - start) as f64 / self.window as f64;
+        WindowCounts {
+            attempts: entry.current.attempts as f64 + entry.previous.attempts as f64 * overlap,
+            failures: entry.current.failures as f64 + entry.previous.failures as f64 * overlap,
+        }
+    }
+
+    /// Start counting `ip` afresh (after a ban)
+    pub fn reset(&self, ip: &str) {
+        self.ips.lock().unwrap_or_else(|e| e.into_inner()).remove(ip);
+    }
+}
+
+/// A ban, active or past
+#[derive(Debug, Clone, Serialize)]
+pub struct Ban {
+    pub ip: String,
+    pub reason: String,
+    /// `auto`, `admin` or `canary`
+    pub source: String,
+    /// Bans within the decay period, including this one
+    pub strikes: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub lifted_at: Option<i64>,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: i64) -> bool {
+        self.lifted_at.is_none() && self.expires_at > now
+    }
+}
+
+const COLUMNS: &str = "ip, reason, source, strikes, created_at, expires_at, lifted_at";
+
+fn row(r: &rusqlite::Row) -> rusqlite::Result<Ban> {
+    Ok(Ban {
+        ip: r.get(0)?,
+        reason: r.get(1)?,
+        source: r.get(2)?,
+        strikes: r.get(3)?,
+        created_at: r.get(4)?,
+        expires_at: r.get(5)?,
+        lifted_at: r.get(6)?,
+    })
+}
+
+/// Latest ban of `ip`, active or not
+pub fn find(db: &Database, ip: &str) -> Result<Option<Ban>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            &format!("SELECT {} FROM ip_bans WHERE ip = ?1", COLUMNS),
+            params![ip],
+            row,
+        )
+        .optional()
+}
+
+/// The ban currently in force for `ip`
+pub fn active(db: &Database, ip: &str, now: i64) -> Result<Option<Ban>, rusqlite::Error> {
+    Ok(find(db, ip)?.filter(|ban| ban.is_active(now)))
+}
+
+/// Ban `ip` for its next strike; `seconds` overrides the escalating length
+pub fn ban(
+    db: &Database,
+    ip: &str,
+    cfg: &IpBanConfig,
+    reason: &str,
+    source: &str,
+    seconds: Option<i64>,
+) -> Result<Ban, rusqlite::Error> {
+    let now = Database::now_ts();
+    let previous = find(db, ip)?;
+    let strikes = match &previous {
+        // strikes decay once the last ban has been over for `decay_seconds`
+        Some(p) if p.lifted_at.unwrap_or(p.expires_at) + cfg.decay_seconds > now => p.strikes + 1,
+        _ => 1,
+    };
+    let expires_at = now + seconds.unwrap_or_else(|| cfg.ban_duration(strikes));
+    db.conn().execute(
+        "INSERT INTO ip_bans (ip, reason, source, strikes, created_at, expires_at, lifted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULL)
+         ON CONFLICT(ip) DO UPDATE SET
+             reason = ?2, source = ?3, strikes = ?4, created_at = ?5, expires_at = ?6, lifted_at = NULL",
+        params![ip, reason, source, strikes, now, expires_at],
+    )?;
+    Ok(Ban {
+        ip: ip.to_string(),
+        reason: reason.to_string(),
+        source: source.to_string(),
+        strikes,
+        created_at: now,
+        expires_at,
+        lifted_at: None,
+    })
+}
+
+/// Lengthen an active ban; `None` if `ip` is not banned
+pub fn extend(db: &Database, ip: &str, seconds: i64) -> Result<Option<Ban>, rusqlite::Error> {
+    let now = Database::now_ts();
+    db.conn().execute(
+        "UPDATE ip_bans SET expires_at = expires_at + ?1 WHERE ip = ?2 AND lifted_at IS NULL AND expires_at > ?3",
+        params![seconds, ip, now],
+    )?;
+    active(db, ip, now)
+}
+
+/// Lift an active ban and forget the address's strikes; returns whether
+/// there was one
+pub fn lift(db: &Database, ip: &str) -> Result<bool, rusqlite::Error> {
+    let now = Database::now_ts();
+    Ok(db.conn().execute(
+        "UPDATE ip_bans SET lifted_at = ?1, strikes = 0 WHERE ip = ?2 AND lifted_at IS NULL AND expires_at > ?1",
+        params![now, ip],
+    )? > 0)
+}
+
+/// Bans, newest first; only active ones unless `include_expired`
+pub fn list(db: &Database, include_expired: bool, limit: i64, offset: i64) -> Result<Vec<Ban>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM ip_bans
+         WHERE ?1 OR (lifted_at IS NULL AND expires_at > ?2)
+         ORDER BY created_at DESC, ip LIMIT ?3 OFFSET ?4",
+        COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![include_expired, Database::now_ts(), limit, offset], row)?;
+    rows.collect()
+}
+
+pub fn active_count(db: &Database, now: i64) -> Result<i64, rusqlite::Error> {
+    db.conn().query_row(
+        "SELECT COUNT(*) FROM ip_bans WHERE lifted_at IS NULL AND expires_at > ?1",
+        params![now],
+        |r| r.get(0),
+    )
+}
+
+/// Delete bans whose strikes have decayed
+pub fn purge(db: &Database, cfg: &IpBanConfig, now: i64) -> Result<usize, rusqlite::Error> {
+    db.conn().execute(
+        "DELETE FROM ip_bans WHERE COALESCE(lifted_at, expires_at) + ?1 <= ?2",
+        params![cfg.decay_seconds, now],
+    )
+}
+
+/// Whether a response counts as a failed attempt
+fn is_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+    )
+}
+
+/// Middleware state: the ban list and this instance's failure counters
+pub struct IpBanGuard {
+    pub db: Arc<Database>,
+    pub audit: Arc<AuditLogger>,
+    pub cfg: IpBanConfig,
+    pub tracker: FailureTracker,
+    pub trust_forwarded_for: bool,
+}
+
+impl IpBanGuard {
+    pub fn new(db: Arc<Database>, audit: Arc<AuditLogger>, cfg: &IpBanConfig, trust_forwarded_for: bool) -> Self {
+        Self {
+            db,
+            audit,
+            tracker: FailureTracker::new(cfg.window_seconds),
+            cfg: cfg.clone(),
+            trust_forwarded_for,
+        }
+    }
+
+    /// Seconds until the ban on `ip` expires, or `None` when it may proceed.
+    /// A ban list outage fails open: it must not lock everyone out.
+    pub fn banned_for(&self, ip: &str) -> Option<i64> {
+        let now = Database::now_ts();
+        match active(&self.db, ip, now) {
+            Ok(Some(ban)) => {
+                MetricsRecorder::record_ip_ban_rejection();
+                Some((ban.expires_at - now).max(1))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to check IP bans: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Count a response of `ip`, banning it when it crosses the threshold
+    pub fn observe(&self, ip: &str, status: StatusCode) {
+        if !self.cfg.enabled || !(status.is_success() || is_failure(status)) {
+            return;
+        }
+        let counts = self.tracker.record(ip, is_failure(status), Database::now_ts());
+        if !counts.exceeds(&self.cfg) {
+            return;
+        }
+        self.tracker.reset(ip);
+        let reason = format!(
+            "{:.0} of {:.0} requests failed within {}s",
+            counts.failures, counts.attempts, self.cfg.window_seconds
+        );
+        match ban(&self.db, ip, &self.cfg, &reason, "auto", None) {
+            Ok(ban) => {
+                warn!("Banned {} until {} ({})", ip, ban.expires_at, reason);
+                MetricsRecorder::record_ip_ban("auto");
+                let metadata = serde_json::json!({
+                    "source": "auto",
+                    "reason": reason,
+                    "strikes": ban.strikes,
+                    "expires_at": ban.expires_at,
+                });
+                self.audit.log(
+                    &self.db,
+                    AuditEventType::IpBanned,
+                    None,
+                    None,
+                    Some(ip),
+                    None,
+                    Some(&metadata.to_string()),
+                    true,
+                );
+            }
+            Err(e) => warn!("Failed to ban {}: {}", ip, e),
+        }
+    }
+}
+
+/// Told to banned addresses
+pub const BANNED_MESSAGE: &str = "too many failed attempts from this address";
+
+/// Reject banned addresses and count the outcome of everyone else's requests
+pub async fn enforce(
+    State(guard): State<Arc<IpBanGuard>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = peer.map(|ConnectInfo(addr)| addr);
+    let Some(ip) = client_ip(request.headers(), peer, guard.trust_forwarded_for) else {
+        return next.run(request).await;
+    };
+    if let Some(wait) = guard.banned_for(&ip) {
+        let mut response =
+            ErrorResponse::forbidden(ApiError::new("IP_BANNED", BANNED_MESSAGE)).into_response();
+        if let Ok(retry_after) = HeaderValue::from_str(&wait.to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after);
+        }
+        return response;
+    }
+
+    let response = next.run(request).await;
+    guard.observe(&ip, response.status());
+    response
+}
+
+/// Publish the number of active bans and delete decayed ones
+pub fn spawn_maintenance(db: Arc<Database>, leader: Arc<LeaderElection>, cfg: IpBanConfig) {
+    let every = Duration::from_secs(60);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            let now = Database::now_ts();
+            match active_count(&db, now) {
+                Ok(n) => MetricsRecorder::record_ip_bans_active(n),
+                Err(e) => warn!("Failed to count IP bans: {}", e),
+            }
+            if !leader.lead("ip_bans", every) {
+                continue;
+            }
+            match purge(&db, &cfg, now) {
+                Ok(0) => {}
+                Ok(n) => info!("Removed {} decayed IP bans", n),
+                Err(e) => warn!("Failed to remove decayed IP bans: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sliding_window_weights_the_previous_bucket() {
+        let tracker = FailureTracker::new(100);
+        for _ in 0..10 {
+            tracker.record("203.0.113.9", true, 1_050);
+        }
+        // half of the previous bucket still overlaps the window
+        let counts = tracker.record("203.0.113.9", false, 1_150);
+        assert_eq!(counts, WindowCounts { attempts: 6.0, failures: 5.0 });
+        // two windows later nothing is left
+        let counts = tracker.record("203.0.113.9", true, 1_400);
+        assert_eq!(counts, WindowCounts { attempts: 1.0, failures: 1.0 });
+    }
+
+    #[test]
+    fn bans_escalate_up_to_the_maximum() {
+        let cfg = IpBanConfig {
+            ban_seconds: 600,
+            max_ban_seconds: 3000,
+            ..Default::default()
+        };
+        assert_eq!(cfg.ban_duration(1), 600);
+        assert_eq!(cfg.ban_duration(3), 2400);
+        assert_eq!(cfg.ban_duration(4), 3000);
+        let counts = WindowCounts { attempts: 20.0, failures: 17.0 };
+        assert!(counts.exceeds(&cfg));
+        assert!(!WindowCounts { attempts: 19.0, failures: 19.0 }.exceeds(&cfg));
+    }
+}
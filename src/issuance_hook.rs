@@ -0,0 +1,306 @@
+//! Pre-issuance hook for external fraud checks.
+//!
+//! Before tokens are issued for a completed login (magic link, TOTP or
+//! passkey), the configured hook is asked whether the login may proceed. It
+//! can deny the login, or allow it with annotations (risk scores, case ids)
+//! that are recorded on the login's audit and webhook event. A hook that
+//! errors, answers late, or sits behind an open circuit is handled by the
+//! deployment's `on_failure` policy.
+
+use crate::{
+    resilience::{CircuitBreaker, DependencyPolicy},
+    webhooks::{self, EVENT_ID_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+};
+use metrics::counter;
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::{Deserialize, Serialize};
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// What to do when the hook cannot give an answer in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Issue tokens anyway; an outage of the fraud system never blocks sign-in
+    #[default]
+    Open,
+    /// Deny the login
+    Closed,
+}
+
+/// `[issuance_hook]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct IssuanceHookConfig {
+    /// Endpoint receiving a POST per login; unset disables the hook
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Signs each call like a webhook delivery (`X-Webhook-Signature`)
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Time the hook has to answer, including connecting
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+impl Default for IssuanceHookConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            secret: None,
+            timeout_ms: default_timeout_ms(),
+            on_failure: FailurePolicy::Open,
+        }
+    }
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+/// The login about to be issued tokens, as sent to the hook
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuanceRequest {
+    pub user_id: String,
+    /// Audit event of the completed factor, e.g. `magic_link_verified`
+    pub method: &'static str,
+    pub client_id: Option<String>,
+    pub country: Option<String>,
+    pub user_agent: Option<String>,
+    pub flow_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+/// The hook's answer: `{"decision": "allow" | "deny", "reason": ..., "annotations": {...}}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HookResponse {
+    pub decision: Decision,
+    /// Why a login was denied; logged and audited, never shown to the user
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub annotations: Option<serde_json::Value>,
+}
+
+pub type HookFuture<'a> = Pin<Box<dyn Future<Output = Result<HookResponse, String>> + Send + 'a>>;
+
+/// A fraud check consulted before tokens are issued. Implement this to call
+/// a fraud system in-process instead of over HTTP.
+pub trait IssuanceHook: Send + Sync {
+    fn check<'a>(&'a self, request: &'a IssuanceRequest) -> HookFuture<'a>;
+}
+
+/// Calls the hook endpoint with the request as JSON
+pub struct HttpHook {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl HttpHook {
+    pub fn new(url: String, secret: Option<String>, timeout: Duration) -> Self {
+        let client = Client::builder().timeout(timeout).build().unwrap();
+        Self { client, url, secret }
+    }
+}
+
+impl IssuanceHook for HttpHook {
+    fn check<'a>(&'a self, request: &'a IssuanceRequest) -> HookFuture<'a> {
+        Box::pin(async move {
+            let body = serde_json::to_vec(request).map_err(|e| e.to_string())?;
+            let call_id = Uuid::new_v4().to_string();
+            let mut call = self
+                .client
+                .post(&self.url)
+                .header(EVENT_ID_HEADER, &call_id)
+                .header(CONTENT_TYPE, "application/json");
+            if let Some(secret) = &self.secret {
+                let timestamp = chrono::Utc::now().timestamp();
+                call = call
+                    .header(TIMESTAMP_HEADER, timestamp)
+                    .header(SIGNATURE_HEADER, webhooks::sign(secret, &call_id, timestamp, &body));
+            }
+            let response = call.body(body).send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("issuance hook returned {}", response.status()));
+            }
+            response.json().await.map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// What the hook (or its failure policy) decided for one login
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// No hook configured
+    Skipped,
+    Allow(Option<serde_json::Value>),
+    /// The hook failed and the policy is to fail open
+    FailedOpen(String),
+    Deny(String),
+}
+
+impl Outcome {
+    /// Apply the failure policy to a hook result
+    pub fn decide(result: Result<HookResponse, String>, on_failure: FailurePolicy) -> Self {
+        match result {
+            Ok(HookResponse {
+                decision: Decision::Allow,
+                annotations,
+                ..
+            }) => Self::Allow(annotations),
+            Ok(HookResponse {
+                decision: Decision::Deny,
+                reason,
+                ..
+            }) => Self::Deny(reason.unwrap_or_else(|| "denied by issuance hook".to_string())),
+            Err(e) => match on_failure {
+                FailurePolicy::Open => Self::FailedOpen(e),
+                FailurePolicy::Closed => Self::Deny(format!("issuance hook unavailable: {}", e)),
+            },
+        }
+    }
+
+    /// Recorded as `issuance_hook` on the login's audit and webhook event
+    pub fn metadata(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::Skipped => None,
+            Self::Allow(annotations) => Some(serde_json::json!({ "decision": "allow", "annotations": annotations })),
+            Self::FailedOpen(error) => Some(serde_json::json!({ "decision": "allow", "error": error })),
+            Self::Deny(reason) => Some(serde_json::json!({ "decision": "deny", "reason": reason })),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Skipped => "skipped",
+            Self::Allow(_) => "allow",
+            Self::FailedOpen(_) => "failed_open",
+            Self::Deny(_) => "deny",
+        }
+    }
+}
+
+/// The configured hook with its timeout, circuit breaker and failure policy
+pub struct IssuanceGate {
+    hook: Option<Arc<dyn IssuanceHook>>,
+    timeout: Duration,
+    on_failure: FailurePolicy,
+    breaker: CircuitBreaker,
+}
+
+impl IssuanceGate {
+    pub fn new(cfg: &IssuanceHookConfig, policy: DependencyPolicy) -> Self {
+        let timeout = Duration::from_millis(cfg.timeout_ms);
+        let hook = cfg
+            .url
+            .clone()
+            .map(|url| Arc::new(HttpHook::new(url, cfg.secret.clone(), timeout)) as Arc<dyn IssuanceHook>);
+        if hook.is_some() {
+            info!("Issuance hook enabled ({}ms, fail {:?})", cfg.timeout_ms, cfg.on_failure);
+        }
+        Self {
+            hook,
+            timeout,
+            on_failure: cfg.on_failure,
+            breaker: CircuitBreaker::new("issuance_hook", policy),
+        }
+    }
+
+    /// Consult `hook` instead of the configured endpoint
+    pub fn with_hook(mut self, hook: Arc<dyn IssuanceHook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Ask the hook about a login. Called once, without retries: the user is waiting.
+    pub async fn check(&self, request: &IssuanceRequest) -> Outcome {
+        let Some(hook) = &self.hook else {
+            return Outcome::Skipped;
+        };
+        let result = if !self.breaker.try_acquire() {
+            Err("circuit open".to_string())
+        } else {
+            let result = match tokio::time::timeout(self.timeout, hook.check(request)).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("no answer within {}ms", self.timeout.as_millis())),
+            };
+            match &result {
+                Ok(_) => self.breaker.on_success(),
+                Err(_) => self.breaker.on_failure(),
+            }
+            result
+        };
+        let outcome = Outcome::decide(result, self.on_failure);
+        match &outcome {
+            Outcome::FailedOpen(e) => warn!(user_id = %request.user_id, "issuance hook failed, issuing anyway: {}", e),
+            Outcome::Deny(reason) => info!(user_id = %request.user_id, "issuance hook denied login: {}", reason),
+            _ => {}
+        }
+        counter!("issuance_hook_decisions_total", "outcome" => outcome.label()).increment(1);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(json: &str) -> Result<HookResponse, String> {
+        Ok(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn decisions_and_failure_policy() {
+        let allow = answer(r#"{"decision":"allow","annotations":{"risk":12}}"#);
+        assert_eq!(
+            Outcome::decide(allow, FailurePolicy::Closed),
+            Outcome::Allow(Some(serde_json::json!({ "risk": 12 })))
+        );
+        let deny = answer(r#"{"decision":"deny","reason":"velocity"}"#);
+        assert_eq!(Outcome::decide(deny, FailurePolicy::Open), Outcome::Deny("velocity".into()));
+
+        let down = || Err("timeout".to_string());
+        assert_eq!(Outcome::decide(down(), FailurePolicy::Open), Outcome::FailedOpen("timeout".into()));
+        assert!(matches!(Outcome::decide(down(), FailurePolicy::Closed), Outcome::Deny(_)));
+        assert_eq!(Outcome::Skipped.metadata(), None);
+    }
+
+    struct Slow;
+
+    impl IssuanceHook for Slow {
+        fn check<'a>(&'a self, _: &'a IssuanceRequest) -> HookFuture<'a> {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Err("unreachable".to_string())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_hook_hits_the_timeout() {
+        let cfg = IssuanceHookConfig {
+            timeout_ms: 20,
+            on_failure: FailurePolicy::Closed,
+            ..Default::default()
+        };
+        let gate = IssuanceGate::new(&cfg, DependencyPolicy::default()).with_hook(Arc::new(Slow));
+        let request = IssuanceRequest {
+            user_id: "u1".into(),
+            method: "totp_verified",
+            client_id: None,
+            country: None,
+            user_agent: None,
+            flow_id: None,
+        };
+        assert!(matches!(gate.check(&request).await, Outcome::Deny(reason) if reason.contains("20ms")));
+    }
+}
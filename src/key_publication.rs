@@ -0,0 +1,190 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+
+/// `[key_publication]` configuration: how signing keys appear in JWKS and
+/// discovery documents relative to when they sign or stop signing tokens
+#[derive(Debug, Deserialize, Clone)]
+pub struct KeyPublicationConfig {
+    /// Publish a key this long before it starts signing, so every cache has
+    /// it by the time tokens carrying its `kid` appear
+    #[serde(default = "default_prepublish_seconds")]
+    pub prepublish_seconds: i64,
+    /// Keep publishing a retired key this long, covering tokens it signed
+    #[serde(default = "default_overlap_seconds")]
+    pub overlap_seconds: i64,
+    /// Upper bound for `Cache-Control: max-age`
+    #[serde(default = "default_max_age_seconds")]
+    pub max_age_seconds: i64,
+}
+
+impl Default for KeyPublicationConfig {
+    fn default() -> Self {
+        Self {
+            prepublish_seconds: default_prepublish_seconds(),
+            overlap_seconds: default_overlap_seconds(),
+            max_age_seconds: default_max_age_seconds(),
+        }
+    }
+}
+
+fn default_prepublish_seconds() -> i64 {
+    86400
+}
+
+fn default_overlap_seconds() -> i64 {
+    86400
+}
+
+fn default_max_age_seconds() -> i64 {
+    3600
+}
+
+/// Lifecycle of one signing key (unix seconds)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyWindow {
+    /// Starts signing new tokens
+    pub activates_at: i64,
+    /// Stops signing new tokens (`None` = current key with no successor yet)
+    pub retires_at: Option<i64>,
+}
+
+impl KeyPublicationConfig {
+    /// Whether a key belongs in published key sets at `now`
+    pub fn is_published(&self, key: KeyWindow, now: i64) -> bool {
+        let from = key.activates_at - self.prepublish_seconds;
+        let until = key.retires_at.map(|r| r + self.overlap_seconds);
+        now >= from && until.is_none_or(|u| now < u)
+    }
+
+    /// Whether a key may sign tokens at `now`. A key only signs once its
+    /// pre-publication period has passed, even if scheduled earlier.
+    pub fn is_signing(&self, key: KeyWindow, now: i64) -> bool {
+        now >= key.activates_at && key.retires_at.is_none_or(|r| now < r)
+    }
+
+    /// `max-age` for a key set served at `now`: never past the next moment
+    /// a key enters or leaves the set, so caches cannot serve a stale set
+    /// across a rotation boundary
+    pub fn max_age(&self, keys: &[KeyWindow], now: i64) -> i64 {
+        keys.iter()
+            .flat_map(|k| {
+                [
+                    Some(k.activates_at - self.prepublish_seconds),
+                    k.retires_at.map(|r| r + self.overlap_seconds),
+                ]
+            })
+            .flatten()
+            .filter(|t| *t > now)
+            .map(|t| t - now)
+            .fold(self.max_age_seconds, i64::min)
+            .max(0)
+    }
+}
+
+/// Strong ETag for a serialized document
+pub fn etag(body: &[u8]) -> String {
+    format!("\"{}\"", &HEXLOWER.encode(&crate::crypto::sha256(body))[..32])
+}
+
+/// Serve a public, CDN-cacheable JSON document (JWKS, discovery).
+///
+/// Sets `Cache-Control` and a content-derived `ETag`, and answers a matching
+/// `If-None-Match` with `304 Not Modified`.
+pub fn cacheable_json<T: Serialize>(request: &HeaderMap, document: &T, max_age: i64) -> Response {
+    let body = match serde_json::to_vec(document) {
+        Ok(b) => b,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let tag = etag(&body);
+    let cache_control = format!("public, max-age={}, must-revalidate", max_age.max(0));
+
+    let not_modified = request
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim() == tag || t.trim() == "*"));
+
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+    };
+    let headers = response.headers_mut();
+    if let Ok(v) = HeaderValue::from_str(&tag) {
+        headers.insert(header::ETAG, v);
+    }
+    if let Ok(v) = HeaderValue::from_str(&cache_control) {
+        headers.insert(header::CACHE_CONTROL, v);
+    }
+    headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> KeyPublicationConfig {
+        KeyPublicationConfig {
+            prepublish_seconds: 100,
+            overlap_seconds: 50,
+            max_age_seconds: 1000,
+        }
+    }
+
+    #[test]
+    fn next_key_is_published_before_it_signs() {
+        let next = KeyWindow {
+            activates_at: 1_000,
+            retires_at: None,
+        };
+        assert!(!cfg().is_published(next, 899));
+        assert!(cfg().is_published(next, 900));
+        assert!(!cfg().is_signing(next, 950));
+        assert!(cfg().is_signing(next, 1_000));
+    }
+
+    #[test]
+    fn retired_key_stays_published_for_overlap() {
+        let old = KeyWindow {
+            activates_at: 0,
+            retires_at: Some(1_000),
+        };
+        assert!(!cfg().is_signing(old, 1_000));
+        assert!(cfg().is_published(old, 1_049));
+        assert!(!cfg().is_published(old, 1_050));
+    }
+
+    #[test]
+    fn max_age_stops_at_next_boundary() {
+        let keys = [
+            KeyWindow {
+                activates_at: 0,
+                retires_at: Some(1_000),
+            },
+            KeyWindow {
+                activates_at: 1_000,
+                retires_at: None,
+            },
+        ];
+        // the retired key drops out at 1_050
+        assert_eq!(cfg().max_age(&keys, 1_000), 50);
+        assert_eq!(cfg().max_age(&keys, 2_000), 1000);
+    }
+
+    #[test]
+    fn matching_etag_returns_not_modified() {
+        let doc = serde_json::json!({ "keys": [] });
+        let first = cacheable_json(&HeaderMap::new(), &doc, 60);
+        assert_eq!(first.status(), StatusCode::OK);
+        let tag = first.headers()[header::ETAG].clone();
+        assert_eq!(first.headers()[header::CACHE_CONTROL], "public, max-age=60, must-revalidate");
+
+        let mut conditional = HeaderMap::new();
+        conditional.insert(header::IF_NONE_MATCH, tag);
+        assert_eq!(cacheable_json(&conditional, &doc, 60).status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,256 @@
+//! Per-stage timings of auth requests.
+//!
+//! The `trace` middleware gives each auth request a timing record; the
+//! service adds to it around the stages that dominate login latency
+//! (database lookups, token minting, email enqueue, WebAuthn verification).
+//! When the request finishes, every stage is exported as
+//! `auth_stage_duration_seconds{stage}`, the breakdown is logged (at `warn`
+//! once the request exceeds `budget_ms`), and clients in
+//! `debug_header_allow` receive it as a `Server-Timing` header. A sampled
+//! `traceparent` becomes the current trace for the request (see `exemplars`).
+
+use crate::{exemplars, ip_access::Cidr, metrics::MetricsRecorder, middleware::client_ip};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    fmt::Write,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Response header carrying the breakdown for allowlisted clients
+pub const SERVER_TIMING_HEADER: &str = "Server-Timing";
+
+/// `[latency]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct LatencyConfig {
+    /// Requests slower than this are logged at `warn` with their stages
+    #[serde(default = "default_budget_ms")]
+    pub budget_ms: u64,
+    /// Client networks (CIDR) that receive the `Server-Timing` header
+    #[serde(default)]
+    pub debug_header_allow: Vec<String>,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        Self {
+            budget_ms: default_budget_ms(),
+            debug_header_allow: Vec::new(),
+        }
+    }
+}
+
+fn default_budget_ms() -> u64 {
+    500
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    DbLookup,
+    TokenMint,
+    EmailEnqueue,
+    WebauthnVerify,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Self::DbLookup, Self::TokenMint, Self::EmailEnqueue, Self::WebauthnVerify];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DbLookup => "db_lookup",
+            Self::TokenMint => "token_mint",
+            Self::EmailEnqueue => "email_enqueue",
+            Self::WebauthnVerify => "webauthn_verify",
+        }
+    }
+}
+
+/// Time spent per stage during one request
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Timings {
+    stages: [Duration; 4],
+}
+
+impl Timings {
+    fn slot(stage: Stage) -> usize {
+        Stage::ALL.iter().position(|s| *s == stage).unwrap_or(0)
+    }
+
+    pub fn add(&mut self, stage: Stage, elapsed: Duration) {
+        self.stages[Self::slot(stage)] += elapsed;
+    }
+
+    pub fn get(&self, stage: Stage) -> Duration {
+        self.stages[Self::slot(stage)]
+    }
+
+    /// Stages that took any time, in pipeline order
+    pub fn stages(&self) -> impl Iterator<Item = (Stage, Duration)> + '_ {
+        Stage::ALL.into_iter().map(move |s| (s, self.get(s))).filter(|(_, d)| !d.is_zero())
+    }
+
+    /// `Server-Timing` value, e.g. `db_lookup;dur=1.20, total;dur=4.75`
+    pub fn server_timing(&self, total: Duration) -> String {
+        let mut value = String::new();
+        for (stage, elapsed) in self.stages() {
+            let _ = write!(value, "{};dur={:.2}, ", stage.as_str(), elapsed.as_secs_f64() * 1000.0);
+        }
+        let _ = write!(value, "total;dur={:.2}", total.as_secs_f64() * 1000.0);
+        value
+    }
+}
+
+tokio::task_local! {
+    static TIMINGS: RefCell<Timings>;
+}
+
+/// Add `elapsed` to the current request's record; a no-op outside `trace`
+pub fn record(stage: Stage, elapsed: Duration) {
+    let _ = TIMINGS.try_with(|t| t.borrow_mut().add(stage, elapsed));
+}
+
+/// Run `f`, charging its duration to `stage`
+pub fn time<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    record(stage, started.elapsed());
+    result
+}
+
+/// Charges the time until it is dropped to a stage; see [`start`]
+pub struct Timer {
+    stage: Stage,
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        record(self.stage, self.started.elapsed());
+    }
+}
+
+#[must_use = "the stage is timed until the timer is dropped"]
+pub fn start(stage: Stage) -> Timer {
+    Timer {
+        stage,
+        started: Instant::now(),
+    }
+}
+
+/// Middleware state
+pub struct LatencyTracer {
+    budget: Duration,
+    debug_allow: Vec<Cidr>,
+    trust_forwarded_for: bool,
+}
+
+impl LatencyTracer {
+    pub fn new(cfg: &LatencyConfig, trust_forwarded_for: bool) -> Self {
+        let debug_allow = cfg
+            .debug_header_allow
+            .iter()
+            .filter_map(|c| match c.parse() {
+                Ok(cidr) => Some(cidr),
+                Err(e) => {
+                    warn!("Ignoring latency debug_header_allow entry: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            budget: Duration::from_millis(cfg.budget_ms),
+            debug_allow,
+            trust_forwarded_for,
+        }
+    }
+}
+
+/// Collect stage timings for an auth request, then export and log them
+pub async fn trace(
+    State(tracer): State<Arc<LatencyTracer>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = peer.map(|ConnectInfo(addr)| addr);
+    let debug_header = !tracer.debug_allow.is_empty()
+        && client_ip(request.headers(), peer, tracer.trust_forwarded_for)
+            .and_then(|ip| ip.parse().ok())
+            .is_some_and(|ip| tracer.debug_allow.iter().any(|c| c.contains(&ip)));
+    let path = request.uri().path().to_string();
+    let trace_id = request
+        .headers()
+        .get(exemplars::TRACEPARENT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(exemplars::sampled_trace_id)
+        .map(str::to_string);
+    let started = Instant::now();
+    let (mut response, timings) = TIMINGS
+        .scope(
+            RefCell::new(Timings::default()),
+            exemplars::scope(trace_id.clone(), async move {
+                let response = next.run(request).await;
+                (response, TIMINGS.with(|t| t.take()))
+            }),
+        )
+        .await;
+    let total = started.elapsed();
+
+    for (stage, elapsed) in timings.stages() {
+        MetricsRecorder::record_stage_duration(stage.as_str(), elapsed.as_secs_f64(), trace_id.as_deref());
+    }
+    let breakdown = timings.server_timing(total);
+    let status = response.status().as_u16();
+    if total > tracer.budget {
+        warn!(path = %path, status, stages = %breakdown, "Auth request over latency budget");
+    } else {
+        debug!(path = %path, status, stages = %breakdown, "Auth request timings");
+    }
+    if debug_header {
+        if let Ok(value) = HeaderValue::from_str(&breakdown) {
+            response.headers_mut().insert(SERVER_TIMING_HEADER, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stages_accumulate_within_a_request() {
+        let timings = TIMINGS
+            .scope(RefCell::new(Timings::default()), async {
+                record(Stage::DbLookup, Duration::from_millis(2));
+                time(Stage::DbLookup, || ());
+                let timer = start(Stage::TokenMint);
+                std::thread::sleep(Duration::from_millis(1));
+                drop(timer);
+                TIMINGS.with(|t| t.take())
+            })
+            .await;
+        assert!(timings.get(Stage::DbLookup) >= Duration::from_millis(2));
+        assert_eq!(timings.get(Stage::EmailEnqueue), Duration::ZERO);
+        assert_eq!(timings.stages().count(), 2);
+
+        // outside a traced request recording is a no-op
+        record(Stage::DbLookup, Duration::from_millis(1));
+
+        let mut timings = Timings::default();
+        timings.add(Stage::WebauthnVerify, Duration::from_micros(1500));
+        assert_eq!(
+            timings.server_timing(Duration::from_millis(3)),
+            "webauthn_verify;dur=1.50, total;dur=3.00"
+        );
+    }
+}
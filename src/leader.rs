@@ -0,0 +1,168 @@
+//! Leader election for singleton background jobs.
+//!
+//! Every replica runs the same job loops, but before each run a job takes a
+//! lease row in `job_leases`. Only the lease holder runs; it renews the lease
+//! on every tick, so if it dies the lease lapses and another replica takes
+//! over at its next tick. Leases outlive the job interval by a grace period,
+//! which keeps replicas whose timers are out of phase from running the same
+//! tick twice. While [maintenance](crate::maintenance) is read-only no job
+//! runs anywhere, and no lease is taken or renewed.
+
+use crate::{db::Database, maintenance::MaintenanceMode};
+use rusqlite::params;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// `[leader]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct LeaderConfig {
+    /// Elect one replica per job. Disable only when exactly one instance runs.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Identity of this replica; defaults to `HOSTNAME` (the pod name on
+    /// Kubernetes) plus the process id
+    #[serde(default)]
+    pub node_id: Option<String>,
+    /// How long past a job's interval a lease stays valid without renewal
+    #[serde(default = "default_grace_seconds")]
+    pub grace_seconds: u64,
+}
+
+impl Default for LeaderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            node_id: None,
+            grace_seconds: default_grace_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_grace_seconds() -> u64 {
+    30
+}
+
+/// Take or renew the lease on `job` for `holder`. Succeeds when the lease is
+/// free, expired, or already held by `holder`.
+pub fn try_acquire(db: &Database, job: &str, holder: &str, ttl_seconds: i64) -> Result<bool, rusqlite::Error> {
+    let now = Database::now_ts();
+    let changed = db.conn().execute(
+        "INSERT INTO job_leases (job, holder, acquired_at, expires_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(job) DO UPDATE SET
+             acquired_at = CASE WHEN job_leases.holder = excluded.holder THEN job_leases.acquired_at ELSE excluded.acquired_at END,
+             holder = excluded.holder,
+             expires_at = excluded.expires_at
+         WHERE job_leases.holder = excluded.holder OR job_leases.expires_at <= ?3",
+        params![job, holder, now, now + ttl_seconds],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Give up `holder`'s leases so another replica can take over right away
+pub fn release(db: &Database, holder: &str) -> Result<usize, rusqlite::Error> {
+    db.conn().execute("DELETE FROM job_leases WHERE holder = ?1", params![holder])
+}
+
+/// This replica's view of the job leases
+pub struct LeaderElection {
+    db: Arc<Database>,
+    node_id: String,
+    enabled: bool,
+    grace: Duration,
+    /// Jobs this replica currently leads, to log changes of leadership once
+    leading: Mutex<HashSet<String>>,
+    /// Read-only maintenance pauses every job
+    maintenance: Option<Arc<MaintenanceMode>>,
+    /// Jobs were last skipped for read-only mode, to log pausing once
+    paused: AtomicBool,
+}
+
+impl LeaderElection {
+    pub fn new(db: Arc<Database>, cfg: &LeaderConfig) -> Self {
+        let node_id = cfg.node_id.clone().unwrap_or_else(|| match std::env::var("HOSTNAME") {
+            Ok(host) if !host.is_empty() => format!("{}:{}", host, std::process::id()),
+            _ => Uuid::new_v4().to_string(),
+        });
+        Self {
+            db,
+            node_id,
+            enabled: cfg.enabled,
+            grace: Duration::from_secs(cfg.grace_seconds),
+            leading: Mutex::new(HashSet::new()),
+            maintenance: None,
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    /// Skip every job while `maintenance` is read-only
+    pub fn with_maintenance(mut self, maintenance: Arc<MaintenanceMode>) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Whether this replica should run `job`, which runs every `every`.
+    /// Call once per tick: a held lease is renewed by the call. False for
+    /// every job while read-only, so background jobs stop writing too.
+    pub fn lead(&self, job: &str, every: Duration) -> bool {
+        let read_only = self.maintenance.as_ref().is_some_and(|m| m.is_read_only());
+        if self.paused.swap(read_only, Ordering::Relaxed) != read_only {
+            if read_only {
+                info!("Read-only mode: background jobs paused");
+            } else {
+                info!("Read-only mode over: background jobs resumed");
+            }
+        }
+        if read_only {
+            return false;
+        }
+        if !self.enabled {
+            return true;
+        }
+        let ttl = (every + self.grace).as_secs().max(1) as i64;
+        let leads = match try_acquire(&self.db, job, &self.node_id, ttl) {
+            Ok(leads) => leads,
+            Err(e) => {
+                // without the lease table nobody can tell who leads; skip rather than duplicate
+                warn!("Leader election for {} failed: {}", job, e);
+                false
+            }
+        };
+        let mut leading = self.leading.lock().unwrap();
+        if leads && leading.insert(job.to_string()) {
+            info!("{} leads job {}", self.node_id, job);
+        } else if !leads && leading.remove(job) {
+            info!("{} no longer leads job {}", self.node_id, job);
+        }
+        leads
+    }
+
+    /// Release every lease this replica holds; call on shutdown
+    pub fn release_all(&self) {
+        if !self.enabled {
+            return;
+        }
+        match release(&self.db, &self.node_id) {
+            Ok(0) => {}
+            Ok(n) => info!("Released {} job leases", n),
+            Err(e) => warn!("Failed to release job leases: {}", e),
+        }
+        self.leading.lock().unwrap().clear();
+    }
+}
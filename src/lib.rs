@@ -0,0 +1,96 @@
+//! Passwordless authentication server library.
+//!
+//! The `passwordless-auth` binary is a thin wrapper around these modules;
+//! embedders can mount the auth flows in their own stack via [`service`] and
+//! the framework [`adapters`].
+
+pub mod aaguids;
+pub mod abuse_reports;
+pub mod action_links;
+pub mod address;
+pub mod adapters;
+pub mod admin;
+pub mod admin_keys;
+pub mod admin_users;
+pub mod api_version;
+pub mod applications;
+pub mod attempt_token;
+pub mod audit;
+pub mod auth_context;
+pub mod bootstrap;
+pub mod broadcasts;
+pub mod canaries;
+pub mod challenge_store;
+pub mod chaos;
+pub mod client_registration;
+pub mod compaction;
+pub mod compression;
+pub mod config;
+pub mod consents;
+pub mod cors;
+pub mod credential_resets;
+pub mod crypto;
+pub mod db;
+pub mod debug_sampling;
+pub mod deliverability;
+pub mod device;
+pub mod device_authorization;
+pub mod dlq;
+pub mod doctor;
+pub mod email;
+pub mod email_queue;
+pub mod email_quota;
+pub mod email_templates;
+pub mod error;
+pub mod exemplars;
+pub mod extractors;
+pub mod geo_policy;
+pub mod health;
+pub mod html;
+pub mod ip_access;
+pub mod ip_bans;
+pub mod issuance_hook;
+pub mod leader;
+pub mod link_urls;
+pub mod jwt;
+pub mod latency;
+pub mod key_publication;
+pub mod key_rotation;
+pub mod load_shed;
+pub mod magic_link;
+pub mod maintenance;
+pub mod metrics;
+pub mod middleware;
+pub mod models;
+pub mod notifications;
+pub mod oauth;
+pub mod outbox;
+pub mod passkey_nudge;
+pub mod profile;
+pub mod rate_limit;
+pub mod redirects;
+pub mod regions;
+pub mod repository;
+pub mod resilience;
+pub mod revocation;
+pub mod routes;
+pub mod runtime_info;
+pub mod schema;
+pub mod secret_scan;
+pub mod security_notices;
+pub mod seed;
+pub mod service;
+pub mod session;
+pub mod session_expiry;
+pub mod stale_accounts;
+pub mod storage;
+pub mod subjects;
+pub mod suppression;
+pub mod totp;
+pub mod transport;
+pub mod user_agent;
+#[cfg(feature = "verifier")]
+pub mod verifier;
+pub mod webauthn;
+pub mod webhook_secrets;
+pub mod webhooks;
@@ -0,0 +1,214 @@
+//! Registered base URLs for links sent by email, per application and environment.
+//!
+//! `[[link_base_urls]]` entries register where magic links and action links
+//! may point, optionally restricted to one environment (the `APP_PROFILE`)
+//! and one application. The URL is chosen and checked when an email is
+//! about to be sent: the most specific registration for the calling
+//! application and the running environment wins, and a URL whose host is
+//! registered for another environment is refused, so a staging deployment
+//! running with production's `config.toml` cannot email production links.
+//! The global `magic_link_base_url` / `action_link_base_url` are used only
+//! while no entry registers that kind of link.
+
+use crate::config::{Config, Profile};
+use reqwest::Url;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// One `[[link_base_urls]]` entry
+#[derive(Debug, Deserialize, Clone)]
+pub struct LinkBaseUrl {
+    /// `dev`, `staging` or `prod`; unset registers the URLs for every environment
+    #[serde(default)]
+    pub environment: Option<Profile>,
+    /// Application the URLs belong to; unset for every application without its own
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub magic_link_base_url: Option<String>,
+    #[serde(default)]
+    pub action_link_base_url: Option<String>,
+}
+
+impl LinkBaseUrl {
+    fn url(&self, kind: LinkKind) -> Option<&str> {
+        match kind {
+            LinkKind::Magic => self.magic_link_base_url.as_deref(),
+            LinkKind::Action => self.action_link_base_url.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Magic,
+    Action,
+}
+
+impl LinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Magic => "magic_link_base_url",
+            Self::Action => "action_link_base_url",
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LinkUrlError {
+    #[error("no {kind} registered for client {client:?} in environment {environment:?}")]
+    NotRegistered {
+        kind: &'static str,
+        client: Option<String>,
+        environment: Option<&'static str>,
+    },
+    #[error("{url} is registered for {other} and not for environment {environment:?}")]
+    OtherEnvironment {
+        url: String,
+        other: &'static str,
+        environment: Option<&'static str>,
+    },
+    #[error("{0} is not a URL")]
+    Invalid(String),
+}
+
+fn host(url: &str) -> Result<(String, Option<u16>), LinkUrlError> {
+    let parsed = Url::parse(url).map_err(|_| LinkUrlError::Invalid(url.to_string()))?;
+    let host = parsed.host_str().ok_or_else(|| LinkUrlError::Invalid(url.to_string()))?;
+    Ok((host.to_ascii_lowercase(), parsed.port_or_known_default()))
+}
+
+/// Base URL for a `kind` link emailed on behalf of `client_id` by this
+/// deployment, checked against the registrations of other environments
+pub fn resolve<'a>(cfg: &'a Config, kind: LinkKind, client_id: Option<&str>) -> Result<&'a str, LinkUrlError> {
+    let environment = cfg.profile;
+    let mut best: Option<(u8, &LinkBaseUrl)> = None;
+    for entry in cfg.link_base_urls.iter().filter(|e| e.url(kind).is_some()) {
+        let client_rank = match (&entry.client_id, client_id) {
+            (None, _) => 0,
+            (Some(id), Some(client)) if id == client => 2,
+            _ => continue,
+        };
+        let environment_rank = match entry.environment {
+            None => 0,
+            Some(e) if Some(e) == environment => 1,
+            Some(_) => continue,
+        };
+        let rank = client_rank + environment_rank;
+        if best.is_none_or(|(b, _)| rank > b) {
+            best = Some((rank, entry));
+        }
+    }
+    let (url, pinned) = match best {
+        Some((_, entry)) => (entry.url(kind).unwrap_or_default(), entry.environment.is_some()),
+        None if !cfg.link_base_urls.iter().any(|e| e.url(kind).is_some()) => match kind {
+            LinkKind::Magic => (cfg.magic_link_base_url.as_str(), false),
+            LinkKind::Action => (cfg.action_link_base_url.as_str(), false),
+        },
+        None => {
+            return Err(LinkUrlError::NotRegistered {
+                kind: kind.as_str(),
+                client: client_id.map(str::to_string),
+                environment: environment.map(|e| e.as_str()),
+            })
+        }
+    };
+    let origin = host(url)?;
+    if !pinned {
+        let foreign = cfg.link_base_urls.iter().find_map(|entry| {
+            let other = entry.environment.filter(|e| Some(*e) != environment)?;
+            let urls = [LinkKind::Magic, LinkKind::Action].map(|k| entry.url(k));
+            urls.into_iter()
+                .flatten()
+                .any(|u| host(u).is_ok_and(|h| h == origin))
+                .then_some(other)
+        });
+        if let Some(other) = foreign {
+            return Err(LinkUrlError::OtherEnvironment {
+                url: url.to_string(),
+                other: other.as_str(),
+                environment: environment.map(|e| e.as_str()),
+            });
+        }
+    }
+    Ok(url)
+}
+
+/// `(key, url)` of every base URL registered for `environment`, for
+/// configuration checks
+pub fn registered(cfg: &Config, environment: Option<Profile>) -> Vec<(&'static str, &str)> {
+    cfg.link_base_urls
+        .iter()
+        .filter(|entry| entry.environment.is_none_or(|e| Some(e) == environment))
+        .flat_map(|entry| [LinkKind::Magic, LinkKind::Action].map(|k| entry.url(k).map(|u| (k.as_str(), u))))
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_registration_wins_and_other_environments_are_refused() {
+        let base = r#"
+jwt_secret = "jwt-secret-value-1234567890"
+access_token_expiry_seconds = 900
+refresh_token_expiry_seconds = 604800
+magic_link_expiry_seconds = 600
+magic_link_base_url = "https://auth.example.com/verify/magic"
+smtp_host = "smtp.example.com"
+smtp_port = 587
+smtp_username = "mailer"
+smtp_password = "smtp-password-value"
+email_from = "no-reply@example.com"
+webauthn_rp_id = "example.com"
+webauthn_origin = "https://example.com"
+webauthn_rp_name = "Example"
+database_path = ":memory:"
+
+[[link_base_urls]]
+environment = "prod"
+magic_link_base_url = "https://auth.example.com/verify/magic"
+
+[[link_base_urls]]
+environment = "staging"
+magic_link_base_url = "https://auth.staging.example.com/verify/magic"
+
+[[link_base_urls]]
+environment = "staging"
+client_id = "mobile"
+magic_link_base_url = "https://m.staging.example.com/verify/magic"
+"#;
+        let staging = Config::from_layers(base, None, Some(Profile::Staging)).unwrap();
+        assert_eq!(
+            resolve(&staging, LinkKind::Magic, None).unwrap(),
+            "https://auth.staging.example.com/verify/magic"
+        );
+        assert_eq!(
+            resolve(&staging, LinkKind::Magic, Some("mobile")).unwrap(),
+            "https://m.staging.example.com/verify/magic"
+        );
+        // no action link registrations: the global URL is used
+        assert_eq!(resolve(&staging, LinkKind::Action, None).unwrap(), staging.action_link_base_url);
+
+        let dev = Config::from_layers(base, None, Some(Profile::Dev)).unwrap();
+        assert!(matches!(
+            resolve(&dev, LinkKind::Magic, None),
+            Err(LinkUrlError::NotRegistered { .. })
+        ));
+
+        // a copied production URL registered for every environment is refused outside prod
+        let copied = format!(
+            "{}\n[[link_base_urls]]\nmagic_link_base_url = \"https://auth.example.com/verify/magic\"\n",
+            base.replace("environment = \"staging\"", "environment = \"dev\"")
+        );
+        let staging = Config::from_layers(&copied, None, Some(Profile::Staging)).unwrap();
+        assert!(matches!(
+            resolve(&staging, LinkKind::Magic, None),
+            Err(LinkUrlError::OtherEnvironment { other: "prod", .. })
+        ));
+        let prod = Config::from_layers(&copied, None, Some(Profile::Prod)).unwrap();
+        assert_eq!(resolve(&prod, LinkKind::Magic, None).unwrap(), "https://auth.example.com/verify/magic");
+    }
+}
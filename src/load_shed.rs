@@ -0,0 +1,230 @@
+use crate::metrics::MetricsRecorder;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// `[load_shedding]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct LoadSheddingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Auth requests handled concurrently at most
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+    /// Share of `max_in_flight` that `normal` requests may fill
+    #[serde(default = "default_normal_percent")]
+    pub normal_percent: usize,
+    /// Share of `max_in_flight` that `low` requests (new sign-ins) may fill
+    #[serde(default = "default_low_percent")]
+    pub low_percent: usize,
+    /// How long a `critical` request waits for a free slot before it is shed
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    /// `Retry-After` sent with 503 responses
+    #[serde(default = "default_retry_after_seconds")]
+    pub retry_after_seconds: u64,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_in_flight: default_max_in_flight(),
+            normal_percent: default_normal_percent(),
+            low_percent: default_low_percent(),
+            queue_timeout_ms: default_queue_timeout_ms(),
+            retry_after_seconds: default_retry_after_seconds(),
+        }
+    }
+}
+
+fn default_max_in_flight() -> usize {
+    256
+}
+
+fn default_normal_percent() -> usize {
+    80
+}
+
+fn default_low_percent() -> usize {
+    50
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    500
+}
+
+fn default_retry_after_seconds() -> u64 {
+    5
+}
+
+/// Who loses first when the server is overloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Existing users keeping or finishing a session; queued briefly, shed last
+    Critical,
+    Normal,
+    /// Starting a new sign-in (sends email or SMS); shed first
+    Low,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Critical => "critical",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+/// Priority class of an auth route
+pub fn classify(path: &str) -> Priority {
+    match path {
+        "/token/refresh"
+        | "/token/verify-batch"
+        | "/oauth/introspect"
+        | "/verify/magic"
+        | "/totp/verify"
+        | "/webauthn/login/complete" => Priority::Critical,
+        "/request/magic"
+        | "/email/verify/request"
+        | "/webauthn/register/options"
+        | "/webauthn/login/options"
+        | "/device/code" => Priority::Low,
+        _ => Priority::Normal,
+    }
+}
+
+/// Concurrency limit that reserves headroom for higher priority classes.
+///
+/// `normal` and `low` requests are admitted only while the number of free
+/// slots stays above what their share leaves for the classes above them, so
+/// as load rises new sign-ins are rejected first and refreshes last.
+pub struct LoadShedder {
+    cfg: LoadSheddingConfig,
+    slots: Arc<Semaphore>,
+}
+
+impl LoadShedder {
+    pub fn new(cfg: &LoadSheddingConfig) -> Self {
+        Self {
+            cfg: cfg.clone(),
+            slots: Arc::new(Semaphore::new(cfg.max_in_flight)),
+        }
+    }
+
+    /// Free slots a request of this class must leave for higher classes
+    fn reserved(&self, priority: Priority) -> usize {
+        let max = self.cfg.max_in_flight;
+        let share = match priority {
+            Priority::Critical => return 0,
+            Priority::Normal => self.cfg.normal_percent,
+            Priority::Low => self.cfg.low_percent,
+        };
+        max - max * share.min(100) / 100
+    }
+
+    /// A slot for the request, or `None` when it should be shed
+    pub async fn admit(&self, priority: Priority) -> Option<OwnedSemaphorePermit> {
+        if priority == Priority::Critical {
+            let wait = Duration::from_millis(self.cfg.queue_timeout_ms);
+            return tokio::time::timeout(wait, self.slots.clone().acquire_owned()).await.ok()?.ok();
+        }
+        if self.slots.available_permits() <= self.reserved(priority) {
+            return None;
+        }
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.cfg.max_in_flight - self.slots.available_permits()
+    }
+
+    /// Admit a request to the auth route `path`, or count and log it as shed
+    pub async fn admit_request(&self, path: &str) -> Admission {
+        if !self.cfg.enabled {
+            return Admission::Unlimited;
+        }
+        let priority = classify(path);
+        match self.admit(priority).await {
+            Some(slot) => {
+                MetricsRecorder::record_requests_in_flight(self.in_flight());
+                Admission::Admitted(slot)
+            }
+            None => {
+                warn!("Shedding {} request to {} ({} in flight)", priority.as_str(), path, self.in_flight());
+                MetricsRecorder::record_request_shed(priority.as_str());
+                Admission::Shed
+            }
+        }
+    }
+
+    /// `Retry-After` sent with shed requests
+    pub fn retry_after_seconds(&self) -> u64 {
+        self.cfg.retry_after_seconds
+    }
+}
+
+/// Outcome of [`LoadShedder::admit_request`]
+pub enum Admission {
+    /// Load shedding is off
+    Unlimited,
+    /// Holds a slot until dropped
+    Admitted(OwnedSemaphorePermit),
+    /// Reject with 503 + `Retry-After`
+    Shed,
+}
+
+/// Told to shed requests
+pub const BUSY_MESSAGE: &str = "Server is busy. Please try again shortly.";
+
+/// Reject auth requests with 503 + `Retry-After` once their class's share is used up
+pub async fn shed(State(shedder): State<Arc<LoadShedder>>, request: Request, next: Next) -> Response {
+    let _slot = match shedder.admit_request(request.uri().path()).await {
+        Admission::Shed => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, shedder.retry_after_seconds().to_string())],
+                BUSY_MESSAGE,
+            )
+                .into_response()
+        }
+        admission => admission,
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn low_priority_is_shed_before_critical() {
+        let shedder = LoadShedder::new(&LoadSheddingConfig {
+            enabled: true,
+            max_in_flight: 4,
+            queue_timeout_ms: 10,
+            ..Default::default()
+        });
+        let a = shedder.admit(Priority::Low).await.unwrap();
+        let b = shedder.admit(Priority::Low).await.unwrap();
+        // 2 of 4 slots used: low (50%) is full, normal (80% = 3) is not
+        assert!(shedder.admit(Priority::Low).await.is_none());
+        let c = shedder.admit(Priority::Normal).await.unwrap();
+        assert!(shedder.admit(Priority::Normal).await.is_none());
+        let d = shedder.admit(Priority::Critical).await.unwrap();
+        assert!(shedder.admit(Priority::Critical).await.is_none());
+        drop((a, b, c, d));
+        assert_eq!(shedder.in_flight(), 0);
+        assert_eq!(classify("/token/refresh"), Priority::Critical);
+        assert_eq!(classify("/request/magic"), Priority::Low);
+    }
+}
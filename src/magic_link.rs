@@ -1,9 +1,71 @@
+use crate::crypto::{self, constant_time_eq};
 use crate::db::Database;
 use crate::models::MagicLink;
-use rusqlite::params;
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
 use uuid::Uuid;
 use thiserror::Error;
 
+/// What happens to a user's outstanding links when another one is requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IssuePolicy {
+    /// Every request issues a new link; older ones stay valid (up to `max_outstanding`)
+    #[default]
+    Multiple,
+    /// Within `resend_window_seconds` of the last link, send that link again
+    /// under a fresh token (only digests are stored), keeping its flow and
+    /// expiry; the earlier email's token stops working
+    Resend,
+    /// A new link invalidates every older one
+    Replace,
+}
+
+/// `[magic_links]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct MagicLinkIssuanceConfig {
+    #[serde(default)]
+    pub policy: IssuePolicy,
+    #[serde(default = "default_resend_window_seconds")]
+    pub resend_window_seconds: i64,
+    /// Valid links a user may hold; the oldest are invalidated beyond it (0 = unlimited)
+    #[serde(default = "default_max_outstanding")]
+    pub max_outstanding: u32,
+}
+
+impl Default for MagicLinkIssuanceConfig {
+    fn default() -> Self {
+        Self {
+            policy: IssuePolicy::Multiple,
+            resend_window_seconds: default_resend_window_seconds(),
+            max_outstanding: default_max_outstanding(),
+        }
+    }
+}
+
+fn default_resend_window_seconds() -> i64 {
+    120
+}
+
+fn default_max_outstanding() -> u32 {
+    3
+}
+
+/// Header carrying a login flow id, on the `/request/magic` response and the email
+pub const FLOW_HEADER: &str = "X-Auth-Flow";
+
+/// A link to email, from [`MagicLink::issue`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedLink {
+    /// Secret link token; only its [`crypto::token_digest`] is stored
+    pub token: String,
+    /// Correlation id of the login flow; a resent link keeps its original flow
+    pub flow_id: String,
+    /// An outstanding link is being sent again
+    pub resent: bool,
+}
+
 #[derive(Debug, Error)]
 pub enum MagicLinkError {
     #[error("db error: {0}")]
@@ -12,6 +74,109 @@ pub enum MagicLinkError {
     Invalid,
     #[error("already used")]
     Used,
+    #[error("invalid link binding: {0}")]
+    InvalidBinding(&'static str),
+    #[error("link is bound to another client")]
+    BindingMismatch,
+}
+
+/// Client binding chosen at `/request/magic`: the link then only yields
+/// tokens together with the matching PKCE verifier (RFC 7636, S256) and/or
+/// the same `state`, so a link stolen from the mailbox is not enough
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkBinding {
+    code_challenge: Option<String>,
+    state_hash: Option<String>,
+}
+
+/// What the client presents with the token to satisfy a [`LinkBinding`]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LinkProof {
+    #[serde(default)]
+    pub code_verifier: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+const MAX_STATE_LEN: usize = 512;
+
+/// Wrong proofs a bound link tolerates before it is expired
+pub const MAX_FAILED_PROOFS: i64 = 5;
+
+/// RFC 7636 §4.1: 43 to 128 characters of `[A-Za-z0-9-._~]`
+fn valid_verifier(verifier: &str) -> bool {
+    (43..=128).contains(&verifier.len())
+        && verifier.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~'))
+}
+
+fn sha256(input: &str) -> [u8; 32] {
+    crypto::sha256(input.as_bytes())
+}
+
+impl LinkBinding {
+    /// Validate the binding parameters; only the `S256` method is accepted
+    pub fn new(
+        code_challenge: Option<&str>,
+        code_challenge_method: Option<&str>,
+        state: Option<&str>,
+    ) -> Result<Self, MagicLinkError> {
+        if let Some(challenge) = code_challenge {
+            if code_challenge_method.unwrap_or("S256") != "S256" {
+                return Err(MagicLinkError::InvalidBinding("code_challenge_method must be S256"));
+            }
+            // base64url of a SHA-256 digest, without padding
+            if challenge.len() != 43 || BASE64URL_NOPAD.decode(challenge.as_bytes()).is_err() {
+                return Err(MagicLinkError::InvalidBinding("code_challenge must be a base64url SHA-256 digest"));
+            }
+        } else if code_challenge_method.is_some() {
+            return Err(MagicLinkError::InvalidBinding("code_challenge_method without code_challenge"));
+        }
+        if state.is_some_and(|s| s.is_empty() || s.len() > MAX_STATE_LEN) {
+            return Err(MagicLinkError::InvalidBinding("state must be 1 to 512 characters"));
+        }
+        Ok(Self {
+            code_challenge: code_challenge.map(str::to_string),
+            // stored hashed; it is as good as a password for this one link
+            state_hash: state.map(|s| HEXLOWER.encode(&sha256(s))),
+        })
+    }
+
+    /// A binding read back from storage
+    pub fn stored(code_challenge: Option<String>, state_hash: Option<String>) -> Self {
+        Self {
+            code_challenge,
+            state_hash,
+        }
+    }
+
+    pub fn code_challenge(&self) -> Option<&str> {
+        self.code_challenge.as_deref()
+    }
+
+    /// Hex SHA-256 of the `state`
+    pub fn state_hash(&self) -> Option<&str> {
+        self.state_hash.as_deref()
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.code_challenge.is_some() || self.state_hash.is_some()
+    }
+
+    /// Whether `proof` satisfies this binding; unbound links accept any proof
+    pub fn verify(&self, proof: &LinkProof) -> bool {
+        let pkce = self.code_challenge.as_ref().is_none_or(|challenge| {
+            proof.code_verifier.as_deref().filter(|v| valid_verifier(v)).is_some_and(|verifier| {
+                let computed = BASE64URL_NOPAD.encode(&sha256(verifier));
+                constant_time_eq(computed.as_bytes(), challenge.as_bytes())
+            })
+        });
+        let state = self.state_hash.as_ref().is_none_or(|hash| {
+            proof.state.as_deref().is_some_and(|state| {
+                constant_time_eq(HEXLOWER.encode(&sha256(state)).as_bytes(), hash.as_bytes())
+            })
+        });
+        pkce && state
+    }
 }
 
 impl MagicLink {
@@ -21,33 +186,227 @@ impl MagicLink {
         expiry_seconds: i64,
     ) -> Result<String, MagicLinkError> {
         let token = Uuid::new_v4().to_string();
-        let expires_at = Database::now_ts() + expiry_seconds;
-        db.conn.execute(
-            "INSERT INTO magic_links (token, user_id, expires_at, used) VALUES (?1, ?2, ?3, 0)",
-            params![token, user_id, expires_at],
+        let now = Database::now_ts();
+        db.conn().execute(
+            "INSERT INTO magic_links (token, user_id, expires_at, used, created_at) VALUES (?1, ?2, ?3, 0, ?4)",
+            params![crypto::token_digest(&token), user_id, now + expiry_seconds, now],
         )?;
         Ok(token)
     }
 
+    /// Issue a link according to the issuance policy. `client` is the
+    /// requesting application and the redirect it asked for; tokens minted
+    /// from the link are issued to that application. A link is only resent
+    /// to a request with the same client, redirect and binding.
+    pub fn issue(
+        db: &Database,
+        user_id: &str,
+        expiry_seconds: i64,
+        cfg: &MagicLinkIssuanceConfig,
+        client: Option<(&str, Option<&str>)>,
+        binding: &LinkBinding,
+    ) -> Result<IssuedLink, MagicLinkError> {
+        let now = Database::now_ts();
+        let (client_id, redirect_uri) = client.map_or((None, None), |(id, uri)| (Some(id), uri));
+        let token = Uuid::new_v4().to_string();
+        let digest = crypto::token_digest(&token);
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        if cfg.policy == IssuePolicy::Resend {
+            let recent: Option<(String, Option<String>)> = tx
+                .query_row(
+                    "SELECT token, flow_id FROM magic_links
+                     WHERE user_id = ?1 AND used = 0 AND expires_at >= ?2 AND created_at >= ?3
+                       AND client_id IS ?4 AND redirect_uri IS ?5 AND code_challenge IS ?6 AND state_hash IS ?7
+                     ORDER BY rowid DESC LIMIT 1",
+                    params![
+                        user_id,
+                        now,
+                        now - cfg.resend_window_seconds,
+                        client_id,
+                        redirect_uri,
+                        binding.code_challenge,
+                        binding.state_hash,
+                    ],
+                    |r| Ok((r.get(0)?, r.get(1)?)),
+                )
+                .optional()?;
+            if let Some((previous, flow_id)) = recent {
+                // the old token cannot be recovered from its digest, so the link gets a new one;
+                // links issued before flows were tracked get a flow now
+                let flow_id = flow_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+                tx.execute(
+                    "UPDATE magic_links SET token = ?1, flow_id = ?2 WHERE token = ?3",
+                    params![digest, flow_id, previous],
+                )?;
+                tx.commit()?;
+                return Ok(IssuedLink {
+                    token,
+                    flow_id,
+                    resent: true,
+                });
+            }
+        }
+
+        let flow_id = Uuid::new_v4().to_string();
+        tx.execute(
+            "INSERT INTO magic_links
+                 (token, user_id, expires_at, used, created_at, client_id, redirect_uri, code_challenge, state_hash, flow_id)
+             VALUES (?1, ?2, ?3, 0, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                digest,
+                user_id,
+                now + expiry_seconds,
+                now,
+                client_id,
+                redirect_uri,
+                binding.code_challenge,
+                binding.state_hash,
+                flow_id,
+            ],
+        )?;
+        let keep = match cfg.policy {
+            IssuePolicy::Replace => 1,
+            _ => cfg.max_outstanding,
+        };
+        if keep > 0 {
+            // expired rather than deleted, like links invalidated by an admin
+            tx.execute(
+                "UPDATE magic_links SET expires_at = ?1
+                 WHERE user_id = ?2 AND used = 0 AND expires_at > ?1 AND rowid NOT IN (
+                     SELECT rowid FROM magic_links WHERE user_id = ?2 AND used = 0 AND expires_at > ?1
+                     ORDER BY rowid DESC LIMIT ?3)",
+                params![now - 1, user_id, keep],
+            )?;
+        }
+        tx.commit()?;
+        Ok(IssuedLink {
+            token,
+            flow_id,
+            resent: false,
+        })
+    }
+
+    /// Login-flow correlation id of a link, if it has one
+    pub fn flow_id(db: &Database, token: &str) -> Result<Option<String>, MagicLinkError> {
+        Ok(db
+            .conn()
+            .query_row(
+                "SELECT flow_id FROM magic_links WHERE token = ?1",
+                params![crypto::token_digest(token)],
+                |r| r.get(0),
+            )
+            .optional()?
+            .flatten())
+    }
+
+    /// Remember where to send the user after this link is consumed
+    pub fn set_redirect(
+        db: &Database,
+        token: &str,
+        client_id: &str,
+        redirect_uri: &str,
+    ) -> Result<(), MagicLinkError> {
+        db.conn().execute(
+            "UPDATE magic_links SET client_id = ?1, redirect_uri = ?2 WHERE token = ?3",
+            params![client_id, redirect_uri, crypto::token_digest(token)],
+        )?;
+        Ok(())
+    }
+
+    /// Remember the IP that requested this link, for abuse reports
+    pub fn set_requester(db: &Database, token: &str, ip: &str) -> Result<(), MagicLinkError> {
+        db.conn().execute(
+            "UPDATE magic_links SET requested_ip = ?1 WHERE token = ?2",
+            params![ip, crypto::token_digest(token)],
+        )?;
+        Ok(())
+    }
+
+    /// `(client_id, redirect_uri)` requested when the link was issued
+    pub fn redirect(db: &Database, token: &str) -> Result<Option<(String, String)>, MagicLinkError> {
+        let conn = db.conn();
+        let mut stmt = conn.prepare("SELECT client_id, redirect_uri FROM magic_links WHERE token = ?1")?;
+        let mut rows = stmt.query(params![crypto::token_digest(token)])?;
+        match rows.next()? {
+            Some(r) => {
+                let client_id: Option<String> = r.get(0)?;
+                let redirect_uri: Option<String> = r.get(1)?;
+                Ok(client_id.zip(redirect_uri))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Application the link was requested for
+    pub fn client_id(db: &Database, token: &str) -> Result<Option<String>, MagicLinkError> {
+        Ok(db
+            .conn()
+            .query_row(
+                "SELECT client_id FROM magic_links WHERE token = ?1",
+                params![crypto::token_digest(token)],
+                |r| r.get(0),
+            )
+            .optional()?
+            .flatten())
+    }
+
+    /// Expire every unused link (failure injection); returns how many were live
+    pub fn expire_all(db: &Database) -> Result<usize, MagicLinkError> {
+        let past = Database::now_ts() - 1;
+        Ok(db.conn().execute(
+            "UPDATE magic_links SET expires_at = ?1 WHERE used = 0 AND expires_at > ?1",
+            params![past],
+        )?)
+    }
+
+    /// Consume an unbound link
     pub fn consume(db: &Database, token: &str) -> Result<String, MagicLinkError> {
-        let mut stmt = db
-            .conn
-            .prepare("SELECT user_id, expires_at, used FROM magic_links WHERE token = ?1")?;
-        let mut rows = stmt.query(params![token])?;
+        Self::consume_with(db, token, &LinkProof::default())
+    }
+
+    /// Consume a link, checking its client binding first. A wrong proof
+    /// leaves the link usable so a thief cannot burn the owner's link with
+    /// one guess; after [`MAX_FAILED_PROOFS`] the link is expired so the
+    /// binding cannot be brute-forced while it is live.
+    pub fn consume_with(db: &Database, token: &str, proof: &LinkProof) -> Result<String, MagicLinkError> {
+        let digest = crypto::token_digest(token);
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
+            "SELECT token, user_id, expires_at, used, code_challenge, state_hash FROM magic_links WHERE token = ?1",
+        )?;
+        let mut rows = stmt.query(params![digest])?;
         if let Some(r) = rows.next()? {
-            let user_id: String = r.get(0)?;
-            let expires_at: i64 = r.get(1)?;
-            let used: i64 = r.get(2)?;
+            let stored: String = r.get(0)?;
+            let user_id: String = r.get(1)?;
+            let expires_at: i64 = r.get(2)?;
+            let used: i64 = r.get(3)?;
+            let binding = LinkBinding {
+                code_challenge: r.get(4)?,
+                state_hash: r.get(5)?,
+            };
             let now = Database::now_ts();
+            if !constant_time_eq(stored.as_bytes(), digest.as_bytes()) {
+                return Err(MagicLinkError::Invalid);
+            }
             if used != 0 {
                 return Err(MagicLinkError::Used);
             }
             if now > expires_at {
                 return Err(MagicLinkError::Invalid);
             }
-            db.conn.execute(
+            if !binding.verify(proof) {
+                db.conn().execute(
+                    "UPDATE magic_links SET failed_proofs = failed_proofs + 1,
+                         expires_at = CASE WHEN failed_proofs + 1 >= ?2 THEN ?3 ELSE expires_at END
+                     WHERE token = ?1",
+                    params![digest, MAX_FAILED_PROOFS, now - 1],
+                )?;
+                return Err(MagicLinkError::BindingMismatch);
+            }
+            db.conn().execute(
                 "UPDATE magic_links SET used = 1 WHERE token = ?1",
-                params![token],
+                params![digest],
             )?;
             Ok(user_id)
         } else {
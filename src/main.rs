@@ -1,49 +1,71 @@
-mod admin;
-mod audit;
-mod config;
-mod db;
-mod email;
-mod email_templates;
-mod error;
-mod jwt;
-mod magic_link;
-mod metrics;
-mod middleware;
-mod models;
-mod rate_limit;
-mod routes;
-mod session;
-mod totp;
-mod webauthn;
-mod webhooks;
-
 use axum::{middleware as axum_middleware, routing::get, Router};
-use std::{fs, net::SocketAddr, sync::Arc, time::SystemTime};
+use std::{net::SocketAddr, sync::Arc, time::{Duration, SystemTime}};
 use tokio::signal;
 use tower::ServiceBuilder;
-use tower_http::{
-    compression::CompressionLayer,
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
-};
+use tower_http::trace::TraceLayer;
 use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use crate::admin::{admin_router, AdminState};
-use crate::audit::AuditLogger;
-use crate::config::Config;
-use crate::db::Database;
-use crate::email::Emailer;
-use crate::metrics::{init_metrics, metrics_router, MetricsState};
-use crate::rate_limit::IpRateLimiter;
-use crate::routes::{router, AppState};
-use crate::webauthn::WebauthnState;
-use crate::webhooks::WebhookSender;
+use passwordless_auth::aaguids;
+use passwordless_auth::admin::{admin_router, AdminState};
+use passwordless_auth::admin_keys::{self, AdminGuard};
+use passwordless_auth::api_version::{self, ApiVersion, Versioning};
+use passwordless_auth::audit::{self, AuditLogger};
+use passwordless_auth::chaos::{chaos_router, ChaosState};
+use passwordless_auth::challenge_store;
+use passwordless_auth::bootstrap;
+use passwordless_auth::broadcasts;
+use passwordless_auth::compaction;
+use passwordless_auth::compression;
+use passwordless_auth::config::{self, Config, EmailDelivery};
+use passwordless_auth::credential_resets;
+use passwordless_auth::crypto;
+use passwordless_auth::cors::{self, RouteGroup};
+use passwordless_auth::db::Database;
+use passwordless_auth::debug_sampling::{self, DebugSampler};
+use passwordless_auth::deliverability::MxChecker;
+use passwordless_auth::issuance_hook::IssuanceGate;
+use passwordless_auth::doctor;
+use passwordless_auth::email::Emailer;
+use passwordless_auth::exemplars;
+use passwordless_auth::health::DependencyHealth;
+use passwordless_auth::ip_access::{self, IpAccessControl, IpGuard};
+use passwordless_auth::ip_bans::{self, IpBanGuard};
+use passwordless_auth::jwt::KeyRing;
+use passwordless_auth::latency::{self, LatencyTracer};
+use passwordless_auth::maintenance::{self, MaintenanceMode};
+use passwordless_auth::leader::LeaderElection;
+use passwordless_auth::load_shed::{self, LoadShedder};
+use passwordless_auth::metrics::{init_metrics, metrics_router, MetricsState};
+use passwordless_auth::middleware;
+use passwordless_auth::notifications::SmsSender;
+use passwordless_auth::outbox;
+use passwordless_auth::regions;
+use passwordless_auth::revocation::{self, RevocationBus};
+use passwordless_auth::routes::{self, router, AppState};
+use passwordless_auth::runtime_info::RuntimeInfo;
+use passwordless_auth::schema;
+use passwordless_auth::secret_scan::{self, ScrubbedStdout, SecretScanner};
+use passwordless_auth::seed;
+use passwordless_auth::session_expiry;
+use passwordless_auth::stale_accounts;
+use passwordless_auth::storage;
+use passwordless_auth::webauthn::WebauthnState;
+use passwordless_auth::webhook_secrets;
+use passwordless_auth::key_rotation;
+use passwordless_auth::webhooks::WebhookSender;
 
 #[tokio::main]
 async fn main() {
+    // `init`: write a production config, database and first admin, then exit
+    if std::env::args().nth(1).as_deref() == Some("init") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        std::process::exit(bootstrap::main(&args));
+    }
+
     // Load config first to get log level
-    let cfg = match Config::load("config.toml") {
+    let config_path = config::config_path();
+    let cfg = match Config::load(&config_path) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Failed to load config: {}", e);
@@ -51,9 +73,12 @@ async fn main() {
         }
     };
 
-    // Initialize structured logging
+    // Initialize structured logging; token-shaped values never reach the output
+    if let Ok(scanner) = SecretScanner::for_config(&cfg) {
+        secret_scan::install(scanner);
+    }
     tracing_subscriber::registry()
-        .with(fmt::layer().with_target(true))
+        .with(fmt::layer().with_target(true).with_writer(ScrubbedStdout))
         .with(
             EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| EnvFilter::new(&cfg.log_level)),
@@ -61,7 +86,27 @@ async fn main() {
         .init();
 
     info!("🚀 Starting Passwordless Auth Server v{}", env!("CARGO_PKG_VERSION"));
-    info!("Configuration loaded from config.toml");
+    match cfg.profile {
+        Some(profile) => info!("Configuration loaded from {} (profile: {})", config_path, profile.as_str()),
+        None => info!("Configuration loaded from {}", config_path),
+    }
+    // Known-answer tests before anything is hashed or signed
+    match crypto::self_test() {
+        Ok(()) => info!("Crypto provider: {}", crypto::PROVIDER),
+        Err(e) => {
+            error!("Crypto self-test failed ({}): {}", crypto::PROVIDER, e);
+            std::process::exit(1);
+        }
+    }
+    if cfg.require_fips {
+        if let Err(e) = crypto::check_fips_boundary() {
+            error!("require_fips is set but {}", e);
+            std::process::exit(1);
+        }
+    }
+    if cfg.email_delivery == EmailDelivery::Log {
+        info!("email_delivery = \"log\": emails are written to the log, not sent");
+    }
 
     // Initialize Prometheus metrics
     let prometheus_handle = if cfg.enable_metrics {
@@ -71,6 +116,9 @@ async fn main() {
         info!("Metrics disabled");
         init_metrics() // Still initialize but won't expose endpoint
     };
+    if cfg.exemplars.enabled {
+        exemplars::install();
+    }
 
     // Open database and run migrations
     let db = match Database::open(&cfg.database_path) {
@@ -81,92 +129,438 @@ async fn main() {
         }
     };
     info!("Database opened: {}", cfg.database_path);
+    // incremental vacuum can only be switched on before the first table exists
+    if let Err(e) = compaction::prepare(&db) {
+        warn!("Failed to enable incremental vacuum: {}", e);
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+
+    // `--doctor`: full configuration self-test (before migrations, so pending ones are reported)
+    if args.iter().any(|a| a == "--doctor") {
+        let report = doctor::run(&cfg, &db, true);
+        println!("{}", report);
+        std::process::exit(if report.has_fatal() { 1 } else { 0 });
+    }
 
     // Run migrations
-    for migration_file in &["migrations/init.sql", "migrations/002_email_queue.sql", "migrations/003_production_features.sql"] {
-        if let Ok(migration_sql) = fs::read_to_string(migration_file) {
-            db.migrate(&migration_sql).unwrap_or_else(|e| {
-                warn!("Migration {} already applied or failed: {}", migration_file, e);
-            });
-            info!("Applied migration: {}", migration_file);
+    match db.apply_migrations() {
+        Ok(applied) => {
+            for name in applied {
+                info!("Applied migration: {}", name);
+            }
+        }
+        Err(e) => {
+            error!("Database migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Refuse to serve against a schema the code does not match
+    let fingerprint = match schema::verify(&db) {
+        Ok(fingerprint) => {
+            info!("Database schema verified ({})", fingerprint);
+            fingerprint
+        }
+        Err(e) => {
+            error!("Schema check failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Magic links and refresh tokens, when `store` keeps them outside SQLite
+    let tokens = match storage::token_store(&cfg) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            error!("Cannot open the token store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let runtime_info = Arc::new(RuntimeInfo::collect(&cfg, &fingerprint));
+    runtime_info.log_banner();
+
+    // Lighter self-test on every start (no outbound connections)
+    for check in doctor::run(&cfg, &db, false).problems() {
+        warn!("Config doctor: {}: {} (run with --doctor for a full report)", check.name, check.message);
+    }
+
+    // `--seed [N]`: generate demo data and exit (dev_mode only)
+    if let Some(pos) = args.iter().position(|a| a == "--seed") {
+        if !cfg.dev_mode {
+            error!("--seed is only available with dev_mode = true");
+            std::process::exit(1);
+        }
+        let count = args
+            .get(pos + 1)
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(100);
+        match seed::seed(&db, count) {
+            Ok(summary) => {
+                info!("Seeded demo data: {:?}", summary);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                error!("Seeding failed: {}", e);
+                std::process::exit(1);
+            }
         }
     }
 
     // Initialize components
     let emailer = Emailer::new(&cfg);
     let webauthn = WebauthnState::new(&cfg);
-    let audit = Arc::new(AuditLogger::new());
-    let webhook_sender = Arc::new(WebhookSender::new(
-        cfg.webhook_url.clone(),
-        cfg.webhook_secret.clone(),
-    ));
+    let webhook_sender = Arc::new(
+        WebhookSender::new(cfg.webhook_url.clone(), cfg.webhook_secret.clone())
+            .with_policy(cfg.dependency_policy("webhook")),
+    );
+    // Rotated secrets replace the configured one
+    if let Err(e) = webhook_secrets::load(&db, &webhook_sender, cfg.webhook_secret.as_deref()) {
+        warn!("Failed to load webhook secrets: {}", e);
+    }
+    let mut audit =
+        AuditLogger::with_partitioning(cfg.audit.partitioning).with_full_text_search(cfg.audit.full_text_search);
+    if cfg.audit.security_alerts {
+        let (alerts, alert_rx) = tokio::sync::mpsc::unbounded_channel();
+        audit = audit.with_alerts(alerts);
+        audit::spawn_alert_forwarder(alert_rx, webhook_sender.clone());
+    }
+    let audit = Arc::new(audit);
+    if let Err(e) = audit.prepare_partitions(&db) {
+        warn!("Failed to pre-create audit partitions: {}", e);
+    }
+    if let Err(e) = audit.prepare_search(&db) {
+        warn!("Failed to prepare audit search indexes: {}", e);
+    }
+
+    // Revocations are applied locally and broadcast to other instances;
+    // in the eventual session mode they are also synced between regions
+    let db = Arc::new(db);
+    let revocations = Arc::new(RevocationBus::new(&cfg.revocation).with_log(db.clone(), &cfg.sessions));
+    revocations.spawn_subscriber();
+    if let Err(e) = revocation::load_denied_access_tokens(&db, revocations.cache()) {
+        warn!("Failed to load revoked access tokens: {}", e);
+    }
+    if cfg.sessions.is_eventual() {
+        info!("Eventual session mode in region {} ({} peers)", cfg.sessions.region, cfg.sessions.peers.len());
+        regions::spawn_sync(db.clone(), revocations.clone(), cfg.sessions.clone());
+    }
+
+    // Access tokens are signed with jwt_secret, the [jwt_signing] keys or generated keys
+    let keys = match KeyRing::load(&cfg) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            error!("Cannot load JWT signing keys: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if cfg.key_rotation.enabled {
+        if let Err(e) = key_rotation::prepare(&db, &keys) {
+            error!("Cannot prepare rotated signing keys: {}", e);
+            std::process::exit(1);
+        }
+    }
+    info!("Access tokens are signed with {}", keys.algorithm(Database::now_ts()).as_str());
+
+    // Read-only switch for maintenance windows; refreshes keep working
+    if cfg.maintenance.read_only {
+        warn!("Starting in read-only mode: sign-ins and other writes are refused");
+    }
+    let maintenance = match MaintenanceMode::new(&cfg.maintenance, db.clone()) {
+        Ok(mode) => Arc::new(mode),
+        Err(e) => {
+            error!("Cannot store the maintenance switch: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    info!("Initializing rate limiter ({}req/min)", cfg.rate_limit_per_minute);
-    let rate_limiter = Arc::new(IpRateLimiter::new(cfg.rate_limit_per_minute));
+    // Under overload, refreshes and verifications win over new sign-ins
+    if cfg.load_shedding.enabled {
+        info!("Load shedding enabled ({} concurrent auth requests)", cfg.load_shedding.max_in_flight);
+    }
+    let shedder = Arc::new(LoadShedder::new(&cfg.load_shedding));
+
+    // Addresses that keep failing authentication are banned for a while
+    if cfg.ip_bans.enabled {
+        info!("Automatic IP bans enabled ({}s failure window)", cfg.ip_bans.window_seconds);
+    }
+    let ban_guard = Arc::new(IpBanGuard::new(
+        db.clone(),
+        audit.clone(),
+        &cfg.ip_bans,
+        cfg.ip_access.trust_forwarded_for,
+    ));
 
     // Create application state
     let app_state = AppState {
         cfg: Arc::new(cfg.clone()),
-        db: Arc::new(db),
+        db,
         emailer: Arc::new(emailer),
         webauthn: Arc::new(webauthn),
         audit: audit.clone(),
         webhook: webhook_sender,
+        chaos: Arc::new(ChaosState::new()),
+        revocations: revocations.clone(),
+        sms: Arc::new(SmsSender::new(&cfg.notifications, cfg.dependency_policy("sms"))),
+        mx: Arc::new(MxChecker::new(&cfg.deliverability)),
+        issuance: Arc::new(IssuanceGate::new(&cfg.issuance_hook, cfg.dependency_policy("issuance_hook"))),
+        keys,
+        tokens,
+        maintenance: maintenance.clone(),
+        shedder: shedder.clone(),
+        ip_bans: ban_guard.clone(),
     };
 
+    // Probe SMTP, webhook and storage in the background for /health/dependencies
+    let dependencies = Arc::new(DependencyHealth::new());
+    dependencies.clone().spawn_prober(
+        app_state.db.clone(),
+        app_state.emailer.clone(),
+        app_state.webhook.clone(),
+        Duration::from_secs(cfg.dependency_probe_interval_seconds),
+    );
+
+    // Singleton jobs run on one replica at a time, elected through job
+    // leases, and none runs while read-only
+    let leader = Arc::new(
+        LeaderElection::new(app_state.db.clone(), &cfg.leader).with_maintenance(maintenance.clone()),
+    );
+    info!("Background jobs elect a leader as {}", leader.node_id());
+
+    // Deliver audit and webhook events recorded in the outbox
+    outbox::spawn_dispatcher(
+        app_state.db.clone(),
+        leader.clone(),
+        audit.clone(),
+        app_state.webhook.clone(),
+        Duration::from_millis(cfg.outbox_poll_interval_ms),
+    );
+
+    // Forget revocations peers have long since pulled
+    regions::spawn_retention(
+        app_state.db.clone(),
+        leader.clone(),
+        cfg.sessions.clone(),
+        Duration::from_secs(3600),
+    );
+
+    // Rotate audit events by severity
+    audit::spawn_retention(
+        app_state.db.clone(),
+        leader.clone(),
+        cfg.audit.retention.clone(),
+        Duration::from_secs(3600),
+    );
+
+    // `session_expiring` webhooks before a user's last session runs out
+    session_expiry::spawn_notifier(app_state.db.clone(), leader.clone(), cfg.session_expiry.clone());
+
+    // Pick up webhook secret rotations made on other replicas
+    webhook_secrets::spawn_refresher(
+        app_state.db.clone(),
+        leader.clone(),
+        app_state.webhook.clone(),
+        cfg.webhook_secret.clone(),
+        cfg.webhook_secrets.clone(),
+    );
+
+    // Generate signing keys on schedule and pick up rotations made on other replicas
+    key_rotation::spawn_rotator(
+        app_state.db.clone(),
+        leader.clone(),
+        app_state.keys.clone(),
+        cfg.key_rotation.clone(),
+    );
+
+    // Sampled failed requests are kept for debugging only briefly
+    if cfg.debug_sampling.enabled {
+        warn!("Debug sampling enabled: {}% of failed auth requests are recorded", cfg.debug_sampling.sample_percent);
+        debug_sampling::spawn_retention(
+            app_state.db.clone(),
+            leader.clone(),
+            cfg.debug_sampling.retention_seconds,
+            Duration::from_secs(600),
+        );
+    }
+    let sampler = DebugSampler {
+        db: app_state.db.clone(),
+        cfg: cfg.debug_sampling.clone(),
+    };
+
+    ip_bans::spawn_maintenance(app_state.db.clone(), leader.clone(), cfg.ip_bans.clone());
+
+    // Keep the WAL and free pages from growing the database files forever
+    compaction::spawn_compactor(
+        app_state.db.clone(),
+        leader.clone(),
+        cfg.database_path.clone(),
+        cfg.compaction.clone(),
+    );
+
+    // Pending WebAuthn challenges are served from memory and expire on schedule
+    challenge_store::spawn_evictor(
+        app_state.webauthn.challenges.clone(),
+        app_state.db.clone(),
+        leader.clone(),
+        cfg.challenge_cache.clone(),
+    );
+
+    // Admin broadcasts trickle into the email queue
+    broadcasts::spawn_sender(app_state.db.clone(), leader.clone(), cfg.broadcasts.clone());
+
+    // Forced credential resets, one batch of users per run
+    credential_resets::spawn_runner(
+        app_state.db.clone(),
+        leader.clone(),
+        app_state.revocations.clone(),
+        app_state.tokens.clone(),
+        cfg.credential_resets.clone(),
+    );
+
+    // Inactive accounts are flagged, then disabled and purged
+    stale_accounts::spawn_runner(
+        app_state.db.clone(),
+        leader.clone(),
+        app_state.revocations.clone(),
+        app_state.tokens.clone(),
+        cfg.stale_accounts.clone(),
+    );
+
+    // Names of authenticator models, used as default passkey names
+    aaguids::spawn_refresh(app_state.db.clone(), leader.clone(), cfg.aaguids.clone());
+
+    // Per-stage timings of auth requests (Server-Timing for allowlisted clients)
+    let tracer = Arc::new(LatencyTracer::new(&cfg.latency, cfg.ip_access.trust_forwarded_for));
+
     // Create metrics state
     let metrics_state = MetricsState {
         start_time: SystemTime::now(),
         prometheus_handle,
+        dependencies,
     };
 
     // Create admin state
     let admin_state = AdminState {
         db: app_state.db.clone(),
         audit: audit.clone(),
+        revocations,
+        info: runtime_info,
+        webhook: app_state.webhook.clone(),
+        webhook_secret: cfg.webhook_secret.clone(),
+        webhook_secrets: cfg.webhook_secrets.clone(),
+        keys: app_state.keys.clone(),
+        key_rotation: cfg.key_rotation.clone(),
+        ip_bans: cfg.ip_bans.clone(),
+        credential_resets: cfg.credential_resets.clone(),
+        maintenance: maintenance.clone(),
+        tokens: app_state.tokens.clone(),
     };
 
-    // Configure CORS
-    let cors = if cfg.cors_allow_all {
-        info!("CORS: Allowing all origins");
-        CorsLayer::permissive()
-    } else if !cfg.cors_allowed_origins.is_empty() {
-        info!("CORS: Allowing origins: {:?}", cfg.cors_allowed_origins);
-        CorsLayer::new()
-            .allow_origin(
-                cfg.cors_allowed_origins
-                    .iter()
-                    .map(|o| o.parse().unwrap())
-                    .collect::<Vec<_>>(),
-            )
-            .allow_methods(Any)
-            .allow_headers(Any)
+    // IP access control for admin and metrics routes (reloaded on SIGHUP)
+    let ip_acl = match IpAccessControl::new(&cfg.ip_access) {
+        Ok(acl) => Arc::new(acl),
+        Err(e) => {
+            error!("Invalid IP access rules: {}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(unix)]
+    ip_access::spawn_reload_on_sighup(ip_acl.clone(), config_path.clone());
+    let admin_guard = IpGuard {
+        acl: ip_acl.clone(),
+        group: RouteGroup::Admin,
+    };
+    let metrics_guard = IpGuard {
+        acl: ip_acl,
+        group: RouteGroup::Metrics,
+    };
+    // fail closed: without a credential check `/admin` is only mounted in dev_mode
+    let admin = admin_router(admin_state)
+        .layer(axum_middleware::from_fn_with_state(maintenance.clone(), maintenance::enforce));
+    let admin = if cfg.admin.enabled() {
+        info!(access_tokens = cfg.admin.access_tokens, "Admin API requires an API key or admin access token");
+        let guard = AdminGuard {
+            db: app_state.db.clone(),
+            audit: audit.clone(),
+            revocations: app_state.revocations.clone(),
+            keys: app_state.keys.clone(),
+            jwt_leeway_seconds: cfg.jwt_leeway_seconds,
+            cfg: cfg.admin.clone(),
+        };
+        Some(admin.layer(axum_middleware::from_fn_with_state(guard, admin_keys::authenticate)))
+    } else if cfg.dev_mode {
+        warn!("⚠️  dev_mode enabled: Admin API is unauthenticated; set [admin] require_api_key or access_tokens");
+        Some(admin.layer(axum_middleware::from_fn(admin_keys::dev_mode)))
     } else {
-        info!("CORS: Disabled");
-        CorsLayer::new()
+        warn!("Admin API not mounted: set [admin] require_api_key or access_tokens (keys come from `passwordless-auth init`)");
+        None
+    };
+
+    // Auth routes, unprefixed (version negotiated via Accept) and under /v1 and /v2
+    let auth_routes = |pinned: Option<ApiVersion>| {
+        let versioning = Versioning {
+            pinned,
+            cfg: cfg.api_versions.clone(),
+        };
+        compression::apply(
+            router(app_state.clone())
+                .layer(axum_middleware::from_fn_with_state(sampler.clone(), debug_sampling::sample))
+                .layer(axum_middleware::from_fn_with_state(shedder.clone(), load_shed::shed))
+                .layer(axum_middleware::from_fn(middleware::no_store))
+                .layer(axum_middleware::from_fn_with_state(maintenance.clone(), maintenance::enforce))
+                .layer(axum_middleware::from_fn_with_state(ban_guard.clone(), ip_bans::enforce))
+                .layer(axum_middleware::from_fn_with_state(versioning, api_version::negotiate))
+                .layer(axum_middleware::from_fn_with_state(tracer.clone(), latency::trace)),
+            &cfg,
+            RouteGroup::Public,
+        )
     };
 
     // Build main application router
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(|| async {
             format!("Passwordless Auth Server v{} - Production Ready 🔒", env!("CARGO_PKG_VERSION"))
         }))
         // Auth routes
-        .merge(router(app_state.clone()))
-        // Admin routes (prefixed with /admin)
-        .nest("/admin", admin_router(admin_state))
+        .merge(auth_routes(None))
+        .nest("/v1", auth_routes(Some(ApiVersion::V1)))
+        .nest("/v2", auth_routes(Some(ApiVersion::V2)))
+        // Public keys of access tokens, cacheable (outside the no-store auth routes)
+        .route("/.well-known/jwks.json", get(routes::jwks).with_state(app_state.keys.clone()))
+        .layer(cors::layer(&cfg, RouteGroup::Public))
         // Metrics and health routes
-        .merge(metrics_router(metrics_state))
-        // Apply middleware layers
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(CompressionLayer::new())
-                .layer(cors)
-                .layer(axum_middleware::from_fn(middleware::security_headers))
-                .layer(axum_middleware::from_fn(middleware::request_id)),
+        .merge(
+            compression::apply(metrics_router(metrics_state, metrics_guard), &cfg, RouteGroup::Metrics)
+                .layer(cors::layer(&cfg, RouteGroup::Metrics)),
         );
 
+    // Admin routes (prefixed with /admin)
+    let admin_mounted = admin.is_some();
+    if let Some(admin) = admin {
+        app = app.nest(
+            "/admin",
+            compression::apply(
+                admin.layer(axum_middleware::from_fn(middleware::no_store)),
+                &cfg,
+                RouteGroup::Admin,
+            )
+            .layer(axum_middleware::from_fn_with_state(admin_guard, ip_access::enforce))
+            .layer(cors::layer(&cfg, RouteGroup::Admin)),
+        );
+    }
+
+    // Failure injection endpoints for rehearsing degradations in staging
+    if cfg.dev_mode {
+        warn!("⚠️  dev_mode enabled: /dev/chaos failure injection endpoints are exposed");
+        app = app.nest("/dev/chaos", chaos_router(app_state.clone()));
+    }
+
+    // Apply middleware layers
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(TraceLayer::new_for_http())
+            .layer(axum_middleware::from_fn_with_state(cfg.hsts, middleware::security_headers))
+            .layer(axum_middleware::from_fn(middleware::request_id)),
+    );
+
     // Bind server
     let addr = SocketAddr::from((
         cfg.server_host.parse::<std::net::IpAddr>().unwrap_or_else(|_| {
@@ -178,8 +572,11 @@ async fn main() {
 
     info!("🎧 Server listening on http://{}", addr);
     info!("📊 Health check: http://{}/health", addr);
+    info!("🩺 Dependencies: http://{}/health/dependencies", addr);
     info!("📈 Metrics: http://{}/metrics", addr);
-    info!("🔧 Admin API: http://{}/admin/*", addr);
+    if admin_mounted {
+        info!("🔧 Admin API: http://{}/admin/*", addr);
+    }
 
     // Create server with graceful shutdown
     let listener = tokio::net::TcpListener::bind(addr)
@@ -197,6 +594,8 @@ async fn main() {
     .await
     .unwrap();
 
+    // let another replica take over the singleton jobs without waiting for the leases to lapse
+    leader.release_all();
     info!("Server shutdown complete");
 }
 
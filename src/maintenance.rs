@@ -0,0 +1,228 @@
+//! Read-only mode for maintenance windows and online schema migrations.
+//!
+//! While read-only, existing sessions keep working: access tokens verify,
+//! refresh tokens rotate and read endpoints answer. Everything else that
+//! writes (sign-ins, registrations, enrollments, admin changes) is refused
+//! with `503` and `Retry-After`. The switch is stored in the database, so
+//! `PUT /admin/maintenance` on one replica flips every replica sharing it;
+//! each instance rereads it after `[maintenance] cache_ms`. `[maintenance]
+//! read_only` switches it on at startup.
+//!
+//! [`enforce`] refuses requests to the bundled routers; [`AuthService`]
+//! checks the switch itself, so the actix and tower adapters obey it too.
+//!
+//! [`AuthService`]: crate::service::AuthService
+
+use crate::{
+    db::Database,
+    error::{ApiError, ErrorResponse},
+    metrics::MetricsRecorder,
+};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// `[maintenance]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct MaintenanceConfig {
+    /// Start in read-only mode
+    #[serde(default)]
+    pub read_only: bool,
+    /// Shown to clients whose requests are refused
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// `Retry-After` sent with refused requests
+    #[serde(default = "default_retry_after_seconds")]
+    pub retry_after_seconds: u64,
+    /// How long an instance reuses the stored switch before reading it again
+    #[serde(default = "default_cache_ms")]
+    pub cache_ms: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            reason: None,
+            retry_after_seconds: default_retry_after_seconds(),
+            cache_ms: default_cache_ms(),
+        }
+    }
+}
+
+fn default_retry_after_seconds() -> u64 {
+    300
+}
+
+fn default_cache_ms() -> u64 {
+    1000
+}
+
+/// Current state of the switch (`GET /admin/maintenance`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub read_only: bool,
+    pub reason: Option<String>,
+    /// When read-only mode was entered
+    pub since: Option<i64>,
+}
+
+/// Read-only switch stored in the database, shared by the auth and admin
+/// routers and by every instance on the database
+pub struct MaintenanceMode {
+    db: Arc<Database>,
+    /// Last status read and when
+    cached: RwLock<Option<(Status, Instant)>>,
+    cache_ttl: Duration,
+    retry_after_seconds: u64,
+}
+
+impl MaintenanceMode {
+    /// The stored switch, turned on first when `cfg.read_only` is set
+    pub fn new(cfg: &MaintenanceConfig, db: Arc<Database>) -> Result<Self, rusqlite::Error> {
+        let mode = Self {
+            db,
+            cached: RwLock::new(None),
+            cache_ttl: Duration::from_millis(cfg.cache_ms),
+            retry_after_seconds: cfg.retry_after_seconds,
+        };
+        if cfg.read_only {
+            mode.set(true, cfg.reason.clone())?;
+        }
+        Ok(mode)
+    }
+
+    /// The stored status, at most `cache_ms` old. While the database cannot
+    /// be read the last status read is kept.
+    pub fn status(&self) -> Status {
+        if let Some((status, read_at)) = &*self.cached.read().unwrap_or_else(|e| e.into_inner()) {
+            if read_at.elapsed() < self.cache_ttl {
+                return status.clone();
+            }
+        }
+        match load(&self.db) {
+            Ok(status) => self.remember(status),
+            Err(e) => {
+                warn!("Failed to read the maintenance switch: {}", e);
+                let cached = self.cached.read().unwrap_or_else(|e| e.into_inner());
+                cached.as_ref().map(|(status, _)| status.clone()).unwrap_or_default()
+            }
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.status().read_only
+    }
+
+    /// `Retry-After` for refused requests
+    pub fn retry_after_seconds(&self) -> u64 {
+        self.retry_after_seconds
+    }
+
+    /// Enter or leave read-only mode on every instance; `since` is kept when
+    /// already read-only
+    pub fn set(&self, read_only: bool, reason: Option<String>) -> Result<Status, rusqlite::Error> {
+        let conn = self.db.conn();
+        let tx = conn.unchecked_transaction()?;
+        let now = Database::now_ts();
+        let since = match (read_only, load(&self.db)?.since) {
+            (false, _) => None,
+            (true, Some(since)) => Some(since),
+            (true, None) => Some(now),
+        };
+        let status = Status {
+            read_only,
+            reason: reason.filter(|_| read_only),
+            since,
+        };
+        tx.execute(
+            "INSERT INTO maintenance_state (id, read_only, reason, since, updated_at) VALUES (1, ?1, ?2, ?3, ?4)
+             ON CONFLICT(id) DO UPDATE SET read_only = excluded.read_only, reason = excluded.reason,
+                 since = excluded.since, updated_at = excluded.updated_at",
+            params![status.read_only, status.reason, status.since, now],
+        )?;
+        tx.commit()?;
+        Ok(self.remember(status))
+    }
+
+    fn remember(&self, status: Status) -> Status {
+        *self.cached.write().unwrap_or_else(|e| e.into_inner()) = Some((status.clone(), Instant::now()));
+        status
+    }
+}
+
+fn load(db: &Database) -> Result<Status, rusqlite::Error> {
+    let status = db
+        .conn()
+        .query_row(
+            "SELECT read_only, reason, since FROM maintenance_state WHERE id = 1",
+            [],
+            |r| {
+                Ok(Status {
+                    read_only: r.get(0)?,
+                    reason: r.get(1)?,
+                    since: r.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(status.unwrap_or_default())
+}
+
+/// Whether a request may proceed in read-only mode. Paths are relative to
+/// the auth or admin router.
+pub fn allows(method: &Method, path: &str) -> bool {
+    match path {
+        "/token/refresh" | "/token/verify-batch" | "/oauth/introspect" => true,
+        // switching maintenance mode off must keep working
+        "/maintenance" => true,
+        // following a magic link consumes it
+        "/verify/magic" => false,
+        _ => matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS),
+    }
+}
+
+/// Refuse writes with 503 + `Retry-After` while read-only
+pub async fn enforce(State(mode): State<Arc<MaintenanceMode>>, request: Request, next: Next) -> Response {
+    if !mode.is_read_only() || allows(request.method(), request.uri().path()) {
+        return next.run(request).await;
+    }
+    MetricsRecorder::record_read_only_rejection();
+    let message = mode
+        .status()
+        .reason
+        .unwrap_or_else(|| "down for maintenance, existing sessions keep working".to_string());
+    let mut response =
+        ErrorResponse::new(StatusCode::SERVICE_UNAVAILABLE, ApiError::new("READ_ONLY", message)).into_response();
+    if let Ok(retry_after) = HeaderValue::from_str(&mode.retry_after_seconds().to_string()) {
+        response.headers_mut().insert(header::RETRY_AFTER, retry_after);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_and_reads_stay_available() {
+        assert!(allows(&Method::POST, "/token/refresh"));
+        assert!(allows(&Method::GET, "/me/sessions"));
+        assert!(allows(&Method::PUT, "/maintenance"));
+        assert!(!allows(&Method::POST, "/totp/enroll"));
+        assert!(!allows(&Method::GET, "/verify/magic"));
+        assert!(allows(&Method::GET, "/action/abc.sig"));
+        assert!(!allows(&Method::POST, "/action/abc.sig"));
+        assert!(!allows(&Method::DELETE, "/users/u1/sessions"));
+    }
+}
@@ -1,6 +1,10 @@
+use crate::exemplars;
+use crate::health::DependencyHealth;
+use crate::ip_access::{self, IpGuard};
 use axum::{
     extract::State,
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
+    middleware,
     response::IntoResponse,
     routing::get,
     Router,
@@ -8,6 +12,7 @@ use axum::{
 use metrics::{counter, gauge, histogram};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use serde::Serialize;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Initialize Prometheus metrics exporter
@@ -18,6 +23,16 @@ pub fn init_metrics() -> PrometheusHandle {
             &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
         )
         .unwrap()
+        .set_buckets_for_metric(
+            Matcher::Full("auth_stage_duration_seconds".to_string()),
+            exemplars::STAGE_BUCKETS,
+        )
+        .unwrap()
+        .set_buckets_for_metric(
+            Matcher::Full("email_send_duration_seconds".to_string()),
+            exemplars::EMAIL_SEND_BUCKETS,
+        )
+        .unwrap()
         .install_recorder()
         .unwrap()
 }
@@ -28,13 +43,18 @@ pub struct MetricsRecorder;
 impl MetricsRecorder {
     /// Record a successful authentication
     pub fn record_auth_success(method: &str) {
-        counter!("auth_attempts_total", "method" => method, "status" => "success").increment(1);
+        counter!("auth_attempts_total", "method" => method.to_string(), "status" => "success").increment(1);
     }
 
     /// Record a failed authentication
     pub fn record_auth_failure(method: &str, reason: &str) {
-        counter!("auth_attempts_total", "method" => method, "status" => "failure", "reason" => reason)
-            .increment(1);
+        counter!(
+            "auth_attempts_total",
+            "method" => method.to_string(),
+            "status" => "failure",
+            "reason" => reason.to_string()
+        )
+        .increment(1);
     }
 
     /// Record email sent
@@ -66,23 +86,130 @@ impl MetricsRecorder {
 
     /// Record rate limit hit
     pub fn record_rate_limit_hit(limit_type: &str) {
-        counter!("rate_limit_hits_total", "type" => limit_type).increment(1);
+        counter!("rate_limit_hits_total", "type" => limit_type.to_string()).increment(1);
+    }
+
+    /// Record a request rejected by load shedding
+    pub fn record_request_shed(priority: &str) {
+        counter!("requests_shed_total", "priority" => priority.to_string()).increment(1);
+    }
+
+    /// Record an address banned for repeated auth failures (`auto`) or by an admin
+    pub fn record_ip_ban(source: &str) {
+        counter!("ip_bans_total", "source" => source.to_string()).increment(1);
+    }
+
+    /// Record a request rejected because its address is banned
+    pub fn record_ip_ban_rejection() {
+        counter!("ip_ban_rejections_total").increment(1);
+    }
+
+    /// Record the number of bans currently in force
+    pub fn record_ip_bans_active(active: i64) {
+        gauge!("ip_bans_active").set(active as f64);
+    }
+
+    /// Record the size of the database files (`[compaction]`)
+    pub fn record_db_size(db_bytes: u64, wal_bytes: u64, freelist_bytes: u64) {
+        gauge!("sqlite_db_size_bytes").set(db_bytes as f64);
+        gauge!("sqlite_wal_size_bytes").set(wal_bytes as f64);
+        gauge!("sqlite_freelist_bytes").set(freelist_bytes as f64);
+    }
+
+    /// Record a WAL checkpoint; `busy` when readers or writers blocked it
+    pub fn record_checkpoint(busy: bool, duration_secs: f64) {
+        let outcome = if busy { "busy" } else { "complete" };
+        counter!("sqlite_checkpoints_total", "outcome" => outcome).increment(1);
+        histogram!("sqlite_checkpoint_duration_seconds").record(duration_secs);
+    }
+
+    /// Record free pages returned to the filesystem by incremental vacuum
+    pub fn record_vacuumed_pages(pages: u64) {
+        counter!("sqlite_vacuumed_pages_total").increment(pages);
+    }
+
+    /// Record an email refused by the per-user `[email_quota]`
+    pub fn record_email_quota_exceeded(window_seconds: i64) {
+        counter!("email_quota_exceeded_total", "window" => window_seconds.to_string()).increment(1);
+    }
+
+    /// Record a pending-challenge lookup answered from memory (`hit`) or the database
+    pub fn record_challenge_lookup(hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        counter!("challenge_cache_lookups_total", "result" => result).increment(1);
+    }
+
+    /// Record the number of pending challenges held in memory
+    pub fn record_challenges_cached(entries: usize) {
+        gauge!("challenge_cache_entries").set(entries as f64);
+    }
+
+    /// Record a sign-in attempt on a canary account (`magic_link`, `totp` or `webauthn`)
+    pub fn record_canary_triggered(method: &str) {
+        counter!("canary_triggers_total", "method" => method.to_string()).increment(1);
+    }
+
+    /// Record a broadcast recipient moved into the email queue (`queued`) or
+    /// skipped (`opted_out`, `suppressed`)
+    pub fn record_broadcast_email(outcome: &str) {
+        counter!("broadcast_emails_total", "outcome" => outcome.to_string()).increment(1);
+    }
+
+    /// Record a user whose credentials a forced reset revoked
+    pub fn record_credential_reset_user() {
+        counter!("credential_reset_users_total").increment(1);
+    }
+
+    /// Record an account moved by the inactivity policy (`flagged`,
+    /// `disabled`, `purged`, `reactivated` or `reinstated`)
+    pub fn record_stale_account(transition: &'static str) {
+        counter!("stale_account_transitions_total", "transition" => transition).increment(1);
+    }
+
+    /// Record a login matching a `[geo_policy]` rule (`block` or `step_up`)
+    pub fn record_geo_policy(action: &str, allowed: bool) {
+        let outcome = if allowed { "allowed" } else { "refused" };
+        counter!("geo_policy_triggers_total", "action" => action.to_string(), "outcome" => outcome).increment(1);
+    }
+
+    /// Record a write refused because the instance is read-only
+    pub fn record_read_only_rejection() {
+        counter!("read_only_rejections_total").increment(1);
+    }
+
+    /// Record auth requests currently admitted by load shedding
+    pub fn record_requests_in_flight(in_flight: usize) {
+        gauge!("requests_in_flight").set(in_flight as f64);
     }
 
     /// Record HTTP request duration
     pub fn record_request_duration(method: &str, path: &str, status: u16, duration_secs: f64) {
         histogram!(
             "http_request_duration_seconds",
-            "method" => method,
-            "path" => path,
+            "method" => method.to_string(),
+            "path" => path.to_string(),
             "status" => status.to_string()
         )
         .record(duration_secs);
     }
 
+    /// Record the time one auth request spent in a stage (see `latency`),
+    /// with the request's trace as exemplar
+    pub fn record_stage_duration(stage: &str, duration_secs: f64, trace_id: Option<&str>) {
+        histogram!("auth_stage_duration_seconds", "stage" => stage.to_string()).record(duration_secs);
+        exemplars::observe("auth_stage_duration_seconds", &[("stage", stage)], duration_secs, trace_id);
+    }
+
+    /// Record the time taken to hand one email to the SMTP server, with the
+    /// trace of the request that sent it as exemplar
+    pub fn record_email_send_duration(duration_secs: f64, trace_id: Option<&str>) {
+        histogram!("email_send_duration_seconds").record(duration_secs);
+        exemplars::observe("email_send_duration_seconds", &[], duration_secs, trace_id);
+    }
+
     /// Record database query duration
     pub fn record_db_query_duration(query_type: &str, duration_secs: f64) {
-        histogram!("db_query_duration_seconds", "type" => query_type).record(duration_secs);
+        histogram!("db_query_duration_seconds", "type" => query_type.to_string()).record(duration_secs);
     }
 }
 
@@ -100,6 +227,7 @@ pub struct HealthResponse {
 pub struct MetricsState {
     pub start_time: SystemTime,
     pub prometheus_handle: PrometheusHandle,
+    pub dependencies: Arc<DependencyHealth>,
 }
 
 /// Health check endpoint
@@ -121,6 +249,12 @@ pub async fn health_check(State(state): State<MetricsState>) -> impl IntoRespons
     (StatusCode::OK, axum::Json(response))
 }
 
+/// Dependency status for the ops status page (cached probe results, never
+/// blocks on a slow dependency)
+pub async fn dependencies_check(State(state): State<MetricsState>) -> impl IntoResponse {
+    (StatusCode::OK, axum::Json(state.dependencies.report()))
+}
+
 /// Readiness check endpoint (for Kubernetes)
 pub async fn readiness_check() -> impl IntoResponse {
     // In production, you might check database connectivity, etc.
@@ -132,17 +266,35 @@ pub async fn liveness_check() -> impl IntoResponse {
     (StatusCode::OK, "alive")
 }
 
-/// Prometheus metrics endpoint
-pub async fn metrics_handler(State(state): State<MetricsState>) -> impl IntoResponse {
-    state.prometheus_handle.render()
+/// Prometheus metrics endpoint; scrapers that accept OpenMetrics get
+/// exemplars when `[exemplars]` is enabled
+pub async fn metrics_handler(State(state): State<MetricsState>, headers: HeaderMap) -> impl IntoResponse {
+    let exposition = state.prometheus_handle.render();
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(exemplars::accepts_openmetrics);
+    match exemplars::installed() {
+        Some(exemplars) if openmetrics => (
+            [(header::CONTENT_TYPE, exemplars::OPENMETRICS_CONTENT_TYPE)],
+            exemplars.annotate(&exposition),
+        )
+            .into_response(),
+        _ => exposition.into_response(),
+    }
 }
 
-/// Create metrics router
-pub fn metrics_router(state: MetricsState) -> Router {
+/// Create metrics router; `/metrics` is subject to the IP access rules while
+/// health probes stay reachable for orchestrators
+pub fn metrics_router(state: MetricsState, guard: IpGuard) -> Router {
     Router::new()
         .route("/health", get(health_check))
+        .route("/health/dependencies", get(dependencies_check))
         .route("/readiness", get(readiness_check))
         .route("/liveness", get(liveness_check))
-        .route("/metrics", get(metrics_handler))
+        .route(
+            "/metrics",
+            get(metrics_handler).layer(middleware::from_fn_with_state(guard, ip_access::enforce)),
+        )
         .with_state(state)
 }
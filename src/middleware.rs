@@ -1,23 +1,25 @@
 use axum::{
-    body::Body,
-    extract::Request,
-    http::{header, HeaderValue, StatusCode},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use std::net::SocketAddr;
 use tracing::warn;
 use uuid::Uuid;
 
-/// Add security headers to all responses
-pub async fn security_headers(request: Request, next: Next) -> Response {
+/// Add security headers to all responses; the state is the `hsts` setting
+pub async fn security_headers(State(hsts): State<bool>, request: Request, next: Next) -> Response {
     let mut response = next.run(request).await;
     let headers = response.headers_mut();
 
-    // HSTS - Force HTTPS for 1 year
-    headers.insert(
-        header::STRICT_TRANSPORT_SECURITY,
-        HeaderValue::from_static("max-age=31536000; includeSubDomains; preload"),
-    );
+    // HSTS - Force HTTPS for 1 year (off in development, where it would pin localhost to https)
+    if hsts {
+        headers.insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static("max-age=31536000; includeSubDomains; preload"),
+        );
+    }
 
     // Prevent MIME type sniffing
     headers.insert(
@@ -33,7 +35,7 @@ pub async fn security_headers(request: Request, next: Next) -> Response {
 
     // XSS Protection
     headers.insert(
-        HeaderValue::from_static("X-XSS-Protection"),
+        HeaderName::from_static("x-xss-protection"),
         HeaderValue::from_static("1; mode=block"),
     );
 
@@ -53,7 +55,7 @@ pub async fn security_headers(request: Request, next: Next) -> Response {
 
     // Permissions Policy (formerly Feature Policy)
     headers.insert(
-        HeaderValue::from_static("Permissions-Policy"),
+        HeaderName::from_static("permissions-policy"),
         HeaderValue::from_static(
             "geolocation=(), microphone=(), camera=(), payment=(), usb=()",
         ),
@@ -62,6 +64,19 @@ pub async fn security_headers(request: Request, next: Next) -> Response {
     response
 }
 
+/// Mark responses as uncacheable unless the handler chose a policy itself
+/// (e.g. the published signing keys). Auth and admin responses carry tokens,
+/// sessions and personal data that must not land in browser or proxy caches.
+pub async fn no_store(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if !headers.contains_key(header::CACHE_CONTROL) {
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    }
+    response
+}
+
 /// Add request ID to all requests for tracing
 pub async fn request_id(mut request: Request, next: Next) -> Response {
     let request_id = Uuid::new_v4().to_string();
@@ -73,7 +88,7 @@ pub async fn request_id(mut request: Request, next: Next) -> Response {
 
     // Add to response headers
     response.headers_mut().insert(
-        HeaderValue::from_static("X-Request-ID"),
+        HeaderName::from_static("x-request-id"),
         HeaderValue::from_str(&request_id).unwrap(),
     );
 
@@ -120,8 +135,21 @@ pub async fn validate_content_type(request: Request, next: Next) -> Result<Respo
 
 /// Extract IP address from request
 pub fn extract_ip_address(request: &Request) -> Option<String> {
+    forwarded_ip(request.headers())
+}
+
+/// Client address from `X-Forwarded-For` / `X-Real-IP`, or else the peer
+/// address. Forwarding headers are only honoured behind a trusted proxy.
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_forwarded_for: bool) -> Option<String> {
+    trust_forwarded_for
+        .then(|| forwarded_ip(headers))
+        .flatten()
+        .or_else(|| peer.map(|addr| addr.ip().to_string()))
+}
+
+fn forwarded_ip(headers: &HeaderMap) -> Option<String> {
     // Try X-Forwarded-For first (for proxies)
-    if let Some(forwarded_for) = request.headers().get("X-Forwarded-For") {
+    if let Some(forwarded_for) = headers.get("X-Forwarded-For") {
         if let Ok(value) = forwarded_for.to_str() {
             // Take the first IP in the list
             if let Some(ip) = value.split(',').next() {
@@ -131,14 +159,12 @@ pub fn extract_ip_address(request: &Request) -> Option<String> {
     }
 
     // Try X-Real-IP
-    if let Some(real_ip) = request.headers().get("X-Real-IP") {
+    if let Some(real_ip) = headers.get("X-Real-IP") {
         if let Ok(value) = real_ip.to_str() {
             return Some(value.to_string());
         }
     }
 
-    // Fall back to connection info
-    // Note: This would need ConnectInfo in the actual handler
     None
 }
 
@@ -1,3 +1,4 @@
+use crate::{device::ClientHints, user_agent::UserAgent};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,11 +17,46 @@ pub struct MagicLink {
     pub used: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RefreshToken {
+    /// Hex SHA-256 of the token; the token itself is never stored
     pub token: String,
     pub user_id: String,
+    /// Public session identifier shared by every rotation of the token
+    pub session_id: Option<String>,
     pub expires_at: i64,
     pub revoked: bool,
     pub created_at: i64,
 }
+
+/// A refresh session with the client hints captured when it was created
+#[derive(Debug, Clone)]
+pub struct UserSession {
+    pub refresh: RefreshToken,
+    pub device: Option<ClientHints>,
+    /// Browser and OS parsed from the `User-Agent` at creation
+    pub agent: Option<UserAgent>,
+}
+
+/// What an outstanding sign-in challenge is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeKind {
+    MagicLink,
+    WebauthnRegister,
+    WebauthnLogin,
+}
+
+/// An unused, unexpired magic link or WebAuthn ceremony
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingChallenge {
+    /// `ml_<n>` for magic links (the token itself is never exposed),
+    /// the pending ceremony id for WebAuthn
+    pub id: String,
+    pub kind: ChallengeKind,
+    pub user_id: String,
+    pub email: String,
+    /// Not recorded for magic links
+    pub created_at: Option<i64>,
+    pub expires_at: i64,
+}
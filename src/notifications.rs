@@ -0,0 +1,434 @@
+use crate::{
+    crypto,
+    db::Database,
+    resilience::{CallError, CircuitBreaker, DependencyPolicy},
+};
+use reqwest::Client;
+use rand::Rng;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use thiserror::Error;
+use tracing::{error, info};
+
+/// `[notifications]` configuration
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct NotificationConfig {
+    /// HTTP SMS gateway receiving `{"to": "+4915...", "body": "..."}`;
+    /// unset = the SMS channel cannot be selected
+    #[serde(default)]
+    pub sms_gateway_url: Option<String>,
+    /// Sent as `Authorization: Bearer <token>` to the gateway
+    #[serde(default)]
+    pub sms_gateway_token: Option<String>,
+}
+
+/// What a message is about; users choose per category whether and where
+/// they receive it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// Magic links and signed action links (always email)
+    SignIn,
+    /// Credential changes: passkeys, TOTP, recovery codes
+    Security,
+    /// Sign-ins from new devices or countries
+    NewSignIn,
+    ProductUpdates,
+    Marketing,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Self::SignIn,
+        Self::Security,
+        Self::NewSignIn,
+        Self::ProductUpdates,
+        Self::Marketing,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::SignIn => "sign_in",
+            Self::Security => "security",
+            Self::NewSignIn => "new_sign_in",
+            Self::ProductUpdates => "product_updates",
+            Self::Marketing => "marketing",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == s)
+    }
+
+    /// Mandatory categories cannot be turned off
+    pub fn mandatory(&self) -> bool {
+        matches!(self, Self::SignIn | Self::Security)
+    }
+
+    /// Marketing is opt-in; everything else is on until turned off
+    fn default_enabled(&self) -> bool {
+        !matches!(self, Self::Marketing)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    #[default]
+    Email,
+    Sms,
+}
+
+impl Channel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Sms => "sms",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        if s == "sms" {
+            Self::Sms
+        } else {
+            Self::Email
+        }
+    }
+}
+
+/// Effective preference for one category
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Preference {
+    pub category: Category,
+    pub enabled: bool,
+    pub channel: Channel,
+    pub mandatory: bool,
+}
+
+/// Seconds a phone confirmation code stays valid
+pub const PHONE_CODE_TTL_SECONDS: i64 = 600;
+
+/// Wrong codes after which a pending number must be requested again
+pub const PHONE_CODE_MAX_ATTEMPTS: i64 = 5;
+
+/// Seconds before another code can be sent for the same user
+pub const PHONE_CODE_RESEND_SECONDS: i64 = 60;
+
+/// `GET /me/notifications` response
+#[derive(Debug, Serialize)]
+pub struct PreferenceCenter {
+    /// Confirmed number SMS notifications go to
+    pub phone: Option<String>,
+    /// Number waiting for its confirmation code
+    pub pending_phone: Option<String>,
+    pub preferences: Vec<Preference>,
+}
+
+/// Partial update for one category; omitted fields are left unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PreferencePatch {
+    pub enabled: Option<bool>,
+    pub channel: Option<Channel>,
+}
+
+#[derive(Debug, Error)]
+pub enum NotificationError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("unknown notification category {0:?}")]
+    UnknownCategory(String),
+    #[error("{0} notifications are mandatory and cannot be turned off")]
+    Mandatory(&'static str),
+    #[error("{0} notifications are delivered by email only")]
+    EmailOnly(&'static str),
+    #[error("sms delivery is not configured")]
+    SmsUnavailable,
+    #[error("a phone number is required for sms delivery")]
+    PhoneRequired,
+    #[error("phone number must be in E.164 format (+ followed by 8 to 15 digits)")]
+    InvalidPhone,
+    #[error("a confirmation code was sent recently; wait before requesting another")]
+    CodeRecentlySent,
+    #[error("no phone number is waiting for confirmation")]
+    NoPendingPhone,
+    #[error("invalid confirmation code")]
+    InvalidCode,
+}
+
+/// Where to deliver one message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    Email,
+    Sms { phone: String },
+}
+
+/// Effective preferences for every category
+pub fn preferences(db: &Database, user_id: &str) -> Result<Vec<Preference>, rusqlite::Error> {
+    // `kind` holds the category name
+    let conn = db.conn();
+    let mut stmt = conn.prepare("SELECT kind, enabled, channel FROM notification_preferences WHERE user_id = ?1")?;
+    let stored: HashMap<String, (bool, String)> = stmt
+        .query_map(params![user_id], |r| Ok((r.get(0)?, (r.get(1)?, r.get(2)?))))?
+        .collect::<Result<_, _>>()?;
+    Ok(Category::ALL
+        .into_iter()
+        .map(|category| {
+            let (enabled, channel) = stored
+                .get(category.as_str())
+                .map(|(enabled, channel)| (*enabled, Channel::parse(channel)))
+                .unwrap_or((category.default_enabled(), Channel::Email));
+            Preference {
+                category,
+                enabled: enabled || category.mandatory(),
+                channel,
+                mandatory: category.mandatory(),
+            }
+        })
+        .collect())
+}
+
+pub fn phone(db: &Database, user_id: &str) -> Result<Option<String>, rusqlite::Error> {
+    Ok(db
+        .conn()
+        .query_row("SELECT phone FROM users WHERE id = ?1", params![user_id], |r| r.get(0))
+        .optional()?
+        .flatten())
+}
+
+fn check_phone(phone: &str) -> Result<(), NotificationError> {
+    let digits = phone.strip_prefix('+').ok_or(NotificationError::InvalidPhone)?;
+    if !(8..=15).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(NotificationError::InvalidPhone);
+    }
+    Ok(())
+}
+
+/// Number waiting for its confirmation code, while the code is valid
+pub fn pending_phone(db: &Database, user_id: &str) -> Result<Option<String>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            "SELECT phone FROM phone_verifications WHERE user_id = ?1 AND expires_at > ?2",
+            params![user_id, Database::now_ts()],
+            |r| r.get(0),
+        )
+        .optional()
+}
+
+/// Start confirming `phone` (E.164): returns the code to text to it. The
+/// number receives nothing else until [`confirm_phone`] accepts the code.
+pub fn request_phone(
+    db: &Database,
+    user_id: &str,
+    phone: &str,
+    sms_available: bool,
+) -> Result<String, NotificationError> {
+    check_phone(phone)?;
+    if !sms_available {
+        return Err(NotificationError::SmsUnavailable);
+    }
+    let now = Database::now_ts();
+    let last: Option<i64> = db
+        .conn()
+        .query_row(
+            "SELECT created_at FROM phone_verifications WHERE user_id = ?1",
+            params![user_id],
+            |r| r.get(0),
+        )
+        .optional()?;
+    if last.is_some_and(|at| now - at < PHONE_CODE_RESEND_SECONDS) {
+        return Err(NotificationError::CodeRecentlySent);
+    }
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    db.conn().execute(
+        "INSERT INTO phone_verifications (user_id, phone, code_hash, attempts, expires_at, created_at)
+         VALUES (?1, ?2, ?3, 0, ?4, ?5)
+         ON CONFLICT(user_id) DO UPDATE SET phone = excluded.phone, code_hash = excluded.code_hash, attempts = 0,
+             expires_at = excluded.expires_at, created_at = excluded.created_at",
+        params![user_id, phone, crypto::token_digest(&code), now + PHONE_CODE_TTL_SECONDS, now],
+    )?;
+    Ok(code)
+}
+
+/// Make the pending number the user's SMS number if `code` is the one sent
+/// to it; returns the number. Too many wrong codes discard the pending number.
+pub fn confirm_phone(db: &Database, user_id: &str, code: &str) -> Result<String, NotificationError> {
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let (phone, code_hash, attempts): (String, String, i64) = tx
+        .query_row(
+            "SELECT phone, code_hash, attempts FROM phone_verifications WHERE user_id = ?1 AND expires_at > ?2",
+            params![user_id, Database::now_ts()],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .optional()?
+        .ok_or(NotificationError::NoPendingPhone)?;
+    if !crypto::constant_time_eq(crypto::token_digest(code.trim()).as_bytes(), code_hash.as_bytes()) {
+        if attempts + 1 >= PHONE_CODE_MAX_ATTEMPTS {
+            tx.execute("DELETE FROM phone_verifications WHERE user_id = ?1", params![user_id])?;
+        } else {
+            tx.execute(
+                "UPDATE phone_verifications SET attempts = attempts + 1 WHERE user_id = ?1",
+                params![user_id],
+            )?;
+        }
+        tx.commit()?;
+        return Err(NotificationError::InvalidCode);
+    }
+    tx.execute("DELETE FROM phone_verifications WHERE user_id = ?1", params![user_id])?;
+    tx.execute("UPDATE users SET phone = ?1 WHERE id = ?2", params![phone, user_id])?;
+    tx.commit()?;
+    Ok(phone)
+}
+
+/// Set or clear the SMS number (E.164) without a confirmation code, for a
+/// number confirmed elsewhere. Clearing it moves SMS categories back to email.
+pub fn set_phone(db: &Database, user_id: &str, phone: Option<&str>) -> Result<(), NotificationError> {
+    if let Some(p) = phone {
+        check_phone(p)?;
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("UPDATE users SET phone = ?1 WHERE id = ?2", params![phone, user_id])?;
+    if phone.is_none() {
+        tx.execute(
+            "UPDATE notification_preferences SET channel = 'email' WHERE user_id = ?1",
+            params![user_id],
+        )?;
+        tx.execute("DELETE FROM phone_verifications WHERE user_id = ?1", params![user_id])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Apply a `PATCH /me/notifications` body atomically
+pub fn update(
+    db: &Database,
+    user_id: &str,
+    patches: &HashMap<String, PreferencePatch>,
+    sms_available: bool,
+) -> Result<Vec<Preference>, NotificationError> {
+    let current = preferences(db, user_id)?;
+    let has_phone = phone(db, user_id)?.is_some();
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    for (name, patch) in patches {
+        let category = Category::parse(name).ok_or_else(|| NotificationError::UnknownCategory(name.clone()))?;
+        let pref = current.iter().find(|p| p.category == category).expect("all categories listed");
+        let enabled = patch.enabled.unwrap_or(pref.enabled);
+        let channel = patch.channel.unwrap_or(pref.channel);
+        if !enabled && category.mandatory() {
+            return Err(NotificationError::Mandatory(category.as_str()));
+        }
+        if channel == Channel::Sms {
+            if category == Category::SignIn {
+                return Err(NotificationError::EmailOnly(category.as_str()));
+            }
+            if !sms_available {
+                return Err(NotificationError::SmsUnavailable);
+            }
+            if !has_phone {
+                return Err(NotificationError::PhoneRequired);
+            }
+        }
+        tx.execute(
+            "INSERT INTO notification_preferences (user_id, kind, enabled, channel, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(user_id, kind) DO UPDATE SET enabled = excluded.enabled, channel = excluded.channel, updated_at = excluded.updated_at",
+            params![user_id, category.as_str(), enabled, channel.as_str(), Database::now_ts()],
+        )?;
+    }
+    tx.commit()?;
+    Ok(preferences(db, user_id)?)
+}
+
+/// How (and whether) to deliver a message in `category` to the user.
+/// `None` means the user turned the category off.
+pub fn delivery(db: &Database, user_id: &str, category: Category) -> Result<Option<Delivery>, rusqlite::Error> {
+    let pref = preferences(db, user_id)?
+        .into_iter()
+        .find(|p| p.category == category)
+        .expect("all categories listed");
+    if !pref.enabled {
+        return Ok(None);
+    }
+    Ok(Some(match (pref.channel, phone(db, user_id)?) {
+        (Channel::Sms, Some(phone)) if category != Category::SignIn => Delivery::Sms { phone },
+        _ => Delivery::Email,
+    }))
+}
+
+/// Sends text messages through the configured HTTP gateway
+#[derive(Clone)]
+pub struct SmsSender {
+    client: Client,
+    gateway_url: Option<String>,
+    gateway_token: Option<String>,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl SmsSender {
+    pub fn new(cfg: &NotificationConfig, policy: DependencyPolicy) -> Self {
+        Self {
+            client: Client::builder().timeout(Duration::from_secs(10)).build().unwrap(),
+            gateway_url: cfg.sms_gateway_url.clone(),
+            gateway_token: cfg.sms_gateway_token.clone(),
+            breaker: Arc::new(CircuitBreaker::new("sms", policy)),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.gateway_url.is_some()
+    }
+
+    pub async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        let Some(url) = &self.gateway_url else {
+            return Err("sms gateway not configured".to_string());
+        };
+        let payload = serde_json::json!({ "to": to, "body": body });
+        let result = self
+            .breaker
+            .call_async(|| async {
+                let mut request = self.client.post(url).json(&payload);
+                if let Some(token) = &self.gateway_token {
+                    request = request.bearer_auth(token);
+                }
+                let response = request.send().await.map_err(|e| e.to_string())?;
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("sms gateway returned {}", response.status()))
+                }
+            })
+            .await;
+        match result {
+            Ok(()) => Ok(()),
+            Err(CallError::Open) => Err("sms circuit open".to_string()),
+            Err(CallError::Failed(e)) => Err(e),
+        }
+    }
+
+    /// Send in the background; failures are logged
+    pub fn send_background(&self, to: String, body: String) {
+        let sender = self.clone();
+        tokio::spawn(async move {
+            match sender.send(&to, &body).await {
+                Ok(()) => info!("SMS notification sent"),
+                Err(e) => error!("Failed to send SMS notification: {}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categories_round_trip() {
+        for category in Category::ALL {
+            assert_eq!(Category::parse(category.as_str()), Some(category));
+        }
+        assert!(Category::SignIn.mandatory() && Category::Security.mandatory());
+        assert!(!Category::Marketing.default_enabled());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{client_registration::RegistrationError, service::ServiceError};
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+
+/// Realm advertised in `WWW-Authenticate` challenges
+const REALM: &str = "passwordless-auth";
+
+/// Error codes from RFC 6749 §5.2, RFC 6750 §3.1, RFC 7009, RFC 7591 §3.2.2 and RFC 8628
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OAuthErrorCode {
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedTokenType,
+    InvalidScope,
+    InvalidToken,
+    InsufficientScope,
+    AccessDenied,
+    AuthorizationPending,
+    SlowDown,
+    ExpiredToken,
+    InvalidRedirectUri,
+    InvalidClientMetadata,
+    ServerError,
+    TemporarilyUnavailable,
+}
+
+impl OAuthErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::UnsupportedTokenType => "unsupported_token_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::InvalidToken => "invalid_token",
+            Self::InsufficientScope => "insufficient_scope",
+            Self::AccessDenied => "access_denied",
+            Self::AuthorizationPending => "authorization_pending",
+            Self::SlowDown => "slow_down",
+            Self::ExpiredToken => "expired_token",
+            Self::InvalidRedirectUri => "invalid_redirect_uri",
+            Self::InvalidClientMetadata => "invalid_client_metadata",
+            Self::ServerError => "server_error",
+            Self::TemporarilyUnavailable => "temporarily_unavailable",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidClient | Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::InsufficientScope => StatusCode::FORBIDDEN,
+            Self::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::TemporarilyUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// OAuth 2.0 error response for token, introspection, registration and revocation endpoints.
+///
+/// Serializes as `{"error": "...", "error_description": "..."}` instead of the
+/// [`crate::error::ApiError`] envelope so standard OAuth clients can parse it.
+#[derive(Debug, Clone, Serialize)]
+pub struct OAuthError {
+    pub error: OAuthErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+impl OAuthError {
+    pub fn new(error: OAuthErrorCode) -> Self {
+        Self {
+            error,
+            error_description: None,
+        }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.error_description = Some(description.into());
+        self
+    }
+
+    pub fn invalid_request(description: impl Into<String>) -> Self {
+        Self::new(OAuthErrorCode::InvalidRequest).with_description(description)
+    }
+
+    pub fn invalid_client() -> Self {
+        Self::new(OAuthErrorCode::InvalidClient).with_description("client authentication failed")
+    }
+
+    pub fn invalid_grant(description: impl Into<String>) -> Self {
+        Self::new(OAuthErrorCode::InvalidGrant).with_description(description)
+    }
+
+    pub fn invalid_token() -> Self {
+        Self::new(OAuthErrorCode::InvalidToken).with_description("token is invalid or expired")
+    }
+
+    pub fn server_error() -> Self {
+        Self::new(OAuthErrorCode::ServerError)
+    }
+
+    /// `WWW-Authenticate` challenge for this error, if the spec requires one
+    pub fn www_authenticate(&self) -> Option<String> {
+        match self.error {
+            // RFC 6749 §5.2: clients authenticating via HTTP Basic get a Basic challenge
+            OAuthErrorCode::InvalidClient => Some(format!("Basic realm=\"{}\"", REALM)),
+            // RFC 6750 §3: bearer token errors
+            OAuthErrorCode::InvalidToken | OAuthErrorCode::InsufficientScope => {
+                let mut challenge = format!(
+                    "Bearer realm=\"{}\", error=\"{}\"",
+                    REALM,
+                    self.error.as_str()
+                );
+                if let Some(desc) = &self.error_description {
+                    challenge.push_str(&format!(
+                        ", error_description=\"{}\"",
+                        desc.replace(['"', '\\'], "")
+                    ));
+                }
+                Some(challenge)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let challenge = self.www_authenticate();
+        let mut resp = (self.error.status(), Json(&self)).into_response();
+        let headers = resp.headers_mut();
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+        if let Some(value) = challenge.and_then(|c| HeaderValue::from_str(&c).ok()) {
+            headers.insert(header::WWW_AUTHENTICATE, value);
+        }
+        resp
+    }
+}
+
+impl From<ServiceError> for OAuthError {
+    fn from(e: ServiceError) -> Self {
+        match e {
+            ServiceError::InvalidToken | ServiceError::InvalidTokenKind => Self::invalid_token(),
+            ServiceError::InvalidRefresh => Self::invalid_grant("refresh token is invalid or revoked"),
+            ServiceError::MagicLinkUsed | ServiceError::MagicLinkInvalid => {
+                Self::invalid_grant(e.public_message())
+            }
+            ServiceError::Internal(_) | ServiceError::EmailFailed => Self::server_error(),
+            ServiceError::ReadOnly { .. } => {
+                Self::new(OAuthErrorCode::TemporarilyUnavailable).with_description(e.public_message())
+            }
+            e => Self::invalid_request(e.public_message()),
+        }
+    }
+}
+
+impl From<RegistrationError> for OAuthError {
+    fn from(e: RegistrationError) -> Self {
+        match e {
+            RegistrationError::RedirectUri(_) => {
+                Self::new(OAuthErrorCode::InvalidRedirectUri).with_description(e.to_string())
+            }
+            RegistrationError::Metadata(_) => {
+                Self::new(OAuthErrorCode::InvalidClientMetadata).with_description(e.to_string())
+            }
+            RegistrationError::Db(_) | RegistrationError::Reviewed(_) => Self::server_error(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_client_gets_basic_challenge() {
+        let resp = OAuthError::invalid_client().into_response();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            resp.headers()[header::WWW_AUTHENTICATE],
+            "Basic realm=\"passwordless-auth\""
+        );
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[test]
+    fn invalid_token_gets_bearer_challenge() {
+        let err = OAuthError::invalid_token();
+        assert_eq!(
+            err.www_authenticate().unwrap(),
+            "Bearer realm=\"passwordless-auth\", error=\"invalid_token\", error_description=\"token is invalid or expired\""
+        );
+        assert_eq!(err.error.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn serializes_rfc6749_shape() {
+        let json = serde_json::to_value(OAuthError::new(OAuthErrorCode::UnsupportedGrantType)).unwrap();
+        assert_eq!(json, serde_json::json!({ "error": "unsupported_grant_type" }));
+        assert!(OAuthError::new(OAuthErrorCode::InvalidGrant).www_authenticate().is_none());
+    }
+}
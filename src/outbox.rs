@@ -0,0 +1,270 @@
+use crate::{
+    audit::{AuditEventType, AuditLogger},
+    db::Database,
+    leader::LeaderElection,
+    webhooks::{WebhookEventType, WebhookPayload, WebhookSender},
+};
+use chrono::{TimeZone, Utc};
+use rusqlite::{params, Connection};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::time::interval;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+/// Webhook deliveries are dead-lettered after this many failed attempts
+pub const MAX_ATTEMPTS: i64 = 10;
+
+/// Rows handled per dispatcher tick
+const BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Error)]
+pub enum OutboxError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// An auth event recorded alongside the domain change that caused it
+#[derive(Debug, Clone)]
+pub struct OutboxEvent {
+    pub audit: AuditEventType,
+    pub webhook: Option<WebhookEventType>,
+    pub user_id: Option<String>,
+    pub email: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub success: bool,
+}
+
+impl OutboxEvent {
+    pub fn new(audit: AuditEventType) -> Self {
+        Self {
+            audit,
+            webhook: None,
+            user_id: None,
+            email: None,
+            metadata: None,
+            success: true,
+        }
+    }
+
+    pub fn user(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn webhook(mut self, event: WebhookEventType) -> Self {
+        self.webhook = Some(event);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+}
+
+/// A pending outbox row
+#[derive(Debug)]
+struct OutboxRow {
+    id: i64,
+    event_id: String,
+    audit_event: String,
+    webhook_event: Option<String>,
+    user_id: Option<String>,
+    email: Option<String>,
+    metadata: Option<String>,
+    success: bool,
+    created_at: i64,
+    attempts: i64,
+    audit_written: bool,
+}
+
+pub struct Outbox;
+
+impl Outbox {
+    /// Record an event. Pass the open transaction of the domain change so the
+    /// event is committed (or rolled back) together with it.
+    pub fn enqueue(conn: &Connection, event: &OutboxEvent) -> Result<String, OutboxError> {
+        let event_id = Uuid::new_v4().to_string();
+        let now = Database::now_ts();
+        let webhook_event = match &event.webhook {
+            Some(w) => serde_json::to_value(w)?.as_str().map(str::to_string),
+            None => None,
+        };
+        conn.execute(
+            "INSERT INTO outbox (event_id, audit_event, webhook_event, user_id, email, metadata, success, created_at, next_attempt_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+            params![
+                event_id,
+                event.audit.as_str(),
+                webhook_event,
+                event.user_id,
+                event.email,
+                event.metadata.as_ref().map(|m| m.to_string()),
+                event.success,
+                now
+            ],
+        )?;
+        Ok(event_id)
+    }
+
+    fn fetch_due(conn: &Connection, limit: i64) -> Result<Vec<OutboxRow>, OutboxError> {
+        let mut stmt = conn.prepare(
+            "SELECT id, event_id, audit_event, webhook_event, user_id, email, metadata, success, created_at, attempts, audit_written_at
+             FROM outbox
+             WHERE delivered_at IS NULL AND attempts < ?1 AND next_attempt_at <= ?2
+             ORDER BY id ASC LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![MAX_ATTEMPTS, Database::now_ts(), limit], |r| {
+            Ok(OutboxRow {
+                id: r.get(0)?,
+                event_id: r.get(1)?,
+                audit_event: r.get(2)?,
+                webhook_event: r.get(3)?,
+                user_id: r.get(4)?,
+                email: r.get(5)?,
+                metadata: r.get(6)?,
+                success: r.get(7)?,
+                created_at: r.get(8)?,
+                attempts: r.get(9)?,
+                audit_written: r.get::<_, Option<i64>>(10)?.is_some(),
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Number of events still waiting for delivery
+    pub fn pending_count(conn: &Connection) -> Result<i64, OutboxError> {
+        Ok(conn.query_row(
+            "SELECT COUNT(*) FROM outbox WHERE delivered_at IS NULL AND attempts < ?1",
+            params![MAX_ATTEMPTS],
+            |r| r.get(0),
+        )?)
+    }
+
+    /// Deliver due events: the audit row is written exactly once (same database),
+    /// webhooks are delivered at least once with a stable `X-Webhook-Event-Id`.
+    pub async fn dispatch_batch(
+        db: &Database,
+        audit: &AuditLogger,
+        webhook: &WebhookSender,
+    ) -> Result<usize, OutboxError> {
+        let due = Self::fetch_due(&db.conn(), BATCH_SIZE)?;
+        let mut delivered = 0;
+
+        for row in due {
+            if !row.audit_written {
+                if let Err(e) = Self::write_audit(db, audit, &row) {
+                    // stays pending and is retried like a failed webhook
+                    Self::retry_later(db, &row, &format!("audit write failed: {}", e))?;
+                    continue;
+                }
+            }
+
+            let outcome = match (&row.webhook_event, row.user_id.clone()) {
+                (Some(event), Some(user_id)) if webhook.is_configured() => {
+                    match serde_json::from_value::<WebhookEventType>(event.clone().into()) {
+                        Ok(event) => {
+                            let payload = WebhookPayload {
+                                event,
+                                user_id,
+                                email: row.email.clone(),
+                                timestamp: Utc
+                                    .timestamp_opt(row.created_at, 0)
+                                    .single()
+                                    .unwrap_or_else(Utc::now)
+                                    .to_rfc3339(),
+                                metadata: row.metadata.as_deref().and_then(|m| serde_json::from_str(m).ok()),
+                            };
+                            webhook.deliver(&payload, &row.event_id).await
+                        }
+                        // unknown event names can never be delivered
+                        Err(e) => {
+                            warn!("Dropping outbox webhook {}: {}", row.event_id, e);
+                            Ok(())
+                        }
+                    }
+                }
+                _ => Ok(()),
+            };
+
+            match outcome {
+                Ok(()) => {
+                    db.conn().execute(
+                        "UPDATE outbox SET delivered_at = ?1, last_error = NULL WHERE id = ?2",
+                        params![Database::now_ts(), row.id],
+                    )?;
+                    delivered += 1;
+                }
+                Err(e) => Self::retry_later(db, &row, &e)?,
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// Write the row's audit event and mark it written, both or neither
+    fn write_audit(db: &Database, audit: &AuditLogger, row: &OutboxRow) -> Result<(), rusqlite::Error> {
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        audit.record(
+            &tx,
+            &row.audit_event,
+            row.user_id.as_deref(),
+            row.email.as_deref(),
+            None,
+            None,
+            row.metadata.as_deref(),
+            row.success,
+        )?;
+        tx.execute(
+            "UPDATE outbox SET audit_written_at = ?1 WHERE id = ?2",
+            params![Database::now_ts(), row.id],
+        )?;
+        tx.commit()
+    }
+
+    /// Schedule another attempt with exponential backoff, dead-lettering
+    /// the row after `MAX_ATTEMPTS`
+    fn retry_later(db: &Database, row: &OutboxRow, e: &str) -> Result<(), OutboxError> {
+        let attempts = row.attempts + 1;
+        let backoff = 2_i64.pow(attempts.min(8) as u32); // seconds, capped at ~4 minutes
+        let now = Database::now_ts();
+        let dead_at = (attempts >= MAX_ATTEMPTS).then_some(now);
+        db.conn().execute(
+            "UPDATE outbox SET attempts = ?1, last_error = ?2, next_attempt_at = ?3, dead_at = ?4 WHERE id = ?5",
+            params![attempts, e, now + backoff, dead_at, row.id],
+        )?;
+        if dead_at.is_some() {
+            error!("Outbox event {} dead-lettered after {} attempts: {}", row.event_id, attempts, e);
+        }
+        Ok(())
+    }
+}
+
+/// Drain the outbox every `every` in the background. Only the elected
+/// replica dispatches, so webhooks are not delivered once per replica.
+pub fn spawn_dispatcher(
+    db: Arc<Database>,
+    leader: Arc<LeaderElection>,
+    audit: Arc<AuditLogger>,
+    webhook: Arc<WebhookSender>,
+    every: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("outbox_dispatch", every) {
+                continue;
+            }
+            match Outbox::dispatch_batch(&db, &audit, &webhook).await {
+                Ok(0) => {}
+                Ok(n) => debug!("Outbox dispatched {} events", n),
+                Err(e) => error!("Outbox dispatch failed: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,109 @@
+use crate::{db::Database, jwt};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+/// JWT `kind` of pre-authorized passkey registration tickets
+pub const TICKET_KIND: &str = "passkey_registration";
+
+/// `[passkey_nudge]` configuration table
+#[derive(Debug, Deserialize, Clone)]
+pub struct PasskeyNudgeConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Minimum time between prompts for the same user
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: i64,
+    /// Stop prompting after this many prompts (0 = no limit)
+    #[serde(default = "default_max_prompts")]
+    pub max_prompts: u32,
+    /// Lifetime of the registration ticket included with a prompt
+    #[serde(default = "default_ticket_ttl_seconds")]
+    pub ticket_ttl_seconds: i64,
+}
+
+impl Default for PasskeyNudgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            interval_seconds: default_interval_seconds(),
+            max_prompts: default_max_prompts(),
+            ticket_ttl_seconds: default_ticket_ttl_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval_seconds() -> i64 {
+    604800
+}
+
+fn default_max_prompts() -> u32 {
+    3
+}
+
+fn default_ticket_ttl_seconds() -> i64 {
+    600
+}
+
+/// Hint added to `verify_magic` responses for users who should add a passkey
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyNudge {
+    pub suggest_passkey: bool,
+    /// Accepted by `/webauthn/register/options` in place of an email
+    pub passkey_registration_ticket: String,
+}
+
+/// Offer a nudge if the user has no passkey and is due for a prompt.
+/// Records the prompt when one is returned.
+pub fn offer(
+    db: &Database,
+    cfg: &PasskeyNudgeConfig,
+    jwt_secret: &str,
+    user_id: &str,
+) -> Result<Option<PasskeyNudge>, rusqlite::Error> {
+    if !cfg.enabled {
+        return Ok(None);
+    }
+    if db.credentials().count_for_user(user_id)? > 0 {
+        return Ok(None);
+    }
+
+    let now = Database::now_ts();
+    let state: Option<(u32, i64)> = db
+        .conn()
+        .query_row(
+            "SELECT prompts, last_prompted_at FROM passkey_nudges WHERE user_id = ?1",
+            params![user_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()?;
+    if let Some((prompts, last)) = state {
+        if (cfg.max_prompts > 0 && prompts >= cfg.max_prompts) || now - last < cfg.interval_seconds {
+            return Ok(None);
+        }
+    }
+
+    let Ok(ticket) = jwt::create_token(user_id, jwt_secret, cfg.ticket_ttl_seconds, TICKET_KIND) else {
+        return Ok(None);
+    };
+    db.conn().execute(
+        "INSERT INTO passkey_nudges (user_id, prompts, last_prompted_at) VALUES (?1, 1, ?2)
+         ON CONFLICT(user_id) DO UPDATE SET prompts = prompts + 1, last_prompted_at = excluded.last_prompted_at",
+        params![user_id, now],
+    )?;
+    Ok(Some(PasskeyNudge {
+        suggest_passkey: true,
+        passkey_registration_ticket: ticket,
+    }))
+}
+
+/// User id a registration ticket was issued for
+pub fn verify_ticket(jwt_secret: &str, leeway_seconds: u64, ticket: &str) -> Option<String> {
+    jwt::verify_token(ticket, jwt_secret, leeway_seconds)
+        .ok()
+        .filter(|c| c.kind == TICKET_KIND)
+        .map(|c| c.sub)
+}
@@ -0,0 +1,61 @@
+use crate::db::Database;
+use rusqlite::params;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+/// `kind` of the restricted access token issued while required profile
+/// fields are missing; it only authorizes `POST /me/profile/complete`
+pub const PROFILE_TOKEN_KIND: &str = "profile";
+
+/// Longest accepted field value, in characters
+const MAX_VALUE_CHARS: usize = 256;
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("profile field {0:?} is not registered for this application")]
+    UnknownField(String),
+    #[error("profile field {0:?} must be 1 to 256 printable characters")]
+    InvalidValue(String),
+}
+
+/// Required fields the user has not provided yet, in registration order
+pub fn missing_fields(db: &Database, user_id: &str, required: &[String]) -> Result<Vec<String>, rusqlite::Error> {
+    if required.is_empty() {
+        return Ok(Vec::new());
+    }
+    let conn = db.conn();
+    let mut stmt = conn.prepare("SELECT field FROM user_profile_fields WHERE user_id = ?1")?;
+    let present: HashSet<String> = stmt
+        .query_map(params![user_id], |r| r.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(required.iter().filter(|f| !present.contains(*f)).cloned().collect())
+}
+
+/// Store submitted fields; only fields the application registered are accepted
+pub fn save(
+    db: &Database,
+    user_id: &str,
+    fields: &HashMap<String, String>,
+    registered: &[String],
+) -> Result<(), ProfileError> {
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    for (field, value) in fields {
+        if !registered.contains(field) {
+            return Err(ProfileError::UnknownField(field.clone()));
+        }
+        let value = value.trim();
+        if value.is_empty() || value.chars().count() > MAX_VALUE_CHARS || value.chars().any(char::is_control) {
+            return Err(ProfileError::InvalidValue(field.clone()));
+        }
+        tx.execute(
+            "INSERT INTO user_profile_fields (user_id, field, value, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(user_id, field) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            params![user_id, field, value, Database::now_ts()],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
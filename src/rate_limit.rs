@@ -1,5 +1,4 @@
 use axum::{
-    body::Body,
     extract::{ConnectInfo, Request},
     http::StatusCode,
     middleware::Next,
@@ -10,7 +9,7 @@ use governor::{
     state::{InMemoryState, NotKeyed},
     Quota, RateLimiter as GovernorRateLimiter,
 };
-use std::{net::SocketAddr, num::NonZeroU32, sync::Arc, time::Duration};
+use std::{net::SocketAddr, num::NonZeroU32, sync::Arc};
 use tracing::warn;
 
 /// Rate limiter for IP-based requests
@@ -52,7 +51,9 @@ impl IpRateLimiter {
     }
 }
 
-/// Email-specific rate limiter to prevent abuse
+/// Email-specific rate limiter to prevent abuse. Process-local and global;
+/// the per-user budget that holds across restarts and replicas is
+/// [`crate::email_quota`].
 pub struct EmailRateLimiter {
     limiter: Arc<GovernorRateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
 }
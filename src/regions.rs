@@ -0,0 +1,373 @@
+//! Active-active deployments across regions that do not share a database
+//! synchronously.
+//!
+//! In `eventual` mode refresh tokens are signed *session assertions* that any
+//! region can validate on its own: the session id, user, original sign-in
+//! time and rotation generation are carried in the token instead of being
+//! looked up in `refresh_tokens`. Revocations are appended to a local
+//! `revocation_log` and pulled by peer regions in the background.
+//!
+//! Conflicts resolve in favour of revocation:
+//!
+//! * a revoked session stays revoked, even when another region rotated it
+//!   before hearing about the revocation;
+//! * "revoke all sessions" at time `T` kills every session that signed in at
+//!   or before `T + clock_skew_seconds`, whichever region issued it;
+//! * an assertion older than the newest generation this region has seen for
+//!   its session is treated as a stolen token and revokes the session.
+
+use crate::{
+    crypto::{constant_time_eq, hmac_sha256},
+    db::Database,
+    leader::LeaderElection,
+    revocation::{self, RevocationBus, RevocationEvent},
+};
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Client;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+
+/// How sessions are validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionMode {
+    /// Refresh tokens are rows in the local database (single region)
+    #[default]
+    Strict,
+    /// Refresh tokens are signed session assertions, revocations sync asynchronously
+    Eventual,
+}
+
+/// `[sessions]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct SessionsConfig {
+    #[serde(default)]
+    pub mode: SessionMode,
+    /// Name of this region, recorded as the origin of its revocations
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Signs session assertions; must be identical in every region.
+    /// Unset = derived from `jwt_secret`
+    #[serde(default)]
+    pub assertion_secret: Option<String>,
+    /// Base URLs of the other regions, polled for revocations
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Bearer token peers present to `/internal/revocations`; unset = endpoint disabled
+    #[serde(default)]
+    pub sync_token: Option<String>,
+    #[serde(default = "default_sync_interval_seconds")]
+    pub sync_interval_seconds: u64,
+    /// Tolerated clock difference between regions
+    #[serde(default = "default_clock_skew_seconds")]
+    pub clock_skew_seconds: i64,
+    /// How long revocations and rotation generations are kept. Must outlast
+    /// the longest refresh token lifetime and any peer outage: a peer that
+    /// pulls after an entry was purged never hears of it.
+    #[serde(default = "default_log_retention_seconds")]
+    pub log_retention_seconds: i64,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            mode: SessionMode::Strict,
+            region: default_region(),
+            assertion_secret: None,
+            peers: Vec::new(),
+            sync_token: None,
+            sync_interval_seconds: default_sync_interval_seconds(),
+            clock_skew_seconds: default_clock_skew_seconds(),
+            log_retention_seconds: default_log_retention_seconds(),
+        }
+    }
+}
+
+fn default_region() -> String {
+    "default".to_string()
+}
+
+fn default_sync_interval_seconds() -> u64 {
+    15
+}
+
+fn default_clock_skew_seconds() -> i64 {
+    5
+}
+
+fn default_log_retention_seconds() -> i64 {
+    30 * 86400
+}
+
+impl SessionsConfig {
+    pub fn is_eventual(&self) -> bool {
+        self.mode == SessionMode::Eventual
+    }
+
+    /// Key for session assertions
+    pub fn assertion_key(&self, jwt_secret: &str) -> String {
+        match &self.assertion_secret {
+            Some(secret) => secret.clone(),
+            None => data_encoding::HEXLOWER.encode(&hmac_sha256(jwt_secret.as_bytes(), b"session-assertion")),
+        }
+    }
+}
+
+const ASSERTION_PREFIX: &str = "sa1.";
+
+/// Self-contained refresh credential for `eventual` mode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionAssertion {
+    /// Session id, stable across rotations
+    pub sid: String,
+    /// Internal user id
+    pub uid: String,
+    /// When the user signed in; compared against "revoke all" times
+    pub auth_time: i64,
+    /// Rotation counter, starting at 0
+    pub generation: u32,
+    /// Region that issued this assertion
+    pub region: String,
+    pub exp: i64,
+    /// Application the session was signed in to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+}
+
+impl SessionAssertion {
+    /// The assertion handed out on the next rotation
+    pub fn rotated(&self, region: &str, ttl_seconds: i64) -> Self {
+        Self {
+            generation: self.generation + 1,
+            region: region.to_string(),
+            exp: Database::now_ts() + ttl_seconds,
+            ..self.clone()
+        }
+    }
+}
+
+pub fn is_assertion(token: &str) -> bool {
+    token.starts_with(ASSERTION_PREFIX)
+}
+
+pub fn sign(key: &str, assertion: &SessionAssertion) -> String {
+    let body = BASE64URL_NOPAD.encode(serde_json::to_string(assertion).unwrap_or_default().as_bytes());
+    let mac = hmac_sha256(key.as_bytes(), format!("session|{}", body).as_bytes());
+    format!("{}{}.{}", ASSERTION_PREFIX, body, BASE64URL_NOPAD.encode(&mac))
+}
+
+/// Signature and expiry check; revocation is checked by [`is_revoked`]
+pub fn verify(key: &str, token: &str) -> Option<SessionAssertion> {
+    let (body, sig) = token.strip_prefix(ASSERTION_PREFIX)?.split_once('.')?;
+    let mac = hmac_sha256(key.as_bytes(), format!("session|{}", body).as_bytes());
+    if !constant_time_eq(BASE64URL_NOPAD.encode(&mac).as_bytes(), sig.as_bytes()) {
+        return None;
+    }
+    let assertion: SessionAssertion = serde_json::from_slice(&BASE64URL_NOPAD.decode(body.as_bytes()).ok()?).ok()?;
+    (assertion.exp > Database::now_ts()).then_some(assertion)
+}
+
+/// One entry of the revocation log, as served to peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggedRevocation {
+    pub seq: i64,
+    pub origin: String,
+    /// When the revocation happened in its origin region
+    pub at: i64,
+    pub event: RevocationEvent,
+}
+
+fn subject(event: &RevocationEvent) -> (&'static str, &str, Option<i64>) {
+    match event {
+        RevocationEvent::SessionRevoked { session_id, .. } => ("session", session_id, None),
+        RevocationEvent::UserSessionsRevoked { user_id, at } | RevocationEvent::UserDisabled { user_id, at } => {
+            ("user", user_id, Some(*at))
+        }
+        RevocationEvent::AccessTokenRevoked { jti, .. } => ("token", jti, None),
+    }
+}
+
+/// Append a revocation to the log; returns false when it was already known
+pub fn record(db: &Database, event: &RevocationEvent, origin: &str, at: i64) -> Result<bool, rusqlite::Error> {
+    let (kind, subject, event_at) = subject(event);
+    let json = serde_json::to_string(event).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let inserted = db.conn().execute(
+        "INSERT OR IGNORE INTO revocation_log (kind, subject, at, origin, event) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![kind, subject, event_at.unwrap_or(at), origin, json],
+    )?;
+    Ok(inserted > 0)
+}
+
+/// Log entries after `seq`, oldest first
+pub fn since(db: &Database, seq: i64, limit: i64) -> Result<Vec<LoggedRevocation>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn
+        .prepare("SELECT seq, origin, at, event FROM revocation_log WHERE seq > ?1 ORDER BY seq LIMIT ?2")?;
+    let rows = stmt.query_map(params![seq, limit], |r| {
+        Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, i64>(2)?, r.get::<_, String>(3)?))
+    })?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let (seq, origin, at, event) = row?;
+        match serde_json::from_str(&event) {
+            Ok(event) => entries.push(LoggedRevocation { seq, origin, at, event }),
+            Err(e) => warn!("Skipping unreadable revocation log entry {}: {}", seq, e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether the assertion's session was revoked in any region this one has heard from
+pub fn is_revoked(db: &Database, assertion: &SessionAssertion, clock_skew_seconds: i64) -> Result<bool, rusqlite::Error> {
+    let revoked: i64 = db.conn().query_row(
+        "SELECT EXISTS(SELECT 1 FROM revocation_log WHERE kind = 'session' AND subject = ?1)
+             OR EXISTS(SELECT 1 FROM revocation_log WHERE kind = 'user' AND subject = ?2 AND at + ?3 >= ?4)",
+        params![assertion.sid, assertion.uid, clock_skew_seconds, assertion.auth_time],
+        |r| r.get(0),
+    )?;
+    Ok(revoked != 0)
+}
+
+/// Whether this region already rotated the assertion's session past it
+pub fn is_superseded(db: &Database, assertion: &SessionAssertion) -> Result<bool, rusqlite::Error> {
+    let seen: Option<u32> = db
+        .conn()
+        .query_row(
+            "SELECT generation FROM session_generations WHERE session_id = ?1",
+            params![assertion.sid],
+            |r| r.get(0),
+        )
+        .optional()?;
+    Ok(seen.is_some_and(|seen| assertion.generation < seen))
+}
+
+/// Record that the assertion is being rotated. Returns false when it is
+/// older than one this region already rotated (the token was reused). The
+/// check and the update are one statement, so of two concurrent rotations
+/// of the same assertion only one succeeds.
+pub fn observe_generation(db: &Database, assertion: &SessionAssertion) -> Result<bool, rusqlite::Error> {
+    let changed = db.conn().execute(
+        "INSERT INTO session_generations (session_id, generation, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(session_id) DO UPDATE SET generation = excluded.generation, updated_at = excluded.updated_at
+         WHERE session_generations.generation < excluded.generation",
+        params![assertion.sid, assertion.generation + 1, Database::now_ts()],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Drop revocations and rotation generations recorded before `cutoff`.
+/// Returns how many rows were removed.
+pub fn purge_before(db: &Database, cutoff: i64) -> Result<usize, rusqlite::Error> {
+    let revocations = db.conn().execute("DELETE FROM revocation_log WHERE at < ?1", params![cutoff])?;
+    let generations = db
+        .conn()
+        .execute("DELETE FROM session_generations WHERE updated_at < ?1", params![cutoff])?;
+    Ok(revocations + generations)
+}
+
+/// Apply `log_retention_seconds` every `every` in the background, on the
+/// elected replica only
+pub fn spawn_retention(db: Arc<Database>, leader: Arc<LeaderElection>, cfg: SessionsConfig, every: Duration) {
+    if !cfg.is_eventual() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("revocation_log_retention", every) {
+                continue;
+            }
+            match purge_before(&db, Database::now_ts() - cfg.log_retention_seconds) {
+                Ok(0) => {}
+                Ok(n) => info!("Revocation log retention removed {} entries", n),
+                Err(e) => warn!("Revocation log retention failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Entries returned per `/internal/revocations` request
+pub const SYNC_PAGE: i64 = 500;
+
+/// Poll every peer for revocations and apply them locally (log and cache).
+/// Cursors live in memory; after a restart the whole log is pulled again,
+/// which is harmless because recording is idempotent.
+pub fn spawn_sync(db: Arc<Database>, bus: Arc<RevocationBus>, cfg: SessionsConfig) {
+    if !cfg.is_eventual() || cfg.peers.is_empty() {
+        return;
+    }
+    let Some(token) = cfg.sync_token.clone() else {
+        warn!("[sessions] peers are configured without a sync_token; revocations will not sync");
+        return;
+    };
+    let client = Client::builder().timeout(Duration::from_secs(10)).build().unwrap();
+    tokio::spawn(async move {
+        let mut cursors = vec![0i64; cfg.peers.len()];
+        let mut ticker = tokio::time::interval(Duration::from_secs(cfg.sync_interval_seconds));
+        loop {
+            ticker.tick().await;
+            for (peer, cursor) in cfg.peers.iter().zip(cursors.iter_mut()) {
+                match pull(&client, peer, &token, *cursor).await {
+                    Ok(entries) => {
+                        for entry in &entries {
+                            *cursor = (*cursor).max(entry.seq);
+                            if entry.origin == cfg.region {
+                                continue;
+                            }
+                            match record(&db, &entry.event, &entry.origin, entry.at) {
+                                Ok(true) => {
+                                    debug!("Revocation from {} applied: {:?}", entry.origin, entry.event);
+                                    if let RevocationEvent::AccessTokenRevoked { jti, exp } = &entry.event {
+                                        if let Err(e) = revocation::deny_access_token(&db, jti, *exp) {
+                                            warn!("Failed to persist revoked access token {}: {}", jti, e);
+                                        }
+                                    }
+                                    bus.cache().apply(&entry.event);
+                                }
+                                Ok(false) => {}
+                                Err(e) => warn!("Failed to record revocation from {}: {}", peer, e),
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Revocation sync with {} failed: {}", peer, e),
+                }
+            }
+        }
+    });
+}
+
+async fn pull(client: &Client, peer: &str, token: &str, cursor: i64) -> Result<Vec<LoggedRevocation>, String> {
+    let url = format!("{}/internal/revocations?since={}", peer.trim_end_matches('/'), cursor);
+    let response = client.get(&url).bearer_auth(token).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("peer returned {}", response.status()));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assertions_are_signed_and_expire() {
+        let assertion = SessionAssertion {
+            sid: "s1".into(),
+            uid: "u1".into(),
+            auth_time: Database::now_ts(),
+            generation: 0,
+            region: "eu".into(),
+            exp: Database::now_ts() + 60,
+            cid: Some("web".into()),
+        };
+        let token = sign("k", &assertion);
+        assert!(is_assertion(&token));
+        assert_eq!(verify("k", &token), Some(assertion.clone()));
+        assert_eq!(verify("other", &token), None);
+        let expired = SessionAssertion { exp: Database::now_ts() - 1, ..assertion };
+        assert_eq!(verify("k", &sign("k", &expired)), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Typed queries for handler and admin code.
+//!
+//! Handlers (`routes`, `admin`, `chaos`) and the auth service (`service`)
+//! never touch [`Database::conn`]; they go through these repositories, which
+//! return `models` structs, or through the owning module (`Session`, `dlq`,
+//! `notifications`, ...). Their SQL is in [`queries`](crate::storage::queries),
+//! checked against the migrations at build time.
+
+use crate::{
+    crypto,
+    db::Database,
+    models::{ChallengeKind, PendingChallenge, RefreshToken, User, UserSession},
+    storage::queries as sql,
+    user_agent::UserAgent,
+};
+use rusqlite::{params, OptionalExtension, Row};
+
+impl Database {
+    pub fn users(&self) -> Users<'_> {
+        Users { db: self }
+    }
+
+    pub fn sessions(&self) -> Sessions<'_> {
+        Sessions { db: self }
+    }
+
+    pub fn credentials(&self) -> Credentials<'_> {
+        Credentials { db: self }
+    }
+
+    pub fn challenges(&self) -> Challenges<'_> {
+        Challenges { db: self }
+    }
+}
+
+fn user_from_row(r: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: r.get(0)?,
+        email: r.get(1)?,
+        totp_secret: r.get(2)?,
+        created_at: r.get(3)?,
+    })
+}
+
+pub struct Users<'a> {
+    db: &'a Database,
+}
+
+impl Users<'_> {
+    pub fn find_by_id(&self, id: &str) -> Result<Option<User>, rusqlite::Error> {
+        self.db
+            .conn()
+            .query_row(sql::USER_BY_ID, params![id], user_from_row)
+            .optional()
+    }
+
+    pub fn find_by_email(&self, email: &str) -> Result<Option<User>, rusqlite::Error> {
+        self.db
+            .conn()
+            .query_row(sql::USER_BY_EMAIL, params![email], user_from_row)
+            .optional()
+    }
+
+    /// Newest users first
+    pub fn list(&self, limit: i64, offset: i64) -> Result<Vec<User>, rusqlite::Error> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare(sql::USERS_PAGE)?;
+        let users = stmt.query_map(params![limit, offset], user_from_row)?;
+        users.collect()
+    }
+
+    pub fn count(&self) -> Result<i64, rusqlite::Error> {
+        self.db.conn().query_row(sql::USER_COUNT, [], |r| r.get(0))
+    }
+
+    /// Changes whenever [`Users::list`] would return something different
+    /// (`updated_at` is maintained by triggers)
+    pub fn list_version(&self) -> Result<String, rusqlite::Error> {
+        self.db.conn().query_row(sql::USERS_VERSION, [], |r| {
+            Ok(format!("{}:{}", r.get::<_, i64>(0)?, r.get::<_, Option<i64>>(1)?.unwrap_or_default()))
+        })
+    }
+
+    /// Change a user's role; false when there is no such user
+    pub fn set_role(&self, id: &str, role: &str) -> Result<bool, rusqlite::Error> {
+        let updated = self.db.conn().execute(sql::SET_USER_ROLE, params![role, id])?;
+        Ok(updated > 0)
+    }
+
+    /// Delete a user and everything stored about them (see
+    /// [`crate::db::delete_user`]); false when there is no such user
+    pub fn delete(&self, id: &str) -> Result<bool, rusqlite::Error> {
+        let conn = self.db.conn();
+        let tx = conn.unchecked_transaction()?;
+        let exists: bool = tx.query_row(sql::USER_EXISTS, params![id], |r| r.get(0))?;
+        if exists {
+            crate::db::delete_user(&tx, id)?;
+        }
+        tx.commit()?;
+        Ok(exists)
+    }
+
+    /// Lift an account freeze; false when the user is not frozen
+    pub fn unfreeze(&self, id: &str) -> Result<bool, rusqlite::Error> {
+        let updated = self.db.conn().execute(sql::UNFREEZE_USER, params![id])?;
+        Ok(updated > 0)
+    }
+
+    /// Freeze the account and revoke its SQLite refresh tokens
+    pub fn freeze(&self, id: &str, now: i64) -> Result<(), rusqlite::Error> {
+        let conn = self.db.conn();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(sql::FREEZE_USER, params![now, id])?;
+        tx.execute(sql::REVOKE_USER_REFRESH_TOKENS, params![id])?;
+        tx.commit()
+    }
+
+    /// When the account was frozen; `None` for an active account or no such user
+    pub fn frozen_at(&self, id: &str) -> Result<Option<i64>, rusqlite::Error> {
+        let frozen = self.db.conn().query_row(sql::USER_FROZEN_AT, params![id], |r| r.get(0)).optional()?;
+        Ok(frozen.flatten())
+    }
+
+    pub fn role(&self, id: &str) -> Result<Option<String>, rusqlite::Error> {
+        self.db.conn().query_row(sql::USER_ROLE, params![id], |r| r.get(0)).optional()
+    }
+
+    pub fn mark_email_verified(&self, id: &str, now: i64) -> Result<(), rusqlite::Error> {
+        self.db.conn().execute(sql::VERIFY_USER_EMAIL, params![now, id])?;
+        Ok(())
+    }
+
+    /// Set a new, verified address; fails with a constraint violation when
+    /// another user has it
+    pub fn change_email(&self, id: &str, email: &str, now: i64) -> Result<(), rusqlite::Error> {
+        self.db.conn().execute(sql::CHANGE_USER_EMAIL, params![email, now, id])?;
+        Ok(())
+    }
+
+    /// Store the first TOTP secret; false when the user already has one
+    pub fn enroll_totp(&self, id: &str, secret: &str) -> Result<bool, rusqlite::Error> {
+        let updated = self.db.conn().execute(sql::ENROLL_TOTP, params![secret, id])?;
+        Ok(updated > 0)
+    }
+
+    /// Replace the TOTP secret (`None` turns TOTP off) if it is still
+    /// `current`, and forget the failed attempts against it; false when it
+    /// changed meanwhile. Run it inside [`Database::transaction`] so both
+    /// happen together.
+    pub fn replace_totp(&self, id: &str, current: &str, secret: Option<&str>) -> Result<bool, rusqlite::Error> {
+        let conn = self.db.conn();
+        if conn.execute(sql::REPLACE_TOTP, params![secret, id, current])? == 0 {
+            return Ok(false);
+        }
+        conn.execute(sql::CLEAR_TOTP_ATTEMPTS, params![id])?;
+        Ok(true)
+    }
+
+    /// Devices the user has signed in from
+    pub fn device_count(&self, id: &str) -> Result<i64, rusqlite::Error> {
+        self.db.conn().query_row(sql::DEVICE_COUNT_OF_USER, params![id], |r| r.get(0))
+    }
+}
+
+fn refresh_from_row(r: &Row) -> rusqlite::Result<RefreshToken> {
+    Ok(RefreshToken {
+        token: r.get(0)?,
+        user_id: r.get(1)?,
+        session_id: r.get(2)?,
+        expires_at: r.get(3)?,
+        revoked: r.get(4)?,
+        created_at: r.get(5)?,
+    })
+}
+
+pub struct Sessions<'a> {
+    db: &'a Database,
+}
+
+impl Sessions<'_> {
+    /// Every refresh token the user holds or held, newest first, with its device
+    pub fn list_for_user(&self, user_id: &str) -> Result<Vec<UserSession>, rusqlite::Error> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare(sql::SESSIONS_OF_USER)?;
+        let sessions = stmt.query_map(params![user_id], |r| {
+            Ok(UserSession {
+                refresh: refresh_from_row(r)?,
+                device: r
+                    .get::<_, Option<String>>(6)?
+                    .and_then(|hints| serde_json::from_str(&hints).ok()),
+                agent: UserAgent::from_row(r, 7)?,
+            })
+        })?;
+        sessions.collect()
+    }
+
+    /// Changes whenever [`Sessions::list_for_user`] would return something
+    /// different: sessions are only ever added, revoked or deleted
+    pub fn list_version(&self, user_id: &str) -> Result<String, rusqlite::Error> {
+        self.db.conn().query_row(sql::SESSIONS_VERSION, params![user_id], |r| {
+            Ok(format!("{}:{}:{}", r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?))
+        })
+    }
+
+    pub fn find_by_token(&self, token: &str) -> Result<Option<RefreshToken>, rusqlite::Error> {
+        self.find_by_digest(&crypto::token_digest(token))
+    }
+
+    /// The refresh token stored under `digest` ([`RefreshToken::token`])
+    pub fn find_by_digest(&self, digest: &str) -> Result<Option<RefreshToken>, rusqlite::Error> {
+        self.db
+            .conn()
+            .query_row(sql::REFRESH_TOKEN_BY_DIGEST, params![digest], refresh_from_row)
+            .optional()
+    }
+
+    /// Revoke every refresh token of the user; returns how many were live
+    pub fn revoke_all_for_user(&self, user_id: &str) -> Result<usize, rusqlite::Error> {
+        self.db.conn().execute(sql::REVOKE_USER_REFRESH_TOKENS, params![user_id])
+    }
+
+    pub fn count(&self) -> Result<i64, rusqlite::Error> {
+        self.db.conn().query_row(sql::REFRESH_TOKEN_COUNT, [], |r| r.get(0))
+    }
+
+    /// Refresh tokens that are neither revoked nor expired
+    pub fn count_active(&self) -> Result<i64, rusqlite::Error> {
+        self.db.conn().query_row(sql::ACTIVE_REFRESH_TOKEN_COUNT, params![Database::now_ts()], |r| r.get(0))
+    }
+}
+
+pub struct Credentials<'a> {
+    db: &'a Database,
+}
+
+impl Credentials<'_> {
+    /// Registered WebAuthn credentials (passkeys) of the user
+    pub fn count_for_user(&self, user_id: &str) -> Result<i64, rusqlite::Error> {
+        self.db.conn().query_row(sql::PASSKEY_COUNT_OF_USER, params![user_id], |r| r.get(0))
+    }
+}
+
+/// Prefix of magic link ids in [`PendingChallenge::id`]
+const MAGIC_LINK_ID_PREFIX: &str = "ml_";
+
+fn challenge_from_row(r: &Row) -> rusqlite::Result<PendingChallenge> {
+    let kind = match r.get::<_, String>(1)?.as_str() {
+        "magic_link" => ChallengeKind::MagicLink,
+        "register" => ChallengeKind::WebauthnRegister,
+        _ => ChallengeKind::WebauthnLogin,
+    };
+    Ok(PendingChallenge {
+        id: r.get(0)?,
+        kind,
+        user_id: r.get(2)?,
+        email: r.get(3)?,
+        created_at: r.get(4)?,
+        expires_at: r.get(5)?,
+    })
+}
+
+/// Outstanding magic links and WebAuthn ceremonies
+pub struct Challenges<'a> {
+    db: &'a Database,
+}
+
+impl Challenges<'_> {
+    /// Unused, unexpired challenges (of one user, or everyone), latest expiry first
+    pub fn list_pending(&self, user_id: Option<&str>, limit: i64) -> Result<Vec<PendingChallenge>, rusqlite::Error> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare(sql::PENDING_CHALLENGES)?;
+        let challenges = stmt.query_map(params![Database::now_ts(), user_id, limit], challenge_from_row)?;
+        challenges.collect()
+    }
+
+    pub fn find_pending(&self, id: &str) -> Result<Option<PendingChallenge>, rusqlite::Error> {
+        let now = Database::now_ts();
+        match id.strip_prefix(MAGIC_LINK_ID_PREFIX).and_then(|n| n.parse::<i64>().ok()) {
+            Some(rowid) => self.db.conn().query_row(sql::PENDING_MAGIC_LINK, params![rowid, now], challenge_from_row),
+            None => self.db.conn().query_row(sql::PENDING_CEREMONY, params![id, now], challenge_from_row),
+        }
+        .optional()
+    }
+
+    /// Invalidate one pending challenge; `None` when it was not pending.
+    /// Magic links are expired (verifying reports "invalid or expired"),
+    /// WebAuthn ceremonies are deleted.
+    pub fn invalidate(&self, id: &str) -> Result<Option<PendingChallenge>, rusqlite::Error> {
+        let Some(challenge) = self.find_pending(id)? else {
+            return Ok(None);
+        };
+        match id.strip_prefix(MAGIC_LINK_ID_PREFIX).and_then(|n| n.parse::<i64>().ok()) {
+            Some(rowid) => self.db.conn().execute(sql::EXPIRE_MAGIC_LINK, params![Database::now_ts() - 1, rowid])?,
+            None => self.db.conn().execute(sql::DELETE_CEREMONY, params![id])?,
+        };
+        Ok(Some(challenge))
+    }
+
+    /// Invalidate every pending challenge of the user; returns how many there were
+    pub fn invalidate_for_user(&self, user_id: &str) -> Result<usize, rusqlite::Error> {
+        let now = Database::now_ts();
+        let conn = self.db.conn();
+        let tx = conn.unchecked_transaction()?;
+        let links = tx.execute(sql::EXPIRE_USER_MAGIC_LINKS, params![now - 1, user_id, now])?;
+        let ceremonies = tx.execute(sql::DELETE_USER_CEREMONIES, params![user_id, now])?;
+        tx.commit()?;
+        Ok(links + ceremonies)
+    }
+}
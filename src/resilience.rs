@@ -0,0 +1,303 @@
+use metrics::{counter, gauge};
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Circuit breaker and retry settings for one outbound dependency
+/// (`[resilience.smtp]`, `[resilience.webhook]`, ...)
+#[derive(Debug, Deserialize, Clone)]
+pub struct DependencyPolicy {
+    /// Consecutive failures that open the circuit
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a single half-open probe
+    #[serde(default = "default_cooldown_seconds")]
+    pub cooldown_seconds: u64,
+    /// Extra attempts after the first failure (0 disables retries)
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// First retry delay; doubles per attempt
+    #[serde(default = "default_retry_base_ms")]
+    pub retry_base_ms: u64,
+}
+
+impl Default for DependencyPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            cooldown_seconds: default_cooldown_seconds(),
+            max_retries: default_max_retries(),
+            retry_base_ms: default_retry_base_ms(),
+        }
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_cooldown_seconds() -> u64 {
+    30
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+fn default_retry_base_ms() -> u64 {
+    200
+}
+
+impl DependencyPolicy {
+    /// Delay before retry number `attempt` (1-based), capped at 10 seconds
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ms = self.retry_base_ms.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        Duration::from_millis(ms.min(10_000))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    /// Cooldown elapsed; one probe call is let through
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn gauge_value(&self) -> f64 {
+        match self {
+            Self::Closed => 0.0,
+            Self::HalfOpen => 1.0,
+            Self::Open => 2.0,
+        }
+    }
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+}
+
+/// Error from a call guarded by a [`CircuitBreaker`]
+#[derive(Debug)]
+pub enum CallError<E> {
+    /// The circuit is open; the dependency was not called
+    Open,
+    /// The dependency was called and failed (after any retries)
+    Failed(E),
+}
+
+/// Per-dependency circuit breaker with half-open probing.
+///
+/// Exposes `circuit_breaker_state{dependency}` (0 closed, 1 half-open,
+/// 2 open) and `dependency_calls_total{dependency, outcome}`.
+pub struct CircuitBreaker {
+    name: &'static str,
+    policy: DependencyPolicy,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, policy: DependencyPolicy) -> Self {
+        gauge!("circuit_breaker_state", "dependency" => name).set(0.0);
+        Self {
+            name,
+            policy,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_in_flight: false,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn policy(&self) -> &DependencyPolicy {
+        &self.policy
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    fn transition(&self, inner: &mut BreakerInner, state: BreakerState) {
+        if inner.state != state {
+            match state {
+                BreakerState::Open => warn!("Circuit for {} opened", self.name),
+                BreakerState::HalfOpen => info!("Circuit for {} half-open, probing", self.name),
+                BreakerState::Closed => info!("Circuit for {} closed", self.name),
+            }
+            inner.state = state;
+            gauge!("circuit_breaker_state", "dependency" => self.name).set(state.gauge_value());
+        }
+    }
+
+    /// Ask permission to call the dependency
+    pub fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let allowed = match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                let cooled = inner
+                    .opened_at
+                    .is_none_or(|t| t.elapsed() >= Duration::from_secs(self.policy.cooldown_seconds));
+                if cooled {
+                    self.transition(&mut inner, BreakerState::HalfOpen);
+                    inner.probe_in_flight = true;
+                }
+                cooled
+            }
+            BreakerState::HalfOpen => {
+                if inner.probe_in_flight {
+                    false
+                } else {
+                    inner.probe_in_flight = true;
+                    true
+                }
+            }
+        };
+        if !allowed {
+            counter!("dependency_calls_total", "dependency" => self.name, "outcome" => "rejected").increment(1);
+        }
+        allowed
+    }
+
+    pub fn on_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = 0;
+        inner.probe_in_flight = false;
+        self.transition(&mut inner, BreakerState::Closed);
+        counter!("dependency_calls_total", "dependency" => self.name, "outcome" => "success").increment(1);
+    }
+
+    pub fn on_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        inner.probe_in_flight = false;
+        if inner.state == BreakerState::HalfOpen
+            || inner.consecutive_failures >= self.policy.failure_threshold.max(1)
+        {
+            inner.opened_at = Some(Instant::now());
+            inner.consecutive_failures = 0;
+            self.transition(&mut inner, BreakerState::Open);
+        }
+        counter!("dependency_calls_total", "dependency" => self.name, "outcome" => "failure").increment(1);
+    }
+
+    /// Run a blocking call through the breaker with bounded retries
+    pub fn call<T, E>(&self, mut f: impl FnMut() -> Result<T, E>) -> Result<T, CallError<E>> {
+        let mut attempt = 0;
+        loop {
+            if !self.try_acquire() {
+                return Err(CallError::Open);
+            }
+            match f() {
+                Ok(v) => {
+                    self.on_success();
+                    return Ok(v);
+                }
+                Err(e) => {
+                    self.on_failure();
+                    attempt += 1;
+                    if attempt > self.policy.max_retries {
+                        return Err(CallError::Failed(e));
+                    }
+                    std::thread::sleep(self.policy.backoff(attempt));
+                }
+            }
+        }
+    }
+
+    /// Async variant of [`CircuitBreaker::call`]
+    pub async fn call_async<T, E, F, Fut>(&self, mut f: F) -> Result<T, CallError<E>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            if !self.try_acquire() {
+                return Err(CallError::Open);
+            }
+            match f().await {
+                Ok(v) => {
+                    self.on_success();
+                    return Ok(v);
+                }
+                Err(e) => {
+                    self.on_failure();
+                    attempt += 1;
+                    if attempt > self.policy.max_retries {
+                        return Err(CallError::Failed(e));
+                    }
+                    tokio::time::sleep(self.policy.backoff(attempt)).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(cooldown_seconds: u64) -> DependencyPolicy {
+        DependencyPolicy {
+            failure_threshold: 2,
+            cooldown_seconds,
+            max_retries: 0,
+            retry_base_ms: 1,
+        }
+    }
+
+    #[test]
+    fn opens_after_threshold_and_rejects() {
+        let breaker = CircuitBreaker::new("test", policy(60));
+        assert!(matches!(breaker.call(|| Err::<(), _>("down")), Err(CallError::Failed(_))));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(matches!(breaker.call(|| Err::<(), _>("down")), Err(CallError::Failed(_))));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(matches!(breaker.call(|| Ok::<_, ()>(1)), Err(CallError::Open)));
+    }
+
+    #[test]
+    fn half_open_probe_closes_on_success() {
+        let breaker = CircuitBreaker::new("test", policy(0));
+        let _ = breaker.call(|| Err::<(), _>("down"));
+        let _ = breaker.call(|| Err::<(), _>("down"));
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        // cooldown elapsed: exactly one probe is admitted
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(!breaker.try_acquire());
+        breaker.on_success();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn retries_are_bounded() {
+        let breaker = CircuitBreaker::new("test", DependencyPolicy {
+            failure_threshold: 10,
+            max_retries: 2,
+            retry_base_ms: 1,
+            ..Default::default()
+        });
+        let mut calls = 0;
+        let result = breaker.call(|| {
+            calls += 1;
+            Err::<(), _>("down")
+        });
+        assert!(matches!(result, Err(CallError::Failed("down"))));
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{db::Database, jwt::Claims, regions::{self, SessionsConfig}};
+use futures_util::StreamExt;
+use redis::{AsyncCommands, RedisResult};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// `[revocation]` configuration: cross-instance propagation of revocations
+#[derive(Debug, Deserialize, Clone)]
+pub struct RevocationConfig {
+    /// `redis://[:password@]host:port`; unset = single instance, local cache only
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    #[serde(default = "default_channel")]
+    pub channel: String,
+    /// How long cache entries are kept; at least the longest access token lifetime
+    #[serde(default = "default_retain_seconds")]
+    pub retain_seconds: i64,
+}
+
+impl Default for RevocationConfig {
+    fn default() -> Self {
+        Self {
+            redis_url: None,
+            channel: default_channel(),
+            retain_seconds: default_retain_seconds(),
+        }
+    }
+}
+
+fn default_channel() -> String {
+    "passwordless-auth:revocations".to_string()
+}
+
+fn default_retain_seconds() -> i64 {
+    86400
+}
+
+/// Broadcast to every instance when credentials stop being valid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RevocationEvent {
+    /// One session was revoked
+    SessionRevoked { session_id: String, user_id: String },
+    /// Every session of the user was revoked; tokens issued before `at` are invalid
+    UserSessionsRevoked { user_id: String, at: i64 },
+    /// The user may no longer sign in; tokens issued before `at` are invalid
+    UserDisabled { user_id: String, at: i64 },
+    /// One access token was revoked (`POST /oauth/revoke`); rejected until `exp`
+    AccessTokenRevoked { jti: String, exp: i64 },
+}
+
+/// Per-node view of recent revocations, consulted when authenticating access tokens
+#[derive(Default)]
+pub struct RevocationCache {
+    /// user id -> tokens issued before this time are rejected
+    revoked_before: RwLock<HashMap<String, i64>>,
+    /// session id -> when it was revoked
+    revoked_sessions: RwLock<HashMap<String, i64>>,
+    /// access token `jti` -> when the token expires
+    revoked_tokens: RwLock<HashMap<String, i64>>,
+    retain_seconds: i64,
+}
+
+impl RevocationCache {
+    pub fn new(retain_seconds: i64) -> Self {
+        Self {
+            retain_seconds,
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(&self, event: &RevocationEvent) {
+        let now = Database::now_ts();
+        match event {
+            RevocationEvent::SessionRevoked { session_id, .. } => {
+                let mut sessions = self.revoked_sessions.write().unwrap();
+                sessions.retain(|_, at| now - *at < self.retain_seconds);
+                sessions.insert(session_id.clone(), now);
+            }
+            RevocationEvent::UserSessionsRevoked { user_id, at } | RevocationEvent::UserDisabled { user_id, at } => {
+                let mut users = self.revoked_before.write().unwrap();
+                users.retain(|_, t| now - *t < self.retain_seconds);
+                let entry = users.entry(user_id.clone()).or_insert(*at);
+                *entry = (*entry).max(*at);
+            }
+            RevocationEvent::AccessTokenRevoked { jti, exp } => {
+                let mut tokens = self.revoked_tokens.write().unwrap();
+                tokens.retain(|_, exp| *exp >= now);
+                tokens.insert(jti.clone(), *exp);
+            }
+        }
+    }
+
+    /// Whether a token for `user_id` issued at `issued_at` has been revoked.
+    /// Strictly before: a sign-in in the same second as "revoke all" (the
+    /// user signing back in right away) must not be rejected.
+    pub fn is_user_token_revoked(&self, user_id: &str, issued_at: i64) -> bool {
+        self.revoked_before
+            .read()
+            .unwrap()
+            .get(user_id)
+            .is_some_and(|before| issued_at < *before)
+    }
+
+    pub fn is_session_revoked(&self, session_id: &str) -> bool {
+        self.revoked_sessions.read().unwrap().contains_key(session_id)
+    }
+
+    /// Whether an access token of `user_id` was revoked: with all of the
+    /// user's sessions, with its own session, or by itself
+    pub fn is_access_token_revoked(&self, user_id: &str, claims: &Claims) -> bool {
+        self.is_user_token_revoked(user_id, claims.iat as i64)
+            || claims.sid.as_deref().is_some_and(|sid| self.is_session_revoked(sid))
+            || claims.jti.as_deref().is_some_and(|jti| self.revoked_tokens.read().unwrap().contains_key(jti))
+    }
+}
+
+/// Store a revoked access token until it expires; expired entries are
+/// dropped on the way
+pub fn deny_access_token(db: &Database, jti: &str, exp: i64) -> Result<(), rusqlite::Error> {
+    let now = Database::now_ts();
+    db.conn().execute("DELETE FROM revoked_access_tokens WHERE expires_at < ?1", params![now])?;
+    db.conn().execute(
+        "INSERT OR IGNORE INTO revoked_access_tokens (jti, expires_at, revoked_at) VALUES (?1, ?2, ?3)",
+        params![jti, exp, now],
+    )?;
+    Ok(())
+}
+
+/// Load the stored revoked access tokens into `cache`; returns how many
+pub fn load_denied_access_tokens(db: &Database, cache: &RevocationCache) -> Result<usize, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare("SELECT jti, expires_at FROM revoked_access_tokens WHERE expires_at >= ?1")?;
+    let denied = stmt
+        .query_map(params![Database::now_ts()], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (jti, exp) in &denied {
+        cache.apply(&RevocationEvent::AccessTokenRevoked { jti: jti.clone(), exp: *exp });
+    }
+    Ok(denied.len())
+}
+
+/// Applies revocations locally and fans them out to other instances
+pub struct RevocationBus {
+    cache: Arc<RevocationCache>,
+    redis: Option<RedisChannel>,
+    /// Revocation log read by peer regions (`eventual` session mode only)
+    log: Option<(Arc<Database>, String)>,
+}
+
+impl RevocationBus {
+    pub fn new(cfg: &RevocationConfig) -> Self {
+        let redis = cfg.redis_url.as_deref().and_then(|url| match RedisChannel::parse(url, &cfg.channel) {
+            Some(channel) => Some(channel),
+            None => {
+                warn!("Invalid revocation redis_url {:?}; revocations stay local", url);
+                None
+            }
+        });
+        Self {
+            cache: Arc::new(RevocationCache::new(cfg.retain_seconds)),
+            redis,
+            log: None,
+        }
+    }
+
+    /// Also append published revocations to the log that peer regions sync from
+    pub fn with_log(mut self, db: Arc<Database>, sessions: &SessionsConfig) -> Self {
+        if sessions.is_eventual() {
+            self.log = Some((db, sessions.region.clone()));
+        }
+        self
+    }
+
+    pub fn cache(&self) -> &RevocationCache {
+        &self.cache
+    }
+
+    /// Apply on this node and broadcast (fire-and-forget) to the others
+    pub fn publish(&self, event: RevocationEvent) {
+        self.cache.apply(&event);
+        if let Some((db, region)) = &self.log {
+            if let Err(e) = regions::record(db, &event, region, Database::now_ts()) {
+                warn!("Failed to record revocation for peer regions: {}", e);
+            }
+        }
+        if let Some(redis) = self.redis.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = redis.publish(&event).await {
+                    warn!("Failed to broadcast revocation: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Consume revocations published by other instances
+    pub fn spawn_subscriber(&self) {
+        let Some(redis) = self.redis.clone() else {
+            return;
+        };
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            let mut backoff = Duration::from_secs(1);
+            loop {
+                match redis.subscribe(&cache).await {
+                    Ok(()) => backoff = Duration::from_secs(1),
+                    Err(e) => warn!("Revocation subscription lost: {}", e),
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(30));
+            }
+        });
+    }
+}
+
+/// Redis pub/sub channel shared by the instances
+#[derive(Clone)]
+struct RedisChannel {
+    client: redis::Client,
+    channel: String,
+}
+
+impl RedisChannel {
+    fn parse(url: &str, channel: &str) -> Option<Self> {
+        Some(Self {
+            client: redis::Client::open(url).ok()?,
+            channel: channel.to_string(),
+        })
+    }
+
+    async fn publish(&self, event: &RevocationEvent) -> RedisResult<()> {
+        let payload = serde_json::to_string(event).map_err(io::Error::other)?;
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        conn.publish(&self.channel, payload).await
+    }
+
+    /// Apply messages until the subscription drops
+    async fn subscribe(&self, cache: &RevocationCache) -> RedisResult<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.channel).await?;
+        info!("Subscribed to revocation channel {}", self.channel);
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let event = message
+                .get_payload::<String>()
+                .ok()
+                .and_then(|payload| serde_json::from_str::<RevocationEvent>(&payload).ok());
+            match event {
+                Some(event) => {
+                    debug!("Revocation received: {:?}", event);
+                    cache.apply(&event);
+                }
+                None => warn!("Ignoring malformed revocation message"),
+            }
+        }
+        Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_revocation_rejects_older_tokens() {
+        let cache = RevocationCache::new(3600);
+        let now = Database::now_ts();
+        cache.apply(&RevocationEvent::UserSessionsRevoked {
+            user_id: "u1".into(),
+            at: now,
+        });
+        assert!(cache.is_user_token_revoked("u1", now - 10));
+        assert!(!cache.is_user_token_revoked("u1", now), "signed in again right after");
+        assert!(!cache.is_user_token_revoked("u1", now + 1));
+        assert!(!cache.is_user_token_revoked("u2", now - 10));
+    }
+
+    #[test]
+    fn revoked_access_tokens_are_rejected_by_jti() {
+        let cache = RevocationCache::new(3600);
+        let now = Database::now_ts();
+        let claims = |jti: &str| Claims {
+            sub: "sub".into(),
+            exp: (now + 600) as usize,
+            iat: now as usize,
+            nbf: None,
+            iss: None,
+            aud: Vec::new(),
+            sid: Some("s1".into()),
+            jti: Some(jti.into()),
+            kind: "access".into(),
+        };
+        cache.apply(&RevocationEvent::AccessTokenRevoked {
+            jti: "t1".into(),
+            exp: now + 600,
+        });
+        assert!(cache.is_access_token_revoked("u1", &claims("t1")));
+        assert!(!cache.is_access_token_revoked("u1", &claims("t2")));
+        cache.apply(&RevocationEvent::SessionRevoked {
+            session_id: "s1".into(),
+            user_id: "u1".into(),
+        });
+        assert!(cache.is_access_token_revoked("u1", &claims("t2")));
+    }
+
+    #[test]
+    fn events_round_trip_as_tagged_json() {
+        let event = RevocationEvent::SessionRevoked {
+            session_id: "s1".into(),
+            user_id: "u1".into(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("\"type\":\"session_revoked\""));
+        assert_eq!(serde_json::from_str::<RevocationEvent>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn parses_redis_url() {
+        let ch = RedisChannel::parse("redis://:s3cret@cache.internal:6380/0", "rev").unwrap();
+        let info = ch.client.get_connection_info();
+        assert_eq!(info.addr, redis::ConnectionAddr::Tcp("cache.internal".into(), 6380));
+        assert_eq!(info.redis.password.as_deref(), Some("s3cret"));
+        assert!(RedisChannel::parse("http://cache", "rev").is_none());
+    }
+}
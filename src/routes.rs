@@ -1,23 +1,43 @@
 use axum::{
-    extract::{Query, State, Path},
-    http::StatusCode,
-    response::{IntoResponse, Json},
-    routing::{post, get},
+    extract::{ConnectInfo, Form, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
+    routing::{delete, post, get},
     Router,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use crate::{
+    action_links::ActionPurpose,
+    admin_keys,
+    admin_users::AdminRole,
+    applications::{self, ApplicationConfig},
+    client_registration::{self, ClientMetadata, RegistrationMode},
     config::Config,
+    consents::{self, ConsentError, ConsentScope},
     db::Database,
+    device::{self, ClientHints},
+    device_authorization::{self, DeviceTokens, Poll},
     email::Emailer,
-    magic_link::{MagicLink, MagicLinkError},
+    error::{ApiError, ErrorResponse},
+    extractors::{self, AppClient, AuthUser, OAuthClient, OptionalJson, ProfileUser},
+    geo_policy,
+    html,
     jwt,
+    magic_link::{LinkBinding, LinkProof, FLOW_HEADER},
+    middleware::client_ip,
+    notifications::{self, NotificationError, PreferenceCenter, PreferencePatch},
+    oauth::{OAuthError, OAuthErrorCode},
+    profile::{self, ProfileError},
+    regions,
+    security_notices,
+    service::{AuthService, FactorProof, ServiceError},
     session::Session,
-    totp,
-    webauthn::WebauthnState,
+    transport,
+    user_agent::{self, UserAgent},
+    webauthn::{self, WebauthnState},
 };
-use std::sync::Arc;
-use tracing::{info, error};
+use std::{borrow::Cow, collections::HashMap, net::SocketAddr, sync::Arc};
+use tracing::error;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -27,279 +47,856 @@ pub struct AppState {
     pub webauthn: Arc<WebauthnState>,
     pub audit: Arc<crate::audit::AuditLogger>,
     pub webhook: Arc<crate::webhooks::WebhookSender>,
+    pub chaos: Arc<crate::chaos::ChaosState>,
+    pub revocations: Arc<crate::revocation::RevocationBus>,
+    pub sms: Arc<crate::notifications::SmsSender>,
+    pub mx: Arc<crate::deliverability::MxChecker>,
+    pub issuance: Arc<crate::issuance_hook::IssuanceGate>,
+    /// Signs and verifies access tokens
+    pub keys: Arc<jwt::KeyRing>,
+    /// Magic links and refresh tokens outside SQLite (`store = "redis"` or `"postgres"`);
+    /// `None` keeps them in `db`
+    pub tokens: Option<crate::storage::SharedTokenStore>,
+    /// Read-only switch; [`AuthService`] refuses writes while it is on
+    pub maintenance: Arc<crate::maintenance::MaintenanceMode>,
+    /// Concurrency limit shared by every adapter serving the auth routes
+    pub shedder: Arc<crate::load_shed::LoadShedder>,
+    /// Ban list and failure counters shared by every adapter serving the auth routes
+    pub ip_bans: Arc<crate::ip_bans::IpBanGuard>,
+}
+
+impl AppState {
+    /// A configured application, or an approved client registered at
+    /// `/oauth/register`
+    pub fn application(&self, client_id: &str) -> Option<Cow<'_, ApplicationConfig>> {
+        if let Some(app) = self.cfg.application(client_id) {
+            return Some(Cow::Borrowed(app));
+        }
+        client_registration::application(&self.db, client_id)
+            .unwrap_or_else(|e| {
+                error!("Database error: {}", e);
+                None
+            })
+            .map(Cow::Owned)
+    }
+
+    /// Where magic links and refresh tokens live (`store`)
+    pub fn tokens(&self) -> &dyn crate::storage::TokenStore {
+        match &self.tokens {
+            Some(tokens) => tokens.as_ref(),
+            None => self.db.as_ref(),
+        }
+    }
 }
 
 pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/request/magic", post(request_magic))
-        .route("/verify/magic", get(verify_magic))
+        .route("/verify/magic", get(verify_magic).post(verify_magic_bound))
+        .route("/report/unsolicited", get(report_unsolicited_page).post(report_unsolicited))
         .route("/totp/enroll", post(totp_enroll))
         .route("/totp/verify", post(totp_verify))
+        .route("/totp/rotate", post(totp_rotate))
+        .route("/totp/disable", post(totp_disable))
         .route("/token/refresh", post(refresh_token))
+        .route("/logout", post(logout))
+        .route("/token/verify-batch", post(verify_token_batch))
+        .route("/token/status", get(token_status))
+        .route("/oauth/introspect", post(introspect_token))
+        .route("/oauth/register", post(register_client))
+        .route("/oauth/revoke", post(revoke_token))
+        .route("/device", get(device_page).post(review_device))
+        .route("/device/code", post(device_code))
+        .route("/device/token", post(device_token))
+        .route("/auth/context", get(auth_context))
         .route("/webauthn/register/options", post(webauthn_register_options))
         .route("/webauthn/register/complete", post(webauthn_register_complete))
         .route("/webauthn/login/options", post(webauthn_login_options))
         .route("/webauthn/login/complete", post(webauthn_login_complete))
+        .route("/action/:token", get(action_page).post(perform_action))
+        .route("/email/verify/request", post(request_email_verification))
+        .route(
+            "/sessions/:session_id/metadata",
+            post(set_session_metadata).get(get_session_metadata),
+        )
+        .route("/me", get(me))
+        .route("/me/sessions", get(list_my_sessions))
+        .route("/me/webauthn/credentials", get(list_my_credentials))
+        .route("/me/notifications", get(get_notifications).patch(update_notifications))
+        .route("/me/notifications/phone/confirm", post(confirm_phone))
+        .route("/me/profile/complete", post(complete_profile))
+        .route("/me/consents", get(list_my_consents).post(grant_consent))
+        .route("/me/consents/:client_id", delete(revoke_consent))
+        .route("/userinfo", get(userinfo))
+        .route("/internal/revocations", get(list_revocations))
         .with_state(state)
 }
 
 #[derive(Deserialize)]
 struct RequestMagicBody {
     email: String,
+    /// Post-login target; must be in the application's `allowed_redirect_uris`
+    #[serde(default)]
+    redirect_uri: Option<String>,
+    /// PKCE (S256) challenge; the link then needs the `code_verifier` to yield tokens
+    #[serde(default)]
+    code_challenge: Option<String>,
+    #[serde(default)]
+    code_challenge_method: Option<String>,
+    /// Opaque value that must be presented again with the link
+    #[serde(default)]
+    state: Option<String>,
+}
+
+fn service_error(e: ServiceError) -> Response {
+    let mut error = ApiError::new(e.code(), e.public_message());
+    let mut response = (e.status(), e.public_message()).into_response();
+    if let ServiceError::EmailQuotaExceeded { reset_at } = e {
+        // details carry the reset time for v2 clients; Retry-After for everyone
+        if let Some(at) = chrono::DateTime::from_timestamp(reset_at, 0) {
+            error = error.with_details(at.to_rfc3339());
+        }
+        let wait = (reset_at - Database::now_ts()).max(1);
+        if let Ok(value) = HeaderValue::from_str(&wait.to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+    }
+    if let ServiceError::ReadOnly { retry_after } = e {
+        if let Ok(value) = HeaderValue::from_str(&retry_after.to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+    }
+    error.attach(response)
 }
 
 async fn request_magic(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
     Json(body): Json<RequestMagicBody>,
 ) -> impl IntoResponse {
-    let user_id = match state.db.get_or_create_user(&body.email) {
-        Ok(id) => id,
-        Err(e) => {
-            error!("user creation failed: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response();
-        }
+    let binding = match LinkBinding::new(
+        body.code_challenge.as_deref(),
+        body.code_challenge_method.as_deref(),
+        body.state.as_deref(),
+    ) {
+        Ok(binding) => binding,
+        Err(e) => return service_error(e.into()),
     };
-    match MagicLink::generate(&state.db, &user_id, state.cfg.magic_link_expiry_seconds) {
-        Ok(token) => {
-            if let Err(e) = state.emailer.send_magic_link(&body.email, &token) {
-                error!("email send failed: {}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, "email failed").into_response();
-            }
-            (StatusCode::OK, "magic link sent").into_response()
-        }
-        Err(e) => {
-            error!("magic link generation failed: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response()
+    let ip = peer_ip(&state, &headers, peer);
+    match AuthService::new(state)
+        .for_client(applications::client_id(&headers))
+        .with_ip(ip)
+        .request_magic(&body.email, body.redirect_uri.as_deref(), &binding)
+        .await
+    {
+        Ok(flow_id) => (StatusCode::OK, [(FLOW_HEADER, flow_id)], "magic link sent").into_response(),
+        Err(ServiceError::UndeliverableDomain(domain)) => {
+            ErrorResponse::bad_request(ApiError::undeliverable_domain(domain)).into_response()
         }
+        Err(e) => service_error(e),
     }
 }
 
 #[derive(Deserialize)]
 struct VerifyQuery {
     token: String,
-}
-
-#[derive(Serialize)]
-struct AuthResponse {
-    access_token: String,
-    refresh_token: String,
+    /// Proof for links requested with `code_challenge` / `state`
+    #[serde(flatten)]
+    proof: LinkProof,
 }
 
 async fn verify_magic(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(q): Query<VerifyQuery>,
 ) -> impl IntoResponse {
-    match MagicLink::consume(&state.db, &q.token) {
-        Ok(user_id) => {
-            // issue tokens
-            let access = jwt::create_token(
-                &user_id,
-                &state.cfg.jwt_secret,
-                state.cfg.access_token_expiry_seconds,
-                "access",
-            )
-            .unwrap();
-            let refresh =
-                Session::create_refresh_token(&state.db, &user_id, state.cfg.refresh_token_expiry_seconds)
-                    .unwrap();
-            let refresh_jwt = jwt::create_token(
-                &refresh,
-                &state.cfg.jwt_secret,
-                state.cfg.refresh_token_expiry_seconds,
-                "refresh",
-            )
-            .unwrap();
-            let resp = AuthResponse {
-                access_token: access,
-                refresh_token: refresh_jwt,
-            };
-            (StatusCode::OK, Json(resp)).into_response()
-        }
-        Err(MagicLinkError::Used) => (StatusCode::BAD_REQUEST, "link already used").into_response(),
-        Err(MagicLinkError::Invalid) => (StatusCode::BAD_REQUEST, "invalid or expired").into_response(),
-        Err(e) => {
-            error!("verify magic error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response()
-        }
+    complete_magic(state, headers, q).await
+}
+
+/// Same as `GET /verify/magic`, keeping the `code_verifier` out of URLs and access logs
+async fn verify_magic_bound(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<VerifyQuery>,
+) -> impl IntoResponse {
+    complete_magic(state, headers, body).await
+}
+
+async fn complete_magic(state: AppState, headers: HeaderMap, q: VerifyQuery) -> Response {
+    match AuthService::new(state.clone())
+        .for_client(applications::client_id(&headers))
+        .with_device(ClientHints::from_headers(&headers))
+        .with_user_agent(user_agent::from_headers(&headers))
+        .with_country(security_notices::country(&state.cfg.security_notices, &headers))
+        .with_asn(geo_policy::asn(&state.cfg.geo_policy, &headers))
+        .with_platform_authenticator(device::platform_authenticator(&headers))
+        .verify_magic(&q.token, &q.proof)
+        .await
+    {
+        Ok(resp) => transport::token_response(&state.cfg, &headers, resp),
+        Err(e) => service_error(e),
     }
 }
 
+/// Confirmation page behind the "This wasn't me" email link. The report
+/// itself needs a POST, so mail scanners following links cannot file one.
+const REPORT_PAGE: &str = "<!doctype html>\n<title>Report sign-in link</title>\n\
+<p>Someone asked for a sign-in link to your account. If it wasn't you, cancel the link and report it.</p>\n\
+<form method=\"post\"><button type=\"submit\">This wasn't me</button></form>\n";
+
+async fn report_unsolicited_page() -> impl IntoResponse {
+    Html(REPORT_PAGE)
+}
+
 #[derive(Deserialize)]
-struct TotpEnrollBody {
-    email: String,
+struct ReportQuery {
+    token: String,
 }
 
-#[derive(Serialize)]
-struct TotpEnrollResp {
-    secret: String,
-    otpauth_url: String,
+async fn report_unsolicited(State(state): State<AppState>, Query(q): Query<ReportQuery>) -> impl IntoResponse {
+    match AuthService::new(state).report_unsolicited(&q.token).await {
+        Ok(()) => (StatusCode::OK, "reported, the link can no longer be used").into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct TotpEnrollBody {
+    email: String,
 }
 
 async fn totp_enroll(
     State(state): State<AppState>,
     Json(body): Json<TotpEnrollBody>,
 ) -> impl IntoResponse {
-    let user_id = match state.db.get_or_create_user(&body.email) {
-        Ok(id) => id,
-        Err(e) => {
-            error!("user get/create failed: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response();
-        }
-    };
-
-    let secret = totp::generate_secret();
-    // store in user record
-    if let Err(e) = state.db.conn.execute(
-        "UPDATE users SET totp_secret = ?1 WHERE id = ?2",
-        rusqlite::params![secret, user_id],
-    ) {
-        error!("saving totp secret failed: {}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response();
+    match AuthService::new(state).totp_enroll(&body.email).await {
+        Ok(resp) => (StatusCode::OK, Json(resp)).into_response(),
+        Err(e) => service_error(e),
     }
-
-    let url = totp::generate_otpauth_url(&secret, &body.email, "PasswordlessAuth");
-    let resp = TotpEnrollResp {
-        secret,
-        otpauth_url: url,
-    };
-    (StatusCode::OK, Json(resp)).into_response()
 }
 
 #[derive(Deserialize)]
 struct TotpVerifyBody {
     email: String,
     code: String,
+    /// Required after a failed attempt (returned in that failure's response)
+    #[serde(default)]
+    attempt_token: Option<String>,
 }
 
 async fn totp_verify(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<TotpVerifyBody>,
 ) -> impl IntoResponse {
-    // load user and secret
-    let mut stmt = match state.db.conn.prepare("SELECT id, totp_secret FROM users WHERE email = ?1") {
-        Ok(s) => s,
-        Err(e) => {
-            error!("db error: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response();
+    // nothing earlier in the flow records the application, so one with a
+    // secret must authenticate here to have tokens issued to it
+    let client_id = applications::client_id(&headers);
+    let app = client_id.and_then(|id| extractors::public_or_confidential_client(&headers, &state, Some(id)));
+    if client_id.is_some() && app.is_none() {
+        return ErrorResponse::unauthorized(ApiError::unauthorized("Invalid client credentials")).into_response();
+    }
+    match AuthService::new(state.clone())
+        .for_client(app.as_ref().map(|app| app.client_id.as_str()))
+        .with_device(ClientHints::from_headers(&headers))
+        .with_user_agent(user_agent::from_headers(&headers))
+        .with_country(security_notices::country(&state.cfg.security_notices, &headers))
+        .with_asn(geo_policy::asn(&state.cfg.geo_policy, &headers))
+        .totp_verify(&body.email, &body.code, body.attempt_token.as_deref())
+        .await
+    {
+        Ok(resp) => transport::token_response(&state.cfg, &headers, resp),
+        Err(ServiceError::InvalidTotpRetry {
+            attempt_token,
+            remaining_attempts,
+        }) => ApiError::invalid_totp().attach(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid totp",
+                    "attempt_token": attempt_token,
+                    "remaining_attempts": remaining_attempts,
+                })),
+            )
+                .into_response(),
+        ),
+        Err(e) => service_error(e),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct RefreshBody {
+    /// Optional when the refresh token is carried in the session cookie
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct LogoutBody {
+    /// Optional when the refresh token is carried in the session cookie
+    #[serde(default)]
+    refresh_token: Option<String>,
+    /// Revoke every session of the user, not just this one
+    #[serde(default)]
+    all: bool,
+}
+
+/// Exactly one of `code` or `passkey` confirms the current factor
+#[derive(Deserialize)]
+struct FactorProofBody {
+    code: Option<String>,
+    passkey: Option<PasskeyProofBody>,
+}
+
+impl FactorProofBody {
+    fn into_proof(self) -> Result<FactorProof, ServiceError> {
+        match (self.code, self.passkey) {
+            (Some(code), None) => Ok(FactorProof::Totp(code)),
+            (None, Some(p)) => Ok(FactorProof::Passkey {
+                pending_id: p.pending_id,
+                response: p.response,
+            }),
+            _ => Err(ServiceError::FactorConfirmationRequired),
         }
+    }
+}
+
+#[derive(Deserialize)]
+struct PasskeyProofBody {
+    pending_id: String,
+    response: serde_json::Value,
+}
+
+/// Client address recorded against passkeys asserted by the request
+fn peer_ip(state: &AppState, headers: &HeaderMap, peer: Option<ConnectInfo<SocketAddr>>) -> Option<String> {
+    client_ip(headers, peer.map(|ConnectInfo(addr)| addr), state.cfg.ip_access.trust_forwarded_for)
+}
+
+async fn totp_rotate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    user: AuthUser,
+    Json(body): Json<FactorProofBody>,
+) -> impl IntoResponse {
+    let proof = match body.into_proof() {
+        Ok(proof) => proof,
+        Err(e) => return service_error(e),
     };
-    let mut rows = match stmt.query(rusqlite::params![body.email]) {
-        Ok(r) => r,
-        Err(e) => {
-            error!("query failed: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response();
-        }
+    let ip = peer_ip(&state, &headers, peer);
+    match AuthService::new(state).with_ip(ip).totp_rotate(&user.user_id, proof).await {
+        Ok(resp) => (StatusCode::OK, Json(resp)).into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+async fn totp_disable(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    user: AuthUser,
+    Json(body): Json<FactorProofBody>,
+) -> impl IntoResponse {
+    let proof = match body.into_proof() {
+        Ok(proof) => proof,
+        Err(e) => return service_error(e),
     };
-    if let Some(r) = rows.next().unwrap_or(None) {
-        let user_id: String = r.get(0).unwrap();
-        let secret: Option<String> = r.get(1).unwrap();
-        if let Some(s) = secret {
-            match totp::verify_code(&s, &body.code) {
-                Ok(_) => {
-                    let access = jwt::create_token(
-                        &user_id,
-                        &state.cfg.jwt_secret,
-                        state.cfg.access_token_expiry_seconds,
-                        "access",
-                    )
-                    .unwrap();
-                    let refresh = Session::create_refresh_token(&state.db, &user_id, state.cfg.refresh_token_expiry_seconds)
-                        .unwrap();
-                    let refresh_jwt = jwt::create_token(
-                        &refresh,
-                        &state.cfg.jwt_secret,
-                        state.cfg.refresh_token_expiry_seconds,
-                        "refresh",
-                    )
-                    .unwrap();
-                    let resp = AuthResponse {
-                        access_token: access,
-                        refresh_token: refresh_jwt,
-                    };
-                    return (StatusCode::OK, Json(resp)).into_response();
-                }
-                Err(_) => return (StatusCode::BAD_REQUEST, "invalid totp").into_response(),
-            }
-        } else {
-            return (StatusCode::BAD_REQUEST, "totp not enrolled").into_response();
-        }
+    let ip = peer_ip(&state, &headers, peer);
+    match AuthService::new(state).with_ip(ip).totp_disable(&user.user_id, proof).await {
+        Ok(()) => (StatusCode::OK, "totp disabled").into_response(),
+        Err(e) => service_error(e),
     }
-    (StatusCode::BAD_REQUEST, "user not found").into_response()
 }
 
 #[derive(Deserialize)]
-struct RefreshBody {
-    refresh_token: String,
+struct VerifyBatchBody {
+    tokens: Vec<String>,
+}
+
+/// Outcome for one token, in request order
+#[derive(serde::Serialize)]
+struct TokenVerification {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claims: Option<jwt::Claims>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Verify many tokens at once for batch jobs (application credentials required)
+async fn verify_token_batch(
+    State(state): State<AppState>,
+    AppClient(_app): AppClient,
+    Json(body): Json<VerifyBatchBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if body.tokens.len() > state.cfg.verify_batch_max_tokens {
+        return Err(ErrorResponse::bad_request(ApiError::validation_error(format!(
+            "at most {} tokens per batch",
+            state.cfg.verify_batch_max_tokens
+        ))));
+    }
+
+    let verifier = state.keys.verifier(state.cfg.jwt_leeway_seconds);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let results = tokio::task::spawn_blocking(move || verifier.verify_batch(&body.tokens, threads))
+        .await
+        .map_err(|e| {
+            error!("Batch verification failed: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
+        })?;
+
+    // signature first, then the checks every access-token endpoint applies
+    let mut verified = Vec::with_capacity(results.len());
+    for result in results {
+        let checked = match result {
+            Ok(claims) => match extractors::accept(&state, &claims, &["access"]) {
+                Ok(_) => Ok(claims),
+                Err(extractors::Rejection::Db(e)) => {
+                    error!("Database error: {}", e);
+                    return Err(ErrorResponse::internal_error(ApiError::internal_error()));
+                }
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(e.to_string()),
+        };
+        verified.push(match checked {
+            Ok(claims) => TokenVerification {
+                valid: true,
+                claims: Some(claims),
+                error: None,
+            },
+            Err(error) => TokenVerification {
+                valid: false,
+                claims: None,
+                error: Some(error),
+            },
+        });
+    }
+
+    Ok(Json(serde_json::json!({ "results": verified })))
 }
 
 async fn refresh_token(
     State(state): State<AppState>,
-    Json(body): Json<RefreshBody>,
+    method: Method,
+    headers: HeaderMap,
+    OptionalJson(body): OptionalJson<RefreshBody>,
 ) -> impl IntoResponse {
-    // verify JWT of refresh token
-    match jwt::verify_token(&body.refresh_token, &state.cfg.jwt_secret) {
-        Ok(claims) => {
-            if claims.kind != "refresh" {
-                return (StatusCode::BAD_REQUEST, "invalid token kind").into_response();
+    let refresh = match body.refresh_token {
+        Some(refresh) => refresh,
+        None => {
+            let Some(refresh) = transport::read_cookie(&headers, &state.cfg.cookie.refresh_name) else {
+                return (StatusCode::BAD_REQUEST, "missing refresh token").into_response();
+            };
+            if !transport::cookie_request_allowed(&state.cfg.cookie, &method, &headers) {
+                return extractors::cross_site().into_response();
             }
-            let raw_refresh = claims.sub;
-            // validate session store
-            match Session::validate_refresh_token(&state.db, &raw_refresh) {
-                Ok(user_id) => {
-                    let access = jwt::create_token(
-                        &user_id,
-                        &state.cfg.jwt_secret,
-                        state.cfg.access_token_expiry_seconds,
-                        "access",
-                    )
-                    .unwrap();
-                    let refresh = Session::create_refresh_token(&state.db, &user_id, state.cfg.refresh_token_expiry_seconds)
-                        .unwrap();
-                    let refresh_jwt = jwt::create_token(
-                        &refresh,
-                        &state.cfg.jwt_secret,
-                        state.cfg.refresh_token_expiry_seconds,
-                        "refresh",
-                    )
-                    .unwrap();
-                    let resp = AuthResponse {
-                        access_token: access,
-                        refresh_token: refresh_jwt,
-                    };
-                    (StatusCode::OK, Json(resp)).into_response()
-                }
-                Err(_) => (StatusCode::UNAUTHORIZED, "invalid refresh").into_response(),
+            refresh
+        }
+    };
+    match AuthService::new(state.clone())
+        .for_client(applications::client_id(&headers))
+        .refresh(&refresh)
+        .await
+    {
+        Ok(resp) => transport::token_response(&state.cfg, &headers, resp),
+        Err(e) => service_error(e),
+    }
+}
+
+/// Revoke the session of the presented refresh token (body or cookie), or
+/// with `all` every session of its user, and clear the token cookies
+async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    method: Method,
+    OptionalJson(body): OptionalJson<LogoutBody>,
+) -> impl IntoResponse {
+    let refresh = match body.refresh_token {
+        Some(refresh) => refresh,
+        None => {
+            let Some(refresh) = transport::read_cookie(&headers, &state.cfg.cookie.refresh_name) else {
+                return (StatusCode::BAD_REQUEST, "missing refresh token").into_response();
+            };
+            if !transport::cookie_request_allowed(&state.cfg.cookie, &method, &headers) {
+                return extractors::cross_site().into_response();
             }
+            refresh
         }
+    };
+    let ip = peer_ip(&state, &headers, peer);
+    match AuthService::new(state.clone()).with_ip(ip).logout(&refresh, body.all).await {
+        Ok(()) => transport::logout_response(&state.cfg, &headers),
+        Err(e) => service_error(e),
+    }
+}
+
+/// Remaining lifetime of the caller's access token, and of the refresh token
+/// when it is presented (`X-Refresh-Token` header or the refresh cookie)
+async fn token_status(State(state): State<AppState>, headers: HeaderMap, user: AuthUser) -> impl IntoResponse {
+    let refresh = headers
+        .get("X-Refresh-Token")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| transport::read_cookie(&headers, &state.cfg.cookie.refresh_name));
+    match AuthService::new(state).token_status(&user.user_id, user.claims.exp as i64, refresh.as_deref()) {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+/// RFC 7662 introspection and RFC 7009 revocation request
+/// (`application/x-www-form-urlencoded`)
+#[derive(Deserialize)]
+struct TokenForm {
+    token: Option<String>,
+    token_type_hint: Option<String>,
+}
+
+/// Token introspection for applications and resource servers holding client
+/// credentials; unknown, expired and revoked tokens are `{"active": false}`
+async fn introspect_token(
+    State(state): State<AppState>,
+    OAuthClient(app): OAuthClient,
+    Form(form): Form<TokenForm>,
+) -> Response {
+    let Some(token) = form.token.filter(|t| !t.is_empty()) else {
+        return OAuthError::invalid_request("token is required").into_response();
+    };
+    match AuthService::new(state).introspect(&app, &token, form.token_type_hint.as_deref()) {
+        Ok(introspection) => ([(header::CACHE_CONTROL, "no-store")], Json(introspection)).into_response(),
+        Err(e) => OAuthError::from(e).into_response(),
+    }
+}
+
+/// Token revocation for applications holding client credentials (RFC 7009);
+/// unknown and already invalid tokens also answer `200`
+async fn revoke_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    OAuthClient(app): OAuthClient,
+    Form(form): Form<TokenForm>,
+) -> Response {
+    let Some(token) = form.token.filter(|t| !t.is_empty()) else {
+        return OAuthError::invalid_request("token is required").into_response();
+    };
+    let ip = peer_ip(&state, &headers, peer);
+    match AuthService::new(state).with_ip(ip).revoke(&app, &token, form.token_type_hint.as_deref()) {
+        Ok(()) => ([(header::CACHE_CONTROL, "no-store")], StatusCode::OK).into_response(),
+        Err(e) => OAuthError::from(e).into_response(),
+    }
+}
+
+/// Dynamic client registration (RFC 7591). Depending on `[client_registration]
+/// mode`, callers need an admin API key or register clients that wait for
+/// approval; the client secret is only ever returned here.
+async fn register_client(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Json(metadata): Json<ClientMetadata>,
+) -> Response {
+    let cfg = &state.cfg.client_registration;
+    if cfg.mode == RegistrationMode::Disabled {
+        return ErrorResponse::not_found(ApiError::not_found("Not found")).into_response();
+    }
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    let admin_key = match presented.map(|key| admin_keys::verify(&state.db, key)) {
+        None => None,
+        Some(Ok(Some(key))) if key.role >= AdminRole::Operator => Some(key),
+        Some(Ok(Some(_))) => {
+            return OAuthError::new(OAuthErrorCode::InsufficientScope)
+                .with_description("registering clients requires the operator admin role")
+                .into_response()
+        }
+        Some(Ok(None)) => return OAuthError::invalid_token().into_response(),
+        Some(Err(e)) => {
+            error!("Database error: {}", e);
+            return OAuthError::server_error().into_response();
+        }
+    };
+    if cfg.mode == RegistrationMode::Admin && admin_key.is_none() {
+        return OAuthError::new(OAuthErrorCode::InvalidToken)
+            .with_description("an admin API key is required")
+            .into_response();
+    }
+    let registered_by = admin_key.as_ref().map(|key| key.id.as_str());
+    let registration =
+        match client_registration::register(&state.db, cfg, &metadata, registered_by, Database::now_ts()) {
+            Ok(registration) => registration,
+            Err(e) => return OAuthError::from(e).into_response(),
+        };
+    let client = &registration.client;
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::ClientRegistrationChanged,
+        None,
+        None,
+        peer_ip(&state, &headers, peer).as_deref(),
+        None,
+        Some(
+            &serde_json::json!({
+                "action": "registered",
+                "client_id": client.client_id,
+                "status": client.status,
+                "registered_by": client.registered_by,
+            })
+            .to_string(),
+        ),
+        true,
+    );
+    (StatusCode::CREATED, [(header::CACHE_CONTROL, "no-store")], Json(registration)).into_response()
+}
+
+/// Grant type of `POST /device/token` (RFC 8628 §3.4)
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// RFC 8628 device authorization request; `scope` is ignored, applications
+/// ask for profile data through consents
+#[derive(Deserialize)]
+struct DeviceCodeForm {
+    client_id: Option<String>,
+}
+
+/// Start the device flow. Public clients send their `client_id`,
+/// confidential ones authenticate with HTTP Basic.
+async fn device_code(State(state): State<AppState>, headers: HeaderMap, Form(form): Form<DeviceCodeForm>) -> Response {
+    let cfg = &state.cfg.device_authorization;
+    if !cfg.enabled {
+        return ErrorResponse::not_found(ApiError::not_found("Not found")).into_response();
+    }
+    let Some(app) = extractors::public_or_confidential_client(&headers, &state, form.client_id.as_deref()) else {
+        return OAuthError::invalid_client().into_response();
+    };
+    match device_authorization::start(&state.db, cfg, &app.client_id, Database::now_ts()) {
+        Ok(authorization) => ([(header::CACHE_CONTROL, "no-store")], Json(authorization)).into_response(),
         Err(e) => {
-            error!("refresh token verify failed: {}", e);
-            (StatusCode::BAD_REQUEST, "invalid token").into_response()
+            error!("Database error: {}", e);
+            OAuthError::server_error().into_response()
         }
     }
 }
 
+#[derive(Deserialize)]
+struct DeviceTokenForm {
+    grant_type: Option<String>,
+    device_code: Option<String>,
+    client_id: Option<String>,
+}
+
+/// Polled by the device until the user decided; answers `authorization_pending`
+/// and `slow_down` meanwhile, then tokens for a new session once
+async fn device_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Form(form): Form<DeviceTokenForm>,
+) -> Response {
+    if !state.cfg.device_authorization.enabled {
+        return ErrorResponse::not_found(ApiError::not_found("Not found")).into_response();
+    }
+    match form.grant_type.as_deref() {
+        Some(DEVICE_CODE_GRANT) => {}
+        Some(_) => return OAuthError::new(OAuthErrorCode::UnsupportedGrantType).into_response(),
+        None => return OAuthError::invalid_request("grant_type is required").into_response(),
+    }
+    let Some(device_code) = form.device_code.filter(|c| !c.is_empty()) else {
+        return OAuthError::invalid_request("device_code is required").into_response();
+    };
+    let Some(app) = extractors::public_or_confidential_client(&headers, &state, form.client_id.as_deref()) else {
+        return OAuthError::invalid_client().into_response();
+    };
+    let now = Database::now_ts();
+    let user_id = match device_authorization::poll(&state.db, &device_code, &app.client_id, now) {
+        Ok(Some(Poll::Approved(user_id))) => user_id,
+        Ok(Some(Poll::Pending)) => return OAuthError::new(OAuthErrorCode::AuthorizationPending).into_response(),
+        Ok(Some(Poll::SlowDown)) => return OAuthError::new(OAuthErrorCode::SlowDown).into_response(),
+        Ok(Some(Poll::Denied)) => return OAuthError::new(OAuthErrorCode::AccessDenied).into_response(),
+        Ok(Some(Poll::Expired)) => return OAuthError::new(OAuthErrorCode::ExpiredToken).into_response(),
+        Ok(None) => return OAuthError::invalid_grant("device code is invalid or was already used").into_response(),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return OAuthError::server_error().into_response();
+        }
+    };
+    let ip = peer_ip(&state, &headers, peer);
+    match AuthService::new(state.clone())
+        .for_client(Some(&app.client_id))
+        .with_ip(ip)
+        .with_user_agent(user_agent::from_headers(&headers))
+        .with_country(security_notices::country(&state.cfg.security_notices, &headers))
+        .with_asn(geo_policy::asn(&state.cfg.geo_policy, &headers))
+        .device_login(&user_id)
+        .await
+    {
+        Ok(tokens) => ([(header::CACHE_CONTROL, "no-store")], Json(DeviceTokens::new(tokens, now))).into_response(),
+        Err(e) => OAuthError::from(e).into_response(),
+    }
+}
+
+fn device_html(status: StatusCode, body: &str) -> Response {
+    let page = format!("<!doctype html>\n<title>Sign in a device</title>\n{}", body);
+    (status, Html(page)).into_response()
+}
+
+const DEVICE_CODE_FORM: &str = "<form method=\"get\">\n<label>Code shown on your device \
+<input name=\"user_code\" autocomplete=\"off\" autocapitalize=\"characters\" required></label>\n\
+<button type=\"submit\">Continue</button></form>\n";
+
+#[derive(Deserialize)]
+struct DevicePageQuery {
+    user_code: Option<String>,
+}
+
+/// Verification page of the device flow: asks for the user code, then shows
+/// which application is asking before the signed-in user approves or denies
+/// it. Sign-in is read from the access-token cookie.
+async fn device_page(State(state): State<AppState>, Query(q): Query<DevicePageQuery>) -> Response {
+    if !state.cfg.device_authorization.enabled {
+        return ErrorResponse::not_found(ApiError::not_found("Not found")).into_response();
+    }
+    let Some(user_code) = q.user_code.filter(|c| !c.trim().is_empty()) else {
+        return device_html(StatusCode::OK, DEVICE_CODE_FORM);
+    };
+    let pending = match device_authorization::pending(&state.db, &user_code, Database::now_ts()) {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            let body = format!("<p>This code is unknown or has expired.</p>\n{}", DEVICE_CODE_FORM);
+            return device_html(StatusCode::NOT_FOUND, &body);
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return ErrorResponse::internal_error(ApiError::internal_error()).into_response();
+        }
+    };
+    let name = state.application(&pending.client_id).map_or(pending.client_id.clone(), |app| app.name.clone());
+    let body = format!(
+        "<p>{} wants to sign in to your account on a device showing the code <b>{}</b>. \
+Only continue if you started this sign-in.</p>\n<form method=\"post\">\
+<input type=\"hidden\" name=\"user_code\" value=\"{}\">\n\
+<button type=\"submit\" name=\"decision\" value=\"approve\">Approve</button>\n\
+<button type=\"submit\" name=\"decision\" value=\"deny\">Deny</button></form>\n",
+        html::escape(&name),
+        html::escape(&pending.user_code),
+        html::escape(&pending.user_code),
+    );
+    device_html(StatusCode::OK, &body)
+}
+
+#[derive(Deserialize)]
+struct DeviceReviewForm {
+    user_code: String,
+    /// `approve` or `deny`
+    decision: String,
+}
+
+/// The signed-in user approves or denies a device's request. Applications
+/// hosting their own verification page post here with the user's bearer
+/// token.
+async fn review_device(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    user: AuthUser,
+    Form(form): Form<DeviceReviewForm>,
+) -> Response {
+    if !state.cfg.device_authorization.enabled {
+        return ErrorResponse::not_found(ApiError::not_found("Not found")).into_response();
+    }
+    let approve = match form.decision.as_str() {
+        "approve" => true,
+        "deny" => false,
+        _ => {
+            return ErrorResponse::bad_request(ApiError::validation_error("decision must be approve or deny"))
+                .into_response()
+        }
+    };
+    let reviewed = device_authorization::review(&state.db, &form.user_code, &user.user_id, approve, Database::now_ts());
+    let pending = match reviewed {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            let body = format!("<p>This code is unknown, has expired or was already used.</p>\n{}", DEVICE_CODE_FORM);
+            return device_html(StatusCode::NOT_FOUND, &body);
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return ErrorResponse::internal_error(ApiError::internal_error()).into_response();
+        }
+    };
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::DeviceAuthorizationReviewed,
+        Some(&user.user_id),
+        None,
+        peer_ip(&state, &headers, peer).as_deref(),
+        None,
+        Some(
+            &serde_json::json!({
+                "client_id": pending.client_id,
+                "user_code": pending.user_code,
+                "approved": approve,
+            })
+            .to_string(),
+        ),
+        true,
+    );
+    let message = if approve {
+        "<p>Device approved. Return to your device to continue.</p>\n"
+    } else {
+        "<p>Sign-in denied. The device was not signed in.</p>\n"
+    };
+    device_html(StatusCode::OK, message)
+}
+
+/// Forward-auth endpoint for gateways: `204` with the signed auth context of
+/// the bearer's session in the `[auth_context]` header. Hidden unless enabled.
+async fn auth_context(State(state): State<AppState>, user: AuthUser) -> Response {
+    let cfg = &state.cfg.auth_context;
+    if !cfg.enabled {
+        return ErrorResponse::not_found(ApiError::not_found("Not found")).into_response();
+    }
+    let Ok(name) = header::HeaderName::from_bytes(cfg.header.as_bytes()) else {
+        error!("[auth_context] header {:?} is not a valid header name", cfg.header);
+        return ErrorResponse::internal_error(ApiError::internal_error()).into_response();
+    };
+    let context = AuthService::new(state.clone()).auth_context(&user.user_id, user.claims.sid.as_deref());
+    match context.map(|c| HeaderValue::from_str(&c)) {
+        Ok(Ok(value)) => (StatusCode::NO_CONTENT, [(name, value)]).into_response(),
+        Ok(Err(e)) => {
+            error!("auth context is not a valid header value: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error()).into_response()
+        }
+        Err(e) => service_error(e),
+    }
+}
+
+/// Identify the user by `email`, or by a `ticket` from a passkey nudge
 #[derive(Deserialize)]
 struct WebauthnRegisterOptionsBody {
-    email: String,
+    email: Option<String>,
+    ticket: Option<String>,
 }
 
 async fn webauthn_register_options(
     State(state): State<AppState>,
     Json(body): Json<WebauthnRegisterOptionsBody>,
 ) -> impl IntoResponse {
-    let user_id = match state.db.get_or_create_user(&body.email) {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response(),
+    let service = AuthService::new(state);
+    let result = match (&body.ticket, &body.email) {
+        (Some(ticket), _) => service.webauthn_register_options_for_ticket(ticket).await,
+        (None, Some(email)) => service.webauthn_register_options(email).await,
+        (None, None) => return (StatusCode::BAD_REQUEST, "email or ticket required").into_response(),
     };
-    match state
-        .webauthn
-        .start_registration(&state.db, &user_id, &body.email)
-    {
+    match result {
         Ok(opts) => (StatusCode::OK, Json(opts)).into_response(),
-        Err(e) => {
-            error!("webauthn start reg error: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response()
-        }
+        Err(e) => service_error(e),
     }
 }
 
@@ -313,15 +910,12 @@ async fn webauthn_register_complete(
     State(state): State<AppState>,
     Json(body): Json<WebauthnRegisterCompleteBody>,
 ) -> impl IntoResponse {
-    match state
-        .webauthn
-        .finish_registration(&state.db, &body.pending_id, body.response.clone())
+    match AuthService::new(state)
+        .webauthn_register_complete(&body.pending_id, body.response)
+        .await
     {
-        Ok(_) => (StatusCode::OK, "registered").into_response(),
-        Err(e) => {
-            error!("reg complete failed: {:?}", e);
-            (StatusCode::BAD_REQUEST, "failed").into_response()
-        }
+        Ok(()) => (StatusCode::OK, "registered").into_response(),
+        Err(e) => service_error(e),
     }
 }
 
@@ -332,32 +926,16 @@ struct WebauthnLoginOptionsBody {
 
 async fn webauthn_login_options(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<WebauthnLoginOptionsBody>,
 ) -> impl IntoResponse {
-    // need user id
-    let mut stmt = match state
-        .db
-        .conn
-        .prepare("SELECT id FROM users WHERE email = ?1")
+    match AuthService::new(state)
+        .for_client(applications::client_id(&headers))
+        .webauthn_login_options(&body.email)
+        .await
     {
-        Ok(s) => s,
-        Err(e) => {
-            error!("db error: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response();
-        }
-    };
-    let mut rows = stmt.query(rusqlite::params![body.email]).unwrap();
-    if let Some(r) = rows.next().unwrap_or(None) {
-        let user_id: String = r.get(0).unwrap();
-        match state.webauthn.start_login(&state.db, &user_id) {
-            Ok(opts) => (StatusCode::OK, Json(opts)).into_response(),
-            Err(e) => {
-                error!("webauthn start login error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "error").into_response()
-            }
-        }
-    } else {
-        (StatusCode::BAD_REQUEST, "user not found").into_response()
+        Ok(opts) => (StatusCode::OK, Json(opts)).into_response(),
+        Err(e) => service_error(e),
     }
 }
 
@@ -369,38 +947,443 @@ struct WebauthnLoginCompleteBody {
 
 async fn webauthn_login_complete(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
     Json(body): Json<WebauthnLoginCompleteBody>,
 ) -> impl IntoResponse {
-    match state
-        .webauthn
-        .finish_login(&state.db, &body.pending_id, body.response.clone())
+    match AuthService::new(state.clone())
+        .with_ip(peer_ip(&state, &headers, peer))
+        .with_device(ClientHints::from_headers(&headers))
+        .with_user_agent(user_agent::from_headers(&headers))
+        .with_country(security_notices::country(&state.cfg.security_notices, &headers))
+        .with_asn(geo_policy::asn(&state.cfg.geo_policy, &headers))
+        .webauthn_login_complete(&body.pending_id, body.response)
+        .await
     {
-        Ok(user_id) => {
-            let access = jwt::create_token(
-                &user_id,
-                &state.cfg.jwt_secret,
-                state.cfg.access_token_expiry_seconds,
-                "access",
-            )
-            .unwrap();
-            let refresh = Session::create_refresh_token(&state.db, &user_id, state.cfg.refresh_token_expiry_seconds)
-                .unwrap();
-            let refresh_jwt = jwt::create_token(
-                &refresh,
-                &state.cfg.jwt_secret,
-                state.cfg.refresh_token_expiry_seconds,
-                "refresh",
-            )
-            .unwrap();
-            let resp = AuthResponse {
-                access_token: access,
-                refresh_token: refresh_jwt,
-            };
-            (StatusCode::OK, Json(resp)).into_response()
-        }
+        Ok(resp) => transport::token_response(&state.cfg, &headers, resp),
+        Err(e) => service_error(e),
+    }
+}
+
+/// Maximum serialized size of metadata an application may attach to a session
+const MAX_SESSION_METADATA_BYTES: usize = 4096;
+
+async fn set_session_metadata(
+    State(state): State<AppState>,
+    AppClient(app): AppClient,
+    Path(session_id): Path<String>,
+    Json(metadata): Json<serde_json::Value>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if metadata.to_string().len() > MAX_SESSION_METADATA_BYTES {
+        return Err(ErrorResponse::bad_request(ApiError::validation_error(format!(
+            "metadata must not exceed {} bytes",
+            MAX_SESSION_METADATA_BYTES
+        ))));
+    }
+
+    let session = state.tokens().live_session(&session_id).map_err(|e| {
+        error!("Failed to look up session: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+    // applications annotate only the sessions signed in to them; others read as unknown
+    if session.is_none_or(|s| s.client_id.as_deref() != Some(app.client_id.as_str())) {
+        return Err(ErrorResponse::not_found(ApiError::session_not_found()));
+    }
+
+    Session::set_metadata(&state.db, &session_id, &app.client_id, &metadata).map_err(|e| {
+        error!("Failed to store session metadata: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_session_metadata(
+    State(state): State<AppState>,
+    AppClient(app): AppClient,
+    Path(session_id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let metadata = Session::get_metadata(&state.db, &session_id, &app.client_id)
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
+        })?
+        .ok_or_else(|| ErrorResponse::not_found(ApiError::session_not_found()))?;
+
+    Ok(Json(metadata))
+}
+
+/// Confirmation page behind emailed action links. Following the link
+/// changes nothing; the action needs the POST this page submits, so mail
+/// scanners cannot verify, revoke or freeze anything.
+const ACTION_PAGE: &str = "<!doctype html>\n<title>Confirm</title>\n\
+<p>Continue to complete the action from your email.</p>\n\
+<form method=\"post\"><button type=\"submit\">Continue</button></form>\n";
+
+async fn action_page() -> impl IntoResponse {
+    Html(ACTION_PAGE)
+}
+
+async fn perform_action(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match AuthService::new(state).perform_action(&token).await {
+        Ok(outcome) => (StatusCode::OK, Json(outcome)).into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+async fn request_email_verification(State(state): State<AppState>, user: AuthUser) -> Response {
+    let email = match state.db.users().find_by_id(&user.user_id) {
+        Ok(Some(user)) => user.email,
+        Ok(None) => return ErrorResponse::not_found(ApiError::user_not_found()).into_response(),
         Err(e) => {
-            error!("webauthn login complete failed: {:?}", e);
-            (StatusCode::BAD_REQUEST, "failed").into_response()
+            error!("Database error: {}", e);
+            return ErrorResponse::internal_error(ApiError::internal_error()).into_response();
+        }
+    };
+
+    match AuthService::new(state)
+        .send_action_link(ActionPurpose::VerifyEmail, &user.user_id, &email, serde_json::Value::Null)
+        .await
+    {
+        Ok(()) => (StatusCode::OK, "verification link sent").into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+fn notification_error(e: NotificationError) -> ErrorResponse {
+    match e {
+        NotificationError::Db(e) => {
+            error!("Database error: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
         }
+        e => ErrorResponse::bad_request(ApiError::validation_error(e.to_string())),
     }
 }
+
+async fn get_notifications(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let preferences = notifications::preferences(&state.db, &user.user_id).map_err(|e| notification_error(e.into()))?;
+    Ok(Json(preference_center(&state.db, &user.user_id, preferences)?))
+}
+
+fn preference_center(
+    db: &Database,
+    user_id: &str,
+    preferences: Vec<notifications::Preference>,
+) -> Result<PreferenceCenter, ErrorResponse> {
+    let db_error = |e: rusqlite::Error| notification_error(e.into());
+    Ok(PreferenceCenter {
+        phone: notifications::phone(db, user_id).map_err(db_error)?,
+        pending_phone: notifications::pending_phone(db, user_id).map_err(db_error)?,
+        preferences,
+    })
+}
+
+#[derive(Deserialize)]
+struct UpdateNotificationsBody {
+    /// E.164 number for SMS delivery, used once confirmed with the code
+    /// texted to it; an empty string removes the number
+    #[serde(default)]
+    phone: Option<String>,
+    /// Category name to partial update
+    #[serde(default)]
+    preferences: HashMap<String, PreferencePatch>,
+}
+
+async fn update_notifications(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(body): Json<UpdateNotificationsBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let db = &state.db;
+    match body.phone.as_deref().map(str::trim) {
+        Some("") => notifications::set_phone(db, &user.user_id, None).map_err(notification_error)?,
+        Some(phone) => {
+            let code = notifications::request_phone(db, &user.user_id, phone, state.sms.is_configured())
+                .map_err(notification_error)?;
+            state
+                .sms
+                .send_background(phone.to_string(), format!("Your verification code is {}", code));
+        }
+        None => {}
+    }
+    let preferences = notifications::update(db, &user.user_id, &body.preferences, state.sms.is_configured())
+        .map_err(notification_error)?;
+    Ok(Json(preference_center(db, &user.user_id, preferences)?))
+}
+
+#[derive(Deserialize)]
+struct ConfirmPhoneBody {
+    code: String,
+}
+
+/// Confirm the pending phone number with the code texted to it
+async fn confirm_phone(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(body): Json<ConfirmPhoneBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let db = &state.db;
+    notifications::confirm_phone(db, &user.user_id, &body.code).map_err(notification_error)?;
+    let preferences = notifications::preferences(db, &user.user_id).map_err(|e| notification_error(e.into()))?;
+    Ok(Json(preference_center(db, &user.user_id, preferences)?))
+}
+
+#[derive(Deserialize)]
+struct CompleteProfileBody {
+    fields: HashMap<String, String>,
+}
+
+/// The signed-in user
+#[derive(serde::Serialize)]
+struct Me {
+    /// The access token's `sub` (public or pairwise per application)
+    id: String,
+    email: String,
+    totp_enabled: bool,
+    passkeys: Vec<webauthn::CredentialInfo>,
+}
+
+/// Who the caller's access token belongs to
+async fn me(State(state): State<AppState>, user: AuthUser) -> Result<impl IntoResponse, ErrorResponse> {
+    let db_error = |e: rusqlite::Error| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    };
+    let account = state
+        .db
+        .users()
+        .find_by_id(&user.user_id)
+        .map_err(db_error)?
+        .ok_or_else(|| ErrorResponse::unauthorized(ApiError::invalid_token()))?;
+    let passkeys = webauthn::list_credentials(&state.db, &user.user_id).map_err(db_error)?;
+    Ok(Json(Me {
+        id: user.claims.sub,
+        email: account.email,
+        totp_enabled: account.totp_secret.is_some(),
+        passkeys,
+    }))
+}
+
+/// One of the caller's signed-in sessions
+#[derive(serde::Serialize)]
+struct MySession {
+    session_id: Option<String>,
+    created_at: i64,
+    expires_at: i64,
+    /// e.g. `Chrome 126 on Windows`
+    device: String,
+    agent: Option<UserAgent>,
+}
+
+/// The caller's active sessions, newest first
+async fn list_my_sessions(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let sessions = state.tokens().list_sessions(&user.user_id).map_err(|e| {
+        error!("Failed to list sessions: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+    let sessions: Vec<MySession> = sessions
+        .into_iter()
+        .map(|s| MySession {
+            session_id: s.session_id,
+            created_at: s.created_at,
+            expires_at: s.expires_at,
+            device: s.agent.as_ref().map_or_else(|| "Unknown device".to_string(), UserAgent::display),
+            agent: s.agent,
+        })
+        .collect();
+    Ok(Json(sessions))
+}
+
+/// The caller's passkeys with when, how often and from where each was last used
+async fn list_my_credentials(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let credentials = webauthn::list_credentials(&state.db, &user.user_id).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+    Ok(Json(credentials))
+}
+
+/// Collect required profile fields. Once none are missing, `/token/refresh`
+/// returns a full access token.
+async fn complete_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ProfileUser(user): ProfileUser,
+    Json(body): Json<CompleteProfileBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let app = applications::client_id(&headers).and_then(|id| state.application(id));
+    // the fields are those of the application the token was issued to, not of
+    // whichever one the header names
+    if app.as_ref().is_some_and(|app| !app.issued(&user.claims.aud)) {
+        return Err(ErrorResponse::forbidden(ApiError::forbidden("token was not issued to this application")));
+    }
+    let required = app.as_ref().map(|app| app.required_profile_fields.as_slice()).unwrap_or_default();
+    let db = &state.db;
+    let db_error = |e: rusqlite::Error| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    };
+    profile::save(db, &user.user_id, &body.fields, required).map_err(|e| match e {
+        ProfileError::Db(e) => db_error(e),
+        e => ErrorResponse::bad_request(ApiError::validation_error(e.to_string())),
+    })?;
+    let missing_fields = profile::missing_fields(db, &user.user_id, required).map_err(db_error)?;
+    Ok(Json(serde_json::json!({
+        "complete": missing_fields.is_empty(),
+        "missing_fields": missing_fields,
+    })))
+}
+
+fn consent_error(e: ConsentError) -> ErrorResponse {
+    match e {
+        ConsentError::Db(e) => {
+            error!("Database error: {}", e);
+            ErrorResponse::internal_error(ApiError::internal_error())
+        }
+        e => ErrorResponse::bad_request(ApiError::validation_error(e.to_string())),
+    }
+}
+
+/// Applications the caller shares profile data with, and which
+async fn list_my_consents(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let mut applications = state.cfg.applications.clone();
+    applications.extend(client_registration::approved(&state.db).map_err(|e| consent_error(e.into()))?);
+    let consents =
+        consents::list(&state.db, &user.user_id, &applications).map_err(|e| consent_error(e.into()))?;
+    Ok(Json(consents))
+}
+
+#[derive(Deserialize)]
+struct GrantConsentBody {
+    client_id: String,
+    scopes: Vec<ConsentScope>,
+}
+
+/// Approve scopes the application requests; returns the caller's consents
+async fn grant_consent(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(body): Json<GrantConsentBody>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let app = state.application(&body.client_id).ok_or_else(|| {
+        ErrorResponse::bad_request(ApiError::validation_error(format!("unknown application {:?}", body.client_id)))
+    })?;
+    consents::grant(&state.db, &user.user_id, &app, &body.scopes).map_err(consent_error)?;
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::ConsentChanged,
+        Some(&user.user_id),
+        None,
+        None,
+        None,
+        Some(&serde_json::json!({ "client_id": app.client_id, "granted": body.scopes }).to_string()),
+        true,
+    );
+    list_my_consents(State(state), user).await
+}
+
+#[derive(Deserialize)]
+struct RevokeConsentQuery {
+    /// Revoke only this scope instead of everything granted to the application
+    #[serde(default)]
+    scope: Option<ConsentScope>,
+}
+
+/// Withdraw consent; the application stops receiving the claims at once
+async fn revoke_consent(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(client_id): Path<String>,
+    Query(q): Query<RevokeConsentQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let revoked = consents::revoke(&state.db, &user.user_id, &client_id, q.scope)
+        .map_err(|e| consent_error(e.into()))?;
+    if revoked.is_empty() {
+        return Err(ErrorResponse::not_found(ApiError::not_found("No such consent")));
+    }
+    state.audit.log(
+        &state.db,
+        crate::audit::AuditEventType::ConsentChanged,
+        Some(&user.user_id),
+        None,
+        None,
+        None,
+        Some(&serde_json::json!({ "client_id": client_id, "revoked": revoked }).to_string()),
+        true,
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Claims about the caller for the application named by `X-Client-Id`,
+/// limited to the scopes the user consented to
+async fn userinfo(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    user: AuthUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let app = applications::client_id(&headers)
+        .and_then(|id| state.application(id))
+        .ok_or_else(|| {
+            ErrorResponse::bad_request(ApiError::validation_error("X-Client-Id must name a registered application"))
+        })?;
+    // a token issued to another application cannot read this one's claims
+    if !app.issued(&user.claims.aud) {
+        return Err(ErrorResponse::forbidden(ApiError::forbidden("token was not issued to this application")));
+    }
+    let claims = consents::userinfo(&state.db, &user.user_id, &app).map_err(|e| consent_error(e.into()))?;
+    Ok(Json(claims))
+}
+
+#[derive(Deserialize)]
+struct RevocationsQuery {
+    #[serde(default)]
+    since: i64,
+}
+
+/// Revocation log for peer regions (`[sessions] mode = "eventual"`), after
+/// the `since` cursor. Hidden unless a `sync_token` is configured.
+async fn list_revocations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<RevocationsQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let sessions = &state.cfg.sessions;
+    let Some(expected) = sessions.sync_token.as_deref().filter(|_| sessions.is_eventual()) else {
+        return Err(ErrorResponse::not_found(ApiError::not_found("Not found")));
+    };
+    let presented = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !crate::crypto::constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+        return Err(ErrorResponse::unauthorized(ApiError::invalid_token()));
+    }
+    let entries = regions::since(&state.db, q.since, regions::SYNC_PAGE).map_err(|e| {
+        error!("Database error: {}", e);
+        ErrorResponse::internal_error(ApiError::internal_error())
+    })?;
+    Ok(Json(entries))
+}
+
+/// Public keys of access tokens (`GET /.well-known/jwks.json`), cacheable
+/// until the key set next changes
+pub async fn jwks(State(keys): State<Arc<jwt::KeyRing>>, headers: HeaderMap) -> Response {
+    let (jwks, max_age) = keys.jwks(Database::now_ts());
+    crate::key_publication::cacheable_json(&headers, &jwks, max_age)
+}
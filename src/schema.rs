@@ -0,0 +1,110 @@
+use crate::crypto::Sha256Hasher;
+use crate::db::{Database, MIGRATIONS};
+use rusqlite::Connection;
+use std::{collections::BTreeMap, fmt};
+use thiserror::Error;
+
+/// Table name to `(column, declared type)` pairs, sorted by table
+type Columns = BTreeMap<String, Vec<(String, String)>>;
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("migration {0} does not apply to an empty database: {1}")]
+    Reference(&'static str, rusqlite::Error),
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error(
+        "database schema {live} does not match the migrations ({expected}); missing: {}. \
+         Apply the pending migrations (see `--doctor`) before starting the server",
+        missing.join(", ")
+    )]
+    Mismatch {
+        missing: Vec<String>,
+        expected: Fingerprint,
+        live: Fingerprint,
+    },
+}
+
+/// Short hash over every table, column and declared type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint(String);
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn columns(conn: &Connection) -> Result<Columns, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT m.name, p.name, p.type FROM sqlite_master m, pragma_table_info(m.name) p
+         WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%' ORDER BY m.name, p.cid",
+    )?;
+    let mut tables = Columns::new();
+    let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?)))?;
+    for row in rows {
+        let (table, column, ty) = row?;
+        tables.entry(table).or_default().push((column, ty.to_ascii_uppercase()));
+    }
+    Ok(tables)
+}
+
+fn fingerprint(tables: &Columns) -> Fingerprint {
+    let mut hasher = Sha256Hasher::new();
+    for (table, columns) in tables {
+        for (column, ty) in columns {
+            hasher.update(format!("{}.{} {}\n", table, column, ty));
+        }
+    }
+    Fingerprint(data_encoding::HEXLOWER.encode(&hasher.finalize()[..8]))
+}
+
+/// The schema the migrations in [`MIGRATIONS`] produce on an empty database
+fn expected() -> Result<Columns, SchemaError> {
+    let reference = Connection::open_in_memory()?;
+    crate::db::register_functions(&reference)?;
+    for migration in MIGRATIONS {
+        reference
+            .execute_batch(migration.sql)
+            .map_err(|e| SchemaError::Reference(migration.name, e))?;
+    }
+    Ok(columns(&reference)?)
+}
+
+/// Every expected table and column missing from `live`, as `table` or `table.column`
+fn missing(expected: &Columns, live: &Columns) -> Vec<String> {
+    let mut missing = Vec::new();
+    for (table, columns) in expected {
+        match live.get(table) {
+            None => missing.push(table.clone()),
+            Some(present) => missing.extend(
+                columns
+                    .iter()
+                    .filter(|(c, _)| !present.iter().any(|(p, _)| p == c))
+                    .map(|(c, _)| format!("{}.{}", table, c)),
+            ),
+        }
+    }
+    missing
+}
+
+/// Check the live database against the schema the migrations produce.
+///
+/// Extra tables and columns (audit partitions, operator additions) are
+/// tolerated; anything the code expects but the database lacks is an error,
+/// so a forgotten migration stops startup instead of surfacing as
+/// `no such column` errors in handlers.
+pub fn verify(db: &Database) -> Result<Fingerprint, SchemaError> {
+    let expected = expected()?;
+    let live = columns(&db.conn())?;
+    let missing = missing(&expected, &live);
+    if missing.is_empty() {
+        Ok(fingerprint(&live))
+    } else {
+        Err(SchemaError::Mismatch {
+            missing,
+            expected: fingerprint(&expected),
+            live: fingerprint(&live),
+        })
+    }
+}
@@ -0,0 +1,241 @@
+//! Last-line guard against credentials leaking into logs and error bodies.
+//!
+//! Values shaped like JWTs, UUID tokens (magic links, sessions), base32 TOTP
+//! secrets or our own `pak_` / `whsec_` / `pcs_` keys are replaced with a short
+//! fingerprint, e.g. `[jwt:3f9a1c2e]`, in every log line (through
+//! [`ScrubbedStdout`]) and in the `message` and `details` of every
+//! [`ApiError`](crate::error::ApiError). The fingerprint is the first bytes
+//! of the value's SHA-256, so a known value can still be matched against a
+//! log line. `[secret_scanning] extra_patterns` adds deployment-specific
+//! regexes; embedders can install a scanner with their own detectors.
+
+use crate::{
+    config::{Config, EmailDelivery},
+    crypto,
+};
+use data_encoding::HEXLOWER;
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    sync::{Arc, OnceLock, RwLock},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// `[secret_scanning]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecretScanConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Fingerprint UUIDs given as a token or secret (`token=...`,
+    /// `"refresh_token": "..."`); bare UUIDs are ids and are kept
+    #[serde(default = "default_enabled")]
+    pub uuids: bool,
+    /// Additional regexes, reported as `custom`
+    #[serde(default)]
+    pub extra_patterns: Vec<String>,
+}
+
+impl Default for SecretScanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            uuids: default_enabled(),
+            extra_patterns: Vec::new(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A kind of secret and how to find it
+pub struct Detector {
+    /// Label in the replacement, e.g. `jwt`
+    pub name: &'static str,
+    pub pattern: Regex,
+    /// Rejects pattern matches that are not secrets
+    pub accept: fn(&str) -> bool,
+}
+
+/// Capture group holding the secret when a pattern also matches context
+const SECRET_GROUP: &str = "secret";
+
+/// A UUID given as a token or secret; bare UUIDs are user and request ids
+const TOKEN_UUID: &str = concat!(
+    r#"(?i)(?:token|secret)["']?\s*[:=]\s*["']?"#,
+    r"(?P<secret>[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12})\b",
+);
+
+impl Detector {
+    pub fn new(name: &'static str, pattern: Regex) -> Self {
+        Self {
+            name,
+            pattern,
+            accept: |_| true,
+        }
+    }
+}
+
+/// Random base32 has digits; long all-letter words are not secrets
+fn looks_random(value: &str) -> bool {
+    value.bytes().any(|b| b.is_ascii_digit()) && value.bytes().any(|b| b.is_ascii_alphabetic())
+}
+
+/// Replaces detected secrets with fingerprints
+pub struct SecretScanner {
+    detectors: Vec<Detector>,
+}
+
+impl SecretScanner {
+    pub fn new(cfg: &SecretScanConfig) -> Result<Self, regex::Error> {
+        if !cfg.enabled {
+            return Ok(Self { detectors: Vec::new() });
+        }
+        let mut detectors = vec![
+            Detector::new(
+                "jwt",
+                Regex::new(r"eyJ[A-Za-z0-9_-]{5,}\.[A-Za-z0-9_-]{5,}\.[A-Za-z0-9_-]*")?,
+            ),
+            Detector::new("key", Regex::new(r"\b(?:pak|whsec|pcs)_[A-Za-z0-9_-]{16,}")?),
+            Detector {
+                name: "base32",
+                pattern: Regex::new(r"\b[A-Z2-7]{16,}={0,6}")?,
+                accept: looks_random,
+            },
+        ];
+        if cfg.uuids {
+            detectors.push(Detector::new("uuid", Regex::new(TOKEN_UUID)?));
+        }
+        for pattern in &cfg.extra_patterns {
+            detectors.push(Detector::new("custom", Regex::new(pattern)?));
+        }
+        Ok(Self { detectors })
+    }
+
+    /// The scanner for a deployment. With `email_delivery = "log"` sign-in
+    /// links are written to the log on purpose, so their tokens are kept.
+    pub fn for_config(cfg: &Config) -> Result<Self, regex::Error> {
+        let mut scanning = cfg.secret_scanning.clone();
+        scanning.uuids &= cfg.email_delivery != EmailDelivery::Log;
+        Self::new(&scanning)
+    }
+
+    /// Add a detector; earlier detectors win where matches overlap. A
+    /// pattern with a `secret` group only replaces that group.
+    pub fn with_detector(mut self, detector: Detector) -> Self {
+        self.detectors.push(detector);
+        self
+    }
+
+    pub fn scrub<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for detector in &self.detectors {
+            if !detector.pattern.is_match(&text) {
+                continue;
+            }
+            let replaced = detector.pattern.replace_all(&text, |caps: &regex::Captures| {
+                let whole = caps.get(0).expect("group 0 always matches");
+                let secret = caps.name(SECRET_GROUP).unwrap_or(whole);
+                if !(detector.accept)(secret.as_str()) {
+                    return whole.as_str().to_string();
+                }
+                let context = &whole.as_str()[..secret.start() - whole.start()];
+                format!("{}[{}:{}]", context, detector.name, fingerprint(secret.as_str()))
+            });
+            match replaced {
+                Cow::Owned(replaced) if replaced != *text => text = Cow::Owned(replaced),
+                _ => {}
+            }
+        }
+        text
+    }
+}
+
+/// First bytes of the value's SHA-256, hex encoded
+pub fn fingerprint(value: &str) -> String {
+    HEXLOWER.encode(&crypto::sha256(value.as_bytes())[..4])
+}
+
+fn global() -> &'static RwLock<Arc<SecretScanner>> {
+    static SCANNER: OnceLock<RwLock<Arc<SecretScanner>>> = OnceLock::new();
+    SCANNER.get_or_init(|| {
+        let scanner = SecretScanner::new(&SecretScanConfig::default()).expect("built-in patterns compile");
+        RwLock::new(Arc::new(scanner))
+    })
+}
+
+/// Replace the process-wide scanner (the defaults apply until then)
+pub fn install(scanner: SecretScanner) {
+    *global().write().unwrap_or_else(|e| e.into_inner()) = Arc::new(scanner);
+}
+
+/// Scrub `text` with the process-wide scanner
+pub fn scrub(text: &str) -> Cow<'_, str> {
+    let scanner = global().read().unwrap_or_else(|e| e.into_inner()).clone();
+    scanner.scrub(text)
+}
+
+/// Writer that scrubs each formatted log record before passing it on
+pub struct Scrubbed<W>(W);
+
+impl<W: Write> Write for Scrubbed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.0.write_all(scrub(text).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// `tracing_subscriber` writer for stdout with secrets scrubbed
+pub struct ScrubbedStdout;
+
+impl<'a> MakeWriter<'a> for ScrubbedStdout {
+    type Writer = Scrubbed<io::Stdout>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Scrubbed(io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_become_fingerprints() {
+        let scanner = SecretScanner::new(&SecretScanConfig::default()).unwrap();
+        let jwt = crate::jwt::create_token("user-1", "supersecret1234567890", 60, "access").unwrap();
+        let scrubbed = scanner.scrub(&format!("verify failed for {}", jwt)).into_owned();
+        assert_eq!(scrubbed, format!("verify failed for [jwt:{}]", fingerprint(&jwt)));
+
+        let totp = crate::totp::generate_secret();
+        assert!(scanner.scrub(&format!("secret={}", totp)).starts_with("secret=[base32:"));
+        let link = "6f1c2a4e-9b3d-4c7a-8e21-0d5f6a7b8c9d";
+        assert_eq!(
+            scanner.scrub(&format!("/verify/magic?token={}", link)),
+            format!("/verify/magic?token=[uuid:{}]", fingerprint(link))
+        );
+        assert!(matches!(scanner.scrub(&format!("user {} signed in", link)), Cow::Borrowed(_)));
+        assert!(scanner.scrub("key pak_AbCdEfGhIjKlMnOpQrStUv").contains("[key:"));
+
+        // error codes and ordinary text are left alone
+        let plain = "FACTOR_CONFIRMATION_REQUIRED: AUTHENTICATIONFAILED for user";
+        assert!(matches!(scanner.scrub(plain), Cow::Borrowed(_)));
+
+        let off = SecretScanner::new(&SecretScanConfig {
+            enabled: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(off.scrub(&jwt), jwt);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{db::Database, device::ClientHints, notifications::Category, user_agent::UserAgent};
+use axum::http::HeaderMap;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+/// `[security_notices]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecurityNoticeConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Header carrying the client's ISO 3166 country code, set by the edge
+    /// proxy or CDN (e.g. `CF-IPCountry`); unset disables new-country notices
+    #[serde(default)]
+    pub country_header: Option<String>,
+    /// Lifetime of the one-click revoke/freeze links in notices
+    #[serde(default = "default_link_ttl_seconds")]
+    pub link_ttl_seconds: i64,
+}
+
+impl Default for SecurityNoticeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            country_header: None,
+            link_ttl_seconds: default_link_ttl_seconds(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_link_ttl_seconds() -> i64 {
+    259200
+}
+
+/// Security-relevant account changes users are emailed about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoticeKind {
+    /// Sign-in from a device the user has not used before
+    NewDevice,
+    /// Sign-in from a country the user has not signed in from before
+    NewCountry,
+    PasskeyAdded,
+    TotpDisabled,
+    RecoveryCodesRegenerated,
+}
+
+impl NoticeKind {
+    pub const ALL: [NoticeKind; 5] = [
+        Self::NewDevice,
+        Self::NewCountry,
+        Self::PasskeyAdded,
+        Self::TotpDisabled,
+        Self::RecoveryCodesRegenerated,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NewDevice => "new_device",
+            Self::NewCountry => "new_country",
+            Self::PasskeyAdded => "passkey_added",
+            Self::TotpDisabled => "totp_disabled",
+            Self::RecoveryCodesRegenerated => "recovery_codes_regenerated",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == s)
+    }
+
+    /// Preference center category the notice is delivered under
+    pub fn category(&self) -> Category {
+        match self {
+            Self::NewDevice | Self::NewCountry => Category::NewSignIn,
+            Self::PasskeyAdded | Self::TotpDisabled | Self::RecoveryCodesRegenerated => Category::Security,
+        }
+    }
+}
+
+/// One notice to deliver
+#[derive(Debug, Clone)]
+pub struct SecurityNotice {
+    pub kind: NoticeKind,
+    /// Session the change happened in; gets a one-click revoke link
+    pub session_id: Option<String>,
+    /// Human-readable context (device summary, country code)
+    pub detail: Option<String>,
+    /// Browser and OS the change was made from, e.g. `Chrome 126 on Windows`
+    pub device: Option<String>,
+}
+
+impl SecurityNotice {
+    pub fn new(kind: NoticeKind) -> Self {
+        Self {
+            kind,
+            session_id: None,
+            detail: None,
+            device: None,
+        }
+    }
+
+    pub fn session(mut self, session_id: &str) -> Self {
+        self.session_id = Some(session_id.to_string());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn device(mut self, device: impl Into<String>) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+}
+
+/// Client country from the configured edge header, if it is a real ISO code
+pub fn country(cfg: &SecurityNoticeConfig, headers: &HeaderMap) -> Option<String> {
+    let name = cfg.country_header.as_deref()?;
+    let code = headers.get(name)?.to_str().ok()?.trim().to_ascii_uppercase();
+    // `XX` (unknown) and `T1` (Tor) are placeholders used by CDNs
+    let valid = code.len() == 2 && code.chars().all(|c| c.is_ascii_uppercase()) && code != "XX";
+    valid.then_some(code)
+}
+
+/// Short description of a device for notice emails: the parsed browser and
+/// OS when the request had a recognisable `User-Agent`, else the UA-CH platform
+pub fn device_summary(hints: &ClientHints, agent: Option<&UserAgent>) -> String {
+    let kind = match hints.mobile {
+        Some(true) => "mobile device",
+        _ => "device",
+    };
+    let mut summary = match (agent, hints.platform.as_deref()) {
+        (Some(agent), _) => agent.display(),
+        (None, Some(platform)) => format!("{} {}", platform, kind),
+        (None, None) => format!("unknown {}", kind),
+    };
+    if let Some(tz) = &hints.timezone {
+        summary.push_str(&format!(" ({})", tz));
+    }
+    summary
+}
+
+/// Remember a sign-in country. Returns true when it is new for a user who
+/// has signed in from somewhere else before (a first sign-in is not notable).
+pub fn record_country(conn: &Connection, user_id: &str, country: &str) -> Result<bool, rusqlite::Error> {
+    let now = Database::now_ts();
+    let known: Option<i64> = conn
+        .query_row(
+            "SELECT first_seen_at FROM user_countries WHERE user_id = ?1 AND country = ?2",
+            params![user_id, country],
+            |r| r.get(0),
+        )
+        .optional()?;
+    let others: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM user_countries WHERE user_id = ?1 AND country != ?2",
+        params![user_id, country],
+        |r| r.get(0),
+    )?;
+    conn.execute(
+        "INSERT INTO user_countries (user_id, country, first_seen_at, last_seen_at) VALUES (?1, ?2, ?3, ?3)
+         ON CONFLICT(user_id, country) DO UPDATE SET last_seen_at = excluded.last_seen_at",
+        params![user_id, country, now],
+    )?;
+    Ok(known.is_none() && others > 0)
+}
@@ -0,0 +1,130 @@
+use crate::{audit::AuditEventType, crypto, db::{Database, DbError}, totp, user_agent};
+use chrono::{Duration, Utc};
+use rand::{seq::SliceRandom, Rng};
+use rusqlite::params;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Events sampled when generating fake audit history
+const HISTORY_EVENTS: &[(AuditEventType, bool)] = &[
+    (AuditEventType::MagicLinkRequested, true),
+    (AuditEventType::MagicLinkVerified, true),
+    (AuditEventType::MagicLinkFailed, false),
+    (AuditEventType::TotpVerified, true),
+    (AuditEventType::TotpFailed, false),
+    (AuditEventType::WebauthnLoginCompleted, true),
+    (AuditEventType::WebauthnLoginFailed, false),
+    (AuditEventType::TokenRefreshed, true),
+];
+
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Safari/605.1.15",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0",
+];
+
+/// Counts of generated demo rows
+#[derive(Debug, Default, Serialize)]
+pub struct SeedSummary {
+    pub users: usize,
+    pub totp_enrolled: usize,
+    pub webauthn_credentials: usize,
+    pub sessions: usize,
+    pub audit_events: usize,
+}
+
+/// Generate `count` demo users with a realistic mix of TOTP/WebAuthn
+/// enrollment, refresh sessions and audit history.
+pub fn seed(db: &Database, count: usize) -> Result<SeedSummary, DbError> {
+    let mut rng = rand::thread_rng();
+    let mut summary = SeedSummary::default();
+    let now = Utc::now();
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+
+    for _ in 0..count {
+        let user_id = Uuid::new_v4().to_string();
+        let email = format!("demo+{}@example.com", &user_id[..8]);
+        let created = now - Duration::days(rng.gen_range(0..365));
+        let totp_secret = if rng.gen_bool(0.4) {
+            summary.totp_enrolled += 1;
+            Some(totp::generate_secret())
+        } else {
+            None
+        };
+        tx.execute(
+            "INSERT INTO users (id, email, totp_secret, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![user_id, email, totp_secret, created.timestamp()],
+        )?;
+        summary.users += 1;
+
+        if rng.gen_bool(0.3) {
+            for _ in 0..rng.gen_range(1..=2) {
+                let credential_id: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
+                let public_key: Vec<u8> = (0..77).map(|_| rng.gen()).collect();
+                tx.execute(
+                    "INSERT INTO webauthn_registrations (id, user_id, credential_id, public_key, sign_count, transports, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        Uuid::new_v4().to_string(),
+                        user_id,
+                        credential_id,
+                        public_key,
+                        rng.gen_range(0..500),
+                        "[\"internal\"]",
+                        created.timestamp()
+                    ],
+                )?;
+                summary.webauthn_credentials += 1;
+            }
+        }
+
+        for _ in 0..rng.gen_range(0..=3) {
+            let issued = now - Duration::hours(rng.gen_range(0..24 * 14));
+            let agent = user_agent::parse(USER_AGENTS.choose(&mut rng).unwrap());
+            tx.execute(
+                &format!(
+                    "INSERT INTO refresh_tokens (token, user_id, expires_at, revoked, created_at, session_id, {}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    user_agent::COLUMNS
+                ),
+                params![
+                    crypto::token_digest(&Uuid::new_v4().to_string()),
+                    user_id,
+                    (issued + Duration::days(7)).timestamp(),
+                    rng.gen_bool(0.1),
+                    issued.timestamp(),
+                    Uuid::new_v4().to_string(),
+                    agent.browser,
+                    agent.browser_version,
+                    agent.os,
+                    agent.os_version,
+                    agent.device_type.map(|d| d.as_str())
+                ],
+            )?;
+            summary.sessions += 1;
+        }
+
+        for _ in 0..rng.gen_range(3..=10) {
+            let (event, success) = HISTORY_EVENTS.choose(&mut rng).unwrap();
+            let at = now - Duration::minutes(rng.gen_range(0..60 * 24 * 30));
+            tx.execute(
+                "INSERT INTO audit_logs (event_type, user_id, email, ip_address, user_agent, metadata, success, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    event.as_str(),
+                    user_id,
+                    email,
+                    format!("203.0.113.{}", rng.gen_range(1..255)),
+                    USER_AGENTS.choose(&mut rng).unwrap(),
+                    Option::<String>::None,
+                    success,
+                    at.to_rfc3339()
+                ],
+            )?;
+            summary.audit_events += 1;
+        }
+    }
+
+    tx.commit()?;
+    Ok(summary)
+}
@@ -0,0 +1,1807 @@
+use crate::{
+    abuse_reports,
+    action_links::{ActionLink, ActionLinkError, ActionPurpose},
+    address::EmailAddress,
+    applications::ApplicationConfig,
+    attempt_token::{self, AttemptClaims},
+    auth_context::{self, AuthContext},
+    canaries, consents,
+    deliverability::{self, Enforcement, Verdict},
+    device::{self, ClientHints},
+    email_quota,
+    email_templates::{EmailTemplates, RenderedEmail},
+    geo_policy::{self, Action},
+    ip_bans,
+    issuance_hook::{IssuanceRequest, Outcome},
+    jwt,
+    audit::AuditEventType,
+    latency::{self, Stage},
+    link_urls::{self, LinkKind},
+    magic_link::{LinkBinding, LinkProof, MagicLinkError},
+    metrics::MetricsRecorder,
+    models::MagicLink,
+    notifications::{self, Category, Delivery},
+    outbox::{Outbox, OutboxEvent},
+    passkey_nudge::{self, PasskeyNudge},
+    profile,
+    redirects,
+    regions::{self, SessionAssertion},
+    revocation::{self, RevocationEvent},
+    security_notices::{self, NoticeKind, SecurityNotice},
+    routes::AppState,
+    session::{NewSession, Session},
+    stale_accounts,
+    storage::{StorageError, TokenStore},
+    subjects, suppression, totp,
+    user_agent::UserAgent,
+    webhooks::WebhookEventType,
+};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, error, warn};
+use webauthn_rs_core::proto::{CreationChallengeResponse, RequestChallengeResponse};
+
+/// Framework-agnostic authentication service.
+///
+/// Holds the business logic behind every auth route so that the axum router,
+/// the tower facade and the actix adapter all share one implementation.
+#[derive(Clone)]
+pub struct AuthService {
+    state: AppState,
+    client_id: Option<String>,
+    device: Option<ClientHints>,
+    platform_authenticator: bool,
+    country: Option<String>,
+    asn: Option<u32>,
+    user_agent: Option<UserAgent>,
+    ip: Option<String>,
+}
+
+/// Errors surfaced by [`AuthService`] operations
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("internal error: {0}")]
+    Internal(String),
+    #[error("email failed")]
+    EmailFailed,
+    #[error("link already used")]
+    MagicLinkUsed,
+    #[error("invalid or expired")]
+    MagicLinkInvalid,
+    #[error("invalid totp")]
+    InvalidTotp,
+    #[error("totp not enrolled")]
+    TotpNotEnrolled,
+    #[error("totp already enrolled")]
+    TotpAlreadyEnrolled,
+    #[error("user not found")]
+    UserNotFound,
+    #[error("invalid token kind")]
+    InvalidTokenKind,
+    #[error("invalid refresh")]
+    InvalidRefresh,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("webauthn failed")]
+    WebauthnFailed,
+    #[error("action link already used")]
+    ActionLinkUsed,
+    #[error("invalid or expired action link")]
+    ActionLinkInvalid,
+    #[error("email already in use")]
+    EmailInUse,
+    #[error("invalid totp, retry with attempt token")]
+    InvalidTotpRetry {
+        attempt_token: String,
+        remaining_attempts: u32,
+    },
+    #[error("attempt token required")]
+    AttemptTokenRequired,
+    #[error("too many attempts")]
+    TooManyAttempts,
+    #[error("current factor confirmation required")]
+    FactorConfirmationRequired,
+    #[error("redirect uri not allowed")]
+    RedirectNotAllowed,
+    #[error("account frozen")]
+    AccountFrozen,
+    #[error("recipient domain {0} cannot receive email")]
+    UndeliverableDomain(String),
+    #[error("invalid email address: {0}")]
+    InvalidEmail(#[from] crate::address::AddressError),
+    #[error("email address needs SMTPUTF8, which the mail server does not support")]
+    Smtputf8Unsupported,
+    #[error("invalid link binding: {0}")]
+    InvalidLinkBinding(&'static str),
+    #[error("link is bound to another client")]
+    LinkBindingMismatch,
+    #[error("token issuance denied: {0}")]
+    IssuanceDenied(String),
+    #[error("recipient is on the suppression list")]
+    EmailSuppressed,
+    #[error("login blocked by geo policy ({0})")]
+    GeoBlocked(String),
+    #[error("second factor required by geo policy ({0})")]
+    StepUpRequired(String),
+    #[error("email link base url: {0}")]
+    LinkUrlNotRegistered(String),
+    #[error("read-only mode")]
+    ReadOnly {
+        /// Seconds to wait before retrying
+        retry_after: u64,
+    },
+    #[error("email quota exceeded until {reset_at}")]
+    EmailQuotaExceeded {
+        /// When the next email may be sent (unix seconds)
+        reset_at: i64,
+    },
+}
+
+impl From<MagicLinkError> for ServiceError {
+    fn from(e: MagicLinkError) -> Self {
+        match e {
+            MagicLinkError::Used => Self::MagicLinkUsed,
+            MagicLinkError::Invalid => Self::MagicLinkInvalid,
+            MagicLinkError::InvalidBinding(reason) => Self::InvalidLinkBinding(reason),
+            MagicLinkError::BindingMismatch => Self::LinkBindingMismatch,
+            e => internal(e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ServiceError {
+    fn from(e: rusqlite::Error) -> Self {
+        internal(e)
+    }
+}
+
+impl ServiceError {
+    /// HTTP status code adapters should respond with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Internal(_) | Self::EmailFailed | Self::LinkUrlNotRegistered(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidRefresh | Self::LinkBindingMismatch => StatusCode::UNAUTHORIZED,
+            Self::EmailInUse | Self::TotpAlreadyEnrolled => StatusCode::CONFLICT,
+            Self::AttemptTokenRequired => StatusCode::PRECONDITION_REQUIRED,
+            Self::ReadOnly { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::TooManyAttempts | Self::EmailQuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::AccountFrozen | Self::IssuanceDenied(_) | Self::GeoBlocked(_) | Self::StepUpRequired(_) => {
+                StatusCode::FORBIDDEN
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Stable machine-readable code, reported in the v2 error envelope
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Internal(_) => "INTERNAL_ERROR",
+            Self::EmailFailed => "EMAIL_FAILED",
+            Self::MagicLinkUsed => "MAGIC_LINK_USED",
+            Self::MagicLinkInvalid => "MAGIC_LINK_INVALID",
+            Self::InvalidTotp | Self::InvalidTotpRetry { .. } => "INVALID_TOTP",
+            Self::TotpNotEnrolled => "TOTP_NOT_ENROLLED",
+            Self::TotpAlreadyEnrolled => "TOTP_ALREADY_ENROLLED",
+            Self::UserNotFound => "USER_NOT_FOUND",
+            Self::InvalidTokenKind => "INVALID_TOKEN_KIND",
+            Self::InvalidRefresh => "INVALID_REFRESH",
+            Self::InvalidToken => "INVALID_TOKEN",
+            Self::WebauthnFailed => "WEBAUTHN_ERROR",
+            Self::ActionLinkUsed => "ACTION_LINK_USED",
+            Self::ActionLinkInvalid => "ACTION_LINK_INVALID",
+            Self::EmailInUse => "EMAIL_IN_USE",
+            Self::AttemptTokenRequired => "ATTEMPT_TOKEN_REQUIRED",
+            Self::TooManyAttempts => "TOO_MANY_ATTEMPTS",
+            Self::FactorConfirmationRequired => "FACTOR_CONFIRMATION_REQUIRED",
+            Self::RedirectNotAllowed => "REDIRECT_NOT_ALLOWED",
+            Self::AccountFrozen => "ACCOUNT_FROZEN",
+            Self::UndeliverableDomain(_) => "UNDELIVERABLE_DOMAIN",
+            Self::InvalidEmail(_) => "INVALID_EMAIL",
+            Self::Smtputf8Unsupported => "SMTPUTF8_UNSUPPORTED",
+            Self::InvalidLinkBinding(_) => "INVALID_LINK_BINDING",
+            Self::LinkBindingMismatch => "LINK_BINDING_MISMATCH",
+            Self::IssuanceDenied(_) => "ISSUANCE_DENIED",
+            Self::EmailSuppressed => "EMAIL_SUPPRESSED",
+            Self::GeoBlocked(_) => "GEO_BLOCKED",
+            Self::StepUpRequired(_) => "STEP_UP_REQUIRED",
+            Self::LinkUrlNotRegistered(_) => "LINK_URL_NOT_REGISTERED",
+            Self::EmailQuotaExceeded { .. } => "EMAIL_QUOTA_EXCEEDED",
+            Self::ReadOnly { .. } => "READ_ONLY",
+        }
+    }
+
+    /// Message safe to return to clients (internal details are never exposed)
+    pub fn public_message(&self) -> &'static str {
+        match self {
+            Self::Internal(_) => "error",
+            Self::EmailFailed => "email failed",
+            Self::MagicLinkUsed => "link already used",
+            Self::MagicLinkInvalid => "invalid or expired",
+            Self::InvalidTotp | Self::InvalidTotpRetry { .. } => "invalid totp",
+            Self::TotpNotEnrolled => "totp not enrolled",
+            Self::TotpAlreadyEnrolled => "totp already enrolled, use POST /totp/rotate to replace it",
+            Self::UserNotFound => "user not found",
+            Self::InvalidTokenKind => "invalid token kind",
+            Self::InvalidRefresh => "invalid refresh",
+            Self::InvalidToken => "invalid token",
+            Self::WebauthnFailed => "failed",
+            Self::ActionLinkUsed => "link already used",
+            Self::ActionLinkInvalid => "invalid or expired",
+            Self::EmailInUse => "email already in use",
+            Self::AttemptTokenRequired => "attempt token required",
+            Self::TooManyAttempts => "too many attempts",
+            Self::FactorConfirmationRequired => "totp code or passkey assertion required",
+            Self::RedirectNotAllowed => "redirect_uri is not registered for this application",
+            Self::AccountFrozen => "account frozen, contact support",
+            Self::UndeliverableDomain(_) => "recipient domain cannot receive email",
+            Self::InvalidEmail(_) => "invalid email address",
+            Self::Smtputf8Unsupported => "email addresses with non-ASCII characters before the @ are not supported by our mail server",
+            Self::InvalidLinkBinding(reason) => reason,
+            Self::LinkBindingMismatch => "code_verifier or state does not match the link request",
+            Self::IssuanceDenied(_) => "sign-in blocked, contact support",
+            Self::EmailSuppressed => "we cannot send email to this address, contact support",
+            Self::GeoBlocked(_) => "sign-in is not allowed from your location",
+            Self::StepUpRequired(_) => "sign in with your authenticator app or passkey from this location",
+            Self::LinkUrlNotRegistered(_) => "email links are not configured for this environment",
+            Self::EmailQuotaExceeded { .. } => "too many emails sent to this address, try again later",
+            Self::ReadOnly { .. } => "down for maintenance, existing sessions keep working",
+        }
+    }
+}
+
+/// Tokens returned by every successful login completion
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// When the access token expires (unix seconds)
+    pub expires_at: i64,
+    /// When the refresh token expires; sign in again before then
+    pub refresh_expires_at: i64,
+    /// Public session identifier relying apps can attach metadata to
+    pub session_id: String,
+    /// Passkey upgrade hint (magic-link logins only)
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub passkey_nudge: Option<PasskeyNudge>,
+    /// Registered post-login target requested with the magic link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_uri: Option<String>,
+    /// Required profile fields still missing; while non-empty the access
+    /// token only authorizes `POST /me/profile/complete`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_fields: Vec<String>,
+    /// Correlation id of the magic link login flow this completes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_id: Option<String>,
+}
+
+/// Remaining lifetime of the caller's tokens (`GET /token/status`)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenStatus {
+    pub expires_at: i64,
+    pub expires_in: i64,
+    /// Only known when the refresh token is presented and still valid
+    pub refresh_expires_at: Option<i64>,
+    pub refresh_expires_in: Option<i64>,
+    /// The refresh token is invalid or inside the `[session_expiry]` warning
+    /// window; prompt the user to sign in again while it is convenient
+    pub reauth_recommended: bool,
+}
+
+/// Token introspection response (RFC 7662, `POST /oauth/introspect`); an
+/// inactive token is reported as `{"active": false}` alone
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Introspection {
+    pub active: bool,
+    /// The user's subject as the introspecting application knows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    /// Space-separated scopes the user consented to share with the application
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// `access` or `refresh`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// Result of following a signed action link
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActionOutcome {
+    pub action: ActionPurpose,
+    pub status: String,
+}
+
+/// Proof of an existing factor, required before replacing the TOTP secret
+#[derive(Debug)]
+pub enum FactorProof {
+    /// Code from the current authenticator
+    Totp(String),
+    /// Assertion for a `/webauthn/login/options` challenge
+    Passkey {
+        pending_id: String,
+        response: serde_json::Value,
+    },
+}
+
+/// TOTP enrollment result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollResp {
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+impl AuthService {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            client_id: None,
+            device: None,
+            platform_authenticator: false,
+            country: None,
+            asn: None,
+            user_agent: None,
+            ip: None,
+        }
+    }
+
+    /// Scope the service to the calling application (used to resolve
+    /// per-application token settings)
+    pub fn for_client(mut self, client_id: Option<&str>) -> Self {
+        self.client_id = client_id.map(str::to_string);
+        self
+    }
+
+    /// Attach consented client hints; recorded against sessions created by this call
+    pub fn with_device(mut self, hints: Option<ClientHints>) -> Self {
+        self.device = hints;
+        self
+    }
+
+    /// Whether the client can register a platform passkey (enables upgrade prompts)
+    pub fn with_platform_authenticator(mut self, available: bool) -> Self {
+        self.platform_authenticator = available;
+        self
+    }
+
+    /// Client country (from the trusted edge header); new countries trigger a notice
+    pub fn with_country(mut self, country: Option<String>) -> Self {
+        self.country = country;
+        self
+    }
+
+    /// Client AS number (from the trusted edge header); checked against `[geo_policy]`
+    pub fn with_asn(mut self, asn: Option<u32>) -> Self {
+        self.asn = asn;
+        self
+    }
+
+    /// Parsed `User-Agent` of the caller; stored on sessions created by this call
+    pub fn with_user_agent(mut self, agent: Option<UserAgent>) -> Self {
+        self.user_agent = agent;
+        self
+    }
+
+    /// Client IP address; recorded as the last use of a passkey asserted by this call
+    pub fn with_ip(mut self, ip: Option<String>) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Refuse writes while read-only mode is on, whichever adapter the call
+    /// came through; refreshes, introspection and reads stay available
+    fn writable(&self) -> Result<(), ServiceError> {
+        let maintenance = &self.state.maintenance;
+        if !maintenance.is_read_only() {
+            return Ok(());
+        }
+        MetricsRecorder::record_read_only_rejection();
+        Err(ServiceError::ReadOnly {
+            retry_after: maintenance.retry_after_seconds(),
+        })
+    }
+
+    /// Issue an access token and a fresh refresh session for the user
+    pub fn issue_tokens(&self, user_id: &str) -> Result<AuthResponse, ServiceError> {
+        let (access_ttl, refresh_ttl) = self.token_lifetimes(user_id)?;
+        let session = self.create_session(user_id, refresh_ttl, &[])?;
+        self.sign_tokens(user_id, session, access_ttl, refresh_ttl)
+    }
+
+    /// Start a refresh session for a sign-in with the methods `amr`
+    fn create_session(&self, user_id: &str, refresh_ttl: i64, amr: &[&str]) -> Result<NewSession, ServiceError> {
+        let client_id = self.application_id();
+        self.tokens()
+            .create_refresh_token(user_id, refresh_ttl, client_id.as_deref(), amr, self.user_agent.as_ref())
+            .map_err(internal)
+    }
+
+    /// Where magic links and refresh tokens live (`store`)
+    fn tokens(&self) -> &dyn TokenStore {
+        self.state.tokens()
+    }
+
+    /// `client_id` of the calling application, if it is a registered one
+    fn application_id(&self) -> Option<String> {
+        let app = self.client_id.as_deref().and_then(|id| self.state.application(id));
+        app.map(|app| app.client_id.clone())
+    }
+
+    /// Issue tokens for a completed login. The new session and its audit and
+    /// webhook events are committed in one transaction via the outbox; an
+    /// external token store (`store = "redis"` / `"postgres"`) writes the
+    /// session outside it, so a login rolled back there is revoked again.
+    /// `flow_id` ties a magic link login to its request. The geo policy and
+    /// the pre-issuance hook run first and may deny the login.
+    async fn complete_login(
+        &self,
+        user_id: &str,
+        method: AuditEventType,
+        flow_id: Option<&str>,
+    ) -> Result<AuthResponse, ServiceError> {
+        let frozen = latency::time(Stage::DbLookup, || self.state.db.users().frozen_at(user_id)).map_err(internal)?;
+        if frozen.is_some() {
+            return Err(ServiceError::AccountFrozen);
+        }
+        self.check_geo_policy(user_id, &method, flow_id)?;
+        let hook = self.check_issuance(user_id, &method, flow_id).await?;
+        let (access_ttl, refresh_ttl) = self.token_lifetimes(user_id)?;
+        // the connection stays locked for the transaction only, not across the notification below
+        let mut created = None;
+        let committed = self.state.db.transaction(|tx| {
+            let session = self.create_session(user_id, refresh_ttl, auth_context::amr_for(&method))?;
+            created = Some(session.session_id.clone());
+            stale_accounts::record_login(tx, user_id, crate::db::Database::now_ts()).map_err(internal)?;
+            let device = match &self.device {
+                Some(hints) => Some(device::record(tx, user_id, &session.session_id, hints).map_err(internal)?),
+                None => None,
+            };
+            let new_country = match &self.country {
+                Some(country) => security_notices::record_country(tx, user_id, country).map_err(internal)?,
+                None => false,
+            };
+            // one email per sign-in; a user's very first device is not notable
+            let agent = self.user_agent.as_ref();
+            let notice = match (&self.country, &device) {
+                (Some(country), _) if new_country => {
+                    let notice = SecurityNotice::new(NoticeKind::NewCountry).detail(country);
+                    Some(match agent {
+                        Some(agent) => notice.device(agent.display()),
+                        None => notice,
+                    })
+                }
+                (_, Some(d)) if d.new_device => {
+                    let known_devices = self.state.db.users().device_count(user_id).map_err(internal)?;
+                    (known_devices > 1).then(|| {
+                        SecurityNotice::new(NoticeKind::NewDevice).detail(security_notices::device_summary(&d.hints, agent))
+                    })
+                }
+                _ => None,
+            }
+            .map(|n| n.session(&session.session_id));
+            let event = OutboxEvent::new(method)
+                .user(user_id)
+                .webhook(WebhookEventType::UserAuthenticated)
+                .metadata(serde_json::json!({
+                    "session_id": session.session_id,
+                    "client_id": self.client_id,
+                    "device": device,
+                    "flow_id": flow_id,
+                    "issuance_hook": hook.metadata(),
+                }));
+            Outbox::enqueue(tx, &event).map_err(internal)?;
+            Ok::<_, ServiceError>((session, notice))
+        });
+        let (session, notice) = match committed {
+            Ok(committed) => committed,
+            Err(e) => {
+                if let (Some(tokens), Some(session_id)) = (&self.state.tokens, created) {
+                    if let Err(revoke) = tokens.revoke_session(user_id, &session_id) {
+                        error!("Failed to revoke session {} of a failed login: {}", session_id, revoke);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        let mut resp = self.sign_tokens(user_id, session, access_ttl, refresh_ttl)?;
+        resp.flow_id = flow_id.map(str::to_string);
+        if let Some(notice) = notice {
+            self.notify(user_id, notice).await;
+        }
+        Ok(resp)
+    }
+
+    /// Apply the calling application's country/ASN rules to a login. A
+    /// step-up rule is satisfied by TOTP and passkey logins; every match is
+    /// audited.
+    fn check_geo_policy(
+        &self,
+        user_id: &str,
+        method: &AuditEventType,
+        flow_id: Option<&str>,
+    ) -> Result<(), ServiceError> {
+        let rules = self.state.cfg.geo_rules(self.client_id.as_deref());
+        let Some(decision) = geo_policy::evaluate(rules, self.country.as_deref(), self.asn) else {
+            return Ok(());
+        };
+        let second_factor = matches!(
+            method,
+            AuditEventType::TotpVerified | AuditEventType::WebauthnLoginCompleted
+        );
+        let (event, allowed) = match decision.action {
+            Action::Block => (AuditEventType::GeoPolicyBlocked, false),
+            Action::StepUp => (AuditEventType::GeoPolicyStepUp, second_factor),
+        };
+        MetricsRecorder::record_geo_policy(decision.action.as_str(), allowed);
+        self.state.audit.log(
+            &self.state.db,
+            event,
+            Some(user_id),
+            None,
+            self.ip.as_deref(),
+            None,
+            Some(
+                &serde_json::json!({
+                    "method": method.as_str(),
+                    "trigger": decision.trigger,
+                    "country": self.country,
+                    "asn": self.asn,
+                    "client_id": self.client_id,
+                    "flow_id": flow_id,
+                })
+                .to_string(),
+            ),
+            allowed,
+        );
+        match decision.action {
+            _ if allowed => Ok(()),
+            Action::Block => Err(ServiceError::GeoBlocked(decision.trigger)),
+            Action::StepUp => Err(ServiceError::StepUpRequired(decision.trigger)),
+        }
+    }
+
+    /// Ask the pre-issuance hook about a login; a denial is audited
+    async fn check_issuance(
+        &self,
+        user_id: &str,
+        method: &AuditEventType,
+        flow_id: Option<&str>,
+    ) -> Result<Outcome, ServiceError> {
+        let request = IssuanceRequest {
+            user_id: user_id.to_string(),
+            method: method.as_str(),
+            client_id: self.client_id.clone(),
+            country: self.country.clone(),
+            user_agent: self.user_agent.as_ref().map(UserAgent::display),
+            flow_id: flow_id.map(str::to_string),
+        };
+        let outcome = self.state.issuance.check(&request).await;
+        if let Outcome::Deny(reason) = &outcome {
+            self.state.audit.log(
+                &self.state.db,
+                AuditEventType::IssuanceDenied,
+                Some(user_id),
+                None,
+                None,
+                None,
+                Some(&serde_json::json!({ "method": method.as_str(), "reason": reason, "flow_id": flow_id }).to_string()),
+                false,
+            );
+            return Err(ServiceError::IssuanceDenied(reason.clone()));
+        }
+        Ok(outcome)
+    }
+
+    /// Email a security notice with one-click revoke/freeze links, unless
+    /// notices are off or the user opted out of this kind. Failures are logged.
+    pub async fn notify(&self, user_id: &str, notice: SecurityNotice) {
+        let cfg = &self.state.cfg;
+        if !cfg.security_notices.enabled {
+            return;
+        }
+        if let Err(e) = self.send_notice(user_id, &notice).await {
+            error!("security notice {} failed: {}", notice.kind.as_str(), e);
+        }
+    }
+
+    async fn send_notice(&self, user_id: &str, notice: &SecurityNotice) -> Result<(), ServiceError> {
+        let cfg = &self.state.cfg;
+        let db = &self.state.db;
+        let Some(delivery) = notifications::delivery(db, user_id, notice.kind.category()).map_err(internal)?
+        else {
+            return Ok(());
+        };
+        let email = db
+            .users()
+            .find_by_id(user_id)
+            .map_err(internal)?
+            .ok_or(ServiceError::UserNotFound)?
+            .email;
+        let ttl = cfg.security_notices.link_ttl_seconds;
+        let base_url = self.link_base_url(LinkKind::Action)?;
+        let link = |purpose, payload: serde_json::Value| -> Result<String, ServiceError> {
+            let token = ActionLink::issue(db, &cfg.jwt_secret, purpose, user_id, &payload, ttl).map_err(internal)?;
+            Ok(format!("{}/{}", base_url.trim_end_matches('/'), token))
+        };
+        let revoke = match &notice.session_id {
+            Some(session_id) => Some(link(
+                ActionPurpose::RevokeSession,
+                serde_json::json!({ "session_id": session_id }),
+            )?),
+            None => None,
+        };
+        let freeze = link(ActionPurpose::FreezeAccount, serde_json::Value::Null)?;
+        let rendered = EmailTemplates::security_notice(notice, &email, revoke.as_deref(), &freeze);
+        let sms = format!("{}. Not you? Freeze your account: {}", rendered.subject, freeze);
+        self.deliver(delivery, &email, rendered, sms).await
+    }
+
+    /// Send a rendered email, or `sms_text` when the user chose SMS for the category
+    async fn deliver(
+        &self,
+        delivery: Delivery,
+        email: &str,
+        rendered: RenderedEmail,
+        sms_text: String,
+    ) -> Result<(), ServiceError> {
+        match delivery {
+            Delivery::Sms { phone } if self.state.sms.is_configured() => {
+                self.state.sms.send_background(phone, sms_text);
+                Ok(())
+            }
+            _ => {
+                if self.suppressed(email)? {
+                    return Ok(());
+                }
+                let _timer = latency::start(Stage::EmailEnqueue);
+                self.state.emailer.send_rendered(email, rendered).await.map_err(internal)
+            }
+        }
+    }
+
+    /// Registered base URL for links emailed on behalf of the calling application
+    fn link_base_url(&self, kind: LinkKind) -> Result<&str, ServiceError> {
+        link_urls::resolve(&self.state.cfg, kind, self.client_id.as_deref()).map_err(|e| {
+            error!("refusing to email a link: {}", e);
+            ServiceError::LinkUrlNotRegistered(e.to_string())
+        })
+    }
+
+    /// Count an email to the user against `[email_quota]`, or refuse it
+    fn reserve_email(&self, user_id: &str) -> Result<(), ServiceError> {
+        let quota = &self.state.cfg.email_quota;
+        match email_quota::reserve(&self.state.db, quota, user_id, crate::db::Database::now_ts()).map_err(internal)? {
+            None => Ok(()),
+            Some(exceeded) => {
+                warn!(
+                    "email quota ({} per {}s) exhausted for user {} until {}",
+                    exceeded.limit.max, exceeded.limit.window_seconds, user_id, exceeded.reset_at
+                );
+                MetricsRecorder::record_email_quota_exceeded(exceeded.limit.window_seconds);
+                Err(ServiceError::EmailQuotaExceeded {
+                    reset_at: exceeded.reset_at,
+                })
+            }
+        }
+    }
+
+    /// Whether `email` is on the suppression list; callers skip or refuse the send
+    fn suppressed(&self, email: &str) -> Result<bool, ServiceError> {
+        let suppressed = suppression::is_suppressed(&self.state.db, email).map_err(internal)?;
+        if suppressed {
+            warn!("not emailing {}: address is suppressed", crate::address::display(email));
+        }
+        Ok(suppressed)
+    }
+
+    /// Whether `email` is a canary account. An attempt on one is audited as
+    /// a security event and, if the canary asks for it, bans the caller's
+    /// address; the caller answers as it would for an ordinary account.
+    fn canary(&self, email: &str, method: &str) -> Result<bool, ServiceError> {
+        let cfg = &self.state.cfg;
+        let db = &self.state.db;
+        if !cfg.canaries.enabled {
+            return Ok(false);
+        }
+        let Some(canary) = canaries::trip(db, email, crate::db::Database::now_ts()).map_err(internal)? else {
+            return Ok(false);
+        };
+        warn!(
+            "{} attempt on canary account {} from {}",
+            method,
+            crate::address::display(email),
+            self.ip.as_deref().unwrap_or("unknown address")
+        );
+        MetricsRecorder::record_canary_triggered(method);
+        let mut banned_until = None;
+        if let Some(ip) = self.ip.as_deref().filter(|_| canary.ban_ip) {
+            match ip_bans::ban(db, ip, &cfg.ip_bans, "canary account", "canary", cfg.canaries.ban_seconds) {
+                Ok(ban) => {
+                    MetricsRecorder::record_ip_ban("canary");
+                    let metadata = serde_json::json!({
+                        "source": "canary",
+                        "reason": "canary account",
+                        "strikes": ban.strikes,
+                        "expires_at": ban.expires_at,
+                    });
+                    self.state.audit.log(
+                        db,
+                        AuditEventType::IpBanned,
+                        None,
+                        None,
+                        Some(ip),
+                        None,
+                        Some(&metadata.to_string()),
+                        true,
+                    );
+                    banned_until = Some(ban.expires_at);
+                }
+                Err(e) => error!("failed to ban {} after a canary attempt: {}", ip, e),
+            }
+        }
+        let user_agent = self.user_agent.as_ref().map(UserAgent::display);
+        self.state.audit.log(
+            db,
+            AuditEventType::CanaryTriggered,
+            None,
+            Some(email),
+            self.ip.as_deref(),
+            user_agent.as_deref(),
+            Some(
+                &serde_json::json!({
+                    "method": method,
+                    "note": canary.note,
+                    "triggers": canary.triggers,
+                    "client_id": self.client_id,
+                    "country": self.country,
+                    "banned_until": banned_until,
+                })
+                .to_string(),
+            ),
+            false,
+        );
+        Ok(true)
+    }
+
+    fn role(&self, user_id: &str) -> Result<String, ServiceError> {
+        self.state
+            .db
+            .users()
+            .role(user_id)
+            .map_err(internal)?
+            .ok_or(ServiceError::UserNotFound)
+    }
+
+    /// Resolve `(access, refresh)` lifetimes for the user's role and the calling application
+    fn token_lifetimes(&self, user_id: &str) -> Result<(i64, i64), ServiceError> {
+        let role = self.role(user_id)?;
+        Ok(self.state.cfg.token_lifetimes(self.client_id.as_deref(), &role))
+    }
+
+    /// Signed `[auth_context]` header value for the caller's session, which
+    /// a gateway forwards to downstream services
+    pub fn auth_context(&self, user_id: &str, session_id: Option<&str>) -> Result<String, ServiceError> {
+        let cfg = &self.state.cfg;
+        // tokens minted before the `sid` claim carry no session
+        let session_id = session_id.ok_or(ServiceError::InvalidToken)?;
+        let amr = self
+            .tokens()
+            .session_amr(user_id, session_id)
+            .map_err(internal)?
+            .ok_or(ServiceError::InvalidToken)?;
+        let context = AuthContext {
+            uid: user_id.to_string(),
+            sid: session_id.to_string(),
+            scopes: cfg.auth_context.scopes_for(&self.role(user_id)?),
+            amr,
+            exp: crate::db::Database::now_ts() + cfg.auth_context.ttl_seconds,
+        };
+        Ok(auth_context::sign(&cfg.auth_context.key(&cfg.jwt_secret), &context))
+    }
+
+    fn sign_tokens(
+        &self,
+        user_id: &str,
+        session: NewSession,
+        access_ttl: i64,
+        refresh_ttl: i64,
+    ) -> Result<AuthResponse, ServiceError> {
+        let cfg = &self.state.cfg;
+        let minted = latency::start(Stage::TokenMint);
+        let refresh = if cfg.sessions.is_eventual() {
+            let now = crate::db::Database::now_ts();
+            let assertion = SessionAssertion {
+                sid: session.session_id.clone(),
+                uid: user_id.to_string(),
+                auth_time: now,
+                generation: 0,
+                region: cfg.sessions.region.clone(),
+                exp: now + refresh_ttl,
+                cid: self.application_id(),
+            };
+            regions::sign(&cfg.sessions.assertion_key(&cfg.jwt_secret), &assertion)
+        } else {
+            jwt::create_token(&session.token, &cfg.jwt_secret, refresh_ttl, "refresh").map_err(internal)?
+        };
+        let refresh_expires_at = crate::db::Database::now_ts() + refresh_ttl;
+        drop(minted);
+        self.sign_access(user_id, session.session_id, (refresh, refresh_expires_at), access_ttl)
+    }
+
+    /// Access token for the user, paired with an already issued refresh
+    /// credential and its expiry
+    fn sign_access(
+        &self,
+        user_id: &str,
+        session_id: String,
+        (refresh_token, refresh_expires_at): (String, i64),
+        access_ttl: i64,
+    ) -> Result<AuthResponse, ServiceError> {
+        let _minted = latency::start(Stage::TokenMint);
+        let cfg = &self.state.cfg;
+        let app = self.client_id.as_deref().and_then(|id| self.state.application(id));
+        let app = app.as_deref();
+        let subject = subjects::subject_for(&self.state.db, user_id, app).map_err(internal)?;
+        let required = app.map_or(&[][..], |app| app.required_profile_fields.as_slice());
+        let missing_fields = profile::missing_fields(&self.state.db, user_id, required).map_err(internal)?;
+        let kind = if missing_fields.is_empty() { "access" } else { profile::PROFILE_TOKEN_KIND };
+        let audience = app.map(|app| app.audiences()).unwrap_or_default();
+        let access = self
+            .state
+            .keys
+            .create_token_for(&subject, access_ttl, kind, cfg.jwt_issuer.as_deref(), &audience, Some(&session_id))
+            .map_err(internal)?;
+        Ok(AuthResponse {
+            access_token: access,
+            refresh_token,
+            expires_at: crate::db::Database::now_ts() + access_ttl,
+            refresh_expires_at,
+            session_id,
+            passkey_nudge: None,
+            redirect_uri: None,
+            missing_fields,
+            flow_id: None,
+        })
+    }
+
+    /// Email a magic link. `redirect_uri` must be registered for the calling
+    /// application (see [`AuthService::for_client`]); a bound link must be
+    /// verified with the matching [`LinkProof`]. Returns the login flow id,
+    /// which follows the link through its email, verification and tokens.
+    pub async fn request_magic(
+        &self,
+        email: &str,
+        redirect_uri: Option<&str>,
+        binding: &LinkBinding,
+    ) -> Result<String, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let cfg = &self.state.cfg;
+        let app = self.client_id.as_deref().and_then(|id| self.state.application(id));
+        let app = app.as_deref();
+        let redirect = match redirect_uri {
+            Some(uri) => {
+                let url = redirects::validate_for(app, uri).map_err(|e| {
+                    warn!("refusing magic link redirect {:?}: {}", uri, e);
+                    ServiceError::RedirectNotAllowed
+                })?;
+                Some(url.to_string())
+            }
+            None => None,
+        };
+        let address = EmailAddress::parse(email)?;
+        if address.requires_smtputf8() && !self.state.emailer.supports_smtputf8().await {
+            return Err(ServiceError::Smtputf8Unsupported);
+        }
+        let email = &address.to_string();
+        if self.canary(email, "magic_link")? {
+            // looks sent; nothing is issued
+            return Ok(uuid::Uuid::new_v4().to_string());
+        }
+        if self.suppressed(email)? {
+            return Err(ServiceError::EmailSuppressed);
+        }
+        let base_url = self.link_base_url(LinkKind::Magic)?;
+        if let Verdict::Undeliverable(reason) = self.state.mx.check(email).await {
+            let domain = deliverability::domain_of(email).unwrap_or_default();
+            if self.state.mx.enforcement() == Enforcement::Reject {
+                return Err(ServiceError::UndeliverableDomain(domain));
+            }
+            warn!("sending magic link to undeliverable domain {} ({})", domain, reason);
+        }
+        if let Some(ip) = &self.ip {
+            if abuse_reports::is_blocked(&self.state.db, ip, &cfg.abuse_reports).map_err(internal)? {
+                warn!("refusing magic link request from {} (reported for unsolicited links)", ip);
+                return Err(ServiceError::TooManyAttempts);
+            }
+        }
+        let user_id = latency::time(Stage::DbLookup, || self.state.db.get_or_create_user(email)).map_err(internal)?;
+        self.reserve_email(&user_id)?;
+        let link = self
+            .tokens()
+            .issue_magic_link(
+                &user_id,
+                cfg.magic_link_expiry_seconds,
+                &cfg.magic_links,
+                app.map(|app| (app.client_id.as_str(), redirect.as_deref())),
+                binding,
+            )
+            .map_err(internal)?;
+        if link.resent {
+            debug!(flow_id = %link.flow_id, "resending outstanding magic link to user {}", user_id);
+        }
+        if let Some(ip) = &self.ip {
+            MagicLink::set_requester(&self.state.db, &link.token, ip).map_err(internal)?;
+        }
+        self.state.audit.log(
+            &self.state.db,
+            AuditEventType::MagicLinkRequested,
+            Some(&user_id),
+            Some(email),
+            None,
+            None,
+            Some(&serde_json::json!({ "flow_id": link.flow_id, "resent": link.resent }).to_string()),
+            true,
+        );
+        if self.state.chaos.email_failure() {
+            error!(flow_id = %link.flow_id, "email send failed: forced by chaos injection");
+            return Err(ServiceError::EmailFailed);
+        }
+        let timer = latency::start(Stage::EmailEnqueue);
+        let sent = self.state.emailer.send_magic_link(email, base_url, &link.token, &link.flow_id).await;
+        drop(timer);
+        sent.map_err(|e| {
+            error!(flow_id = %link.flow_id, "email send failed: {}", e);
+            ServiceError::EmailFailed
+        })?;
+        Ok(link.flow_id)
+    }
+
+    /// "This wasn't me": expire the link and flag the IP that requested it
+    pub async fn report_unsolicited(&self, token: &str) -> Result<(), ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let report = abuse_reports::report(&self.state.db, token, &self.state.cfg.abuse_reports)
+            .map_err(internal)?
+            .ok_or(ServiceError::MagicLinkInvalid)?;
+        if !report.duplicate {
+            warn!(
+                flow_id = ?report.flow_id,
+                "user {} reported an unsolicited magic link requested from {:?}",
+                report.user_id,
+                report.requested_ip
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn verify_magic(&self, token: &str, proof: &LinkProof) -> Result<AuthResponse, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let links = self.tokens();
+        let redirect = links.magic_link_redirect(token).map_err(internal)?;
+        let flow_id = links.magic_link_flow_id(token).map_err(internal)?;
+        // tokens go to the application the link was requested for, not to
+        // whichever client id the verifying request claims
+        let client_id = links.magic_link_client(token).map_err(internal)?;
+        let service = self.clone().for_client(client_id.as_deref());
+        match latency::time(Stage::DbLookup, || links.consume_magic_link(token, proof)) {
+            Ok(user_id) => {
+                let mut resp = service
+                    .complete_login(&user_id, AuditEventType::MagicLinkVerified, flow_id.as_deref())
+                    .await?;
+                // re-checked so removing a rule also stops links already in flight
+                resp.redirect_uri = redirect.and_then(|(client_id, uri)| {
+                    let app = self.state.application(&client_id);
+                    match redirects::validate_for(app.as_deref(), &uri) {
+                        Ok(url) => Some(url.to_string()),
+                        Err(e) => {
+                            warn!("dropping magic link redirect {:?}: {}", uri, e);
+                            None
+                        }
+                    }
+                });
+                if self.platform_authenticator {
+                    let cfg = &self.state.cfg;
+                    resp.passkey_nudge =
+                        passkey_nudge::offer(&self.state.db, &cfg.passkey_nudge, &cfg.jwt_secret, &user_id)
+                            .unwrap_or_else(|e| {
+                                error!("passkey nudge failed: {}", e);
+                                None
+                            });
+                }
+                Ok(resp)
+            }
+            Err(e) => {
+                if let Some(flow_id) = &flow_id {
+                    self.state.audit.log(
+                        &self.state.db,
+                        AuditEventType::MagicLinkFailed,
+                        None,
+                        None,
+                        None,
+                        None,
+                        Some(&serde_json::json!({ "flow_id": flow_id, "reason": e.to_string() }).to_string()),
+                        false,
+                    );
+                }
+                match e {
+                    StorageError::BindingMismatch => {
+                        warn!(flow_id = ?flow_id, "magic link presented without its client binding proof");
+                        Err(ServiceError::LinkBindingMismatch)
+                    }
+                    StorageError::Used => Err(ServiceError::MagicLinkUsed),
+                    StorageError::Invalid => Err(ServiceError::MagicLinkInvalid),
+                    e => Err(internal(e)),
+                }
+            }
+        }
+    }
+
+    pub async fn totp_enroll(&self, email: &str) -> Result<TotpEnrollResp, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let email = &EmailAddress::parse(email)?.to_string();
+        let user_id = self.state.db.get_or_create_user(email).map_err(internal)?;
+        let secret = totp::generate_secret();
+        // only a first enrollment is unauthenticated; replacing a secret goes
+        // through totp_rotate, which confirms the current factor
+        let enrolled = self.state.db.users().enroll_totp(&user_id, &secret).map_err(internal)?;
+        if !enrolled {
+            return Err(ServiceError::TotpAlreadyEnrolled);
+        }
+
+        let otpauth_url = totp::generate_otpauth_url(&secret, email, "PasswordlessAuth");
+        Ok(TotpEnrollResp {
+            secret,
+            otpauth_url,
+        })
+    }
+
+    /// Replace the user's TOTP secret after confirming the current TOTP or a
+    /// passkey. The old secret stops working in the same transaction.
+    pub async fn totp_rotate(&self, user_id: &str, proof: FactorProof) -> Result<TotpEnrollResp, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let db = &self.state.db;
+        let user = db.users().find_by_id(user_id).map_err(internal)?.ok_or(ServiceError::UserNotFound)?;
+        let (email, old_secret) = (user.email, user.totp_secret.ok_or(ServiceError::TotpNotEnrolled)?);
+
+        let confirmed_with = self.confirm_factor(user_id, &old_secret, proof)?;
+
+        let secret = totp::generate_secret();
+        db.transaction(|tx| {
+            // compare-and-swap so a concurrent rotation cannot be silently overwritten
+            if !db.users().replace_totp(user_id, &old_secret, Some(&secret)).map_err(internal)? {
+                return Err(ServiceError::InvalidTotp);
+            }
+            let event = OutboxEvent::new(AuditEventType::TotpEnrolled)
+                .user(user_id)
+                .webhook(WebhookEventType::TotpEnrolled)
+                .metadata(serde_json::json!({
+                    "rotated": true,
+                    "confirmed_with": confirmed_with,
+                }));
+            Outbox::enqueue(tx, &event).map_err(internal)
+        })?;
+
+        let rendered = EmailTemplates::totp_rotated(&email);
+        let sent = match notifications::delivery(db, user_id, Category::Security).map_err(internal) {
+            Ok(Some(delivery)) => {
+                let sms = format!("{}. Not you? Contact support.", rendered.subject);
+                self.deliver(delivery, &email, rendered, sms).await
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            error!("totp rotation notice failed: {}", e);
+        }
+
+        let otpauth_url = totp::generate_otpauth_url(&secret, &email, "PasswordlessAuth");
+        Ok(TotpEnrollResp {
+            secret,
+            otpauth_url,
+        })
+    }
+
+    /// Turn TOTP off after confirming the current TOTP or a passkey
+    pub async fn totp_disable(&self, user_id: &str, proof: FactorProof) -> Result<(), ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let db = &self.state.db;
+        let user = db.users().find_by_id(user_id).map_err(internal)?.ok_or(ServiceError::UserNotFound)?;
+        let old_secret = user.totp_secret.ok_or(ServiceError::TotpNotEnrolled)?;
+        let confirmed_with = self.confirm_factor(user_id, &old_secret, proof)?;
+
+        db.transaction(|tx| {
+            if !db.users().replace_totp(user_id, &old_secret, None).map_err(internal)? {
+                return Err(ServiceError::InvalidTotp);
+            }
+            let event = OutboxEvent::new(AuditEventType::TotpDisabled)
+                .user(user_id)
+                .metadata(serde_json::json!({ "confirmed_with": confirmed_with }));
+            Outbox::enqueue(tx, &event).map_err(internal)
+        })?;
+
+        self.notify(user_id, SecurityNotice::new(NoticeKind::TotpDisabled)).await;
+        Ok(())
+    }
+
+    /// Check proof of the user's current factor before changing it; returns
+    /// the factor used (`totp` or `passkey`)
+    fn confirm_factor(&self, user_id: &str, secret: &str, proof: FactorProof) -> Result<&'static str, ServiceError> {
+        let db = &self.state.db;
+        let cfg = &self.state.cfg;
+        match proof {
+            FactorProof::Totp(code) => {
+                if attempt_token::current(db, user_id)
+                    .map_err(internal)?
+                    .is_some_and(|w| w.failures >= cfg.totp_max_attempts)
+                {
+                    return Err(ServiceError::TooManyAttempts);
+                }
+                if totp::verify_code(secret, &code).is_err() {
+                    attempt_token::record_failure(db, user_id, cfg.totp_attempt_window_seconds)
+                        .map_err(internal)?;
+                    return Err(ServiceError::InvalidTotp);
+                }
+                Ok("totp")
+            }
+            FactorProof::Passkey { pending_id, response } => {
+                let asserted = latency::time(Stage::WebauthnVerify, || {
+                    self.state.webauthn.finish_login(db, &pending_id, response, self.ip.as_deref())
+                })
+                .map_err(|e| {
+                    error!("factor confirmation with passkey failed: {:?}", e);
+                    ServiceError::WebauthnFailed
+                })?;
+                if asserted != user_id {
+                    return Err(ServiceError::WebauthnFailed);
+                }
+                Ok("passkey")
+            }
+        }
+    }
+
+    /// Verify a TOTP code. Once `totp_attempt_token_after` attempts have
+    /// failed, `attempt_token` from the previous response must accompany the
+    /// next attempt.
+    pub async fn totp_verify(
+        &self,
+        email: &str,
+        code: &str,
+        attempt_token: Option<&str>,
+    ) -> Result<AuthResponse, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let email = EmailAddress::parse(email)?.to_string();
+        if self.canary(&email, "totp")? {
+            return Err(ServiceError::InvalidTotp);
+        }
+        let user = latency::time(Stage::DbLookup, || self.state.db.users().find_by_email(&email))
+            .map_err(internal)?
+            .ok_or(ServiceError::UserNotFound)?;
+        let (user_id, secret) = (user.id, user.totp_secret.ok_or(ServiceError::TotpNotEnrolled)?);
+
+        let db = &self.state.db;
+        let cfg = &self.state.cfg;
+        let key = attempt_token::key(&cfg.jwt_secret);
+        if let Some(window) = attempt_token::current(db, &user_id).map_err(internal)? {
+            if window.failures >= cfg.totp_max_attempts {
+                return Err(ServiceError::TooManyAttempts);
+            }
+            if window.failures >= cfg.totp_attempt_token_after {
+                let claims = attempt_token
+                    .and_then(|t| attempt_token::verify(&key, t))
+                    .filter(|c| c.window == window.window_id && c.seq == window.seq)
+                    .ok_or(ServiceError::AttemptTokenRequired)?;
+                if !attempt_token::claim(db, &user_id, claims.seq).map_err(internal)? {
+                    return Err(ServiceError::AttemptTokenRequired);
+                }
+            }
+        }
+
+        if totp::verify_code(&secret, code).is_err() {
+            let window = attempt_token::record_failure(db, &user_id, cfg.totp_attempt_window_seconds)
+                .map_err(internal)?;
+            if window.failures >= cfg.totp_max_attempts {
+                return Err(ServiceError::TooManyAttempts);
+            }
+            let token = attempt_token::sign(
+                &key,
+                &AttemptClaims {
+                    window: window.window_id,
+                    failures: window.failures,
+                    seq: window.seq,
+                    exp: window.expires_at,
+                },
+            );
+            return Err(ServiceError::InvalidTotpRetry {
+                attempt_token: token,
+                remaining_attempts: cfg.totp_max_attempts - window.failures,
+            });
+        }
+
+        attempt_token::clear(db, &user_id).map_err(internal)?;
+        self.complete_login(&user_id, AuditEventType::TotpVerified, None).await
+    }
+
+    pub async fn refresh(&self, refresh_token: &str) -> Result<AuthResponse, ServiceError> {
+        self.state.chaos.inject_db_latency().await;
+        if self.state.cfg.sessions.is_eventual() && regions::is_assertion(refresh_token) {
+            return self.refresh_assertion(refresh_token);
+        }
+        let cfg = &self.state.cfg;
+        let claims = jwt::verify_token(refresh_token, &cfg.jwt_secret, cfg.jwt_leeway_seconds).map_err(|e| {
+            error!("refresh token verify failed: {}", e);
+            ServiceError::InvalidToken
+        })?;
+        if claims.kind != "refresh" {
+            return Err(ServiceError::InvalidTokenKind);
+        }
+        let live = latency::time(Stage::DbLookup, || self.tokens().live_refresh_token(&claims.sub))
+            .map_err(internal)?
+            .ok_or(ServiceError::InvalidRefresh)?;
+        // lifetimes and audiences follow the application the session was
+        // signed in to, not the client id the refreshing request claims
+        let service = self.clone().for_client(live.client_id.as_deref());
+        let (access_ttl, refresh_ttl) = service.token_lifetimes(&live.user_id)?;
+        let (user_id, session) = self
+            .tokens()
+            .rotate_refresh_token(&claims.sub, refresh_ttl)
+            .map_err(|e| match e {
+                StorageError::Invalid => ServiceError::InvalidRefresh,
+                e => internal(e),
+            })?;
+        service.sign_tokens(&user_id, session, access_ttl, refresh_ttl)
+    }
+
+    /// Log out: revoke the session of `refresh_token` (a refresh token or a
+    /// session assertion), or with `all` every session of its user
+    pub async fn logout(&self, refresh_token: &str, all: bool) -> Result<(), ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let cfg = &self.state.cfg;
+        let now = crate::db::Database::now_ts();
+        let (user_id, session_id) = if cfg.sessions.is_eventual() && regions::is_assertion(refresh_token) {
+            // the session row may live in another region; the revocation reaches it
+            let assertion = regions::verify(&cfg.sessions.assertion_key(&cfg.jwt_secret), refresh_token)
+                .ok_or(ServiceError::InvalidRefresh)?;
+            (assertion.uid, Some(assertion.sid))
+        } else {
+            let claims = jwt::verify_token(refresh_token, &cfg.jwt_secret, cfg.jwt_leeway_seconds)
+                .map_err(|_| ServiceError::InvalidToken)?;
+            if claims.kind != "refresh" {
+                return Err(ServiceError::InvalidTokenKind);
+            }
+            let session = self
+                .tokens()
+                .live_refresh_token(&claims.sub)
+                .map_err(internal)?
+                .ok_or(ServiceError::InvalidRefresh)?;
+            self.tokens().revoke_refresh_token(&claims.sub).map_err(internal)?;
+            (session.user_id, session.session_id)
+        };
+
+        if all {
+            self.state.db.sessions().revoke_all_for_user(&user_id).map_err(internal)?;
+            if let Some(tokens) = &self.state.tokens {
+                tokens.revoke_user_refresh_tokens(&user_id).map_err(internal)?;
+            }
+            self.state.revocations.publish(RevocationEvent::UserSessionsRevoked {
+                user_id: user_id.clone(),
+                at: now,
+            });
+        } else if let Some(session_id) = session_id.clone() {
+            self.state.revocations.publish(RevocationEvent::SessionRevoked {
+                session_id,
+                user_id: user_id.clone(),
+            });
+        }
+        self.state.audit.log(
+            &self.state.db,
+            AuditEventType::UserLoggedOut,
+            Some(&user_id),
+            None,
+            self.ip.as_deref(),
+            None,
+            Some(&serde_json::json!({ "session_id": session_id, "all": all }).to_string()),
+            true,
+        );
+        Ok(())
+    }
+
+    /// Lifetime left on an access token (expiring at `access_expires_at`) and,
+    /// if presented, on the user's refresh token
+    pub fn token_status(
+        &self,
+        user_id: &str,
+        access_expires_at: i64,
+        refresh_token: Option<&str>,
+    ) -> Result<TokenStatus, ServiceError> {
+        let now = crate::db::Database::now_ts();
+        let refresh_expires_at = match refresh_token {
+            Some(token) => self.refresh_expiry(user_id, token)?,
+            None => None,
+        };
+        let refresh_expires_in = refresh_expires_at.map(|at| at - now);
+        let reauth_recommended = match refresh_expires_in {
+            Some(remaining) => self.state.cfg.session_expiry.reauth_recommended(remaining),
+            None => refresh_token.is_some(),
+        };
+        Ok(TokenStatus {
+            expires_at: access_expires_at,
+            expires_in: (access_expires_at - now).max(0),
+            refresh_expires_at,
+            refresh_expires_in,
+            reauth_recommended,
+        })
+    }
+
+    /// Expiry of a live refresh token (or session assertion) belonging to `user_id`
+    fn refresh_expiry(&self, user_id: &str, token: &str) -> Result<Option<i64>, ServiceError> {
+        let cfg = &self.state.cfg;
+        if cfg.sessions.is_eventual() && regions::is_assertion(token) {
+            let assertion = regions::verify(&cfg.sessions.assertion_key(&cfg.jwt_secret), token);
+            return Ok(assertion.filter(|a| a.uid == user_id).map(|a| a.exp));
+        }
+        let Ok(claims) = jwt::verify_token(token, &cfg.jwt_secret, cfg.jwt_leeway_seconds) else {
+            return Ok(None);
+        };
+        if claims.kind != "refresh" {
+            return Ok(None);
+        }
+        let session = self.tokens().live_refresh_token(&claims.sub).map_err(internal)?;
+        Ok(session.filter(|s| s.user_id == user_id).map(|s| s.expires_at))
+    }
+
+    /// Introspect an access or refresh token on behalf of `app` (RFC 7662).
+    /// Tokens that do not verify, whose session or user was revoked, or
+    /// that were issued for another application's audience are inactive.
+    /// `hint` (`access_token` or `refresh_token`) only decides which kind is
+    /// tried first.
+    pub fn introspect(
+        &self,
+        app: &ApplicationConfig,
+        token: &str,
+        hint: Option<&str>,
+    ) -> Result<Introspection, ServiceError> {
+        let found = if hint == Some("refresh_token") {
+            match self.introspect_refresh(app, token)? {
+                Some(found) => Some(found),
+                None => self.introspect_access(app, token)?,
+            }
+        } else {
+            match self.introspect_access(app, token)? {
+                Some(found) => Some(found),
+                None => self.introspect_refresh(app, token)?,
+            }
+        };
+        Ok(found.unwrap_or_default())
+    }
+
+    /// `None` unless `token` is a genuine access token
+    fn introspect_access(&self, app: &ApplicationConfig, token: &str) -> Result<Option<Introspection>, ServiceError> {
+        let cfg = &self.state.cfg;
+        let Ok(claims) = self.state.keys.verify(token, cfg.jwt_leeway_seconds) else {
+            return Ok(None);
+        };
+        if claims.kind != "access" {
+            return Ok(None);
+        }
+        let audiences = app.audiences();
+        if !claims.aud.is_empty() && !claims.aud.iter().any(|a| audiences.contains(&a.as_str())) {
+            return Ok(Some(Introspection::default()));
+        }
+        let Some(user_id) = subjects::resolve(&self.state.db, &claims.sub).map_err(internal)? else {
+            return Ok(Some(Introspection::default()));
+        };
+        let revoked = self.state.revocations.cache().is_access_token_revoked(&user_id, &claims);
+        // in `eventual` mode the session row may live in another region, and
+        // an external token store is reached through the revocation cache
+        let ended = match claims.sid.as_deref() {
+            Some(sid) if !cfg.sessions.is_eventual() && self.state.tokens.is_none() => {
+                !Session::is_active(&self.state.db, sid).map_err(internal)?
+            }
+            _ => false,
+        };
+        if revoked || ended {
+            return Ok(Some(Introspection::default()));
+        }
+        Ok(Some(Introspection {
+            active: true,
+            sub: Some(claims.sub),
+            exp: Some(claims.exp as i64),
+            scope: self.granted_scope(app, &user_id)?,
+            kind: Some("access".into()),
+        }))
+    }
+
+    /// `None` unless `token` is a genuine refresh token or session assertion
+    fn introspect_refresh(&self, app: &ApplicationConfig, token: &str) -> Result<Option<Introspection>, ServiceError> {
+        let cfg = &self.state.cfg;
+        let db = &self.state.db;
+        if cfg.sessions.is_eventual() && regions::is_assertion(token) {
+            let Some(assertion) = regions::verify(&cfg.sessions.assertion_key(&cfg.jwt_secret), token) else {
+                return Ok(None);
+            };
+            let cache = self.state.revocations.cache();
+            if cache.is_session_revoked(&assertion.sid)
+                || cache.is_user_token_revoked(&assertion.uid, assertion.auth_time)
+                || regions::is_revoked(db, &assertion, cfg.sessions.clock_skew_seconds).map_err(internal)?
+                || regions::is_superseded(db, &assertion).map_err(internal)?
+            {
+                return Ok(Some(Introspection::default()));
+            }
+            return self.active_refresh(app, &assertion.uid, assertion.exp).map(Some);
+        }
+        let Ok(claims) = jwt::verify_token(token, &cfg.jwt_secret, cfg.jwt_leeway_seconds) else {
+            return Ok(None);
+        };
+        if claims.kind != "refresh" {
+            return Ok(None);
+        }
+        match self.tokens().live_refresh_token(&claims.sub).map_err(internal)? {
+            Some(session) => self.active_refresh(app, &session.user_id, session.expires_at).map(Some),
+            None => Ok(Some(Introspection::default())),
+        }
+    }
+
+    fn active_refresh(&self, app: &ApplicationConfig, user_id: &str, exp: i64) -> Result<Introspection, ServiceError> {
+        Ok(Introspection {
+            active: true,
+            sub: Some(subjects::subject_for(&self.state.db, user_id, Some(app)).map_err(internal)?),
+            exp: Some(exp),
+            scope: self.granted_scope(app, user_id)?,
+            kind: Some("refresh".into()),
+        })
+    }
+
+    /// Consent scopes `app` requests and the user granted it, space-separated
+    fn granted_scope(&self, app: &ApplicationConfig, user_id: &str) -> Result<Option<String>, ServiceError> {
+        let granted = consents::granted(&self.state.db, user_id, &app.client_id).map_err(internal)?;
+        let scopes: Vec<&str> = granted.iter().filter(|s| app.scopes.contains(s)).map(|s| s.as_str()).collect();
+        Ok((!scopes.is_empty()).then(|| scopes.join(" ")))
+    }
+
+    /// Revoke an access or refresh token on behalf of `app` (RFC 7009). A
+    /// refresh token ends its session, taking the session's access tokens
+    /// with it; an access token is denied by its `jti` until it expires.
+    /// Unknown and already invalid tokens, and access tokens issued for
+    /// another application's audience, are left alone without an error.
+    pub fn revoke(&self, app: &ApplicationConfig, token: &str, hint: Option<&str>) -> Result<(), ServiceError> {
+        self.writable()?;
+        if hint == Some("refresh_token") {
+            if !self.revoke_refresh(app, token)? {
+                self.revoke_access(app, token)?;
+            }
+        } else if !self.revoke_access(app, token)? {
+            self.revoke_refresh(app, token)?;
+        }
+        Ok(())
+    }
+
+    /// False unless `token` is a genuine access token
+    fn revoke_access(&self, app: &ApplicationConfig, token: &str) -> Result<bool, ServiceError> {
+        let Ok(claims) = self.state.keys.verify(token, self.state.cfg.jwt_leeway_seconds) else {
+            return Ok(false);
+        };
+        if claims.kind != "access" {
+            return Ok(false);
+        }
+        let audiences = app.audiences();
+        if !claims.aud.is_empty() && !claims.aud.iter().any(|a| audiences.contains(&a.as_str())) {
+            return Ok(true);
+        }
+        // tokens issued before access tokens carried a `jti` expire on their own
+        let Some(jti) = claims.jti else {
+            return Ok(true);
+        };
+        let exp = claims.exp as i64;
+        revocation::deny_access_token(&self.state.db, &jti, exp).map_err(internal)?;
+        self.state.revocations.publish(RevocationEvent::AccessTokenRevoked { jti: jti.clone(), exp });
+        let user_id = subjects::resolve(&self.state.db, &claims.sub).map_err(internal)?;
+        self.audit_revocation(app, user_id.as_deref(), serde_json::json!({ "jti": jti }));
+        Ok(true)
+    }
+
+    /// False unless `token` is a genuine refresh token or session assertion
+    fn revoke_refresh(&self, app: &ApplicationConfig, token: &str) -> Result<bool, ServiceError> {
+        let cfg = &self.state.cfg;
+        let (user_id, session_id) = if cfg.sessions.is_eventual() && regions::is_assertion(token) {
+            // the session row may live in another region; the revocation reaches it
+            let Some(assertion) = regions::verify(&cfg.sessions.assertion_key(&cfg.jwt_secret), token) else {
+                return Ok(false);
+            };
+            (assertion.uid, Some(assertion.sid))
+        } else {
+            let Ok(claims) = jwt::verify_token(token, &cfg.jwt_secret, cfg.jwt_leeway_seconds) else {
+                return Ok(false);
+            };
+            if claims.kind != "refresh" {
+                return Ok(false);
+            }
+            let Some(session) = self.tokens().live_refresh_token(&claims.sub).map_err(internal)? else {
+                return Ok(true);
+            };
+            self.tokens().revoke_refresh_token(&claims.sub).map_err(internal)?;
+            (session.user_id, session.session_id)
+        };
+        if let Some(session_id) = session_id.clone() {
+            self.state.revocations.publish(RevocationEvent::SessionRevoked {
+                session_id,
+                user_id: user_id.clone(),
+            });
+        }
+        self.audit_revocation(app, Some(&user_id), serde_json::json!({ "session_id": session_id }));
+        Ok(true)
+    }
+
+    fn audit_revocation(&self, app: &ApplicationConfig, user_id: Option<&str>, mut metadata: serde_json::Value) {
+        metadata["client_id"] = app.client_id.clone().into();
+        metadata["source"] = "oauth_revoke".into();
+        self.state.audit.log(
+            &self.state.db,
+            AuditEventType::SessionRevoked,
+            user_id,
+            None,
+            self.ip.as_deref(),
+            None,
+            Some(&metadata.to_string()),
+            true,
+        );
+    }
+
+    /// `eventual` session mode: validate the session assertion without the
+    /// session row (it may live in another region) and rotate it
+    fn refresh_assertion(&self, refresh_token: &str) -> Result<AuthResponse, ServiceError> {
+        let sessions = &self.state.cfg.sessions;
+        let key = sessions.assertion_key(&self.state.cfg.jwt_secret);
+        let assertion = regions::verify(&key, refresh_token).ok_or(ServiceError::InvalidRefresh)?;
+        let cache = self.state.revocations.cache();
+        if cache.is_session_revoked(&assertion.sid)
+            || cache.is_user_token_revoked(&assertion.uid, assertion.auth_time)
+            || regions::is_revoked(&self.state.db, &assertion, sessions.clock_skew_seconds).map_err(internal)?
+        {
+            return Err(ServiceError::InvalidRefresh);
+        }
+        if !regions::observe_generation(&self.state.db, &assertion).map_err(internal)? {
+            warn!("session assertion reuse for session {}; revoking it", assertion.sid);
+            self.state.revocations.publish(RevocationEvent::SessionRevoked {
+                session_id: assertion.sid,
+                user_id: assertion.uid,
+            });
+            return Err(ServiceError::InvalidRefresh);
+        }
+        let service = self.clone().for_client(assertion.cid.as_deref());
+        let (access_ttl, refresh_ttl) = service.token_lifetimes(&assertion.uid)?;
+        let rotated = assertion.rotated(&sessions.region, refresh_ttl);
+        let refresh = (regions::sign(&key, &rotated), rotated.exp);
+        service.sign_access(&assertion.uid, assertion.sid, refresh, access_ttl)
+    }
+
+    pub async fn webauthn_register_options(
+        &self,
+        email: &str,
+    ) -> Result<CreationChallengeResponse, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let address = EmailAddress::parse(email)?;
+        let user_id = self.state.db.get_or_create_user(&address.to_string()).map_err(internal)?;
+        self.state
+            .webauthn
+            .start_registration(&self.state.db, &user_id, &address.display())
+            .map_err(|e| internal(format!("{:?}", e)))
+    }
+
+    /// Registration options for the user a passkey nudge ticket was issued to
+    pub async fn webauthn_register_options_for_ticket(
+        &self,
+        ticket: &str,
+    ) -> Result<CreationChallengeResponse, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let cfg = &self.state.cfg;
+        let user_id = passkey_nudge::verify_ticket(&cfg.jwt_secret, cfg.jwt_leeway_seconds, ticket)
+            .ok_or(ServiceError::InvalidToken)?;
+        let email = self
+            .state
+            .db
+            .users()
+            .find_by_id(&user_id)
+            .map_err(internal)?
+            .ok_or(ServiceError::UserNotFound)?
+            .email;
+        self.state
+            .webauthn
+            .start_registration(&self.state.db, &user_id, &email)
+            .map_err(|e| internal(format!("{:?}", e)))
+    }
+
+    pub async fn webauthn_register_complete(
+        &self,
+        pending_id: &str,
+        response: serde_json::Value,
+    ) -> Result<(), ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let user_id = latency::time(Stage::WebauthnVerify, || {
+            self.state.webauthn.finish_registration(&self.state.db, pending_id, response)
+        })
+        .map_err(|e| {
+            error!("reg complete failed: {:?}", e);
+            ServiceError::WebauthnFailed
+        })?;
+        self.notify(&user_id, SecurityNotice::new(NoticeKind::PasskeyAdded)).await;
+        Ok(())
+    }
+
+    pub async fn webauthn_login_options(
+        &self,
+        email: &str,
+    ) -> Result<RequestChallengeResponse, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        let email = EmailAddress::parse(email)?.to_string();
+        if self.canary(&email, "webauthn")? {
+            return Err(ServiceError::UserNotFound);
+        }
+        let user_id = latency::time(Stage::DbLookup, || self.state.db.users().find_by_email(&email))
+            .map_err(internal)?
+            .ok_or(ServiceError::UserNotFound)?
+            .id;
+        self.state
+            .webauthn
+            .start_login(&self.state.db, &user_id, self.application_id().as_deref())
+            .map_err(|e| internal(format!("{:?}", e)))
+    }
+
+    pub async fn webauthn_login_complete(
+        &self,
+        pending_id: &str,
+        response: serde_json::Value,
+    ) -> Result<AuthResponse, ServiceError> {
+        self.writable()?;
+        self.state.chaos.inject_db_latency().await;
+        // like magic links, tokens go to the application the ceremony was
+        // started for, not to whichever client id the completing request claims
+        let client_id = self.state.webauthn.login_client(&self.state.db, pending_id).map_err(|e| {
+            error!("webauthn login complete failed: {:?}", e);
+            ServiceError::WebauthnFailed
+        })?;
+        let service = self.clone().for_client(client_id.as_deref());
+        let user_id = latency::time(Stage::WebauthnVerify, || {
+            self.state.webauthn.finish_login(&self.state.db, pending_id, response, self.ip.as_deref())
+        })
+        .map_err(|e| {
+            error!("webauthn login complete failed: {:?}", e);
+            ServiceError::WebauthnFailed
+        })?;
+        service.complete_login(&user_id, AuditEventType::WebauthnLoginCompleted, None).await
+    }
+
+    /// Sign in a device whose request the user approved at `/device`
+    /// (RFC 8628); the device gets a session of its own
+    pub async fn device_login(&self, user_id: &str) -> Result<AuthResponse, ServiceError> {
+        self.writable()?;
+        self.complete_login(user_id, AuditEventType::DeviceLoginCompleted, None).await
+    }
+}
+
+impl AuthService {
+    /// Issue a signed action link for the user and email it to `email`
+    pub async fn send_action_link(
+        &self,
+        purpose: ActionPurpose,
+        user_id: &str,
+        email: &str,
+        payload: serde_json::Value,
+    ) -> Result<(), ServiceError> {
+        self.writable()?;
+        if self.suppressed(email)? {
+            return Err(ServiceError::EmailSuppressed);
+        }
+        let cfg = &self.state.cfg;
+        let base_url = self.link_base_url(LinkKind::Action)?;
+        self.reserve_email(user_id)?;
+        let token = ActionLink::issue(
+            &self.state.db,
+            &cfg.jwt_secret,
+            purpose,
+            user_id,
+            &payload,
+            cfg.action_link_expiry_seconds,
+        )
+        .map_err(internal)?;
+        let link = format!("{}/{}", base_url.trim_end_matches('/'), token);
+        let rendered = EmailTemplates::action_link(purpose, email, &link, cfg.action_link_expiry_seconds);
+        self.state
+            .emailer
+            .send_rendered(email, rendered)
+            .await
+            .map_err(|e| {
+                error!("action link email failed: {}", e);
+                ServiceError::EmailFailed
+            })
+    }
+
+    /// Consume a signed action link and perform the action it authorizes
+    pub async fn perform_action(&self, token: &str) -> Result<ActionOutcome, ServiceError> {
+        self.writable()?;
+        let link = ActionLink::consume(&self.state.db, &self.state.cfg.jwt_secret, token)
+            .map_err(|e| match e {
+                ActionLinkError::Used => ServiceError::ActionLinkUsed,
+                ActionLinkError::Invalid => ServiceError::ActionLinkInvalid,
+                e => internal(e),
+            })?;
+        let db = &self.state.db;
+        let now = crate::db::Database::now_ts();
+
+        match link.purpose {
+            ActionPurpose::VerifyEmail => {
+                db.users().mark_email_verified(&link.user_id, now).map_err(internal)?;
+            }
+            ActionPurpose::ConfirmEmailChange => {
+                let new_email = link.payload["new_email"]
+                    .as_str()
+                    .and_then(|e| EmailAddress::parse(e).ok())
+                    .ok_or(ServiceError::ActionLinkInvalid)?
+                    .to_string();
+                db.users().change_email(&link.user_id, &new_email, now).map_err(|e| match e {
+                    rusqlite::Error::SqliteFailure(f, _) if f.code == rusqlite::ErrorCode::ConstraintViolation => {
+                        ServiceError::EmailInUse
+                    }
+                    e => internal(e),
+                })?;
+            }
+            ActionPurpose::RevokeSession => {
+                let session_id = link.payload["session_id"]
+                    .as_str()
+                    .ok_or(ServiceError::ActionLinkInvalid)?;
+                self.tokens().revoke_session(&link.user_id, session_id).map_err(internal)?;
+                self.state.revocations.publish(RevocationEvent::SessionRevoked {
+                    session_id: session_id.to_string(),
+                    user_id: link.user_id.clone(),
+                });
+            }
+            ActionPurpose::FreezeAccount => {
+                db.users().freeze(&link.user_id, now).map_err(internal)?;
+                if let Some(tokens) = &self.state.tokens {
+                    tokens.revoke_user_refresh_tokens(&link.user_id).map_err(internal)?;
+                }
+                self.state.revocations.publish(RevocationEvent::UserDisabled {
+                    user_id: link.user_id.clone(),
+                    at: now,
+                });
+            }
+        }
+
+        self.state.audit.log(
+            db,
+            crate::audit::AuditEventType::ActionLinkUsed,
+            Some(&link.user_id),
+            None,
+            None,
+            None,
+            Some(&serde_json::json!({ "purpose": link.purpose.as_str() }).to_string()),
+            true,
+        );
+
+        Ok(ActionOutcome {
+            action: link.purpose,
+            status: "completed".to_string(),
+        })
+    }
+}
+
+fn internal<E: std::fmt::Display>(e: E) -> ServiceError {
+    error!("auth service error: {}", e);
+    ServiceError::Internal(e.to_string())
+}
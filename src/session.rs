@@ -1,5 +1,9 @@
-use crate::db::Database;
-use rusqlite::params;
+use crate::{
+    crypto::{self, constant_time_eq},
+    db::Database,
+    user_agent::{self, UserAgent},
+};
+use rusqlite::{params, OptionalExtension};
 use uuid::Uuid;
 use thiserror::Error;
 
@@ -13,36 +17,218 @@ pub enum SessionError {
 
 pub struct Session;
 
+/// A newly created refresh session
+pub struct NewSession {
+    /// Secret refresh token value; only its [`crypto::token_digest`] is stored
+    pub token: String,
+    /// Public identifier that is safe to hand to relying applications
+    pub session_id: String,
+}
+
 impl Session {
     pub fn create_refresh_token(
         db: &Database,
         user_id: &str,
         expiry_seconds: i64,
     ) -> Result<String, SessionError> {
+        Ok(Self::create(db, user_id, expiry_seconds)?.token)
+    }
+
+    pub fn create(
+        db: &Database,
+        user_id: &str,
+        expiry_seconds: i64,
+    ) -> Result<NewSession, SessionError> {
         let token = Uuid::new_v4().to_string();
+        let session_id = Uuid::new_v4().to_string();
         let now = Database::now_ts();
         let expires_at = now + expiry_seconds;
-        db.conn.execute(
-            "INSERT INTO refresh_tokens (token, user_id, expires_at, revoked, created_at) VALUES (?1, ?2, ?3, 0, ?4)",
-            params![token, user_id, expires_at, now],
+        db.conn().execute(
+            "INSERT INTO refresh_tokens (token, user_id, expires_at, revoked, created_at, session_id) VALUES (?1, ?2, ?3, 0, ?4, ?5)",
+            params![crypto::token_digest(&token), user_id, expires_at, now, session_id],
         )?;
-        Ok(token)
+        Ok(NewSession { token, session_id })
+    }
+
+    /// Record the browser and OS the session was created from
+    pub fn set_user_agent(db: &Database, session_id: &str, agent: &UserAgent) -> Result<(), SessionError> {
+        db.conn().execute(
+            "UPDATE refresh_tokens SET ua_browser = ?1, ua_browser_version = ?2, ua_os = ?3, ua_os_version = ?4, ua_device = ?5
+             WHERE session_id = ?6",
+            params![
+                agent.browser,
+                agent.browser_version,
+                agent.os,
+                agent.os_version,
+                agent.device_type.map(|d| d.as_str()),
+                session_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Record how the user signed in to start the session (`amr` values)
+    pub fn set_amr(db: &Database, session_id: &str, amr: &[&str]) -> Result<(), SessionError> {
+        db.conn().execute(
+            "UPDATE refresh_tokens SET amr = ?1 WHERE session_id = ?2",
+            params![serde_json::json!(amr).to_string(), session_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record the application the session was signed in to
+    pub fn set_client(db: &Database, session_id: &str, client_id: &str) -> Result<(), SessionError> {
+        db.conn().execute(
+            "UPDATE refresh_tokens SET client_id = ?1 WHERE session_id = ?2",
+            params![client_id, session_id],
+        )?;
+        Ok(())
+    }
+
+    /// Application the session of this refresh token was signed in to
+    pub fn client_id(db: &Database, token: &str) -> Result<Option<String>, SessionError> {
+        Ok(db
+            .conn()
+            .query_row(
+                "SELECT client_id FROM refresh_tokens WHERE token = ?1",
+                params![crypto::token_digest(token)],
+                |r| r.get(0),
+            )
+            .optional()?
+            .flatten())
+    }
+
+    /// `amr` of the user's session while it still has a live refresh token;
+    /// `None` once it has ended
+    pub fn live_amr(db: &Database, user_id: &str, session_id: &str) -> Result<Option<Vec<String>>, SessionError> {
+        let amr: Option<Option<String>> = db
+            .conn()
+            .query_row(
+                "SELECT amr FROM refresh_tokens
+                 WHERE session_id = ?1 AND user_id = ?2 AND revoked = 0 AND expires_at > ?3",
+                params![session_id, user_id, Database::now_ts()],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(amr.map(|amr| amr.and_then(|a| serde_json::from_str(&a).ok()).unwrap_or_default()))
+    }
+
+    /// Whether the session exists and still has a live refresh token
+    pub fn is_active(db: &Database, session_id: &str) -> Result<bool, SessionError> {
+        let count: i64 = db.conn().query_row(
+            "SELECT COUNT(*) FROM refresh_tokens WHERE session_id = ?1 AND revoked = 0 AND expires_at > ?2",
+            params![session_id, Database::now_ts()],
+            |r| r.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// Attach (or replace) an application's metadata on a session
+    pub fn set_metadata(
+        db: &Database,
+        session_id: &str,
+        client_id: &str,
+        metadata: &serde_json::Value,
+    ) -> Result<(), SessionError> {
+        db.conn().execute(
+            "INSERT INTO session_metadata (session_id, client_id, metadata, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(session_id, client_id) DO UPDATE SET metadata = excluded.metadata, updated_at = excluded.updated_at",
+            params![session_id, client_id, metadata.to_string(), Database::now_ts()],
+        )?;
+        Ok(())
+    }
+
+    /// Fetch the metadata an application attached to a session
+    pub fn get_metadata(
+        db: &Database,
+        session_id: &str,
+        client_id: &str,
+    ) -> Result<Option<serde_json::Value>, SessionError> {
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
+            "SELECT metadata FROM session_metadata WHERE session_id = ?1 AND client_id = ?2",
+        )?;
+        let mut rows = stmt.query(params![session_id, client_id])?;
+        if let Some(r) = rows.next()? {
+            let raw: String = r.get(0)?;
+            Ok(serde_json::from_str(&raw).ok())
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Rotate a refresh token: the predecessor is marked rotated (and no longer
+    /// valid) and a successor carrying the same `session_id` is inserted, both in
+    /// one transaction. Returns the owning user and the new token.
+    pub fn rotate(
+        db: &Database,
+        token: &str,
+        expiry_seconds: i64,
+    ) -> Result<(String, NewSession), SessionError> {
+        let digest = crypto::token_digest(token);
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        let now = Database::now_ts();
+        let (user_id, session_id): (String, String) = {
+            let mut stmt = tx.prepare(
+                "SELECT user_id, session_id FROM refresh_tokens WHERE token = ?1 AND revoked = 0 AND expires_at > ?2",
+            )?;
+            let mut rows = stmt.query(params![digest, now])?;
+            let r = rows.next()?.ok_or(SessionError::Invalid)?;
+            // rows issued before sessions had identifiers start a new session
+            let session_id: Option<String> = r.get(1)?;
+            (r.get(0)?, session_id.unwrap_or_else(|| Uuid::new_v4().to_string()))
+        };
+
+        let next = Uuid::new_v4().to_string();
+        let next_digest = crypto::token_digest(&next);
+        // the revoked = 0 guard makes concurrent rotations of one token lose
+        let updated = tx.execute(
+            "UPDATE refresh_tokens SET revoked = 1, rotated_at = ?1, replaced_by = ?2 WHERE token = ?3 AND revoked = 0",
+            params![now, next_digest, digest],
+        )?;
+        if updated == 0 {
+            return Err(SessionError::Invalid);
+        }
+        // the successor keeps the agent parsed, the methods used and the
+        // application signed in to when the session was created
+        tx.execute(
+            &format!(
+                "INSERT INTO refresh_tokens
+                     (token, user_id, expires_at, revoked, created_at, session_id, amr, client_id, {0})
+                 SELECT ?1, ?2, ?3, 0, ?4, ?5, amr, client_id, {0} FROM refresh_tokens WHERE token = ?6",
+                user_agent::COLUMNS
+            ),
+            params![next_digest, user_id, now + expiry_seconds, now, session_id, digest],
+        )?;
+        tx.commit()?;
+
+        Ok((
+            user_id,
+            NewSession {
+                token: next,
+                session_id,
+            },
+        ))
     }
 
     pub fn validate_refresh_token(
         db: &Database,
         token: &str,
     ) -> Result<String, SessionError> {
-        let mut stmt = db.conn.prepare(
-            "SELECT user_id, expires_at, revoked FROM refresh_tokens WHERE token = ?1",
+        let digest = crypto::token_digest(token);
+        let conn = db.conn();
+        let mut stmt = conn.prepare(
+            "SELECT token, user_id, expires_at, revoked FROM refresh_tokens WHERE token = ?1",
         )?;
-        let mut rows = stmt.query(params![token])?;
+        let mut rows = stmt.query(params![digest])?;
         if let Some(r) = rows.next()? {
-            let user_id: String = r.get(0)?;
-            let expires_at: i64 = r.get(1)?;
-            let revoked: i64 = r.get(2)?;
+            let stored: String = r.get(0)?;
+            let user_id: String = r.get(1)?;
+            let expires_at: i64 = r.get(2)?;
+            let revoked: i64 = r.get(3)?;
             let now = Database::now_ts();
-            if revoked != 0 || now > expires_at {
+            if !constant_time_eq(stored.as_bytes(), digest.as_bytes()) || revoked != 0 || now > expires_at {
                 return Err(SessionError::Invalid);
             }
             Ok(user_id)
@@ -52,9 +238,22 @@ impl Session {
     }
 
     pub fn revoke_refresh_token(db: &Database, token: &str) -> Result<(), SessionError> {
-        db.conn.execute(
+        Self::revoke_by_digest(db, &crypto::token_digest(token))
+    }
+
+    /// Revoke one of the user's sessions; returns how many tokens were live
+    pub fn revoke_session(db: &Database, user_id: &str, session_id: &str) -> Result<usize, SessionError> {
+        Ok(db.conn().execute(
+            "UPDATE refresh_tokens SET revoked = 1 WHERE session_id = ?1 AND user_id = ?2 AND revoked = 0",
+            params![session_id, user_id],
+        )?)
+    }
+
+    /// Revoke the refresh token stored under `digest`, as listed to admins
+    pub fn revoke_by_digest(db: &Database, digest: &str) -> Result<(), SessionError> {
+        db.conn().execute(
             "UPDATE refresh_tokens SET revoked = 1 WHERE token = ?1",
-            params![token],
+            params![digest],
         )?;
         Ok(())
     }
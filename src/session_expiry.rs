@@ -0,0 +1,138 @@
+//! Warnings before a user's sessions run out.
+//!
+//! Clients see the remaining lifetime in every token response and at
+//! `GET /token/status`, with `reauth_recommended` once the refresh token is
+//! inside the warning window. Optionally, a `session_expiring` webhook is
+//! emitted when a user's last live session enters the window, so relying
+//! apps can prompt a fresh sign-in before the user is logged out.
+
+use crate::{
+    audit::AuditEventType,
+    db::Database,
+    leader::LeaderElection,
+    outbox::{Outbox, OutboxError, OutboxEvent},
+    webhooks::WebhookEventType,
+};
+use rusqlite::params;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+/// `[session_expiry]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct SessionExpiryConfig {
+    /// Remaining refresh lifetime below which re-authentication is recommended
+    #[serde(default = "default_warn_before_seconds")]
+    pub warn_before_seconds: i64,
+    /// Emit `session_expiring` when a user's last session enters the window
+    #[serde(default)]
+    pub webhook: bool,
+    #[serde(default = "default_check_interval_seconds")]
+    pub check_interval_seconds: u64,
+}
+
+impl Default for SessionExpiryConfig {
+    fn default() -> Self {
+        Self {
+            warn_before_seconds: default_warn_before_seconds(),
+            webhook: false,
+            check_interval_seconds: default_check_interval_seconds(),
+        }
+    }
+}
+
+fn default_warn_before_seconds() -> i64 {
+    86400
+}
+
+fn default_check_interval_seconds() -> u64 {
+    300
+}
+
+impl SessionExpiryConfig {
+    /// Whether a refresh token with `remaining` seconds left should be replaced by a fresh sign-in
+    pub fn reauth_recommended(&self, remaining: i64) -> bool {
+        remaining <= self.warn_before_seconds
+    }
+}
+
+/// A user's last live session, about to expire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiringSession {
+    pub token: String,
+    pub user_id: String,
+    pub session_id: Option<String>,
+    pub expires_at: i64,
+}
+
+/// Live sessions expiring within `window` seconds of `now` that are their
+/// user's last one and have not been announced yet
+pub fn due(db: &Database, now: i64, window: i64) -> Result<Vec<ExpiringSession>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT r.token, r.user_id, r.session_id, r.expires_at FROM refresh_tokens r
+         WHERE r.revoked = 0 AND r.expiry_notified_at IS NULL
+           AND r.expires_at > ?1 AND r.expires_at <= ?1 + ?2
+           AND NOT EXISTS (
+               SELECT 1 FROM refresh_tokens o
+               WHERE o.user_id = r.user_id AND o.revoked = 0 AND o.expires_at > r.expires_at
+           )
+         ORDER BY r.expires_at",
+    )?;
+    let rows = stmt.query_map(params![now, window], |r| {
+        Ok(ExpiringSession {
+            token: r.get(0)?,
+            user_id: r.get(1)?,
+            session_id: r.get(2)?,
+            expires_at: r.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Queue a `session_expiring` event for every due session; returns how many
+pub fn notify_due(db: &Database, cfg: &SessionExpiryConfig) -> Result<usize, OutboxError> {
+    let now = Database::now_ts();
+    let sessions = due(db, now, cfg.warn_before_seconds)?;
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    for session in &sessions {
+        let event = OutboxEvent::new(AuditEventType::SessionExpiring)
+            .user(&session.user_id)
+            .webhook(WebhookEventType::SessionExpiring)
+            .metadata(serde_json::json!({
+                "session_id": session.session_id,
+                "expires_at": session.expires_at,
+                "expires_in": session.expires_at - now,
+            }));
+        Outbox::enqueue(&tx, &event)?;
+        tx.execute(
+            "UPDATE refresh_tokens SET expiry_notified_at = ?1 WHERE token = ?2",
+            params![now, session.token],
+        )?;
+    }
+    tx.commit()?;
+    Ok(sessions.len())
+}
+
+/// Periodically announce expiring sessions (when `webhook` is on)
+pub fn spawn_notifier(db: Arc<Database>, leader: Arc<LeaderElection>, cfg: SessionExpiryConfig) {
+    if !cfg.webhook {
+        return;
+    }
+    let every = Duration::from_secs(cfg.check_interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("session_expiry", every) {
+                continue;
+            }
+            match notify_due(&db, &cfg) {
+                Ok(0) => {}
+                Ok(n) => info!("Announced {} expiring sessions", n),
+                Err(e) => warn!("Session expiry check failed: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,430 @@
+//! Inactivity policy for accounts nobody uses anymore.
+//!
+//! The elected replica looks for accounts without a successful sign-in for
+//! `[stale_accounts] inactive_days` (accounts that never signed in count from
+//! their creation). Each one is flagged and, with `notify`, emailed. A flagged
+//! account that still has not signed in `disable_after_days` later is
+//! disabled: frozen like a self-service freeze, with its sessions revoked.
+//! With `purge_after_days` set, a disabled account is eventually deleted.
+//!
+//! Signing in while flagged reactivates the account. A disabled account can
+//! only come back through an admin (reinstate, or lifting the freeze), which
+//! also restarts its inactivity clock. Every transition is audited and the
+//! current state of each account is listed at `GET /admin/stale-accounts`.
+
+use crate::{
+    audit::AuditEventType,
+    db::{self, Database},
+    email_queue::{EmailQueue, QueueError},
+    email_templates::EmailTemplates,
+    leader::LeaderElection,
+    metrics::MetricsRecorder,
+    outbox::{Outbox, OutboxError, OutboxEvent},
+    revocation::{RevocationBus, RevocationEvent},
+    storage::{revoke_stored_sessions, SharedTokenStore},
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+use tracing::{info, warn};
+
+const DAY: i64 = 86400;
+
+/// `[stale_accounts]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct StaleAccountConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Days without a successful sign-in before an account is flagged
+    #[serde(default = "default_inactive_days")]
+    pub inactive_days: i64,
+    /// Email flagged accounts that they are about to be disabled
+    #[serde(default = "default_notify")]
+    pub notify: bool,
+    /// Days after flagging until the account is disabled; unset = never
+    #[serde(default = "default_disable_after_days")]
+    pub disable_after_days: Option<i64>,
+    /// Days after disabling until the account is deleted; unset = never
+    #[serde(default)]
+    pub purge_after_days: Option<i64>,
+    /// Accounts moved per transition and run
+    #[serde(default = "default_users_per_run")]
+    pub users_per_run: u32,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+impl Default for StaleAccountConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            inactive_days: default_inactive_days(),
+            notify: default_notify(),
+            disable_after_days: default_disable_after_days(),
+            purge_after_days: None,
+            users_per_run: default_users_per_run(),
+            interval_seconds: default_interval_seconds(),
+        }
+    }
+}
+
+fn default_inactive_days() -> i64 {
+    365
+}
+
+fn default_notify() -> bool {
+    true
+}
+
+fn default_disable_after_days() -> Option<i64> {
+    Some(30)
+}
+
+fn default_users_per_run() -> u32 {
+    100
+}
+
+fn default_interval_seconds() -> u64 {
+    3600
+}
+
+#[derive(Debug, Error)]
+pub enum StaleAccountError {
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("outbox error: {0}")]
+    Outbox(#[from] OutboxError),
+    #[error("account is {0}")]
+    NotStale(String),
+}
+
+/// An account the policy has acted on, in its current state
+#[derive(Debug, Clone, Serialize)]
+pub struct StaleAccount {
+    pub user_id: String,
+    /// `None` once purged
+    pub email: Option<String>,
+    /// `flagged`, `disabled`, `purged`, `reactivated` (signed in while
+    /// flagged) or `reinstated` (by an admin)
+    pub state: String,
+    pub last_login_at: Option<i64>,
+    pub flagged_at: i64,
+    /// Whether the flagging email was queued
+    pub notified: bool,
+    pub disabled_at: Option<i64>,
+    pub purged_at: Option<i64>,
+    pub updated_at: i64,
+}
+
+/// What one run of the policy changed
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Sweep {
+    pub reactivated: usize,
+    pub flagged: usize,
+    /// Users disabled, whose access tokens the caller should revoke
+    pub disabled: Vec<String>,
+    pub purged: usize,
+}
+
+const COLUMNS: &str = "s.user_id, u.email, s.state, u.last_login_at, s.flagged_at, s.email_id IS NOT NULL, \
+                       s.disabled_at, s.purged_at, s.updated_at";
+
+fn from_row(r: &Row) -> rusqlite::Result<StaleAccount> {
+    Ok(StaleAccount {
+        user_id: r.get(0)?,
+        email: r.get(1)?,
+        state: r.get(2)?,
+        last_login_at: r.get(3)?,
+        flagged_at: r.get(4)?,
+        notified: r.get(5)?,
+        disabled_at: r.get(6)?,
+        purged_at: r.get(7)?,
+        updated_at: r.get(8)?,
+    })
+}
+
+pub fn get(db: &Database, user_id: &str) -> Result<Option<StaleAccount>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            &format!(
+                "SELECT {} FROM stale_accounts s LEFT JOIN users u ON u.id = s.user_id WHERE s.user_id = ?1",
+                COLUMNS
+            ),
+            params![user_id],
+            from_row,
+        )
+        .optional()
+}
+
+/// Accounts in `state` (any when `None`), most recently changed first
+pub fn list(db: &Database, state: Option<&str>, limit: i64) -> Result<Vec<StaleAccount>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM stale_accounts s LEFT JOIN users u ON u.id = s.user_id
+         WHERE ?1 IS NULL OR s.state = ?1 ORDER BY s.updated_at DESC, s.user_id LIMIT ?2",
+        COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![state, limit], from_row)?;
+    rows.collect()
+}
+
+/// Note a successful sign-in, in the login's transaction
+pub fn record_login(conn: &Connection, user_id: &str, now: i64) -> Result<(), rusqlite::Error> {
+    conn.execute("UPDATE users SET last_login_at = ?2 WHERE id = ?1", params![user_id, now])?;
+    Ok(())
+}
+
+/// Move a flagged or disabled account back to active (`reactivated` when the
+/// user signed in, `reinstated` by an admin) and withdraw an unsent notice
+fn reactivate(conn: &Connection, account: &StaleAccount, by_admin: bool, now: i64) -> Result<(), OutboxError> {
+    let state = if by_admin { "reinstated" } else { "reactivated" };
+    conn.execute(
+        "DELETE FROM email_queue WHERE status = 'pending' AND id = (
+             SELECT email_id FROM stale_accounts WHERE user_id = ?1)",
+        params![account.user_id],
+    )?;
+    conn.execute(
+        "UPDATE stale_accounts SET state = ?2, updated_at = ?3 WHERE user_id = ?1",
+        params![account.user_id, state, now],
+    )?;
+    let event = OutboxEvent::new(AuditEventType::StaleAccountReactivated)
+        .user(&account.user_id)
+        .metadata(serde_json::json!({ "from": account.state, "to": state }));
+    Outbox::enqueue(conn, &event)?;
+    MetricsRecorder::record_stale_account(state);
+    Ok(())
+}
+
+/// Admin override: unfreeze a disabled account, or clear a flag, and restart
+/// its inactivity clock. `None` when the policy never acted on the user.
+pub fn reinstate(db: &Database, user_id: &str, now: i64) -> Result<Option<StaleAccount>, StaleAccountError> {
+    let Some(account) = get(db, user_id)? else {
+        return Ok(None);
+    };
+    if account.state != "flagged" && account.state != "disabled" {
+        return Err(StaleAccountError::NotStale(account.state));
+    }
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    if account.state == "disabled" {
+        tx.execute("UPDATE users SET frozen_at = NULL WHERE id = ?1", params![user_id])?;
+    }
+    reactivate(&tx, &account, true, now)?;
+    tx.commit()?;
+    Ok(get(db, user_id)?)
+}
+
+/// Accounts of existing users matching `filter` (a condition on `?1` plus
+/// ordering), at most `limit`
+fn select(db: &Database, filter: &str, cutoff: i64, limit: u32) -> Result<Vec<StaleAccount>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM stale_accounts s JOIN users u ON u.id = s.user_id WHERE {} LIMIT ?2",
+        COLUMNS, filter
+    ))?;
+    let rows = stmt.query_map(params![cutoff, limit.max(1)], from_row)?;
+    rows.collect()
+}
+
+/// Flagged users who signed in since, and disabled ones whose freeze an admin lifted
+fn reactivate_returned(db: &Database, cfg: &StaleAccountConfig, now: i64) -> Result<usize, OutboxError> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM stale_accounts s JOIN users u ON u.id = s.user_id
+         WHERE (s.state = 'flagged' AND u.last_login_at > s.flagged_at)
+            OR (s.state = 'disabled' AND u.frozen_at IS NULL)
+         LIMIT ?1",
+        COLUMNS
+    ))?;
+    let returned = stmt
+        .query_map(params![cfg.users_per_run.max(1)], from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    for account in &returned {
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        reactivate(&tx, account, account.state == "disabled", now)?;
+        tx.commit()?;
+    }
+    Ok(returned.len())
+}
+
+/// Flag accounts inactive for `inactive_days`, emailing them with `notify`
+fn flag_inactive(db: &Database, cfg: &StaleAccountConfig, now: i64) -> Result<usize, OutboxError> {
+    let cutoff = now - cfg.inactive_days * DAY;
+    // reactivated and reinstated accounts count from when they came back
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT u.id, u.email, u.last_login_at FROM users u LEFT JOIN stale_accounts s ON s.user_id = u.id
+         WHERE u.frozen_at IS NULL AND COALESCE(u.last_login_at, u.created_at) < ?1
+           AND (s.user_id IS NULL OR (s.state IN ('reactivated', 'reinstated') AND s.updated_at < ?1))
+         ORDER BY COALESCE(u.last_login_at, u.created_at) LIMIT ?2",
+    )?;
+    let due = stmt
+        .query_map(params![cutoff, cfg.users_per_run.max(1)], |r| {
+            Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, Option<i64>>(2)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (user_id, email, last_login_at) in &due {
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        // a suppressed address is flagged without an email
+        let mut email_id = None;
+        if cfg.notify {
+            let rendered = EmailTemplates::stale_account(
+                email,
+                cfg.inactive_days,
+                cfg.disable_after_days,
+                cfg.purge_after_days.is_some(),
+            );
+            match EmailQueue::enqueue(db, email, &rendered.subject, &rendered.text, Some(&rendered.html)) {
+                Ok(id) => email_id = Some(id),
+                Err(QueueError::Suppressed) => {}
+                Err(QueueError::Db(e)) => return Err(e.into()),
+            }
+        }
+        tx.execute(
+            "INSERT INTO stale_accounts (user_id, state, flagged_at, email_id, updated_at)
+             VALUES (?1, 'flagged', ?2, ?3, ?2)
+             ON CONFLICT(user_id) DO UPDATE SET state = 'flagged', flagged_at = ?2, email_id = ?3,
+                 disabled_at = NULL, updated_at = ?2",
+            params![user_id, now, email_id],
+        )?;
+        let event = OutboxEvent::new(AuditEventType::StaleAccountFlagged)
+            .user(user_id)
+            .metadata(serde_json::json!({
+                "last_login_at": last_login_at,
+                "inactive_days": cfg.inactive_days,
+                "notified": email_id.is_some(),
+            }));
+        Outbox::enqueue(&tx, &event)?;
+        tx.commit()?;
+        MetricsRecorder::record_stale_account("flagged");
+    }
+    Ok(due.len())
+}
+
+/// Freeze accounts flagged `disable_after_days` ago and revoke their sessions
+fn disable_flagged(db: &Database, cfg: &StaleAccountConfig, now: i64) -> Result<Vec<String>, OutboxError> {
+    let Some(days) = cfg.disable_after_days else {
+        return Ok(Vec::new());
+    };
+    let due = select(
+        db,
+        "s.state = 'flagged' AND s.flagged_at <= ?1 AND COALESCE(u.last_login_at, 0) <= s.flagged_at
+         ORDER BY s.flagged_at",
+        now - days * DAY,
+        cfg.users_per_run,
+    )?;
+    for account in &due {
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE users SET frozen_at = COALESCE(frozen_at, ?2) WHERE id = ?1",
+            params![account.user_id, now],
+        )?;
+        let sessions = tx.execute(
+            "UPDATE refresh_tokens SET revoked = 1 WHERE user_id = ?1 AND revoked = 0",
+            params![account.user_id],
+        )?;
+        tx.execute(
+            "UPDATE stale_accounts SET state = 'disabled', disabled_at = ?2, updated_at = ?2 WHERE user_id = ?1",
+            params![account.user_id, now],
+        )?;
+        let event = OutboxEvent::new(AuditEventType::StaleAccountDisabled)
+            .user(&account.user_id)
+            .metadata(serde_json::json!({ "flagged_at": account.flagged_at, "sessions_revoked": sessions }));
+        Outbox::enqueue(&tx, &event)?;
+        tx.commit()?;
+        MetricsRecorder::record_stale_account("disabled");
+    }
+    Ok(due.into_iter().map(|a| a.user_id).collect())
+}
+
+/// Delete accounts disabled `purge_after_days` ago that are still frozen
+fn purge_disabled(db: &Database, cfg: &StaleAccountConfig, now: i64) -> Result<usize, OutboxError> {
+    let Some(days) = cfg.purge_after_days else {
+        return Ok(0);
+    };
+    let due = select(
+        db,
+        "s.state = 'disabled' AND s.disabled_at <= ?1 AND u.frozen_at IS NOT NULL ORDER BY s.disabled_at",
+        now - days * DAY,
+        cfg.users_per_run,
+    )?;
+    for account in &due {
+        let conn = db.conn();
+        let tx = conn.unchecked_transaction()?;
+        db::delete_user(&tx, &account.user_id)?;
+        tx.execute(
+            "UPDATE stale_accounts SET state = 'purged', email_id = NULL, purged_at = ?2, updated_at = ?2
+             WHERE user_id = ?1",
+            params![account.user_id, now],
+        )?;
+        // the user row is gone, so the id is only named in the metadata
+        let event = OutboxEvent::new(AuditEventType::StaleAccountPurged).metadata(serde_json::json!({
+            "user_id": account.user_id,
+            "flagged_at": account.flagged_at,
+            "disabled_at": account.disabled_at,
+        }));
+        Outbox::enqueue(&tx, &event)?;
+        tx.commit()?;
+        MetricsRecorder::record_stale_account("purged");
+    }
+    Ok(due.len())
+}
+
+/// Apply the policy once: reactivate returning accounts, then flag, disable
+/// and purge, at most `users_per_run` accounts per step
+pub fn run(db: &Database, cfg: &StaleAccountConfig, now: i64) -> Result<Sweep, OutboxError> {
+    Ok(Sweep {
+        reactivated: reactivate_returned(db, cfg, now)?,
+        flagged: flag_inactive(db, cfg, now)?,
+        disabled: disable_flagged(db, cfg, now)?,
+        purged: purge_disabled(db, cfg, now)?,
+    })
+}
+
+/// Apply the policy on the elected replica every `interval_seconds`,
+/// revoking the access tokens of disabled users
+pub fn spawn_runner(
+    db: Arc<Database>,
+    leader: Arc<LeaderElection>,
+    revocations: Arc<RevocationBus>,
+    tokens: Option<SharedTokenStore>,
+    cfg: StaleAccountConfig,
+) {
+    if !cfg.enabled {
+        return;
+    }
+    let every = Duration::from_secs(cfg.interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if !leader.lead("stale_accounts", every) {
+                continue;
+            }
+            let now = Database::now_ts();
+            match run(&db, &cfg, now) {
+                Ok(sweep) => {
+                    if sweep != Sweep::default() {
+                        info!(
+                            "Inactive accounts: {} flagged, {} disabled, {} purged, {} reactivated",
+                            sweep.flagged,
+                            sweep.disabled.len(),
+                            sweep.purged,
+                            sweep.reactivated
+                        );
+                    }
+                    for user_id in sweep.disabled {
+                        revoke_stored_sessions(tokens.as_deref(), &user_id);
+                        revocations.publish(RevocationEvent::UserDisabled { user_id, at: now });
+                    }
+                }
+                Err(e) => warn!("Failed to apply the inactive account policy: {}", e),
+            }
+        }
+    });
+}
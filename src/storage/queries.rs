@@ -0,0 +1,132 @@
+//! SQL of the SQLite storage layer, checked when the crate is built.
+//!
+//! `build.rs` applies the migrations listed in `db::MIGRATIONS` to an
+//! in-memory database and prepares every statement in [`ALL`] against it, so
+//! a query naming a table or column the migrations do not create fails the
+//! build instead of answering requests with 500s. The build script includes
+//! this file on its own: it must not refer to the rest of the crate.
+//!
+//! Covers the repositories (`repository`), which the auth service goes
+//! through for users, and the SQLite passkey store (`storage::sqlite`). The
+//! subsystems owning their tables (`audit`, `outbox`, `session`, ...) keep
+//! their SQL inline and are not checked, nor are statements assembled at
+//! runtime (`db::delete_user`); the live database is still compared with the
+//! migrations at startup (`schema`).
+
+macro_rules! user_columns {
+    () => {
+        "id, email, totp_secret, created_at"
+    };
+}
+
+macro_rules! refresh_columns {
+    () => {
+        "r.token, r.user_id, r.session_id, r.expires_at, r.revoked, r.created_at"
+    };
+}
+
+macro_rules! agent_columns {
+    () => {
+        "ua_browser, ua_browser_version, ua_os, ua_os_version, ua_device"
+    };
+}
+
+macro_rules! pending_magic_links {
+    () => {
+        "SELECT 'ml_' || m.rowid, 'magic_link', m.user_id, u.email, m.created_at, m.expires_at
+         FROM magic_links m JOIN users u ON u.id = m.user_id"
+    };
+}
+
+macro_rules! pending_ceremonies {
+    () => {
+        "SELECT p.id, p.purpose, p.user_id, u.email, p.created_at, p.expires_at
+         FROM pending_webauthn p JOIN users u ON u.id = p.user_id"
+    };
+}
+
+macro_rules! queries {
+    ($($(#[$doc:meta])* $name:ident = $sql:expr;)*) => {
+        $($(#[$doc])* pub const $name: &str = $sql;)*
+
+        /// Every statement by name, for the build-time check
+        pub const ALL: &[(&str, &str)] = &[$((stringify!($name), $name)),*];
+    };
+}
+
+/// Columns of `refresh_tokens` holding the parsed agent, in `UserAgent::from_row` order
+pub const AGENT_COLUMNS: &str = agent_columns!();
+
+queries! {
+    USER_BY_ID = concat!("SELECT ", user_columns!(), " FROM users WHERE id = ?1");
+    USER_BY_EMAIL = concat!("SELECT ", user_columns!(), " FROM users WHERE email = ?1");
+    /// Newest first, paged
+    USERS_PAGE = concat!("SELECT ", user_columns!(), " FROM users ORDER BY created_at DESC LIMIT ?1 OFFSET ?2");
+    USER_COUNT = "SELECT COUNT(*) FROM users";
+    USERS_VERSION = "SELECT COUNT(*), MAX(updated_at) FROM users";
+    SET_USER_ROLE = "UPDATE users SET role = ?1 WHERE id = ?2";
+    USER_EXISTS = "SELECT EXISTS(SELECT 1 FROM users WHERE id = ?1)";
+    UNFREEZE_USER = "UPDATE users SET frozen_at = NULL WHERE id = ?1 AND frozen_at IS NOT NULL";
+    /// Keeps the time of an earlier freeze
+    FREEZE_USER = "UPDATE users SET frozen_at = COALESCE(frozen_at, ?1) WHERE id = ?2";
+    USER_FROZEN_AT = "SELECT frozen_at FROM users WHERE id = ?1";
+    USER_ROLE = "SELECT role FROM users WHERE id = ?1";
+    VERIFY_USER_EMAIL = "UPDATE users SET email_verified_at = ?1 WHERE id = ?2";
+    CHANGE_USER_EMAIL = "UPDATE users SET email = ?1, email_verified_at = ?2 WHERE id = ?3";
+    /// First enrollment only
+    ENROLL_TOTP = "UPDATE users SET totp_secret = ?1 WHERE id = ?2 AND totp_secret IS NULL";
+    /// Compare-and-swap on the current secret; `?1` NULL disables TOTP
+    REPLACE_TOTP = "UPDATE users SET totp_secret = ?1 WHERE id = ?2 AND totp_secret = ?3";
+    CLEAR_TOTP_ATTEMPTS = "DELETE FROM totp_attempts WHERE user_id = ?1";
+    DEVICE_COUNT_OF_USER = "SELECT COUNT(*) FROM user_devices WHERE user_id = ?1";
+
+    /// Refresh tokens with their device hints and parsed agent, newest first
+    SESSIONS_OF_USER = concat!(
+        "SELECT ",
+        refresh_columns!(),
+        ", d.hints, ",
+        agent_columns!(),
+        " FROM refresh_tokens r LEFT JOIN session_devices d ON d.session_id = r.session_id
+         WHERE r.user_id = ?1 ORDER BY r.created_at DESC"
+    );
+    SESSIONS_VERSION = "SELECT COUNT(*), COALESCE(SUM(revoked), 0), COALESCE(MAX(created_at), 0)
+         FROM refresh_tokens WHERE user_id = ?1";
+    REFRESH_TOKEN_BY_DIGEST = concat!("SELECT ", refresh_columns!(), " FROM refresh_tokens r WHERE r.token = ?1");
+    REVOKE_USER_REFRESH_TOKENS = "UPDATE refresh_tokens SET revoked = 1 WHERE user_id = ?1 AND revoked = 0";
+    REFRESH_TOKEN_COUNT = "SELECT COUNT(*) FROM refresh_tokens";
+    /// The live refresh token of session `?1`
+    LIVE_SESSION = "SELECT user_id, expires_at, client_id, amr FROM refresh_tokens
+         WHERE session_id = ?1 AND revoked = 0 AND expires_at > ?2";
+    ACTIVE_REFRESH_TOKEN_COUNT = "SELECT COUNT(*) FROM refresh_tokens WHERE revoked = 0 AND expires_at > ?1";
+
+    PASSKEY_COUNT_OF_USER = "SELECT COUNT(*) FROM webauthn_registrations WHERE user_id = ?1";
+    INSERT_PASSKEY = "INSERT INTO webauthn_registrations
+             (id, user_id, credential_id, public_key, sign_count, transports, aaguid, name, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
+    PASSKEYS_OF_USER = "SELECT public_key FROM webauthn_registrations WHERE user_id = ?1";
+    PASSKEY_BY_CREDENTIAL_ID = "SELECT id, user_id, sign_count FROM webauthn_registrations WHERE credential_id = ?1";
+    /// Only moves `sign_count` forward
+    RECORD_ASSERTION = "UPDATE webauthn_registrations
+         SET sign_count = ?1, last_used_at = ?2, use_count = use_count + 1, last_ip = COALESCE(?3, last_ip)
+         WHERE id = ?4 AND sign_count < ?1";
+
+    /// Unused magic links and WebAuthn ceremonies (of user `?2`, or everyone), latest expiry first
+    PENDING_CHALLENGES = concat!(
+        pending_magic_links!(),
+        "
+         WHERE m.used = 0 AND m.expires_at >= ?1 AND (?2 IS NULL OR m.user_id = ?2)
+         UNION ALL
+         ",
+        pending_ceremonies!(),
+        "
+         WHERE p.expires_at >= ?1 AND (?2 IS NULL OR p.user_id = ?2)
+         ORDER BY 6 DESC LIMIT ?3"
+    );
+    PENDING_MAGIC_LINK = concat!(pending_magic_links!(), " WHERE m.rowid = ?1 AND m.used = 0 AND m.expires_at >= ?2");
+    PENDING_CEREMONY = concat!(pending_ceremonies!(), " WHERE p.id = ?1 AND p.expires_at >= ?2");
+    EXPIRE_MAGIC_LINK = "UPDATE magic_links SET expires_at = ?1 WHERE rowid = ?2";
+    DELETE_CEREMONY = "DELETE FROM pending_webauthn WHERE id = ?1";
+    EXPIRE_USER_MAGIC_LINKS =
+        "UPDATE magic_links SET expires_at = ?1 WHERE user_id = ?2 AND used = 0 AND expires_at >= ?3";
+    DELETE_USER_CEREMONIES = "DELETE FROM pending_webauthn WHERE user_id = ?1 AND expires_at >= ?2";
+}
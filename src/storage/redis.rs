@@ -0,0 +1,801 @@
+//! Magic links, refresh tokens and pending WebAuthn challenges in Redis
+//! (`store = "redis"`), shared by every replica pointed at the same server.
+//!
+//! Each record is a hash under `{key_prefix}{kind}:{id}` that expires
+//! [`GRACE_SECONDS`] after the record itself, so expired entries are removed
+//! by Redis instead of a purge job; until then they still read as expired
+//! rather than unknown. Magic links and refresh tokens are keyed by their
+//! [`crypto::token_digest`], never the token itself. Per-user indexes (`magic_links:{user_id}`,
+//! `refresh_tokens:{user_id}`) back the issuance policies and mass
+//! revocation; `session:{session_id}` holds the digest of the session's
+//! current refresh token. Steps that must not interleave across replicas run as Lua
+//! scripts, which Redis executes atomically.
+//!
+//! The client speaks RESP over one blocking connection, reopened after an
+//! error; like the PostgreSQL backend it runs under `block_in_place` when
+//! called from a tokio worker.
+
+use super::{MagicLinkStore, RefreshSession, RefreshTokenStore, SessionListing, StorageError};
+use crate::{
+    challenge_store::{Challenge, ChallengeStore, Purpose},
+    crypto,
+    db::Database,
+    magic_link::{IssuePolicy, IssuedLink, LinkBinding, LinkProof, MagicLinkIssuanceConfig, MAX_FAILED_PROOFS},
+    session::NewSession,
+    user_agent::UserAgent,
+};
+use data_encoding::BASE64URL_NOPAD;
+use redis::{ConnectionAddr, IntoConnectionInfo};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// Seconds a record outlives its own expiry before Redis drops it
+pub const GRACE_SECONDS: i64 = 60;
+
+/// `[redis]` configuration, used when `store = "redis"`
+#[derive(Debug, Deserialize, Clone)]
+pub struct RedisStoreConfig {
+    /// `redis://[:password@]host:port[/db]`
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Prepended to every key, so several deployments can share one server
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// Connect, read and write timeout
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for RedisStoreConfig {
+    fn default() -> Self {
+        Self {
+            url: default_url(),
+            key_prefix: default_key_prefix(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+}
+
+fn default_url() -> String {
+    "redis://127.0.0.1:6379".to_string()
+}
+
+fn default_key_prefix() -> String {
+    "passwordless:".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+/// A decoded RESP reply; errors surface as `io::Error` instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Reply {
+    Nil,
+    Int(i64),
+    Data(String),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn int(&self) -> i64 {
+        match self {
+            Self::Int(n) => *n,
+            Self::Data(s) => s.parse().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn into_string(self) -> Option<String> {
+        match self {
+            Self::Data(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn into_array(self) -> Vec<Reply> {
+        match self {
+            Self::Array(items) => items,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Field map of an `HGETALL` reply; empty when the key does not exist
+    fn into_fields(self) -> HashMap<String, String> {
+        let mut items = self.into_array().into_iter().filter_map(Reply::into_string);
+        let mut fields = HashMap::new();
+        while let (Some(field), Some(value)) = (items.next(), items.next()) {
+            fields.insert(field, value);
+        }
+        fields
+    }
+}
+
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut cmd = format!("*{}\r\n", args.len());
+    for arg in args {
+        cmd.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    cmd.into_bytes()
+}
+
+fn read_reply(conn: &mut impl BufRead) -> io::Result<Reply> {
+    let mut line = String::new();
+    if conn.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at(1.min(line.len()));
+    let len = || rest.parse::<i64>().map_err(io::Error::other);
+    match kind {
+        "+" => Ok(Reply::Data(rest.to_string())),
+        ":" => Ok(Reply::Int(len()?)),
+        "-" => Err(io::Error::other(format!("redis error: {}", rest))),
+        "$" => {
+            let len = len()?;
+            if len < 0 {
+                return Ok(Reply::Nil);
+            }
+            let mut buf = vec![0; len as usize + 2];
+            conn.read_exact(&mut buf)?;
+            buf.truncate(len as usize);
+            Ok(Reply::Data(String::from_utf8(buf).map_err(io::Error::other)?))
+        }
+        "*" => {
+            let len = len()?;
+            if len < 0 {
+                return Ok(Reply::Nil);
+            }
+            (0..len).map(|_| read_reply(conn)).collect::<io::Result<_>>().map(Reply::Array)
+        }
+        _ => Err(io::Error::other(format!("unexpected reply {:?}", line))),
+    }
+}
+
+/// `HSET` the field/value pairs after ARGV[1] and expire the hash at ARGV[1]
+const PUT: &str = "redis.call('HSET', KEYS[1], unpack(ARGV, 2))
+redis.call('EXPIREAT', KEYS[1], ARGV[1])";
+
+/// Expire the challenge if live; 1 when it was
+const EXPIRE_CHALLENGE: &str = "local expires_at = tonumber(redis.call('HGET', KEYS[1], 'expires_at'))
+if expires_at and expires_at >= tonumber(ARGV[1]) then
+    redis.call('HSET', KEYS[1], 'expires_at', ARGV[1] - 1)
+    return 1
+end
+return 0";
+
+/// Move a matching live link to the new digest ARGV[2] (resend) or store a
+/// new one, then expire the oldest live links beyond ARGV[7]. Returns
+/// `{flow_id, resent}`.
+const ISSUE_MAGIC_LINK: &str = "local prefix, now = ARGV[1], tonumber(ARGV[4])
+local since, keep = tonumber(ARGV[6]), tonumber(ARGV[7])
+local binding = {'client_id', 'redirect_uri', 'code_challenge', 'state_hash'}
+local tokens = redis.call('ZREVRANGE', KEYS[1], 0, -1)
+local function live(link)
+    return link[1] == '0' and tonumber(link[2]) >= now
+end
+if since >= 0 then
+    for _, token in ipairs(tokens) do
+        local link = redis.call('HMGET', prefix .. token, 'used', 'expires_at', 'created_at', 'flow_id',
+            unpack(binding))
+        if link[1] and live(link) and tonumber(link[3]) >= since then
+            local same = true
+            for i = 1, 4 do
+                if (link[4 + i] or '') ~= ARGV[7 + i] then same = false end
+            end
+            if same then
+                local seq = redis.call('ZSCORE', KEYS[1], token)
+                redis.call('RENAME', prefix .. token, prefix .. ARGV[2])
+                redis.call('ZREM', KEYS[1], token)
+                redis.call('ZADD', KEYS[1], seq, ARGV[2])
+                return {link[4], 1}
+            end
+        end
+    end
+end
+local fields = {'user_id', ARGV[12], 'expires_at', ARGV[5], 'used', '0', 'created_at', ARGV[4], 'flow_id', ARGV[3]}
+for i = 1, 4 do
+    if ARGV[7 + i] ~= '' then
+        table.insert(fields, binding[i])
+        table.insert(fields, ARGV[7 + i])
+    end
+end
+redis.call('HSET', prefix .. ARGV[2], unpack(fields))
+redis.call('EXPIREAT', prefix .. ARGV[2], ARGV[5] + ARGV[13])
+redis.call('ZADD', KEYS[1], redis.call('INCR', KEYS[2]), ARGV[2])
+redis.call('EXPIREAT', KEYS[1], ARGV[5] + ARGV[13])
+local live_links = 1
+for _, token in ipairs(tokens) do
+    local link = redis.call('HMGET', prefix .. token, 'used', 'expires_at')
+    if not link[1] then
+        redis.call('ZREM', KEYS[1], token)
+    elseif live(link) then
+        live_links = live_links + 1
+        if keep > 0 and live_links > keep then
+            redis.call('HSET', prefix .. token, 'expires_at', now - 1)
+        end
+    end
+end
+return {ARGV[3], 0}";
+
+/// Mark a link used: 1 done, 0 already used, -1 unknown
+const USE_MAGIC_LINK: &str = "local used = redis.call('HGET', KEYS[1], 'used')
+if not used then return -1 end
+if used == '1' then return 0 end
+redis.call('HSET', KEYS[1], 'used', '1')
+return 1";
+
+/// Count a wrong proof for a link, expiring it at ARGV[1] failures (ARGV[2]
+/// is the past expiry); -1 when the link is gone
+const FAIL_MAGIC_LINK: &str = "if redis.call('EXISTS', KEYS[1]) == 0 then return -1 end
+local failed = redis.call('HINCRBY', KEYS[1], 'failed_proofs', 1)
+if failed >= tonumber(ARGV[1]) then redis.call('HSET', KEYS[1], 'expires_at', ARGV[2]) end
+return failed";
+
+/// Store a refresh token, index it under its user and point its session
+/// (KEYS[3]) at it; ARGV[7] is the session's application and ARGV[9] its
+/// user agent as JSON, if any
+const PUT_REFRESH_TOKEN: &str = "redis.call('HSET', KEYS[1], 'user_id', ARGV[2], 'session_id', ARGV[3],
+    'expires_at', ARGV[4], 'revoked', '0', 'created_at', ARGV[5], 'amr', ARGV[8])
+if ARGV[7] ~= '' then
+    redis.call('HSET', KEYS[1], 'client_id', ARGV[7])
+end
+if ARGV[9] ~= '' then
+    redis.call('HSET', KEYS[1], 'agent', ARGV[9])
+end
+redis.call('EXPIREAT', KEYS[1], ARGV[4] + ARGV[6])
+redis.call('SET', KEYS[3], ARGV[1])
+redis.call('EXPIREAT', KEYS[3], ARGV[4] + ARGV[6])
+redis.call('SADD', KEYS[2], ARGV[1])
+if redis.call('TTL', KEYS[2]) < ARGV[4] + ARGV[6] - ARGV[5] then
+    redis.call('EXPIREAT', KEYS[2], ARGV[4] + ARGV[6])
+end";
+
+/// Revoke a live token and store its successor in the same session;
+/// returns `{user_id, session_id}`, or nil when the token is not live
+const ROTATE_REFRESH_TOKEN: &str = "local token = redis.call('HMGET', KEYS[1], 'user_id', 'session_id', 'expires_at',
+    'revoked', 'client_id', 'amr', 'agent')
+if token[4] ~= '0' or tonumber(token[3]) <= tonumber(ARGV[2]) then return nil end
+redis.call('HSET', KEYS[1], 'revoked', '1', 'rotated_at', ARGV[2], 'replaced_by', ARGV[1])
+local next_key, index = ARGV[4] .. 'refresh_token:' .. ARGV[1], ARGV[4] .. 'refresh_tokens:' .. token[1]
+redis.call('HSET', next_key, 'user_id', token[1], 'session_id', token[2], 'expires_at', ARGV[3], 'revoked', '0',
+    'created_at', ARGV[2])
+if token[5] then
+    redis.call('HSET', next_key, 'client_id', token[5])
+end
+if token[6] then
+    redis.call('HSET', next_key, 'amr', token[6])
+end
+if token[7] then
+    redis.call('HSET', next_key, 'agent', token[7])
+end
+redis.call('EXPIREAT', next_key, ARGV[3] + ARGV[5])
+local session_key = ARGV[4] .. 'session:' .. token[2]
+redis.call('SET', session_key, ARGV[1])
+redis.call('EXPIREAT', session_key, ARGV[3] + ARGV[5])
+redis.call('SADD', index, ARGV[1])
+if redis.call('TTL', index) < ARGV[3] + ARGV[5] - ARGV[2] then
+    redis.call('EXPIREAT', index, ARGV[3] + ARGV[5])
+end
+return {token[1], token[2]}";
+
+/// `amr` of the live token of session ARGV[2] in the index, or nil
+const LIVE_SESSION_AMR: &str = "for _, token in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local state = redis.call('HMGET', ARGV[1] .. token, 'session_id', 'revoked', 'expires_at', 'amr')
+    if state[1] == ARGV[2] and state[2] == '0' and tonumber(state[3]) > tonumber(ARGV[3]) then
+        return state[4] or '[]'
+    end
+end
+return nil";
+
+/// `{session_id, created_at, expires_at, agent}` of every live token in the
+/// index, flattened; `agent` is '' when none was recorded
+const LIVE_SESSIONS: &str = "local sessions = {}
+for _, token in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local state = redis.call('HMGET', ARGV[1] .. token, 'session_id', 'revoked', 'expires_at', 'created_at', 'agent')
+    if state[2] == '0' and tonumber(state[3]) > tonumber(ARGV[2]) then
+        table.insert(sessions, state[1])
+        table.insert(sessions, state[4])
+        table.insert(sessions, state[3])
+        table.insert(sessions, state[5] or '')
+    end
+end
+return sessions";
+
+/// Revoke a token if it exists
+const REVOKE_REFRESH_TOKEN: &str = "if redis.call('EXISTS', KEYS[1]) == 1 then
+    redis.call('HSET', KEYS[1], 'revoked', '1')
+end";
+
+/// Revoke every unrevoked token in the index, or only those of session
+/// ARGV[2] when given, dropping expired members; returns how many were
+/// revoked
+const REVOKE_USER_REFRESH_TOKENS: &str = "local revoked = 0
+for _, token in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local key = ARGV[1] .. token
+    local state = redis.call('HMGET', key, 'revoked', 'session_id')
+    if not state[1] then
+        redis.call('SREM', KEYS[1], token)
+    elseif state[1] == '0' and (ARGV[2] == '' or state[2] == ARGV[2]) then
+        redis.call('HSET', key, 'revoked', '1')
+        revoked = revoked + 1
+    end
+end
+return revoked";
+
+pub struct RedisStore {
+    addr: String,
+    password: Option<String>,
+    db: Option<u32>,
+    prefix: String,
+    timeout: Duration,
+    conn: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+impl RedisStore {
+    /// A store for `cfg`; connects on first use
+    pub fn new(cfg: &RedisStoreConfig) -> Result<Self, StorageError> {
+        let invalid = || StorageError::Redis(io::Error::other(format!("invalid redis url {:?}", cfg.url)));
+        let info = cfg.url.as_str().into_connection_info().map_err(|_| invalid())?;
+        // The store speaks plain RESP over TCP, so TLS and unix sockets are refused
+        let ConnectionAddr::Tcp(host, port) = info.addr else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            addr: format!("{}:{}", host, port),
+            password: info.redis.password,
+            db: Some(u32::try_from(info.redis.db).map_err(|_| invalid())?).filter(|db| *db != 0),
+            prefix: cfg.key_prefix.clone(),
+            timeout: Duration::from_millis(cfg.timeout_ms.max(1)),
+            conn: Mutex::new(None),
+        })
+    }
+
+    fn key(&self, kind: &str, id: &str) -> String {
+        format!("{}{}:{}", self.prefix, kind, id)
+    }
+
+    fn connect(&self) -> io::Result<BufReader<TcpStream>> {
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("{} does not resolve", self.addr)))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut conn = BufReader::new(stream);
+        if let Some(password) = &self.password {
+            send(&mut conn, &["AUTH", password])?;
+        }
+        if let Some(db) = self.db {
+            send(&mut conn, &["SELECT", &db.to_string()])?;
+        }
+        Ok(conn)
+    }
+
+    fn command(&self, args: &[&str]) -> Result<Reply, StorageError> {
+        let run = || {
+            let mut conn = self.conn.lock().unwrap();
+            if conn.is_none() {
+                *conn = Some(self.connect()?);
+            }
+            let reply = send(conn.as_mut().unwrap(), args);
+            if reply.is_err() {
+                // a failed exchange may leave a reply unread; reconnect next time
+                *conn = None;
+            }
+            reply
+        };
+        let reply = match tokio::runtime::Handle::try_current() {
+            Ok(_) => tokio::task::block_in_place(run),
+            Err(_) => run(),
+        };
+        Ok(reply?)
+    }
+
+    fn eval(&self, script: &str, keys: &[&str], args: &[&str]) -> Result<Reply, StorageError> {
+        let numkeys = keys.len().to_string();
+        let mut cmd = vec!["EVAL", script, &numkeys];
+        cmd.extend_from_slice(keys);
+        cmd.extend_from_slice(args);
+        self.command(&cmd)
+    }
+
+    fn fields(&self, key: &str) -> Result<HashMap<String, String>, StorageError> {
+        Ok(self.command(&["HGETALL", key])?.into_fields())
+    }
+}
+
+fn send(conn: &mut BufReader<TcpStream>, args: &[&str]) -> io::Result<Reply> {
+    conn.get_mut().write_all(&encode(args))?;
+    read_reply(conn)
+}
+
+/// `amr` values stored as a JSON array; tokens from before sessions recorded them have none
+fn parse_amr(amr: Option<&str>) -> Vec<String> {
+    amr.and_then(|amr| serde_json::from_str(amr).ok()).unwrap_or_default()
+}
+
+fn number(fields: &HashMap<String, String>, field: &str) -> i64 {
+    fields.get(field).and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+fn decode(fields: &HashMap<String, String>, field: &str) -> Vec<u8> {
+    fields
+        .get(field)
+        .and_then(|v| BASE64URL_NOPAD.decode(v.as_bytes()).ok())
+        .unwrap_or_default()
+}
+
+impl ChallengeStore for RedisStore {
+    fn put(&self, _db: &Database, challenge: Challenge) -> Result<(), StorageError> {
+        let key = self.key("challenge", &challenge.id);
+        self.eval(
+            PUT,
+            &[&key],
+            &[
+                &(challenge.expires_at + GRACE_SECONDS).to_string(),
+                "user_id",
+                &challenge.user_id,
+                "purpose",
+                challenge.purpose.as_str(),
+                "challenge",
+                &BASE64URL_NOPAD.encode(&challenge.challenge),
+                "options",
+                &BASE64URL_NOPAD.encode(&challenge.options),
+                "created_at",
+                &challenge.created_at.to_string(),
+                "expires_at",
+                &challenge.expires_at.to_string(),
+                "client_id",
+                challenge.client_id.as_deref().unwrap_or_default(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, _db: &Database, id: &str, purpose: Purpose) -> Result<Option<Challenge>, StorageError> {
+        let fields = self.fields(&self.key("challenge", id))?;
+        if fields.get("purpose").map(String::as_str) != Some(purpose.as_str()) {
+            return Ok(None);
+        }
+        Ok(Some(Challenge {
+            id: id.to_string(),
+            user_id: fields.get("user_id").cloned().unwrap_or_default(),
+            purpose,
+            challenge: decode(&fields, "challenge"),
+            options: decode(&fields, "options"),
+            created_at: number(&fields, "created_at"),
+            expires_at: number(&fields, "expires_at"),
+            client_id: fields.get("client_id").filter(|id| !id.is_empty()).cloned(),
+        }))
+    }
+
+    fn consume(&self, _db: &Database, id: &str) -> Result<bool, StorageError> {
+        Ok(self.command(&["DEL", &self.key("challenge", id)])?.int() > 0)
+    }
+
+    fn expire_all(&self, _db: &Database, now: i64) -> Result<usize, StorageError> {
+        let pattern = self.key("challenge", "*");
+        let (mut cursor, mut expired) = ("0".to_string(), 0);
+        loop {
+            let mut page = self
+                .command(&["SCAN", &cursor, "MATCH", &pattern, "COUNT", "100"])?
+                .into_array()
+                .into_iter();
+            cursor = page.next().and_then(Reply::into_string).unwrap_or_default();
+            for key in page.next().map(Reply::into_array).unwrap_or_default() {
+                if let Some(key) = key.into_string() {
+                    expired += self.eval(EXPIRE_CHALLENGE, &[&key], &[&now.to_string()])?.int() as usize;
+                }
+            }
+            if cursor == "0" || cursor.is_empty() {
+                return Ok(expired);
+            }
+        }
+    }
+}
+
+impl MagicLinkStore for RedisStore {
+    fn issue_magic_link(
+        &self,
+        user_id: &str,
+        expiry_seconds: i64,
+        cfg: &MagicLinkIssuanceConfig,
+        client: Option<(&str, Option<&str>)>,
+        binding: &LinkBinding,
+    ) -> Result<IssuedLink, StorageError> {
+        let now = Database::now_ts();
+        let (client_id, redirect_uri) = client.map_or((None, None), |(id, uri)| (Some(id), uri));
+        let since = match cfg.policy {
+            IssuePolicy::Resend => now - cfg.resend_window_seconds,
+            _ => -1,
+        };
+        let keep = match cfg.policy {
+            IssuePolicy::Replace => 1,
+            _ => i64::from(cfg.max_outstanding),
+        };
+        let token = Uuid::new_v4().to_string();
+        let mut reply = self
+            .eval(
+                ISSUE_MAGIC_LINK,
+                &[&self.key("magic_links", user_id), &self.key("magic_link_seq", "all")],
+                &[
+                    &self.key("magic_link", ""),
+                    &crypto::token_digest(&token),
+                    &Uuid::new_v4().to_string(),
+                    &now.to_string(),
+                    &(now + expiry_seconds).to_string(),
+                    &since.to_string(),
+                    &keep.to_string(),
+                    client_id.unwrap_or(""),
+                    redirect_uri.unwrap_or(""),
+                    binding.code_challenge().unwrap_or(""),
+                    binding.state_hash().unwrap_or(""),
+                    user_id,
+                    &GRACE_SECONDS.to_string(),
+                ],
+            )?
+            .into_array()
+            .into_iter();
+        Ok(IssuedLink {
+            token,
+            flow_id: reply.next().and_then(Reply::into_string).unwrap_or_default(),
+            resent: reply.next().map(|r| r.int()) == Some(1),
+        })
+    }
+
+    fn consume_magic_link(&self, token: &str, proof: &LinkProof) -> Result<String, StorageError> {
+        let key = self.key("magic_link", &crypto::token_digest(token));
+        let fields = self.fields(&key)?;
+        let user_id = fields.get("user_id").cloned().ok_or(StorageError::Invalid)?;
+        if fields.get("used").map(String::as_str) == Some("1") {
+            return Err(StorageError::Used);
+        }
+        if Database::now_ts() > number(&fields, "expires_at") {
+            return Err(StorageError::Invalid);
+        }
+        let binding = LinkBinding::stored(fields.get("code_challenge").cloned(), fields.get("state_hash").cloned());
+        if !binding.verify(proof) {
+            let past = (Database::now_ts() - 1).to_string();
+            self.eval(FAIL_MAGIC_LINK, &[&key], &[&MAX_FAILED_PROOFS.to_string(), &past])?;
+            return Err(StorageError::BindingMismatch);
+        }
+        match self.eval(USE_MAGIC_LINK, &[&key], &[])?.int() {
+            1 => Ok(user_id),
+            0 => Err(StorageError::Used),
+            _ => Err(StorageError::Invalid),
+        }
+    }
+
+    fn magic_link_redirect(&self, token: &str) -> Result<Option<(String, String)>, StorageError> {
+        let mut reply = self
+            .command(&["HMGET", &self.key("magic_link", &crypto::token_digest(token)), "client_id", "redirect_uri"])?
+            .into_array()
+            .into_iter()
+            .map(Reply::into_string);
+        Ok(reply.next().flatten().zip(reply.next().flatten()))
+    }
+
+    fn magic_link_client(&self, token: &str) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .command(&["HGET", &self.key("magic_link", &crypto::token_digest(token)), "client_id"])?
+            .into_string())
+    }
+
+    fn magic_link_flow_id(&self, token: &str) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .command(&["HGET", &self.key("magic_link", &crypto::token_digest(token)), "flow_id"])?
+            .into_string())
+    }
+}
+
+impl RedisStore {
+    /// Session of the refresh token stored under `digest`, if it is live
+    fn live_digest(&self, digest: &str) -> Result<Option<RefreshSession>, StorageError> {
+        let mut fields = self.fields(&self.key("refresh_token", digest))?;
+        let expires_at = number(&fields, "expires_at");
+        if fields.get("revoked").map(String::as_str) != Some("0") || expires_at < Database::now_ts() {
+            return Ok(None);
+        }
+        Ok(fields.remove("user_id").map(|user_id| RefreshSession {
+            user_id,
+            session_id: fields.remove("session_id"),
+            expires_at,
+            client_id: fields.remove("client_id"),
+            amr: parse_amr(fields.get("amr").map(String::as_str)),
+        }))
+    }
+
+    fn revoke_indexed(&self, user_id: &str, session_id: &str) -> Result<usize, StorageError> {
+        let revoked = self.eval(
+            REVOKE_USER_REFRESH_TOKENS,
+            &[&self.key("refresh_tokens", user_id)],
+            &[&self.key("refresh_token", ""), session_id],
+        )?;
+        Ok(revoked.int() as usize)
+    }
+}
+
+impl RefreshTokenStore for RedisStore {
+    fn create_refresh_token(
+        &self,
+        user_id: &str,
+        expiry_seconds: i64,
+        client_id: Option<&str>,
+        amr: &[&str],
+        agent: Option<&UserAgent>,
+    ) -> Result<NewSession, StorageError> {
+        let token = Uuid::new_v4().to_string();
+        let session_id = Uuid::new_v4().to_string();
+        let now = Database::now_ts();
+        let digest = crypto::token_digest(&token);
+        let agent = agent.map(|agent| serde_json::json!(agent).to_string());
+        self.eval(
+            PUT_REFRESH_TOKEN,
+            &[
+                &self.key("refresh_token", &digest),
+                &self.key("refresh_tokens", user_id),
+                &self.key("session", &session_id),
+            ],
+            &[
+                &digest,
+                user_id,
+                &session_id,
+                &(now + expiry_seconds).to_string(),
+                &now.to_string(),
+                &GRACE_SECONDS.to_string(),
+                client_id.unwrap_or(""),
+                &serde_json::json!(amr).to_string(),
+                agent.as_deref().unwrap_or(""),
+            ],
+        )?;
+        Ok(NewSession { token, session_id })
+    }
+
+    fn live_refresh_token(&self, token: &str) -> Result<Option<RefreshSession>, StorageError> {
+        self.live_digest(&crypto::token_digest(token))
+    }
+
+    fn live_session(&self, session_id: &str) -> Result<Option<RefreshSession>, StorageError> {
+        match self.command(&["GET", &self.key("session", session_id)])?.into_string() {
+            Some(digest) => self.live_digest(&digest),
+            None => Ok(None),
+        }
+    }
+
+    fn session_amr(&self, user_id: &str, session_id: &str) -> Result<Option<Vec<String>>, StorageError> {
+        let amr = self.eval(
+            LIVE_SESSION_AMR,
+            &[&self.key("refresh_tokens", user_id)],
+            &[&self.key("refresh_token", ""), session_id, &Database::now_ts().to_string()],
+        )?;
+        Ok(amr.into_string().map(|amr| parse_amr(Some(&amr))))
+    }
+
+    fn validate_refresh_token(&self, token: &str) -> Result<String, StorageError> {
+        let fields = self.fields(&self.key("refresh_token", &crypto::token_digest(token)))?;
+        let live = fields.get("revoked").map(String::as_str) == Some("0");
+        if !live || number(&fields, "expires_at") < Database::now_ts() {
+            return Err(StorageError::Invalid);
+        }
+        fields.get("user_id").cloned().ok_or(StorageError::Invalid)
+    }
+
+    fn list_sessions(&self, user_id: &str) -> Result<Vec<SessionListing>, StorageError> {
+        let reply = self.eval(
+            LIVE_SESSIONS,
+            &[&self.key("refresh_tokens", user_id)],
+            &[&self.key("refresh_token", ""), &Database::now_ts().to_string()],
+        )?;
+        let fields: Vec<String> = reply.into_array().into_iter().filter_map(Reply::into_string).collect();
+        let mut sessions: Vec<SessionListing> = fields
+            .chunks_exact(4)
+            .map(|session| SessionListing {
+                session_id: Some(session[0].clone()),
+                created_at: session[1].parse().unwrap_or(0),
+                expires_at: session[2].parse().unwrap_or(0),
+                agent: serde_json::from_str(&session[3]).ok(),
+            })
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+        Ok(sessions)
+    }
+
+    fn rotate_refresh_token(&self, token: &str, expiry_seconds: i64) -> Result<(String, NewSession), StorageError> {
+        let now = Database::now_ts();
+        let next = Uuid::new_v4().to_string();
+        let reply = self.eval(
+            ROTATE_REFRESH_TOKEN,
+            &[&self.key("refresh_token", &crypto::token_digest(token))],
+            &[
+                &crypto::token_digest(&next),
+                &now.to_string(),
+                &(now + expiry_seconds).to_string(),
+                &self.prefix,
+                &GRACE_SECONDS.to_string(),
+            ],
+        )?;
+        let mut owner = reply.into_array().into_iter().filter_map(Reply::into_string);
+        match (owner.next(), owner.next()) {
+            (Some(user_id), Some(session_id)) => Ok((
+                user_id,
+                NewSession {
+                    token: next,
+                    session_id,
+                },
+            )),
+            _ => Err(StorageError::Invalid),
+        }
+    }
+
+    fn revoke_refresh_token(&self, token: &str) -> Result<(), StorageError> {
+        self.eval(REVOKE_REFRESH_TOKEN, &[&self.key("refresh_token", &crypto::token_digest(token))], &[])?;
+        Ok(())
+    }
+
+    fn revoke_user_refresh_tokens(&self, user_id: &str) -> Result<usize, StorageError> {
+        self.revoke_indexed(user_id, "")
+    }
+
+    fn revoke_session(&self, user_id: &str, session_id: &str) -> Result<usize, StorageError> {
+        self.revoke_indexed(user_id, session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn encodes_commands_as_bulk_string_arrays() {
+        assert_eq!(encode(&["GET", "k:1"]), b"*2\r\n$3\r\nGET\r\n$3\r\nk:1\r\n".to_vec());
+    }
+
+    #[test]
+    fn reads_nested_replies() {
+        let mut conn = Cursor::new(b"*3\r\n:1\r\n$-1\r\n*2\r\n+OK\r\n$5\r\nhello\r\n".to_vec());
+        assert_eq!(
+            read_reply(&mut conn).unwrap(),
+            Reply::Array(vec![
+                Reply::Int(1),
+                Reply::Nil,
+                Reply::Array(vec![Reply::Data("OK".into()), Reply::Data("hello".into())]),
+            ])
+        );
+        assert!(read_reply(&mut Cursor::new(b"-ERR wrong type\r\n".to_vec())).is_err());
+
+        let hash = Reply::Array(vec![Reply::Data("used".into()), Reply::Data("0".into())]);
+        assert_eq!(hash.into_fields().get("used").map(String::as_str), Some("0"));
+    }
+
+    #[test]
+    fn parses_store_url() {
+        let cfg = RedisStoreConfig {
+            url: "redis://:s3cret@cache.internal:6380/2".to_string(),
+            ..Default::default()
+        };
+        let store = RedisStore::new(&cfg).unwrap();
+        assert_eq!(
+            (store.addr.as_str(), store.password.as_deref(), store.db),
+            ("cache.internal:6380", Some("s3cret"), Some(2))
+        );
+        assert_eq!(store.key("refresh_token", "t1"), "passwordless:refresh_token:t1");
+        for url in ["http://cache", "rediss://cache", "redis://cache/zero"] {
+            let cfg = RedisStoreConfig {
+                url: url.to_string(),
+                ..Default::default()
+            };
+            assert!(RedisStore::new(&cfg).is_err());
+        }
+    }
+}
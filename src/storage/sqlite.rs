@@ -0,0 +1,244 @@
+//! [`Storage`] on the SQLite [`Database`], the default backend.
+//!
+//! Magic links and refresh tokens delegate to [`MagicLink`] and [`Session`],
+//! which other SQLite code (the outbox, session expiry) shares transactions
+//! with; passkey rows are only written here. Statements come from
+//! [`queries`](super::queries).
+
+use super::{
+    queries as sql, MagicLinkStore, NewCredential, RefreshSession, RefreshTokenStore, SessionListing, Storage,
+    StorageError, StoredCredential, UserStore, WebauthnCredentialStore,
+};
+use crate::{
+    db::{Database, DbError},
+    magic_link::{IssuedLink, LinkBinding, LinkProof, MagicLinkError, MagicLinkIssuanceConfig},
+    models::{MagicLink, User},
+    session::{NewSession, Session, SessionError},
+    user_agent::UserAgent,
+    webauthn::{self, CredentialInfo},
+};
+use rusqlite::{params, OptionalExtension};
+
+impl From<DbError> for StorageError {
+    fn from(e: DbError) -> Self {
+        match e {
+            DbError::Sql(e) | DbError::Migration(_, e) => Self::Sqlite(e),
+        }
+    }
+}
+
+impl From<MagicLinkError> for StorageError {
+    fn from(e: MagicLinkError) -> Self {
+        match e {
+            MagicLinkError::Db(e) => Self::Sqlite(e),
+            MagicLinkError::Used => Self::Used,
+            MagicLinkError::BindingMismatch => Self::BindingMismatch,
+            MagicLinkError::Invalid | MagicLinkError::InvalidBinding(_) => Self::Invalid,
+        }
+    }
+}
+
+impl From<SessionError> for StorageError {
+    fn from(e: SessionError) -> Self {
+        match e {
+            SessionError::Db(e) => Self::Sqlite(e),
+            SessionError::Invalid => Self::Invalid,
+        }
+    }
+}
+
+impl UserStore for Database {
+    fn ensure_user(&self, email: &str) -> Result<String, StorageError> {
+        Ok(self.get_or_create_user(email)?)
+    }
+
+    fn find_user(&self, id: &str) -> Result<Option<User>, StorageError> {
+        Ok(self.users().find_by_id(id)?)
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, StorageError> {
+        Ok(self.users().find_by_email(email)?)
+    }
+}
+
+impl MagicLinkStore for Database {
+    fn issue_magic_link(
+        &self,
+        user_id: &str,
+        expiry_seconds: i64,
+        cfg: &MagicLinkIssuanceConfig,
+        client: Option<(&str, Option<&str>)>,
+        binding: &LinkBinding,
+    ) -> Result<IssuedLink, StorageError> {
+        Ok(MagicLink::issue(self, user_id, expiry_seconds, cfg, client, binding)?)
+    }
+
+    fn consume_magic_link(&self, token: &str, proof: &LinkProof) -> Result<String, StorageError> {
+        Ok(MagicLink::consume_with(self, token, proof)?)
+    }
+
+    fn magic_link_redirect(&self, token: &str) -> Result<Option<(String, String)>, StorageError> {
+        Ok(MagicLink::redirect(self, token)?)
+    }
+
+    fn magic_link_client(&self, token: &str) -> Result<Option<String>, StorageError> {
+        Ok(MagicLink::client_id(self, token)?)
+    }
+
+    fn magic_link_flow_id(&self, token: &str) -> Result<Option<String>, StorageError> {
+        Ok(MagicLink::flow_id(self, token)?)
+    }
+}
+
+impl RefreshTokenStore for Database {
+    fn create_refresh_token(
+        &self,
+        user_id: &str,
+        expiry_seconds: i64,
+        client_id: Option<&str>,
+        amr: &[&str],
+        agent: Option<&UserAgent>,
+    ) -> Result<NewSession, StorageError> {
+        let session = Session::create(self, user_id, expiry_seconds)?;
+        Session::set_amr(self, &session.session_id, amr)?;
+        if let Some(agent) = agent {
+            Session::set_user_agent(self, &session.session_id, agent)?;
+        }
+        if let Some(client_id) = client_id {
+            Session::set_client(self, &session.session_id, client_id)?;
+        }
+        Ok(session)
+    }
+
+    fn validate_refresh_token(&self, token: &str) -> Result<String, StorageError> {
+        Ok(Session::validate_refresh_token(self, token)?)
+    }
+
+    fn live_refresh_token(&self, token: &str) -> Result<Option<RefreshSession>, StorageError> {
+        let now = Database::now_ts();
+        let Some(refresh) = self.sessions().find_by_token(token)?.filter(|r| !r.revoked && r.expires_at > now) else {
+            return Ok(None);
+        };
+        let amr = match &refresh.session_id {
+            Some(session_id) => Session::live_amr(self, &refresh.user_id, session_id)?.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        Ok(Some(RefreshSession {
+            session_id: refresh.session_id,
+            user_id: refresh.user_id,
+            expires_at: refresh.expires_at,
+            client_id: Session::client_id(self, token)?,
+            amr,
+        }))
+    }
+
+    fn live_session(&self, session_id: &str) -> Result<Option<RefreshSession>, StorageError> {
+        let session = self
+            .conn()
+            .query_row(sql::LIVE_SESSION, params![session_id, Database::now_ts()], |r| {
+                Ok(RefreshSession {
+                    user_id: r.get(0)?,
+                    session_id: Some(session_id.to_string()),
+                    expires_at: r.get(1)?,
+                    client_id: r.get(2)?,
+                    amr: r
+                        .get::<_, Option<String>>(3)?
+                        .and_then(|amr| serde_json::from_str(&amr).ok())
+                        .unwrap_or_default(),
+                })
+            })
+            .optional()?;
+        Ok(session)
+    }
+
+    fn session_amr(&self, user_id: &str, session_id: &str) -> Result<Option<Vec<String>>, StorageError> {
+        Ok(Session::live_amr(self, user_id, session_id)?)
+    }
+
+    fn list_sessions(&self, user_id: &str) -> Result<Vec<SessionListing>, StorageError> {
+        let now = Database::now_ts();
+        Ok(self
+            .sessions()
+            .list_for_user(user_id)?
+            .into_iter()
+            .filter(|s| !s.refresh.revoked && s.refresh.expires_at > now)
+            .map(|s| SessionListing {
+                session_id: s.refresh.session_id,
+                created_at: s.refresh.created_at,
+                expires_at: s.refresh.expires_at,
+                agent: s.agent,
+            })
+            .collect())
+    }
+
+    fn rotate_refresh_token(&self, token: &str, expiry_seconds: i64) -> Result<(String, NewSession), StorageError> {
+        Ok(Session::rotate(self, token, expiry_seconds)?)
+    }
+
+    fn revoke_refresh_token(&self, token: &str) -> Result<(), StorageError> {
+        Ok(Session::revoke_refresh_token(self, token)?)
+    }
+
+    fn revoke_user_refresh_tokens(&self, user_id: &str) -> Result<usize, StorageError> {
+        Ok(self.sessions().revoke_all_for_user(user_id)?)
+    }
+
+    fn revoke_session(&self, user_id: &str, session_id: &str) -> Result<usize, StorageError> {
+        Ok(Session::revoke_session(self, user_id, session_id)?)
+    }
+}
+
+impl WebauthnCredentialStore for Database {
+    fn add_credential(&self, credential: &NewCredential) -> Result<(), StorageError> {
+        self.conn().execute(
+            sql::INSERT_PASSKEY,
+            params![
+                credential.id,
+                credential.user_id,
+                credential.credential_id,
+                credential.public_key,
+                credential.sign_count,
+                credential.transports,
+                credential.aaguid,
+                credential.name,
+                credential.created_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn credential_keys(&self, user_id: &str) -> Result<Vec<Vec<u8>>, StorageError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(sql::PASSKEYS_OF_USER)?;
+        let keys = stmt.query_map(params![user_id], |r| r.get(0))?;
+        Ok(keys.collect::<Result<_, _>>()?)
+    }
+
+    fn find_credential(&self, credential_id: &[u8]) -> Result<Option<StoredCredential>, StorageError> {
+        Ok(self
+            .conn()
+            .query_row(sql::PASSKEY_BY_CREDENTIAL_ID, params![credential_id], |r| {
+                Ok(StoredCredential {
+                    id: r.get(0)?,
+                    user_id: r.get(1)?,
+                    sign_count: r.get(2)?,
+                })
+            })
+            .optional()?)
+    }
+
+    fn record_assertion(&self, id: &str, sign_count: i64, ip: Option<&str>, now: i64) -> Result<bool, StorageError> {
+        let updated = self.conn().execute(sql::RECORD_ASSERTION, params![sign_count, now, ip, id])?;
+        Ok(updated > 0)
+    }
+
+    fn list_credentials(&self, user_id: &str) -> Result<Vec<CredentialInfo>, StorageError> {
+        Ok(webauthn::list_credentials(self, user_id)?)
+    }
+}
+
+impl Storage for Database {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+}
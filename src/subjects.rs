@@ -0,0 +1,91 @@
+use crate::{applications::ApplicationConfig, crypto::hmac_sha256, db::Database};
+use rand::RngCore;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+
+/// Which `sub` an application sees in access tokens
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectType {
+    /// One public subject per user, shared by every application
+    #[default]
+    Public,
+    /// A distinct subject per (user, application) so apps cannot correlate users.
+    /// Derived as HMAC(pairwise_salt, user_id) when the application sets a salt,
+    /// otherwise a random value stored per application.
+    Pairwise,
+}
+
+/// Mapping key used for the shared public subject
+const PUBLIC_SCOPE: &str = "";
+
+fn random_subject() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    data_encoding::BASE32_NOPAD.encode(&bytes).to_lowercase()
+}
+
+/// OIDC-style pairwise subject: `base32(HMAC-SHA256(salt, user_id))`, truncated
+/// to 160 bits. Deterministic, so it survives database restores and can be
+/// recomputed by anyone holding the application's salt.
+pub fn pairwise_subject(salt: &str, user_id: &str) -> String {
+    let mac = hmac_sha256(salt.as_bytes(), user_id.as_bytes());
+    data_encoding::BASE32_NOPAD.encode(&mac[..20]).to_lowercase()
+}
+
+/// Public subject identifier for `user_id` as seen by `app`.
+///
+/// Internal user UUIDs never leave the server in access tokens; the subject is
+/// a random value created on first use and stored in `user_subjects`.
+pub fn subject_for(
+    db: &Database,
+    user_id: &str,
+    app: Option<&ApplicationConfig>,
+) -> Result<String, rusqlite::Error> {
+    let scope = match app {
+        Some(app) if app.subject_type == SubjectType::Pairwise => app.client_id.as_str(),
+        _ => PUBLIC_SCOPE,
+    };
+
+    if let Some(salt) = app.filter(|_| scope != PUBLIC_SCOPE).and_then(|a| a.pairwise_salt.as_deref()) {
+        let subject = pairwise_subject(salt, user_id);
+        // recorded for reverse lookup; replaces any earlier random or old-salt subject
+        db.conn().execute(
+            "INSERT INTO user_subjects (subject, user_id, client_id, created_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(user_id, client_id) DO UPDATE SET subject = excluded.subject",
+            params![subject, user_id, scope, Database::now_ts()],
+        )?;
+        return Ok(subject);
+    }
+
+    if let Some(subject) = lookup(db, user_id, scope)? {
+        return Ok(subject);
+    }
+    db.conn().execute(
+        "INSERT OR IGNORE INTO user_subjects (subject, user_id, client_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![random_subject(), user_id, scope, Database::now_ts()],
+    )?;
+    // a concurrent insert may have won; read back whichever row exists
+    lookup(db, user_id, scope)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+fn lookup(db: &Database, user_id: &str, scope: &str) -> Result<Option<String>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            "SELECT subject FROM user_subjects WHERE user_id = ?1 AND client_id = ?2",
+            params![user_id, scope],
+            |r| r.get(0),
+        )
+        .optional()
+}
+
+/// Internal user id behind a subject from an access token
+pub fn resolve(db: &Database, subject: &str) -> Result<Option<String>, rusqlite::Error> {
+    db.conn()
+        .query_row(
+            "SELECT user_id FROM user_subjects WHERE subject = ?1",
+            params![subject],
+            |r| r.get(0),
+        )
+        .optional()
+}
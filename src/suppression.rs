@@ -0,0 +1,240 @@
+//! Addresses we must not email: hard bounces, spam complaints and manual blocks.
+//!
+//! Every email is checked against the list before it is sent or enqueued;
+//! mailing known-bad addresses hurts the sending domain's reputation with
+//! every provider. Entries come from admins, one at a time or as a bulk
+//! import of a provider's suppression export.
+
+use crate::{address::EmailAddress, db::Database};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SuppressionError {
+    #[error("invalid email address")]
+    InvalidAddress,
+    #[error("db error: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    HardBounce,
+    Complaint,
+    Manual,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::HardBounce => "hard_bounce",
+            Self::Complaint => "complaint",
+            Self::Manual => "manual",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "hard_bounce" => Some(Self::HardBounce),
+            "complaint" => Some(Self::Complaint),
+            "manual" => Some(Self::Manual),
+            _ => None,
+        }
+    }
+
+    /// Reason named in a provider export column (`Bounce`, `spamreport`, ...)
+    fn from_export(value: &str) -> Option<Self> {
+        let value = value.to_ascii_lowercase();
+        if value.contains("complaint") || value.contains("spam") || value.contains("abuse") {
+            Some(Self::Complaint)
+        } else if value.contains("bounce") || value.contains("invalid") || value.contains("blocked") {
+            Some(Self::HardBounce)
+        } else if value.contains("manual") || value.contains("unsubscribe") {
+            Some(Self::Manual)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: Reason,
+    /// Where the entry came from, e.g. `admin` or `import:sendgrid`
+    pub source: String,
+    pub note: Option<String>,
+    pub created_at: i64,
+}
+
+/// Outcome of a bulk import
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    pub added: usize,
+    /// Already suppressed; the existing entry is kept
+    pub existing: usize,
+    /// Lines without a valid address
+    pub invalid: usize,
+}
+
+/// Canonical form of an address for the list; `None` if it does not parse
+pub fn normalize(email: &str) -> Option<String> {
+    EmailAddress::parse(email).ok().map(|address| address.to_string())
+}
+
+fn row(r: &rusqlite::Row) -> rusqlite::Result<Suppression> {
+    let reason: String = r.get(1)?;
+    Ok(Suppression {
+        email: r.get(0)?,
+        reason: Reason::parse(&reason).unwrap_or(Reason::Manual),
+        source: r.get(2)?,
+        note: r.get(3)?,
+        created_at: r.get(4)?,
+    })
+}
+
+/// The entry suppressing `email`, if any
+pub fn find(db: &Database, email: &str) -> Result<Option<Suppression>, rusqlite::Error> {
+    let Some(email) = normalize(email) else {
+        return Ok(None);
+    };
+    db.conn()
+        .query_row(
+            "SELECT email, reason, source, note, created_at FROM email_suppressions WHERE email = ?1",
+            params![email],
+            row,
+        )
+        .optional()
+}
+
+pub fn is_suppressed(db: &Database, email: &str) -> Result<bool, rusqlite::Error> {
+    Ok(find(db, email)?.is_some())
+}
+
+/// Suppress an address; returns `false` if it already was (the entry is
+/// replaced so the latest reason wins)
+pub fn add(
+    db: &Database,
+    email: &str,
+    reason: Reason,
+    source: &str,
+    note: Option<&str>,
+) -> Result<bool, SuppressionError> {
+    let email = normalize(email).ok_or(SuppressionError::InvalidAddress)?;
+    let existed = find(db, &email)?.is_some();
+    db.conn().execute(
+        "INSERT INTO email_suppressions (email, reason, source, note, created_at) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(email) DO UPDATE SET reason = ?2, source = ?3, note = ?4",
+        params![email, reason.as_str(), source, note, Database::now_ts()],
+    )?;
+    Ok(!existed)
+}
+
+/// Lift a suppression; returns whether there was one
+pub fn remove(db: &Database, email: &str) -> Result<bool, rusqlite::Error> {
+    let email = normalize(email).unwrap_or_else(|| email.to_string());
+    Ok(db
+        .conn()
+        .execute("DELETE FROM email_suppressions WHERE email = ?1", params![email])?
+        > 0)
+}
+
+/// Entries, newest first, optionally for one reason
+pub fn list(db: &Database, reason: Option<Reason>, limit: i64, offset: i64) -> Result<Vec<Suppression>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT email, reason, source, note, created_at FROM email_suppressions
+         WHERE ?1 IS NULL OR reason = ?1
+         ORDER BY created_at DESC, email LIMIT ?2 OFFSET ?3",
+    )?;
+    let rows = stmt.query_map(params![reason.map(|r| r.as_str()), limit, offset], row)?;
+    rows.collect()
+}
+
+/// Split a CSV line, honouring double-quoted fields
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+/// Import a provider suppression export: CSV with a header naming an email
+/// column (`email`, `Email Address`, `EmailAddress`, ...) and optionally a
+/// `reason` or `type` column, or simply one address per line. Rows without
+/// a recognised reason get `default_reason`.
+pub fn import(
+    db: &Database,
+    export: &str,
+    default_reason: Reason,
+    source: &str,
+) -> Result<ImportSummary, rusqlite::Error> {
+    let mut lines = export.lines().map(str::trim).filter(|l| !l.is_empty()).peekable();
+    let header = lines.peek().map(|l| csv_fields(&l.to_ascii_lowercase()));
+    let (email_col, reason_col) = match header.as_deref() {
+        Some(fields) if normalize(&fields[0]).is_none() && fields.iter().any(|f| f.contains("email")) => {
+            lines.next();
+            (
+                fields.iter().position(|f| f.contains("email")).unwrap_or(0),
+                fields.iter().position(|f| f == "reason" || f == "type" || f.contains("reason")),
+            )
+        }
+        _ => (0, None),
+    };
+
+    let mut summary = ImportSummary::default();
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let now = Database::now_ts();
+    for line in lines {
+        let fields = csv_fields(line);
+        let Some(email) = fields.get(email_col).and_then(|e| normalize(e)) else {
+            summary.invalid += 1;
+            continue;
+        };
+        let reason = reason_col
+            .and_then(|col| fields.get(col))
+            .and_then(|r| Reason::from_export(r))
+            .unwrap_or(default_reason);
+        let added = tx.execute(
+            "INSERT OR IGNORE INTO email_suppressions (email, reason, source, note, created_at) VALUES (?1, ?2, ?3, NULL, ?4)",
+            params![email, reason.as_str(), source, now],
+        )?;
+        if added > 0 {
+            summary.added += 1;
+        } else {
+            summary.existing += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_reasons_and_quoted_fields() {
+        assert_eq!(Reason::from_export("spamreport"), Some(Reason::Complaint));
+        assert_eq!(Reason::from_export("BOUNCE"), Some(Reason::HardBounce));
+        assert_eq!(Reason::from_export("other"), None);
+        assert_eq!(
+            csv_fields(r#"a@example.com,"550 5.1.1 ""user unknown"", bad",bounce"#),
+            ["a@example.com", r#"550 5.1.1 "user unknown", bad"#, "bounce"]
+        );
+    }
+}
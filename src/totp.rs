@@ -1,7 +1,6 @@
 use base32::{Alphabet, encode};
-use rand::Rng;
 use thiserror::Error;
-use totp_lite::{totp_custom, Sha1};
+use totp_lite::{totp_custom, Sha1, Sha256, Sha512};
 
 #[derive(Debug, Error)]
 pub enum TotpError {
@@ -9,6 +8,23 @@ pub enum TotpError {
     Invalid,
 }
 
+/// HMAC hash of the code; RFC 6238 allows SHA-1, SHA-256 and SHA-512
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// The `digits`-digit code for the raw `secret` at unix time `timestamp`
+pub fn code_at(secret: &[u8], algorithm: TotpAlgorithm, period: u64, digits: u32, timestamp: u64) -> String {
+    match algorithm {
+        TotpAlgorithm::Sha1 => totp_custom::<Sha1>(period, digits, secret, timestamp),
+        TotpAlgorithm::Sha256 => totp_custom::<Sha256>(period, digits, secret, timestamp),
+        TotpAlgorithm::Sha512 => totp_custom::<Sha512>(period, digits, secret, timestamp),
+    }
+}
+
 pub fn generate_secret() -> String {
     // generate 20 bytes -> base32
     let bytes: Vec<u8> = (0..20).map(|_| rand::random::<u8>()).collect();
@@ -19,10 +35,24 @@ pub fn generate_otpauth_url(secret: &str, user_email: &str, issuer: &str) -> Str
     // Example: otpauth://totp/PasswordlessAuth:user@example.com?secret=ABCDEF&issuer=PasswordlessAuth&algorithm=SHA1&digits=6&period=30
     format!(
         "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits=6&period=30",
-        issuer, user_email, secret, issuer
+        issuer,
+        encode_label(&crate::address::display(user_email)),
+        secret,
+        issuer
     )
 }
 
+/// Percent-encode the account label; authenticator apps decode it as UTF-8
+fn encode_label(label: &str) -> String {
+    label
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' | b'+' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
 pub fn verify_code(secret: &str, code: &str) -> Result<(), TotpError> {
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -31,7 +61,11 @@ pub fn verify_code(secret: &str, code: &str) -> Result<(), TotpError> {
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs();
+    verify_code_at(secret, code, timestamp)
+}
 
+/// [`verify_code`] at unix time `timestamp`
+pub fn verify_code_at(secret: &str, code: &str, timestamp: u64) -> Result<(), TotpError> {
     // Decode secret from base32
     let secret_bytes = match base32::decode(Alphabet::RFC4648 { padding: false }, secret) {
         Some(bytes) => bytes,
@@ -39,16 +73,16 @@ pub fn verify_code(secret: &str, code: &str) -> Result<(), TotpError> {
     };
 
     // time step 30s, 6 digits, SHA1 default
-    let now_code = totp_custom::<Sha1>(30, 6, &secret_bytes, timestamp);
+    let now_code = code_at(&secret_bytes, TotpAlgorithm::Sha1, 30, 6, timestamp);
 
-    if format!("{:06}", now_code) == code {
+    if now_code == code {
         Ok(())
     } else {
         // allow +/-1 step for clock skew (30 seconds)
-        let prev_code = totp_custom::<Sha1>(30, 6, &secret_bytes, timestamp - 30);
-        let next_code = totp_custom::<Sha1>(30, 6, &secret_bytes, timestamp + 30);
+        let prev_code = code_at(&secret_bytes, TotpAlgorithm::Sha1, 30, 6, timestamp.saturating_sub(30));
+        let next_code = code_at(&secret_bytes, TotpAlgorithm::Sha1, 30, 6, timestamp + 30);
 
-        if format!("{:06}", prev_code) == code || format!("{:06}", next_code) == code {
+        if prev_code == code || next_code == code {
             Ok(())
         } else {
             Err(TotpError::Invalid)
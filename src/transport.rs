@@ -0,0 +1,271 @@
+use crate::{applications, config::Config, db::Database, passkey_nudge::PasskeyNudge, service::AuthResponse};
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+
+/// How issued tokens are handed back to the client
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTransport {
+    /// JSON response body
+    #[default]
+    Body,
+    /// `X-Access-Token` / `X-Refresh-Token` response headers
+    Header,
+    /// `Set-Cookie` only (HttpOnly), for SSR apps
+    Cookie,
+    /// `Set-Cookie` plus JSON body, for SPAs sharing a deployment with SSR apps
+    Both,
+}
+
+/// Cookie settings used by the `cookie` and `both` transports
+#[derive(Debug, Deserialize, Clone)]
+pub struct CookieConfig {
+    #[serde(default = "default_access_cookie_name")]
+    pub access_name: String,
+    #[serde(default = "default_refresh_cookie_name")]
+    pub refresh_name: String,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default = "default_cookie_secure")]
+    pub secure: bool,
+    #[serde(default = "default_cookie_same_site")]
+    pub same_site: String,
+    /// Other origins (`https://app.example.com`) whose pages may send
+    /// cookie-authenticated writes, e.g. an SPA on a sibling subdomain
+    #[serde(default)]
+    pub trusted_origins: Vec<String>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            access_name: default_access_cookie_name(),
+            refresh_name: default_refresh_cookie_name(),
+            domain: None,
+            secure: default_cookie_secure(),
+            same_site: default_cookie_same_site(),
+            trusted_origins: Vec::new(),
+        }
+    }
+}
+
+fn default_access_cookie_name() -> String {
+    "pa_access".to_string()
+}
+
+fn default_refresh_cookie_name() -> String {
+    "pa_refresh".to_string()
+}
+
+fn default_cookie_secure() -> bool {
+    true
+}
+
+fn default_cookie_same_site() -> String {
+    "Lax".to_string()
+}
+
+#[derive(Serialize)]
+struct CookieOnlyBody<'a> {
+    authenticated: bool,
+    session_id: &'a str,
+    expires_at: i64,
+    refresh_expires_at: i64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    passkey_nudge: Option<&'a PasskeyNudge>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect_uri: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    missing_fields: &'a [String],
+}
+
+/// Resolve the transport for the calling application
+pub fn resolve(cfg: &Config, headers: &HeaderMap) -> TokenTransport {
+    applications::client_id(headers)
+        .and_then(|id| cfg.application(id))
+        .and_then(|app| app.token_transport)
+        .unwrap_or(cfg.token_transport)
+}
+
+/// Build the HTTP response carrying freshly issued tokens
+pub fn token_response(cfg: &Config, headers: &HeaderMap, tokens: AuthResponse) -> Response {
+    let transport = resolve(cfg, headers);
+    let mut response = match transport {
+        TokenTransport::Body | TokenTransport::Both => (StatusCode::OK, Json(&tokens)).into_response(),
+        TokenTransport::Header | TokenTransport::Cookie => {
+            let body = CookieOnlyBody {
+                authenticated: true,
+                session_id: &tokens.session_id,
+                expires_at: tokens.expires_at,
+                refresh_expires_at: tokens.refresh_expires_at,
+                passkey_nudge: tokens.passkey_nudge.as_ref(),
+                redirect_uri: tokens.redirect_uri.as_deref(),
+                missing_fields: &tokens.missing_fields,
+            };
+            (StatusCode::OK, Json(body)).into_response()
+        }
+    };
+
+    // tokens never travel in the URL, so only the cookie transport follows the
+    // (already validated) redirect itself; other clients navigate on their own
+    if transport == TokenTransport::Cookie {
+        if let Some(location) = tokens.redirect_uri.as_deref().and_then(|u| HeaderValue::from_str(u).ok()) {
+            *response.status_mut() = StatusCode::SEE_OTHER;
+            response.headers_mut().insert(header::LOCATION, location);
+        }
+    }
+
+    let out = response.headers_mut();
+    match transport {
+        TokenTransport::Body => {}
+        TokenTransport::Header => {
+            if let Ok(v) = HeaderValue::from_str(&tokens.access_token) {
+                out.insert("X-Access-Token", v);
+            }
+            if let Ok(v) = HeaderValue::from_str(&tokens.refresh_token) {
+                out.insert("X-Refresh-Token", v);
+            }
+        }
+        TokenTransport::Cookie | TokenTransport::Both => {
+            // the lifetimes the tokens were minted with, which may be an
+            // application's or a role's rather than the global ones
+            let now = Database::now_ts();
+            let access = set_cookie(
+                &cfg.cookie,
+                &cfg.cookie.access_name,
+                &tokens.access_token,
+                (tokens.expires_at - now).max(0),
+            );
+            let refresh = set_cookie(
+                &cfg.cookie,
+                &cfg.cookie.refresh_name,
+                &tokens.refresh_token,
+                (tokens.refresh_expires_at - now).max(0),
+            );
+            for c in [access, refresh] {
+                if let Ok(v) = HeaderValue::from_str(&c) {
+                    out.append(header::SET_COOKIE, v);
+                }
+            }
+        }
+    }
+    response
+}
+
+/// `204` for a completed logout, expiring the token cookies of cookie clients
+pub fn logout_response(cfg: &Config, headers: &HeaderMap) -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    if matches!(resolve(cfg, headers), TokenTransport::Cookie | TokenTransport::Both) {
+        for name in [&cfg.cookie.access_name, &cfg.cookie.refresh_name] {
+            if let Ok(v) = HeaderValue::from_str(&set_cookie(&cfg.cookie, name, "", 0)) {
+                response.headers_mut().append(header::SET_COOKIE, v);
+            }
+        }
+    }
+    response
+}
+
+fn set_cookie(cfg: &CookieConfig, name: &str, value: &str, max_age: i64) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite={}",
+        name, value, max_age, cfg.same_site
+    );
+    if cfg.secure {
+        cookie.push_str("; Secure");
+    }
+    if let Some(domain) = &cfg.domain {
+        cookie.push_str(&format!("; Domain={}", domain));
+    }
+    cookie
+}
+
+/// Whether a request carrying its token in a cookie may act on it. Browsers
+/// attach cookies to requests other sites trigger, so a write must come from
+/// this service's own origin or a `trusted_origins` entry: `Sec-Fetch-Site`
+/// decides when the browser sends it, else `Origin` is compared with `Host`.
+/// Writes with neither header are refused; only browsers hold the cookies,
+/// other clients send the token in `Authorization` or the body.
+pub fn cookie_request_allowed(cfg: &CookieConfig, method: &Method, headers: &HeaderMap) -> bool {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+    let get = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok());
+    let origin = get(header::ORIGIN.as_str());
+    if origin.is_some_and(|o| cfg.trusted_origins.iter().any(|t| t.trim_end_matches('/') == o)) {
+        return true;
+    }
+    match (get("sec-fetch-site"), origin) {
+        (Some(site), _) => matches!(site, "same-origin" | "none"),
+        (None, Some(origin)) => {
+            let host = origin.split_once("://").map(|(_, host)| host);
+            host.is_some_and(|h| Some(h) == get(header::HOST.as_str()))
+        }
+        (None, None) => false,
+    }
+}
+
+/// Read a cookie value from the request's `Cookie` headers
+pub fn read_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; pa_access=abc.def.ghi; pa_refresh=xyz"),
+        );
+        assert_eq!(read_cookie(&headers, "pa_access").as_deref(), Some("abc.def.ghi"));
+        assert_eq!(read_cookie(&headers, "pa_refresh").as_deref(), Some("xyz"));
+        assert!(read_cookie(&headers, "missing").is_none());
+    }
+
+    #[test]
+    fn test_cookie_writes_need_same_origin() {
+        let cfg = CookieConfig {
+            trusted_origins: vec!["https://app.example.com".into()],
+            ..Default::default()
+        };
+        let request = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        let allowed = |method, pairs| cookie_request_allowed(&cfg, &method, &request(pairs));
+
+        assert!(allowed(Method::GET, &[("sec-fetch-site", "cross-site")]));
+        assert!(allowed(Method::POST, &[("sec-fetch-site", "same-origin")]));
+        assert!(!allowed(Method::POST, &[("sec-fetch-site", "cross-site"), ("origin", "https://evil.test")]));
+        assert!(!allowed(Method::DELETE, &[("sec-fetch-site", "same-site")]));
+        assert!(allowed(Method::POST, &[("sec-fetch-site", "same-site"), ("origin", "https://app.example.com")]));
+        assert!(allowed(Method::POST, &[("origin", "https://auth.example.com"), ("host", "auth.example.com")]));
+        assert!(!allowed(Method::POST, &[("origin", "https://evil.test"), ("host", "auth.example.com")]));
+        assert!(!allowed(Method::PATCH, &[]));
+    }
+
+    #[test]
+    fn test_set_cookie_attributes() {
+        let cfg = CookieConfig::default();
+        let cookie = set_cookie(&cfg, "pa_access", "tok", 900);
+        assert!(cookie.starts_with("pa_access=tok; Path=/; Max-Age=900; HttpOnly"));
+        assert!(cookie.contains("SameSite=Lax"));
+        assert!(cookie.ends_with("; Secure"));
+    }
+}
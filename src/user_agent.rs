@@ -0,0 +1,235 @@
+use axum::http::{header, HeaderMap};
+use rusqlite::Row;
+use serde::{Deserialize, Serialize};
+
+/// Kind of client a `User-Agent` belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+    Tablet,
+    Bot,
+}
+
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Desktop => "desktop",
+            Self::Mobile => "mobile",
+            Self::Tablet => "tablet",
+            Self::Bot => "bot",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [Self::Desktop, Self::Mobile, Self::Tablet, Self::Bot]
+            .into_iter()
+            .find(|d| d.as_str() == s)
+    }
+}
+
+/// Browser and OS of a session, parsed from the `User-Agent` it was created
+/// with. Only families and versions are kept, never the raw header, so this
+/// is stored for every session regardless of client-hint consent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserAgent {
+    pub browser: Option<String>,
+    /// Major version only
+    pub browser_version: Option<String>,
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub device_type: Option<DeviceType>,
+}
+
+/// Columns of `refresh_tokens` holding the parsed agent, in [`UserAgent::from_row`] order
+pub const COLUMNS: &str = crate::storage::queries::AGENT_COLUMNS;
+
+/// Parsed `User-Agent` of a request, if it sent one
+pub fn from_headers(headers: &HeaderMap) -> Option<UserAgent> {
+    let ua = headers.get(header::USER_AGENT)?.to_str().ok()?;
+    Some(parse(ua)).filter(|agent| !agent.is_empty())
+}
+
+/// Browser tokens in match order: more specific brands first, since Edge,
+/// Opera and Samsung Internet all also claim to be Chrome and Safari
+const BROWSERS: &[(&str, &str)] = &[
+    ("Edg/", "Edge"),
+    ("EdgA/", "Edge"),
+    ("EdgiOS/", "Edge"),
+    ("OPR/", "Opera"),
+    ("OPiOS/", "Opera"),
+    ("SamsungBrowser/", "Samsung Internet"),
+    ("FxiOS/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Firefox/", "Firefox"),
+    ("Chromium/", "Chromium"),
+    ("Chrome/", "Chrome"),
+    ("curl/", "curl"),
+];
+
+const BOT_MARKERS: &[&str] = &["bot", "crawler", "spider", "slurp"];
+
+pub fn parse(ua: &str) -> UserAgent {
+    let (os, os_version) = parse_os(ua);
+    let (browser, browser_version) = parse_browser(ua);
+    let lower = ua.to_ascii_lowercase();
+    let device_type = if BOT_MARKERS.iter().any(|m| lower.contains(m)) {
+        Some(DeviceType::Bot)
+    } else if ua.contains("iPad") || (os == Some("Android") && !ua.contains("Mobile")) {
+        Some(DeviceType::Tablet)
+    } else if ua.contains("Mobi") || ua.contains("iPhone") || ua.contains("iPod") {
+        Some(DeviceType::Mobile)
+    } else if os.is_some() {
+        Some(DeviceType::Desktop)
+    } else {
+        None
+    };
+    UserAgent {
+        browser: browser.map(str::to_string),
+        browser_version,
+        os: os.map(str::to_string),
+        os_version,
+        device_type,
+    }
+}
+
+fn parse_browser(ua: &str) -> (Option<&'static str>, Option<String>) {
+    for (token, name) in BROWSERS {
+        if let Some(version) = version_after(ua, token) {
+            return (Some(*name), Some(major(&version)));
+        }
+    }
+    // Safari puts its own version in `Version/`; `Safari/` is the WebKit build
+    if ua.contains("Safari/") || ua.contains("AppleWebKit/") {
+        if let Some(version) = version_after(ua, "Version/") {
+            return (Some("Safari"), Some(major(&version)));
+        }
+        if ua.contains("Safari/") {
+            return (Some("Safari"), None);
+        }
+    }
+    (None, None)
+}
+
+fn parse_os(ua: &str) -> (Option<&'static str>, Option<String>) {
+    if ua.contains("iPhone") || ua.contains("iPad") || ua.contains("iPod") {
+        let version = version_after(ua, "OS ").map(|v| v.replace('_', "."));
+        return (Some(if ua.contains("iPad") { "iPadOS" } else { "iOS" }), version);
+    }
+    if let Some(version) = version_after(ua, "Android ") {
+        return (Some("Android"), Some(version));
+    }
+    if ua.contains("Android") {
+        return (Some("Android"), None);
+    }
+    if let Some(nt) = version_after(ua, "Windows NT ") {
+        // 10.0 covers Windows 10 and 11; browsers no longer tell them apart
+        let version = match nt.as_str() {
+            "6.1" => Some("7"),
+            "6.2" => Some("8"),
+            "6.3" => Some("8.1"),
+            _ => None,
+        };
+        return (Some("Windows"), version.map(str::to_string));
+    }
+    if ua.contains("Windows") {
+        return (Some("Windows"), None);
+    }
+    if ua.contains("CrOS") {
+        return (Some("ChromeOS"), None);
+    }
+    if ua.contains("Macintosh") || ua.contains("Mac OS X") {
+        let version = version_after(ua, "Mac OS X ").map(|v| v.replace('_', "."));
+        return (Some("macOS"), version);
+    }
+    if ua.contains("Linux") || ua.contains("X11") {
+        return (Some("Linux"), None);
+    }
+    (None, None)
+}
+
+/// The `1.2.3` (or `1_2_3`) version directly after `token`
+fn version_after(ua: &str, token: &str) -> Option<String> {
+    let start = ua.find(token)? + token.len();
+    let version: String = ua[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '_')
+        .collect();
+    let version = version.trim_end_matches(['.', '_']);
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+fn major(version: &str) -> String {
+    version.split('.').next().unwrap_or(version).to_string()
+}
+
+impl UserAgent {
+    fn is_empty(&self) -> bool {
+        self.browser.is_none() && self.os.is_none()
+    }
+
+    /// Human-readable summary, e.g. `Chrome 126 on Windows`
+    pub fn display(&self) -> String {
+        let browser = match (&self.browser, &self.browser_version) {
+            (Some(name), Some(version)) => format!("{} {}", name, version),
+            (Some(name), None) => name.clone(),
+            (None, _) => "Unknown browser".to_string(),
+        };
+        match &self.os {
+            Some(os) => format!("{} on {}", browser, os),
+            None if self.browser.is_some() => browser,
+            None => "Unknown device".to_string(),
+        }
+    }
+
+    /// Read the parsed agent from [`COLUMNS`] starting at `offset`
+    pub fn from_row(r: &Row, offset: usize) -> rusqlite::Result<Option<Self>> {
+        let agent = Self {
+            browser: r.get(offset)?,
+            browser_version: r.get(offset + 1)?,
+            os: r.get(offset + 2)?,
+            os_version: r.get(offset + 3)?,
+            device_type: r
+                .get::<_, Option<String>>(offset + 4)?
+                .as_deref()
+                .and_then(DeviceType::parse),
+        };
+        Ok((!agent.is_empty()).then_some(agent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_common_agents() {
+        let chrome = parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36");
+        assert_eq!(chrome.display(), "Chrome 126 on Windows");
+        assert_eq!(chrome.device_type, Some(DeviceType::Desktop));
+
+        let edge = parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.2592.87");
+        assert_eq!(edge.display(), "Edge 126 on Windows");
+
+        let safari = parse("Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1");
+        assert_eq!(safari.display(), "Safari 17 on iOS");
+        assert_eq!(safari.os_version.as_deref(), Some("17.5"));
+        assert_eq!(safari.device_type, Some(DeviceType::Mobile));
+
+        let firefox = parse("Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0");
+        assert_eq!(firefox.display(), "Firefox 127 on Linux");
+
+        let android = parse("Mozilla/5.0 (Linux; Android 14; SM-X710) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36");
+        assert_eq!(android.display(), "Chrome 126 on Android");
+        assert_eq!(android.device_type, Some(DeviceType::Tablet));
+
+        let mac = parse("Mozilla/5.0 (Macintosh; Intel Mac OS X 14_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Safari/605.1.15");
+        assert_eq!(mac.display(), "Safari 17 on macOS");
+        assert_eq!(mac.os_version.as_deref(), Some("14.5"));
+
+        assert_eq!(parse("curl/8.5.0").display(), "curl 8");
+        assert_eq!(parse("Googlebot/2.1 (+http://www.google.com/bot.html)").device_type, Some(DeviceType::Bot));
+        assert_eq!(parse("something else").display(), "Unknown device");
+    }
+}
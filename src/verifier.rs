@@ -0,0 +1,350 @@
+//! Access-token verification for resource servers (feature `verifier`).
+//!
+//! Services that accept our access tokens embed a [`TokenVerifier`]: it
+//! fetches the JWKS document, caches it for the `Cache-Control: max-age`
+//! the auth server sends (or `cache_seconds`), and refetches early when a
+//! token names a key it has not seen, which covers key rollover. Verified
+//! tokens are remembered until they expire, so repeated calls with one
+//! token skip the signature check. HS256, RS256 and ES256 tokens are
+//! accepted; the claim rules are those of `passwordless_auth_client`, plus
+//! optional `issuer` and `audience` checks.
+//!
+//! ```ignore
+//! let verifier = Arc::new(TokenVerifier::new(VerifierConfig {
+//!     jwks_url: "https://auth.example.com/.well-known/jwks.json".into(),
+//!     audience: Some("billing".into()),
+//!     ..Default::default()
+//! }));
+//! let app = Router::new().route("/invoices", get(list_invoices)).with_state(verifier);
+//!
+//! async fn list_invoices(VerifiedToken(claims): VerifiedToken) -> String {
+//!     claims.sub
+//! }
+//! ```
+
+use crate::error::{ApiError, ErrorResponse};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+use passwordless_auth_client::{Claims, Jwks, Validation, Verifier, VerifyError};
+use reqwest::Client;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+/// Resource-server settings, typically a `[verifier]` table of the
+/// embedding service's own configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct VerifierConfig {
+    /// JWKS document of the auth server
+    pub jwks_url: String,
+    /// Required `iss`; the auth server's `jwt_issuer`
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Audience `aud` must contain; this service's `[[apis]]` audience
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Clock skew tolerated on `exp`, `nbf` and `iat`
+    #[serde(default = "default_leeway_seconds")]
+    pub leeway_seconds: u64,
+    /// Key set lifetime when the response has no `max-age`
+    #[serde(default = "default_cache_seconds")]
+    pub cache_seconds: u64,
+    /// Minimum time between refetches triggered by unknown keys
+    #[serde(default = "default_min_refetch_seconds")]
+    pub min_refetch_seconds: u64,
+    /// Verified tokens remembered until they expire; 0 disables the cache
+    #[serde(default = "default_token_cache_size")]
+    pub token_cache_size: usize,
+}
+
+impl Default for VerifierConfig {
+    fn default() -> Self {
+        Self {
+            jwks_url: String::new(),
+            issuer: None,
+            audience: None,
+            leeway_seconds: default_leeway_seconds(),
+            cache_seconds: default_cache_seconds(),
+            min_refetch_seconds: default_min_refetch_seconds(),
+            token_cache_size: default_token_cache_size(),
+        }
+    }
+}
+
+fn default_leeway_seconds() -> u64 {
+    60
+}
+
+fn default_cache_seconds() -> u64 {
+    300
+}
+
+fn default_min_refetch_seconds() -> u64 {
+    30
+}
+
+fn default_token_cache_size() -> usize {
+    10_000
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("invalid token: {0}")]
+    Invalid(VerifyError),
+    #[error("key set unavailable: {0}")]
+    KeysUnavailable(String),
+}
+
+struct CachedKeys {
+    jwks: Jwks,
+    /// Raw document, to notice when the key set changed
+    body: String,
+    fetched_at: Instant,
+    max_age: Duration,
+}
+
+/// Verifies access tokens against the auth server's published keys
+pub struct TokenVerifier {
+    cfg: VerifierConfig,
+    client: Client,
+    keys: RwLock<Option<CachedKeys>>,
+    /// Fixed keys (shared secret); never fetched
+    pinned: bool,
+    tokens: Mutex<HashMap<String, Claims>>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// `max-age` of a `Cache-Control` header
+fn max_age(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(header::CACHE_CONTROL)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find_map(|d| d.trim().strip_prefix("max-age=")?.parse().ok())
+        .map(Duration::from_secs)
+}
+
+impl TokenVerifier {
+    pub fn new(cfg: VerifierConfig) -> Self {
+        Self {
+            cfg,
+            client: Client::builder().timeout(Duration::from_secs(10)).build().unwrap(),
+            keys: RwLock::new(None),
+            pinned: false,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Verify with a shared HS256 secret (services deployed with
+    /// `jwt_secret`) instead of fetching a key set
+    pub fn with_shared_secret(cfg: VerifierConfig, secret: &str) -> Self {
+        let verifier = Self::new(cfg);
+        *verifier.keys.try_write().expect("new lock is free") = Some(CachedKeys {
+            jwks: Jwks::shared_secret(secret.as_bytes()),
+            body: String::new(),
+            fetched_at: Instant::now(),
+            max_age: Duration::MAX,
+        });
+        Self { pinned: true, ..verifier }
+    }
+
+    fn rules(&self, now: u64) -> Validation {
+        let mut rules = Validation::access(now).with_leeway(self.cfg.leeway_seconds);
+        if let Some(issuer) = &self.cfg.issuer {
+            rules = rules.with_issuer(issuer.clone());
+        }
+        if let Some(audience) = &self.cfg.audience {
+            rules = rules.with_audience(audience.clone());
+        }
+        rules
+    }
+
+    /// Verify an access token, fetching or refreshing keys as needed
+    pub async fn verify(&self, token: &str) -> Result<Claims, TokenError> {
+        let now = unix_now();
+        if let Some(claims) = self.cached_token(token, now) {
+            return Ok(claims);
+        }
+        self.refresh(false).await?;
+        let rules = self.rules(now);
+        let mut result = self.check(token, &rules).await;
+        if result == Err(VerifyError::UnknownKey) && self.refresh(true).await? {
+            // a new key may have been published since the last fetch
+            result = self.check(token, &rules).await;
+        }
+        let claims = result.map_err(TokenError::Invalid)?;
+        self.remember(token, &claims, now);
+        Ok(claims)
+    }
+
+    async fn check(&self, token: &str, rules: &Validation) -> Result<Claims, VerifyError> {
+        match self.keys.read().await.as_ref() {
+            Some(keys) => match Verifier::new(&keys.jwks).verify(token, rules) {
+                // the client crate only checks HMAC signatures
+                Err(VerifyError::UnsupportedAlgorithm(_)) => crate::jwt::verify_with_jwks(&keys.jwks, token, rules),
+                result => result,
+            },
+            None => Err(VerifyError::UnknownKey),
+        }
+    }
+
+    /// Refetch the key set when it is stale, or early (`unknown_key`) when
+    /// the last fetch is at least `min_refetch_seconds` old. Returns whether
+    /// a new document was loaded.
+    async fn refresh(&self, unknown_key: bool) -> Result<bool, TokenError> {
+        if self.pinned {
+            return Ok(false);
+        }
+        let due = |keys: &Option<CachedKeys>| match keys {
+            None => true,
+            Some(k) if unknown_key => k.fetched_at.elapsed() >= Duration::from_secs(self.cfg.min_refetch_seconds),
+            Some(k) => k.fetched_at.elapsed() >= k.max_age,
+        };
+        if !due(&*self.keys.read().await) {
+            return Ok(false);
+        }
+        let mut keys = self.keys.write().await;
+        // another request may have refreshed while we waited for the lock
+        if !due(&keys) {
+            return Ok(false);
+        }
+        match self.fetch().await {
+            Ok((jwks, body, max_age)) => {
+                if keys.as_ref().is_some_and(|k| k.body != body) {
+                    // removed keys must not keep validating remembered tokens
+                    self.tokens.lock().unwrap_or_else(|e| e.into_inner()).clear();
+                }
+                *keys = Some(CachedKeys {
+                    jwks,
+                    body,
+                    fetched_at: Instant::now(),
+                    max_age,
+                });
+                Ok(true)
+            }
+            // keep serving the last known keys while the auth server is unreachable
+            Err(e) if keys.is_some() => {
+                warn!("JWKS refresh failed, using cached keys: {}", e);
+                if let Some(k) = keys.as_mut() {
+                    k.fetched_at = Instant::now();
+                    k.max_age = Duration::from_secs(self.cfg.min_refetch_seconds);
+                }
+                Ok(false)
+            }
+            Err(e) => Err(TokenError::KeysUnavailable(e)),
+        }
+    }
+
+    async fn fetch(&self) -> Result<(Jwks, String, Duration), String> {
+        let response = self
+            .client
+            .get(&self.cfg.jwks_url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("JWKS endpoint returned {}", response.status()));
+        }
+        let max_age = max_age(response.headers()).unwrap_or(Duration::from_secs(self.cfg.cache_seconds));
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        let jwks = Jwks::from_json(&body).map_err(|e| e.to_string())?;
+        Ok((jwks, body, max_age))
+    }
+
+    fn cached_token(&self, token: &str, now: u64) -> Option<Claims> {
+        if self.cfg.token_cache_size == 0 {
+            return None;
+        }
+        let tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        tokens.get(token).filter(|c| c.exp as u64 > now).cloned()
+    }
+
+    fn remember(&self, token: &str, claims: &Claims, now: u64) {
+        if self.cfg.token_cache_size == 0 {
+            return;
+        }
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        if tokens.len() >= self.cfg.token_cache_size {
+            tokens.retain(|_, c| c.exp as u64 > now);
+            if tokens.len() >= self.cfg.token_cache_size {
+                tokens.clear();
+            }
+        }
+        tokens.insert(token.to_string(), claims.clone());
+    }
+}
+
+/// Claims of a verified `Authorization: Bearer` access token. Works in any
+/// router whose state provides an `Arc<TokenVerifier>`.
+pub struct VerifiedToken(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for VerifiedToken
+where
+    Arc<TokenVerifier>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = crate::extractors::bearer_token(parts)
+            .ok_or_else(|| ErrorResponse::unauthorized(ApiError::unauthorized("Missing access token")))?;
+        match Arc::<TokenVerifier>::from_ref(state).verify(&token).await {
+            Ok(claims) => Ok(Self(claims)),
+            Err(TokenError::Invalid(_)) => Err(ErrorResponse::unauthorized(ApiError::invalid_token())),
+            Err(TokenError::KeysUnavailable(e)) => {
+                warn!("Cannot verify access token: {}", e);
+                Err(ErrorResponse::new(
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                    ApiError::new("SERVICE_UNAVAILABLE", "token keys unavailable"),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shared_secret_tokens_are_verified_and_remembered() {
+        let secret = "supersecret1234567890";
+        let cfg = VerifierConfig {
+            audience: Some("billing".into()),
+            ..Default::default()
+        };
+        let verifier = TokenVerifier::with_shared_secret(cfg, secret);
+
+        let audience = ["billing", "reports"];
+        let token = crate::jwt::create_token_for("sub-1", secret, 60, "access", None, &audience, None).unwrap();
+        assert_eq!(verifier.verify(&token).await.unwrap().sub, "sub-1");
+        assert!(verifier.cached_token(&token, unix_now()).is_some());
+
+        let other = crate::jwt::create_token_for("sub-1", secret, 60, "access", None, &["reports"], None).unwrap();
+        assert!(matches!(
+            verifier.verify(&other).await,
+            Err(TokenError::Invalid(VerifyError::WrongAudience))
+        ));
+    }
+
+    #[test]
+    fn cache_control_max_age() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CACHE_CONTROL, "public, max-age=600, must-revalidate".parse().unwrap());
+        assert_eq!(max_age(&headers), Some(Duration::from_secs(600)));
+    }
+}
@@ -1,23 +1,75 @@
+use crate::aaguids;
+use crate::challenge_store::{self, Challenge, ChallengeStore, Purpose};
 use crate::config::Config;
 use crate::db::Database;
+use crate::storage::{redis::RedisStore, NewCredential, StorageError, StoreBackend, WebauthnCredentialStore};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use webauthn_rs::prelude::*;
+use url::Url;
+use webauthn_rs_core::proto::{
+    AuthenticatorTransport, Base64UrlSafeData, COSEAlgorithm, CreationChallengeResponse, Credential,
+    PublicKeyCredential, RegisterPublicKeyCredential, RegistrationState, RequestChallengeResponse,
+    ResidentKeyRequirement, AttestationConveyancePreference, AuthenticationState, UserVerificationPolicy,
+};
+use webauthn_rs_core::WebauthnCore;
 use rusqlite::params;
 use uuid::Uuid;
-use chrono::Utc;
 
 #[derive(Debug, Error)]
 pub enum WebauthnError {
     #[error("webauthn internal error: {0}")]
-    Internal(#[from] WebauthnErrorKind),
+    Internal(#[from] webauthn_rs_core::error::WebauthnError),
     #[error("missing pending challenge")]
     MissingChallenge,
     #[error("verification failed")]
     VerificationFailed,
     #[error("database error: {0}")]
     Db(#[from] rusqlite::Error),
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// A registered passkey with its usage, as shown to the user and admins
+#[derive(Debug, Serialize)]
+pub struct CredentialInfo {
+    pub id: String,
+    /// Authenticator model, when the authenticator disclosed it
+    pub aaguid: Option<String>,
+    /// Display name, by default the authenticator model's name
+    pub name: Option<String>,
+    pub created_at: i64,
+    pub transports: Vec<AuthenticatorTransport>,
+    /// Last successful assertion; `None` if never used since registration
+    pub last_used_at: Option<i64>,
+    pub use_count: i64,
+    pub last_ip: Option<String>,
+}
+
+/// A user's passkeys, most recently used first
+pub fn list_credentials(db: &Database, user_id: &str) -> Result<Vec<CredentialInfo>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT id, created_at, transports, last_used_at, use_count, last_ip, aaguid, name
+         FROM webauthn_registrations WHERE user_id = ?1 ORDER BY COALESCE(last_used_at, created_at) DESC",
+    )?;
+    let rows = stmt.query_map(params![user_id], |r| {
+        let transports: Option<String> = r.get(2)?;
+        Ok(CredentialInfo {
+            id: r.get(0)?,
+            aaguid: r.get(6)?,
+            name: r.get(7)?,
+            created_at: r.get(1)?,
+            transports: transports.and_then(|t| serde_json::from_str(&t).ok()).unwrap_or_default(),
+            last_used_at: r.get(3)?,
+            use_count: r.get(4)?,
+            last_ip: r.get(5)?,
+        })
+    })?;
+    rows.collect()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -26,47 +78,272 @@ pub struct PublicKeyCredentialDescriptorSerializable {
     pub transports: Option<Vec<AuthenticatorTransport>>,
 }
 
+/// `[webauthn]` configuration of the generated ceremony options
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebauthnOptionsConfig {
+    /// How long a registration challenge stays valid (also the client `timeout`)
+    #[serde(default = "default_ttl_seconds")]
+    pub registration_ttl_seconds: u32,
+    /// How long a login challenge stays valid (also the client `timeout`)
+    #[serde(default = "default_ttl_seconds")]
+    pub login_ttl_seconds: u32,
+    /// Random bytes per challenge; WebAuthn requires at least 16
+    #[serde(default = "default_challenge_bytes")]
+    pub challenge_bytes: usize,
+    #[serde(default = "default_user_verification")]
+    pub user_verification: Requirement,
+    /// Whether authenticators must store a discoverable credential
+    #[serde(default = "default_resident_key")]
+    pub resident_key: Requirement,
+    /// Signature algorithms offered at registration, in order of preference
+    #[serde(default = "default_algorithms")]
+    pub algorithms: Vec<Algorithm>,
+}
+
+impl Default for WebauthnOptionsConfig {
+    fn default() -> Self {
+        Self {
+            registration_ttl_seconds: default_ttl_seconds(),
+            login_ttl_seconds: default_ttl_seconds(),
+            challenge_bytes: default_challenge_bytes(),
+            user_verification: default_user_verification(),
+            resident_key: default_resident_key(),
+            algorithms: default_algorithms(),
+        }
+    }
+}
+
+fn default_ttl_seconds() -> u32 {
+    300
+}
+
+fn default_challenge_bytes() -> usize {
+    32
+}
+
+fn default_user_verification() -> Requirement {
+    Requirement::Required
+}
+
+fn default_resident_key() -> Requirement {
+    Requirement::Discouraged
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::Es256, Algorithm::Rs256]
+}
+
+/// WebAuthn `required` / `preferred` / `discouraged` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Requirement {
+    Required,
+    Preferred,
+    Discouraged,
+}
+
+impl Requirement {
+    fn user_verification(self) -> UserVerificationPolicy {
+        match self {
+            Self::Required => UserVerificationPolicy::Required,
+            Self::Preferred => UserVerificationPolicy::Preferred,
+            Self::Discouraged => UserVerificationPolicy::Discouraged_DO_NOT_USE,
+        }
+    }
+
+    fn resident_key(self) -> ResidentKeyRequirement {
+        match self {
+            Self::Required => ResidentKeyRequirement::Required,
+            Self::Preferred => ResidentKeyRequirement::Preferred,
+            Self::Discouraged => ResidentKeyRequirement::Discouraged,
+        }
+    }
+}
+
+/// Credential signature algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Algorithm {
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+impl Algorithm {
+    /// COSE algorithm identifier
+    fn cose(self) -> COSEAlgorithm {
+        match self {
+            Self::Es256 => COSEAlgorithm::ES256,
+            Self::Rs256 => COSEAlgorithm::RS256,
+            Self::EdDsa => COSEAlgorithm::EDDSA,
+        }
+    }
+}
+
+impl WebauthnOptionsConfig {
+    /// Checked at startup, so a typo fails there rather than in every ceremony
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, ttl) in [
+            ("registration_ttl_seconds", self.registration_ttl_seconds),
+            ("login_ttl_seconds", self.login_ttl_seconds),
+        ] {
+            if !(30..=3600).contains(&ttl) {
+                return Err(format!("[webauthn] {} must be between 30 and 3600, got {}", key, ttl));
+            }
+        }
+        if !(16..=64).contains(&self.challenge_bytes) {
+            return Err(format!(
+                "[webauthn] challenge_bytes must be between 16 and 64, got {}",
+                self.challenge_bytes
+            ));
+        }
+        if self.algorithms.is_empty() {
+            return Err("[webauthn] algorithms must not be empty".to_string());
+        }
+        if self.algorithms.iter().collect::<HashSet<_>>().len() != self.algorithms.len() {
+            return Err("[webauthn] algorithms lists an algorithm twice".to_string());
+        }
+        Ok(())
+    }
+
+    /// A fresh challenge of `challenge_bytes` random bytes
+    fn challenge(&self) -> Vec<u8> {
+        let mut challenge = vec![0u8; self.challenge_bytes];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        challenge
+    }
+}
+
+/// Serialized ceremony state for the pending challenge, with the library's
+/// fixed-size challenge replaced by ours so the client and the state agree
+fn stored_state<T: Serialize>(state: &T, challenge: &[u8]) -> Vec<u8> {
+    let mut state = serde_json::to_value(state).unwrap();
+    state["challenge"] = serde_json::to_value(Base64UrlSafeData::from(challenge.to_vec())).unwrap();
+    serde_json::to_vec(&state).unwrap()
+}
+
+/// Ceremonies run on [`WebauthnCore`] rather than the `webauthn-rs` passkey
+/// wrappers, which fix user verification, resident keys and algorithms; here
+/// `[webauthn]` decides them, and the stored state enforces them at finish.
 pub struct WebauthnState {
-    pub rp: RelyingParty,
+    /// Relying party for registrations; its timeout is `registration_ttl_seconds`
+    pub registration: WebauthnCore,
+    /// Relying party for logins; its timeout is `login_ttl_seconds`
+    pub login: WebauthnCore,
+    pub options: WebauthnOptionsConfig,
+    /// Pending ceremonies between the options and complete calls
+    pub challenges: Arc<dyn ChallengeStore>,
 }
 
 impl WebauthnState {
     pub fn new(cfg: &Config) -> Self {
-        let rp = RelyingParty::builder(cfg.webauthn_rp_id.clone(), cfg.webauthn_origin.clone())
-            .name(cfg.webauthn_rp_name.clone())
-            .build()
-            .expect("invalid RP setup");
-        Self { rp }
+        let origin = Url::parse(&cfg.webauthn_origin).expect("invalid RP setup");
+        let rp = |ttl_seconds: u32| {
+            WebauthnCore::new_unsafe_experts_only(
+                &cfg.webauthn_rp_name,
+                &cfg.webauthn_rp_id,
+                vec![origin.clone()],
+                Duration::from_secs(ttl_seconds as u64),
+                None,
+                None,
+            )
+        };
+        Self {
+            registration: rp(cfg.webauthn.registration_ttl_seconds),
+            login: rp(cfg.webauthn.login_ttl_seconds),
+            options: cfg.webauthn.clone(),
+            challenges: match cfg.store {
+                StoreBackend::Redis => Arc::new(RedisStore::new(&cfg.redis).expect("invalid [redis] url")),
+                StoreBackend::Sqlite | StoreBackend::Postgres => challenge_store::from_config(&cfg.challenge_cache),
+            },
+        }
     }
 
-    pub fn start_registration(
+    /// Keep pending ceremonies in `store` instead of the configured one
+    pub fn with_challenge_store(mut self, store: Arc<dyn ChallengeStore>) -> Self {
+        self.challenges = store;
+        self
+    }
+
+    /// The unexpired pending ceremony `pending_id` for `purpose`
+    fn pending(&self, db: &Database, pending_id: &str, purpose: Purpose) -> Result<Challenge, WebauthnError> {
+        let pending = self
+            .challenges
+            .get(db, pending_id, purpose)?
+            .ok_or(WebauthnError::MissingChallenge)?;
+        if pending.is_expired(Database::now_ts()) {
+            return Err(WebauthnError::VerificationFailed);
+        }
+        Ok(pending)
+    }
+
+    /// Use up a verified ceremony; fails when it was invalidated meanwhile
+    fn consume(&self, db: &Database, pending_id: &str) -> Result<(), WebauthnError> {
+        if !self.challenges.consume(db, pending_id)? {
+            return Err(WebauthnError::MissingChallenge);
+        }
+        Ok(())
+    }
+
+    /// Passkeys of `user_id` this server can verify assertions for
+    fn credentials(db: &Database, user_id: &str) -> Result<Vec<Credential>, WebauthnError> {
+        Ok(db
+            .credential_keys(user_id)?
+            .iter()
+            .filter_map(|c| serde_json::from_slice(c).ok())
+            .collect())
+    }
+
+    /// Registration options for `user_id` and the pending ceremony checking
+    /// the response, not yet stored
+    pub fn new_registration(
         &self,
         db: &Database,
         user_id: &str,
         user_name: &str,
-    ) -> Result<PublicKeyCredentialCreationOptions, WebauthnError> {
-        let user = PublicKeyCredentialUserEntityBuilder::new(user_id.as_bytes().to_vec())
-            .name(user_name.to_string())
-            .display_name(user_name.to_string())
-            .build()
-            .map_err(We)??;
-
-        let creation = self
-            .rp
-            .start_passkey_registration(Some(user), None)
-            .map_err(We)??;
-
-        let challenge = creation.challenge().clone();
-        let id = Uuid::new_v4().to_string();
-        let now = Database::now_ts();
-        let expires_at = now + 300; // 5 minutes
+    ) -> Result<(CreationChallengeResponse, Challenge), WebauthnError> {
+        let options = &self.options;
+        let exclude = Self::credentials(db, user_id)?.into_iter().map(|c| c.cred_id).collect();
+        let builder = self
+            .registration
+            .new_challenge_register_builder(user_id.as_bytes(), user_name, user_name)?
+            .attestation(AttestationConveyancePreference::None)
+            .credential_algorithms(options.algorithms.iter().map(|a| a.cose()).collect())
+            .require_resident_key(options.resident_key == Requirement::Required)
+            .user_verification_policy(options.user_verification.user_verification())
+            .exclude_credentials(Some(exclude));
+        let (mut creation, state) = self.registration.generate_challenge_register(builder)?;
+        if let Some(selection) = creation.public_key.authenticator_selection.as_mut() {
+            // `preferred` is a hint to the authenticator; only `required` is enforced
+            selection.resident_key = Some(options.resident_key.resident_key());
+        }
+        let challenge = options.challenge();
+        creation.public_key.challenge = challenge.clone().into();
 
-        let serialized = serde_json::to_vec(&creation).unwrap();
-        db.conn.execute(
-            "INSERT INTO pending_webauthn (id, user_id, challenge, purpose, created_at, expires_at, serialized_options) VALUES (?1, ?2, ?3, 'register', ?4, ?5, ?6)",
-            params![id, user_id, challenge.clone(), now, expires_at, serialized],
-        )?;
+        let now = Database::now_ts();
+        let pending = Challenge {
+            id: Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            purpose: Purpose::Register,
+            options: stored_state(&state, &challenge),
+            challenge,
+            created_at: now,
+            expires_at: now + options.registration_ttl_seconds as i64,
+            client_id: None,
+        };
+        Ok((creation, pending))
+    }
 
+    pub fn start_registration(
+        &self,
+        db: &Database,
+        user_id: &str,
+        user_name: &str,
+    ) -> Result<CreationChallengeResponse, WebauthnError> {
+        let (creation, pending) = self.new_registration(db, user_id, user_name)?;
+        self.challenges.put(db, pending)?;
         Ok(creation)
     }
 
@@ -75,153 +352,140 @@ impl WebauthnState {
         db: &Database,
         pending_id: &str,
         response: serde_json::Value,
-    ) -> Result<(), WebauthnError> {
-        // load pending
-        let mut stmt = db.conn.prepare(
-            "SELECT user_id, challenge, serialized_options, expires_at FROM pending_webauthn WHERE id = ?1 AND purpose = 'register'"
-        )?;
-        let mut rows = stmt.query(params![pending_id])?;
-        let row = rows.next()?.ok_or(WebauthnError::MissingChallenge)?;
-        let user_id: String = row.get(0)?;
-        let challenge: Vec<u8> = row.get(1)?;
-        let serialized: Vec<u8> = row.get(2)?;
-        let expires_at: i64 = row.get(3)?;
-        if Database::now_ts() > expires_at {
-            return Err(WebauthnError::VerificationFailed);
-        }
-
-        let options: PublicKeyCredentialCreationOptions =
-            serde_json::from_slice(&serialized).map_err(|_| WebauthnError::VerificationFailed)?;
-        let attestation_response: PublicKeyCredential =
+    ) -> Result<String, WebauthnError> {
+        let pending = self.pending(db, pending_id, Purpose::Register)?;
+        let user_id = pending.user_id;
+        let state: RegistrationState =
+            serde_json::from_slice(&pending.options).map_err(|_| WebauthnError::VerificationFailed)?;
+        let aaguid = aaguids::from_registration(&response);
+        let attestation_response: RegisterPublicKeyCredential =
             serde_json::from_value(response).map_err(|_| WebauthnError::VerificationFailed)?;
 
-        let registration_info = self
-            .rp
-            .finish_passkey_registration(&options, &attestation_response, None)
-            .map_err(We)??;
+        let credential = self.registration.register_credential(&attestation_response, &state, None)?;
+        self.consume(db, pending_id)?;
 
         // Persist credential
-        let registration_id = Uuid::new_v4().to_string();
-        let credential_id = registration_info.cred_id().clone();
-        let public_key = registration_info.credential_public_key().clone();
-        let sign_count = registration_info.sign_count();
-        let transports = serde_json::to_string(
-            &registration_info
-                .transports()
-                .unwrap_or(&vec![])
-                .iter()
-                .map(|t| t.clone())
-                .collect::<Vec<_>>(),
-        )
-        .unwrap();
-        let now = Database::now_ts();
-
-        db.conn.execute(
-            "INSERT INTO webauthn_registrations (id, user_id, credential_id, public_key, sign_count, transports, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                registration_id,
-                user_id,
-                credential_id,
-                public_key,
-                sign_count as i64,
-                transports,
-                now
-            ],
-        )?;
+        let transports = serde_json::to_string(&credential.transports.clone().unwrap_or_default()).unwrap();
+        // a failed lookup leaves the passkey unnamed rather than failing the registration
+        let name = aaguid.as_deref().and_then(|a| aaguids::name_for(db, a).ok().flatten());
+        db.add_credential(&NewCredential {
+            id: Uuid::new_v4().to_string(),
+            user_id: user_id.clone(),
+            credential_id: credential.cred_id.to_vec(),
+            public_key: serde_json::to_vec(&credential).unwrap(),
+            sign_count: credential.counter as i64,
+            transports,
+            aaguid,
+            name,
+            created_at: Database::now_ts(),
+        })?;
+        Ok(user_id)
+    }
 
-        // cleanup pending
-        db.conn.execute("DELETE FROM pending_webauthn WHERE id = ?1", params![pending_id])?;
-        Ok(())
+    /// Expire every pending challenge (failure injection); returns how many were live
+    pub fn expire_challenges(&self, db: &Database) -> Result<usize, WebauthnError> {
+        Ok(self.challenges.expire_all(db, Database::now_ts())?)
     }
 
+    /// Start a passkey login for the application `client_id`; see [`Self::login_client`]
     pub fn start_login(
         &self,
         db: &Database,
         user_id: &str,
-    ) -> Result<PublicKeyCredentialRequestOptions, WebauthnError> {
-        // load existing credentials to exclude none
-        let mut stmt = db.conn.prepare(
-            "SELECT credential_id FROM webauthn_registrations WHERE user_id = ?1",
+        client_id: Option<&str>,
+    ) -> Result<RequestChallengeResponse, WebauthnError> {
+        let builder = self.login.new_challenge_authenticate_builder(
+            Self::credentials(db, user_id)?,
+            Some(self.options.user_verification.user_verification()),
         )?;
-        let mut rows = stmt.query(params![user_id])?;
-        let mut allow_list = vec![];
-        while let Some(r) = rows.next()? {
-            let cred_id: Vec<u8> = r.get(0)?;
-            allow_list.push(PublicKeyCredentialDescriptor::new(cred_id.clone(), None));
-        }
-        let request = self
-            .rp
-            .start_passkey_authentication(Some(allow_list), None)
-            .map_err(We)??;
+        let (mut request, state) = self.login.generate_challenge_authenticate(builder)?;
+        let challenge = self.options.challenge();
+        request.public_key.challenge = challenge.clone().into();
 
-        let challenge = request.challenge().clone();
-        let id = Uuid::new_v4().to_string();
         let now = Database::now_ts();
-        let expires_at = now + 300;
-        let serialized = serde_json::to_vec(&request).unwrap();
-
-        db.conn.execute(
-            "INSERT INTO pending_webauthn (id, user_id, challenge, purpose, created_at, expires_at, serialized_options) VALUES (?1, ?2, ?3, 'login', ?4, ?5, ?6)",
-            params![id, user_id, challenge.clone(), now, expires_at, serialized],
+        self.challenges.put(
+            db,
+            Challenge {
+                id: Uuid::new_v4().to_string(),
+                user_id: user_id.to_string(),
+                purpose: Purpose::Login,
+                options: stored_state(&state, &challenge),
+                challenge,
+                created_at: now,
+                expires_at: now + self.options.login_ttl_seconds as i64,
+                client_id: client_id.map(str::to_string),
+            },
         )?;
 
         Ok(request)
     }
 
+    /// Application the login ceremony `pending_id` was started for
+    pub fn login_client(&self, db: &Database, pending_id: &str) -> Result<Option<String>, WebauthnError> {
+        Ok(self.challenges.get(db, pending_id, Purpose::Login)?.and_then(|c| c.client_id))
+    }
+
+    /// Verify an assertion and record the credential's use from `ip`
     pub fn finish_login(
         &self,
         db: &Database,
         pending_id: &str,
         response: serde_json::Value,
+        ip: Option<&str>,
     ) -> Result<String, WebauthnError> {
-        let mut stmt = db.conn.prepare(
-            "SELECT user_id, serialized_options, expires_at FROM pending_webauthn WHERE id = ?1 AND purpose = 'login'",
-        )?;
-        let mut rows = stmt.query(params![pending_id])?;
-        let row = rows.next()?.ok_or(WebauthnError::MissingChallenge)?;
-        let user_id: String = row.get(0)?;
-        let serialized: Vec<u8> = row.get(1)?;
-        let expires_at: i64 = row.get(2)?;
-        if Database::now_ts() > expires_at {
-            return Err(WebauthnError::VerificationFailed);
-        }
-        let options: PublicKeyCredentialRequestOptions =
-            serde_json::from_slice(&serialized).map_err(|_| WebauthnError::VerificationFailed)?;
+        let pending = self.pending(db, pending_id, Purpose::Login)?;
+        let user_id = pending.user_id;
+        let state: AuthenticationState =
+            serde_json::from_slice(&pending.options).map_err(|_| WebauthnError::VerificationFailed)?;
         let assertion_response: PublicKeyCredential =
             serde_json::from_value(response).map_err(|_| WebauthnError::VerificationFailed)?;
 
-        let authentication_info = self
-            .rp
-            .finish_passkey_authentication(&options, &assertion_response, None)
-            .map_err(We)??;
-
-        // verify credential exists and update sign_count
-        let credential_id = authentication_info.cred_id().clone();
-        let mut stmt2 = db.conn.prepare("SELECT id, sign_count FROM webauthn_registrations WHERE credential_id = ?1")?;
-        let mut rows2 = stmt2.query(params![credential_id.clone()])?;
-        if let Some(r2) = rows2.next()? {
-            let reg_id: String = r2.get(0)?;
-            let stored_sign_count: i64 = r2.get(1)?;
-            let new_sign_count = authentication_info.sign_count() as i64;
-            if new_sign_count <= stored_sign_count {
-                return Err(WebauthnError::VerificationFailed);
-            }
-            db.conn.execute(
-                "UPDATE webauthn_registrations SET sign_count = ?1 WHERE id = ?2",
-                params![new_sign_count, reg_id],
-            )?;
-        } else {
+        let authentication_info = self.login.authenticate_credential(&assertion_response, &state)?;
+
+        // verify credential exists, then update sign_count and usage
+        let stored = db
+            .find_credential(authentication_info.cred_id())?
+            .ok_or(WebauthnError::VerificationFailed)?;
+        let new_sign_count = authentication_info.counter() as i64;
+        if new_sign_count <= stored.sign_count {
+            return Err(WebauthnError::VerificationFailed);
+        }
+        self.consume(db, pending_id)?;
+        // a concurrent assertion that already moved the counter past ours wins
+        if !db.record_assertion(&stored.id, new_sign_count, ip, Database::now_ts())? {
             return Err(WebauthnError::VerificationFailed);
         }
-
-        // cleanup pending
-        db.conn.execute("DELETE FROM pending_webauthn WHERE id = ?1", params![pending_id])?;
 
         Ok(user_id)
     }
 }
 
-// helper to convert webauthn-rs internal errors
-fn We(e: webauthn_rs::prelude::WebauthnError) -> WebauthnErrorKind {
-    WebauthnErrorKind::from(e)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_options() {
+        assert!(WebauthnOptionsConfig::default().validate().is_ok());
+
+        let parsed: WebauthnOptionsConfig =
+            toml::from_str("login_ttl_seconds = 120\nuser_verification = \"preferred\"\nalgorithms = [\"EdDSA\", \"ES256\"]").unwrap();
+        assert!(parsed.validate().is_ok());
+        assert_eq!(
+            parsed.algorithms.iter().map(|a| a.cose() as i64).collect::<Vec<_>>(),
+            vec![-8, -7]
+        );
+        assert_eq!(parsed.registration_ttl_seconds, 300);
+
+        for bad in [
+            "challenge_bytes = 8",
+            "login_ttl_seconds = 5",
+            "algorithms = []",
+            "algorithms = [\"ES256\", \"ES256\"]",
+        ] {
+            let cfg: WebauthnOptionsConfig = toml::from_str(bad).unwrap();
+            assert!(cfg.validate().is_err(), "{}", bad);
+        }
+        assert!(toml::from_str::<WebauthnOptionsConfig>("algorithms = [\"PS256\"]").is_err());
+    }
 }
@@ -0,0 +1,197 @@
+//! Rotation of the webhook signing secret without receiver downtime.
+//!
+//! A rotation mints a new current secret and keeps the previous one signing
+//! for `[webhook_secrets] grace_period_seconds`: during the grace period
+//! every delivery carries one `X-Webhook-Signature` entry per secret, so a
+//! receiver verifies with either its old or its new secret while it is
+//! being redeployed. Afterwards the previous secret simply stops signing.
+//! Until the first rotation the configured `webhook_secret` is the current
+//! secret. Every instance reloads the secrets from the database, so a
+//! rotation triggered on one replica reaches all of them.
+
+use crate::{crypto, db::Database, leader::LeaderElection, webhooks::WebhookSender};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use rand::RngCore;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+/// Prefix of generated webhook secrets
+pub const SECRET_PREFIX: &str = "whsec_";
+
+/// `[webhook_secrets]` configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookSecretConfig {
+    /// How long the previous secret keeps signing after a rotation
+    #[serde(default = "default_grace_period_seconds")]
+    pub grace_period_seconds: i64,
+    /// How often each instance reloads the secrets
+    #[serde(default = "default_refresh_interval_seconds")]
+    pub refresh_interval_seconds: u64,
+}
+
+impl Default for WebhookSecretConfig {
+    fn default() -> Self {
+        Self {
+            grace_period_seconds: default_grace_period_seconds(),
+            refresh_interval_seconds: default_refresh_interval_seconds(),
+        }
+    }
+}
+
+fn default_grace_period_seconds() -> i64 {
+    86400
+}
+
+fn default_refresh_interval_seconds() -> u64 {
+    60
+}
+
+/// One stored secret, as listed to admins (never the secret itself)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecretVersion {
+    pub version: i64,
+    /// First bytes of the secret's SHA-256, to match it against a receiver's
+    pub fingerprint: String,
+    pub created_at: i64,
+    /// Replaced by a newer secret; `None` for the current one
+    pub retired_at: Option<i64>,
+    /// Stops signing at this time
+    pub expires_at: Option<i64>,
+}
+
+/// Outcome of a rotation; the new secret is only ever shown here
+#[derive(Debug, Clone, Serialize)]
+pub struct Rotation {
+    pub version: i64,
+    pub secret: String,
+    /// When the previous secret stops signing, if there was one
+    pub previous_expires_at: Option<i64>,
+}
+
+pub fn fingerprint(secret: &str) -> String {
+    HEXLOWER.encode(&crypto::sha256(secret.as_bytes())[..8])
+}
+
+fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", SECRET_PREFIX, BASE64URL_NOPAD.encode(&bytes))
+}
+
+/// Replace the current secret. The configured secret counts as the current
+/// one until the first rotation, so it is kept for the grace period too.
+pub fn rotate(db: &Database, configured: Option<&str>, grace_period_seconds: i64) -> Result<Rotation, rusqlite::Error> {
+    let now = Database::now_ts();
+    let conn = db.conn();
+    let tx = conn.unchecked_transaction()?;
+    let stored: i64 = tx.query_row("SELECT COUNT(*) FROM webhook_secrets", [], |r| r.get(0))?;
+    if let (0, Some(secret)) = (stored, configured.filter(|s| !s.is_empty())) {
+        tx.execute(
+            "INSERT INTO webhook_secrets (secret, created_at) VALUES (?1, ?2)",
+            params![secret, now],
+        )?;
+    }
+    let expires_at = now + grace_period_seconds.max(0);
+    let retired = tx.execute(
+        "UPDATE webhook_secrets SET retired_at = ?1, expires_at = ?2 WHERE retired_at IS NULL",
+        params![now, expires_at],
+    )?;
+    let secret = generate();
+    tx.execute(
+        "INSERT INTO webhook_secrets (secret, created_at) VALUES (?1, ?2)",
+        params![secret, now],
+    )?;
+    let version = tx.last_insert_rowid();
+    tx.commit()?;
+    Ok(Rotation {
+        version,
+        secret,
+        previous_expires_at: (retired > 0).then_some(expires_at),
+    })
+}
+
+/// End the grace period now, once every receiver verifies with the new
+/// secret; returns how many previous secrets stopped signing
+pub fn expire_previous(db: &Database) -> Result<usize, rusqlite::Error> {
+    let now = Database::now_ts();
+    db.conn().execute(
+        "UPDATE webhook_secrets SET expires_at = ?1 WHERE retired_at IS NOT NULL AND expires_at > ?1",
+        params![now],
+    )
+}
+
+/// Secrets that sign deliveries at `now`, current first
+pub fn active(db: &Database, configured: Option<&str>, now: i64) -> Result<Vec<String>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT secret FROM webhook_secrets WHERE expires_at IS NULL OR expires_at > ?1 ORDER BY version DESC",
+    )?;
+    let secrets = stmt.query_map(params![now], |r| r.get(0))?.collect::<Result<Vec<String>, _>>()?;
+    if secrets.is_empty() {
+        return Ok(configured.filter(|s| !s.is_empty()).map(String::from).into_iter().collect());
+    }
+    Ok(secrets)
+}
+
+/// Stored secrets, newest first
+pub fn list(db: &Database) -> Result<Vec<SecretVersion>, rusqlite::Error> {
+    let conn = db.conn();
+    let mut stmt = conn.prepare(
+        "SELECT version, secret, created_at, retired_at, expires_at FROM webhook_secrets ORDER BY version DESC",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        Ok(SecretVersion {
+            version: r.get(0)?,
+            fingerprint: fingerprint(&r.get::<_, String>(1)?),
+            created_at: r.get(2)?,
+            retired_at: r.get(3)?,
+            expires_at: r.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Delete secrets that stopped signing before `now`
+pub fn purge_expired(db: &Database, now: i64) -> Result<usize, rusqlite::Error> {
+    db.conn()
+        .execute("DELETE FROM webhook_secrets WHERE expires_at <= ?1", params![now])
+}
+
+/// Load the signing secrets into `sender`
+pub fn load(db: &Database, sender: &WebhookSender, configured: Option<&str>) -> Result<(), rusqlite::Error> {
+    sender.set_secrets(active(db, configured, Database::now_ts())?);
+    Ok(())
+}
+
+/// Keep this instance's signing secrets in step with rotations and expiries
+pub fn spawn_refresher(
+    db: Arc<Database>,
+    leader: Arc<LeaderElection>,
+    sender: Arc<WebhookSender>,
+    configured: Option<String>,
+    cfg: WebhookSecretConfig,
+) {
+    if !sender.is_configured() {
+        return;
+    }
+    let every = Duration::from_secs(cfg.refresh_interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = load(&db, &sender, configured.as_deref()) {
+                warn!("Failed to reload webhook secrets: {}", e);
+            }
+            if !leader.lead("webhook_secrets", every) {
+                continue;
+            }
+            match purge_expired(&db, Database::now_ts()) {
+                Ok(0) => {}
+                Ok(n) => info!("Removed {} expired webhook secrets", n),
+                Err(e) => warn!("Failed to remove expired webhook secrets: {}", e),
+            }
+        }
+    });
+}
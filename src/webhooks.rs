@@ -1,8 +1,96 @@
-use reqwest::Client;
+use crate::crypto::{constant_time_eq, hmac_sha256};
+use crate::resilience::{CallError, CircuitBreaker, DependencyPolicy};
+use axum::http::HeaderMap;
+use reqwest::{header::CONTENT_TYPE, Client};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
 use tracing::{error, info};
 
+pub const EVENT_ID_HEADER: &str = "X-Webhook-Event-Id";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// Suggested receiver tolerance for [`verify_signature`]
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(300);
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+/// `v1=<hex HMAC-SHA256 of "{event_id}.{timestamp}.{body}">`
+pub fn sign(secret: &str, event_id: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut signed = format!("{}.{}.", event_id, timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    format!("v1={}", data_encoding::HEXLOWER.encode(&hmac_sha256(secret.as_bytes(), &signed)))
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("missing {0} header")]
+    MissingHeader(&'static str),
+    #[error("malformed {0} header")]
+    Malformed(&'static str),
+    #[error("timestamp is {0}s away from now, outside the tolerance")]
+    OutsideTolerance(i64),
+    #[error("signature does not match")]
+    Mismatch,
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &'static str) -> Result<&'a str, SignatureError> {
+    headers
+        .get(name)
+        .ok_or(SignatureError::MissingHeader(name))?
+        .to_str()
+        .map_err(|_| SignatureError::Malformed(name))
+}
+
+/// Verify a webhook delivery in a receiver.
+///
+/// Checks the `X-Webhook-Signature` HMAC over the event id, timestamp and
+/// raw body (pass the bytes exactly as received, before JSON parsing), and
+/// rejects deliveries whose timestamp is more than `tolerance` away from
+/// now. Every attempt is signed afresh, so retries stay inside the window.
+/// Within the window, deduplicate on the returned event id to reject replays.
+pub fn verify_signature<'a>(
+    headers: &'a HeaderMap,
+    body: &[u8],
+    secret: &str,
+    tolerance: Duration,
+) -> Result<&'a str, SignatureError> {
+    verify_signature_at(headers, body, secret, tolerance, unix_now())
+}
+
+/// [`verify_signature`] against a given clock (unix seconds)
+pub fn verify_signature_at<'a>(
+    headers: &'a HeaderMap,
+    body: &[u8],
+    secret: &str,
+    tolerance: Duration,
+    now: i64,
+) -> Result<&'a str, SignatureError> {
+    let event_id = header(headers, EVENT_ID_HEADER)?;
+    let timestamp: i64 = header(headers, TIMESTAMP_HEADER)?
+        .trim()
+        .parse()
+        .map_err(|_| SignatureError::Malformed(TIMESTAMP_HEADER))?;
+    let age = now - timestamp;
+    if age.unsigned_abs() > tolerance.as_secs() {
+        return Err(SignatureError::OutsideTolerance(age));
+    }
+    let expected = sign(secret, event_id, timestamp, body);
+    // several comma-separated signatures are accepted (secret rotation)
+    let matched = header(headers, SIGNATURE_HEADER)?
+        .split(',')
+        .any(|candidate| constant_time_eq(candidate.trim().as_bytes(), expected.as_bytes()));
+    if matched {
+        Ok(event_id)
+    } else {
+        Err(SignatureError::Mismatch)
+    }
+}
+
 /// Webhook event types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -11,8 +99,14 @@ pub enum WebhookEventType {
     UserAuthenticated,
     SessionCreated,
     SessionRevoked,
+    /// A user's last session enters the `[session_expiry]` warning window
+    SessionExpiring,
+    /// A recipient reported a magic link they did not request
+    UnsolicitedLinkReported,
     TotpEnrolled,
     WebauthnRegistered,
+    /// Security-severity audit event (when `[audit] security_alerts` is on)
+    SecurityAlert,
 }
 
 /// Webhook payload
@@ -30,7 +124,9 @@ pub struct WebhookPayload {
 pub struct WebhookSender {
     client: Client,
     webhook_url: Option<String>,
-    webhook_secret: Option<String>,
+    /// Signing secrets, current first (see `webhook_secrets`)
+    secrets: Arc<RwLock<Vec<String>>>,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl WebhookSender {
@@ -43,38 +139,97 @@ impl WebhookSender {
         Self {
             client,
             webhook_url,
-            webhook_secret,
+            secrets: Arc::new(RwLock::new(webhook_secret.into_iter().collect())),
+            breaker: Arc::new(CircuitBreaker::new("webhook", DependencyPolicy::default())),
         }
     }
 
-    /// Send a webhook event (async, fire-and-forget)
-    pub async fn send(&self, payload: WebhookPayload) {
-        if let Some(url) = &self.webhook_url {
-            info!("Sending webhook for event: {:?}", payload.event);
+    /// Use a specific circuit breaker / retry policy for the webhook target
+    pub fn with_policy(mut self, policy: DependencyPolicy) -> Self {
+        self.breaker = Arc::new(CircuitBreaker::new("webhook", policy));
+        self
+    }
 
-            let mut request = self.client.post(url).json(&payload);
+    /// Replace the signing secrets, current first
+    pub fn set_secrets(&self, secrets: Vec<String>) {
+        *self.secrets.write().unwrap_or_else(|e| e.into_inner()) = secrets;
+    }
 
-            // Add secret as header if configured
-            if let Some(secret) = &self.webhook_secret {
-                request = request.header("X-Webhook-Secret", secret);
-            }
+    pub fn is_configured(&self) -> bool {
+        self.webhook_url.is_some()
+    }
+
+    /// Check that the webhook endpoint accepts connections (any HTTP status counts)
+    pub async fn probe(&self) -> Result<(), String> {
+        let Some(url) = &self.webhook_url else {
+            return Err("webhook not configured".to_string());
+        };
+        self.client
+            .head(url)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
 
-            match request.send().await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        info!("Webhook sent successfully: {:?}", payload.event);
-                    } else {
-                        error!(
-                            "Webhook failed with status {}: {:?}",
-                            response.status(),
-                            payload.event
-                        );
-                    }
+    /// Deliver a webhook event, reporting failure so the caller can retry.
+    ///
+    /// `event_id` is sent as `X-Webhook-Event-Id` and stays the same across
+    /// retries so receivers can deduplicate. With a secret configured each
+    /// attempt carries `X-Webhook-Timestamp` and `X-Webhook-Signature`
+    /// (see [`verify_signature`]), with one signature per active secret
+    /// while a rotation's grace period runs.
+    pub async fn deliver(&self, payload: &WebhookPayload, event_id: &str) -> Result<(), String> {
+        let Some(url) = &self.webhook_url else {
+            return Ok(());
+        };
+        info!("Sending webhook for event: {:?}", payload.event);
+        let body = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+        let secrets = self.secrets.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let result = self
+            .breaker
+            .call_async(|| async {
+                let mut request = self
+                    .client
+                    .post(url)
+                    .header(EVENT_ID_HEADER, event_id)
+                    .header(CONTENT_TYPE, "application/json");
+
+                if !secrets.is_empty() {
+                    let timestamp = unix_now();
+                    let signatures: Vec<String> =
+                        secrets.iter().map(|secret| sign(secret, event_id, timestamp, &body)).collect();
+                    request = request
+                        .header(TIMESTAMP_HEADER, timestamp)
+                        .header(SIGNATURE_HEADER, signatures.join(","));
                 }
-                Err(e) => {
-                    error!("Failed to send webhook: {}", e);
+                let request = request.body(body.clone());
+
+                let response = request.send().await.map_err(|e| e.to_string())?;
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("webhook endpoint returned {}", response.status()))
                 }
+            })
+            .await;
+
+        match result {
+            Ok(()) => {
+                info!("Webhook sent successfully: {:?}", payload.event);
+                Ok(())
             }
+            Err(CallError::Open) => Err("webhook circuit open".to_string()),
+            Err(CallError::Failed(e)) => Err(e),
+        }
+    }
+
+    /// Send a webhook event (async, fire-and-forget)
+    pub async fn send(&self, payload: WebhookPayload) {
+        let event_id = uuid::Uuid::new_v4().to_string();
+        if let Err(e) = self.deliver(&payload, &event_id).await {
+            error!("Failed to send webhook {:?}: {}", payload.event, e);
         }
     }
 
@@ -88,3 +243,61 @@ impl WebhookSender {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test";
+    const BODY: &[u8] = br#"{"event":"session_revoked","user_id":"u1"}"#;
+    const VECTOR: &str = "v1=9457df54b9a93af8cfac1e614d86f5dad620967fec3d567d4efe55fb63ef62bf";
+
+    fn headers(timestamp: &str, signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(EVENT_ID_HEADER, "evt_1".parse().unwrap());
+        headers.insert(TIMESTAMP_HEADER, timestamp.parse().unwrap());
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn signature_test_vector() {
+        assert_eq!(sign(SECRET, "evt_1", 1_700_000_000, BODY), VECTOR);
+        let ok = headers("1700000000", VECTOR);
+        assert_eq!(verify_signature_at(&ok, BODY, SECRET, DEFAULT_TOLERANCE, 1_700_000_120), Ok("evt_1"));
+        // rotation: any listed signature may match
+        let rotated = headers("1700000000", &format!("v1=00, {}", VECTOR));
+        assert!(verify_signature_at(&rotated, BODY, SECRET, DEFAULT_TOLERANCE, 1_700_000_000).is_ok());
+    }
+
+    #[test]
+    fn tampering_and_replays_are_rejected() {
+        let now = 1_700_000_000;
+        let ok = headers("1700000000", VECTOR);
+        assert_eq!(
+            verify_signature_at(&ok, br#"{"event":"session_revoked","user_id":"u2"}"#, SECRET, DEFAULT_TOLERANCE, now),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(verify_signature_at(&ok, BODY, "other", DEFAULT_TOLERANCE, now), Err(SignatureError::Mismatch));
+        assert_eq!(
+            verify_signature_at(&ok, BODY, SECRET, DEFAULT_TOLERANCE, now + 301),
+            Err(SignatureError::OutsideTolerance(301))
+        );
+        // the timestamp and event id are signed, so neither can be refreshed by a replayer
+        assert_eq!(
+            verify_signature_at(&headers("1700000300", VECTOR), BODY, SECRET, DEFAULT_TOLERANCE, now + 301),
+            Err(SignatureError::Mismatch)
+        );
+        let mut renamed = ok.clone();
+        renamed.insert(EVENT_ID_HEADER, "evt_2".parse().unwrap());
+        assert_eq!(verify_signature_at(&renamed, BODY, SECRET, DEFAULT_TOLERANCE, now), Err(SignatureError::Mismatch));
+        assert_eq!(
+            verify_signature_at(&headers("soon", VECTOR), BODY, SECRET, DEFAULT_TOLERANCE, now),
+            Err(SignatureError::Malformed(TIMESTAMP_HEADER))
+        );
+        assert_eq!(
+            verify_signature_at(&HeaderMap::new(), BODY, SECRET, DEFAULT_TOLERANCE, now),
+            Err(SignatureError::MissingHeader(EVENT_ID_HEADER))
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Conformance vectors for TOTP, WebAuthn and JWT.
+//!
+//! Fixed inputs with known outputs, so algorithm or configuration changes
+//! cannot silently change what authenticators and token consumers see.
+//! Needs no network or external services:
+//!
+//! ```bash
+//! cargo test --features conformance --test conformance
+//! ```
+#![cfg(feature = "conformance")]
+
+use passwordless_auth::{
+    challenge_store::Challenge,
+    config::Config,
+    db::Database,
+    jwt,
+    totp::{self, TotpAlgorithm},
+    webauthn::WebauthnState,
+};
+
+// ── TOTP: RFC 6238 Appendix B ───────────────────────────────────────────────
+
+const SEED_SHA1: &[u8] = b"12345678901234567890";
+const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+/// `(time, SHA-1, SHA-256, SHA-512)`, 8 digits, 30 second steps
+const RFC6238_VECTORS: [(u64, &str, &str, &str); 6] = [
+    (59, "94287082", "46119246", "90693936"),
+    (1111111109, "07081804", "68084774", "25091201"),
+    (1111111111, "14050471", "67062674", "99943326"),
+    (1234567890, "89005924", "91819424", "93441116"),
+    (2000000000, "69279037", "90698825", "38618901"),
+    (20000000000, "65353130", "77737706", "47863826"),
+];
+
+#[test]
+fn totp_matches_rfc6238_vectors() {
+    for (time, sha1, sha256, sha512) in RFC6238_VECTORS {
+        assert_eq!(totp::code_at(SEED_SHA1, TotpAlgorithm::Sha1, 30, 8, time), sha1, "SHA-1 at {}", time);
+        assert_eq!(totp::code_at(SEED_SHA256, TotpAlgorithm::Sha256, 30, 8, time), sha256, "SHA-256 at {}", time);
+        assert_eq!(totp::code_at(SEED_SHA512, TotpAlgorithm::Sha512, 30, 8, time), sha512, "SHA-512 at {}", time);
+    }
+}
+
+#[test]
+fn totp_verification_uses_six_digit_sha1_with_one_step_of_skew() {
+    // base32 of SEED_SHA1
+    let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    let time = 1111111111;
+    let code = totp::code_at(SEED_SHA1, TotpAlgorithm::Sha1, 30, 6, time);
+    assert_eq!(code, "050471");
+    assert!(totp::verify_code_at(secret, &code, time).is_ok());
+    assert!(totp::verify_code_at(secret, &code, time + 30).is_ok());
+    assert!(totp::verify_code_at(secret, &code, time - 30).is_ok());
+    assert!(totp::verify_code_at(secret, &code, time + 90).is_err());
+    assert!(totp::verify_code_at(secret, "14050471", time).is_err());
+}
+
+// ── WebAuthn: registration with `none` and `packed` (self) attestation ─────
+
+/// Challenge all attestation fixtures were signed over: bytes 0..32
+const FIXTURE_CHALLENGE: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8";
+
+/// `webauthn.create` client data for [`FIXTURE_CHALLENGE`] from `https://example.com`
+const CLIENT_DATA_JSON: &str = "\
+    eyJ0eXBlIjoid2ViYXV0aG4uY3JlYXRlIiwiY2hhbGxlbmdlIjoiQUFFQ0F3UUZCZ2NJQ1FvTERBME9EeEFSRWhNVUZSWVhH\
+    QmthR3h3ZEhoOCIsIm9yaWdpbiI6Imh0dHBzOi8vZXhhbXBsZS5jb20iLCJjcm9zc09yaWdpbiI6ZmFsc2V9";
+
+/// `fmt = "none"`: ES256 credential `a1a1...`, UP|UV|AT flags, rpIdHash of `example.com`
+const NONE_ATTESTATION: &str = "\
+    o2NmbXRkbm9uZWdhdHRTdG10oGhhdXRoRGF0YViUo3mm9u6vuaVeN4wRgDTidR5oL6ufLTCrE9ISVYbOGUdFAAAAAAAAAAAA\
+    AAAAAAAAAAAAAAAAEKGhoaGhoaGhoaGhoaGhoaGlAQIDJiABIVggQAfRThiddu0svwuFafoyMZk3EZixhWngoGZuSbHlRNoi\
+    WCAQt_tz4pC3uJnxNXKWf6tNNDi0qhGUShxOCAHq3uohCw";
+
+/// `fmt = "packed"` self attestation (`alg = -7`) of credential `b2b2...` with the same key
+const PACKED_ATTESTATION: &str = "\
+    o2NmbXRmcGFja2VkZ2F0dFN0bXSiY2FsZyZjc2lnWEcwRQIhAMEv48AX-jg9hBJB1_PKfitrnBt8f0ca26Gp0hKmQBq9AiA6\
+    2vFwECJ56Ei7t3lg0mfRdIbCRfhCVpna-7kkac6qM2hhdXRoRGF0YViUo3mm9u6vuaVeN4wRgDTidR5oL6ufLTCrE9ISVYbO\
+    GUdFAAAAAAAAAAAAAAAAAAAAAAAAAAAAELKysrKysrKysrKysrKysrKlAQIDJiABIVggQAfRThiddu0svwuFafoyMZk3EZix\
+    hWngoGZuSbHlRNoiWCAQt_tz4pC3uJnxNXKWf6tNNDi0qhGUShxOCAHq3uohCw";
+
+const CONFIG: &str = r#"
+jwt_secret = "conformance-secret-0123456789"
+access_token_expiry_seconds = 900
+refresh_token_expiry_seconds = 604800
+magic_link_expiry_seconds = 600
+magic_link_base_url = "https://example.com/verify/magic"
+smtp_host = "smtp.example.com"
+smtp_port = 587
+smtp_username = "mailer"
+smtp_password = "smtp-password-value"
+email_from = "no-reply@example.com"
+webauthn_rp_id = "example.com"
+webauthn_origin = "https://example.com"
+webauthn_rp_name = "Example"
+database_path = ":memory:"
+"#;
+
+fn migrated_db() -> Database {
+    let db = Database::open(":memory:").expect("open db");
+    db.apply_migrations().expect("migrate");
+    db
+}
+
+/// Replace every `challenge` in the stored ceremony state with the fixture's
+fn pin_challenge(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if key == "challenge" {
+                    *child = serde_json::Value::String(FIXTURE_CHALLENGE.to_string());
+                } else {
+                    pin_challenge(child);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(pin_challenge),
+        _ => {}
+    }
+}
+
+/// A pending registration as this server would issue it, with the challenge
+/// the fixtures were signed over
+fn pending_registration(state: &WebauthnState, db: &Database, id: &str, user_id: &str) {
+    let (_, pending) = state.new_registration(db, user_id, "fixture@example.com").unwrap();
+    let mut options: serde_json::Value = serde_json::from_slice(&pending.options).unwrap();
+    pin_challenge(&mut options);
+    state
+        .challenges
+        .put(
+            db,
+            Challenge {
+                id: id.to_string(),
+                challenge: (0u8..32).collect(),
+                options: serde_json::to_vec(&options).unwrap(),
+                ..pending
+            },
+        )
+        .unwrap();
+}
+
+fn credential(id: &str, attestation: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "rawId": id,
+        "type": "public-key",
+        "response": { "attestationObject": attestation, "clientDataJSON": CLIENT_DATA_JSON },
+        "extensions": {},
+    })
+}
+
+#[test]
+fn webauthn_accepts_none_and_packed_self_attestation() {
+    let cfg = Config::from_layers(CONFIG, None, None).unwrap();
+    let state = WebauthnState::new(&cfg);
+    let db = migrated_db();
+    let user_id = db.get_or_create_user("fixture@example.com").unwrap();
+
+    pending_registration(&state, &db, "fixture-none", &user_id);
+    let registered = state
+        .finish_registration(&db, "fixture-none", credential("oaGhoaGhoaGhoaGhoaGhoQ", NONE_ATTESTATION))
+        .expect("none attestation");
+    assert_eq!(registered, user_id);
+
+    pending_registration(&state, &db, "fixture-packed", &user_id);
+    let registered = state
+        .finish_registration(&db, "fixture-packed", credential("srKysrKysrKysrKysrKysg", PACKED_ATTESTATION))
+        .expect("packed attestation");
+    assert_eq!(registered, user_id);
+    assert_eq!(passwordless_auth::webauthn::list_credentials(&db, &user_id).unwrap().len(), 2);
+}
+
+#[test]
+fn webauthn_rejects_tampered_attestation() {
+    let cfg = Config::from_layers(CONFIG, None, None).unwrap();
+    let state = WebauthnState::new(&cfg);
+    let db = migrated_db();
+    let user_id = db.get_or_create_user("fixture@example.com").unwrap();
+
+    // one flipped bit in the packed signature
+    let tampered = PACKED_ATTESTATION.replacen("AMEv48AX", "AMEv48AY", 1);
+    pending_registration(&state, &db, "fixture-tampered", &user_id);
+    assert!(state
+        .finish_registration(&db, "fixture-tampered", credential("srKysrKysrKysrKysrKysg", &tampered))
+        .is_err());
+
+    // signed for another relying party
+    let other = CONFIG
+        .replace("webauthn_rp_id = \"example.com\"", "webauthn_rp_id = \"other.example\"")
+        .replace("webauthn_origin = \"https://example.com\"", "webauthn_origin = \"https://other.example\"");
+    let other = Config::from_layers(&other, None, None).unwrap();
+    let state = WebauthnState::new(&other);
+    pending_registration(&state, &db, "fixture-other-rp", &user_id);
+    assert!(state
+        .finish_registration(&db, "fixture-other-rp", credential("oaGhoaGhoaGhoaGhoaGhoQ", NONE_ATTESTATION))
+        .is_err());
+}
+
+// ── JWT: HS256 interoperability ────────────────────────────────────────────
+
+const JWT_SECRET: &str = "conformance-secret-0123456789";
+
+/// `{"alg":"HS256","typ":"JWT"}` / `{"sub":"user-123","exp":4102444800,"iat":1700000000,"kind":"access"}`,
+/// signed by a generic JWT library
+const HS256_TOKEN: &str = "\
+    eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.\
+    eyJzdWIiOiJ1c2VyLTEyMyIsImV4cCI6NDEwMjQ0NDgwMCwiaWF0IjoxNzAwMDAwMDAwLCJraW5kIjoiYWNjZXNzIn0.\
+    EHdu4t7Z8YUZGF6_eJ32252xb2AjC8eYfNh8_rF-ZN4";
+
+/// The same claims signed with HS512
+const HS512_TOKEN: &str = "\
+    eyJhbGciOiJIUzUxMiIsInR5cCI6IkpXVCJ9.\
+    eyJzdWIiOiJ1c2VyLTEyMyIsImV4cCI6NDEwMjQ0NDgwMCwiaWF0IjoxNzAwMDAwMDAwLCJraW5kIjoiYWNjZXNzIn0.\
+    MSR3tTAQze3RDed4E0ABF0f2JmqNpct3rp0xyig_kOstiezO9vbQjcPvV9yuoS_cSp6SHBYgFo9x34xtzzdmgA";
+
+/// The same claims with `alg = none` and no signature
+const UNSIGNED_TOKEN: &str = "\
+    eyJhbGciOiJub25lIiwidHlwIjoiSldUIn0.\
+    eyJzdWIiOiJ1c2VyLTEyMyIsImV4cCI6NDEwMjQ0NDgwMCwiaWF0IjoxNzAwMDAwMDAwLCJraW5kIjoiYWNjZXNzIn0.";
+
+/// Expired at 1700000600
+const EXPIRED_TOKEN: &str = "\
+    eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.\
+    eyJzdWIiOiJ1c2VyLTEyMyIsImV4cCI6MTcwMDAwMDYwMCwiaWF0IjoxNzAwMDAwMDAwLCJraW5kIjoiYWNjZXNzIn0.\
+    cf5Bc6VT0PPpoCFNOpc1FNvc8tK6q8RPnj9BOwdEAWk";
+
+#[test]
+fn jwt_verifies_foreign_hs256_tokens() {
+    let claims = jwt::verify_token(HS256_TOKEN, JWT_SECRET, 0).expect("HS256 vector");
+    assert_eq!((claims.sub.as_str(), claims.kind.as_str()), ("user-123", "access"));
+    assert_eq!((claims.exp, claims.iat, claims.nbf), (4102444800, 1700000000, None));
+
+    assert!(jwt::verify_token(HS256_TOKEN, "another-secret-0123456789", 0).is_err());
+    assert!(jwt::verify_token(HS512_TOKEN, JWT_SECRET, 0).is_err());
+    assert!(jwt::verify_token(UNSIGNED_TOKEN, JWT_SECRET, 0).is_err());
+    assert!(jwt::verify_token(EXPIRED_TOKEN, JWT_SECRET, 0).is_err());
+}
+
+#[test]
+fn jwt_issued_tokens_are_plain_hs256() {
+    use data_encoding::BASE64URL_NOPAD;
+
+    let token = jwt::create_token_for("user-123", JWT_SECRET, 60, "access", Some("https://auth.example.com"), &[], None)
+        .unwrap();
+    let parts: Vec<&str> = token.split('.').collect();
+    assert_eq!(parts.len(), 3);
+    let segment = |i: usize| -> serde_json::Value {
+        serde_json::from_slice(&BASE64URL_NOPAD.decode(parts[i].as_bytes()).unwrap()).unwrap()
+    };
+    assert_eq!(segment(0), serde_json::json!({ "alg": "HS256", "typ": "JWT" }));
+
+    // any HMAC-SHA256 implementation can check the signature
+    let signing_input = format!("{}.{}", parts[0], parts[1]);
+    let mac = passwordless_auth::crypto::hmac_sha256(JWT_SECRET.as_bytes(), signing_input.as_bytes());
+    assert_eq!(parts[2], BASE64URL_NOPAD.encode(&mac));
+
+    let claims = segment(1);
+    let mut keys: Vec<&str> = claims.as_object().unwrap().keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, ["exp", "iat", "iss", "jti", "kind", "nbf", "sub"]);
+}
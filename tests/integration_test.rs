@@ -2,7 +2,7 @@ use reqwest::Client;
 use serde_json::Value;
 use std::{
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
     thread,
     time::{Duration, Instant},
@@ -12,9 +12,14 @@ use tokio::time::sleep;
 use uuid::Uuid;
 use rusqlite::{Connection, params};
 
-fn build_config_override(base: &str, db_path: &str, tempdir: &PathBuf) -> String {
+/// Every test starts a server on port 3000, so they take turns
+static PORT: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn build_config_override(base: &str, db_path: &str, tempdir: &Path) -> String {
     let mut config = fs::read_to_string(base).expect("read base config.toml");
     config = config.replace("database_path = \"auth.db\"", &format!("database_path = \"{}\"", db_path));
+    // print emails instead of sending them; tests read tokens from the database
+    config = config.replace("# email_delivery = \"smtp\"", "email_delivery = \"log\"");
     // ensure magic_link_base_url points to localhost
     // optional: already in base
     let dest = tempdir.join("config.toml");
@@ -22,6 +27,18 @@ fn build_config_override(base: &str, db_path: &str, tempdir: &PathBuf) -> String
     dest.to_string_lossy().to_string()
 }
 
+/// Point the user's magic link at a fresh token and return it; the server
+/// stores only digests, so the emailed token cannot be read back
+fn plant_magic_token(conn: &Connection, email: &str) -> String {
+    let token = Uuid::new_v4().to_string();
+    conn.execute(
+        "UPDATE magic_links SET token = ?1 WHERE user_id = (SELECT id FROM users WHERE email = ?2)",
+        params![passwordless_auth::crypto::token_digest(&token), email],
+    )
+    .unwrap();
+    token
+}
+
 async fn wait_for_server_ready() {
     let client = Client::new();
     let start = Instant::now();
@@ -57,7 +74,7 @@ fn start_server_in_dir(dir: &PathBuf) -> Child {
         p
     };
 
-    let mut child = Command::new(bin_path)
+    let child = Command::new(bin_path)
         .current_dir(dir)
         .env("RUST_LOG", "info")
         .stdout(Stdio::null())
@@ -71,21 +88,14 @@ fn start_server_in_dir(dir: &PathBuf) -> Child {
 
 #[tokio::test]
 async fn magic_link_flow() {
+    let _port = PORT.lock().await;
     // Setup temp workspace
     let temp = TempDir::new().unwrap();
     let tmp_path = temp.path().to_path_buf();
 
-    // Copy migration file
-    fs::create_dir_all(tmp_path.join("migrations")).unwrap();
-    fs::copy(
-        PathBuf::from("migrations/init.sql"),
-        tmp_path.join("migrations/init.sql"),
-    )
-    .unwrap();
-
     // Override config to point at temp db
     let db_file = tmp_path.join("auth.db");
-    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
 
     // Start server
     let mut child = start_server_in_dir(&tmp_path);
@@ -105,16 +115,9 @@ async fn magic_link_flow() {
         .expect("request magic link");
     assert!(resp.status().is_success());
 
-    // Open DB and fetch token
+    // Only the token's digest is stored, so swap in one the test knows
     let conn = Connection::open(db_file).unwrap();
-    let mut stmt = conn
-        .prepare(
-            "SELECT token FROM magic_links JOIN users ON users.id=magic_links.user_id WHERE users.email = ?1",
-        )
-        .unwrap();
-    let token_row: String = stmt
-        .query_row(params![email], |r| r.get(0))
-        .unwrap();
+    let token_row = plant_magic_token(&conn, &email);
 
     // Verify magic link
     let verify = client
@@ -128,24 +131,33 @@ async fn magic_link_flow() {
     assert!(body.get("access_token").is_some(), "missing access_token");
     assert!(body.get("refresh_token").is_some(), "missing refresh_token");
 
+    // The access token identifies the user
+    let me: Value = client
+        .get("http://localhost:3000/me")
+        .bearer_auth(body["access_token"].as_str().unwrap())
+        .send()
+        .await
+        .expect("me")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(me["email"], email.as_str());
+    assert_eq!(me["totp_enabled"], false);
+    assert_eq!(me["passkeys"], serde_json::json!([]));
+
     // Cleanup
     let _ = child.kill();
+    let _ = child.wait();
 }
 
 #[tokio::test]
 async fn refresh_token_flow() {
+    let _port = PORT.lock().await;
     let temp = TempDir::new().unwrap();
     let tmp_path = temp.path().to_path_buf();
 
-    fs::create_dir_all(tmp_path.join("migrations")).unwrap();
-    fs::copy(
-        PathBuf::from("migrations/init.sql"),
-        tmp_path.join("migrations/init.sql"),
-    )
-    .unwrap();
-
     let db_file = tmp_path.join("auth.db");
-    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
 
     let mut child = start_server_in_dir(&tmp_path);
     wait_for_server_ready().await;
@@ -161,16 +173,9 @@ async fn refresh_token_flow() {
         .await
         .unwrap();
 
-    // Pull magic link token from DB
+    // Replace the emailed magic link token with a known one
     let conn = Connection::open(db_file).unwrap();
-    let mut stmt = conn
-        .prepare(
-            "SELECT token FROM magic_links JOIN users ON users.id=magic_links.user_id WHERE users.email = ?1",
-        )
-        .unwrap();
-    let magic_token: String = stmt
-        .query_row(params![email], |r| r.get(0))
-        .unwrap();
+    let magic_token = plant_magic_token(&conn, &email);
 
     let verify = client
         .get("http://localhost:3000/verify/magic")
@@ -199,23 +204,399 @@ async fn refresh_token_flow() {
     assert!(new_tokens.get("access_token").is_some());
     assert!(new_tokens.get("refresh_token").is_some());
 
+    // Logging out ends the session; its refresh token stops working
+    let rotated = new_tokens["refresh_token"].as_str().unwrap();
+    let logout = client
+        .post("http://localhost:3000/logout")
+        .json(&serde_json::json!({ "refresh_token": rotated }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(logout.status(), reqwest::StatusCode::NO_CONTENT);
+    let after = client
+        .post("http://localhost:3000/token/refresh")
+        .json(&serde_json::json!({ "refresh_token": rotated }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(after.status(), reqwest::StatusCode::UNAUTHORIZED);
+
     let _ = child.kill();
+    let _ = child.wait();
 }
 
 #[tokio::test]
-async fn totp_flow() {
+async fn cookie_transport_refuses_cross_site_writes() {
+    let _port = PORT.lock().await;
     let temp = TempDir::new().unwrap();
     let tmp_path = temp.path().to_path_buf();
 
-    fs::create_dir_all(tmp_path.join("migrations")).unwrap();
-    fs::copy(
-        PathBuf::from("migrations/init.sql"),
-        tmp_path.join("migrations/init.sql"),
-    )
-    .unwrap();
+    let db_file = tmp_path.join("auth.db");
+    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    let config = fs::read_to_string(&config_path).unwrap();
+    fs::write(&config_path, config.replace("token_transport = \"body\"", "token_transport = \"cookie\"")).unwrap();
+
+    let mut child = start_server_in_dir(&tmp_path);
+    wait_for_server_ready().await;
+
+    let client = Client::new();
+    let email = format!("cookie+{}@example.com", Uuid::new_v4());
+    client
+        .post("http://localhost:3000/request/magic")
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .unwrap();
+    let conn = Connection::open(db_file).unwrap();
+    let magic_token = plant_magic_token(&conn, &email);
+    let verify = client
+        .get("http://localhost:3000/verify/magic")
+        .query(&[("token", magic_token)])
+        .send()
+        .await
+        .unwrap();
+    assert!(verify.status().is_success());
+    // the tokens come as cookies only; send them back the way a browser would
+    let cookies = verify
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok()?.split(';').next())
+        .collect::<Vec<_>>()
+        .join("; ");
+    assert!(cookies.contains("pa_access=") && cookies.contains("pa_refresh="));
+
+    let update_notifications = |site: Option<&'static str>| {
+        let request = client
+            .patch("http://localhost:3000/me/notifications")
+            .header(reqwest::header::COOKIE, &cookies)
+            .json(&serde_json::json!({}));
+        match site {
+            Some(site) => request.header("Sec-Fetch-Site", site).header("Origin", "https://evil.test"),
+            None => request,
+        }
+        .send()
+    };
+    let forged = update_notifications(Some("cross-site")).await.unwrap();
+    assert_eq!(forged.status(), reqwest::StatusCode::FORBIDDEN);
+    let unlabelled = update_notifications(None).await.unwrap();
+    assert_eq!(unlabelled.status(), reqwest::StatusCode::FORBIDDEN);
+    let own_page = update_notifications(Some("same-origin")).await.unwrap();
+    assert_eq!(own_page.status(), reqwest::StatusCode::OK);
+
+    // reads stay open to other sites; the cookie is only a credential for them
+    let me = client
+        .get("http://localhost:3000/me")
+        .header(reqwest::header::COOKIE, &cookies)
+        .header("Sec-Fetch-Site", "cross-site")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(me.status(), reqwest::StatusCode::OK);
+
+    let logout = |site: &'static str| {
+        client
+            .post("http://localhost:3000/logout")
+            .header(reqwest::header::COOKIE, &cookies)
+            .header("Sec-Fetch-Site", site)
+            .json(&serde_json::json!({}))
+            .send()
+    };
+    assert_eq!(logout("cross-site").await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(logout("same-origin").await.unwrap().status(), reqwest::StatusCode::NO_CONTENT);
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[tokio::test]
+async fn token_introspection_flow() {
+    let _port = PORT.lock().await;
+    let temp = TempDir::new().unwrap();
+    let tmp_path = temp.path().to_path_buf();
+
+    let db_file = tmp_path.join("auth.db");
+    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    // a resource server with client credentials
+    let mut config = fs::read_to_string(&config_path).unwrap();
+    config.push_str(
+        "\n[[applications]]\nclient_id = \"billing\"\nname = \"Billing\"\nclient_secret = \"billing-secret\"\n",
+    );
+    fs::write(&config_path, config).unwrap();
+
+    let mut child = start_server_in_dir(&tmp_path);
+    wait_for_server_ready().await;
+
+    let client = Client::new();
+    let email = format!("introspect+{}@example.com", Uuid::new_v4());
+    client
+        .post("http://localhost:3000/request/magic")
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .unwrap();
+    let conn = Connection::open(db_file).unwrap();
+    let magic_token = plant_magic_token(&conn, &email);
+    let tokens: Value = client
+        .get("http://localhost:3000/verify/magic")
+        .query(&[("token", magic_token)])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let access = tokens["access_token"].as_str().unwrap();
+    let refresh = tokens["refresh_token"].as_str().unwrap();
+
+    let introspect = |token: &str| {
+        client
+            .post("http://localhost:3000/oauth/introspect")
+            .basic_auth("billing", Some("billing-secret"))
+            .form(&[("token", token)])
+            .send()
+    };
+
+    // Wrong credentials get an OAuth invalid_client error
+    let denied = client
+        .post("http://localhost:3000/oauth/introspect")
+        .basic_auth("billing", Some("wrong"))
+        .form(&[("token", access)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(denied.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(denied.json::<Value>().await.unwrap()["error"], "invalid_client");
+
+    let access_info: Value = introspect(access).await.unwrap().json().await.unwrap();
+    assert_eq!(access_info["active"], true);
+    assert_eq!(access_info["kind"], "access");
+    let refresh_info: Value = introspect(refresh).await.unwrap().json().await.unwrap();
+    assert_eq!(refresh_info["active"], true);
+    assert_eq!(refresh_info["kind"], "refresh");
+    assert_eq!(refresh_info["sub"], access_info["sub"]);
+    assert!(refresh_info["exp"].as_i64().unwrap() > access_info["exp"].as_i64().unwrap());
+
+    // After logout both tokens are inactive, and nothing else is reported
+    let logout = client
+        .post("http://localhost:3000/logout")
+        .json(&serde_json::json!({ "refresh_token": refresh }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(logout.status(), reqwest::StatusCode::NO_CONTENT);
+    let revoked: Value = introspect(refresh).await.unwrap().json().await.unwrap();
+    assert_eq!(revoked, serde_json::json!({ "active": false }));
+    let revoked: Value = introspect(access).await.unwrap().json().await.unwrap();
+    assert_eq!(revoked, serde_json::json!({ "active": false }));
+    let garbage: Value = introspect("not-a-token").await.unwrap().json().await.unwrap();
+    assert_eq!(garbage, serde_json::json!({ "active": false }));
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[tokio::test]
+async fn token_revocation_flow() {
+    let _port = PORT.lock().await;
+    let temp = TempDir::new().unwrap();
+    let tmp_path = temp.path().to_path_buf();
 
     let db_file = tmp_path.join("auth.db");
     let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    let mut config = fs::read_to_string(&config_path).unwrap();
+    config.push_str(
+        "\n[[applications]]\nclient_id = \"billing\"\nname = \"Billing\"\nclient_secret = \"billing-secret\"\n",
+    );
+    fs::write(&config_path, config).unwrap();
+
+    let mut child = start_server_in_dir(&tmp_path);
+    wait_for_server_ready().await;
+
+    let client = Client::new();
+    let email = format!("revoke+{}@example.com", Uuid::new_v4());
+    client
+        .post("http://localhost:3000/request/magic")
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .unwrap();
+    let conn = Connection::open(db_file).unwrap();
+    let magic_token = plant_magic_token(&conn, &email);
+    let tokens: Value = client
+        .get("http://localhost:3000/verify/magic")
+        .query(&[("token", magic_token)])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let access = tokens["access_token"].as_str().unwrap();
+    let refresh = tokens["refresh_token"].as_str().unwrap();
+
+    let call = |path: &'static str, token: &str| {
+        client
+            .post(format!("http://localhost:3000{}", path))
+            .basic_auth("billing", Some("billing-secret"))
+            .form(&[("token", token)])
+            .send()
+    };
+
+    // Revoking the access token leaves its session alone
+    let revoked = call("/oauth/revoke", access).await.unwrap();
+    assert_eq!(revoked.status(), reqwest::StatusCode::OK);
+    let info: Value = call("/oauth/introspect", access).await.unwrap().json().await.unwrap();
+    assert_eq!(info["active"], false);
+    let me = client
+        .get("http://localhost:3000/me")
+        .bearer_auth(access)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(me.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let info: Value = call("/oauth/introspect", refresh).await.unwrap().json().await.unwrap();
+    assert_eq!(info["active"], true);
+
+    // Revoking the refresh token ends the session
+    let revoked = call("/oauth/revoke", refresh).await.unwrap();
+    assert_eq!(revoked.status(), reqwest::StatusCode::OK);
+    let info: Value = call("/oauth/introspect", refresh).await.unwrap().json().await.unwrap();
+    assert_eq!(info["active"], false);
+
+    // Unknown tokens are answered like valid ones
+    let garbage = call("/oauth/revoke", "not-a-token").await.unwrap();
+    assert_eq!(garbage.status(), reqwest::StatusCode::OK);
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[tokio::test]
+async fn device_authorization_flow() {
+    let _port = PORT.lock().await;
+    let temp = TempDir::new().unwrap();
+    let tmp_path = temp.path().to_path_buf();
+
+    let db_file = tmp_path.join("auth.db");
+    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    // a public client without a secret
+    let mut config = fs::read_to_string(&config_path).unwrap();
+    config.push_str("\n[[applications]]\nclient_id = \"tv\"\nname = \"Living Room TV\"\n");
+    config.push_str("\n[device_authorization]\nenabled = true\ninterval_seconds = 1\n");
+    fs::write(&config_path, config).unwrap();
+
+    let mut child = start_server_in_dir(&tmp_path);
+    wait_for_server_ready().await;
+
+    let client = Client::new();
+    let unknown = client
+        .post("http://localhost:3000/device/code")
+        .form(&[("client_id", "nope")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let started: Value = client
+        .post("http://localhost:3000/device/code")
+        .form(&[("client_id", "tv")])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let device_code = started["device_code"].as_str().unwrap();
+    let user_code = started["user_code"].as_str().unwrap();
+
+    let poll = || {
+        client
+            .post("http://localhost:3000/device/token")
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("device_code", device_code),
+                ("client_id", "tv"),
+            ])
+            .send()
+    };
+    let pending = poll().await.unwrap();
+    assert_eq!(pending.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(pending.json::<Value>().await.unwrap()["error"], "authorization_pending");
+
+    // The verification page names the application
+    let page = client
+        .get("http://localhost:3000/device")
+        .query(&[("user_code", user_code)])
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("Living Room TV"));
+
+    // A signed-in user approves the code
+    let email = format!("device+{}@example.com", Uuid::new_v4());
+    client
+        .post("http://localhost:3000/request/magic")
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .unwrap();
+    let conn = Connection::open(db_file).unwrap();
+    let magic_token = plant_magic_token(&conn, &email);
+    let tokens: Value = client
+        .get("http://localhost:3000/verify/magic")
+        .query(&[("token", magic_token)])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let access = tokens["access_token"].as_str().unwrap();
+    let approved = client
+        .post("http://localhost:3000/device")
+        .bearer_auth(access)
+        .form(&[("user_code", user_code), ("decision", "approve")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(approved.status(), reqwest::StatusCode::OK);
+
+    sleep(Duration::from_millis(1100)).await;
+    let granted = poll().await.unwrap();
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+    let granted: Value = granted.json().await.unwrap();
+    assert_eq!(granted["token_type"], "Bearer");
+    assert_ne!(granted["session_id"], tokens["session_id"]);
+    let me: Value = client
+        .get("http://localhost:3000/me")
+        .bearer_auth(granted["access_token"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(me["email"], email.as_str());
+
+    // The device code is used up
+    let reused = poll().await.unwrap();
+    assert_eq!(reused.json::<Value>().await.unwrap()["error"], "invalid_grant");
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[tokio::test]
+async fn totp_flow() {
+    let _port = PORT.lock().await;
+    let temp = TempDir::new().unwrap();
+    let tmp_path = temp.path().to_path_buf();
+
+    let db_file = tmp_path.join("auth.db");
+    build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
 
     let mut child = start_server_in_dir(&tmp_path);
     wait_for_server_ready().await;
@@ -239,11 +620,20 @@ async fn totp_flow() {
         .unwrap()
         .to_string();
 
+    // A second enrollment must not replace the secret without confirmation
+    let again = client
+        .post("http://localhost:3000/totp/enroll")
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(again.status(), reqwest::StatusCode::CONFLICT);
+
     // Compute current TOTP code using same algorithm (allow slight skew)
     let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret)
         .expect("decode base32 secret");
-    let code_num = oath::totp_raw_now(&secret_bytes, 6, 0, 30);
-    let code = format!("{:06}", code_num);
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let code = passwordless_auth::totp::code_at(&secret_bytes, passwordless_auth::totp::TotpAlgorithm::Sha1, 30, 6, now);
 
     // Verify TOTP
     let verify = client
@@ -267,20 +657,16 @@ async fn totp_flow() {
     assert!(bad.status().is_client_error());
 
     let _ = child.kill();
+    let _ = child.wait();
 }
 
 #[tokio::test]
 async fn invalid_magic_link() {
+    let _port = PORT.lock().await;
     let temp = TempDir::new().unwrap();
     let tmp_path = temp.path().to_path_buf();
-    fs::create_dir_all(tmp_path.join("migrations")).unwrap();
-    fs::copy(
-        PathBuf::from("migrations/init.sql"),
-        tmp_path.join("migrations/init.sql"),
-    )
-    .unwrap();
     let db_file = tmp_path.join("auth.db");
-    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
     let mut child = start_server_in_dir(&tmp_path);
     wait_for_server_ready().await;
 
@@ -294,20 +680,42 @@ async fn invalid_magic_link() {
     assert!(resp.status().is_client_error());
 
     let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[tokio::test]
+async fn admin_api_is_not_served_without_authentication() {
+    let _port = PORT.lock().await;
+    let temp = TempDir::new().unwrap();
+    let tmp_path = temp.path().to_path_buf();
+    let db_file = tmp_path.join("auth.db");
+    // the shipped config turns neither [admin] require_api_key nor access_tokens on
+    build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    let mut child = start_server_in_dir(&tmp_path);
+    wait_for_server_ready().await;
+
+    let client = Client::new();
+    let users = client.get("http://localhost:3000/admin/users").send().await.unwrap();
+    assert_eq!(users.status(), reqwest::StatusCode::NOT_FOUND);
+    let maintenance = client
+        .put("http://localhost:3000/admin/maintenance")
+        .json(&serde_json::json!({ "enabled": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(maintenance.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let _ = child.kill();
+    let _ = child.wait();
 }
 
 #[tokio::test]
 async fn webauthn_options_and_invalid_complete() {
+    let _port = PORT.lock().await;
     let temp = TempDir::new().unwrap();
     let tmp_path = temp.path().to_path_buf();
-    fs::create_dir_all(tmp_path.join("migrations")).unwrap();
-    fs::copy(
-        PathBuf::from("migrations/init.sql"),
-        tmp_path.join("migrations/init.sql"),
-    )
-    .unwrap();
     let db_file = tmp_path.join("auth.db");
-    let config_path = build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
+    build_config_override("config.toml", db_file.to_str().unwrap(), &tmp_path);
     let mut child = start_server_in_dir(&tmp_path);
     wait_for_server_ready().await;
 
@@ -357,4 +765,5 @@ async fn webauthn_options_and_invalid_complete() {
     assert!(bad_login.status().is_client_error());
 
     let _ = child.kill();
+    let _ = child.wait();
 }
@@ -0,0 +1,224 @@
+//! The same checks against every `Storage` backend. PostgreSQL runs with
+//! `--features postgres` and `TEST_POSTGRES_URL` pointing at a scratch database;
+//! the Redis token store runs with `TEST_REDIS_URL` set.
+
+use passwordless_auth::{
+    challenge_store::{Challenge, ChallengeStore, Purpose},
+    db::Database,
+    magic_link::{IssuePolicy, LinkBinding, LinkProof, MagicLinkIssuanceConfig},
+    storage::{
+        redis::{RedisStore, RedisStoreConfig},
+        MagicLinkStore, NewCredential, RefreshTokenStore, Storage, StorageError,
+    },
+    user_agent,
+};
+use uuid::Uuid;
+
+fn check(storage: &dyn Storage) {
+    // unique per run, so a reused PostgreSQL database does not interfere
+    let email = format!("{}@example.com", Uuid::new_v4());
+    let user_id = storage.ensure_user(&email).unwrap();
+    assert_eq!(storage.ensure_user(&email).unwrap(), user_id);
+    assert_eq!(storage.find_user(&user_id).unwrap().unwrap().email, email);
+    assert!(storage.find_user_by_email("nobody@example.com").unwrap().is_none());
+    check_tokens(storage, &user_id);
+
+    // passkeys: the sign count must move forward
+    let credential_id = Uuid::new_v4().as_bytes().to_vec();
+    storage
+        .add_credential(&NewCredential {
+            id: Uuid::new_v4().to_string(),
+            user_id: user_id.clone(),
+            credential_id: credential_id.clone(),
+            public_key: vec![1, 2, 3],
+            sign_count: 5,
+            transports: "[\"internal\"]".to_string(),
+            aaguid: Some("cb69481e-8ff7-4039-93ec-0a2729a154a8".to_string()),
+            name: Some("YubiKey 5 Series".to_string()),
+            created_at: Database::now_ts(),
+        })
+        .unwrap();
+    assert_eq!(storage.credential_keys(&user_id).unwrap(), vec![vec![1, 2, 3]]);
+    let stored = storage.find_credential(&credential_id).unwrap().unwrap();
+    assert_eq!((stored.user_id.as_str(), stored.sign_count), (user_id.as_str(), 5));
+    assert!(!storage.record_assertion(&stored.id, 5, Some("192.0.2.1"), Database::now_ts()).unwrap());
+    assert!(storage.record_assertion(&stored.id, 6, Some("192.0.2.1"), Database::now_ts()).unwrap());
+    let listed = storage.list_credentials(&user_id).unwrap();
+    assert_eq!((listed[0].use_count, listed[0].last_ip.as_deref()), (1, Some("192.0.2.1")));
+    assert_eq!(listed[0].name.as_deref(), Some("YubiKey 5 Series"));
+}
+
+/// Magic link and refresh token checks, for backends that store only those
+fn check_tokens<S: MagicLinkStore + RefreshTokenStore + ?Sized>(storage: &S, user_id: &str) {
+    let user_id = user_id.to_string();
+
+    // magic links: resend within the window under a fresh token, single use, binding checked before use
+    let resend = MagicLinkIssuanceConfig {
+        policy: IssuePolicy::Resend,
+        ..Default::default()
+    };
+    let unbound = LinkBinding::default();
+    let first = storage
+        .issue_magic_link(&user_id, 600, &resend, Some(("web", Some("https://app.example.com/cb"))), &unbound)
+        .unwrap();
+    let again = storage
+        .issue_magic_link(&user_id, 600, &resend, Some(("web", Some("https://app.example.com/cb"))), &unbound)
+        .unwrap();
+    assert!(again.resent);
+    assert_eq!(again.flow_id, first.flow_id);
+    assert_ne!(again.token, first.token);
+    assert!(matches!(
+        storage.consume_magic_link(&first.token, &LinkProof::default()),
+        Err(StorageError::Invalid)
+    ));
+    assert_eq!(
+        storage.magic_link_redirect(&again.token).unwrap(),
+        Some(("web".to_string(), "https://app.example.com/cb".to_string()))
+    );
+    assert_eq!(storage.magic_link_client(&again.token).unwrap(), Some("web".to_string()));
+    assert_eq!(storage.magic_link_flow_id(&again.token).unwrap(), Some(first.flow_id.clone()));
+    assert_eq!(storage.consume_magic_link(&again.token, &LinkProof::default()).unwrap(), user_id);
+    assert!(matches!(
+        storage.consume_magic_link(&again.token, &LinkProof::default()),
+        Err(StorageError::Used)
+    ));
+
+    let bound = LinkBinding::new(None, None, Some("state-1")).unwrap();
+    let link = storage
+        .issue_magic_link(&user_id, 600, &MagicLinkIssuanceConfig::default(), None, &bound)
+        .unwrap();
+    assert!(matches!(
+        storage.consume_magic_link(&link.token, &LinkProof::default()),
+        Err(StorageError::BindingMismatch)
+    ));
+    let proof = LinkProof {
+        state: Some("state-1".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(storage.consume_magic_link(&link.token, &proof).unwrap(), user_id);
+
+    // only the newest link survives the replace policy
+    let replace = MagicLinkIssuanceConfig {
+        policy: IssuePolicy::Replace,
+        ..Default::default()
+    };
+    let older = storage.issue_magic_link(&user_id, 600, &replace, None, &unbound).unwrap();
+    let newer = storage.issue_magic_link(&user_id, 600, &replace, None, &unbound).unwrap();
+    assert!(matches!(
+        storage.consume_magic_link(&older.token, &LinkProof::default()),
+        Err(StorageError::Invalid)
+    ));
+    storage.consume_magic_link(&newer.token, &LinkProof::default()).unwrap();
+
+    // refresh tokens: rotation keeps the session, its client, sign-in
+    // methods and user agent, and retires the predecessor
+    let agent = user_agent::parse("Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0");
+    let session = storage
+        .create_refresh_token(&user_id, 3600, Some("web"), &["otp", "mfa"], Some(&agent))
+        .unwrap();
+    assert_eq!(storage.validate_refresh_token(&session.token).unwrap(), user_id);
+    let (owner, next) = storage.rotate_refresh_token(&session.token, 3600).unwrap();
+    assert_eq!((owner.as_str(), next.session_id.as_str()), (user_id.as_str(), session.session_id.as_str()));
+    assert!(matches!(storage.validate_refresh_token(&session.token), Err(StorageError::Invalid)));
+    assert_eq!(storage.live_refresh_token(&session.token).unwrap(), None);
+    let live = storage.live_refresh_token(&next.token).unwrap().expect("live successor");
+    assert_eq!(live.session_id.as_deref(), Some(session.session_id.as_str()));
+    assert_eq!(live.client_id.as_deref(), Some("web"));
+    assert_eq!(live.amr, ["otp", "mfa"]);
+    assert_eq!(
+        storage.session_amr(&user_id, &session.session_id).unwrap(),
+        Some(vec!["otp".to_string(), "mfa".to_string()])
+    );
+    // sessions are found by id through their current token
+    let found = storage.live_session(&session.session_id).unwrap().expect("live session");
+    assert_eq!((found.user_id.as_str(), found.client_id.as_deref()), (user_id.as_str(), Some("web")));
+    assert_eq!(found.expires_at, live.expires_at);
+    assert_eq!(storage.live_session("no-such-session").unwrap(), None);
+    assert!(matches!(
+        storage.rotate_refresh_token(&session.token, 3600),
+        Err(StorageError::Invalid)
+    ));
+    let other = storage.create_refresh_token(&user_id, 3600, None, &[], None).unwrap();
+    assert_eq!(storage.session_amr(&user_id, &other.session_id).unwrap(), Some(Vec::new()));
+    // only live tokens are listed, one per session, with the agent they started from
+    let listed = storage.list_sessions(&user_id).unwrap();
+    assert_eq!(listed.len(), 2);
+    let rotated = listed
+        .iter()
+        .find(|s| s.session_id.as_deref() == Some(session.session_id.as_str()))
+        .expect("rotated session listed");
+    assert_eq!(rotated.agent.as_ref(), Some(&agent));
+    assert_eq!(rotated.expires_at, live.expires_at);
+    assert!(listed.iter().any(|s| s.session_id.as_deref() == Some(other.session_id.as_str()) && s.agent.is_none()));
+    assert_eq!(storage.revoke_session(&user_id, &other.session_id).unwrap(), 1);
+    assert_eq!(storage.live_refresh_token(&other.token).unwrap(), None);
+    assert_eq!(storage.live_session(&other.session_id).unwrap(), None);
+    assert_eq!(storage.session_amr(&user_id, &other.session_id).unwrap(), None);
+    assert_eq!(storage.session_amr("someone-else", &session.session_id).unwrap(), None);
+    assert_eq!(storage.list_sessions(&user_id).unwrap().len(), 1);
+    assert!(storage.list_sessions("someone-else").unwrap().is_empty());
+    assert!(storage.live_refresh_token(&next.token).unwrap().is_some());
+    storage.create_refresh_token(&user_id, 3600, None, &["hwk"], None).unwrap();
+    assert_eq!(storage.revoke_user_refresh_tokens(&user_id).unwrap(), 2);
+    assert!(matches!(storage.validate_refresh_token(&next.token), Err(StorageError::Invalid)));
+}
+
+#[test]
+fn test_sqlite_storage() {
+    let db = Database::open(":memory:").expect("open db");
+    db.apply_migrations().expect("migrate");
+    assert_eq!(db.backend(), "sqlite");
+    check(&db);
+}
+
+#[cfg(feature = "postgres")]
+#[test]
+fn test_postgres_storage() {
+    use passwordless_auth::storage::postgres::{PostgresStorage, MIGRATIONS};
+
+    let Ok(url) = std::env::var("TEST_POSTGRES_URL") else {
+        eprintln!("TEST_POSTGRES_URL not set; skipping");
+        return;
+    };
+    let storage = PostgresStorage::connect(&url).expect("connect");
+    for migration in MIGRATIONS {
+        storage.migrate(migration.sql).expect("migrate");
+    }
+    check(&storage);
+}
+
+#[test]
+fn test_redis_store() {
+    let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+        eprintln!("TEST_REDIS_URL not set; skipping");
+        return;
+    };
+    let store = RedisStore::new(&RedisStoreConfig {
+        url,
+        key_prefix: format!("test-{}:", Uuid::new_v4()),
+        ..Default::default()
+    })
+    .expect("redis url");
+    check_tokens(&store, &Uuid::new_v4().to_string());
+
+    // challenges: read back, expired on demand, consumed once
+    let db = Database::open(":memory:").expect("open db");
+    let now = Database::now_ts();
+    let challenge = Challenge {
+        id: Uuid::new_v4().to_string(),
+        user_id: "u1".to_string(),
+        purpose: Purpose::Login,
+        challenge: vec![0, 1, 254, 255],
+        options: b"{}".to_vec(),
+        created_at: now,
+        expires_at: now + 300,
+        client_id: Some("app-1".to_string()),
+    };
+    store.put(&db, challenge.clone()).unwrap();
+    assert_eq!(store.get(&db, &challenge.id, Purpose::Login).unwrap(), Some(challenge.clone()));
+    assert_eq!(store.get(&db, &challenge.id, Purpose::Register).unwrap(), None);
+    assert_eq!(store.expire_all(&db, now).unwrap(), 1);
+    assert!(store.get(&db, &challenge.id, Purpose::Login).unwrap().unwrap().is_expired(now));
+    assert!(store.consume(&db, &challenge.id).unwrap());
+    assert!(!store.consume(&db, &challenge.id).unwrap());
+}